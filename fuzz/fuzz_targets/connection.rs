@@ -0,0 +1,8 @@
+#![no_main]
+
+extern crate proto;
+
+use libfuzzer_sys::fuzz_target;
+use proto::fuzzing::{fuzz_connection_event, ConnectionEventInput};
+
+fuzz_target!(|data: ConnectionEventInput| fuzz_connection_event(data));
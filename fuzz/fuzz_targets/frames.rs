@@ -0,0 +1,8 @@
+#![no_main]
+
+extern crate proto;
+
+use libfuzzer_sys::fuzz_target;
+use proto::fuzzing::fuzz_decode_frames;
+
+fuzz_target!(|data: &[u8]| fuzz_decode_frames(data));
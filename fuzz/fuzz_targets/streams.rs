@@ -4,7 +4,10 @@ use arbitrary::Arbitrary;
 use libfuzzer_sys::fuzz_target;
 
 extern crate proto;
-use proto::fuzzing::{ConnectionState, ResetStream, Retransmits, StreamsState};
+use std::sync::Arc;
+
+use proto::fuzzing::{ConnectionState, ResetStream, ResetStreamAt, Retransmits, StreamsState};
+use proto::scheduler::{RoundRobinConfig, StreamSchedulerFactory};
 use proto::{Dir, Side, StreamId, VarInt};
 use proto::{SendStream, Streams};
 
@@ -14,6 +17,7 @@ struct StreamParams {
     max_remote_uni: u16,
     max_remote_bi: u16,
     send_window: u16,
+    per_stream_send_buffer: u16,
     receive_window: u16,
     stream_receive_window: u16,
     dir: Dir,
@@ -26,6 +30,7 @@ enum Operation {
     Finish(StreamId),
     ReceivedStopSending(StreamId, VarInt),
     ReceivedReset(ResetStream),
+    ReceivedResetAt(ResetStreamAt),
     Reset(StreamId),
 }
 
@@ -37,8 +42,10 @@ fuzz_target!(|input: (StreamParams, Vec<Operation>)| {
         params.max_remote_uni.into(),
         params.max_remote_bi.into(),
         params.send_window.into(),
+        params.per_stream_send_buffer.into(),
         params.receive_window.into(),
         params.stream_receive_window.into(),
+        Arc::new(RoundRobinConfig::default()).build(),
     );
 
     for operation in operations {
@@ -62,6 +69,11 @@ fuzz_target!(|input: (StreamParams, Vec<Operation>)| {
                     .state()
                     .received_reset(rs);
             }
+            Operation::ReceivedResetAt(rs) => {
+                let _ = Streams::new(&mut state, &conn_state)
+                    .state()
+                    .received_reset_at(rs);
+            }
             Operation::Reset(id) => {
                 let _ =
                     SendStream::new(id, &mut state, &mut pending, &conn_state).reset(0u32.into());
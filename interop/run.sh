@@ -0,0 +1,38 @@
+#!/bin/bash
+# Run quinn against other QUIC implementations with the QUIC Interop Runner
+#
+# Usage: interop/run.sh [IMPLEMENTATION...]
+#
+# Builds the endpoint image from the working tree, then tests it as client and as server against
+# each implementation, msquic and quiche by default. Requires docker with the compose plugin,
+# python3, pip and wireshark (tshark). The matrix is written to interop/report.json and
+# printed as markdown; logs of failed tests are kept under interop/logs.
+set -euo pipefail
+
+ROOT="$(cd "$(dirname "$0")/.." && pwd)"
+RUNNER="${RUNNER:-$ROOT/target/quic-interop-runner}"
+TESTS="${TESTS:-handshake,transfer,retry,resumption,zerortt,keyupdate,chacha20,rebind-port,rebind-addr}"
+PEERS=("$@")
+if [ ${#PEERS[@]} -eq 0 ]; then
+    PEERS=(msquic quiche)
+fi
+
+docker build -f "$ROOT/interop/Dockerfile" -t quinn-interop:local "$ROOT"
+
+if [ ! -d "$RUNNER" ]; then
+    git clone --depth 1 https://github.com/quic-interop/quic-interop-runner "$RUNNER"
+fi
+pip install --quiet -r "$RUNNER/requirements.txt"
+
+cd "$RUNNER"
+PEER_LIST="$(IFS=,; echo "${PEERS[*]}")"
+rm -rf "$ROOT/interop/logs"
+python3 run.py \
+    --replace quinn=quinn-interop:local \
+    --client "quinn,$PEER_LIST" \
+    --server "quinn,$PEER_LIST" \
+    --must-include quinn \
+    --test "$TESTS" \
+    --log-dir "$ROOT/interop/logs" \
+    --json "$ROOT/interop/report.json" \
+    --markdown
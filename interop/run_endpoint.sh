@@ -0,0 +1,14 @@
+#!/bin/bash
+set -e
+
+# Set up the routing needed for the simulation
+/setup.sh
+
+case "$ROLE" in
+    client)
+        # Wait for the simulator to start up
+        /wait-for-it.sh sim:57832 -s -t 30
+        ;;
+esac
+
+RUST_LOG="${RUST_LOG:-info}" exec interop
@@ -0,0 +1,316 @@
+//! Endpoint for the [QUIC Interop Runner]
+//!
+//! Serves or fetches files over HTTP/0.9 (ALPN `hq-interop`) as directed by the environment
+//! variables the runner sets in the endpoint container: `ROLE`, `TESTCASE`, `REQUESTS`, and
+//! optionally `SSLKEYLOGFILE`. Exits with status 127 for test cases that aren't supported, which
+//! the runner reports as such rather than as failures.
+//!
+//! [QUIC Interop Runner]: https://github.com/quic-interop/quic-interop-runner
+
+use std::{
+    env, fs,
+    net::{Ipv6Addr, SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use quinn::{
+    crypto::rustls::{QuicClientConfig, QuicServerConfig},
+    ClientConfig, Connection, Endpoint, ServerConfig,
+};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tracing::{error, info};
+
+const ALPN: &[u8] = b"hq-interop";
+const UNSUPPORTED: u8 = 127;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt::init();
+
+    let testcase = env::var("TESTCASE").unwrap_or_default();
+    let Some(testcase) = TestCase::parse(&testcase) else {
+        info!(testcase, "unsupported test case");
+        return ExitCode::from(UNSUPPORTED);
+    };
+    let result = match env::var("ROLE").as_deref() {
+        Ok("server") => server(testcase).await,
+        Ok("client") => client(testcase).await,
+        _ => Err(anyhow!("ROLE must be either client or server")),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Test cases defined by the runner that this endpoint takes part in
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum TestCase {
+    /// Plain transfers, including `handshake`, `transfer`, `multiplexing`, `longrtt`, and the
+    /// server side of NAT rebinding tests
+    Transfer,
+    /// The server validates every client address with a Retry
+    Retry,
+    /// The client fetches the first file on a first connection, and the rest after resuming it
+    Resumption,
+    /// Like `Resumption`, but requesting the rest in 0-RTT
+    ZeroRtt,
+    /// The client updates keys right after the handshake
+    KeyUpdate,
+    /// The client only offers ChaCha20-Poly1305
+    ChaCha20,
+}
+
+impl TestCase {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "handshake"
+            | "transfer"
+            | "multiplexing"
+            | "longrtt"
+            | "blackhole"
+            | "handshakeloss"
+            | "transferloss"
+            | "handshakecorruption"
+            | "transfercorruption"
+            | "goodput"
+            | "crosstraffic"
+            | "rebind-port"
+            | "rebind-addr" => Self::Transfer,
+            "retry" => Self::Retry,
+            "resumption" => Self::Resumption,
+            "zerortt" => Self::ZeroRtt,
+            "keyupdate" => Self::KeyUpdate,
+            "chacha20" => Self::ChaCha20,
+            _ => return None,
+        })
+    }
+}
+
+async fn server(testcase: TestCase) -> Result<()> {
+    let certs = rustls_pemfile::certs(&mut &*fs::read("/certs/cert.pem")?)
+        .collect::<Result<Vec<CertificateDer>, _>>()
+        .context("parsing certificate")?;
+    let key = rustls_pemfile::private_key(&mut &*fs::read("/certs/priv.key")?)
+        .context("parsing private key")?
+        .ok_or_else(|| anyhow!("no private key found"))?;
+    let mut crypto = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+    crypto.max_early_data_size = u32::MAX;
+    crypto.key_log = Arc::new(rustls::KeyLogFile::new());
+    let config = ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(crypto)?));
+
+    let endpoint = Endpoint::server(config, (Ipv6Addr::UNSPECIFIED, 443).into())?;
+    info!("listening on {}", endpoint.local_addr()?);
+    let www = Arc::new(PathBuf::from("/www"));
+    while let Some(incoming) = endpoint.accept().await {
+        if testcase == TestCase::Retry && !incoming.remote_address_validated() {
+            incoming.retry()?;
+            continue;
+        }
+        let www = www.clone();
+        tokio::spawn(async move {
+            let conn = match incoming.await {
+                Ok(conn) => conn,
+                Err(e) => return error!("handshake failed: {}", e),
+            };
+            while let Ok((send, recv)) = conn.accept_bi().await {
+                let www = www.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve(&www, send, recv).await {
+                        error!("request failed: {:#}", e);
+                    }
+                });
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Answer a single HTTP/0.9 request
+async fn serve(www: &Path, mut send: quinn::SendStream, mut recv: quinn::RecvStream) -> Result<()> {
+    let request = recv.read_to_end(64 * 1024).await?;
+    let request = std::str::from_utf8(&request)?;
+    let path = request
+        .strip_prefix("GET /")
+        .map(str::trim_end)
+        .ok_or_else(|| anyhow!("malformed request {request:?}"))?;
+    if path.split('/').any(|segment| segment == "..") {
+        bail!("path {path:?} escapes the served directory");
+    }
+    send.write_all(&tokio::fs::read(www.join(path)).await?)
+        .await?;
+    send.finish()?;
+    send.stopped().await?;
+    Ok(())
+}
+
+async fn client(testcase: TestCase) -> Result<()> {
+    let requests = env::var("REQUESTS").context("REQUESTS not set")?;
+    let requests = requests
+        .split_whitespace()
+        .map(Request::parse)
+        .collect::<Result<Vec<_>>>()?;
+    let Some(first) = requests.first() else {
+        return Ok(());
+    };
+    let addr = (first.host.as_str(), first.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow!("{} has no address", first.host))?;
+
+    let provider = match testcase {
+        TestCase::ChaCha20 => rustls::crypto::CryptoProvider {
+            cipher_suites: vec![rustls::crypto::ring::cipher_suite::TLS13_CHACHA20_POLY1305_SHA256],
+            ..rustls::crypto::ring::default_provider()
+        },
+        _ => rustls::crypto::ring::default_provider(),
+    };
+    let mut crypto = rustls::ClientConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(SkipServerVerification))
+        .with_no_client_auth();
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+    crypto.enable_early_data = true;
+    crypto.key_log = Arc::new(rustls::KeyLogFile::new());
+
+    let bind: SocketAddr = match addr {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    }
+    .parse()?;
+    let mut endpoint = Endpoint::client(bind)?;
+    endpoint.set_default_client_config(ClientConfig::new(Arc::new(QuicClientConfig::try_from(
+        crypto,
+    )?)));
+
+    match testcase {
+        TestCase::Resumption | TestCase::ZeroRtt => {
+            let conn = endpoint.connect(addr, &first.host)?.await?;
+            fetch(&conn, &requests[..1]).await?;
+            conn.close(0u32.into(), b"");
+            // Wait for the session ticket to be processed before resuming
+            endpoint.wait_idle().await;
+
+            let connecting = endpoint.connect(addr, &first.host)?;
+            let conn = match testcase {
+                TestCase::ZeroRtt => match connecting.into_0rtt() {
+                    Ok((conn, _)) => conn,
+                    Err(_) => bail!("0-RTT unavailable"),
+                },
+                _ => connecting.await?,
+            };
+            fetch(&conn, &requests[1..]).await?;
+            conn.close(0u32.into(), b"");
+        }
+        _ => {
+            let conn = endpoint.connect(addr, &first.host)?.await?;
+            if testcase == TestCase::KeyUpdate {
+                conn.initiate_key_update();
+            }
+            fetch(&conn, &requests).await?;
+            conn.close(0u32.into(), b"");
+        }
+    }
+    endpoint.wait_idle().await;
+    Ok(())
+}
+
+/// Download every request concurrently over `conn` into `/downloads`
+async fn fetch(conn: &Connection, requests: &[Request]) -> Result<()> {
+    let mut tasks = Vec::with_capacity(requests.len());
+    for request in requests {
+        let conn = conn.clone();
+        let path = request.path.clone();
+        tasks.push(tokio::spawn(async move {
+            let (mut send, mut recv) = conn.open_bi().await?;
+            send.write_all(format!("GET /{path}\r\n").as_bytes())
+                .await?;
+            send.finish()?;
+            let body = recv.read_to_end(usize::MAX).await?;
+            let file = Path::new("/downloads").join(path.rsplit('/').next().unwrap_or(&path));
+            tokio::fs::write(file, body).await?;
+            anyhow::Ok(())
+        }));
+    }
+    for task in tasks {
+        task.await??;
+    }
+    Ok(())
+}
+
+/// A URL from `REQUESTS`, e.g. `https://server4:443/file`
+#[derive(Debug)]
+struct Request {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Request {
+    fn parse(url: &str) -> Result<Self> {
+        let rest = url
+            .strip_prefix("https://")
+            .ok_or_else(|| anyhow!("unsupported URL {url}"))?;
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().context("invalid port")?),
+            None => (authority, 443),
+        };
+        Ok(Self {
+            host: host.trim_start_matches('[').trim_end_matches(']').into(),
+            port,
+            path: path.into(),
+        })
+    }
+}
+
+/// The runner's certificates are self-signed, so accept any
+#[derive(Debug)]
+struct SkipServerVerification;
+
+impl rustls::client::danger::ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp: &[u8],
+        _now: UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        rustls::crypto::ring::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
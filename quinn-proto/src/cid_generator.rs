@@ -1,9 +1,10 @@
-use std::{hash::Hasher, time::Duration};
+use std::{fmt, hash::Hasher, sync::Arc, time::Duration};
 
 use rand::{Rng, RngCore};
 
+use crate::crypto::BlockCipherKey;
 use crate::shared::ConnectionId;
-use crate::MAX_CID_SIZE;
+use crate::{ConfigError, MAX_CID_SIZE};
 
 /// Generates connection IDs for incoming connections
 pub trait ConnectionIdGenerator: Send + Sync {
@@ -166,6 +167,224 @@ impl ConnectionIdGenerator for HashedConnectionIdGenerator {
 const NONCE_LEN: usize = 3; // Good for more than 16 million connections
 const SIGNATURE_LEN: usize = 8 - NONCE_LEN; // 8-byte total CID length
 
+/// Routing parameters shared between a server and a QUIC-LB load balancer
+///
+/// See [`QuicLbConnectionIdGenerator`].
+#[derive(Clone)]
+pub struct QuicLbConfig {
+    config_id: u8,
+    server_id: Vec<u8>,
+    nonce_len: usize,
+    key: Option<Arc<dyn BlockCipherKey>>,
+}
+
+impl QuicLbConfig {
+    /// Create a configuration for plaintext connection IDs
+    ///
+    /// `config_id` identifies this configuration to the load balancer, allowing configurations to
+    /// be rotated, and must be less than 7. `server_id` must be between 1 and 15 bytes long and
+    /// `nonce_len` between 4 and 18, with the two together not exceeding 19 bytes.
+    pub fn new(config_id: u8, server_id: &[u8], nonce_len: usize) -> Result<Self, ConfigError> {
+        if config_id >= QUIC_LB_UNROUTABLE
+            || !(1..=15).contains(&server_id.len())
+            || !(4..=18).contains(&nonce_len)
+            || server_id.len() + nonce_len > MAX_CID_SIZE - 1
+        {
+            return Err(ConfigError::OutOfBounds);
+        }
+        Ok(Self {
+            config_id,
+            server_id: server_id.to_vec(),
+            nonce_len,
+            key: None,
+        })
+    }
+
+    /// Encrypt the server ID and nonce with `key`, which must be an AES-128 key such as
+    /// [`AesBlockCipherKey`](crate::crypto::AesBlockCipherKey)
+    ///
+    /// Without a key, the server ID is visible to observers, allowing them to correlate
+    /// connections to the same server.
+    pub fn key(&mut self, key: Arc<dyn BlockCipherKey>) -> &mut Self {
+        self.key = Some(key);
+        self
+    }
+
+    fn cid_len(&self) -> usize {
+        1 + self.server_id.len() + self.nonce_len
+    }
+
+    /// Build the connection ID carrying this configuration's server ID and `nonce`
+    fn encode(&self, nonce: &[u8]) -> ConnectionId {
+        let len = self.cid_len();
+        let server_id_len = self.server_id.len();
+        let mut bytes = [0; MAX_CID_SIZE];
+        bytes[0] = self.config_id << 5 | (len - 1) as u8;
+        bytes[1..1 + server_id_len].copy_from_slice(&self.server_id);
+        bytes[1 + server_id_len..len].copy_from_slice(nonce);
+        if let Some(key) = &self.key {
+            let plaintext = &mut bytes[1..len];
+            match <&mut [u8; 16]>::try_from(&mut *plaintext) {
+                Ok(block) => key.encrypt_block(block),
+                Err(_) => four_pass(&**key, plaintext, false),
+            }
+        }
+        ConnectionId::new(&bytes[..len])
+    }
+
+    /// Recover the server ID from the possibly encrypted portion of `cid`
+    fn decode_server_id<'a>(&self, cid: &[u8], buf: &'a mut [u8; MAX_CID_SIZE]) -> &'a [u8] {
+        let len = cid.len() - 1;
+        let plaintext = &mut buf[..len];
+        plaintext.copy_from_slice(&cid[1..]);
+        if let Some(key) = &self.key {
+            match <&mut [u8; 16]>::try_from(&mut *plaintext) {
+                Ok(block) => key.decrypt_block(block),
+                Err(_) => four_pass(&**key, plaintext, true),
+            }
+        }
+        &buf[..self.server_id.len()]
+    }
+}
+
+impl fmt::Debug for QuicLbConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QuicLbConfig")
+            .field("config_id", &self.config_id)
+            .field("server_id", &self.server_id)
+            .field("nonce_len", &self.nonce_len)
+            .field("key", &self.key.as_ref().map(|_| "[ elided ]"))
+            .finish()
+    }
+}
+
+/// Generates connection IDs which can be routed by QUIC-LB load balancers
+///
+/// Implements the connection ID format of [draft-ietf-quic-load-balancers], which allows
+/// stateless layer 4 load balancers to route packets for a connection to the same server even
+/// when the client's address changes. Each CID consists of a first octet carrying the
+/// configuration ID and the length of the rest of the CID, followed by the server ID and a
+/// nonce, which are encrypted if a [key](QuicLbConfig::key) is configured.
+///
+/// [draft-ietf-quic-load-balancers]: https://datatracker.ietf.org/doc/draft-ietf-quic-load-balancers/
+#[derive(Debug)]
+pub struct QuicLbConnectionIdGenerator {
+    config: QuicLbConfig,
+    /// Source of nonces when encrypting, which must never repeat
+    counter: u64,
+    lifetime: Option<Duration>,
+}
+
+impl QuicLbConnectionIdGenerator {
+    /// Create a generator for `config`
+    pub fn new(config: QuicLbConfig) -> Self {
+        Self {
+            config,
+            counter: rand::thread_rng().gen(),
+            lifetime: None,
+        }
+    }
+
+    /// Set the lifetime of CIDs created by this generator
+    pub fn set_lifetime(&mut self, d: Duration) -> &mut Self {
+        self.lifetime = Some(d);
+        self
+    }
+}
+
+impl ConnectionIdGenerator for QuicLbConnectionIdGenerator {
+    fn generate_cid(&mut self) -> ConnectionId {
+        let mut nonce = [0; MAX_CID_SIZE];
+        let nonce = &mut nonce[..self.config.nonce_len];
+        rand::thread_rng().fill_bytes(nonce);
+        if self.config.key.is_some() {
+            let counter_len = nonce.len().min(8);
+            let nonce_len = nonce.len();
+            nonce[nonce_len - counter_len..]
+                .copy_from_slice(&self.counter.to_be_bytes()[8 - counter_len..]);
+            self.counter = self.counter.wrapping_add(1);
+        }
+        self.config.encode(nonce)
+    }
+
+    fn validate(&self, cid: &ConnectionId) -> Result<(), InvalidCid> {
+        if cid.len() != self.config.cid_len() || cid[0] >> 5 != self.config.config_id {
+            return Err(InvalidCid);
+        }
+        let mut buf = [0; MAX_CID_SIZE];
+        match self.config.decode_server_id(cid, &mut buf) == self.config.server_id {
+            true => Ok(()),
+            false => Err(InvalidCid),
+        }
+    }
+
+    fn cid_len(&self) -> usize {
+        self.config.cid_len()
+    }
+
+    fn cid_lifetime(&self) -> Option<Duration> {
+        self.lifetime
+    }
+}
+
+/// Encrypt or decrypt `data`, which is not a whole block, with a four-round Feistel network
+///
+/// Each half of `data` is in turn padded to a block, encrypted and XORed into the other half. For
+/// odd lengths, the halves share the middle byte, each taking one nibble of it.
+fn four_pass(key: &dyn BlockCipherKey, data: &mut [u8], decrypt: bool) {
+    let len = data.len();
+    let half = (len + 1) / 2;
+    let odd = len % 2 == 1;
+    let (mut left, mut right) = ([0; MAX_CID_SIZE / 2], [0; MAX_CID_SIZE / 2]);
+    left[..half].copy_from_slice(&data[..half]);
+    right[..half].copy_from_slice(&data[len - half..]);
+    if odd {
+        left[half - 1] &= 0xf0;
+        right[0] &= 0x0f;
+    }
+
+    let passes = match decrypt {
+        false => [1, 2, 3, 4],
+        true => [4, 3, 2, 1],
+    };
+    for index in passes {
+        let mut block = [0; 16];
+        if index % 2 == 1 {
+            block[..half].copy_from_slice(&left[..half]);
+            block[14] = len as u8;
+            block[15] = index;
+            key.encrypt_block(&mut block);
+            for (x, y) in right[..half].iter_mut().zip(&block[16 - half..]) {
+                *x ^= y;
+            }
+            if odd {
+                right[0] &= 0x0f;
+            }
+        } else {
+            block[0] = len as u8;
+            block[1] = index;
+            block[16 - half..].copy_from_slice(&right[..half]);
+            key.encrypt_block(&mut block);
+            for (x, y) in left[..half].iter_mut().zip(&block[..half]) {
+                *x ^= y;
+            }
+            if odd {
+                left[half - 1] &= 0xf0;
+            }
+        }
+    }
+
+    data[..half].copy_from_slice(&left[..half]);
+    let overlap = usize::from(odd);
+    data[half..].copy_from_slice(&right[overlap..half]);
+    if odd {
+        data[half - 1] |= right[0];
+    }
+}
+
+/// Configuration ID reserved for connection IDs which load balancers cannot route
+const QUIC_LB_UNROUTABLE: u8 = 7;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -176,4 +395,127 @@ mod tests {
         let cid = generator.generate_cid();
         generator.validate(&cid).unwrap();
     }
+
+    /// Invertible stand-in for AES which mixes every byte of the key and block into every output
+    /// byte
+    struct ToyCipher([u8; 16]);
+
+    impl BlockCipherKey for ToyCipher {
+        fn encrypt_block(&self, block: &mut [u8; 16]) {
+            for round in 0..4 {
+                for (b, k) in block.iter_mut().zip(&self.0) {
+                    *b ^= k ^ round;
+                }
+                for i in 1..16 {
+                    block[i] = block[i].wrapping_add(block[i - 1]).rotate_left(3);
+                }
+                block.rotate_left(1);
+            }
+        }
+
+        fn decrypt_block(&self, block: &mut [u8; 16]) {
+            for round in (0..4).rev() {
+                block.rotate_right(1);
+                for i in (1..16).rev() {
+                    block[i] = block[i].rotate_right(3).wrapping_sub(block[i - 1]);
+                }
+                for (b, k) in block.iter_mut().zip(&self.0) {
+                    *b ^= k ^ round;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn quic_lb_plaintext() {
+        let config = QuicLbConfig::new(2, &[0xab, 0xcd, 0xef], 5).unwrap();
+        let mut generator = QuicLbConnectionIdGenerator::new(config.clone());
+        assert_eq!(generator.cid_len(), 9);
+        let cid = generator.generate_cid();
+        assert_eq!(cid[0], 2 << 5 | 8);
+        assert_eq!(cid[1..4], [0xab, 0xcd, 0xef]);
+        generator.validate(&cid).unwrap();
+
+        let other = QuicLbConfig::new(2, &[0xab, 0xcd, 0xee], 5).unwrap();
+        QuicLbConnectionIdGenerator::new(other)
+            .validate(&cid)
+            .unwrap_err();
+    }
+
+    #[test]
+    fn quic_lb_encrypted() {
+        for server_id_len in 1..=15 {
+            for nonce_len in 4..=(19 - server_id_len) {
+                let server_id = (1..=server_id_len as u8).collect::<Vec<_>>();
+                let mut config = QuicLbConfig::new(0, &server_id, nonce_len).unwrap();
+                config.key(Arc::new(ToyCipher(*b"0123456789abcdef")));
+                let mut generator = QuicLbConnectionIdGenerator::new(config);
+                let a = generator.generate_cid();
+                let b = generator.generate_cid();
+                assert_eq!(a.len(), 1 + server_id_len + nonce_len);
+                assert_ne!(a, b);
+                generator.validate(&a).unwrap();
+                generator.validate(&b).unwrap();
+                let mut buf = [0; MAX_CID_SIZE];
+                assert_eq!(generator.config.decode_server_id(&a, &mut buf), server_id);
+                if server_id_len >= 8 {
+                    let mut forged = a[..].to_vec();
+                    forged[1] ^= 1;
+                    generator.validate(&ConnectionId::new(&forged)).unwrap_err();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn quic_lb_bounds() {
+        assert!(QuicLbConfig::new(7, &[1], 4).is_err());
+        assert!(QuicLbConfig::new(0, &[], 4).is_err());
+        assert!(QuicLbConfig::new(0, &[1], 3).is_err());
+        assert!(QuicLbConfig::new(0, &[1; 15], 5).is_err());
+        assert!(QuicLbConfig::new(0, &[1; 15], 4).is_ok());
+    }
+
+    #[cfg(feature = "aws-lc-rs")]
+    #[test]
+    fn quic_lb_aes() {
+        use crate::crypto::AesBlockCipherKey;
+        use hex_literal::hex;
+
+        // FIPS-197 appendix C.1
+        let key = AesBlockCipherKey::new(&hex!("000102030405060708090a0b0c0d0e0f"));
+        let mut block = hex!("00112233445566778899aabbccddeeff");
+        key.encrypt_block(&mut block);
+        assert_eq!(block, hex!("69c4e0d86a7b0430d8cdb78070b4c55a"));
+        key.decrypt_block(&mut block);
+        assert_eq!(block, hex!("00112233445566778899aabbccddeeff"));
+
+        // draft-ietf-quic-load-balancers appendix B, single-pass encryption
+        let key = Arc::new(AesBlockCipherKey::new(&hex!(
+            "8f95f09245765f80256934e50c66207f"
+        )));
+        let mut config = QuicLbConfig::new(2, &hex!("ed793a51d49b8f5f"), 8).unwrap();
+        config.key(key.clone());
+        let cid = config.encode(&hex!("ee080dbf48c0d1e5"));
+        assert_eq!(cid[..], hex!("504dd2d05a7b0de9b2b9907afb5ecf8cc3"));
+        let generator = QuicLbConnectionIdGenerator::new(config.clone());
+        generator.validate(&cid).unwrap();
+        // A forged connection ID doesn't decrypt to our server ID
+        let mut forged = cid[..].to_vec();
+        forged[5] ^= 1;
+        generator.validate(&ConnectionId::new(&forged)).unwrap_err();
+
+        // Every length round-trips through real AES
+        for server_id_len in 1..=15 {
+            for nonce_len in 4..=(19 - server_id_len) {
+                let server_id = (1..=server_id_len as u8).collect::<Vec<_>>();
+                let mut config = QuicLbConfig::new(1, &server_id, nonce_len).unwrap();
+                config.key(key.clone());
+                let mut generator = QuicLbConnectionIdGenerator::new(config);
+                let cid = generator.generate_cid();
+                let mut buf = [0; MAX_CID_SIZE];
+                assert_eq!(generator.config.decode_server_id(&cid, &mut buf), server_id);
+            }
+        }
+    }
 }
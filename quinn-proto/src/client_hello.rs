@@ -0,0 +1,132 @@
+use bytes::{Buf, Bytes, BytesMut};
+
+use crate::{coding::BufExt, frame, transport_parameters::TransportParameters, Side};
+
+/// The parts of a TLS ClientHello relevant to routing a connection
+///
+/// Obtained with [`Incoming::client_hello()`](crate::Incoming::client_hello) before any TLS work is
+/// done, e.g. to select a [`ServerConfig`](crate::ServerConfig) by server name.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ClientHello {
+    /// The random value chosen by the client, which identifies this particular ClientHello
+    pub random: [u8; 32],
+    /// The server name indication, if any
+    pub server_name: Option<String>,
+    /// The application protocols offered by the client, in order of preference
+    pub alpn_protocols: Vec<Vec<u8>>,
+    /// The client's QUIC transport parameters, if present and well-formed
+    pub transport_parameters: Option<TransportParameters>,
+    /// Whether the client is attempting to send 0-RTT data
+    pub early_data: bool,
+}
+
+impl ClientHello {
+    /// Decode a ClientHello from the CRYPTO frames in the decrypted payload of an Initial packet
+    pub(crate) fn from_initial_payload(payload: Bytes) -> Option<Self> {
+        let mut chunks = frame::Iter::new(payload)
+            .ok()?
+            .filter_map(|frame| match frame {
+                Ok(frame::Frame::Crypto(crypto)) => Some(crypto),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        chunks.sort_unstable_by_key(|crypto| crypto.offset);
+        let mut data = BytesMut::new();
+        for crypto in chunks {
+            // Retransmissions and padding strategies may lead to overlapping frames
+            let Some(skip) = (data.len() as u64).checked_sub(crypto.offset) else {
+                break;
+            };
+            data.extend_from_slice(crypto.data[..].get(skip as usize..).unwrap_or_default());
+        }
+        Self::decode(data.freeze())
+    }
+
+    /// Decode a ClientHello from the start of the Initial-level handshake data
+    ///
+    /// Returns `None` if `data` is not a ClientHello or is truncated.
+    fn decode(mut data: Bytes) -> Option<Self> {
+        if data.get::<u8>().ok()? != HANDSHAKE_TYPE_CLIENT_HELLO {
+            return None;
+        }
+        let len = get_u24(&mut data)?;
+        let mut body = take(&mut data, len)?;
+        // legacy_version
+        take(&mut body, 2)?;
+        let mut random = [0; 32];
+        take(&mut body, 32)?.copy_to_slice(&mut random);
+        let session_id_len = body.get::<u8>().ok()?;
+        take(&mut body, session_id_len.into())?;
+        let cipher_suites_len = body.get::<u16>().ok()?;
+        take(&mut body, cipher_suites_len.into())?;
+        let compression_methods_len = body.get::<u8>().ok()?;
+        take(&mut body, compression_methods_len.into())?;
+        let extensions_len = body.get::<u16>().ok()?;
+        let mut extensions = take(&mut body, extensions_len.into())?;
+
+        let mut hello = Self {
+            random,
+            server_name: None,
+            alpn_protocols: Vec::new(),
+            transport_parameters: None,
+            early_data: false,
+        };
+        while extensions.has_remaining() {
+            let ty = extensions.get::<u16>().ok()?;
+            let len = extensions.get::<u16>().ok()?;
+            let mut extension = take(&mut extensions, len.into())?;
+            match ty {
+                EXTENSION_SERVER_NAME => {
+                    let list_len = extension.get::<u16>().ok()?;
+                    let mut list = take(&mut extension, list_len.into())?;
+                    while list.has_remaining() {
+                        let name_type = list.get::<u8>().ok()?;
+                        let name_len = list.get::<u16>().ok()?;
+                        let name = take(&mut list, name_len.into())?;
+                        if name_type == NAME_TYPE_HOST_NAME {
+                            hello.server_name = Some(String::from_utf8(name.to_vec()).ok()?);
+                        }
+                    }
+                }
+                EXTENSION_ALPN => {
+                    let list_len = extension.get::<u16>().ok()?;
+                    let mut list = take(&mut extension, list_len.into())?;
+                    while list.has_remaining() {
+                        let protocol_len = list.get::<u8>().ok()?;
+                        let protocol = take(&mut list, protocol_len.into())?;
+                        hello.alpn_protocols.push(protocol.to_vec());
+                    }
+                }
+                EXTENSION_QUIC_TRANSPORT_PARAMETERS => {
+                    hello.transport_parameters =
+                        TransportParameters::read(Side::Server, &mut extension).ok();
+                }
+                EXTENSION_EARLY_DATA => hello.early_data = true,
+                _ => {}
+            }
+        }
+        Some(hello)
+    }
+}
+
+fn get_u24(data: &mut Bytes) -> Option<usize> {
+    if data.remaining() < 3 {
+        return None;
+    }
+    Some(data.get_uint(3) as usize)
+}
+
+fn take(data: &mut Bytes, len: usize) -> Option<Bytes> {
+    if data.remaining() < len {
+        return None;
+    }
+    Some(data.split_to(len))
+}
+
+const HANDSHAKE_TYPE_CLIENT_HELLO: u8 = 1;
+const EXTENSION_SERVER_NAME: u16 = 0;
+const EXTENSION_ALPN: u16 = 16;
+const EXTENSION_EARLY_DATA: u16 = 42;
+const EXTENSION_QUIC_TRANSPORT_PARAMETERS: u16 = 0x39;
+const NAME_TYPE_HOST_NAME: u8 = 0;
@@ -6,21 +6,32 @@ use std::{
     time::Duration,
 };
 
+use bytes::Bytes;
 #[cfg(any(feature = "rustls-aws-lc-rs", feature = "rustls-ring"))]
 use rustls::client::WebPkiServerVerifier;
 #[cfg(any(feature = "rustls-aws-lc-rs", feature = "rustls-ring"))]
 use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+#[cfg(any(feature = "rustls-aws-lc-rs", feature = "rustls-ring"))]
+use rustls::{crypto::SupportedKxGroup, server::danger::ClientCertVerifier};
 use thiserror::Error;
 
 #[cfg(any(feature = "rustls-aws-lc-rs", feature = "rustls-ring"))]
-use crate::crypto::rustls::{configured_provider, QuicServerConfig};
+use crate::crypto::rustls::{configured_provider, provider_with_kx_groups, QuicServerConfig};
+#[cfg(feature = "qlog")]
+use crate::QlogFactory;
 use crate::{
-    cid_generator::{ConnectionIdGenerator, HashedConnectionIdGenerator},
+    cid_generator::{
+        ConnectionIdGenerator, HashedConnectionIdGenerator, QuicLbConfig,
+        QuicLbConnectionIdGenerator,
+    },
     congestion,
     crypto::{self, HandshakeTokenKey, HmacKey},
+    scheduler,
     shared::ConnectionId,
-    RandomConnectionIdGenerator, VarInt, VarIntBoundsExceeded, DEFAULT_SUPPORTED_VERSIONS,
-    INITIAL_MTU, MAX_CID_SIZE, MAX_UDP_PAYLOAD,
+    token::{DefaultTokenProvider, TokenLog, TokenMemoryCache, TokenProvider, TokenStore},
+    transport_parameters::TransportParameters,
+    ClientHello, FrameExtension, PacketInspector, RandomConnectionIdGenerator, VarInt,
+    VarIntBoundsExceeded, DEFAULT_SUPPORTED_VERSIONS, INITIAL_MTU, MAX_CID_SIZE, MAX_UDP_PAYLOAD,
 };
 
 /// Parameters governing the core QUIC state machine
@@ -42,28 +53,43 @@ pub struct TransportConfig {
     pub(crate) stream_receive_window: VarInt,
     pub(crate) receive_window: VarInt,
     pub(crate) send_window: u64,
-    pub(crate) send_fairness: bool,
+    pub(crate) per_stream_send_buffer: u64,
+    pub(crate) send_buffer_allocator: SendBufferAllocator,
+    pub(crate) max_buffered_bytes: Option<u64>,
+    pub(crate) window_autotune: Option<(VarInt, VarInt)>,
+    pub(crate) stream_window_update: StreamWindowUpdateConfig,
+    pub(crate) stream_scheduler_factory: Arc<dyn scheduler::StreamSchedulerFactory + Send + Sync>,
 
     pub(crate) packet_threshold: u32,
     pub(crate) time_threshold: f32,
+    pub(crate) max_pto_backoff: u32,
     pub(crate) initial_rtt: Duration,
+    pub(crate) initial_rtt_and_cwnd_hint: Option<(Duration, u64)>,
     pub(crate) initial_mtu: u16,
     pub(crate) min_mtu: u16,
     pub(crate) mtu_discovery_config: Option<MtuDiscoveryConfig>,
+    pub(crate) pacing: Option<PacingConfig>,
     pub(crate) ack_frequency_config: Option<AckFrequencyConfig>,
+    pub(crate) receive_timestamps: bool,
 
     pub(crate) persistent_congestion_threshold: u32,
     pub(crate) keep_alive_interval: Option<Duration>,
+    pub(crate) key_update_interval: Option<u64>,
+    pub(crate) chaos_protection: bool,
     pub(crate) crypto_buffer_size: usize,
     pub(crate) allow_spin: bool,
     pub(crate) datagram_receive_buffer_size: Option<usize>,
     pub(crate) datagram_send_buffer_size: usize,
+    pub(crate) datagram_queue_policy: DatagramDropPolicy,
     #[cfg(test)]
     pub(crate) deterministic_packet_numbers: bool,
 
     pub(crate) congestion_controller_factory: Arc<dyn congestion::ControllerFactory + Send + Sync>,
 
     pub(crate) enable_segmentation_offload: bool,
+
+    pub(crate) custom_transport_parameters: Vec<(VarInt, Bytes)>,
+    pub(crate) frame_extensions: Vec<Arc<dyn FrameExtension>>,
 }
 
 impl TransportConfig {
@@ -146,6 +172,89 @@ impl TransportConfig {
         self
     }
 
+    /// Maximum number of bytes to buffer on a single stream without acknowledgment
+    ///
+    /// Writes to a stream that already has this much unacknowledged data outstanding fail with
+    /// `WriteError::Blocked` until the peer acknowledges some of it, so that a single stream writing
+    /// a large burst cannot consume the entire [`send_window`](Self::send_window) and starve other
+    /// streams. Unlimited by default.
+    pub fn per_stream_send_buffer(&mut self, value: usize) -> &mut Self {
+        self.per_stream_send_buffer = value as u64;
+        self
+    }
+
+    /// How streams store written data until the peer acknowledges it
+    ///
+    /// Defaults to [`SendBufferAllocator::PerWrite`].
+    pub fn send_buffer_allocator(&mut self, value: SendBufferAllocator) -> &mut Self {
+        self.send_buffer_allocator = value;
+        self
+    }
+
+    /// Maximum number of bytes of stream data a connection may buffer
+    ///
+    /// Counts data received from the peer that the application has yet to read, including data
+    /// received out of order, flow control credit issued for data the peer has yet to send, and
+    /// data sent that the peer has yet to acknowledge. Once the connection reaches this limit it
+    /// stops issuing connection-level flow control credit, so the peer cannot send more until the
+    /// application reads some data or the peer acknowledges some. The
+    /// [`receive_window`](Self::receive_window) is capped to this value.
+    ///
+    /// Outgoing data is not throttled by this limit; see [`send_window`](Self::send_window). For a
+    /// limit shared by all connections of an endpoint, see
+    /// [`EndpointConfig::max_buffered_bytes()`]. Unlimited by default.
+    pub fn max_buffered_bytes(&mut self, value: Option<u64>) -> &mut Self {
+        self.max_buffered_bytes = value;
+        self
+    }
+
+    /// Grow the receive windows automatically, keeping them between `min` and `max` bytes
+    ///
+    /// The configured [`stream_receive_window`](Self::stream_receive_window) and
+    /// [`receive_window`](Self::receive_window) are clamped to these bounds and used as initial
+    /// values. Whenever the peer consumes half of a window in less than two round trips, suggesting
+    /// that flow control rather than the network path limits throughput, that window is doubled,
+    /// up to `max`. This lets connections over long, fast paths reach full throughput without
+    /// committing memory for large windows to every connection up front.
+    ///
+    /// Since the default `receive_window` is unlimited, it starts out at `max` unless configured
+    /// explicitly. Disabled by default.
+    pub fn window_autotune(&mut self, min: VarInt, max: VarInt) -> &mut Self {
+        self.window_autotune = Some((min, max.max(min)));
+        self
+    }
+
+    /// When to extend the flow control credit of receive streams
+    ///
+    /// Defaults to extending credit as the application reads, announcing it once an eighth of
+    /// the [`stream_receive_window`](Self::stream_receive_window) was read.
+    pub fn stream_window_update(&mut self, value: StreamWindowUpdateConfig) -> &mut Self {
+        self.stream_window_update = value;
+        self
+    }
+
+    /// The receive window after applying [`window_autotune`](Self::window_autotune) and
+    /// [`max_buffered_bytes`](Self::max_buffered_bytes)
+    pub(crate) fn get_receive_window(&self) -> VarInt {
+        let window = self.autotuned(self.receive_window);
+        match self.max_buffered_bytes {
+            Some(max) => window.min(VarInt::from_u64(max).unwrap_or(VarInt::MAX)),
+            None => window,
+        }
+    }
+
+    /// The stream receive window after applying [`window_autotune`](Self::window_autotune)
+    pub(crate) fn get_stream_receive_window(&self) -> VarInt {
+        self.autotuned(self.stream_receive_window)
+    }
+
+    fn autotuned(&self, window: VarInt) -> VarInt {
+        match self.window_autotune {
+            Some((min, max)) => window.clamp(min, max),
+            None => window,
+        }
+    }
+
     /// Whether to implement fair queuing for send streams having the same priority.
     ///
     /// When enabled, connections schedule data from outgoing streams having the same priority in a
@@ -156,13 +265,47 @@ impl TransportConfig {
     ///
     /// Disabling fairness can reduce fragmentation and protocol overhead for workloads that use
     /// many small streams.
+    ///
+    /// This is a shorthand for [`stream_scheduler()`](Self::stream_scheduler) with a default
+    /// [`RoundRobinConfig`](scheduler::RoundRobinConfig) when enabled, or
+    /// [`StrictPriorityConfig`](scheduler::StrictPriorityConfig) when disabled, replacing any
+    /// previously configured scheduler.
     pub fn send_fairness(&mut self, value: bool) -> &mut Self {
-        self.send_fairness = value;
+        self.stream_scheduler_factory = match value {
+            true => Arc::new(scheduler::RoundRobinConfig::default()),
+            false => Arc::new(scheduler::StrictPriorityConfig::default()),
+        };
+        self
+    }
+
+    /// How to choose which stream to send data from when several have data pending
+    ///
+    /// Defaults to round-robin scheduling within each priority level, as per
+    /// [`send_fairness(true)`](Self::send_fairness). Applications with particular ordering
+    /// requirements, such as HTTP/3 implementations, may provide their own
+    /// [`StreamScheduler`](scheduler::StreamScheduler).
+    ///
+    /// ```
+    /// # use quinn_proto::*; use std::sync::Arc;
+    /// let mut config = TransportConfig::default();
+    /// config.stream_scheduler(Arc::new(scheduler::WeightedFairConfig::default()));
+    /// ```
+    pub fn stream_scheduler(
+        &mut self,
+        factory: Arc<dyn scheduler::StreamSchedulerFactory + Send + Sync + 'static>,
+    ) -> &mut Self {
+        self.stream_scheduler_factory = factory;
         self
     }
 
     /// Maximum reordering in packet number space before FACK style loss detection considers a
     /// packet lost. Should not be less than 3, per RFC5681.
+    ///
+    /// Raising it avoids spurious retransmissions on links that reorder heavily, such as some
+    /// wireless links, at the cost of detecting genuine losses later. Defaults to 3. Can be
+    /// adjusted for an established connection with [`Connection::set_packet_threshold()`].
+    ///
+    /// [`Connection::set_packet_threshold()`]: crate::Connection::set_packet_threshold
     pub fn packet_threshold(&mut self, value: u32) -> &mut Self {
         self.packet_threshold = value;
         self
@@ -170,17 +313,47 @@ impl TransportConfig {
 
     /// Maximum reordering in time space before time based loss detection considers a packet lost,
     /// as a factor of RTT
+    ///
+    /// Defaults to 9/8, per RFC 9002. Can be adjusted for an established connection with
+    /// [`Connection::set_time_threshold()`](crate::Connection::set_time_threshold).
     pub fn time_threshold(&mut self, value: f32) -> &mut Self {
         self.time_threshold = value;
         self
     }
 
+    /// Maximum number of times the probe timeout (PTO) is doubled while successive probes go
+    /// unacknowledged
+    ///
+    /// Lowering it keeps probing frequent through long outages, such as a wireless link fading,
+    /// so that recovery follows soon after connectivity returns, at the cost of more probes sent
+    /// into a dead path. Defaults to 16, which is also the maximum. Can be adjusted for an established connection with
+    /// [`Connection::set_max_pto_backoff()`](crate::Connection::set_max_pto_backoff).
+    pub fn max_pto_backoff(&mut self, value: u32) -> &mut Self {
+        self.max_pto_backoff = value;
+        self
+    }
+
     /// The RTT used before an RTT sample is taken
     pub fn initial_rtt(&mut self, value: Duration) -> &mut Self {
         self.initial_rtt = value;
         self
     }
 
+    /// Round-trip time and congestion window of a previous connection over the same path
+    ///
+    /// Lets the congestion controller jump-start its window once the new connection's first RTT
+    /// sample confirms that the path is still similar, rather than probing its capacity from
+    /// scratch. Suitable values can be taken from [`PathStats::rtt`] and [`PathStats::cwnd`] of
+    /// the previous connection shortly before it ended. Only some controllers, such as
+    /// [`Cubic`](congestion::Cubic), make use of the hint. Defaults to `None`.
+    ///
+    /// [`PathStats::rtt`]: crate::PathStats::rtt
+    /// [`PathStats::cwnd`]: crate::PathStats::cwnd
+    pub fn initial_rtt_and_cwnd_hint(&mut self, value: Option<(Duration, u64)>) -> &mut Self {
+        self.initial_rtt_and_cwnd_hint = value;
+        self
+    }
+
     /// The initial value to be used as the maximum UDP payload size before running MTU discovery
     /// (see [`TransportConfig::mtu_discovery_config`]).
     ///
@@ -224,6 +397,16 @@ impl TransportConfig {
         self
     }
 
+    /// Specifies the pacing config (see [`PacingConfig`] for details)
+    ///
+    /// Pacing spreads transmissions over time instead of sending an entire congestion window at
+    /// once, reducing packet loss caused by overflowing buffers along the network path. Enabled
+    /// with the default config by default; `None` disables pacing entirely.
+    pub fn pacing(&mut self, value: Option<PacingConfig>) -> &mut Self {
+        self.pacing = value;
+        self
+    }
+
     /// Specifies the ACK frequency config (see [`AckFrequencyConfig`] for details)
     ///
     /// The provided configuration will be ignored if the peer does not support the acknowledgement
@@ -237,6 +420,23 @@ impl TransportConfig {
         self
     }
 
+    /// Whether to ask the peer to report when it received our packets
+    ///
+    /// Negotiates the [receive timestamps extension], through which the peer's ACKs carry the
+    /// times at which packets were received, as measured by the peer's clock. These are passed to
+    /// the congestion controller through
+    /// [`Controller::on_receive_timestamp()`](crate::congestion::Controller::on_receive_timestamp),
+    /// giving it a view of one-way delay, which is more accurate than the round-trip time on
+    /// asymmetric paths. Ignored if the peer does not support the extension.
+    ///
+    /// Defaults to `false`. Timestamps are always reported to peers that ask for them.
+    ///
+    /// [receive timestamps extension]: https://datatracker.ietf.org/doc/html/draft-smith-quic-receive-ts-00
+    pub fn receive_timestamps(&mut self, value: bool) -> &mut Self {
+        self.receive_timestamps = value;
+        self
+    }
+
     /// Number of consecutive PTOs after which network is considered to be experiencing persistent congestion.
     pub fn persistent_congestion_threshold(&mut self, value: u32) -> &mut Self {
         self.persistent_congestion_threshold = value;
@@ -255,6 +455,33 @@ impl TransportConfig {
         self
     }
 
+    /// Maximum number of 1-RTT packets to send with the same keys before initiating a key update
+    ///
+    /// Periodic key updates bound the amount of data protected by any single key, as may be
+    /// required for long-lived connections. Regardless of this setting, keys are updated before
+    /// the confidentiality limit of the negotiated cipher suite is reached.
+    ///
+    /// `None` to only update keys when required by the cipher suite, which is the default.
+    pub fn key_update_interval(&mut self, value: Option<u64>) -> &mut Self {
+        self.key_update_interval = value;
+        self
+    }
+
+    /// Whether to randomize the layout of handshake messages to resist ossification and
+    /// fingerprinting
+    ///
+    /// When enabled, the reserved transport parameter sent to keep peers tolerant of unknown
+    /// parameters gets a random identifier and contents, and a client splits its first Initial's
+    /// CRYPTO data into several frames sent in random order, interspersed with PING and PADDING
+    /// frames. This makes it harder for middleboxes to depend on, or fingerprint, a particular
+    /// layout of Initial packets.
+    ///
+    /// Defaults to `false`.
+    pub fn chaos_protection(&mut self, value: bool) -> &mut Self {
+        self.chaos_protection = value;
+        self
+    }
+
     /// Maximum quantity of out-of-order crypto layer data to buffer
     pub fn crypto_buffer_size(&mut self, value: usize) -> &mut Self {
         self.crypto_buffer_size = value;
@@ -286,12 +513,22 @@ impl TransportConfig {
     /// While datagrams are sent ASAP, it is possible for an application to generate data faster
     /// than the link, or even the underlying hardware, can transmit them. This limits the amount of
     /// memory that may be consumed in that case. When the send buffer is full and a new datagram is
-    /// sent, older datagrams are dropped until sufficient space is available.
+    /// sent, datagrams are dropped according to the
+    /// [`datagram_queue_policy`](Self::datagram_queue_policy) until sufficient space is available.
     pub fn datagram_send_buffer_size(&mut self, value: usize) -> &mut Self {
         self.datagram_send_buffer_size = value;
         self
     }
 
+    /// Which datagrams to drop when the outgoing datagram buffer is full
+    ///
+    /// Queued datagrams are transmitted in order of priority, and in the order they were sent
+    /// within a priority. Defaults to [`DatagramDropPolicy::DropOldest`].
+    pub fn datagram_queue_policy(&mut self, value: DatagramDropPolicy) -> &mut Self {
+        self.datagram_queue_policy = value;
+        self
+    }
+
     /// Whether to force every packet number to be used
     ///
     /// By default, packet numbers are occasionally skipped to ensure peers aren't ACKing packets
@@ -335,6 +572,51 @@ impl TransportConfig {
         self.enable_segmentation_offload = enabled;
         self
     }
+
+    /// Send a custom transport parameter to the peer during the handshake
+    ///
+    /// Allows negotiating private extensions without modifying quinn. The peer's custom parameters
+    /// are available from its
+    /// [`TransportParameters`](crate::transport_parameters::TransportParameters) once received.
+    /// Setting the same `id` again replaces the previous value.
+    ///
+    /// Fails with [`ConfigError::ReservedTransportParameter`] if `id` is one quinn encodes itself,
+    /// or is reserved for greasing.
+    pub fn custom_transport_parameter(
+        &mut self,
+        id: VarInt,
+        value: impl Into<Bytes>,
+    ) -> Result<&mut Self, ConfigError> {
+        if TransportParameters::is_builtin(id) {
+            return Err(ConfigError::ReservedTransportParameter);
+        }
+        let value = value.into();
+        match self
+            .custom_transport_parameters
+            .iter_mut()
+            .find(|(x, _)| *x == id)
+        {
+            Some((_, existing)) => *existing = value,
+            None => self.custom_transport_parameters.push((id, value)),
+        }
+        Ok(self)
+    }
+
+    /// Handle the frames of an application-defined QUIC extension
+    ///
+    /// The extension's transport parameter is registered as if by
+    /// [`custom_transport_parameter()`](Self::custom_transport_parameter).
+    ///
+    /// **Unstable:** see [`FrameExtension`].
+    pub fn frame_extension(
+        &mut self,
+        extension: Arc<dyn FrameExtension>,
+    ) -> Result<&mut Self, ConfigError> {
+        let (id, value) = extension.transport_parameter();
+        self.custom_transport_parameter(id, value)?;
+        self.frame_extensions.push(extension);
+        Ok(self)
+    }
 }
 
 impl Default for TransportConfig {
@@ -353,28 +635,43 @@ impl Default for TransportConfig {
             stream_receive_window: STREAM_RWND.into(),
             receive_window: VarInt::MAX,
             send_window: (8 * STREAM_RWND).into(),
-            send_fairness: true,
+            per_stream_send_buffer: u64::MAX,
+            send_buffer_allocator: SendBufferAllocator::default(),
+            max_buffered_bytes: None,
+            window_autotune: None,
+            stream_window_update: StreamWindowUpdateConfig::default(),
+            stream_scheduler_factory: Arc::new(scheduler::RoundRobinConfig::default()),
 
             packet_threshold: 3,
             time_threshold: 9.0 / 8.0,
+            max_pto_backoff: 16,
             initial_rtt: Duration::from_millis(333), // per spec, intentionally distinct from EXPECTED_RTT
+            initial_rtt_and_cwnd_hint: None,
             initial_mtu: INITIAL_MTU,
             min_mtu: INITIAL_MTU,
             mtu_discovery_config: Some(MtuDiscoveryConfig::default()),
+            pacing: Some(PacingConfig::default()),
             ack_frequency_config: None,
+            receive_timestamps: false,
 
             persistent_congestion_threshold: 3,
             keep_alive_interval: None,
+            key_update_interval: None,
+            chaos_protection: false,
             crypto_buffer_size: 16 * 1024,
             allow_spin: true,
             datagram_receive_buffer_size: Some(STREAM_RWND as usize),
             datagram_send_buffer_size: 1024 * 1024,
+            datagram_queue_policy: DatagramDropPolicy::default(),
             #[cfg(test)]
             deterministic_packet_numbers: false,
 
             congestion_controller_factory: Arc::new(congestion::CubicConfig::default()),
 
             enable_segmentation_offload: true,
+
+            custom_transport_parameters: Vec::new(),
+            frame_extensions: Vec::new(),
         }
     }
 }
@@ -388,24 +685,38 @@ impl fmt::Debug for TransportConfig {
             stream_receive_window,
             receive_window,
             send_window,
-            send_fairness,
+            per_stream_send_buffer,
+            send_buffer_allocator,
+            max_buffered_bytes,
+            window_autotune,
+            stream_window_update,
+            stream_scheduler_factory: _,
             packet_threshold,
             time_threshold,
+            max_pto_backoff,
             initial_rtt,
+            initial_rtt_and_cwnd_hint,
             initial_mtu,
             min_mtu,
             mtu_discovery_config,
+            pacing,
             ack_frequency_config,
+            receive_timestamps,
             persistent_congestion_threshold,
             keep_alive_interval,
+            key_update_interval,
+            chaos_protection,
             crypto_buffer_size,
             allow_spin,
             datagram_receive_buffer_size,
             datagram_send_buffer_size,
+            datagram_queue_policy,
             #[cfg(test)]
                 deterministic_packet_numbers: _,
             congestion_controller_factory: _,
             enable_segmentation_offload,
+            custom_transport_parameters,
+            frame_extensions: _,
         } = self;
         fmt.debug_struct("TransportConfig")
             .field("max_concurrent_bidi_streams", max_concurrent_bidi_streams)
@@ -414,25 +725,39 @@ impl fmt::Debug for TransportConfig {
             .field("stream_receive_window", stream_receive_window)
             .field("receive_window", receive_window)
             .field("send_window", send_window)
-            .field("send_fairness", send_fairness)
+            .field("per_stream_send_buffer", per_stream_send_buffer)
+            .field("send_buffer_allocator", send_buffer_allocator)
+            .field("max_buffered_bytes", max_buffered_bytes)
+            .field("window_autotune", window_autotune)
+            .field("stream_window_update", stream_window_update)
+            .field("stream_scheduler_factory", &"[ opaque ]")
             .field("packet_threshold", packet_threshold)
             .field("time_threshold", time_threshold)
+            .field("max_pto_backoff", max_pto_backoff)
             .field("initial_rtt", initial_rtt)
+            .field("initial_rtt_and_cwnd_hint", initial_rtt_and_cwnd_hint)
             .field("initial_mtu", initial_mtu)
             .field("min_mtu", min_mtu)
             .field("mtu_discovery_config", mtu_discovery_config)
+            .field("pacing", pacing)
             .field("ack_frequency_config", ack_frequency_config)
+            .field("receive_timestamps", receive_timestamps)
             .field(
                 "persistent_congestion_threshold",
                 persistent_congestion_threshold,
             )
             .field("keep_alive_interval", keep_alive_interval)
+            .field("key_update_interval", key_update_interval)
+            .field("chaos_protection", chaos_protection)
             .field("crypto_buffer_size", crypto_buffer_size)
             .field("allow_spin", allow_spin)
             .field("datagram_receive_buffer_size", datagram_receive_buffer_size)
             .field("datagram_send_buffer_size", datagram_send_buffer_size)
+            .field("datagram_queue_policy", datagram_queue_policy)
             .field("congestion_controller_factory", &"[ opaque ]")
             .field("enable_segmentation_offload", enable_segmentation_offload)
+            .field("custom_transport_parameters", custom_transport_parameters)
+            .field("frame_extensions", &"[ opaque ]")
             .finish()
     }
 }
@@ -544,13 +869,15 @@ impl Default for AckFrequencyConfig {
 /// QUIC packets padded to a particular size (called PMTU probes), and waiting to see if the remote
 /// peer responds with an ACK. If an ACK is received, that means the probe arrived at the remote
 /// peer, which in turn means that the network path's MTU is of at least the packet's size. If the
-/// probe is lost, it is sent another 2 times before concluding that the MTU is lower than the
-/// packet's size.
+/// probe is lost, it is sent again, up to [`MtuDiscoveryConfig::max_probes`] times in total,
+/// before concluding that the MTU is lower than the packet's size.
 ///
 /// MTU discovery runs on a schedule (e.g. every 600 seconds) specified through
 /// [`MtuDiscoveryConfig::interval`]. The first run happens right after the handshake, and
 /// subsequent discoveries are scheduled to run when the interval has elapsed, starting from the
-/// last time when MTU discovery completed.
+/// last time when MTU discovery completed. A discovery also runs whenever the connection migrates
+/// to a new path, starting from [`TransportConfig::initial_mtu`], or from the current MTU if only
+/// the port of an IPv4 peer changed, as is typical of NAT rebinding.
 ///
 /// Since the search space for MTUs is quite big (the smallest possible MTU is 1200, and the highest
 /// is 65527), Quinn performs a binary search to keep the number of probes as low as possible. The
@@ -562,7 +889,8 @@ impl Default for AckFrequencyConfig {
 /// # Black hole detection
 ///
 /// If, at some point, the network path no longer accepts packets of the detected size, packet loss
-/// will eventually trigger black hole detection and reset the detected MTU to 1200. In that case,
+/// will eventually trigger black hole detection and reset the detected MTU to 1200. How much loss is
+/// tolerated beforehand is controlled by [`MtuDiscoveryConfig::black_hole_threshold`]. In that case,
 /// MTU discovery will be triggered after [`MtuDiscoveryConfig::black_hole_cooldown`] (ignoring the
 /// timer that was set based on [`MtuDiscoveryConfig::interval`]).
 ///
@@ -577,6 +905,8 @@ pub struct MtuDiscoveryConfig {
     pub(crate) upper_bound: u16,
     pub(crate) minimum_change: u16,
     pub(crate) black_hole_cooldown: Duration,
+    pub(crate) max_probes: usize,
+    pub(crate) black_hole_threshold: usize,
 }
 
 impl MtuDiscoveryConfig {
@@ -618,6 +948,26 @@ impl MtuDiscoveryConfig {
         self.minimum_change = value;
         self
     }
+
+    /// Specifies how many probes of a given size may be lost before that size is deemed
+    /// unsupported by the path. Defaults to 3, the `MAX_PROBES` recommended by [RFC
+    /// 8899](https://www.rfc-editor.org/rfc/rfc8899#section-5.1.2).
+    ///
+    /// Values below 1 are treated as 1.
+    pub fn max_probes(&mut self, value: usize) -> &mut Self {
+        self.max_probes = value.max(1);
+        self
+    }
+
+    /// Specifies how many bursts of lost packets that could be explained by a reduction of the
+    /// path's MTU are tolerated before a black hole is detected. Defaults to 3.
+    ///
+    /// Lower values recover from a reduced MTU faster, at the cost of spuriously falling back to
+    /// the minimum MTU more often when losses are caused by congestion.
+    pub fn black_hole_threshold(&mut self, value: usize) -> &mut Self {
+        self.black_hole_threshold = value;
+        self
+    }
 }
 
 impl Default for MtuDiscoveryConfig {
@@ -627,6 +977,262 @@ impl Default for MtuDiscoveryConfig {
             upper_bound: 1452,
             black_hole_cooldown: Duration::from_secs(60),
             minimum_change: 20,
+            max_probes: 3,
+            black_hole_threshold: 3,
+        }
+    }
+}
+
+/// Parameters governing packet pacing
+///
+/// Packets are released in bursts at regular intervals, at a rate slightly faster than one
+/// congestion window per round trip, unless the congestion controller supplies an explicit
+/// [`pacing_rate`](crate::congestion::Controller::pacing_rate). Bursts are sized to whole GSO
+/// batches where possible, so that high rates don't cost extra system calls.
+#[derive(Clone, Debug)]
+pub struct PacingConfig {
+    pub(crate) burst_interval: Duration,
+    pub(crate) min_burst_size: u64,
+    pub(crate) max_burst_size: u64,
+    pub(crate) rate_factor: f32,
+}
+
+impl PacingConfig {
+    /// Interval at which bursts of packets are released
+    ///
+    /// Too short intervals can't be met since timer accuracy in user space is limited, and the
+    /// tokens for an overshot interval are lost; too long intervals make pacing less effective.
+    /// Defaults to 2ms, since runtime timers might have 1ms precision.
+    pub fn burst_interval(&mut self, value: Duration) -> &mut Self {
+        self.burst_interval = value;
+        self
+    }
+
+    /// Minimum size of a burst, in packets
+    ///
+    /// Small bursts are less efficient, can't make use of GSO, and don't make effective use of the
+    /// path's buffer capacity. Defaults to 10.
+    pub fn min_burst_size(&mut self, value: u64) -> &mut Self {
+        self.min_burst_size = value.max(1);
+        self.max_burst_size = self.max_burst_size.max(self.min_burst_size);
+        self
+    }
+
+    /// Maximum size of a burst, in packets
+    ///
+    /// Large bursts might block the connection while they are being built. Defaults to 256, as
+    /// building 256 packets took 1ms in a benchmark.
+    pub fn max_burst_size(&mut self, value: u64) -> &mut Self {
+        self.max_burst_size = value.max(1);
+        self.min_burst_size = self.min_burst_size.min(self.max_burst_size);
+        self
+    }
+
+    /// Ratio of the pacing rate to one congestion window per smoothed round trip
+    ///
+    /// Rates above 1 allow the congestion window to be used up before the next round trip, even
+    /// if timers fire late. Ignored if the congestion controller supplies an explicit pacing rate.
+    /// Defaults to 1.25, as suggested by [RFC
+    /// 9002](https://www.rfc-editor.org/rfc/rfc9002#section-7.7).
+    pub fn rate_factor(&mut self, value: f32) -> &mut Self {
+        self.rate_factor = value;
+        self
+    }
+}
+
+impl Default for PacingConfig {
+    fn default() -> Self {
+        Self {
+            burst_interval: Duration::from_millis(2),
+            min_burst_size: 10,
+            max_burst_size: 256,
+            rate_factor: 1.25,
+        }
+    }
+}
+
+/// Controls whether and how much 0-RTT data a server accepts
+///
+/// 0-RTT data can be replayed by an attacker who captured it, so applications should only act on
+/// it if doing so more than once is harmless. Servers sharing session ticket keys across multiple
+/// nodes cannot detect replays locally, and can instead check a shared record of ClientHellos with
+/// an [`anti_replay`](Self::anti_replay) callback.
+#[derive(Clone)]
+pub struct ZeroRttPolicy {
+    pub(crate) enabled: bool,
+    pub(crate) max_early_data: u64,
+    pub(crate) anti_replay: Option<Arc<dyn AntiReplay>>,
+}
+
+impl ZeroRttPolicy {
+    /// Whether to accept 0-RTT at all
+    ///
+    /// Defaults to true.
+    pub fn enabled(&mut self, value: bool) -> &mut Self {
+        self.enabled = value;
+        self
+    }
+
+    /// Maximum number of bytes of 0-RTT packet payloads processed for each connection
+    ///
+    /// 0-RTT packets beyond this limit are discarded, and their contents retransmitted by the
+    /// client once the handshake completes. Defaults to unlimited, leaving 0-RTT data to be bounded
+    /// by flow control alone.
+    pub fn max_early_data(&mut self, value: u64) -> &mut Self {
+        self.max_early_data = value;
+        self
+    }
+
+    /// Reject 0-RTT for ClientHellos which `anti_replay` reports as replayed
+    pub fn anti_replay(&mut self, anti_replay: Arc<dyn AntiReplay>) -> &mut Self {
+        self.anti_replay = Some(anti_replay);
+        self
+    }
+}
+
+impl Default for ZeroRttPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_early_data: u64::MAX,
+            anti_replay: None,
+        }
+    }
+}
+
+impl fmt::Debug for ZeroRttPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ZeroRttPolicy")
+            .field("enabled", &self.enabled)
+            .field("max_early_data", &self.max_early_data)
+            .field(
+                "anti_replay",
+                &self.anti_replay.as_ref().map(|_| "[ elided ]"),
+            )
+            .finish()
+    }
+}
+
+/// Detects replayed 0-RTT attempts, as configured by [`ZeroRttPolicy::anti_replay()`]
+pub trait AntiReplay: Send + Sync {
+    /// Record a ClientHello attempting 0-RTT, returning whether it may be accepted
+    ///
+    /// Implementations typically remember each [`ClientHello::random`] for as long as session
+    /// tickets are valid, and return `false` if it has been seen before.
+    fn check(&self, hello: &ClientHello) -> bool;
+}
+
+/// Which datagrams to discard when the outgoing datagram buffer is full
+///
+/// Only consulted for datagrams sent with dropping allowed, e.g. by `Datagrams::send` with `drop`
+/// set. Otherwise, sending fails with `SendDatagramError::Blocked` instead.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum DatagramDropPolicy {
+    /// Discard the datagrams that were queued first
+    #[default]
+    DropOldest,
+    /// Discard the datagram being sent, leaving the queue untouched
+    DropNewest,
+    /// Discard datagrams of the lowest priority, oldest first
+    ///
+    /// If the datagram being sent has a lower priority than every queued datagram, it is discarded
+    /// instead.
+    DropLowestPriority,
+}
+
+/// How streams store written data until the peer acknowledges it
+///
+/// See [`TransportConfig::send_buffer_allocator()`].
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum SendBufferAllocator {
+    /// Keep the data of each write in an allocation of its own
+    ///
+    /// Chunks written with `SendStream::write_chunks` are retained without copying, and other
+    /// writes are copied into a buffer of their exact size.
+    #[default]
+    PerWrite,
+    /// Copy written data into shared chunks of `chunk_size` bytes
+    ///
+    /// Consecutive writes fill the same chunk, which is freed once all of its data has been
+    /// acknowledged. This saves an allocation per write, as well as the overhead of tracking each
+    /// write separately, for applications making many small writes. Chunks written with
+    /// `SendStream::write_chunks` are still retained without copying.
+    Arena {
+        /// Size of each chunk, in bytes
+        chunk_size: usize,
+    },
+}
+
+/// When to extend the flow control credit of receive streams
+///
+/// By default, credit is extended as the application reads data, and a `MAX_STREAM_DATA` frame
+/// is sent to announce it once the update is significant enough to be worth a frame. In
+/// [app-driven](Self::app_driven) mode, credit is only extended when the application asks for it.
+///
+/// See [`TransportConfig::stream_window_update()`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StreamWindowUpdateConfig {
+    pub(crate) threshold: f32,
+    pub(crate) min_delta: u64,
+    pub(crate) piggyback: bool,
+    pub(crate) app_driven: bool,
+}
+
+impl StreamWindowUpdateConfig {
+    /// Fraction of the stream receive window that must have been read before announcing the
+    /// updated window
+    ///
+    /// Lower values keep the peer's credit closer to the full window at the cost of more frequent
+    /// `MAX_STREAM_DATA` frames. Values are clamped to `0.0..=1.0`. Defaults to 0.125.
+    pub fn threshold(&mut self, value: f32) -> &mut Self {
+        self.threshold = value.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Minimum number of bytes by which the window must grow before announcing it
+    ///
+    /// Applied in addition to [`threshold`](Self::threshold), to avoid sending a frame for every
+    /// few bytes read on streams with small windows. Effectively capped at the stream receive
+    /// window, so that a peer that used up all its credit is unblocked once everything it sent
+    /// was read. Defaults to 0.
+    pub fn min_delta(&mut self, value: VarInt) -> &mut Self {
+        self.min_delta = value.into();
+        self
+    }
+
+    /// Whether to hold back window updates until a packet is sent for another reason
+    ///
+    /// Saves sending packets just to extend credit, e.g. while acknowledgements of the incoming
+    /// data are delayed. Updates are sent right away regardless once the peer has used up more
+    /// than half of its credit, so that it never becomes blocked waiting for one. Defaults to
+    /// `false`.
+    pub fn piggyback(&mut self, value: bool) -> &mut Self {
+        self.piggyback = value;
+        self
+    }
+
+    /// Whether credit is only extended when the application explicitly asks for it
+    ///
+    /// Streams start with the configured
+    /// [`stream_receive_window`](TransportConfig::stream_receive_window) of credit, and reading
+    /// data does not extend it further. Instead, the application grants additional credit with
+    /// `RecvStream::extend_credit`, which bounds the data the peer can send by what the
+    /// application is ready to accept, for strict backpressure. The
+    /// [`threshold`](Self::threshold) and [`min_delta`](Self::min_delta) do not apply to credit
+    /// granted this way. Defaults to `false`.
+    pub fn app_driven(&mut self, value: bool) -> &mut Self {
+        self.app_driven = value;
+        self
+    }
+}
+
+impl Default for StreamWindowUpdateConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0 / 8.0,
+            min_delta: 0,
+            piggyback: false,
+            app_driven: false,
         }
     }
 }
@@ -649,6 +1255,11 @@ pub struct EndpointConfig {
     pub(crate) min_reset_interval: Duration,
     /// Optional seed to be used internally for random number generation
     pub(crate) rng_seed: Option<[u8; 32]>,
+    pub(crate) max_buffered_bytes: Option<u64>,
+    pub(crate) max_gso_segments: usize,
+    #[cfg(feature = "qlog")]
+    pub(crate) qlog_factory: Option<Arc<dyn QlogFactory>>,
+    pub(crate) packet_inspector: Option<Arc<dyn PacketInspector>>,
 }
 
 impl EndpointConfig {
@@ -664,6 +1275,11 @@ impl EndpointConfig {
             grease_quic_bit: true,
             min_reset_interval: Duration::from_millis(20),
             rng_seed: None,
+            max_buffered_bytes: None,
+            max_gso_segments: usize::MAX,
+            #[cfg(feature = "qlog")]
+            qlog_factory: None,
+            packet_inspector: None,
         }
     }
 
@@ -683,6 +1299,26 @@ impl EndpointConfig {
         self
     }
 
+    /// Get the current connection ID generator factory
+    ///
+    /// Exposed to allow higher-level layers, e.g. the `quinn` crate, to wrap the generator of an
+    /// externally-defined `EndpointConfig`.
+    #[doc(hidden)]
+    pub fn get_cid_generator(
+        &self,
+    ) -> Arc<dyn Fn() -> Box<dyn ConnectionIdGenerator> + Send + Sync> {
+        self.connection_id_generator_factory.clone()
+    }
+
+    /// Generate connection IDs which QUIC-LB load balancers can route to this endpoint
+    ///
+    /// Shorthand for a [`cid_generator`](Self::cid_generator) producing
+    /// [`QuicLbConnectionIdGenerator`]s for `config`, which carries the server ID, key and nonce
+    /// length the load balancer was configured with.
+    pub fn quic_lb(&mut self, config: QuicLbConfig) -> &mut Self {
+        self.cid_generator(move || Box::new(QuicLbConnectionIdGenerator::new(config.clone())))
+    }
+
     /// Private key used to send authenticated connection resets to peers who were
     /// communicating with a previous instance of this endpoint.
     pub fn reset_key(&mut self, key: Arc<dyn HmacKey>) -> &mut Self {
@@ -720,7 +1356,18 @@ impl EndpointConfig {
         self.max_udp_payload_size.into()
     }
 
+    /// Get the current value of [`rng_seed`](Self::rng_seed)
+    #[doc(hidden)]
+    pub fn get_rng_seed(&self) -> Option<[u8; 32]> {
+        self.rng_seed
+    }
+
     /// Override supported QUIC versions
+    ///
+    /// Versions are listed in order of preference. When a server answers a client's first flight
+    /// with a Version Negotiation packet, the client restarts the handshake in the first version
+    /// of this list the server offered, and verifies the choice against the server's
+    /// `version_information` transport parameter to detect downgrade attacks (RFC 9368).
     pub fn supported_versions(&mut self, supported_versions: Vec<u32>) -> &mut Self {
         self.supported_versions = supported_versions;
         self
@@ -761,6 +1408,68 @@ impl EndpointConfig {
         self.rng_seed = seed;
         self
     }
+
+    /// Maximum number of bytes of stream data buffered across all of an endpoint's connections
+    ///
+    /// Accounted like [`TransportConfig::max_buffered_bytes()`], with every connection of the
+    /// endpoint ceasing to issue connection-level flow control credit as the total approaches this
+    /// limit. This keeps memory use predictable when serving large numbers of connections. Newly
+    /// established connections still grant their peer the initial
+    /// [`receive_window`](TransportConfig::receive_window), so that should be kept small enough for
+    /// the limit to be meaningful.
+    ///
+    /// Unlimited by default.
+    pub fn max_buffered_bytes(&mut self, value: Option<u64>) -> &mut Self {
+        self.max_buffered_bytes = value;
+        self
+    }
+
+    /// Whether to use "Generic Segmentation Offload" to send datagrams in batches, and how many
+    /// datagrams a single batch may contain
+    ///
+    /// Batches are additionally limited by what the socket supports, and by
+    /// [`TransportConfig::enable_segmentation_offload()`] for individual connections. Should the
+    /// network interface reject a batch at runtime, its datagrams are sent individually and
+    /// segmentation offload is disabled for the socket.
+    ///
+    /// Enabled without a limit by default.
+    pub fn gso(&mut self, enabled: bool, max_segments: usize) -> &mut Self {
+        self.max_gso_segments = match enabled {
+            true => max_segments.max(1),
+            false => 1,
+        };
+        self
+    }
+
+    /// Get the maximum number of datagrams sent in a single batch, as configured by
+    /// [`gso()`](Self::gso)
+    ///
+    /// Exposed for the same reason as
+    /// [`get_max_udp_payload_size()`](Self::get_max_udp_payload_size).
+    #[doc(hidden)]
+    pub fn get_max_gso_segments(&self) -> usize {
+        self.max_gso_segments
+    }
+
+    /// Record qlog traces of connections to destinations supplied by `factory`
+    ///
+    /// Traces cover packets sent and received, loss recovery and flow control updates from the
+    /// peer, and are written incrementally as events occur. A connection whose destination fails
+    /// to accept writes stops being traced.
+    #[cfg(feature = "qlog")]
+    pub fn qlog_writer(&mut self, factory: Arc<dyn QlogFactory>) -> &mut Self {
+        self.qlog_factory = Some(factory);
+        self
+    }
+
+    /// Pass summaries of every decrypted packet sent or received by the endpoint's connections to
+    /// `inspector`
+    ///
+    /// For debugging and protocol analysis; see [`PacketInspector`].
+    pub fn packet_inspector(&mut self, inspector: Arc<dyn PacketInspector>) -> &mut Self {
+        self.packet_inspector = Some(inspector);
+        self
+    }
 }
 
 impl fmt::Debug for EndpointConfig {
@@ -772,6 +1481,9 @@ impl fmt::Debug for EndpointConfig {
             .field("supported_versions", &self.supported_versions)
             .field("grease_quic_bit", &self.grease_quic_bit)
             .field("rng_seed", &self.rng_seed)
+            .field("max_buffered_bytes", &self.max_buffered_bytes)
+            .field("max_gso_segments", &self.max_gso_segments)
+            .field("packet_inspector", &self.packet_inspector.is_some())
             .finish()
     }
 }
@@ -800,6 +1512,9 @@ pub struct ServerConfig {
     /// Transport configuration to use for incoming connections
     pub transport: Arc<TransportConfig>,
 
+    /// Chooses the transport configuration based on the negotiated application protocol
+    pub(crate) alpn_transport: Option<Arc<AlpnTransportFn>>,
+
     /// TLS configuration used for incoming connections.
     ///
     /// Must be set to use TLS 1.3 only.
@@ -811,6 +1526,18 @@ pub struct ServerConfig {
     /// Microseconds after a stateless retry token was issued for which it's considered valid.
     pub(crate) retry_token_lifetime: Duration,
 
+    /// Issues and validates address validation tokens, if not the default provider
+    pub(crate) token_provider: Option<Arc<dyn TokenProvider>>,
+
+    /// Duration after a NEW_TOKEN token was issued for which it's considered valid
+    pub(crate) new_token_lifetime: Duration,
+
+    /// NEW_TOKEN tokens already accepted by the default token provider
+    pub(crate) used_tokens: Arc<TokenLog>,
+
+    /// Number of NEW_TOKEN frames sent to each client once the handshake is confirmed
+    pub(crate) new_token_count: u32,
+
     /// Whether to allow clients to migrate to new addresses
     ///
     /// Improves behavior for clients that move between different internet connections or suffer NAT
@@ -823,6 +1550,8 @@ pub struct ServerConfig {
     pub(crate) max_incoming: usize,
     pub(crate) incoming_buffer_size: u64,
     pub(crate) incoming_buffer_size_total: u64,
+
+    pub(crate) zero_rtt_policy: ZeroRttPolicy,
 }
 
 impl ServerConfig {
@@ -833,10 +1562,15 @@ impl ServerConfig {
     ) -> Self {
         Self {
             transport: Arc::new(TransportConfig::default()),
+            alpn_transport: None,
             crypto,
 
             token_key,
             retry_token_lifetime: Duration::from_secs(15),
+            token_provider: None,
+            new_token_lifetime: Duration::from_secs(2 * 7 * 24 * 60 * 60),
+            used_tokens: Arc::new(TokenLog::default()),
+            new_token_count: 2,
 
             migration: true,
 
@@ -846,6 +1580,8 @@ impl ServerConfig {
             max_incoming: 1 << 16,
             incoming_buffer_size: 10 << 20,
             incoming_buffer_size_total: 100 << 20,
+
+            zero_rtt_policy: ZeroRttPolicy::default(),
         }
     }
 
@@ -855,6 +1591,24 @@ impl ServerConfig {
         self
     }
 
+    /// Choose the [`TransportConfig`] of each connection based on its application protocol
+    ///
+    /// `f` is called with the protocol the crypto layer will negotiate with the client, e.g. to
+    /// give `h3` connections larger flow control windows than those of a control protocol sharing
+    /// the endpoint. Connections for which `f` returns `None`, which negotiate no protocol, or
+    /// whose protocol can't be determined before the handshake use
+    /// [`transport_config()`](Self::transport_config).
+    ///
+    /// The protocol is predicted from the client's first Initial packet using
+    /// [`crypto::ServerConfig::select_alpn()`].
+    pub fn alpn_transport_config(
+        &mut self,
+        f: impl Fn(&[u8]) -> Option<Arc<TransportConfig>> + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.alpn_transport = Some(Arc::new(f));
+        self
+    }
+
     /// Private key used to authenticate data included in handshake tokens.
     pub fn token_key(&mut self, value: Arc<dyn HandshakeTokenKey>) -> &mut Self {
         self.token_key = value;
@@ -867,6 +1621,45 @@ impl ServerConfig {
         self
     }
 
+    /// Duration after a token sent in a NEW_TOKEN frame was issued for which it's considered valid
+    ///
+    /// Defaults to 2 weeks.
+    pub fn new_token_lifetime(&mut self, value: Duration) -> &mut Self {
+        self.new_token_lifetime = value;
+        self
+    }
+
+    /// Number of tokens to send to each client in NEW_TOKEN frames once the handshake is confirmed
+    ///
+    /// Clients can present these tokens when they next connect to have their address validated
+    /// without a Retry round trip. A client needs a fresh token for every connection it makes to
+    /// avoid them being linkable, and the server accepts each token only once. Defaults to 2.
+    /// Setting this to 0 disables NEW_TOKEN frames.
+    pub fn new_token_count(&mut self, value: u32) -> &mut Self {
+        self.new_token_count = value;
+        self
+    }
+
+    /// Issue and validate address validation tokens with a custom [`TokenProvider`]
+    ///
+    /// Useful when tokens issued by one server must be accepted by others, e.g. behind a load
+    /// balancer. When set, [`token_key()`](Self::token_key),
+    /// [`retry_token_lifetime()`](Self::retry_token_lifetime) and
+    /// [`new_token_lifetime()`](Self::new_token_lifetime) have no effect.
+    pub fn token_provider(&mut self, value: Arc<dyn TokenProvider>) -> &mut Self {
+        self.token_provider = Some(value);
+        self
+    }
+
+    pub(crate) fn default_token_provider(&self) -> DefaultTokenProvider<'_> {
+        DefaultTokenProvider {
+            key: &*self.token_key,
+            used: &self.used_tokens,
+            retry_token_lifetime: self.retry_token_lifetime,
+            new_token_lifetime: self.new_token_lifetime,
+        }
+    }
+
     /// Whether to allow clients to migrate to new addresses
     ///
     /// Improves behavior for clients that move between different internet connections or suffer NAT
@@ -876,6 +1669,34 @@ impl ServerConfig {
         self
     }
 
+    /// Whether and how much 0-RTT data to accept from clients resuming a session
+    ///
+    /// See [`ZeroRttPolicy`]. Defaults to accepting 0-RTT from all clients.
+    pub fn zero_rtt_policy(&mut self, policy: ZeroRttPolicy) -> &mut Self {
+        self.zero_rtt_policy = policy;
+        self
+    }
+
+    /// The preferred addresses that will be communicated to clients during handshaking
+    ///
+    /// Once the handshake is confirmed, a client able to reach the address of the same family as
+    /// the one it connected to migrates there, e.g. to move from an anycast address to a unicast
+    /// address of this particular server. The endpoint must receive datagrams sent to these
+    /// addresses, and be able to send from them, which usually means its socket is bound to a
+    /// wildcard address on the same port.
+    ///
+    /// Equivalent to calling both [`preferred_address_v4()`](Self::preferred_address_v4) and
+    /// [`preferred_address_v6()`](Self::preferred_address_v6).
+    pub fn preferred_address(
+        &mut self,
+        v4: Option<SocketAddrV4>,
+        v6: Option<SocketAddrV6>,
+    ) -> &mut Self {
+        self.preferred_address_v4 = v4;
+        self.preferred_address_v6 = v6;
+        self
+    }
+
     /// The preferred IPv4 address that will be communicated to clients during handshaking.
     /// If the client is able to reach this address, it will switch to it.
     pub fn preferred_address_v4(&mut self, address: Option<SocketAddrV4>) -> &mut Self {
@@ -937,6 +1758,20 @@ impl ServerConfig {
         self.incoming_buffer_size_total = incoming_buffer_size_total;
         self
     }
+
+    /// Log the TLS secrets of incoming connections to the file named by the `SSLKEYLOGFILE`
+    /// environment variable, in the NSS key log format
+    ///
+    /// Lets tools such as Wireshark decrypt captured traffic. Nothing is logged if the variable is
+    /// unset when this is called, or if the [`crypto`](Self::crypto) configuration doesn't support
+    /// key logging. Secrets in the log compromise the confidentiality of the connections, so this
+    /// should only be used for debugging.
+    pub fn enable_key_log(&mut self) -> &mut Self {
+        if let Some(crypto) = self.crypto.with_key_log() {
+            self.crypto = crypto;
+        }
+        self
+    }
 }
 
 #[cfg(any(feature = "rustls-aws-lc-rs", feature = "rustls-ring"))]
@@ -952,6 +1787,38 @@ impl ServerConfig {
             cert_chain, key,
         )?)))
     }
+
+    /// Create a server config like [`with_single_cert()`](Self::with_single_cert), accepting only
+    /// the given key exchange groups, in order of preference
+    ///
+    /// Post-quantum hybrid groups such as `X25519MLKEM768` can be used if the crypto provider
+    /// implements them. The negotiated group is reported by
+    /// [`HandshakeData::key_exchange_group`](crypto::rustls::HandshakeData::key_exchange_group).
+    pub fn with_single_cert_and_kx_groups(
+        cert_chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+        kx_groups: Vec<&'static dyn SupportedKxGroup>,
+    ) -> Result<Self, rustls::Error> {
+        Ok(Self::with_crypto(Arc::new(
+            QuicServerConfig::with_provider(cert_chain, key, provider_with_kx_groups(kx_groups)?)?,
+        )))
+    }
+
+    /// Create a server config like [`with_single_cert()`](Self::with_single_cert), authenticating
+    /// clients with `verifier`
+    ///
+    /// Typically used with a [`rustls::server::WebPkiClientVerifier`] for mutual TLS. The
+    /// certificates of authenticated clients are available as
+    /// [`HandshakeData::peer_certificates`](crypto::rustls::HandshakeData::peer_certificates).
+    pub fn with_client_cert_verifier(
+        cert_chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+        verifier: Arc<dyn ClientCertVerifier>,
+    ) -> Result<Self, rustls::Error> {
+        Ok(Self::with_crypto(Arc::new(
+            QuicServerConfig::with_client_cert_verifier(cert_chain, key, verifier)?,
+        )))
+    }
 }
 
 #[cfg(any(feature = "aws-lc-rs", feature = "ring"))]
@@ -979,9 +1846,19 @@ impl fmt::Debug for ServerConfig {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt.debug_struct("ServerConfig<T>")
             .field("transport", &self.transport)
+            .field(
+                "alpn_transport",
+                &self.alpn_transport.as_ref().map(|_| "[ elided ]"),
+            )
             .field("crypto", &"ServerConfig { elided }")
             .field("token_key", &"[ elided ]")
             .field("retry_token_lifetime", &self.retry_token_lifetime)
+            .field(
+                "token_provider",
+                &self.token_provider.as_ref().map(|_| "[ elided ]"),
+            )
+            .field("new_token_lifetime", &self.new_token_lifetime)
+            .field("new_token_count", &self.new_token_count)
             .field("migration", &self.migration)
             .field("preferred_address_v4", &self.preferred_address_v4)
             .field("preferred_address_v6", &self.preferred_address_v6)
@@ -991,10 +1868,14 @@ impl fmt::Debug for ServerConfig {
                 "incoming_buffer_size_total",
                 &self.incoming_buffer_size_total,
             )
+            .field("zero_rtt_policy", &self.zero_rtt_policy)
             .finish()
     }
 }
 
+/// Callback choosing a [`TransportConfig`] by application protocol
+type AlpnTransportFn = dyn Fn(&[u8]) -> Option<Arc<TransportConfig>> + Send + Sync;
+
 /// Configuration for outgoing connections
 ///
 /// Default values should be suitable for most internet applications.
@@ -1012,6 +1893,15 @@ pub struct ClientConfig {
 
     /// QUIC protocol version to use
     pub(crate) version: u32,
+
+    /// Where tokens from NEW_TOKEN frames are kept for future connections
+    pub(crate) token_store: Arc<dyn TokenStore>,
+
+    /// Whether to restart the handshake when the server demands a Retry
+    pub(crate) allow_retry: bool,
+
+    /// Whether to restart the handshake in another version offered by the server
+    pub(crate) allow_version_negotiation: bool,
 }
 
 impl ClientConfig {
@@ -1024,6 +1914,9 @@ impl ClientConfig {
                 RandomConnectionIdGenerator::new(MAX_CID_SIZE).generate_cid()
             }),
             version: 1,
+            token_store: Arc::new(TokenMemoryCache::default()),
+            allow_retry: true,
+            allow_version_negotiation: true,
         }
     }
 
@@ -1050,10 +1943,61 @@ impl ClientConfig {
     }
 
     /// Set the QUIC version to use
+    ///
+    /// Defaults to [`QUIC_V1`](crate::QUIC_V1). If the server does not support this version, the
+    /// connection falls back to another one from
+    /// [`EndpointConfig::supported_versions()`].
     pub fn version(&mut self, version: u32) -> &mut Self {
         self.version = version;
         self
     }
+
+    /// Whether to follow a server's Retry packet, restarting the handshake to prove our address
+    ///
+    /// If disabled, a server demanding a Retry fails the connection with
+    /// [`ConnectionError::RetryRefused`](crate::ConnectionError::RetryRefused), which allows
+    /// clients with a fallback to give up early on servers under load. Servers may only demand a
+    /// single Retry per connection. Defaults to `true`.
+    pub fn allow_retry(&mut self, value: bool) -> &mut Self {
+        self.allow_retry = value;
+        self
+    }
+
+    /// Whether to fall back to another version offered by a server rejecting [`version()`]
+    ///
+    /// If disabled, a Version Negotiation packet fails the connection with
+    /// [`ConnectionError::VersionMismatch`](crate::ConnectionError::VersionMismatch). At most one
+    /// round of negotiation takes place per connection. Defaults to `true`.
+    ///
+    /// [`version()`]: Self::version
+    pub fn allow_version_negotiation(&mut self, value: bool) -> &mut Self {
+        self.allow_version_negotiation = value;
+        self
+    }
+
+    /// Where to keep tokens received from servers in NEW_TOKEN frames
+    ///
+    /// Tokens are presented when connecting to the same server name again, allowing the server to
+    /// skip validating the client's address with a Retry round trip. Connections made with clones
+    /// of this config share the store. Defaults to a [`TokenMemoryCache`].
+    pub fn token_store(&mut self, store: Arc<dyn TokenStore>) -> &mut Self {
+        self.token_store = store;
+        self
+    }
+
+    /// Log the TLS secrets of outgoing connections to the file named by the `SSLKEYLOGFILE`
+    /// environment variable, in the NSS key log format
+    ///
+    /// Lets tools such as Wireshark decrypt captured traffic. Nothing is logged if the variable is
+    /// unset when this is called, or if the cryptographic configuration doesn't support key
+    /// logging. Secrets in the log compromise the confidentiality of the connections, so this
+    /// should only be used for debugging.
+    pub fn enable_key_log(&mut self) -> &mut Self {
+        if let Some(crypto) = self.crypto.with_key_log() {
+            self.crypto = crypto;
+        }
+        self
+    }
 }
 
 #[cfg(any(feature = "rustls-aws-lc-rs", feature = "rustls-ring"))]
@@ -1074,6 +2018,26 @@ impl ClientConfig {
             WebPkiServerVerifier::builder_with_provider(roots, configured_provider()).build()?,
         ))))
     }
+
+    /// Create a client configuration like
+    /// [`with_root_certificates()`](Self::with_root_certificates), offering only the given key
+    /// exchange groups, in order of preference
+    ///
+    /// Post-quantum hybrid groups such as `X25519MLKEM768` can be used if the crypto provider
+    /// implements them. The negotiated group is reported by
+    /// [`HandshakeData::key_exchange_group`](crypto::rustls::HandshakeData::key_exchange_group).
+    pub fn with_root_certificates_and_kx_groups(
+        roots: Arc<rustls::RootCertStore>,
+        kx_groups: Vec<&'static dyn SupportedKxGroup>,
+    ) -> Result<Self, rustls::Error> {
+        let provider = provider_with_kx_groups(kx_groups)?;
+        let verifier = WebPkiServerVerifier::builder_with_provider(roots, provider.clone())
+            .build()
+            .map_err(|e| rustls::Error::General(e.to_string()))?;
+        Ok(Self::new(Arc::new(
+            crypto::rustls::QuicClientConfig::with_provider(verifier, provider),
+        )))
+    }
 }
 
 impl fmt::Debug for ClientConfig {
@@ -1082,6 +2046,8 @@ impl fmt::Debug for ClientConfig {
             .field("transport", &self.transport)
             .field("crypto", &"ClientConfig { elided }")
             .field("version", &self.version)
+            .field("allow_retry", &self.allow_retry)
+            .field("allow_version_negotiation", &self.allow_version_negotiation)
             .finish_non_exhaustive()
     }
 }
@@ -1093,6 +2059,9 @@ pub enum ConfigError {
     /// Value exceeds supported bounds
     #[error("value exceeds supported bounds")]
     OutOfBounds,
+    /// Transport parameter identifier is used by quinn itself or reserved
+    #[error("transport parameter identifier is reserved")]
+    ReservedTransportParameter,
 }
 
 impl From<TryFromIntError> for ConfigError {
@@ -1,19 +1,36 @@
 //! Logic for controlling the rate at which data is sent
 
 use crate::connection::RttEstimator;
+use crate::EcnCodepoint;
 use std::any::Any;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 mod bbr;
+mod bbr3;
 mod cubic;
+mod hystart;
 mod new_reno;
+mod prague;
 
 pub use bbr::{Bbr, BbrConfig};
+pub use bbr3::{Bbr3, Bbr3Config};
 pub use cubic::{Cubic, CubicConfig};
 pub use new_reno::{NewReno, NewRenoConfig};
+pub use prague::{Prague, PragueConfig};
 
 /// Common interface for different congestion controllers
+///
+/// Implementations outside of this crate are fully supported, and are used just like the built-in
+/// controllers by supplying a [`ControllerFactory`] to
+/// [`TransportConfig::congestion_controller_factory()`](crate::TransportConfig::congestion_controller_factory).
+/// Any methods added to this trait in the future will have default implementations.
+///
+/// For each batch of acknowledgments, `on_ack` is called once per newly acknowledged packet, then
+/// `on_end_acks` once. `on_congestion_event` is called once per batch of packets deemed lost, and
+/// `on_ecn_congestion` once per acknowledgment reporting new congestion experienced marks. `window`
+/// is consulted before every packet that counts towards the
+/// data in flight is sent.
 pub trait Controller: Send + Sync {
     /// One or more packets were just sent
     #[allow(unused_variables)]
@@ -59,6 +76,26 @@ pub trait Controller: Send + Sync {
         lost_bytes: u64,
     );
 
+    /// The peer reported packets as marked congestion experienced (CE) by the network
+    ///
+    /// `sent` is the time the largest newly acknowledged packet was sent, and `ce_marks` the number
+    /// of newly reported marks. Defaults to responding as to a loss, through `on_congestion_event`.
+    #[allow(unused_variables)]
+    fn on_ecn_congestion(&mut self, now: Instant, sent: Instant, ce_marks: u64) {
+        self.on_congestion_event(now, sent, false, 0);
+    }
+
+    /// The peer reported when it received a newly acknowledged packet
+    ///
+    /// `sent` is the time the packet was sent, and `received` the time it was received, measured by
+    /// the peer's clock from an arbitrary basis. As the clocks are not synchronized, only the
+    /// variation of `received - sent` between packets is meaningful, e.g. to track queueing delay
+    /// on the forward path alone. Only called when
+    /// [`TransportConfig::receive_timestamps()`](crate::TransportConfig::receive_timestamps) is
+    /// enabled and the peer supports the extension. Ignored by default.
+    #[allow(unused_variables)]
+    fn on_receive_timestamp(&mut self, sent: Instant, received: Duration) {}
+
     /// The known MTU for the current network path has been updated
     fn on_mtu_update(&mut self, new_mtu: u16);
 
@@ -71,6 +108,40 @@ pub trait Controller: Send + Sync {
     /// Initial congestion window
     fn initial_window(&self) -> u64;
 
+    /// Rate at which packets should be paced, in bytes per second
+    ///
+    /// If `None`, the pacing rate is derived from the congestion window and the smoothed RTT.
+    fn pacing_rate(&self) -> Option<u64> {
+        None
+    }
+
+    /// Estimated bandwidth available on the path, in bytes per second
+    ///
+    /// Reported through [`PathStats::bandwidth`](crate::PathStats::bandwidth). If `None`, the
+    /// estimate is derived from the congestion window and the smoothed RTT.
+    fn bandwidth_estimate(&self) -> Option<u64> {
+        None
+    }
+
+    /// Characteristics of a previous connection's path were supplied through
+    /// [`TransportConfig::initial_rtt_and_cwnd_hint()`](crate::TransportConfig::initial_rtt_and_cwnd_hint)
+    ///
+    /// Called once, before any packet is sent. Controllers may use the hint to grow the window
+    /// faster than slow start would once the current path is found to resemble the previous one,
+    /// as in careful resume. Ignored by default.
+    #[allow(unused_variables)]
+    fn on_resume_hint(&mut self, rtt: Duration, window: u64) {}
+
+    /// Codepoint to mark outgoing packets with while explicit congestion notification is in use
+    ///
+    /// Controllers responding to congestion experienced marks as specified for L4S ([RFC 9331])
+    /// use ECT(1), which lets network queues tell their traffic apart.
+    ///
+    /// [RFC 9331]: https://www.rfc-editor.org/rfc/rfc9331.html
+    fn ecn_codepoint(&self) -> EcnCodepoint {
+        EcnCodepoint::Ect0
+    }
+
     /// Returns Self for use in down-casting to extract implementation details
     fn into_any(self: Box<Self>) -> Box<dyn Any>;
 }
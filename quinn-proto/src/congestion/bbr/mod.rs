@@ -11,7 +11,7 @@ use crate::connection::RttEstimator;
 
 use super::{Controller, ControllerFactory, BASE_DATAGRAM_SIZE};
 
-mod bw_estimation;
+pub(super) mod bw_estimation;
 mod min_max;
 
 /// Experimental! Use at your own risk.
@@ -493,6 +493,10 @@ impl Controller for Bbr {
         self.config.initial_window
     }
 
+    fn bandwidth_estimate(&self) -> Option<u64> {
+        Some(self.max_bandwidth.get_estimate()).filter(|&bw| bw != 0)
+    }
+
     fn into_any(self: Box<Self>) -> Box<dyn Any> {
         self
     }
@@ -0,0 +1,545 @@
+use std::any::Any;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::{Rng, SeedableRng};
+
+use super::bbr::bw_estimation::BandwidthEstimation;
+use super::{Controller, ControllerFactory, BASE_DATAGRAM_SIZE};
+use crate::connection::RttEstimator;
+
+/// Experimental! Use at your own risk.
+///
+/// Version 3 of BBR. In addition to the bandwidth and round-trip time model of
+/// [`Bbr`](super::Bbr), it bounds the amount of data in flight in response to loss, which makes it
+/// considerably fairer to loss-based controllers such as [`Cubic`](super::Cubic) and avoids
+/// persistent loss on links with shallow buffers.
+/// Based on <https://datatracker.ietf.org/doc/html/draft-ietf-ccwg-bbr>.
+///
+/// quinn paces transmissions according to the congestion window, so unlike the reference
+/// algorithm the gains of each phase are applied to the window alone.
+#[derive(Debug, Clone)]
+pub struct Bbr3 {
+    config: Arc<Bbr3Config>,
+    current_mtu: u64,
+    mode: Mode,
+    /// Windowed maximum of recent delivery rate samples, in bytes per second
+    max_bw: BandwidthEstimation,
+    /// Short-term lower bound on the delivery rate, lowered in response to loss
+    bw_lo: u64,
+    /// Long-term upper bound on data in flight, found by probing until loss becomes excessive
+    inflight_hi: u64,
+    /// Short-term lower bound on data in flight, lowered in response to loss
+    inflight_lo: u64,
+    min_rtt: Duration,
+    min_rtt_stamp: Option<Instant>,
+    cwnd: u64,
+    init_cwnd: u64,
+    min_cwnd: u64,
+    /// Bytes acknowledged over the lifetime of the connection
+    delivered: u64,
+    prev_in_flight: u64,
+    round: RoundState,
+    /// Delivery rate at the end of the most recent round of startup that grew it significantly
+    full_bw: u64,
+    /// Number of rounds of startup without significant delivery rate growth
+    full_bw_count: u8,
+    full_bw_reached: bool,
+    /// When the current bandwidth probing cycle began
+    cycle_start: Option<Instant>,
+    /// Time to wait after the start of a cycle before probing for bandwidth again
+    probe_wait: Duration,
+    /// Rounds elapsed since bandwidth was last probed
+    rounds_since_probe: u64,
+    /// Round in which the current phase of bandwidth probing began
+    phase_start_round: u64,
+    /// When `ProbeRtt` may be left, once in flight data has drained to the target
+    probe_rtt_done_at: Option<Instant>,
+    /// Congestion window before entering `ProbeRtt`, restored afterwards
+    prior_cwnd: u64,
+    random_number_generator: rand::rngs::StdRng,
+}
+
+impl Bbr3 {
+    /// Construct a state using the given `config` and current MTU `current_mtu`
+    pub fn new(config: Arc<Bbr3Config>, current_mtu: u16) -> Self {
+        let initial_window = config.initial_window;
+        Self {
+            config,
+            current_mtu: current_mtu as u64,
+            mode: Mode::Startup,
+            max_bw: BandwidthEstimation::default(),
+            bw_lo: u64::MAX,
+            inflight_hi: u64::MAX,
+            inflight_lo: u64::MAX,
+            min_rtt: Duration::ZERO,
+            min_rtt_stamp: None,
+            cwnd: initial_window,
+            init_cwnd: initial_window,
+            min_cwnd: calculate_min_window(current_mtu as u64),
+            delivered: 0,
+            prev_in_flight: 0,
+            round: RoundState::default(),
+            full_bw: 0,
+            full_bw_count: 0,
+            full_bw_reached: false,
+            cycle_start: None,
+            probe_wait: Duration::ZERO,
+            rounds_since_probe: 0,
+            phase_start_round: 0,
+            probe_rtt_done_at: None,
+            prior_cwnd: 0,
+            random_number_generator: rand::rngs::StdRng::from_entropy(),
+        }
+    }
+
+    /// Delivery rate used by the model, in bytes per second
+    fn bw(&self) -> u64 {
+        self.max_bw.get_estimate().min(self.bw_lo)
+    }
+
+    /// Amount of data in flight that would fill the path `gain` times over
+    fn bdp(&self, gain: f64) -> u64 {
+        let bw = self.bw();
+        if bw == 0 || self.min_rtt.is_zero() {
+            return self.init_cwnd;
+        }
+        let bdp = bw as f64 * self.min_rtt.as_secs_f64();
+        ((gain * bdp) as u64).max(self.min_cwnd)
+    }
+
+    /// `inflight_hi`, less a share left free for competing flows
+    fn inflight_with_headroom(&self) -> u64 {
+        if self.inflight_hi == u64::MAX {
+            return u64::MAX;
+        }
+        let headroom = ((K_HEADROOM * self.inflight_hi as f64) as u64).max(self.current_mtu);
+        self.inflight_hi.saturating_sub(headroom).max(self.min_cwnd)
+    }
+
+    fn cwnd_gain(&self) -> f64 {
+        match self.mode {
+            Mode::ProbeBw(Phase::Up) => K_PROBE_UP_CWND_GAIN,
+            _ => K_CWND_GAIN,
+        }
+    }
+
+    /// Whether the loss seen in the current round suggests too much data is in flight
+    fn is_inflight_too_high(&self) -> bool {
+        let total = self.round.delivered + self.round.lost;
+        total != 0 && self.round.lost as f64 > K_LOSS_THRESH * total as f64
+    }
+
+    /// Bound the data in flight after excessive loss while probing
+    fn handle_inflight_too_high(&mut self, now: Instant) {
+        self.inflight_hi = self
+            .prev_in_flight
+            .max((self.bdp(1.0) as f64 * K_BETA) as u64)
+            .max(self.min_cwnd);
+        match self.mode {
+            Mode::Startup => self.full_bw_reached = true,
+            Mode::ProbeBw(Phase::Up) => self.start_probe_bw_down(now),
+            _ => {}
+        }
+    }
+
+    fn reset_lower_bounds(&mut self) {
+        self.bw_lo = u64::MAX;
+        self.inflight_lo = u64::MAX;
+    }
+
+    /// Lower the short-term model after a round with loss, outside of bandwidth probing
+    fn adapt_lower_bounds(&mut self, now: Instant) {
+        if matches!(self.mode, Mode::Startup | Mode::ProbeBw(Phase::Up)) {
+            return;
+        }
+        let round_duration = self
+            .round
+            .start
+            .map_or(Duration::ZERO, |start| now.saturating_duration_since(start));
+        let bw_latest =
+            BandwidthEstimation::bw_from_delta(self.round.delivered, round_duration).unwrap_or(0);
+        if self.bw_lo == u64::MAX {
+            self.bw_lo = self.max_bw.get_estimate();
+        }
+        if self.inflight_lo == u64::MAX {
+            self.inflight_lo = self.cwnd;
+        }
+        self.bw_lo = bw_latest.max((self.bw_lo as f64 * K_BETA) as u64);
+        self.inflight_lo = self
+            .round
+            .delivered
+            .max((self.inflight_lo as f64 * K_BETA) as u64)
+            .max(self.min_cwnd);
+    }
+
+    /// <https://datatracker.ietf.org/doc/html/draft-ietf-ccwg-bbr#section-5.3.1.2>
+    fn check_full_bw_reached(&mut self, app_limited: bool) {
+        if self.full_bw_reached || app_limited {
+            return;
+        }
+        let bw = self.max_bw.get_estimate();
+        if bw as f64 >= self.full_bw as f64 * K_STARTUP_GROWTH_TARGET {
+            self.full_bw = bw;
+            self.full_bw_count = 0;
+            return;
+        }
+        self.full_bw_count += 1;
+        if self.full_bw_count >= K_ROUND_TRIPS_WITHOUT_GROWTH_BEFORE_EXITING_STARTUP {
+            self.full_bw_reached = true;
+        }
+    }
+
+    fn start_probe_bw_down(&mut self, now: Instant) {
+        self.mode = Mode::ProbeBw(Phase::Down);
+        self.phase_start_round = self.round.count;
+        self.rounds_since_probe = 0;
+        self.cycle_start = Some(now);
+        // Randomize the wait so that competing flows don't synchronize their probing
+        self.probe_wait = K_PROBE_WAIT_BASE
+            + self
+                .random_number_generator
+                .gen_range(Duration::ZERO..K_PROBE_WAIT_RAND);
+    }
+
+    fn start_probe_bw_phase(&mut self, phase: Phase) {
+        self.mode = Mode::ProbeBw(phase);
+        self.phase_start_round = self.round.count;
+        if phase == Phase::Refill {
+            self.reset_lower_bounds();
+        }
+    }
+
+    /// Whether bandwidth should be probed again, either because enough time has passed, or
+    /// because a loss-based flow sharing the path would have probed by now
+    fn is_time_to_probe_bw(&self, now: Instant) -> bool {
+        let waited = self
+            .cycle_start
+            .is_some_and(|start| now.saturating_duration_since(start) > self.probe_wait);
+        let reno_rounds = (self.bdp(1.0) / self.current_mtu).min(K_MAX_RENO_ROUNDS);
+        waited || self.rounds_since_probe >= reno_rounds
+    }
+
+    fn update_probe_bw(&mut self, now: Instant, in_flight: u64, bytes_acked: u64) {
+        let Mode::ProbeBw(phase) = self.mode else {
+            return;
+        };
+        match phase {
+            Phase::Down => {
+                if self.is_time_to_probe_bw(now) {
+                    self.start_probe_bw_phase(Phase::Refill);
+                } else if in_flight <= self.bdp(1.0).min(self.inflight_with_headroom()) {
+                    self.start_probe_bw_phase(Phase::Cruise);
+                }
+            }
+            Phase::Cruise => {
+                if self.is_time_to_probe_bw(now) {
+                    self.start_probe_bw_phase(Phase::Refill);
+                }
+            }
+            Phase::Refill => {
+                // Refill the pipe for a round before probing, so that the probe measures the
+                // path rather than the queue left over from a lower rate
+                if self.round.count > self.phase_start_round {
+                    self.start_probe_bw_phase(Phase::Up);
+                }
+            }
+            Phase::Up => {
+                // Raise the upper bound while it limits us and the path absorbs the extra data
+                if self.inflight_hi != u64::MAX
+                    && in_flight + self.current_mtu >= self.inflight_hi
+                    && !self.is_inflight_too_high()
+                {
+                    self.inflight_hi += bytes_acked;
+                }
+                if self.round.count > self.phase_start_round
+                    && in_flight as f64 > self.bdp(K_PROBE_UP_PACING_GAIN) as f64
+                {
+                    self.start_probe_bw_down(now);
+                }
+            }
+        }
+    }
+
+    fn update_probe_rtt(&mut self, now: Instant, in_flight: u64, app_limited: bool) {
+        let expired = self
+            .min_rtt_stamp
+            .is_some_and(|stamp| now.saturating_duration_since(stamp) > K_PROBE_RTT_INTERVAL);
+        if expired && !app_limited && self.mode != Mode::ProbeRtt {
+            self.mode = Mode::ProbeRtt;
+            self.prior_cwnd = self.window();
+            self.probe_rtt_done_at = None;
+        }
+        if self.mode != Mode::ProbeRtt {
+            return;
+        }
+        match self.probe_rtt_done_at {
+            None => {
+                if in_flight <= self.probe_rtt_cwnd() + self.current_mtu {
+                    self.probe_rtt_done_at = Some(now + K_PROBE_RTT_DURATION);
+                }
+            }
+            Some(done_at) if now >= done_at => {
+                self.min_rtt_stamp = Some(now);
+                self.cwnd = self.cwnd.max(self.prior_cwnd);
+                self.reset_lower_bounds();
+                if self.full_bw_reached {
+                    self.start_probe_bw_down(now);
+                } else {
+                    self.mode = Mode::Startup;
+                }
+            }
+            Some(_) => {}
+        }
+    }
+
+    fn probe_rtt_cwnd(&self) -> u64 {
+        self.bdp(K_PROBE_RTT_CWND_GAIN)
+    }
+
+    fn update_cwnd(&mut self, bytes_acked: u64) {
+        let target = self.bdp(self.cwnd_gain());
+        if self.full_bw_reached {
+            self.cwnd = target.min(self.cwnd + bytes_acked);
+        } else if self.cwnd < target || self.delivered < self.init_cwnd {
+            // Never shrink the window during startup
+            self.cwnd += bytes_acked;
+        }
+        self.cwnd = self.cwnd.max(self.min_cwnd);
+    }
+}
+
+impl Controller for Bbr3 {
+    fn on_sent(&mut self, now: Instant, bytes: u64, last_packet_number: u64) {
+        self.round.max_sent_packet_number = last_packet_number;
+        self.max_bw.on_sent(now, bytes);
+    }
+
+    fn on_ack(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        bytes: u64,
+        app_limited: bool,
+        _rtt: &RttEstimator,
+    ) {
+        self.max_bw
+            .on_ack(now, sent, bytes, self.round.count, app_limited);
+        self.delivered += bytes;
+        self.round.delivered += bytes;
+
+        // Unlike `RttEstimator::min()`, this estimate must be able to rise when the path changes
+        let sample = now.saturating_duration_since(sent);
+        let expired = !self
+            .min_rtt_stamp
+            .is_some_and(|stamp| now.saturating_duration_since(stamp) <= K_MIN_RTT_FILTER_LEN);
+        if expired || sample < self.min_rtt {
+            self.min_rtt = sample;
+            self.min_rtt_stamp = Some(now);
+        }
+    }
+
+    fn on_end_acks(
+        &mut self,
+        now: Instant,
+        in_flight: u64,
+        app_limited: bool,
+        largest_packet_num_acked: Option<u64>,
+    ) {
+        let bytes_acked = self.max_bw.bytes_acked_this_window();
+        self.max_bw.end_acks(self.round.count, app_limited);
+        if let Some(largest_acked_packet) = largest_packet_num_acked {
+            self.round.max_acked_packet_number = largest_acked_packet;
+        }
+
+        if bytes_acked > 0 && self.round.max_acked_packet_number > self.round.end_packet_number {
+            // A round trip has passed since the start of the previous round
+            if self.round.lost > 0 {
+                self.adapt_lower_bounds(now);
+            }
+            if self.mode == Mode::Startup {
+                self.check_full_bw_reached(app_limited);
+            }
+            self.round.start_next(now);
+            self.rounds_since_probe += 1;
+        }
+
+        if self.mode == Mode::Startup && self.full_bw_reached {
+            self.mode = Mode::Drain;
+        }
+        if self.mode == Mode::Drain && in_flight <= self.bdp(1.0) {
+            self.start_probe_bw_down(now);
+        }
+        self.update_probe_bw(now, in_flight, bytes_acked);
+        self.update_probe_rtt(now, in_flight, app_limited);
+        self.update_cwnd(bytes_acked);
+
+        self.prev_in_flight = in_flight;
+    }
+
+    fn on_congestion_event(
+        &mut self,
+        now: Instant,
+        _sent: Instant,
+        is_persistent_congestion: bool,
+        lost_bytes: u64,
+    ) {
+        self.round.lost += lost_bytes;
+        if matches!(self.mode, Mode::Startup | Mode::ProbeBw(Phase::Up))
+            && self.is_inflight_too_high()
+        {
+            self.handle_inflight_too_high(now);
+        }
+        if is_persistent_congestion {
+            self.cwnd = self.min_cwnd;
+        }
+    }
+
+    fn on_mtu_update(&mut self, new_mtu: u16) {
+        self.current_mtu = new_mtu as u64;
+        self.min_cwnd = calculate_min_window(self.current_mtu);
+        self.init_cwnd = self.config.initial_window.max(self.min_cwnd);
+        self.cwnd = self.cwnd.max(self.min_cwnd);
+    }
+
+    fn window(&self) -> u64 {
+        let cap = match self.mode {
+            Mode::ProbeBw(Phase::Cruise) | Mode::ProbeRtt => self.inflight_with_headroom(),
+            Mode::ProbeBw(_) => self.inflight_hi,
+            Mode::Startup | Mode::Drain => u64::MAX,
+        };
+        let mut window = self.cwnd.min(cap).min(self.inflight_lo);
+        if self.mode == Mode::ProbeRtt {
+            window = window.min(self.probe_rtt_cwnd());
+        }
+        window.max(self.min_cwnd)
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+
+    fn initial_window(&self) -> u64 {
+        self.config.initial_window
+    }
+
+    fn bandwidth_estimate(&self) -> Option<u64> {
+        Some(self.bw()).filter(|&bw| bw != 0)
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// Configuration for the [`Bbr3`] congestion controller
+#[derive(Debug, Clone)]
+pub struct Bbr3Config {
+    initial_window: u64,
+}
+
+impl Bbr3Config {
+    /// Default limit on the amount of outstanding data in bytes.
+    ///
+    /// Recommended value: `min(10 * max_datagram_size, max(2 * max_datagram_size, 14720))`
+    pub fn initial_window(&mut self, value: u64) -> &mut Self {
+        self.initial_window = value;
+        self
+    }
+}
+
+impl Default for Bbr3Config {
+    fn default() -> Self {
+        Self {
+            initial_window: K_INITIAL_CONGESTION_WINDOW_PACKETS * BASE_DATAGRAM_SIZE,
+        }
+    }
+}
+
+impl ControllerFactory for Bbr3Config {
+    fn build(self: Arc<Self>, _now: Instant, current_mtu: u16) -> Box<dyn Controller> {
+        Box::new(Bbr3::new(self, current_mtu))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Mode {
+    // Rapidly discover the available bandwidth.
+    Startup,
+    // Drain the queue built up during startup.
+    Drain,
+    // Cycle through the phases of probing for bandwidth.
+    ProbeBw(Phase),
+    // Briefly reduce the data in flight to measure the minimum round-trip time.
+    ProbeRtt,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Phase {
+    // Drain any queue created by the previous probe.
+    Down,
+    // Send at the estimated bandwidth, leaving headroom for other flows.
+    Cruise,
+    // Fill the pipe at the estimated bandwidth before probing.
+    Refill,
+    // Probe for additional bandwidth.
+    Up,
+}
+
+/// Accounting for the current round trip, which ends once a packet sent after it began is acked
+#[derive(Debug, Clone, Default)]
+struct RoundState {
+    count: u64,
+    start: Option<Instant>,
+    max_sent_packet_number: u64,
+    max_acked_packet_number: u64,
+    end_packet_number: u64,
+    /// Bytes acknowledged during the round
+    delivered: u64,
+    /// Bytes declared lost during the round
+    lost: u64,
+}
+
+impl RoundState {
+    fn start_next(&mut self, now: Instant) {
+        self.count += 1;
+        self.start = Some(now);
+        self.end_packet_number = self.max_sent_packet_number;
+        self.delivered = 0;
+        self.lost = 0;
+    }
+}
+
+fn calculate_min_window(current_mtu: u64) -> u64 {
+    4 * current_mtu
+}
+
+// The congestion window gain outside of bandwidth probing.
+const K_CWND_GAIN: f64 = 2.0;
+// The congestion window gain while probing for bandwidth.
+const K_PROBE_UP_CWND_GAIN: f64 = 2.25;
+// The pacing gain while probing for bandwidth, also the target data in flight for ending a probe.
+const K_PROBE_UP_PACING_GAIN: f64 = 1.25;
+// The fraction of the estimated BDP kept in flight during ProbeRtt.
+const K_PROBE_RTT_CWND_GAIN: f64 = 0.5;
+
+const K_STARTUP_GROWTH_TARGET: f64 = 1.25;
+const K_ROUND_TRIPS_WITHOUT_GROWTH_BEFORE_EXITING_STARTUP: u8 = 3;
+
+// The maximum tolerated fraction of data lost in a round.
+const K_LOSS_THRESH: f64 = 0.02;
+// The multiplicative decrease applied to the model in response to loss.
+const K_BETA: f64 = 0.7;
+// The fraction of `inflight_hi` left free for other flows.
+const K_HEADROOM: f64 = 0.15;
+
+const K_PROBE_WAIT_BASE: Duration = Duration::from_secs(2);
+const K_PROBE_WAIT_RAND: Duration = Duration::from_secs(1);
+// Probe no less often than a Reno flow with the same BDP would, up to this many rounds.
+const K_MAX_RENO_ROUNDS: u64 = 63;
+
+const K_MIN_RTT_FILTER_LEN: Duration = Duration::from_secs(10);
+const K_PROBE_RTT_INTERVAL: Duration = Duration::from_secs(5);
+const K_PROBE_RTT_DURATION: Duration = Duration::from_millis(200);
+
+const K_INITIAL_CONGESTION_WINDOW_PACKETS: u64 = 10;
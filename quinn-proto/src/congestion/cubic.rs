@@ -2,7 +2,7 @@ use std::any::Any;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use super::{Controller, ControllerFactory, BASE_DATAGRAM_SIZE};
+use super::{hystart::HyStart, Controller, ControllerFactory, BASE_DATAGRAM_SIZE};
 use crate::connection::RttEstimator;
 use std::cmp;
 
@@ -70,6 +70,27 @@ pub struct Cubic {
     recovery_start_time: Option<Instant>,
     cubic_state: State,
     current_mtu: u64,
+    hystart: HyStart,
+    resume: Option<Resume>,
+}
+
+/// Progress of careful resume, jump-starting the window from a previous connection's path
+/// characteristics
+///
+/// See [`Controller::on_resume_hint()`].
+#[derive(Debug, Clone)]
+enum Resume {
+    /// Waiting for an RTT sample to check that the path resembles the saved one
+    ///
+    /// `sampled` is set once a batch of acks has been processed; the RTT sample it yields only
+    /// becomes visible to later acks.
+    Reconnaissance {
+        rtt: Duration,
+        window: u64,
+        sampled: bool,
+    },
+    /// The window was raised at `jump_time`, and packets sent since aren't all acknowledged yet
+    Validating { jump_time: Instant, acked: u64 },
 }
 
 impl Cubic {
@@ -82,6 +103,49 @@ impl Cubic {
             config,
             cubic_state: Default::default(),
             current_mtu: current_mtu as u64,
+            hystart: HyStart::default(),
+            resume: None,
+        }
+    }
+
+    /// Advance careful resume on the acknowledgement of a packet sent at `sent`
+    fn resume_on_ack(&mut self, now: Instant, sent: Instant, bytes: u64, rtt: &RttEstimator) {
+        match self.resume {
+            Some(Resume::Reconnaissance {
+                rtt: saved,
+                window,
+                sampled,
+            }) => {
+                if !sampled {
+                    return;
+                }
+                let current = rtt.latest();
+                if current < saved / 2 || current > saved * 10 {
+                    // The path changed too much for the saved window to be relevant
+                    self.resume = None;
+                    return;
+                }
+                self.window = self.window.max(window / 2);
+                self.resume = Some(Resume::Validating {
+                    jump_time: now,
+                    acked: 0,
+                });
+            }
+            Some(Resume::Validating {
+                jump_time,
+                ref mut acked,
+            }) => {
+                if sent < jump_time {
+                    return;
+                }
+                *acked += bytes;
+                // Packets sent a round trip after the jump being acked means the jump window was
+                // delivered
+                if sent >= jump_time + rtt.get() {
+                    self.resume = None;
+                }
+            }
+            None => {}
         }
     }
 
@@ -99,6 +163,10 @@ impl Controller for Cubic {
         app_limited: bool,
         rtt: &RttEstimator,
     ) {
+        if self.config.hystart && self.window < self.ssthresh {
+            self.hystart.on_rtt_sample(rtt.latest());
+        }
+        self.resume_on_ack(now, sent, bytes, rtt);
         if app_limited
             || self
                 .recovery_start_time
@@ -109,8 +177,8 @@ impl Controller for Cubic {
         }
 
         if self.window < self.ssthresh {
-            // Slow start
-            self.window += bytes;
+            // Slow start, slowed down once HyStart++ detects a queue building
+            self.window += bytes / self.hystart.growth_divisor();
         } else {
             // Congestion avoidance.
             let ca_start_time;
@@ -162,6 +230,29 @@ impl Controller for Cubic {
         }
     }
 
+    fn on_sent(&mut self, _now: Instant, _bytes: u64, last_packet_number: u64) {
+        self.hystart.on_sent(last_packet_number);
+    }
+
+    fn on_end_acks(
+        &mut self,
+        _now: Instant,
+        _in_flight: u64,
+        _app_limited: bool,
+        largest_packet_num_acked: Option<u64>,
+    ) {
+        if let Some(Resume::Reconnaissance { sampled, .. }) = &mut self.resume {
+            *sampled = true;
+        }
+        if self.config.hystart
+            && self.window < self.ssthresh
+            && self.hystart.on_end_acks(largest_packet_num_acked)
+        {
+            // Conservative slow start is over; continue in congestion avoidance
+            self.ssthresh = self.window;
+        }
+    }
+
     fn on_congestion_event(
         &mut self,
         now: Instant,
@@ -179,6 +270,19 @@ impl Controller for Cubic {
 
         self.recovery_start_time = Some(now);
 
+        match self.resume.take() {
+            Some(Resume::Validating { jump_time, acked }) if sent >= jump_time => {
+                // Safe retreat: the jump was too large, fall back to what was actually delivered
+                self.window = cmp::max(acked / 2, self.minimum_window());
+                self.ssthresh = self.window;
+                self.cubic_state.w_max = self.window as f64;
+                self.cubic_state.k = 0.0;
+                self.cubic_state.cwnd_inc = 0;
+                return;
+            }
+            _ => {}
+        }
+
         // Fast convergence
         #[allow(clippy::branches_sharing_code)]
         // https://github.com/rust-lang/rust-clippy/issues/7198
@@ -230,6 +334,16 @@ impl Controller for Cubic {
         self.config.initial_window
     }
 
+    fn on_resume_hint(&mut self, rtt: Duration, window: u64) {
+        if self.config.careful_resume && window / 2 > self.window {
+            self.resume = Some(Resume::Reconnaissance {
+                rtt,
+                window,
+                sampled: false,
+            });
+        }
+    }
+
     fn into_any(self: Box<Self>) -> Box<dyn Any> {
         self
     }
@@ -239,6 +353,8 @@ impl Controller for Cubic {
 #[derive(Debug, Clone)]
 pub struct CubicConfig {
     initial_window: u64,
+    hystart: bool,
+    careful_resume: bool,
 }
 
 impl CubicConfig {
@@ -249,12 +365,36 @@ impl CubicConfig {
         self.initial_window = value;
         self
     }
+
+    /// Whether to end slow start early once the RTT increases, following HyStart++ ([RFC 9406])
+    ///
+    /// Avoids the heavy losses caused by overshooting the available bandwidth. Defaults to
+    /// `true`.
+    ///
+    /// [RFC 9406]: https://www.rfc-editor.org/rfc/rfc9406.html
+    pub fn hystart(&mut self, value: bool) -> &mut Self {
+        self.hystart = value;
+        self
+    }
+
+    /// Whether to jump-start the window from the hint supplied through
+    /// [`TransportConfig::initial_rtt_and_cwnd_hint()`](crate::TransportConfig::initial_rtt_and_cwnd_hint)
+    ///
+    /// Once an RTT sample confirms that the path resembles the one the hint was taken from, the
+    /// window is raised to half the hinted window. If packets sent in the following round trip
+    /// are lost, the window falls back to half of what was delivered in it. Defaults to `true`.
+    pub fn careful_resume(&mut self, value: bool) -> &mut Self {
+        self.careful_resume = value;
+        self
+    }
 }
 
 impl Default for CubicConfig {
     fn default() -> Self {
         Self {
             initial_window: 14720.clamp(2 * BASE_DATAGRAM_SIZE, 10 * BASE_DATAGRAM_SIZE),
+            hystart: true,
+            careful_resume: true,
         }
     }
 }
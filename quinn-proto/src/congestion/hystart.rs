@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+/// Lower bound on the RTT increase indicating that slow start should end
+const MIN_RTT_THRESH: Duration = Duration::from_millis(4);
+/// Upper bound on the RTT increase indicating that slow start should end
+const MAX_RTT_THRESH: Duration = Duration::from_millis(16);
+/// Fraction of the previous round's minimum RTT the RTT must increase by to end slow start
+const MIN_RTT_DIVISOR: u32 = 8;
+/// Number of RTT samples needed in a round before it is compared with the previous one
+const N_RTT_SAMPLE: u32 = 8;
+/// Divisor of the window growth while in conservative slow start
+const CSS_GROWTH_DIVISOR: u64 = 4;
+/// Number of rounds of conservative slow start after which slow start ends
+const CSS_ROUNDS: u32 = 5;
+
+/// Slow start exit detection following HyStart++ ([RFC 9406])
+///
+/// Watches for the RTT rising by a round to the next, which indicates that a queue is building,
+/// then grows the window conservatively for a few rounds before ending slow start, rather than
+/// overshooting until packets are lost.
+///
+/// [RFC 9406]: https://www.rfc-editor.org/rfc/rfc9406.html
+#[derive(Debug, Default, Clone)]
+pub(super) struct HyStart {
+    /// Largest packet number sent when the current round started; the round ends once it is acked
+    window_end: Option<u64>,
+    /// Largest packet number sent so far
+    last_sent: u64,
+    last_round_min_rtt: Option<Duration>,
+    current_round_min_rtt: Option<Duration>,
+    rtt_sample_count: u32,
+    /// Baseline minimum RTT and number of rounds so far, while in conservative slow start
+    css: Option<(Duration, u32)>,
+}
+
+impl HyStart {
+    pub(super) fn on_sent(&mut self, last_packet_number: u64) {
+        self.last_sent = last_packet_number;
+    }
+
+    pub(super) fn on_rtt_sample(&mut self, rtt: Duration) {
+        let current = self.current_round_min_rtt.map_or(rtt, |min| min.min(rtt));
+        self.current_round_min_rtt = Some(current);
+        self.rtt_sample_count += 1;
+        if self.rtt_sample_count < N_RTT_SAMPLE {
+            return;
+        }
+        match self.css {
+            None => {
+                let Some(last) = self.last_round_min_rtt else {
+                    return;
+                };
+                let thresh = (last / MIN_RTT_DIVISOR).clamp(MIN_RTT_THRESH, MAX_RTT_THRESH);
+                if current >= last + thresh {
+                    self.css = Some((current, 0));
+                }
+            }
+            // The RTT increase was spurious, resume slow start
+            Some((baseline, _)) if current < baseline => self.css = None,
+            Some(_) => {}
+        }
+    }
+
+    /// Track rounds, returning whether slow start should end
+    pub(super) fn on_end_acks(&mut self, largest_packet_num_acked: Option<u64>) -> bool {
+        match (self.window_end, largest_packet_num_acked) {
+            (Some(end), Some(largest)) if largest >= end => {}
+            (Some(_), _) => return false,
+            (None, _) => {
+                self.window_end = Some(self.last_sent);
+                return false;
+            }
+        }
+        self.window_end = Some(self.last_sent);
+        self.last_round_min_rtt = self.current_round_min_rtt.take();
+        self.rtt_sample_count = 0;
+        match &mut self.css {
+            Some((_, rounds)) => {
+                *rounds += 1;
+                *rounds >= CSS_ROUNDS
+            }
+            None => false,
+        }
+    }
+
+    /// Factor to divide slow start window growth by
+    pub(super) fn growth_divisor(&self) -> u64 {
+        match self.css {
+            Some(_) => CSS_GROWTH_DIVISOR,
+            None => 1,
+        }
+    }
+}
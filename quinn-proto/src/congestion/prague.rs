@@ -0,0 +1,224 @@
+use std::any::Any;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{Controller, ControllerFactory, BASE_DATAGRAM_SIZE};
+use crate::connection::RttEstimator;
+use crate::EcnCodepoint;
+
+/// An L4S congestion controller, in the style of TCP Prague
+///
+/// Marks packets ECT(1) so that dual-queue AQMs ([RFC 9332]) treat them as L4S traffic, and
+/// reduces its window in proportion to the fraction of packets marked congestion experienced,
+/// rather than halving it as for loss. This keeps queues short with little loss of throughput on
+/// paths deploying such AQMs. Reacts to loss, and to marks from classic AQMs, like `NewReno`.
+///
+/// Only effective when the path passes ECN markings intact; see
+/// [`PathStats::ecn_validated`](crate::PathStats::ecn_validated).
+///
+/// [RFC 9332]: https://www.rfc-editor.org/rfc/rfc9332.html
+#[derive(Debug, Clone)]
+pub struct Prague {
+    config: Arc<PragueConfig>,
+    current_mtu: u64,
+    /// Maximum number of bytes in flight that may be sent.
+    window: u64,
+    /// Slow start threshold in bytes. When the congestion window is below ssthresh, the mode is
+    /// slow start and the window grows by the number of bytes acknowledged.
+    ssthresh: u64,
+    /// The time the window was last reduced. Packets sent before then don't cause another
+    /// reduction, nor grow the window.
+    recovery_start_time: Instant,
+    /// Bytes which had been acked by the peer since the window last grew in congestion avoidance
+    bytes_acked: u64,
+    /// Moving average of the fraction of packets marked congestion experienced
+    alpha: f64,
+    /// Bytes acknowledged in the current round
+    round_acked: u64,
+    /// Bytes reported as marked congestion experienced in the current round
+    round_marked: u64,
+    /// End of the current round, after about a round trip
+    round_end: Option<Instant>,
+    /// Latest smoothed round-trip time
+    rtt: Duration,
+}
+
+impl Prague {
+    /// Construct a state using the given `config` and current time `now`
+    pub fn new(config: Arc<PragueConfig>, now: Instant, current_mtu: u16) -> Self {
+        Self {
+            window: config.initial_window,
+            ssthresh: u64::MAX,
+            recovery_start_time: now,
+            current_mtu: current_mtu as u64,
+            config,
+            bytes_acked: 0,
+            alpha: 1.0,
+            round_acked: 0,
+            round_marked: 0,
+            round_end: None,
+            rtt: Duration::ZERO,
+        }
+    }
+
+    fn minimum_window(&self) -> u64 {
+        2 * self.current_mtu
+    }
+
+    /// Fraction of packets currently estimated to be marked congestion experienced
+    pub fn alpha(&self) -> f64 {
+        self.alpha
+    }
+}
+
+impl Controller for Prague {
+    fn on_ack(
+        &mut self,
+        _now: Instant,
+        sent: Instant,
+        bytes: u64,
+        app_limited: bool,
+        rtt: &RttEstimator,
+    ) {
+        self.round_acked += bytes;
+        self.rtt = rtt.get();
+        if app_limited || sent <= self.recovery_start_time {
+            return;
+        }
+
+        if self.window < self.ssthresh {
+            // Slow start
+            self.window += bytes;
+            if self.window >= self.ssthresh {
+                self.bytes_acked = self.window - self.ssthresh;
+            }
+        } else {
+            // Congestion avoidance, growing by a datagram per round trip as in `NewReno`
+            self.bytes_acked += bytes;
+            if self.bytes_acked >= self.window {
+                self.bytes_acked -= self.window;
+                self.window += self.current_mtu;
+            }
+        }
+    }
+
+    fn on_end_acks(
+        &mut self,
+        now: Instant,
+        _in_flight: u64,
+        _app_limited: bool,
+        _largest_packet_num_acked: Option<u64>,
+    ) {
+        if matches!(self.round_end, Some(end) if now < end) {
+            return;
+        }
+        if self.round_acked > 0 {
+            let marked = (self.round_marked as f64 / self.round_acked as f64).min(1.0);
+            self.alpha += self.config.gain * (marked - self.alpha);
+        }
+        self.round_acked = 0;
+        self.round_marked = 0;
+        self.round_end = Some(now + self.rtt);
+    }
+
+    fn on_ecn_congestion(&mut self, now: Instant, sent: Instant, ce_marks: u64) {
+        self.round_marked += ce_marks * self.current_mtu;
+        if sent <= self.recovery_start_time {
+            return;
+        }
+
+        self.recovery_start_time = now;
+        let reduction = (self.window as f64 * self.alpha / 2.0) as u64;
+        self.window = self
+            .window
+            .saturating_sub(reduction)
+            .max(self.minimum_window());
+        self.ssthresh = self.window;
+    }
+
+    fn on_congestion_event(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        is_persistent_congestion: bool,
+        _lost_bytes: u64,
+    ) {
+        if sent <= self.recovery_start_time {
+            return;
+        }
+
+        self.recovery_start_time = now;
+        self.window = (self.window / 2).max(self.minimum_window());
+        self.ssthresh = self.window;
+
+        if is_persistent_congestion {
+            self.window = self.minimum_window();
+        }
+    }
+
+    fn on_mtu_update(&mut self, new_mtu: u16) {
+        self.current_mtu = new_mtu as u64;
+        self.window = self.window.max(self.minimum_window());
+    }
+
+    fn window(&self) -> u64 {
+        self.window
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+
+    fn initial_window(&self) -> u64 {
+        self.config.initial_window
+    }
+
+    fn ecn_codepoint(&self) -> EcnCodepoint {
+        EcnCodepoint::Ect1
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// Configuration for the `Prague` congestion controller
+#[derive(Debug, Clone)]
+pub struct PragueConfig {
+    initial_window: u64,
+    gain: f64,
+}
+
+impl PragueConfig {
+    /// Default limit on the amount of outstanding data in bytes.
+    ///
+    /// Recommended value: `min(10 * max_datagram_size, max(2 * max_datagram_size, 14720))`
+    pub fn initial_window(&mut self, value: u64) -> &mut Self {
+        self.initial_window = value;
+        self
+    }
+
+    /// Weight of each round trip's fraction of marked packets in the moving average driving
+    /// window reductions
+    ///
+    /// Defaults to 1/16.
+    pub fn gain(&mut self, value: f64) -> &mut Self {
+        self.gain = value;
+        self
+    }
+}
+
+impl Default for PragueConfig {
+    fn default() -> Self {
+        Self {
+            initial_window: 14720.clamp(2 * BASE_DATAGRAM_SIZE, 10 * BASE_DATAGRAM_SIZE),
+            gain: 1.0 / 16.0,
+        }
+    }
+}
+
+impl ControllerFactory for PragueConfig {
+    fn build(self: Arc<Self>, now: Instant, current_mtu: u16) -> Box<dyn Controller> {
+        Box::new(Prague::new(self, now, current_mtu))
+    }
+}
@@ -37,6 +37,11 @@ impl Assembler {
         self.data.clear();
     }
 
+    /// Whether data is being read in order
+    pub(super) fn is_ordered(&self) -> bool {
+        self.state.is_ordered()
+    }
+
     pub(super) fn ensure_ordering(&mut self, ordered: bool) -> Result<(), IllegalOrderedRead> {
         if ordered && !self.state.is_ordered() {
             return Err(IllegalOrderedRead);
@@ -98,6 +103,33 @@ impl Assembler {
         }
     }
 
+    /// Get the contiguous data following the read index, without consuming it
+    ///
+    /// Only meaningful in ordered mode. Only the buffers making up the contiguous data are visited,
+    /// by taking them off the heap in order and putting them back afterwards.
+    pub(super) fn peek(&mut self) -> Vec<Chunk> {
+        let mut offset = self.bytes_read;
+        let mut chunks = Vec::new();
+        let mut visited = Vec::new();
+        while let Some(buffer) = self.data.peek() {
+            if buffer.offset > offset {
+                // Gap in the received data
+                break;
+            }
+            let buffer = self.data.pop().unwrap();
+            let end = buffer.offset + buffer.bytes.len() as u64;
+            // Otherwise already read, or a duplicate of data peeked from a previous buffer
+            if end > offset {
+                let start = (offset - buffer.offset) as usize;
+                chunks.push(Chunk::new(offset, buffer.bytes.slice(start..)));
+                offset = end;
+            }
+            visited.push(buffer);
+        }
+        self.data.extend(visited);
+        chunks
+    }
+
     /// Copy fragmented chunk data to new chunks backed by a single buffer
     ///
     /// This makes sure we're not unnecessarily holding on to many larger allocations.
@@ -361,6 +393,33 @@ mod test {
         assert_matches!(next(&mut x, 32), None);
     }
 
+    #[test]
+    fn peek_ordered() {
+        let mut x = Assembler::new();
+        assert!(x.peek().is_empty());
+        x.insert(0, Bytes::from_static(b"123"), 3);
+        x.insert(2, Bytes::from_static(b"345"), 3);
+        x.insert(7, Bytes::from_static(b"89"), 2);
+        let peeked = x.peek();
+        assert_eq!(peeked.len(), 2);
+        assert_eq!(peeked[0], Chunk::new(0, Bytes::from_static(b"123")));
+        assert_eq!(peeked[1], Chunk::new(3, Bytes::from_static(b"45")));
+        // Peeking doesn't consume any data
+        assert_matches!(next(&mut x, 2), Some(ref y) if &y[..] == b"12");
+        let peeked = x.peek();
+        assert_eq!(peeked[0].offset, 2);
+        let data = peeked.iter().flat_map(|c| &c.bytes[..]).copied();
+        assert_eq!(data.collect::<Vec<_>>(), b"345");
+        // Every buffer taken off the heap while peeking is put back
+        assert_eq!(x.data.len(), 3);
+        x.insert(5, Bytes::from_static(b"67"), 2);
+        assert_eq!(x.peek().len(), 3);
+        assert_matches!(next(&mut x, 32), Some(ref y) if &y[..] == b"345");
+        assert_matches!(next(&mut x, 32), Some(ref y) if &y[..] == b"67");
+        assert_matches!(next(&mut x, 32), Some(ref y) if &y[..] == b"89");
+        assert_matches!(next(&mut x, 32), None);
+    }
+
     #[test]
     fn assemble_unordered() {
         let mut x = Assembler::new();
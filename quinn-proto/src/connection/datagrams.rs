@@ -1,13 +1,13 @@
-use std::collections::VecDeque;
+use std::{collections::VecDeque, time::Instant};
 
 use bytes::Bytes;
 use thiserror::Error;
 use tracing::{debug, trace};
 
-use super::Connection;
+use super::{Connection, Event};
 use crate::{
     frame::{Datagram, FrameStruct},
-    TransportError,
+    DatagramDropPolicy, TransportError,
 };
 
 /// API to control datagram traffic
@@ -18,14 +18,68 @@ pub struct Datagrams<'a> {
 impl<'a> Datagrams<'a> {
     /// Queue an unreliable, unordered datagram for immediate transmission
     ///
-    /// If `drop` is true, previously queued datagrams which are still unsent may be discarded to
-    /// make space for this datagram, in order of oldest to newest. If `drop` is false, and there
+    /// If `drop` is true, previously queued datagrams which are still unsent, or this datagram,
+    /// may be discarded to make space according to the configured
+    /// [`DatagramDropPolicy`]. If `drop` is false, and there
     /// isn't enough space due to previously queued datagrams, this function will return
     /// `SendDatagramError::Blocked`. `Event::DatagramsUnblocked` will be emitted once datagrams
     /// have been sent.
     ///
     /// Returns `Err` iff a `len`-byte datagram cannot currently be sent.
     pub fn send(&mut self, data: Bytes, drop: bool) -> Result<(), SendDatagramError> {
+        self.send_inner(data, drop, None, 0, false).map(|_| ())
+    }
+
+    /// Queue an unreliable, unordered datagram, and report whether it's acknowledged by the peer
+    ///
+    /// Behaves like [`send`](Self::send), except that exactly one of `Event::DatagramAcked` or
+    /// `Event::DatagramLost` will later be emitted with the returned [`DatagramId`], unless the
+    /// connection is closed first. A datagram that is discarded before it can be sent is reported
+    /// as lost.
+    pub fn send_tracked(
+        &mut self,
+        data: Bytes,
+        drop: bool,
+    ) -> Result<DatagramId, SendDatagramError> {
+        self.send_inner(data, drop, None, 0, true)
+    }
+
+    /// Queue an unreliable, unordered datagram with the given `priority`
+    ///
+    /// Behaves like [`send`](Self::send), except that queued datagrams with higher priority are
+    /// transmitted first. Datagrams sent through other methods have priority 0.
+    pub fn send_with_priority(
+        &mut self,
+        data: Bytes,
+        drop: bool,
+        priority: i32,
+    ) -> Result<(), SendDatagramError> {
+        self.send_inner(data, drop, None, priority, false)
+            .map(|_| ())
+    }
+
+    /// Queue an unreliable, unordered datagram to be transmitted no later than `deadline`
+    ///
+    /// Behaves like [`send`](Self::send), except that if the datagram is still queued once
+    /// `deadline` has passed, it is silently discarded rather than transmitted late.
+    pub fn send_with_deadline(
+        &mut self,
+        data: Bytes,
+        drop: bool,
+        deadline: Instant,
+    ) -> Result<(), SendDatagramError> {
+        self.send_inner(data, drop, Some(deadline), 0, false)
+            .map(|_| ())
+    }
+
+    fn send_inner(
+        &mut self,
+        data: Bytes,
+        drop: bool,
+        deadline: Option<Instant>,
+        priority: i32,
+        tracked: bool,
+    ) -> Result<DatagramId, SendDatagramError> {
         if self.conn.config.datagram_receive_buffer_size.is_none() {
             return Err(SendDatagramError::Disabled);
         }
@@ -35,26 +89,33 @@ impl<'a> Datagrams<'a> {
         if data.len() > max {
             return Err(SendDatagramError::TooLarge);
         }
+        let config = &self.conn.config;
+        let state = &mut self.conn.datagrams;
+        let events = &mut self.conn.events;
         if drop {
-            while self.conn.datagrams.outgoing_total > self.conn.config.datagram_send_buffer_size {
-                let prev = self
-                    .conn
-                    .datagrams
+            while state.outgoing_total > config.datagram_send_buffer_size {
+                let Some(victim) = state.drop_candidate(config.datagram_queue_policy, priority)
+                else {
+                    trace!(len = data.len(), "dropping new outgoing datagram");
+                    let id = state.next_id();
+                    if tracked {
+                        events.push_back(Event::DatagramLost(id));
+                    }
+                    return Ok(id);
+                };
+                let prev = state
                     .outgoing
-                    .pop_front()
-                    .expect("datagrams.outgoing_total desynchronized");
-                trace!(len = prev.data.len(), "dropping outgoing datagram");
-                self.conn.datagrams.outgoing_total -= prev.data.len();
+                    .remove(victim)
+                    .expect("drop candidate out of bounds");
+                trace!(len = prev.frame.data.len(), "dropping outgoing datagram");
+                state.outgoing_total -= prev.frame.data.len();
+                prev.discarded(events);
             }
-        } else if self.conn.datagrams.outgoing_total + data.len()
-            > self.conn.config.datagram_send_buffer_size
-        {
-            self.conn.datagrams.send_blocked = true;
+        } else if state.outgoing_total + data.len() > config.datagram_send_buffer_size {
+            state.send_blocked = true;
             return Err(SendDatagramError::Blocked(data));
         }
-        self.conn.datagrams.outgoing_total += data.len();
-        self.conn.datagrams.outgoing.push_back(Datagram { data });
-        Ok(())
+        Ok(state.enqueue(Datagram { data }, deadline, priority, tracked))
     }
 
     /// Compute the maximum size of datagrams that may passed to `send_datagram`
@@ -105,9 +166,11 @@ pub(super) struct DatagramState {
     /// delivered to the application
     pub(super) recv_buffered: usize,
     pub(super) incoming: VecDeque<Datagram>,
-    pub(super) outgoing: VecDeque<Datagram>,
+    pub(super) outgoing: VecDeque<OutgoingDatagram>,
     pub(super) outgoing_total: usize,
     pub(super) send_blocked: bool,
+    /// Identifier to assign to the next outgoing datagram, in order of submission
+    next_id: u64,
 }
 
 impl DatagramState {
@@ -144,8 +207,9 @@ impl DatagramState {
     ///
     /// Used to ensure that reductions in MTU don't get us stuck in a state where we have a datagram
     /// queued but can't send it.
-    pub(super) fn drop_oversized(&mut self, max_payload: usize) {
-        self.outgoing.retain(|datagram| {
+    pub(super) fn drop_oversized(&mut self, max_payload: usize, events: &mut VecDeque<Event>) {
+        self.outgoing.retain(|queued| {
+            let datagram = &queued.frame;
             let result = datagram.data.len() < max_payload;
             if !result {
                 trace!(
@@ -154,28 +218,114 @@ impl DatagramState {
                     max_payload
                 );
                 self.outgoing_total -= datagram.data.len();
+                queued.discarded(events);
             }
             result
         });
     }
 
+    /// Queue `frame` for transmission after all datagrams of the same or higher priority
+    fn enqueue(
+        &mut self,
+        frame: Datagram,
+        deadline: Option<Instant>,
+        priority: i32,
+        tracked: bool,
+    ) -> DatagramId {
+        let index = self
+            .outgoing
+            .iter()
+            .rposition(|x| x.priority >= priority)
+            .map_or(0, |i| i + 1);
+        let id = self.next_id();
+        self.outgoing_total += frame.data.len();
+        self.outgoing.insert(
+            index,
+            OutgoingDatagram {
+                frame,
+                deadline,
+                priority,
+                id,
+                tracked,
+            },
+        );
+        id
+    }
+
+    /// Allocate the identifier of the next outgoing datagram
+    fn next_id(&mut self) -> DatagramId {
+        let id = DatagramId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    /// Index of the queued datagram to discard to make space for a new datagram of `priority`
+    ///
+    /// Returns `None` if the new datagram should be discarded instead.
+    fn drop_candidate(&self, policy: DatagramDropPolicy, priority: i32) -> Option<usize> {
+        match policy {
+            DatagramDropPolicy::DropOldest => self
+                .outgoing
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, x)| x.id)
+                .map(|(i, _)| i),
+            DatagramDropPolicy::DropNewest => None,
+            DatagramDropPolicy::DropLowestPriority => {
+                // The queue is sorted by descending priority, and by age within a priority
+                let lowest = self.outgoing.back()?.priority;
+                if priority < lowest {
+                    return None;
+                }
+                self.outgoing.iter().position(|x| x.priority == lowest)
+            }
+        }
+    }
+
+    /// Discard outgoing datagrams whose deadline is at or before `now`
+    ///
+    /// Returns whether any datagrams were discarded.
+    pub(super) fn drop_expired(&mut self, now: Instant, events: &mut VecDeque<Event>) -> bool {
+        let len = self.outgoing.len();
+        self.outgoing.retain(|datagram| {
+            let expired = datagram.deadline.is_some_and(|deadline| deadline <= now);
+            if expired {
+                trace!(len = datagram.frame.data.len(), "dropping expired datagram");
+                self.outgoing_total -= datagram.frame.data.len();
+                datagram.discarded(events);
+            }
+            !expired
+        });
+        self.outgoing.len() != len
+    }
+
     /// Attempt to write a datagram frame into `buf`, consuming it from `self.outgoing`
     ///
     /// Returns whether a frame was written. At most `max_size` bytes will be written, including
-    /// framing.
-    pub(super) fn write(&mut self, buf: &mut Vec<u8>, max_size: usize) -> bool {
-        let datagram = match self.outgoing.pop_front() {
+    /// framing. If the datagram is tracked, its ID is added to `tracked`.
+    pub(super) fn write(
+        &mut self,
+        buf: &mut Vec<u8>,
+        max_size: usize,
+        tracked: &mut Vec<DatagramId>,
+    ) -> bool {
+        let queued = match self.outgoing.pop_front() {
             Some(x) => x,
             None => return false,
         };
 
-        if buf.len() + datagram.size(true) > max_size {
+        if buf.len() + queued.frame.size(true) > max_size {
             // Future work: we could be more clever about cramming small datagrams into
             // mostly-full packets when a larger one is queued first
-            self.outgoing.push_front(datagram);
+            self.outgoing.push_front(queued);
             return false;
         }
 
+        if queued.tracked {
+            tracked.push(queued.id);
+        }
+        let datagram = queued.frame;
+
         trace!(len = datagram.data.len(), "DATAGRAM");
 
         self.outgoing_total -= datagram.data.len();
@@ -190,6 +340,32 @@ impl DatagramState {
     }
 }
 
+/// A datagram queued for transmission
+pub(super) struct OutgoingDatagram {
+    pub(super) frame: Datagram,
+    /// Time after which the datagram is discarded instead of sent
+    pub(super) deadline: Option<Instant>,
+    /// Datagrams of higher priority are sent first
+    pub(super) priority: i32,
+    /// Identifies the datagram, in order of submission
+    pub(super) id: DatagramId,
+    /// Whether the application wants to know if the datagram is acknowledged
+    pub(super) tracked: bool,
+}
+
+impl OutgoingDatagram {
+    /// Account for the datagram being discarded without being sent
+    fn discarded(&self, events: &mut VecDeque<Event>) {
+        if self.tracked {
+            events.push_back(Event::DatagramLost(self.id));
+        }
+    }
+}
+
+/// Identifies an outgoing datagram sent with [`Datagrams::send_tracked`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct DatagramId(u64);
+
 /// Errors that can arise when sending a datagram
 #[derive(Debug, Error, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub enum SendDatagramError {
@@ -0,0 +1,145 @@
+use std::{fmt, net::SocketAddr};
+
+use bytes::Bytes;
+
+use crate::{
+    frame::{self, Frame},
+    packet::{Header, LongType, SpaceId},
+    Side, StreamId,
+};
+
+/// Observes the packets of every connection of an endpoint, after decryption
+///
+/// Intended for debugging and for protocol analyzers in tests. Packets are summarized by type,
+/// number and the frames they carry; neither keys nor application data are exposed. Configured
+/// with [`EndpointConfig::packet_inspector()`](crate::EndpointConfig::packet_inspector).
+///
+/// Called synchronously while sending and receiving, so implementations should be cheap.
+pub trait PacketInspector: Send + Sync {
+    /// Called for each packet sent, just before it is encrypted
+    fn packet_sent(&self, packet: &PacketSummary) {
+        let _ = packet;
+    }
+
+    /// Called for each packet received, just after it is decrypted
+    fn packet_received(&self, packet: &PacketSummary) {
+        let _ = packet;
+    }
+}
+
+/// A packet seen by a [`PacketInspector`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct PacketSummary {
+    /// Which side of the connection this endpoint is
+    pub side: Side,
+    /// Address of the peer the packet was sent to or received from
+    pub remote: SocketAddr,
+    /// Type of the packet
+    pub ty: PacketType,
+    /// Packet number
+    pub number: u64,
+    /// Size of the packet on the wire, in bytes
+    pub len: usize,
+    /// Frames carried by the packet, in order, excluding padding
+    pub frames: Vec<FrameSummary>,
+}
+
+/// Type of a packet seen by a [`PacketInspector`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PacketType {
+    /// Initial packet, carrying the start of the handshake
+    Initial,
+    /// Handshake packet
+    Handshake,
+    /// 0-RTT packet, carrying early application data
+    ZeroRtt,
+    /// 1-RTT packet, with a short header
+    OneRtt,
+}
+
+impl PacketType {
+    pub(super) fn from_header(header: &Header) -> Option<Self> {
+        Some(match header {
+            Header::Initial(_) => Self::Initial,
+            Header::Long {
+                ty: LongType::Handshake,
+                ..
+            } => Self::Handshake,
+            Header::Long {
+                ty: LongType::ZeroRtt,
+                ..
+            } => Self::ZeroRtt,
+            Header::Short { .. } => Self::OneRtt,
+            Header::Retry { .. } | Header::VersionNegotiate { .. } => return None,
+        })
+    }
+
+    pub(super) fn from_space(space: SpaceId, short_header: bool) -> Self {
+        match space {
+            SpaceId::Initial => Self::Initial,
+            SpaceId::Handshake => Self::Handshake,
+            SpaceId::Data if short_header => Self::OneRtt,
+            SpaceId::Data => Self::ZeroRtt,
+        }
+    }
+}
+
+/// A frame seen by a [`PacketInspector`]
+///
+/// Displays as the frame's name in RFC 9000, followed by its stream and length if any.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct FrameSummary {
+    /// Frame type, as encoded on the wire
+    pub ty: u64,
+    /// Stream the frame concerns, if any
+    pub stream: Option<StreamId>,
+    /// Length of the data carried by STREAM, CRYPTO and DATAGRAM frames
+    pub len: Option<usize>,
+}
+
+impl FrameSummary {
+    fn new(frame: &Frame) -> Self {
+        let (stream, len) = match frame {
+            Frame::Stream(x) => (Some(x.id), Some(x.data.len())),
+            Frame::Crypto(x) => (None, Some(x.data.len())),
+            Frame::Datagram(x) => (None, Some(x.data.len())),
+            Frame::ResetStream(x) => (Some(x.id), None),
+            Frame::ResetStreamAt(x) => (Some(x.id), None),
+            Frame::StopSending(x) => (Some(x.id), None),
+            Frame::MaxStreamData { id, .. } | Frame::StreamDataBlocked { id, .. } => {
+                (Some(*id), None)
+            }
+            _ => (None, None),
+        };
+        Self {
+            ty: frame.ty().0,
+            stream,
+            len,
+        }
+    }
+
+    /// Summarize the frames of a decrypted packet payload, stopping at the first malformed one
+    pub(super) fn parse(payload: Bytes) -> Vec<Self> {
+        let Ok(iter) = frame::Iter::new(payload) else {
+            return Vec::new();
+        };
+        iter.map_while(Result::ok)
+            .filter(|frame| !matches!(frame, Frame::Padding))
+            .map(|frame| Self::new(&frame))
+            .collect()
+    }
+}
+
+impl fmt::Display for FrameSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", frame::Type(self.ty))?;
+        match (self.stream, self.len) {
+            (Some(stream), Some(len)) => write!(f, "({stream}, {len} bytes)"),
+            (Some(stream), None) => write!(f, "({stream})"),
+            (None, Some(len)) => write!(f, "({len} bytes)"),
+            (None, None) => Ok(()),
+        }
+    }
+}
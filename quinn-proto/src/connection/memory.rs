@@ -0,0 +1,51 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Stream data buffered by all of an endpoint's connections, measured against an optional limit
+///
+/// Shared between an endpoint and its connections, each of which reports changes in its own
+/// usage. See [`EndpointConfig::max_buffered_bytes()`](crate::EndpointConfig::max_buffered_bytes).
+#[derive(Debug)]
+pub(crate) struct MemoryBudget {
+    limit: Option<u64>,
+    used: AtomicU64,
+}
+
+impl MemoryBudget {
+    pub(crate) fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit,
+            used: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether usage is tracked at all
+    pub(crate) fn is_limited(&self) -> bool {
+        self.limit.is_some()
+    }
+
+    /// Account for a connection's usage changing from `old` to `new` bytes
+    pub(crate) fn update(&self, old: u64, new: u64) {
+        let Some(limit) = self.limit else {
+            return;
+        };
+        // A single connection can't use more than the whole budget, and clamping prevents
+        // connections with huge flow control windows from overflowing the total
+        let (old, new) = (old.min(limit), new.min(limit));
+        if new > old {
+            self.used.fetch_add(new - old, Ordering::Relaxed);
+        } else {
+            self.used.fetch_sub(old - new, Ordering::Relaxed);
+        }
+    }
+
+    /// Total bytes currently reported by the endpoint's connections
+    pub(crate) fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Number of bytes that may be buffered before the limit is reached
+    pub(crate) fn headroom(&self) -> u64 {
+        self.limit
+            .map_or(u64::MAX, |limit| limit.saturating_sub(self.used()))
+    }
+}
@@ -10,7 +10,7 @@ use std::{
 
 use bytes::{Bytes, BytesMut};
 use frame::StreamMetaVec;
-use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
 use thiserror::Error;
 use tracing::{debug, error, trace, trace_span, warn};
 
@@ -31,10 +31,11 @@ use crate::{
         ConnectionEvent, ConnectionEventInner, ConnectionId, DatagramConnectionEvent, EcnCodepoint,
         EndpointEvent, EndpointEventInner,
     },
-    token::ResetToken,
-    transport_parameters::TransportParameters,
-    Dir, EndpointConfig, Frame, Side, StreamId, Transmit, TransportError, TransportErrorCode,
-    VarInt, MAX_STREAM_COUNT, MIN_INITIAL_SIZE, TIMER_GRANULARITY,
+    token::{ResetToken, TokenStore},
+    transport_parameters::{TransportParameters, VersionInformation},
+    Dir, EndpointConfig, Frame, FrameExtension, SendExtensionFrameError, Side, StreamId, Transmit,
+    TransportError, TransportErrorCode, VarInt, MAX_STREAM_COUNT, MIN_INITIAL_SIZE,
+    TIMER_GRANULARITY,
 };
 
 mod ack_frequency;
@@ -48,41 +49,55 @@ use cid_state::CidState;
 
 mod datagrams;
 use datagrams::DatagramState;
-pub use datagrams::{Datagrams, SendDatagramError};
+pub use datagrams::{DatagramId, Datagrams, SendDatagramError};
+
+mod inspector;
+pub use inspector::{FrameSummary, PacketInspector, PacketSummary, PacketType};
+
+mod memory;
+pub(crate) use memory::MemoryBudget;
 
 mod mtud;
 mod pacing;
 
 mod packet_builder;
-use packet_builder::PacketBuilder;
+use packet_builder::{PacketBuilder, PendingSeals};
 
 mod packet_crypto;
 use packet_crypto::{PrevCrypto, ZeroRttCrypto};
 
 mod paths;
 pub use paths::RttEstimator;
-use paths::{PathData, PathResponses};
+use paths::{EcnState, PathData, PathProbe, PathResponses};
+
+mod qlog;
+#[cfg(feature = "qlog")]
+pub use qlog::QlogFactory;
+use qlog::QlogStream;
 
 mod send_buffer;
 
 mod spaces;
-#[cfg(fuzzing)]
+#[cfg(feature = "fuzzing")]
 pub use spaces::Retransmits;
-#[cfg(not(fuzzing))]
+#[cfg(not(feature = "fuzzing"))]
 use spaces::Retransmits;
 use spaces::{PacketNumberFilter, PacketSpace, SendableFrames, SentPacket, ThinRetransmits};
 
 mod stats;
-pub use stats::{ConnectionStats, FrameStats, PathStats, UdpStats};
+pub use stats::{
+    ConnectionStats, FrameStats, HandshakeEvents, PathStats, StatsDelta, StreamStats, UdpStats,
+};
 
 mod streams;
-#[cfg(fuzzing)]
+#[cfg(feature = "fuzzing")]
 pub use streams::StreamsState;
-#[cfg(not(fuzzing))]
+#[cfg(not(feature = "fuzzing"))]
 use streams::StreamsState;
 pub use streams::{
     BytesSource, Chunks, ClosedStream, FinishError, ReadError, ReadableError, RecvStream,
-    SendStream, ShouldTransmit, StreamEvent, Streams, WriteError, Written,
+    ResetAtError, SendStream, ShouldTransmit, StreamEvent, StreamGroupId, Streams, WriteError,
+    Written,
 };
 
 mod timer;
@@ -145,17 +160,33 @@ pub struct Connection {
     /// Whether MTU detection is supported in this environment
     allow_mtud: bool,
     prev_path: Option<(ConnectionId, PathData)>,
+    /// Local IP address used on `prev_path`
+    prev_local_ip: Option<IpAddr>,
+    /// Whether the path being validated leads to the server's preferred address
+    migrating_to_preferred_address: bool,
     state: State,
     side: Side,
     /// Whether or not 0-RTT was enabled during the handshake. Does not imply acceptance.
     zero_rtt_enabled: bool,
     /// Set if 0-RTT is supported, then cleared when no longer needed.
     zero_rtt_crypto: Option<ZeroRttCrypto>,
+    /// Number of bytes of 0-RTT packet payloads the server may still process
+    zero_rtt_budget: u64,
     key_phase: bool,
     /// How many packets are in the current key phase. Used only for `Data` space.
     key_phase_size: u64,
+    /// Number of 1-RTT key updates performed so far
+    key_updates: u64,
+    /// Whether the application asked for a key update that hasn't been performed yet
+    key_update_requested: bool,
+    /// What to send when the keep-alive timer fires
+    keep_alive_probe: KeepAliveProbe,
     /// Transport parameters set by the peer
     peer_params: TransportParameters,
+    /// Whether `peer_params` were received in this handshake, rather than remembered for 0-RTT
+    peer_params_received: bool,
+    /// The time from which the receive timestamps reported to the peer are measured
+    receive_timestamp_basis: Instant,
     /// Source ConnectionId of the first packet received from the peer
     orig_rem_cid: ConnectionId,
     /// Destination ConnectionId sent by the client on the first Initial
@@ -182,19 +213,33 @@ pub struct Connection {
     /// These are generated in advance to prevent timing attacks and/or DoS by third-party attackers
     /// spoofing key updates.
     next_crypto: Option<KeyPair<Box<dyn PacketKey>>>,
+    /// Packets of the transmit being written, awaiting protection
+    pending_seals: PendingSeals,
     accepted_0rtt: bool,
+    /// The client's first packets, held back by
+    /// [`Endpoint::accept_deferred()`](crate::Endpoint::accept_deferred)
+    deferred_handshake: Option<Box<DeferredHandshake>>,
     /// Whether the idle timer should be reset the next time an ack-eliciting packet is transmitted.
     permit_idle_reset: bool,
     /// Negotiated idle timeout
     idle_timeout: Option<Duration>,
+    /// When the idle timeout was last reset
+    last_activity: Instant,
     timers: TimerTable,
     /// Number of packets received which could not be authenticated
     authentication_failures: u64,
     /// Why the connection was lost, if it has been
     error: Option<ConnectionError>,
-    /// Sent in every outgoing Initial packet. Always empty for servers and after Initial keys are
-    /// discarded.
+    /// Sent in every outgoing Initial packet. Taken from a Retry packet or, initially, from a
+    /// NEW_TOKEN frame received on an earlier connection. Always empty for servers and after Initial
+    /// keys are discarded.
     retry_token: Bytes,
+    /// Where a client saves tokens from NEW_TOKEN frames, and the server name to save them under
+    token_store: Option<(Arc<dyn TokenStore>, String)>,
+    /// Client only: how to restart the handshake if the server rejects our version
+    version_fallback: Option<VersionFallback>,
+    /// Times the handshake was restarted on behalf of the server
+    handshake_events: HandshakeEvents,
     /// Identifies Data-space packet numbers to skip. Not used in earlier spaces.
     packet_number_filter: PacketNumberFilter,
 
@@ -203,6 +248,8 @@ pub struct Connection {
     //
     /// Responses to PATH_CHALLENGE frames
     path_responses: PathResponses,
+    /// Application-requested PATH_CHALLENGE awaiting a response
+    path_probe: Option<PathProbe>,
     close: bool,
 
     //
@@ -215,6 +262,10 @@ pub struct Connection {
     //
     /// The number of times a PTO has been sent without receiving an ack.
     pto_count: u32,
+    /// Loss detection parameters, initialized from the [`TransportConfig`] and adjustable
+    packet_threshold: u32,
+    time_threshold: f32,
+    max_pto_backoff: u32,
 
     //
     // Congestion Control
@@ -234,10 +285,16 @@ pub struct Connection {
     local_cid_state: CidState,
     /// State of the unreliable datagram extension
     datagrams: DatagramState,
+    /// Stream data buffered by all of the endpoint's connections
+    memory: Arc<MemoryBudget>,
+    /// This connection's share of `memory`, as last reported
+    memory_reported: u64,
     /// Connection level statistics
     stats: ConnectionStats,
     /// QUIC version used for the connection.
     version: u32,
+    /// qlog trace of the connection, if enabled
+    qlog: QlogStream,
 }
 
 impl Connection {
@@ -258,6 +315,9 @@ impl Connection {
         allow_mtud: bool,
         rng_seed: [u8; 32],
         path_validated: bool,
+        token_store: Option<(Arc<dyn TokenStore>, String)>,
+        version_fallback: Option<VersionFallback>,
+        memory: Arc<MemoryBudget>,
     ) -> Self {
         let side = if server_config.is_some() {
             Side::Server
@@ -273,7 +333,11 @@ impl Connection {
             expected_token: Bytes::new(),
             client_hello: None,
         });
+        let zero_rtt_budget = server_config
+            .as_ref()
+            .map_or(u64::MAX, |config| config.zero_rtt_policy.max_early_data);
         let mut rng = StdRng::from_seed(rng_seed);
+        let qlog = QlogStream::new(&endpoint_config, side, init_cid, now);
         let mut this = Self {
             endpoint_config,
             server_config,
@@ -290,10 +354,13 @@ impl Connection {
             allow_mtud,
             local_ip,
             prev_path: None,
+            prev_local_ip: None,
+            migrating_to_preferred_address: false,
             side,
             state,
             zero_rtt_enabled: false,
             zero_rtt_crypto: None,
+            zero_rtt_budget,
             key_phase: false,
             // A small initial key phase size ensures peers that don't handle key updates correctly
             // fail sooner rather than later. It's okay for both peers to do this, as the first one
@@ -301,8 +368,15 @@ impl Connection {
             // simultaneous key update by both is just like a regular key update with a really fast
             // response. Inspired by quic-go's similar behavior of performing the first key update
             // at the 100th short-header packet.
-            key_phase_size: rng.gen_range(10..1000),
+            key_phase_size: rng
+                .gen_range(10..1000)
+                .min(config.key_update_interval.unwrap_or(u64::MAX)),
+            key_updates: 0,
+            key_update_requested: false,
+            keep_alive_probe: KeepAliveProbe::Ping,
             peer_params: TransportParameters::default(),
+            peer_params_received: false,
+            receive_timestamp_basis: now,
             orig_rem_cid: rem_cid,
             initial_dst_cid: init_cid,
             retry_src_cid: None,
@@ -315,16 +389,25 @@ impl Connection {
             highest_space: SpaceId::Initial,
             prev_crypto: None,
             next_crypto: None,
+            pending_seals: PendingSeals::default(),
             accepted_0rtt: false,
+            deferred_handshake: None,
             permit_idle_reset: true,
             idle_timeout: match config.max_idle_timeout {
                 None | Some(VarInt(0)) => None,
                 Some(dur) => Some(Duration::from_millis(dur.0)),
             },
+            last_activity: now,
             timers: TimerTable::default(),
             authentication_failures: 0,
             error: None,
-            retry_token: Bytes::new(),
+            retry_token: token_store
+                .as_ref()
+                .and_then(|(store, server_name)| store.take(server_name))
+                .unwrap_or_default(),
+            token_store,
+            version_fallback,
+            handshake_events: HandshakeEvents::default(),
             #[cfg(test)]
             packet_number_filter: match config.deterministic_packet_numbers {
                 false => PacketNumberFilter::new(&mut rng),
@@ -334,6 +417,7 @@ impl Connection {
             packet_number_filter: PacketNumberFilter::new(&mut rng),
 
             path_responses: PathResponses::default(),
+            path_probe: None,
             close: false,
 
             ack_frequency: AckFrequencyState::new(get_max_ack_delay(
@@ -341,6 +425,9 @@ impl Connection {
             )),
 
             pto_count: 0,
+            packet_threshold: config.packet_threshold,
+            time_threshold: config.time_threshold,
+            max_pto_backoff: config.max_pto_backoff,
 
             app_limited: false,
             receiving_ecn: false,
@@ -351,16 +438,28 @@ impl Connection {
                 config.max_concurrent_uni_streams,
                 config.max_concurrent_bidi_streams,
                 config.send_window,
-                config.receive_window,
-                config.stream_receive_window,
+                config.per_stream_send_buffer,
+                config.get_receive_window(),
+                config.get_stream_receive_window(),
+                config.stream_scheduler_factory.clone().build(),
             ),
             datagrams: DatagramState::default(),
+            memory,
+            memory_reported: 0,
             config,
             rem_cids: CidQueue::new(rem_cid),
             rng,
             stats: ConnectionStats::default(),
             version,
+            qlog,
         };
+        if let Some((_, max)) = this.config.window_autotune {
+            this.streams.enable_window_autotune(max);
+        }
+        this.streams
+            .set_send_buffer_allocator(this.config.send_buffer_allocator);
+        this.streams
+            .set_window_update(this.config.stream_window_update);
         if side.is_client() {
             // Kick off the connection
             this.write_crypto();
@@ -456,8 +555,33 @@ impl Connection {
         now: Instant,
         max_datagrams: usize,
         buf: &mut Vec<u8>,
+    ) -> Option<Transmit> {
+        let transmit = self.write_transmit(now, max_datagrams, buf);
+        match transmit {
+            Some(_) => self.seal_packets(buf),
+            None => self.pending_seals.clear(),
+        }
+        transmit
+    }
+
+    /// Write the packets of the next transmit to `buf`, leaving them for
+    /// [`seal_packets()`](Self::seal_packets) to protect
+    fn write_transmit(
+        &mut self,
+        now: Instant,
+        max_datagrams: usize,
+        buf: &mut Vec<u8>,
     ) -> Option<Transmit> {
         assert!(max_datagrams != 0);
+        if self.datagrams.drop_expired(now, &mut self.events) && self.datagrams.send_blocked {
+            self.events.push_back(Event::DatagramsUnblocked);
+            self.datagrams.send_blocked = false;
+        }
+        if self.key_update_requested && self.can_update_keys() {
+            // Send a packet to carry out the update
+            self.spaces[SpaceId::Data].ping_pending = true;
+        }
+        self.update_memory_usage();
         let max_datagrams = match self.config.enable_segmentation_offload {
             false => 1,
             true => max_datagrams.min(MAX_TRANSMIT_SEGMENTS),
@@ -655,13 +779,20 @@ impl Connection {
 
                     // Check whether the next datagram is blocked by pacing
                     let smoothed_rtt = self.path.rtt.get();
-                    if let Some(delay) = self.path.pacing.delay(
-                        smoothed_rtt,
-                        bytes_to_send,
-                        self.path.current_mtu(),
-                        self.path.congestion.window(),
-                        now,
-                    ) {
+                    let pacing_rate = self.path.congestion.pacing_rate();
+                    self.path.pacing.set_max_datagrams(max_datagrams);
+                    let delay = match self.config.pacing {
+                        Some(_) => self.path.pacing.delay(
+                            smoothed_rtt,
+                            bytes_to_send,
+                            self.path.current_mtu(),
+                            self.path.congestion.window(),
+                            pacing_rate,
+                            now,
+                        ),
+                        None => None,
+                    };
+                    if let Some(delay) = delay {
                         self.timers.set(Timer::Pacing, delay);
                         congestion_blocked = true;
                         // Loss probes should be subject to pacing, even though
@@ -776,6 +907,7 @@ impl Connection {
             {
                 // A client stops both sending and processing Initial packets when it
                 // sends its first Handshake packet.
+                self.seal_packets(buf);
                 self.discard_space(now, SpaceId::Initial);
             }
             if let Some(ref mut prev) = self.prev_crypto {
@@ -813,9 +945,11 @@ impl Connection {
                 // especially important with ack delay, since the peer might not
                 // have gotten any other ACK for the data earlier on.
                 if !self.spaces[space_id].pending_acks.ranges().is_empty() {
+                    let receive_timestamps = self.receive_timestamps();
                     Self::populate_acks(
                         now,
                         self.receiving_ecn,
+                        receive_timestamps,
                         &mut SentFrames::default(),
                         &mut self.spaces[space_id],
                         buf,
@@ -895,7 +1029,8 @@ impl Connection {
                     return Some(Transmit {
                         destination: remote,
                         size: buf.len(),
-                        ecn: None,
+                        // Tracked as marked like other packets on the path
+                        ecn: self.path.ecn_codepoint(),
                         segment_size: None,
                         src_ip: self.local_ip,
                     });
@@ -1004,15 +1139,13 @@ impl Connection {
         self.path.total_sent = self.path.total_sent.saturating_add(buf.len() as u64);
 
         self.stats.udp_tx.on_sent(num_datagrams, buf.len());
+        let ecn = self.path.ecn_codepoint();
+        self.path.ecn.on_sent(num_datagrams);
 
         Some(Transmit {
             destination: self.path.remote,
             size: buf.len(),
-            ecn: if self.path.sending_ecn {
-                Some(EcnCodepoint::Ect0)
-            } else {
-                None
-            },
+            ecn,
             segment_size: match num_datagrams {
                 1 => None,
                 _ => Some(segment_size),
@@ -1049,6 +1182,7 @@ impl Connection {
             Datagram(DatagramConnectionEvent {
                 now,
                 remote,
+                local_ip,
                 ecn,
                 first_decode,
                 remaining,
@@ -1081,6 +1215,12 @@ impl Connection {
                     self.handle_coalesced(now, remote, ecn, data);
                 }
 
+                // Once the client starts using our preferred address, respond from it
+                if local_ip != self.local_ip && self.is_preferred_ip(local_ip) {
+                    trace!(?local_ip, "peer migrated to preferred address");
+                    self.local_ip = local_ip;
+                }
+
                 if was_anti_amplification_blocked {
                     // A prior attempt to set the loss detection timer may have failed due to
                     // anti-amplification, so ensure it's set now. Prevents a handshake deadlock if
@@ -1131,7 +1271,7 @@ impl Connection {
                 }
                 Timer::KeepAlive => {
                     trace!("sending keep-alive");
-                    self.ping();
+                    self.send_keep_alive();
                 }
                 Timer::LossDetection => {
                     self.on_loss_detection_timeout(now);
@@ -1142,12 +1282,29 @@ impl Connection {
                 }
                 Timer::PathValidation => {
                     debug!("path validation failed");
-                    if let Some((_, prev)) = self.prev_path.take() {
+                    if let Some((_, mut prev)) = self.prev_path.take() {
+                        prev.revert_from(&self.path);
                         self.path = prev;
+                        self.local_ip = self.prev_local_ip;
                     }
+                    self.migrating_to_preferred_address = false;
                     self.path.challenge = None;
                     self.path.challenge_pending = false;
                 }
+                Timer::PathProbe => {
+                    let Some(probe) = &mut self.path_probe else {
+                        continue;
+                    };
+                    if probe.attempts < PATH_PROBE_ATTEMPTS {
+                        // Challenges aren't retransmitted; send a fresh one
+                        probe.token = self.rng.gen();
+                        probe.sent = None;
+                    } else {
+                        debug!("path probe unanswered");
+                        self.path_probe = None;
+                        self.events.push_back(Event::PathProbed { rtt: None });
+                    }
+                }
                 Timer::Pacing => trace!("pacing timer expired"),
                 Timer::PushNewCid => {
                     // Update `retire_prior_to` field in NEW_CONNECTION_ID frame
@@ -1205,13 +1362,52 @@ impl Connection {
         Datagrams { conn: self }
     }
 
+    /// When the connection was last active, as measured by the idle timeout
+    ///
+    /// This is the most recent time a packet was received from the peer, or an ack-eliciting
+    /// packet was sent after receiving one. The connection times out once it has been idle for
+    /// the negotiated idle timeout.
+    pub fn last_activity(&self) -> Instant {
+        self.last_activity
+    }
+
     /// Returns connection statistics
     pub fn stats(&self) -> ConnectionStats {
         let mut stats = self.stats;
-        stats.path.rtt = self.path.rtt.get();
-        stats.path.cwnd = self.path.congestion.window();
-        stats.path.current_mtu = self.path.mtud.current_mtu();
+        stats.path = self.path_stats();
+        stats
+    }
+
+    /// How often the handshake was restarted at the server's request
+    pub fn handshake_events(&self) -> HandshakeEvents {
+        self.handshake_events
+    }
 
+    /// Statistics of the current path, including the congestion controller's current estimates
+    ///
+    /// Cheaper than [`stats()`](Self::stats) when only the path is of interest.
+    pub fn path_stats(&self) -> PathStats {
+        let mut stats = self.stats.path;
+        let rtt = self.path.rtt.get();
+        let window = self.path.congestion.window();
+        stats.rtt = rtt;
+        stats.min_rtt = self.path.rtt.min();
+        stats.cwnd = window;
+        stats.bytes_in_flight = self.path.in_flight.bytes;
+        stats.bandwidth = match self.path.congestion.bandwidth_estimate() {
+            Some(bandwidth) => bandwidth,
+            None if rtt.is_zero() => 0,
+            None => (window as f64 / rtt.as_secs_f64()) as u64,
+        };
+        stats.pacing_rate = match self.config.pacing {
+            Some(_) => self
+                .path
+                .pacing
+                .rate(rtt, window, self.path.congestion.pacing_rate()),
+            None => 0,
+        };
+        stats.current_mtu = self.path.mtud.current_mtu();
+        stats.ecn_validated = self.path.ecn == EcnState::Capable;
         stats
     }
 
@@ -1222,9 +1418,129 @@ impl Connection {
         self.spaces[self.highest_space].ping_pending = true;
     }
 
-    #[doc(hidden)]
+    /// Measure the round-trip time to the peer with a PATH_CHALLENGE
+    ///
+    /// Unlike [`ping()`](Self::ping), whose acknowledgement the peer may delay, the peer is
+    /// expected to respond to a PATH_CHALLENGE immediately, so that the time until its response
+    /// is a sample of the current path's round-trip time. An [`Event::PathProbed`] is emitted once
+    /// the response arrives, or once the peer failed to respond to several challenges. Does
+    /// nothing if a probe is already underway, in which case its outcome is reported instead.
+    pub fn probe_path(&mut self) {
+        if self.path_probe.is_some() {
+            return;
+        }
+        self.path_probe = Some(PathProbe {
+            token: self.rng.gen(),
+            sent: None,
+            attempts: 0,
+        });
+    }
+
+    /// Choose what is sent when the connection has been idle for
+    /// [`TransportConfig::keep_alive_interval()`]
+    ///
+    /// By default a PING frame is sent. Some middleboxes only consider a mapping live when
+    /// application payload flows, in which case a probe written to a datagram or stream can be
+    /// used instead. If the probe cannot be sent, e.g. because the stream was closed or the peer
+    /// doesn't accept datagrams, a PING is sent in its place.
+    pub fn set_keep_alive_probe(&mut self, probe: KeepAliveProbe) {
+        self.keep_alive_probe = probe;
+    }
+
+    fn send_keep_alive(&mut self) {
+        let sent = match self.keep_alive_probe {
+            KeepAliveProbe::Ping => false,
+            KeepAliveProbe::Datagram(ref mut probe) => {
+                let data = probe();
+                match self.datagrams().send(data, true) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        debug!("keep-alive datagram probe failed: {}", e);
+                        false
+                    }
+                }
+            }
+            KeepAliveProbe::Stream(id, ref mut probe) => {
+                let data = probe();
+                match self.send_stream(id).write(&data) {
+                    Ok(n) if n == data.len() => true,
+                    Ok(n) => {
+                        debug!(
+                            "keep-alive stream probe truncated to {} of {} bytes",
+                            n,
+                            data.len()
+                        );
+                        n > 0
+                    }
+                    Err(e) => {
+                        debug!("keep-alive stream probe on {} failed: {}", id, e);
+                        false
+                    }
+                }
+            }
+        };
+        if !sent {
+            self.ping();
+        }
+    }
+
+    /// Initiate an update of the 1-RTT packet protection keys
+    ///
+    /// The update is performed once the handshake is confirmed and any previous key update has
+    /// been acknowledged by the peer, along with the next 1-RTT packet sent. Completion can be
+    /// observed through [`key_phase()`](Self::key_phase). See also
+    /// [`TransportConfig::key_update_interval()`].
     pub fn initiate_key_update(&mut self) {
-        self.update_keys(None, false);
+        self.key_update_requested = true;
+    }
+
+    /// Number of 1-RTT key updates performed on this connection so far, by either peer
+    pub fn key_phase(&self) -> u64 {
+        self.key_updates
+    }
+
+    /// Whether a new key update may be initiated locally
+    ///
+    /// Key updates must not be initiated before the handshake is confirmed, nor before the peer
+    /// has acknowledged the previous key update.
+    fn can_update_keys(&self) -> bool {
+        self.state.is_established()
+            && self.spaces[SpaceId::Handshake].crypto.is_none()
+            && self
+                .prev_crypto
+                .as_ref()
+                .map_or(true, |prev| prev.end_packet.is_some())
+    }
+
+    /// Perform a locally initiated key update if one is due
+    ///
+    /// `buf` holds the packets written so far, which are protected with the current keys first.
+    pub(super) fn maybe_update_keys(&mut self, buf: &mut [u8]) {
+        let due = self.key_update_requested
+            || self.spaces[SpaceId::Data].sent_with_keys >= self.key_phase_size;
+        if due && self.can_update_keys() {
+            self.seal_packets(buf);
+            self.update_keys(None, false);
+        }
+    }
+
+    /// Protect the packets written to `buf` since the last call
+    fn seal_packets(&mut self, buf: &mut [u8]) {
+        let mut pending = mem::take(&mut self.pending_seals);
+        pending.seal(self, buf);
+        self.pending_seals = pending;
+    }
+
+    /// Keys protecting outgoing packets in `space`
+    fn local_keys(&self, space: SpaceId) -> (&dyn crypto::HeaderKey, &dyn PacketKey) {
+        if let Some(ref crypto) = self.spaces[space].crypto {
+            (&*crypto.header.local, &*crypto.packet.local)
+        } else if space == SpaceId::Data {
+            let zero_rtt = self.zero_rtt_crypto.as_ref().unwrap();
+            (&*zero_rtt.header, &*zero_rtt.packet)
+        } else {
+            unreachable!("tried to send {:?} packet without keys", space);
+        }
     }
 
     /// Get a session reference
@@ -1232,6 +1548,58 @@ impl Connection {
         &*self.crypto
     }
 
+    /// Queue a frame of a registered [`FrameExtension`] for transmission
+    ///
+    /// `body` is the frame following its type, and is retransmitted as-is if lost.
+    ///
+    /// **Unstable:** see [`FrameExtension`].
+    pub fn send_extension_frame(
+        &mut self,
+        ty: VarInt,
+        body: Bytes,
+    ) -> Result<(), SendExtensionFrameError> {
+        if !self
+            .config
+            .frame_extensions
+            .iter()
+            .any(|ext| ext.frame_types().contains(&ty))
+        {
+            return Err(SendExtensionFrameError::UnknownType);
+        }
+        if self.negotiated_extension(ty).is_none() {
+            return Err(SendExtensionFrameError::UnsupportedByPeer);
+        }
+        let frame = frame::ExtensionFrame { ty, body };
+        if frame.size() >= self.path.current_mtu() as usize - self.predict_1rtt_overhead(None) {
+            return Err(SendExtensionFrameError::TooLarge);
+        }
+        self.spaces[SpaceId::Data]
+            .pending
+            .extension_frames
+            .push_back(frame);
+        Ok(())
+    }
+
+    /// The extension defining frame type `ty`, if the peer supports it
+    fn negotiated_extension(&self, ty: VarInt) -> Option<Arc<dyn FrameExtension>> {
+        let ext = self
+            .config
+            .frame_extensions
+            .iter()
+            .find(|ext| ext.frame_types().contains(&ty))?;
+        let (id, _) = ext.transport_parameter();
+        let params = self.peer_transport_parameters()?;
+        params.custom(id).map(|_| ext.clone())
+    }
+
+    /// Transport parameters sent by the peer, once received during the handshake
+    ///
+    /// Gives access to custom parameters registered by the peer's application through
+    /// [`TransportConfig::custom_transport_parameter()`].
+    pub fn peer_transport_parameters(&self) -> Option<&TransportParameters> {
+        self.peer_params_received.then_some(&self.peer_params)
+    }
+
     /// Whether the connection is in the process of being established
     ///
     /// If this returns `false`, the connection may be either established or closed, signaled by the
@@ -1240,6 +1608,15 @@ impl Connection {
         self.state.is_handshake()
     }
 
+    /// Whether the handshake is confirmed
+    ///
+    /// Servers confirm the handshake once it completes; clients once the server acknowledges its
+    /// completion with a HANDSHAKE_DONE frame. Connection migration and key updates are only
+    /// permitted afterwards.
+    pub fn is_handshake_confirmed(&self) -> bool {
+        self.state.is_established() && self.spaces[SpaceId::Handshake].crypto.is_none()
+    }
+
     /// Whether the connection is closed
     ///
     /// Closed connections cannot transport any further data. A connection becomes closed when
@@ -1292,13 +1669,23 @@ impl Connection {
     /// This can be different from the address the endpoint is bound to, in case
     /// the endpoint is bound to a wildcard address like `0.0.0.0` or `::`.
     ///
-    /// This will return `None` for clients, or when no `local_ip` was passed to
+    /// This will return `None` for clients that haven't set one with
+    /// [`migrate_local()`](Self::migrate_local), or when no `local_ip` was passed to
     /// [`Endpoint::handle()`](crate::Endpoint::handle) for the datagrams establishing this
     /// connection.
     pub fn local_ip(&self) -> Option<IpAddr> {
         self.local_ip
     }
 
+    /// Number of finished streams whose data has not yet been fully acknowledged by the peer
+    ///
+    /// Once this reaches zero, the peer's QUIC stack has received everything written to streams
+    /// that were [finished](SendStream::finish), so closing the connection will not cause that
+    /// data to be lost. Streams that are still open are not counted.
+    pub fn unacked_finished_streams(&self) -> usize {
+        self.streams.unacked_finished()
+    }
+
     /// Current best estimate of this connection's latency (round-trip-time)
     pub fn rtt(&self) -> Duration {
         self.path.rtt.get()
@@ -1337,6 +1724,27 @@ impl Connection {
         }
     }
 
+    /// See [`TransportConfig::packet_threshold()`]
+    ///
+    /// Takes effect from the next acknowledgement or timeout.
+    pub fn set_packet_threshold(&mut self, value: u32) {
+        self.packet_threshold = value;
+    }
+
+    /// See [`TransportConfig::time_threshold()`]
+    ///
+    /// Takes effect from the next acknowledgement or timeout.
+    pub fn set_time_threshold(&mut self, value: f32) {
+        self.time_threshold = value;
+    }
+
+    /// See [`TransportConfig::max_pto_backoff()`]
+    ///
+    /// Takes effect from the next acknowledgement or timeout.
+    pub fn set_max_pto_backoff(&mut self, value: u32) {
+        self.max_pto_backoff = value;
+    }
+
     fn on_ack_received(
         &mut self,
         now: Instant,
@@ -1378,9 +1786,23 @@ impl Connection {
             return Ok(());
         }
 
+        // Receive timestamps in descending order of packet number, in microseconds as we asked
+        let timestamps = match space {
+            SpaceId::Data if self.config.receive_timestamps => ack.timestamps().collect(),
+            _ => Vec::new(),
+        };
+
         let mut ack_eliciting_acked = false;
+        let mut ecn_acked = 0;
         for packet in newly_acked.elts() {
             if let Some(info) = self.spaces[space].take(packet) {
+                if let Ok(i) = timestamps.binary_search_by(|&(pn, _)| packet.cmp(&pn)) {
+                    let received = Duration::from_micros(timestamps[i].1);
+                    self.path
+                        .congestion
+                        .on_receive_timestamp(info.time_sent, received);
+                }
+                ecn_acked += u64::from(info.ecn);
                 if let Some(acked) = info.largest_acked {
                     // Assume ACKs for all packets below the largest acknowledged in `packet` have
                     // been received. This can cause the peer to spuriously retransmit if some of
@@ -1438,7 +1860,7 @@ impl Connection {
         }
 
         // Explicit congestion notification
-        if self.path.sending_ecn {
+        if self.path.ecn != EcnState::Failed {
             if let Some(ecn) = ack.ecn {
                 // We only examine ECN counters from ACKs that we are certain we received in transmit
                 // order, allowing us to compute an increase in ECN counts to compare against the number
@@ -1446,15 +1868,21 @@ impl Connection {
                 // reordering.
                 if new_largest {
                     let sent = self.spaces[space].largest_acked_packet_sent;
-                    self.process_ecn(now, space, newly_acked.len() as u64, ecn, sent);
+                    self.process_ecn(now, space, ecn_acked, ecn, sent);
                 }
-            } else {
-                // We always start out sending ECN, so any ack that doesn't acknowledge it disables it.
+            } else if ecn_acked > 0 {
+                // Any ack of a marked packet that doesn't report ECN counts disables it
                 debug!("ECN not acknowledged by peer");
-                self.path.sending_ecn = false;
+                self.path.ecn = EcnState::Failed;
             }
         }
 
+        self.qlog.metrics_updated(
+            now,
+            &self.path.rtt,
+            self.path.congestion.window(),
+            self.path.in_flight.bytes,
+        );
         self.set_loss_detection_timer(now);
         Ok(())
     }
@@ -1468,20 +1896,27 @@ impl Connection {
         ecn: frame::EcnCounts,
         largest_sent_time: Instant,
     ) {
-        match self.spaces[space].detect_ecn(newly_acked, ecn) {
+        let codepoint = self.path.congestion.ecn_codepoint();
+        match self.spaces[space].detect_ecn(newly_acked, codepoint, ecn) {
             Err(e) => {
                 debug!("halting ECN due to verification failure: {}", e);
-                self.path.sending_ecn = false;
+                self.path.ecn = EcnState::Failed;
                 // Wipe out the existing value because it might be garbage and could interfere with
                 // future attempts to use ECN on new paths.
                 self.spaces[space].ecn_feedback = frame::EcnCounts::ZERO;
             }
-            Ok(false) => {}
-            Ok(true) => {
-                self.stats.path.congestion_events += 1;
-                self.path
-                    .congestion
-                    .on_congestion_event(now, largest_sent_time, false, 0);
+            Ok(ce_marks) => {
+                if newly_acked > 0 && self.path.ecn != EcnState::Capable {
+                    trace!("ECN validated");
+                    self.path.ecn = EcnState::Capable;
+                }
+                if ce_marks > 0 {
+                    self.stats.path.congestion_events += 1;
+                    self.stats.path.ecn_ce_marks += ce_marks;
+                    self.path
+                        .congestion
+                        .on_ecn_congestion(now, largest_sent_time, ce_marks);
+                }
             }
         }
     }
@@ -1512,6 +1947,8 @@ impl Connection {
         for frame in info.stream_frames {
             self.streams.received_ack_of(frame);
         }
+        self.events
+            .extend(info.datagrams.into_iter().map(Event::DatagramAcked));
     }
 
     fn set_key_discard_timer(&mut self, now: Instant, space: SpaceId) {
@@ -1572,12 +2009,12 @@ impl Connection {
         let mut lost_mtu_probe = None;
         let in_flight_mtu_probe = self.path.mtud.in_flight_mtu_probe();
         let rtt = self.path.rtt.conservative();
-        let loss_delay = cmp::max(rtt.mul_f32(self.config.time_threshold), TIMER_GRANULARITY);
+        let loss_delay = cmp::max(rtt.mul_f32(self.time_threshold), TIMER_GRANULARITY);
 
         // Packets sent before this time are deemed lost.
         let lost_send_time = now.checked_sub(loss_delay).unwrap();
         let largest_acked_packet = self.spaces[pn_space].largest_acked_packet.unwrap();
-        let packet_threshold = self.config.packet_threshold as u64;
+        let packet_threshold = self.packet_threshold as u64;
         let mut size_of_lost_packets = 0u64;
 
         // InPersistentCongestion: Determine if all packets in the time period before the newest
@@ -1654,10 +2091,14 @@ impl Connection {
 
             for &packet in &lost_packets {
                 let info = self.spaces[pn_space].take(packet).unwrap(); // safe: lost_packets is populated just above
+                self.qlog.packet_lost(now, pn_space, packet);
                 self.remove_in_flight(packet, &info);
+                self.path.on_lost(packet, &info);
                 for frame in info.stream_frames {
                     self.streams.retransmit(frame);
                 }
+                self.events
+                    .extend(info.datagrams.into_iter().map(Event::DatagramLost));
                 self.spaces[pn_space].pending |= info.retransmits;
                 self.path.mtud.on_non_probe_lost(packet, info.size);
             }
@@ -1668,7 +2109,8 @@ impl Connection {
                     .congestion
                     .on_mtu_update(self.path.mtud.current_mtu());
                 if let Some(max_datagram_size) = self.datagrams().max_size() {
-                    self.datagrams.drop_oversized(max_datagram_size);
+                    self.datagrams
+                        .drop_oversized(max_datagram_size, &mut self.events);
                 }
             }
 
@@ -1702,7 +2144,8 @@ impl Connection {
     }
 
     fn pto_time_and_space(&self, now: Instant) -> Option<(Instant, SpaceId)> {
-        let backoff = 2u32.pow(self.pto_count.min(MAX_BACKOFF_EXPONENT));
+        let exponent = self.pto_count.min(self.max_pto_backoff);
+        let backoff = 2u32.pow(exponent.min(MAX_BACKOFF_EXPONENT));
         let mut duration = self.path.rtt.pto_base() * backoff;
 
         if self.path.in_flight.ack_eliciting == 0 {
@@ -1836,8 +2279,16 @@ impl Connection {
                 self.set_key_discard_timer(now, space_id)
             }
         }
+        let max_receive_timestamps = match space_id {
+            SpaceId::Data => self.peer_params.max_receive_timestamps_per_ack,
+            _ => None,
+        };
         let space = &mut self.spaces[space_id];
         space.pending_acks.insert_one(packet, now);
+        if let Some(max) = max_receive_timestamps {
+            let max = max.into_inner().min(MAX_REPORTED_RECEIVE_TIMESTAMPS) as usize;
+            space.pending_acks.record_receive_time(packet, now, max);
+        }
         if packet >= space.rx_packet {
             space.rx_packet = packet;
             // Update outgoing spin bit, inverting iff we're the client
@@ -1846,6 +2297,7 @@ impl Connection {
     }
 
     fn reset_idle_timeout(&mut self, now: Instant, space: SpaceId) {
+        self.last_activity = now;
         let timeout = match self.idle_timeout {
             None => return,
             Some(dur) => dur,
@@ -1858,6 +2310,32 @@ impl Connection {
         self.timers.set(Timer::Idle, now + dt);
     }
 
+    /// Report buffered stream data to the endpoint, and limit flow control credit accordingly
+    fn update_memory_usage(&mut self) {
+        if self.config.max_buffered_bytes.is_none() && !self.memory.is_limited() {
+            return;
+        }
+        // Recompute this connection's share of the budget from scratch so that credit it reserved
+        // but didn't announce becomes available to other connections
+        let used = self.streams.release_unannounced_credit();
+        self.report_memory_usage(used);
+        let headroom = self
+            .config
+            .max_buffered_bytes
+            .map_or(u64::MAX, |max| max.saturating_sub(used))
+            .min(self.memory.headroom());
+        let should_transmit = self.streams.reserve_credit(headroom);
+        self.report_memory_usage(self.streams.committed_bytes());
+        if should_transmit.should_transmit() {
+            self.spaces[SpaceId::Data].pending.max_data = true;
+        }
+    }
+
+    fn report_memory_usage(&mut self, used: u64) {
+        self.memory.update(self.memory_reported, used);
+        self.memory_reported = used;
+    }
+
     fn reset_keep_alive(&mut self, now: Instant) {
         let interval = match self.config.keep_alive_interval {
             Some(x) if self.state.is_established() => x,
@@ -1914,6 +2392,49 @@ impl Connection {
         Ok(())
     }
 
+    /// Hold back the client's first packets until
+    /// [`handle_deferred_handshake()`](Self::handle_deferred_handshake)
+    pub(crate) fn defer_handshake(&mut self, deferred: DeferredHandshake) {
+        debug_assert!(self.side.is_server());
+        self.deferred_handshake = Some(Box::new(deferred));
+    }
+
+    /// Whether the client's first packets await
+    /// [`handle_deferred_handshake()`](Self::handle_deferred_handshake)
+    pub fn has_deferred_handshake(&self) -> bool {
+        self.deferred_handshake.is_some()
+    }
+
+    /// Process the client's first packets, held back by
+    /// [`Endpoint::accept_deferred()`](crate::Endpoint::accept_deferred)
+    ///
+    /// This performs the cryptographic operations of the server's first handshake flight, and may
+    /// be called on another thread than the endpoint's. If the handshake fails, the connection is
+    /// closed as it would be for an error in a later packet. Does nothing if no packets were held
+    /// back.
+    pub fn handle_deferred_handshake(&mut self, now: Instant) {
+        let Some(deferred) = self.deferred_handshake.take() else {
+            return;
+        };
+        let DeferredHandshake {
+            remote,
+            ecn,
+            packet_number,
+            packet,
+            rest,
+            datagrams,
+        } = *deferred;
+
+        let result = self.handle_first_packet(now, remote, ecn, packet_number, packet, rest);
+        if let Err(ref e) = result {
+            debug!("handshake failed: {}", e);
+        }
+        self.conclude_packet(now, remote, false, false, result);
+        for event in datagrams {
+            self.handle_event(ConnectionEvent(ConnectionEventInner::Datagram(event)));
+        }
+    }
+
     fn init_0rtt(&mut self) {
         let (header, packet) = match self.crypto.early_crypto() {
             Some(x) => x,
@@ -1932,6 +2453,9 @@ impl Connection {
                         retry_src_cid: None,
                         stateless_reset_token: None,
                         min_ack_delay: None,
+                        reset_stream_at: false,
+                        max_receive_timestamps_per_ack: None,
+                        version_information: None,
                         ack_delay_exponent: TransportParameters::default().ack_delay_exponent,
                         max_ack_delay: TransportParameters::default().max_ack_delay,
                         ..params
@@ -2031,6 +2555,10 @@ impl Connection {
             }
             self.spaces[space].crypto_offset += outgoing.len() as u64;
             trace!("wrote {} {:?} CRYPTO bytes", outgoing.len(), space);
+            if self.chaos_protection_applies(space) && offset == 0 {
+                self.queue_scrambled_crypto(outgoing);
+                continue;
+            }
             self.spaces[space].pending.crypto.push_back(frame::Crypto {
                 offset,
                 data: outgoing,
@@ -2038,6 +2566,36 @@ impl Connection {
         }
     }
 
+    /// Whether CRYPTO frames sent in `space` should be scrambled for chaos protection
+    fn chaos_protection_applies(&self, space: SpaceId) -> bool {
+        self.config.chaos_protection && space == SpaceId::Initial && self.side.is_client()
+    }
+
+    /// Queue the ClientHello as several CRYPTO frames in random order
+    fn queue_scrambled_crypto(&mut self, mut data: Bytes) {
+        let mut cuts = (0..self.rng.gen_range(1..=4))
+            .map(|_| self.rng.gen_range(1..data.len().max(2)))
+            .filter(|&cut| cut < data.len())
+            .collect::<Vec<_>>();
+        cuts.sort_unstable();
+        cuts.dedup();
+        let mut pieces = Vec::with_capacity(cuts.len() + 1);
+        let mut offset = 0;
+        for cut in cuts {
+            pieces.push(frame::Crypto {
+                offset: offset as u64,
+                data: data.split_to(cut - offset),
+            });
+            offset = cut;
+        }
+        pieces.push(frame::Crypto {
+            offset: offset as u64,
+            data,
+        });
+        pieces.shuffle(&mut self.rng);
+        self.spaces[SpaceId::Initial].pending.crypto.extend(pieces);
+    }
+
     /// Switch to stronger cryptography during handshake
     fn upgrade_crypto(&mut self, space: SpaceId, crypto: Keys) {
         debug_assert!(
@@ -2110,6 +2668,11 @@ impl Connection {
         }
     }
 
+    /// Discard any 0-RTT packets received from the client
+    pub(crate) fn discard_0rtt(&mut self) {
+        self.zero_rtt_budget = 0;
+    }
+
     fn handle_decode(
         &mut self,
         now: Instant,
@@ -2196,11 +2759,20 @@ impl Connection {
                 if number.map_or(false, is_duplicate) {
                     debug!("discarding possible duplicate packet");
                     return;
+                } else if packet.header.is_0rtt()
+                    && packet.payload.len() as u64 > self.zero_rtt_budget
+                {
+                    debug!("discarding 0-RTT packet beyond the early data limit");
+                    self.zero_rtt_budget = 0;
+                    return;
                 } else if self.state.is_handshake() && packet.header.is_short() {
                     // TODO: SHOULD buffer these to improve reordering tolerance.
                     trace!("dropping short packet during handshake");
                     return;
                 } else {
+                    if packet.header.is_0rtt() {
+                        self.zero_rtt_budget -= packet.payload.len() as u64;
+                    }
                     if let Header::Initial(InitialHeader { ref token, .. }) = packet.header {
                         if let State::Handshake(ref hs) = self.state {
                             if self.side.is_server() && token != &hs.expected_token {
@@ -2232,6 +2804,18 @@ impl Connection {
             }
         };
 
+        self.conclude_packet(now, remote, was_closed, was_drained, result);
+    }
+
+    /// Apply the outcome of processing a packet from `remote` to the connection's state
+    fn conclude_packet(
+        &mut self,
+        now: Instant,
+        remote: SocketAddr,
+        was_closed: bool,
+        was_drained: bool,
+        result: Result<(), ConnectionError>,
+    ) {
         // State transitions for error cases
         if let Err(conn_err) = result {
             self.error = Some(conn_err.clone());
@@ -2250,7 +2834,7 @@ impl Connection {
                     debug!("closing connection due to transport error: {}", err);
                     State::closed(err)
                 }
-                ConnectionError::VersionMismatch => State::Draining,
+                ConnectionError::VersionMismatch | ConnectionError::RetryRefused => State::Draining,
                 ConnectionError::LocallyClosed => {
                     unreachable!("LocallyClosed isn't generated by packet processing");
                 }
@@ -2286,6 +2870,24 @@ impl Connection {
         number: Option<u64>,
         packet: Packet,
     ) -> Result<(), ConnectionError> {
+        self.qlog.packet_received(
+            now,
+            &packet.header,
+            number,
+            packet.header_data.len() + packet.payload.len(),
+        );
+        if let Some(inspector) = &self.endpoint_config.packet_inspector {
+            if let (Some(ty), Some(number)) = (PacketType::from_header(&packet.header), number) {
+                inspector.packet_received(&PacketSummary {
+                    side: self.side,
+                    remote,
+                    ty,
+                    number,
+                    len: packet.header_data.len() + packet.payload.len(),
+                    frames: FrameSummary::parse(packet.payload.clone().freeze()),
+                });
+            }
+        }
         let state = match self.state {
             State::Established => {
                 match packet.header.space() {
@@ -2352,7 +2954,16 @@ impl Connection {
                     return Ok(());
                 }
 
+                if self
+                    .version_fallback
+                    .as_ref()
+                    .is_some_and(|x| !x.allow_retry)
+                {
+                    debug!("refusing Retry");
+                    return Err(ConnectionError::RetryRefused);
+                }
                 trace!("retrying with CID {}", rem_cid);
+                self.handshake_events.retries += 1;
                 let client_hello = state.client_hello.take().unwrap();
                 self.retry_src_cid = Some(rem_cid);
                 self.rem_cids.update_initial_cid(rem_cid);
@@ -2382,6 +2993,8 @@ impl Connection {
                 let zero_rtt = mem::take(&mut self.spaces[SpaceId::Data].sent_packets);
                 for (pn, info) in zero_rtt {
                     self.remove_in_flight(pn, &info);
+                    self.events
+                        .extend(info.datagrams.into_iter().map(Event::DatagramLost));
                     self.spaces[SpaceId::Data].pending |= info.retransmits;
                 }
                 self.streams.retransmit_all_for_0rtt();
@@ -2445,6 +3058,8 @@ impl Connection {
                                 mem::take(&mut self.spaces[SpaceId::Data].sent_packets);
                             for (pn, packet) in sent_packets {
                                 self.remove_in_flight(pn, &packet);
+                                self.events
+                                    .extend(packet.datagrams.into_iter().map(Event::DatagramLost));
                             }
                         } else {
                             self.accepted_0rtt = true;
@@ -2459,8 +3074,14 @@ impl Connection {
                     self.issue_first_cids(now);
                 } else {
                     // Server-only
-                    self.spaces[SpaceId::Data].pending.handshake_done = true;
+                    let pending = &mut self.spaces[SpaceId::Data].pending;
+                    pending.handshake_done = true;
+                    let new_token_count = self.server_config.as_ref().unwrap().new_token_count;
+                    pending.new_tokens =
+                        vec![self.path.remote; new_token_count.try_into().unwrap_or(usize::MAX)];
                     self.discard_space(now, SpaceId::Handshake);
+                    self.endpoint_events
+                        .push_back(EndpointEventInner::HandshakeComplete);
                 }
 
                 self.events.push_back(Event::Connected);
@@ -2516,21 +3137,25 @@ impl Connection {
                 Ok(())
             }
             Header::VersionNegotiate { .. } => {
-                if self.total_authed_packets > 1 {
+                // Only one round of negotiation is permitted (RFC 9368 §2.1)
+                if self.total_authed_packets > 1 || self.handshake_events.version_negotiations > 0 {
                     return Ok(());
                 }
-                let supported = packet
+                let offered = packet
                     .payload
-                    .chunks(4)
-                    .any(|x| match <[u8; 4]>::try_from(x) {
-                        Ok(version) => self.version == u32::from_be_bytes(version),
-                        Err(_) => false,
-                    });
-                if supported {
+                    .chunks_exact(4)
+                    .map(|x| u32::from_be_bytes(x.try_into().unwrap()))
+                    .collect::<Vec<_>>();
+                if offered.contains(&self.version) {
                     return Ok(());
                 }
-                debug!("remote doesn't support our version");
-                Err(ConnectionError::VersionMismatch)
+                match self.select_version(&offered) {
+                    Some(version) => self.restart_in_version(now, version),
+                    None => {
+                        debug!("remote doesn't support our version");
+                        Err(ConnectionError::VersionMismatch)
+                    }
+                }
             }
             Header::Short { .. } => unreachable!(
                 "short packets received during handshake are discarded in handle_packet"
@@ -2538,6 +3163,70 @@ impl Connection {
         }
     }
 
+    /// The version a client prefers among those offered by the server, if any
+    ///
+    /// Follows the order of [`EndpointConfig::supported_versions()`].
+    fn select_version(&self, offered: &[u32]) -> Option<u32> {
+        self.endpoint_config
+            .supported_versions
+            .iter()
+            .copied()
+            .find(|x| offered.contains(x))
+    }
+
+    /// Start the handshake afresh in `version`, after the server rejected the current one
+    fn restart_in_version(&mut self, now: Instant, version: u32) -> Result<(), ConnectionError> {
+        let Some(fallback) = self
+            .version_fallback
+            .as_ref()
+            .filter(|x| x.allow_version_negotiation)
+        else {
+            return Err(ConnectionError::VersionMismatch);
+        };
+        debug!(
+            from = self.version,
+            to = version,
+            "restarting in a version the server supports"
+        );
+        let mut params = fallback.params.clone();
+        params.version_information = Some(VersionInformation::new(
+            version,
+            &self.endpoint_config.supported_versions,
+        ));
+        self.crypto = fallback
+            .crypto
+            .clone()
+            .start_session(version, &fallback.server_name, &params)
+            .map_err(|_| ConnectionError::VersionMismatch)?;
+        self.version = version;
+        self.handshake_events.version_negotiations += 1;
+
+        // Resend the handshake from scratch, as after a Retry, keeping any address token
+        let token = mem::take(&mut self.retry_token);
+        self.discard_space(now, SpaceId::Initial);
+        self.retry_token = token;
+        self.spaces[SpaceId::Initial] = PacketSpace {
+            crypto: Some(self.crypto.initial_keys(&self.initial_dst_cid, self.side)),
+            next_packet_number: self.spaces[SpaceId::Initial].next_packet_number,
+            ..PacketSpace::new(now)
+        };
+        if let State::Handshake(ref mut state) = self.state {
+            state.client_hello = None;
+        }
+        let zero_rtt = mem::take(&mut self.spaces[SpaceId::Data].sent_packets);
+        for (pn, info) in zero_rtt {
+            self.remove_in_flight(pn, &info);
+            self.events
+                .extend(info.datagrams.into_iter().map(Event::DatagramLost));
+            self.spaces[SpaceId::Data].pending |= info.retransmits;
+        }
+        self.streams.retransmit_all_for_0rtt();
+        self.zero_rtt_crypto = None;
+        self.write_crypto();
+        self.init_0rtt();
+        Ok(())
+    }
+
     /// Process an Initial or Handshake packet payload
     fn process_early_payload(
         &mut self,
@@ -2605,7 +3294,7 @@ impl Connection {
         let mut close = None;
         let payload_len = payload.len();
         let mut ack_eliciting = false;
-        for result in frame::Iter::new(payload)? {
+        for result in frame::Iter::new(payload)?.with_extensions(&self.config.frame_extensions) {
             let frame = result?;
             let span = match frame {
                 Frame::Padding => continue,
@@ -2690,15 +3379,32 @@ impl Connection {
                             prev_path.challenge = None;
                             prev_path.challenge_pending = false;
                         }
+                        if mem::take(&mut self.migrating_to_preferred_address) {
+                            self.events
+                                .push_back(Event::MigratedToPreferredAddress(self.path.remote));
+                        }
+                    } else if let Some(PathProbe {
+                        sent: Some(sent), ..
+                    }) = self
+                        .path_probe
+                        .filter(|probe| probe.token == token && remote == self.path.remote)
+                    {
+                        let rtt = now.saturating_duration_since(sent);
+                        trace!(?rtt, "path probe answered");
+                        self.timers.stop(Timer::PathProbe);
+                        self.path_probe = None;
+                        self.events.push_back(Event::PathProbed { rtt: Some(rtt) });
                     } else {
                         debug!(token, "ignoring invalid PATH_RESPONSE");
                     }
                 }
                 Frame::MaxData(bytes) => {
                     self.streams.received_max_data(bytes);
+                    self.qlog.flow_control_updated(now, None, bytes.into());
                 }
                 Frame::MaxStreamData { id, offset } => {
                     self.streams.received_max_stream_data(id, offset)?;
+                    self.qlog.flow_control_updated(now, Some(id), offset);
                 }
                 Frame::MaxStreams { dir, count } => {
                     self.streams.received_max_streams(dir, count)?;
@@ -2708,8 +3414,14 @@ impl Connection {
                         self.spaces[SpaceId::Data].pending.max_data = true;
                     }
                 }
+                Frame::ResetStreamAt(frame) => {
+                    if self.streams.received_reset_at(frame)?.should_transmit() {
+                        self.spaces[SpaceId::Data].pending.max_data = true;
+                    }
+                }
                 Frame::DataBlocked { offset } => {
                     debug!(offset, "peer claims to be blocked at connection level");
+                    self.update_memory_usage();
                 }
                 Frame::StreamDataBlocked { id, offset } => {
                     if id.initiator() == self.side && id.dir() == Dir::Uni {
@@ -2829,7 +3541,9 @@ impl Connection {
                         return Err(TransportError::FRAME_ENCODING_ERROR("empty token"));
                     }
                     trace!("got new token");
-                    // TODO: Cache, or perhaps forward to user?
+                    if let Some((ref store, ref server_name)) = self.token_store {
+                        store.insert(server_name, token);
+                    }
                 }
                 Frame::Datagram(datagram) => {
                     if self
@@ -2874,8 +3588,18 @@ impl Connection {
                     }
                     if self.spaces[SpaceId::Handshake].crypto.is_some() {
                         self.discard_space(now, SpaceId::Handshake);
+                        // The handshake is confirmed, so migration is now permitted
+                        self.migrate_to_preferred_address(now);
                     }
                 }
+                Frame::Extension(frame) => {
+                    let Some(ext) = self.negotiated_extension(frame.ty) else {
+                        return Err(TransportError::FRAME_ENCODING_ERROR(
+                            "extension not negotiated",
+                        ));
+                    };
+                    ext.on_frame(frame.ty, frame.body)?;
+                }
             }
         }
 
@@ -2901,7 +3625,9 @@ impl Connection {
             self.close = true;
         }
 
-        if remote != self.path.remote
+        // Clients only change paths of their own accord, e.g. towards a preferred address
+        if self.side.is_server()
+            && remote != self.path.remote
             && !is_probing_packet
             && number == self.spaces[SpaceId::Data].rx_packet
         {
@@ -2953,12 +3679,107 @@ impl Connection {
             // We haven't updated the remote CID yet, this captures the remote CID we were using on
             // the previous path.
             self.prev_path = Some((self.rem_cids.active(), prev));
+            self.prev_local_ip = self.local_ip;
+        }
+
+        self.timers.set(
+            Timer::PathValidation,
+            now + 3 * cmp::max(self.pto(SpaceId::Data), prev_pto),
+        );
+    }
+
+    /// Move the connection to a new local address, as a client
+    ///
+    /// Subsequent packets are sent from `local_ip`, or from an address chosen by the operating
+    /// system if `None`, and the new path is validated with a PATH_CHALLENGE. Congestion control
+    /// and RTT estimation start over, as the new path is likely to traverse a different network.
+    /// If the peer can't be reached from the new address before validation times out, the
+    /// connection falls back to the previous address.
+    ///
+    /// Unlike [`local_address_changed()`](Self::local_address_changed), which reacts to a change
+    /// that has already happened, this allows an application to deliberately switch networks, e.g.
+    /// when the operating system reports that a better interface has become available.
+    pub fn migrate_local(
+        &mut self,
+        now: Instant,
+        local_ip: Option<IpAddr>,
+    ) -> Result<(), MigrateError> {
+        if self.side.is_server() {
+            return Err(MigrateError::NotClient);
+        }
+        if !self.state.is_established() || self.spaces[SpaceId::Handshake].crypto.is_some() {
+            return Err(MigrateError::HandshakeIncomplete);
+        }
+        if self.peer_params.disable_active_migration {
+            return Err(MigrateError::DisabledByPeer);
+        }
+        trace!(?local_ip, "local migration initiated");
+        self.switch_path(now, self.path.remote, local_ip);
+        Ok(())
+    }
+
+    /// Migrate to the server's preferred address, if it advertised one we can use
+    fn migrate_to_preferred_address(&mut self, now: Instant) {
+        let Some(ref preferred) = self.peer_params.preferred_address else {
+            return;
+        };
+        let remote = match self.path.remote {
+            SocketAddr::V4(_) => preferred.address_v4.map(SocketAddr::V4),
+            SocketAddr::V6(_) => preferred.address_v6.map(SocketAddr::V6),
+        };
+        let Some(remote) = remote.filter(|&remote| remote != self.path.remote) else {
+            return;
+        };
+        trace!(%remote, "migrating to preferred address");
+        self.switch_path(now, remote, self.local_ip);
+        self.migrating_to_preferred_address = true;
+    }
+
+    /// Whether `ip` is one of the preferred addresses advertised by this server
+    fn is_preferred_ip(&self, ip: Option<IpAddr>) -> bool {
+        let Some(ref config) = self.server_config else {
+            return false;
+        };
+        let v4 = config.preferred_address_v4.map(|x| IpAddr::V4(*x.ip()));
+        let v6 = config.preferred_address_v6.map(|x| IpAddr::V6(*x.ip()));
+        ip.is_some() && (ip == v4 || ip == v6)
+    }
+
+    /// Start sending on a new path from a client, falling back to the current one if the new path
+    /// can't be validated
+    fn switch_path(&mut self, now: Instant, remote: SocketAddr, local_ip: Option<IpAddr>) {
+        let peer_max_udp_payload_size =
+            u16::try_from(self.peer_params.max_udp_payload_size.into_inner()).unwrap_or(u16::MAX);
+        // The server authenticated its addresses during the handshake, so only its reachability
+        // over the new path needs to be confirmed
+        let mut new_path = PathData::new(
+            remote,
+            self.allow_mtud,
+            Some(peer_max_udp_payload_size),
+            now,
+            true,
+            &self.config,
+        );
+        new_path.challenge = Some(self.rng.gen());
+        new_path.challenge_pending = true;
+        let prev_pto = self.pto(SpaceId::Data);
+
+        let prev = mem::replace(&mut self.path, new_path);
+        // Keep falling back to the last path known to work if it hasn't been superseded
+        if prev.challenge.is_none() || self.prev_path.is_none() {
+            self.prev_path = Some((self.rem_cids.active(), prev));
+            self.prev_local_ip = self.local_ip;
         }
+        self.local_ip = local_ip;
 
         self.timers.set(
             Timer::PathValidation,
             now + 3 * cmp::max(self.pto(SpaceId::Data), prev_pto),
         );
+        // Break linkability, if possible
+        self.update_rem_cid();
+        self.spin = false;
+        self.ping();
     }
 
     /// Handle a change in the local address, i.e. an active migration
@@ -2997,8 +3818,12 @@ impl Connection {
             return;
         }
 
-        // Subtract 1 to account for the CID we supplied while handshaking
-        let n = self.peer_params.issue_cids_limit() - 1;
+        // Subtract 1 to account for the CID we supplied while handshaking, and another for the one
+        // supplied with our preferred address
+        let preferred = self.server_config.as_ref().is_some_and(|config| {
+            config.preferred_address_v4.is_some() || config.preferred_address_v6.is_some()
+        });
+        let n = self.peer_params.issue_cids_limit() - 1 - u64::from(preferred);
         self.endpoint_events
             .push_back(EndpointEventInner::NeedIdentifiers(now, n));
     }
@@ -3012,6 +3837,9 @@ impl Connection {
         pn: u64,
     ) -> SentFrames {
         let mut sent = SentFrames::default();
+        let chaos_protection = self.chaos_protection_applies(space_id);
+        let path_probe_timeout = self.pto(SpaceId::Data);
+        let receive_timestamps = self.receive_timestamps();
         let space = &mut self.spaces[space_id];
         let is_0rtt = space_id == SpaceId::Data && space.crypto.is_none();
         space.pending_acks.maybe_ack_non_eliciting();
@@ -3046,6 +3874,7 @@ impl Connection {
             Self::populate_acks(
                 now,
                 self.receiving_ecn,
+                receive_timestamps,
                 &mut sent,
                 space,
                 buf,
@@ -3098,6 +3927,25 @@ impl Connection {
             }
         }
 
+        // PATH_CHALLENGE requested by the application
+        if buf.len() + 9 < max_size && space_id == SpaceId::Data {
+            if let Some(probe) = self
+                .path_probe
+                .as_mut()
+                .filter(|probe| probe.sent.is_none())
+            {
+                probe.sent = Some(now);
+                probe.attempts += 1;
+                sent.non_retransmits = true;
+                sent.requires_padding = true;
+                trace!("PATH_CHALLENGE {:08x}", probe.token);
+                buf.write(frame::Type::PATH_CHALLENGE);
+                buf.write(probe.token);
+                self.stats.frame_tx.path_challenge += 1;
+                self.timers.set(Timer::PathProbe, now + path_probe_timeout);
+            }
+        }
+
         // PATH_RESPONSE
         if buf.len() + 9 < max_size && space_id == SpaceId::Data {
             if let Some(token) = self.path_responses.pop_on_path(&self.path.remote) {
@@ -3150,10 +3998,25 @@ impl Connection {
                 frame.offset += len as u64;
                 space.pending.crypto.push_front(frame);
             }
+
+            if chaos_protection && buf.len() + CHAOS_MAX_PADDING < max_size {
+                // Intersperse frames that don't affect the handshake
+                if self.rng.gen() {
+                    trace!("PING");
+                    buf.write(frame::Type::PING);
+                    self.stats.frame_tx.ping += 1;
+                } else {
+                    let len = self.rng.gen_range(1..=CHAOS_MAX_PADDING);
+                    trace!("PADDING * {}", len);
+                    buf.resize(buf.len() + len, 0);
+                }
+            }
         }
 
         if space_id == SpaceId::Data {
             self.streams.write_control_frames(
+                now,
+                self.path.rtt.get(),
                 buf,
                 &mut space.pending,
                 &mut sent.retransmits,
@@ -3197,10 +4060,55 @@ impl Connection {
             self.stats.frame_tx.retire_connection_id += 1;
         }
 
+        // NEW_TOKEN
+        while let Some(remote) = space.pending.new_tokens.pop() {
+            if remote != self.path.remote {
+                // Tokens are bound to the address they're sent to, so one for an address the
+                // client has since left would be of no use
+                continue;
+            }
+            let server_config = self.server_config.as_ref().unwrap();
+            let default_tokens = server_config.default_token_provider();
+            let tokens = server_config
+                .token_provider
+                .as_deref()
+                .unwrap_or(&default_tokens);
+            let Some(token) = tokens.new_token(&remote) else {
+                space.pending.new_tokens.clear();
+                break;
+            };
+            let len = VarInt::from_u64(token.len() as u64).unwrap();
+            if buf.len() + 1 + len.size() + token.len() >= max_size {
+                space.pending.new_tokens.push(remote);
+                break;
+            }
+            trace!("NEW_TOKEN");
+            buf.write(frame::Type::NEW_TOKEN);
+            buf.write(len);
+            buf.extend_from_slice(&token);
+            sent.retransmits.get_or_create().new_tokens.push(remote);
+            self.stats.frame_tx.new_token += 1;
+        }
+
+        // Extension frames
+        while let Some(frame) = space.pending.extension_frames.pop_front() {
+            if is_0rtt || space_id != SpaceId::Data || buf.len() + frame.size() >= max_size {
+                space.pending.extension_frames.push_front(frame);
+                break;
+            }
+            trace!(ty = %frame.ty, len = frame.body.len(), "extension frame");
+            frame.encode(buf);
+            sent.retransmits
+                .get_or_create()
+                .extension_frames
+                .push_back(frame);
+            self.stats.frame_tx.extension += 1;
+        }
+
         // DATAGRAM
         let mut sent_datagrams = false;
         while buf.len() + Datagram::SIZE_BOUND < max_size && space_id == SpaceId::Data {
-            match self.datagrams.write(buf, max_size) {
+            match self.datagrams.write(buf, max_size, &mut sent.datagrams) {
                 true => {
                     sent_datagrams = true;
                     sent.non_retransmits = true;
@@ -3216,9 +4124,7 @@ impl Connection {
 
         // STREAM
         if space_id == SpaceId::Data {
-            sent.stream_frames =
-                self.streams
-                    .write_stream_frames(buf, max_size, self.config.send_fairness);
+            sent.stream_frames = self.streams.write_stream_frames(buf, max_size);
             self.stats.frame_tx.stream += sent.stream_frames.len() as u64;
         }
 
@@ -3232,6 +4138,7 @@ impl Connection {
     fn populate_acks(
         now: Instant,
         receiving_ecn: bool,
+        receive_timestamps: Option<(Instant, u64)>,
         sent: &mut SentFrames,
         space: &mut PacketSpace,
         buf: &mut Vec<u8>,
@@ -3260,10 +4167,38 @@ impl Connection {
             delay_micros
         );
 
-        frame::Ack::encode(delay as _, space.pending_acks.ranges(), ecn, buf);
+        let timestamps = receive_timestamps
+            .map(|(basis, exponent)| {
+                space
+                    .pending_acks
+                    .take_receive_times()
+                    .map(|(pn, received)| {
+                        let micros = received.saturating_duration_since(basis).as_micros() as u64;
+                        (pn, micros >> exponent)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .filter(|x| !x.is_empty());
+
+        frame::Ack::encode(
+            delay as _,
+            space.pending_acks.ranges(),
+            ecn,
+            timestamps.as_deref(),
+            buf,
+        );
         stats.frame_tx.acks += 1;
     }
 
+    /// Basis and exponent of the receive timestamps to report, if the peer asked for them
+    fn receive_timestamps(&self) -> Option<(Instant, u64)> {
+        self.peer_params.max_receive_timestamps_per_ack?;
+        Some((
+            self.receive_timestamp_basis,
+            self.peer_params.receive_timestamps_exponent.into_inner(),
+        ))
+    }
+
     fn close_common(&mut self) {
         trace!("connection closed");
         for &timer in &Timer::VALUES {
@@ -3287,9 +4222,41 @@ impl Connection {
                 "CID authentication failure",
             ));
         }
+        self.validate_version_information(&params)?;
 
         self.set_peer_params(params);
+        self.peer_params_received = true;
+
+        Ok(())
+    }
 
+    /// Guard against version downgrades by an attacker (RFC 9368 §4)
+    fn validate_version_information(
+        &self,
+        params: &TransportParameters,
+    ) -> Result<(), TransportError> {
+        let Some(ref info) = params.version_information else {
+            if self.handshake_events.version_negotiations > 0 {
+                return Err(TransportError::VERSION_NEGOTIATION_ERROR(
+                    "version information missing after version negotiation",
+                ));
+            }
+            return Ok(());
+        };
+        if info.chosen != self.version {
+            return Err(TransportError::VERSION_NEGOTIATION_ERROR(
+                "chosen version mismatch",
+            ));
+        }
+        // Had the server's versions been offered by a genuine Version Negotiation packet, we
+        // would have selected the same version
+        if self.handshake_events.version_negotiations > 0
+            && self.select_version(info.available()) != Some(self.version)
+        {
+            return Err(TransportError::VERSION_NEGOTIATION_ERROR(
+                "version downgrade detected",
+            ));
+        }
         Ok(())
     }
 
@@ -3360,7 +4327,8 @@ impl Connection {
         self.key_phase_size = new
             .local
             .confidentiality_limit()
-            .saturating_sub(KEY_UPDATE_MARGIN);
+            .saturating_sub(KEY_UPDATE_MARGIN)
+            .min(self.config.key_update_interval.unwrap_or(u64::MAX));
         let old = mem::replace(
             &mut self.spaces[SpaceId::Data]
                 .crypto
@@ -3376,6 +4344,9 @@ impl Connection {
             update_unacked: remote,
         });
         self.key_phase = !self.key_phase;
+        self.key_updates += 1;
+        // A peer-initiated update replaces the keys just as well
+        self.key_update_requested = false;
     }
 
     fn peer_supports_ack_frequency(&self) -> bool {
@@ -3463,7 +4434,7 @@ impl Connection {
     /// Whether explicit congestion notification is in use on outgoing packets.
     #[cfg(test)]
     pub(crate) fn using_ecn(&self) -> bool {
-        self.path.sending_ecn
+        self.path.ecn.is_marking()
     }
 
     /// The number of received bytes in the current path
@@ -3504,6 +4475,7 @@ impl Connection {
     fn can_send_1rtt(&self, max_size: usize) -> bool {
         self.streams.can_send_stream_data()
             || self.path.challenge_pending
+            || matches!(self.path_probe, Some(PathProbe { sent: None, .. }))
             || self
                 .prev_path
                 .as_ref()
@@ -3513,7 +4485,7 @@ impl Connection {
                 .datagrams
                 .outgoing
                 .front()
-                .map_or(false, |x| x.size(true) <= max_size)
+                .map_or(false, |x| x.frame.size(true) <= max_size)
     }
 
     /// Update counters to account for a packet becoming acknowledged, lost, or abandoned
@@ -3577,6 +4549,12 @@ impl Connection {
     }
 }
 
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.memory.update(self.memory_reported, 0);
+    }
+}
+
 impl fmt::Debug for Connection {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Connection")
@@ -3618,6 +4596,49 @@ pub enum ConnectionError {
     /// Try using longer connection IDs.
     #[error("CIDs exhausted")]
     CidsExhausted,
+    /// The server demanded a Retry, which the client is configured to refuse
+    ///
+    /// See [`ClientConfig::allow_retry()`](crate::ClientConfig::allow_retry).
+    #[error("Retry refused")]
+    RetryRefused,
+}
+
+/// Reasons why a connection could not be moved to a new local address
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum MigrateError {
+    /// Only clients may initiate migration
+    #[error("not a client")]
+    NotClient,
+    /// Migration is not permitted until the handshake is confirmed
+    #[error("handshake incomplete")]
+    HandshakeIncomplete,
+    /// The peer does not permit migration of this connection
+    #[error("migration disabled by peer")]
+    DisabledByPeer,
+}
+
+/// What a connection sends to keep itself alive when idle
+///
+/// See [`Connection::set_keep_alive_probe()`].
+#[derive(Default)]
+pub enum KeepAliveProbe {
+    /// Send a PING frame
+    #[default]
+    Ping,
+    /// Send the returned bytes as an unreliable datagram
+    Datagram(Box<dyn FnMut() -> Bytes + Send + Sync>),
+    /// Write the returned bytes to the given send stream
+    Stream(StreamId, Box<dyn FnMut() -> Bytes + Send + Sync>),
+}
+
+impl fmt::Debug for KeepAliveProbe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Ping => f.write_str("Ping"),
+            Self::Datagram(_) => f.write_str("Datagram(..)"),
+            Self::Stream(id, _) => f.debug_tuple("Stream").field(id).finish(),
+        }
+    }
 }
 
 impl From<Close> for ConnectionError {
@@ -3636,6 +4657,7 @@ impl From<ConnectionError> for io::Error {
         let kind = match x {
             TimedOut => io::ErrorKind::TimedOut,
             Reset => io::ErrorKind::ConnectionReset,
+            RetryRefused => io::ErrorKind::ConnectionRefused,
             ApplicationClosed(_) | ConnectionClosed(_) => io::ErrorKind::ConnectionAborted,
             TransportError(_) | VersionMismatch | LocallyClosed | CidsExhausted => {
                 io::ErrorKind::Other
@@ -3727,6 +4749,17 @@ pub enum Event {
     DatagramReceived,
     /// One or more application datagrams have been sent after blocking
     DatagramsUnblocked,
+    /// A datagram sent with [`Datagrams::send_tracked`] was acknowledged by the peer
+    DatagramAcked(DatagramId),
+    /// A datagram sent with [`Datagrams::send_tracked`] was lost or discarded before being sent
+    DatagramLost(DatagramId),
+    /// The connection moved to the server's preferred address, after validating it
+    MigratedToPreferredAddress(SocketAddr),
+    /// A probe requested with [`Connection::probe_path`] completed
+    PathProbed {
+        /// Round-trip time measured by the probe, or `None` if the peer didn't respond
+        rtt: Option<Duration>,
+    },
 }
 
 fn instant_saturating_sub(x: Instant, y: Instant) -> Duration {
@@ -3752,16 +4785,27 @@ const MIN_PACKET_SPACE: usize = 40;
 /// that numbers around 10 are a good compromise.
 const MAX_TRANSMIT_SEGMENTS: usize = 10;
 
+/// Number of PATH_CHALLENGE frames sent for a path probe before giving up
+const PATH_PROBE_ATTEMPTS: u32 = 3;
+
+/// Maximum number of PADDING bytes interspersed between CRYPTO frames for chaos protection
+const CHAOS_MAX_PADDING: usize = 8;
+
 /// Perform key updates this many packets before the AEAD confidentiality limit.
 ///
 /// Chosen arbitrarily, intended to be large enough to prevent spurious connection loss.
 const KEY_UPDATE_MARGIN: u64 = 10_000;
 
+/// Maximum number of receive timestamps reported in an ACK, whatever the peer asks for
+const MAX_REPORTED_RECEIVE_TIMESTAMPS: u64 = 64;
+
 #[derive(Default)]
 struct SentFrames {
     retransmits: ThinRetransmits,
     largest_acked: Option<u64>,
     stream_frames: StreamMetaVec,
+    /// Tracked datagrams in the packet
+    datagrams: Vec<DatagramId>,
     /// Whether the packet contains non-retransmittable frames (like datagrams)
     non_retransmits: bool,
     requires_padding: bool,
@@ -3777,6 +4821,32 @@ impl SentFrames {
     }
 }
 
+/// The packets that started a connection accepted by
+/// [`Endpoint::accept_deferred()`](crate::Endpoint::accept_deferred)
+pub(crate) struct DeferredHandshake {
+    pub(crate) remote: SocketAddr,
+    pub(crate) ecn: Option<EcnCodepoint>,
+    pub(crate) packet_number: u64,
+    pub(crate) packet: InitialPacket,
+    /// Packets coalesced with the first Initial packet
+    pub(crate) rest: Option<BytesMut>,
+    /// Datagrams received before the connection was accepted
+    pub(crate) datagrams: Vec<DatagramConnectionEvent>,
+}
+
+/// What a client needs to restart its handshake in another version, should the server reply to its
+/// first Initial packet with a Version Negotiation packet
+pub(crate) struct VersionFallback {
+    /// Whether the handshake may be restarted in another version at all
+    pub(crate) allow_version_negotiation: bool,
+    /// Whether the server may have the handshake restarted with a Retry
+    pub(crate) allow_retry: bool,
+    pub(crate) crypto: Arc<dyn crypto::ClientConfig>,
+    pub(crate) server_name: String,
+    /// Transport parameters of the first attempt
+    pub(crate) params: TransportParameters,
+}
+
 /// Compute the negotiated idle timeout based on local and remote max_idle_timeout transport parameters.
 ///
 /// According to the definition of max_idle_timeout, a value of `0` means the timeout is disabled; see <https://www.rfc-editor.org/rfc/rfc9000#section-18.2-4.4.1.>
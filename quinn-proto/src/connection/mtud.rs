@@ -49,10 +49,14 @@ impl MtuDiscovery {
     }
 
     fn with_state(current_mtu: u16, min_mtu: u16, state: Option<EnabledMtuDiscovery>) -> Self {
+        let black_hole_threshold = state.as_ref().map_or(
+            MtuDiscoveryConfig::default().black_hole_threshold,
+            |state| state.config.black_hole_threshold,
+        );
         Self {
             current_mtu,
             state,
-            black_hole_detector: BlackHoleDetector::new(min_mtu),
+            black_hole_detector: BlackHoleDetector::new(min_mtu, black_hole_threshold),
         }
     }
 
@@ -68,6 +72,18 @@ impl MtuDiscovery {
             .and_then(|state| state.poll_transmit(now, self.current_mtu, next_pn))
     }
 
+    /// Notifies the [`MtuDiscovery`] that the connection moved to a path where the current MTU is
+    /// expected to hold, but might be improved upon
+    ///
+    /// Starts a new search on the next call to `poll_transmit`, unless one is already underway.
+    pub(crate) fn on_path_changed(&mut self) {
+        if let Some(state) = &mut self.state {
+            if let Phase::Complete(_) = state.phase {
+                state.phase = Phase::Initial;
+            }
+        }
+    }
+
     /// Notifies the [`MtuDiscovery`] that the peer's `max_udp_payload_size` transport parameter has
     /// been received
     pub(crate) fn on_peer_max_udp_payload_size_received(&mut self, peer_max_udp_payload_size: u16) {
@@ -199,14 +215,14 @@ impl EnabledMtuDiscovery {
             }
 
             // Retransmit lost probes, if any
-            if 0 < state.lost_probe_count && state.lost_probe_count < MAX_PROBE_RETRANSMITS {
+            if 0 < state.lost_probe_count && state.lost_probe_count < self.config.max_probes {
                 state.in_flight_probe = Some(next_pn);
                 return Some(state.last_probed_mtu);
             }
 
             let last_probe_succeeded = state.lost_probe_count == 0;
 
-            // The probe is definitely lost (we reached the MAX_PROBE_RETRANSMITS threshold)
+            // The probe is definitely lost (we reached the `max_probes` threshold)
             if !last_probe_succeeded {
                 state.lost_probe_count = 0;
                 state.in_flight_probe = None;
@@ -347,8 +363,9 @@ impl SearchState {
 /// minimum MTU or (b) smaller than a more recent acknowledged packet, because such a burst could be
 /// fully explained by a reduction in MTU.
 ///
-/// When the number of suspicious loss bursts exceeds [`BLACK_HOLE_THRESHOLD`], we judge the
-/// evidence for an MTU black hole to be sufficient.
+/// When the number of suspicious loss bursts exceeds
+/// [`MtuDiscoveryConfig::black_hole_threshold`], we judge the evidence for an MTU black hole to be
+/// sufficient.
 #[derive(Clone)]
 struct BlackHoleDetector {
     /// Packet loss bursts currently considered suspicious
@@ -363,16 +380,19 @@ struct BlackHoleDetector {
     acked_mtu: u16,
     /// The UDP payload size guaranteed to be supported by the network
     min_mtu: u16,
+    /// Maximum number of suspicious loss bursts that will not trigger black hole detection
+    threshold: usize,
 }
 
 impl BlackHoleDetector {
-    fn new(min_mtu: u16) -> Self {
+    fn new(min_mtu: u16, threshold: usize) -> Self {
         Self {
-            suspicious_loss_bursts: Vec::with_capacity(BLACK_HOLE_THRESHOLD + 1),
+            suspicious_loss_bursts: Vec::with_capacity(threshold + 1),
             current_loss_burst: None,
             largest_post_loss_packet: 0,
             acked_mtu: min_mtu,
             min_mtu,
+            threshold,
         }
     }
 
@@ -425,7 +445,7 @@ impl BlackHoleDetector {
     fn black_hole_detected(&mut self) -> bool {
         self.finish_loss_burst();
 
-        if self.suspicious_loss_bursts.len() <= BLACK_HOLE_THRESHOLD {
+        if self.suspicious_loss_bursts.len() <= self.threshold {
             return false;
         }
 
@@ -462,7 +482,7 @@ impl BlackHoleDetector {
             smallest_packet_size: burst.smallest_packet_size,
         };
 
-        if self.suspicious_loss_bursts.len() <= BLACK_HOLE_THRESHOLD {
+        if self.suspicious_loss_bursts.len() <= self.threshold {
             self.suspicious_loss_bursts.push(burst);
             return;
         }
@@ -500,12 +520,6 @@ struct CurrentLossBurst {
     latest_non_probe: u64,
 }
 
-// Corresponds to the RFC's `MAX_PROBES` constant (see
-// https://www.rfc-editor.org/rfc/rfc8899#section-5.1.2)
-const MAX_PROBE_RETRANSMITS: usize = 3;
-/// Maximum number of suspicious loss bursts that will not trigger black hole detection
-const BLACK_HOLE_THRESHOLD: usize = 3;
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -514,6 +528,8 @@ mod tests {
     use assert_matches::assert_matches;
     use std::time::Duration;
 
+    const BLACK_HOLE_THRESHOLD: usize = 3;
+
     fn default_mtud() -> MtuDiscovery {
         let config = MtuDiscoveryConfig::default();
         MtuDiscovery::new(1_200, 1_200, None, config)
@@ -667,6 +683,51 @@ mod tests {
         }
     }
 
+    #[test]
+    fn mtu_discovery_after_complete_reactivates_on_path_change() {
+        let mut config = MtuDiscoveryConfig::default();
+        config.upper_bound(9_000);
+        let mut mtud = MtuDiscovery::new(1_200, 1_200, None, config);
+        let now = Instant::now();
+        drive_to_completion(&mut mtud, now, 1_500);
+        assert_eq!(mtud.poll_transmit(now, 42), None);
+
+        // A new search starts right away, from the current MTU
+        mtud.on_path_changed();
+        assert_eq!(mtud.poll_transmit(now, 43), Some(5235));
+        assert_eq!(mtud.current_mtu, 1_471);
+
+        // An ongoing search isn't disturbed
+        mtud.on_path_changed();
+        assert_eq!(mtud.in_flight_mtu_probe(), Some(43));
+    }
+
+    #[test]
+    fn mtu_discovery_max_probes() {
+        let mut config = MtuDiscoveryConfig::default();
+        config.max_probes(1);
+        let mut mtud = MtuDiscovery::new(1_200, 1_200, None, config);
+
+        // The probe size is lowered after a single loss
+        let first_probe_size = mtud.poll_transmit(Instant::now(), 0).unwrap();
+        mtud.on_probe_lost();
+        let second_probe_size = mtud.poll_transmit(Instant::now(), 1).unwrap();
+        assert!(second_probe_size < first_probe_size);
+    }
+
+    #[test]
+    fn mtu_discovery_black_hole_threshold() {
+        let mut config = MtuDiscoveryConfig::default();
+        config.black_hole_threshold(1);
+        let mut mtud = MtuDiscovery::new(1_200, 1_200, None, config);
+        let now = Instant::now();
+
+        mtud.on_non_probe_lost(0, 1300);
+        assert!(!mtud.black_hole_detected(now));
+        mtud.on_non_probe_lost(2, 1300);
+        assert!(mtud.black_hole_detected(now));
+    }
+
     #[test]
     fn mtu_discovery_lost_three_probes_lowers_probe_size() {
         let mut mtud = default_mtud();
@@ -851,7 +912,7 @@ mod tests {
     // Loss of packets larger than have been acknowledged should indicate a black hole
     #[test]
     fn simple_black_hole_detection() {
-        let mut bhd = BlackHoleDetector::new(1200);
+        let mut bhd = BlackHoleDetector::new(1200, BLACK_HOLE_THRESHOLD);
         bhd.on_non_probe_acked((BLACK_HOLE_THRESHOLD + 1) as u64 * 2, 1300);
         for i in 0..BLACK_HOLE_THRESHOLD {
             bhd.on_non_probe_lost(i as u64 * 2, 1400);
@@ -866,7 +927,7 @@ mod tests {
     // indicate a black hole
     #[test]
     fn non_suspicious_bursts() {
-        let mut bhd = BlackHoleDetector::new(1200);
+        let mut bhd = BlackHoleDetector::new(1200, BLACK_HOLE_THRESHOLD);
         bhd.on_non_probe_acked((BLACK_HOLE_THRESHOLD + 1) as u64 * 2, 1500);
         for i in 0..(BLACK_HOLE_THRESHOLD + 1) {
             bhd.on_non_probe_lost(i as u64 * 2, 1400);
@@ -878,7 +939,7 @@ mod tests {
     // hole
     #[test]
     fn dynamic_mtu_reduction() {
-        let mut bhd = BlackHoleDetector::new(1200);
+        let mut bhd = BlackHoleDetector::new(1200, BLACK_HOLE_THRESHOLD);
         bhd.on_non_probe_acked(0, 1500);
         for i in 0..(BLACK_HOLE_THRESHOLD + 1) {
             bhd.on_non_probe_lost(i as u64 * 2, 1400);
@@ -889,7 +950,7 @@ mod tests {
     // Bursts containing heterogeneous packets are judged based on the smallest
     #[test]
     fn mixed_non_suspicious_bursts() {
-        let mut bhd = BlackHoleDetector::new(1200);
+        let mut bhd = BlackHoleDetector::new(1200, BLACK_HOLE_THRESHOLD);
         bhd.on_non_probe_acked((BLACK_HOLE_THRESHOLD + 1) as u64 * 3, 1400);
         for i in 0..(BLACK_HOLE_THRESHOLD + 1) {
             bhd.on_non_probe_lost(i as u64 * 3, 1500);
@@ -901,7 +962,7 @@ mod tests {
     // Multi-packet bursts are only counted once
     #[test]
     fn bursts_count_once() {
-        let mut bhd = BlackHoleDetector::new(1200);
+        let mut bhd = BlackHoleDetector::new(1200, BLACK_HOLE_THRESHOLD);
         bhd.on_non_probe_acked((BLACK_HOLE_THRESHOLD + 1) as u64 * 3, 1400);
         for i in 0..(BLACK_HOLE_THRESHOLD) {
             bhd.on_non_probe_lost(i as u64 * 3, 1500);
@@ -915,7 +976,7 @@ mod tests {
     // Non-suspicious bursts don't interfere with detection of suspicious bursts
     #[test]
     fn interleaved_bursts() {
-        let mut bhd = BlackHoleDetector::new(1200);
+        let mut bhd = BlackHoleDetector::new(1200, BLACK_HOLE_THRESHOLD);
         bhd.on_non_probe_acked((BLACK_HOLE_THRESHOLD + 1) as u64 * 4, 1400);
         for i in 0..(BLACK_HOLE_THRESHOLD + 1) {
             bhd.on_non_probe_lost(i as u64 * 4, 1500);
@@ -927,7 +988,7 @@ mod tests {
     // Bursts that are non-suspicious before a delivered packet become suspicious past it
     #[test]
     fn suspicious_after_acked() {
-        let mut bhd = BlackHoleDetector::new(1200);
+        let mut bhd = BlackHoleDetector::new(1200, BLACK_HOLE_THRESHOLD);
         bhd.on_non_probe_acked((BLACK_HOLE_THRESHOLD + 1) as u64 * 2, 1400);
         for i in 0..(BLACK_HOLE_THRESHOLD + 1) {
             bhd.on_non_probe_lost(i as u64 * 2, 1300);
@@ -949,7 +1010,7 @@ mod tests {
     // non-suspicious
     #[test]
     fn retroactively_non_suspicious() {
-        let mut bhd = BlackHoleDetector::new(1200);
+        let mut bhd = BlackHoleDetector::new(1200, BLACK_HOLE_THRESHOLD);
         for i in 0..BLACK_HOLE_THRESHOLD {
             bhd.on_non_probe_lost(i as u64 * 2, 1400);
         }
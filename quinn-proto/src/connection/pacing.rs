@@ -4,6 +4,8 @@ use std::time::{Duration, Instant};
 
 use tracing::warn;
 
+use crate::PacingConfig;
+
 /// A simple token-bucket pacer
 ///
 /// The pacer's capacity is derived on a fraction of the congestion window
@@ -11,60 +13,119 @@ use tracing::warn;
 /// Once the bucket is empty, further transmission is blocked.
 /// The bucket refills at a rate slightly faster
 /// than one congestion window per RTT, as recommended in
-/// <https://tools.ietf.org/html/draft-ietf-quic-recovery-34#section-7.7>
+/// <https://tools.ietf.org/html/draft-ietf-quic-recovery-34#section-7.7>,
+/// unless the congestion controller supplies an explicit pacing rate.
 pub(super) struct Pacer {
+    config: PacingConfig,
     capacity: u64,
     last_window: u64,
     last_mtu: u16,
+    last_rate: Option<u64>,
+    max_datagrams: usize,
+    last_max_datagrams: usize,
     tokens: u64,
     prev: Instant,
 }
 
 impl Pacer {
     /// Obtains a new [`Pacer`].
-    pub(super) fn new(smoothed_rtt: Duration, window: u64, mtu: u16, now: Instant) -> Self {
-        let capacity = optimal_capacity(smoothed_rtt, window, mtu);
+    pub(super) fn new(
+        config: PacingConfig,
+        smoothed_rtt: Duration,
+        window: u64,
+        mtu: u16,
+        now: Instant,
+    ) -> Self {
+        let capacity = optimal_capacity(&config, smoothed_rtt, window, None, mtu, 1);
         Self {
+            config,
             capacity,
             last_window: window,
             last_mtu: mtu,
+            last_rate: None,
+            max_datagrams: 1,
+            last_max_datagrams: 1,
             tokens: capacity,
             prev: now,
         }
     }
 
+    /// The configuration governing this pacer
+    pub(super) fn config(&self) -> &PacingConfig {
+        &self.config
+    }
+
     /// Record that a packet has been transmitted.
     pub(super) fn on_transmit(&mut self, packet_length: u16) {
         self.tokens = self.tokens.saturating_sub(packet_length.into())
     }
 
+    /// Set the number of datagrams that may be sent in a single GSO batch
+    ///
+    /// Bursts are sized to whole batches where possible, so that a burst doesn't end in a
+    /// partially filled batch requiring an extra system call.
+    pub(super) fn set_max_datagrams(&mut self, max_datagrams: usize) {
+        self.max_datagrams = max_datagrams.max(1);
+    }
+
+    /// Rate at which packets are released, in bytes per second
+    ///
+    /// `rate` is the pacing rate requested by the congestion controller, as in
+    /// [`delay()`](Self::delay).
+    pub(super) fn rate(&self, smoothed_rtt: Duration, window: u64, rate: Option<u64>) -> u64 {
+        if let Some(rate) = rate.filter(|&rate| rate != 0) {
+            return rate;
+        }
+        if smoothed_rtt.is_zero() {
+            return u64::MAX;
+        }
+        (window as f64 * self.config.rate_factor as f64 / smoothed_rtt.as_secs_f64()) as u64
+    }
+
     /// Return how long we need to wait before sending `bytes_to_send`
     ///
     /// If we can send a packet right away, this returns `None`. Otherwise, returns `Some(d)`,
     /// where `d` is the time before this function should be called again.
     ///
-    /// The 5/4 ratio used here comes from the suggestion that N = 1.25 in the draft IETF RFC for
-    /// QUIC.
+    /// `rate` is the pacing rate requested by the congestion controller in bytes per second, if
+    /// any. Otherwise, the rate is derived from `window` and `smoothed_rtt`, scaled by the
+    /// configured [`rate_factor`](PacingConfig::rate_factor). The default 5/4 ratio comes from the
+    /// suggestion that N = 1.25 in the draft IETF RFC for QUIC.
     pub(super) fn delay(
         &mut self,
         smoothed_rtt: Duration,
         bytes_to_send: u64,
         mtu: u16,
         window: u64,
+        rate: Option<u64>,
         now: Instant,
     ) -> Option<Instant> {
         debug_assert_ne!(
             window, 0,
             "zero-sized congestion control window is nonsense"
         );
-
-        if window != self.last_window || mtu != self.last_mtu {
-            self.capacity = optimal_capacity(smoothed_rtt, window, mtu);
+        let rate = rate.filter(|&rate| rate != 0);
+
+        if window != self.last_window
+            || mtu != self.last_mtu
+            || rate != self.last_rate
+            || self.max_datagrams != self.last_max_datagrams
+        {
+            self.capacity = optimal_capacity(
+                &self.config,
+                smoothed_rtt,
+                window,
+                rate,
+                mtu,
+                self.max_datagrams,
+            );
 
             // Clamp the tokens
             self.tokens = self.capacity.min(self.tokens);
             self.last_window = window;
             self.last_mtu = mtu;
+            self.last_rate = rate;
+            self.last_max_datagrams = self.max_datagrams;
         }
 
         // if we can already send a packet, there is no need for delay
@@ -72,6 +133,29 @@ impl Pacer {
             return None;
         }
 
+        let time_elapsed = now.checked_duration_since(self.prev).unwrap_or_else(|| {
+            warn!("received a timestamp early than a previous recorded time, ignoring");
+            Default::default()
+        });
+
+        if let Some(rate) = rate {
+            let new_tokens = rate as f64 * time_elapsed.as_secs_f64();
+            self.tokens = self
+                .tokens
+                .saturating_add(new_tokens as _)
+                .min(self.capacity);
+
+            self.prev = now;
+
+            if self.tokens >= bytes_to_send {
+                return None;
+            }
+
+            let missing = (bytes_to_send.max(self.capacity) - self.tokens) as u128;
+            let nanos = missing * 1_000_000_000 / rate as u128;
+            return Some(self.prev + Duration::from_nanos(nanos.min(u64::MAX.into()) as u64));
+        }
+
         // we disable pacing for extremely large windows
         if window > u32::MAX.into() {
             return None;
@@ -79,17 +163,12 @@ impl Pacer {
 
         let window = window as u32;
 
-        let time_elapsed = now.checked_duration_since(self.prev).unwrap_or_else(|| {
-            warn!("received a timestamp early than a previous recorded time, ignoring");
-            Default::default()
-        });
-
         if smoothed_rtt.as_nanos() == 0 {
             return None;
         }
 
         let elapsed_rtts = time_elapsed.as_secs_f64() / smoothed_rtt.as_secs_f64();
-        let new_tokens = window as f64 * 1.25 * elapsed_rtts;
+        let new_tokens = window as f64 * self.config.rate_factor as f64 * elapsed_rtts;
         self.tokens = self
             .tokens
             .saturating_add(new_tokens as _)
@@ -109,11 +188,12 @@ impl Pacer {
 
         // divisions come before multiplications to prevent overflow
         // this is the time at which the pacing window becomes empty
-        Some(self.prev + (unscaled_delay / 5) * 4)
+        let factor = ((self.config.rate_factor * 1000.0) as u32).max(1);
+        Some(self.prev + (unscaled_delay / factor) * 1000)
     }
 }
 
-/// Calculates a pacer capacity for a certain window and RTT
+/// Calculates a pacer capacity for a certain window and RTT, or explicit pacing rate
 ///
 /// The goal is to emit a burst (of size `capacity`) in timer intervals
 /// which compromise between
@@ -126,49 +206,77 @@ impl Pacer {
 /// tokens for the extra-elapsed time can be stored.
 ///
 /// Too long burst intervals make pacing less effective.
-fn optimal_capacity(smoothed_rtt: Duration, window: u64, mtu: u16) -> u64 {
-    let rtt = smoothed_rtt.as_nanos().max(1);
-
-    let capacity = ((window as u128 * BURST_INTERVAL_NANOS) / rtt) as u64;
+///
+/// Where a burst spans more than one GSO batch of `max_datagrams` datagrams, it is rounded down to
+/// whole batches.
+fn optimal_capacity(
+    config: &PacingConfig,
+    smoothed_rtt: Duration,
+    window: u64,
+    rate: Option<u64>,
+    mtu: u16,
+    max_datagrams: usize,
+) -> u64 {
+    let interval = config.burst_interval.as_nanos();
+    let capacity = match rate {
+        Some(rate) => (rate as u128 * interval / 1_000_000_000) as u64,
+        None => {
+            let rtt = smoothed_rtt.as_nanos().max(1);
+            ((window as u128 * interval) / rtt) as u64
+        }
+    };
 
     // Small bursts are less efficient (no GSO), could increase latency and don't effectively
     // use the channel's buffer capacity. Large bursts might block the connection on sending.
-    capacity.clamp(MIN_BURST_SIZE * mtu as u64, MAX_BURST_SIZE * mtu as u64)
+    let capacity = capacity.clamp(
+        config.min_burst_size * mtu as u64,
+        config.max_burst_size * mtu as u64,
+    );
+
+    let batch = max_datagrams as u64 * mtu as u64;
+    if max_datagrams > 1 && capacity >= batch {
+        capacity - capacity % batch
+    } else {
+        capacity
+    }
 }
 
-/// The burst interval
-///
-/// The capacity will we refilled in 4/5 of that time.
-/// 2ms is chosen here since framework timers might have 1ms precision.
-/// If kernel-level pacing is supported later a higher time here might be
-/// more applicable.
-const BURST_INTERVAL_NANOS: u128 = 2_000_000; // 2ms
-
-/// Allows some usage of GSO, and doesn't slow down the handshake.
-const MIN_BURST_SIZE: u64 = 10;
-
-/// Creating 256 packets took 1ms in a benchmark, so larger bursts don't make sense.
-const MAX_BURST_SIZE: u64 = 256;
-
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn burst_interval_nanos() -> u128 {
+        PacingConfig::default().burst_interval.as_nanos()
+    }
+
     #[test]
     fn does_not_panic_on_bad_instant() {
         let old_instant = Instant::now();
         let new_instant = old_instant + Duration::from_micros(15);
         let rtt = Duration::from_micros(400);
 
-        assert!(Pacer::new(rtt, 30000, 1500, new_instant)
-            .delay(Duration::from_micros(0), 0, 1500, 1, old_instant)
-            .is_none());
-        assert!(Pacer::new(rtt, 30000, 1500, new_instant)
-            .delay(Duration::from_micros(0), 1600, 1500, 1, old_instant)
-            .is_none());
-        assert!(Pacer::new(rtt, 30000, 1500, new_instant)
-            .delay(Duration::from_micros(0), 1500, 1500, 3000, old_instant)
-            .is_none());
+        assert!(
+            Pacer::new(PacingConfig::default(), rtt, 30000, 1500, new_instant)
+                .delay(Duration::from_micros(0), 0, 1500, 1, None, old_instant)
+                .is_none()
+        );
+        assert!(
+            Pacer::new(PacingConfig::default(), rtt, 30000, 1500, new_instant)
+                .delay(Duration::from_micros(0), 1600, 1500, 1, None, old_instant)
+                .is_none()
+        );
+        assert!(
+            Pacer::new(PacingConfig::default(), rtt, 30000, 1500, new_instant)
+                .delay(
+                    Duration::from_micros(0),
+                    1500,
+                    1500,
+                    3000,
+                    None,
+                    old_instant
+                )
+                .is_none()
+        );
     }
 
     #[test]
@@ -178,19 +286,31 @@ mod tests {
         let rtt = Duration::from_millis(50);
         let now = Instant::now();
 
-        let pacer = Pacer::new(rtt, window, mtu, now);
+        let pacer = Pacer::new(PacingConfig::default(), rtt, window, mtu, now);
         assert_eq!(
             pacer.capacity,
-            (window as u128 * BURST_INTERVAL_NANOS / rtt.as_nanos()) as u64
+            (window as u128 * burst_interval_nanos() / rtt.as_nanos()) as u64
         );
         assert_eq!(pacer.tokens, pacer.capacity);
 
-        let pacer = Pacer::new(Duration::from_millis(0), window, mtu, now);
-        assert_eq!(pacer.capacity, MAX_BURST_SIZE * mtu as u64);
+        let pacer = Pacer::new(
+            PacingConfig::default(),
+            Duration::from_millis(0),
+            window,
+            mtu,
+            now,
+        );
+        assert_eq!(
+            pacer.capacity,
+            PacingConfig::default().max_burst_size * mtu as u64
+        );
         assert_eq!(pacer.tokens, pacer.capacity);
 
-        let pacer = Pacer::new(rtt, 1, mtu, now);
-        assert_eq!(pacer.capacity, MIN_BURST_SIZE * mtu as u64);
+        let pacer = Pacer::new(PacingConfig::default(), rtt, 1, mtu, now);
+        assert_eq!(
+            pacer.capacity,
+            PacingConfig::default().min_burst_size * mtu as u64
+        );
         assert_eq!(pacer.tokens, pacer.capacity);
     }
 
@@ -201,36 +321,39 @@ mod tests {
         let rtt = Duration::from_millis(50);
         let now = Instant::now();
 
-        let mut pacer = Pacer::new(rtt, window, mtu, now);
+        let mut pacer = Pacer::new(PacingConfig::default(), rtt, window, mtu, now);
         assert_eq!(
             pacer.capacity,
-            (window as u128 * BURST_INTERVAL_NANOS / rtt.as_nanos()) as u64
+            (window as u128 * burst_interval_nanos() / rtt.as_nanos()) as u64
         );
         assert_eq!(pacer.tokens, pacer.capacity);
         let initial_tokens = pacer.tokens;
 
-        pacer.delay(rtt, mtu as u64, mtu, window * 2, now);
+        pacer.delay(rtt, mtu as u64, mtu, window * 2, None, now);
         assert_eq!(
             pacer.capacity,
-            (2 * window as u128 * BURST_INTERVAL_NANOS / rtt.as_nanos()) as u64
+            (2 * window as u128 * burst_interval_nanos() / rtt.as_nanos()) as u64
         );
         assert_eq!(pacer.tokens, initial_tokens);
 
-        pacer.delay(rtt, mtu as u64, mtu, window / 2, now);
+        pacer.delay(rtt, mtu as u64, mtu, window / 2, None, now);
         assert_eq!(
             pacer.capacity,
-            (window as u128 / 2 * BURST_INTERVAL_NANOS / rtt.as_nanos()) as u64
+            (window as u128 / 2 * burst_interval_nanos() / rtt.as_nanos()) as u64
         );
         assert_eq!(pacer.tokens, initial_tokens / 2);
 
-        pacer.delay(rtt, mtu as u64, mtu * 2, window, now);
+        pacer.delay(rtt, mtu as u64, mtu * 2, window, None, now);
         assert_eq!(
             pacer.capacity,
-            (window as u128 * BURST_INTERVAL_NANOS / rtt.as_nanos()) as u64
+            (window as u128 * burst_interval_nanos() / rtt.as_nanos()) as u64
         );
 
-        pacer.delay(rtt, mtu as u64, 20_000, window, now);
-        assert_eq!(pacer.capacity, 20_000_u64 * MIN_BURST_SIZE);
+        pacer.delay(rtt, mtu as u64, 20_000, window, None, now);
+        assert_eq!(
+            pacer.capacity,
+            20_000_u64 * PacingConfig::default().min_burst_size
+        );
     }
 
     #[test]
@@ -240,12 +363,12 @@ mod tests {
         let rtt = Duration::from_millis(50);
         let old_instant = Instant::now();
 
-        let mut pacer = Pacer::new(rtt, window, mtu, old_instant);
+        let mut pacer = Pacer::new(PacingConfig::default(), rtt, window, mtu, old_instant);
         let packet_capacity = pacer.capacity / mtu as u64;
 
         for _ in 0..packet_capacity {
             assert_eq!(
-                pacer.delay(rtt, mtu as u64, mtu, window, old_instant),
+                pacer.delay(rtt, mtu as u64, mtu, window, None, old_instant),
                 None,
                 "When capacity is available packets should be sent immediately"
             );
@@ -253,11 +376,11 @@ mod tests {
             pacer.on_transmit(mtu);
         }
 
-        let pace_duration = Duration::from_nanos((BURST_INTERVAL_NANOS * 4 / 5) as u64);
+        let pace_duration = Duration::from_nanos((burst_interval_nanos() * 4 / 5) as u64);
 
         assert_eq!(
             pacer
-                .delay(rtt, mtu as u64, mtu, window, old_instant)
+                .delay(rtt, mtu as u64, mtu, window, None, old_instant)
                 .expect("Send must be delayed")
                 .duration_since(old_instant),
             pace_duration
@@ -270,6 +393,7 @@ mod tests {
                 mtu as u64,
                 mtu,
                 window,
+                None,
                 old_instant + pace_duration / 2
             ),
             None
@@ -278,7 +402,7 @@ mod tests {
 
         for _ in 0..packet_capacity / 2 {
             assert_eq!(
-                pacer.delay(rtt, mtu as u64, mtu, window, old_instant),
+                pacer.delay(rtt, mtu as u64, mtu, window, None, old_instant),
                 None,
                 "When capacity is available packets should be sent immediately"
             );
@@ -293,10 +417,71 @@ mod tests {
                 mtu as u64,
                 mtu,
                 window,
+                None,
                 old_instant + pace_duration * 3 / 2
             ),
             None
         );
         assert_eq!(pacer.tokens, pacer.capacity);
     }
+
+    #[test]
+    fn uses_explicit_rate() {
+        let window = 2_000_000u64;
+        let mtu = 1000;
+        let rtt = Duration::from_millis(50);
+        let rate = 10_000_000; // 10MB/s
+        let now = Instant::now();
+
+        let mut pacer = Pacer::new(PacingConfig::default(), rtt, window, mtu, now);
+        assert_eq!(
+            pacer.delay(rtt, mtu as u64, mtu, window, Some(rate), now),
+            None
+        );
+        // 2ms worth of data at the explicit rate
+        assert_eq!(pacer.capacity, 20_000);
+
+        for _ in 0..pacer.capacity / mtu as u64 {
+            pacer.on_transmit(mtu);
+        }
+        assert_eq!(
+            pacer
+                .delay(rtt, mtu as u64, mtu, window, Some(rate), now)
+                .expect("Send must be delayed")
+                .duration_since(now),
+            Duration::from_millis(2)
+        );
+        assert_eq!(
+            pacer.delay(
+                rtt,
+                mtu as u64,
+                mtu,
+                window,
+                Some(rate),
+                now + Duration::from_millis(1)
+            ),
+            None
+        );
+        assert_eq!(pacer.tokens, 10_000);
+    }
+
+    #[test]
+    fn aligns_bursts_to_gso_batches() {
+        let window = 2_000_000u64;
+        let mtu = 1000;
+        let rtt = Duration::from_millis(50);
+        let now = Instant::now();
+
+        let mut pacer = Pacer::new(PacingConfig::default(), rtt, window, mtu, now);
+        assert_eq!(pacer.capacity, 80_000);
+
+        pacer.set_max_datagrams(3);
+        pacer.delay(rtt, mtu as u64, mtu, window, None, now);
+        assert_eq!(pacer.capacity, 78_000);
+
+        // Bursts smaller than a batch are left alone
+        pacer.set_max_datagrams(100);
+        pacer.delay(rtt, mtu as u64, mtu, window, None, now);
+        assert_eq!(pacer.capacity, 80_000);
+    }
 }
@@ -4,8 +4,9 @@ use bytes::Bytes;
 use rand::Rng;
 use tracing::{trace, trace_span};
 
-use super::{spaces::SentPacket, Connection, SentFrames};
+use super::{spaces::SentPacket, Connection, FrameSummary, PacketSummary, PacketType, SentFrames};
 use crate::{
+    crypto::BatchedPacket,
     frame::{self, Close},
     packet::{Header, InitialHeader, LongType, PacketNumber, PartialEncode, SpaceId, FIXED_BIT},
     ConnectionId, TransportError, TransportErrorCode, INITIAL_MTU,
@@ -47,9 +48,7 @@ impl PacketBuilder {
         // Initiate key update if we're approaching the confidentiality limit
         let sent_with_keys = conn.spaces[space_id].sent_with_keys;
         if space_id == SpaceId::Data {
-            if sent_with_keys >= conn.key_phase_size {
-                conn.initiate_key_update();
-            }
+            conn.maybe_update_keys(buffer);
         } else {
             let confidentiality_limit = conn.spaces[space_id]
                 .crypto
@@ -192,7 +191,10 @@ impl PacketBuilder {
         let ack_eliciting = self.ack_eliciting;
         let exact_number = self.exact_number;
         let space_id = self.space;
+        let short_header = self.short_header;
         let (size, padded) = self.finish(conn, buffer);
+        conn.qlog
+            .packet_sent(now, space_id, short_header, exact_number, size);
         let sent = match sent {
             Some(sent) => sent,
             None => return,
@@ -208,8 +210,10 @@ impl PacketBuilder {
             time_sent: now,
             size,
             ack_eliciting,
+            ecn: conn.path.ecn.is_marking(),
             retransmits: sent.retransmits,
             stream_frames: sent.stream_frames,
+            datagrams: sent.datagrams,
         };
 
         conn.path
@@ -229,7 +233,10 @@ impl PacketBuilder {
         }
     }
 
-    /// Encrypt packet, returning the length of the packet and whether padding was added
+    /// Queue packet for encryption, returning the length of the packet and whether padding was
+    /// added
+    ///
+    /// The packet is protected by the next call to [`Connection::seal_packets()`].
     pub(super) fn finish(self, conn: &mut Connection, buffer: &mut Vec<u8>) -> (usize, bool) {
         let pad = buffer.len() < self.min_size;
         if pad {
@@ -237,31 +244,84 @@ impl PacketBuilder {
             buffer.resize(self.min_size, 0);
         }
 
-        let space = &conn.spaces[self.space];
-        let (header_crypto, packet_crypto) = if let Some(ref crypto) = space.crypto {
-            (&*crypto.header.local, &*crypto.packet.local)
-        } else if self.space == SpaceId::Data {
-            let zero_rtt = conn.zero_rtt_crypto.as_ref().unwrap();
-            (&*zero_rtt.header, &*zero_rtt.packet)
-        } else {
-            unreachable!("tried to send {:?} packet without keys", self.space);
-        };
+        let tag_len = conn.local_keys(self.space).1.tag_len();
+        debug_assert_eq!(tag_len, self.tag_len, "Mismatching crypto tag len");
 
-        debug_assert_eq!(
-            packet_crypto.tag_len(),
-            self.tag_len,
-            "Mismatching crypto tag len"
-        );
+        if let Some(inspector) = &conn.endpoint_config.packet_inspector {
+            let payload_start = self.partial_encode.start + self.partial_encode.header_len;
+            inspector.packet_sent(&PacketSummary {
+                side: conn.side,
+                remote: conn.path.remote,
+                ty: PacketType::from_space(self.space, self.short_header),
+                number: self.exact_number,
+                len: buffer.len() + tag_len - self.partial_encode.start,
+                frames: FrameSummary::parse(Bytes::copy_from_slice(&buffer[payload_start..])),
+            });
+        }
 
-        buffer.resize(buffer.len() + packet_crypto.tag_len(), 0);
+        buffer.resize(buffer.len() + tag_len, 0);
         let encode_start = self.partial_encode.start;
-        let packet_buf = &mut buffer[encode_start..];
-        self.partial_encode.finish(
-            packet_buf,
-            header_crypto,
-            Some((self.exact_number, packet_crypto)),
+        self.partial_encode.write_len(&mut buffer[encode_start..]);
+        conn.pending_seals.push(
+            self.space,
+            BatchedPacket {
+                number: self.exact_number,
+                range: encode_start..buffer.len(),
+                header_len: self.partial_encode.header_len,
+            },
+            self.partial_encode,
         );
 
         (buffer.len() - encode_start, pad)
     }
 }
+
+/// Packets written to the outgoing buffer but not yet protected
+///
+/// Deferring protection until a transmit is complete lets the packets encrypted with the same keys
+/// be handed to [`PacketKey::encrypt_batch()`](crate::crypto::PacketKey::encrypt_batch) together,
+/// so that key implementations able to seal several packets at once can do so. The storage is
+/// reused across transmits.
+#[derive(Default)]
+pub(super) struct PendingSeals {
+    packets: Vec<BatchedPacket>,
+    /// Space and header encoding of each entry in `packets`
+    headers: Vec<(SpaceId, PartialEncode)>,
+}
+
+impl PendingSeals {
+    fn push(&mut self, space: SpaceId, packet: BatchedPacket, header: PartialEncode) {
+        self.packets.push(packet);
+        self.headers.push((space, header));
+    }
+
+    /// Protect the queued packets in `buf`, which were written by `conn`
+    pub(super) fn seal(&mut self, conn: &Connection, buf: &mut [u8]) {
+        let mut start = 0;
+        while start < self.packets.len() {
+            // Packets of the same space share keys, which never change while packets are queued
+            let space = self.headers[start].0;
+            let end = start
+                + self.headers[start..]
+                    .iter()
+                    .take_while(|(s, _)| *s == space)
+                    .count();
+            let (header_crypto, packet_crypto) = conn.local_keys(space);
+            packet_crypto.encrypt_batch(buf, &self.packets[start..end]);
+            for (packet, (_, header)) in self.packets[start..end]
+                .iter()
+                .zip(&self.headers[start..end])
+            {
+                header.protect_header(&mut buf[packet.range.clone()], header_crypto);
+            }
+            start = end;
+        }
+        self.clear();
+    }
+
+    /// Forget the queued packets, which will not be sent
+    pub(super) fn clear(&mut self) {
+        self.packets.clear();
+        self.headers.clear();
+    }
+}
@@ -1,20 +1,62 @@
 use std::{cmp, net::SocketAddr, time::Duration, time::Instant};
 
-use tracing::trace;
+use tracing::{debug, trace};
 
 use super::{
     mtud::MtuDiscovery,
     pacing::Pacer,
     spaces::{PacketSpace, SentPacket},
 };
-use crate::{congestion, packet::SpaceId, TransportConfig, TIMER_GRANULARITY};
+use crate::{congestion, packet::SpaceId, EcnCodepoint, TransportConfig, TIMER_GRANULARITY};
+
+/// Number of datagrams marked on a new path before waiting for the peer to confirm that the
+/// markings arrive intact, following [RFC 9000 §13.4.2]
+///
+/// [RFC 9000 §13.4.2]: https://www.rfc-editor.org/rfc/rfc9000.html#section-13.4.2
+const ECN_TESTING_DATAGRAMS: u8 = 10;
+
+/// Progress of explicit congestion notification validation on a path
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(super) enum EcnState {
+    /// Marking the first datagrams sent on the path; holds the number left to mark
+    Testing(u8),
+    /// Every testing datagram was sent, without confirmation of the markings yet
+    Unknown,
+    /// The peer confirmed receiving markings intact; every datagram is marked
+    Capable,
+    /// Markings were found to be mangled or unreported, or every marked packet was lost
+    Failed,
+}
+
+impl EcnState {
+    fn new() -> Self {
+        Self::Testing(ECN_TESTING_DATAGRAMS)
+    }
+
+    /// Whether outgoing datagrams are marked
+    pub(super) fn is_marking(self) -> bool {
+        matches!(self, Self::Testing(_) | Self::Capable)
+    }
+
+    /// Account for `datagrams` datagrams having been sent, marked if `is_marking()`
+    pub(super) fn on_sent(&mut self, datagrams: u64) {
+        if let Self::Testing(remaining) = *self {
+            *self = match u64::from(remaining).saturating_sub(datagrams) {
+                0 => Self::Unknown,
+                remaining => Self::Testing(remaining as u8),
+            };
+        }
+    }
+}
 
 /// Description of a particular network path
 pub(super) struct PathData {
     pub(super) remote: SocketAddr,
     pub(super) rtt: RttEstimator,
-    /// Whether we're enabling ECN on outgoing packets
-    pub(super) sending_ecn: bool,
+    /// Progress of explicit congestion notification validation
+    pub(super) ecn: EcnState,
+    /// Number of packets marked during ECN validation that are neither acknowledged nor lost
+    ecn_unconfirmed: u64,
     /// Congestion controller state
     pub(super) congestion: Box<dyn congestion::Controller>,
     /// Pacing state
@@ -53,15 +95,20 @@ impl PathData {
         validated: bool,
         config: &TransportConfig,
     ) -> Self {
-        let congestion = config
+        let mut congestion = config
             .congestion_controller_factory
             .clone()
             .build(now, config.get_initial_mtu());
+        if let Some((rtt, window)) = config.initial_rtt_and_cwnd_hint {
+            congestion.on_resume_hint(rtt, window);
+        }
         Self {
             remote,
             rtt: RttEstimator::new(config.initial_rtt),
-            sending_ecn: true,
+            ecn: EcnState::new(),
+            ecn_unconfirmed: 0,
             pacing: Pacer::new(
+                config.pacing.clone().unwrap_or_default(),
                 config.initial_rtt,
                 congestion.initial_window(),
                 config.get_initial_mtu(),
@@ -95,20 +142,29 @@ impl PathData {
     }
 
     pub(super) fn from_previous(remote: SocketAddr, prev: &Self, now: Instant) -> Self {
+        let mut mtud = prev.mtud.clone();
+        mtud.on_path_changed();
         let congestion = prev.congestion.clone_box();
         let smoothed_rtt = prev.rtt.get();
         Self {
             remote,
             rtt: prev.rtt,
-            pacing: Pacer::new(smoothed_rtt, congestion.window(), prev.current_mtu(), now),
-            sending_ecn: true,
+            pacing: Pacer::new(
+                prev.pacing.config().clone(),
+                smoothed_rtt,
+                congestion.window(),
+                prev.current_mtu(),
+                now,
+            ),
+            ecn: EcnState::new(),
+            ecn_unconfirmed: 0,
             congestion,
             challenge: None,
             challenge_pending: false,
             validated: false,
             total_sent: 0,
             total_recvd: 0,
-            mtud: prev.mtud.clone(),
+            mtud,
             first_packet_after_rtt_sample: prev.first_packet_after_rtt_sample,
             in_flight: InFlight::new(),
             first_packet: None,
@@ -129,6 +185,9 @@ impl PathData {
     /// Account for transmission of `packet` with number `pn` in `space`
     pub(super) fn sent(&mut self, pn: u64, packet: SentPacket, space: &mut PacketSpace) {
         self.in_flight.insert(&packet);
+        if packet.ecn && self.ecn != EcnState::Capable {
+            self.ecn_unconfirmed += 1;
+        }
         if self.first_packet.is_none() {
             self.first_packet = Some(pn);
         }
@@ -144,6 +203,38 @@ impl PathData {
         self.in_flight.remove(packet);
         true
     }
+
+    /// Codepoint to mark outgoing datagrams with, if any
+    pub(super) fn ecn_codepoint(&self) -> Option<EcnCodepoint> {
+        self.ecn
+            .is_marking()
+            .then(|| self.congestion.ecn_codepoint())
+    }
+
+    /// Account for the loss of `packet` with number `pn` in ECN validation
+    ///
+    /// Validation fails if every packet marked while testing the path is lost, as the markings
+    /// may be the cause.
+    pub(super) fn on_lost(&mut self, pn: u64, packet: &SentPacket) {
+        if !packet.ecn
+            || self.first_packet.map_or(true, |first| first > pn)
+            || matches!(self.ecn, EcnState::Capable | EcnState::Failed)
+        {
+            return;
+        }
+        self.ecn_unconfirmed = self.ecn_unconfirmed.saturating_sub(1);
+        if self.ecn == EcnState::Unknown && self.ecn_unconfirmed == 0 {
+            debug!("halting ECN as every marked packet was lost");
+            self.ecn = EcnState::Failed;
+        }
+    }
+
+    /// Take responsibility for the packets still in flight on `abandoned`, a path that superseded
+    /// this one but couldn't be validated
+    pub(super) fn revert_from(&mut self, abandoned: &Self) {
+        self.in_flight.bytes += abandoned.in_flight.bytes;
+        self.in_flight.ack_eliciting += abandoned.in_flight.ack_eliciting;
+    }
 }
 
 /// RTT estimation for a particular network path
@@ -160,7 +251,10 @@ pub struct RttEstimator {
 }
 
 impl RttEstimator {
-    fn new(initial_rtt: Duration) -> Self {
+    /// Construct an estimator which reports `initial_rtt` until the first measurement is made
+    ///
+    /// Useful to exercise congestion controllers outside of a connection.
+    pub fn new(initial_rtt: Duration) -> Self {
         Self {
             latest: initial_rtt,
             smoothed: None,
@@ -187,6 +281,11 @@ impl RttEstimator {
         self.min
     }
 
+    /// The most recent RTT measurement
+    pub fn latest(&self) -> Duration {
+        self.latest
+    }
+
     // PTO computed as described in RFC9002#6.2.1
     pub(crate) fn pto_base(&self) -> Duration {
         self.get() + cmp::max(4 * self.var, TIMER_GRANULARITY)
@@ -320,3 +419,13 @@ impl InFlight {
         self.ack_eliciting -= u64::from(packet.ack_eliciting);
     }
 }
+
+/// A PATH_CHALLENGE requested by the application to measure the round-trip time
+#[derive(Debug, Copy, Clone)]
+pub(super) struct PathProbe {
+    pub(super) token: u64,
+    /// When the challenge was last sent, or `None` if it is yet to be
+    pub(super) sent: Option<Instant>,
+    /// Number of times a challenge was sent
+    pub(super) attempts: u32,
+}
@@ -0,0 +1,255 @@
+//! Tracing of connections in the qlog format, for analysis with tools such as qvis
+//!
+//! Traces are written as qlog 0.3 JSON-SEQ: a header record describing the trace, followed by one
+//! record per event, each preceded by an ASCII record separator and terminated by a newline.
+
+#[cfg(feature = "qlog")]
+use std::io::{self, Write};
+use std::{fmt, time::Instant};
+
+#[cfg(feature = "qlog")]
+use tracing::debug;
+
+use super::RttEstimator;
+use crate::{
+    packet::{Header, LongType, SpaceId},
+    ConnectionId, EndpointConfig, Side, StreamId, VarInt,
+};
+
+/// Constructs destinations for the qlog traces of new connections
+///
+/// Configured with [`EndpointConfig::qlog_writer()`].
+#[cfg(feature = "qlog")]
+pub trait QlogFactory: Send + Sync {
+    /// Get the destination for the trace of a new connection, or `None` to not trace it
+    ///
+    /// `odcid` is the destination connection ID of the client's first Initial packet, which is
+    /// shared by both endpoints and conventionally used to name and correlate trace files.
+    fn writer(&self, side: Side, odcid: ConnectionId) -> Option<Box<dyn io::Write + Send + Sync>>;
+}
+
+/// The qlog trace of a single connection, if one is being recorded
+pub(super) struct QlogStream {
+    #[cfg(feature = "qlog")]
+    trace: Option<Trace>,
+}
+
+impl QlogStream {
+    pub(super) fn new(
+        config: &EndpointConfig,
+        side: Side,
+        odcid: ConnectionId,
+        now: Instant,
+    ) -> Self {
+        #[cfg(feature = "qlog")]
+        if let Some(writer) = config
+            .qlog_factory
+            .as_ref()
+            .and_then(|factory| factory.writer(side, odcid))
+        {
+            let mut this = Self {
+                trace: Some(Trace { writer, start: now }),
+            };
+            let vantage_point = match side {
+                Side::Client => "client",
+                Side::Server => "server",
+            };
+            this.emit(|trace| {
+                write!(
+                    trace.writer,
+                    "\x1e{{\"qlog_version\":\"0.3\",\"qlog_format\":\"JSON-SEQ\",\"title\":\"quinn\",\
+                     \"trace\":{{\"vantage_point\":{{\"type\":\"{vantage_point}\"}},\
+                     \"common_fields\":{{\"ODCID\":\"{odcid}\",\"time_format\":\"relative\",\
+                     \"reference_time\":0}}}}}}\n"
+                )
+            });
+            return this;
+        }
+        #[cfg(not(feature = "qlog"))]
+        let _ = (config, side, odcid, now);
+        Self {
+            #[cfg(feature = "qlog")]
+            trace: None,
+        }
+    }
+
+    pub(super) fn packet_sent(
+        &mut self,
+        now: Instant,
+        space: SpaceId,
+        short_header: bool,
+        number: u64,
+        len: usize,
+    ) {
+        if !self.enabled() {
+            return;
+        }
+        let ty = match space {
+            SpaceId::Initial => "initial",
+            SpaceId::Handshake => "handshake",
+            SpaceId::Data if short_header => "1RTT",
+            SpaceId::Data => "0RTT",
+        };
+        self.event(
+            now,
+            "transport:packet_sent",
+            format_args!(
+                "{{\"header\":{{\"packet_type\":\"{ty}\",\"packet_number\":{number}}},\
+                 \"raw\":{{\"length\":{len}}}}}"
+            ),
+        );
+    }
+
+    pub(super) fn packet_received(
+        &mut self,
+        now: Instant,
+        header: &Header,
+        number: Option<u64>,
+        len: usize,
+    ) {
+        if !self.enabled() {
+            return;
+        }
+        let ty = match header {
+            Header::Initial(_) => "initial",
+            Header::Long {
+                ty: LongType::Handshake,
+                ..
+            } => "handshake",
+            Header::Long {
+                ty: LongType::ZeroRtt,
+                ..
+            } => "0RTT",
+            Header::Retry { .. } => "retry",
+            Header::Short { .. } => "1RTT",
+            Header::VersionNegotiate { .. } => "version_negotiation",
+        };
+        let number = OptionalField("packet_number", number);
+        self.event(
+            now,
+            "transport:packet_received",
+            format_args!(
+                "{{\"header\":{{\"packet_type\":\"{ty}\"{number}}},\"raw\":{{\"length\":{len}}}}}"
+            ),
+        );
+    }
+
+    pub(super) fn packet_lost(&mut self, now: Instant, space: SpaceId, number: u64) {
+        if !self.enabled() {
+            return;
+        }
+        let ty = match space {
+            SpaceId::Initial => "initial",
+            SpaceId::Handshake => "handshake",
+            SpaceId::Data => "1RTT",
+        };
+        self.event(
+            now,
+            "recovery:packet_lost",
+            format_args!("{{\"header\":{{\"packet_type\":\"{ty}\",\"packet_number\":{number}}}}}"),
+        );
+    }
+
+    pub(super) fn metrics_updated(
+        &mut self,
+        now: Instant,
+        rtt: &RttEstimator,
+        congestion_window: u64,
+        bytes_in_flight: u64,
+    ) {
+        if !self.enabled() {
+            return;
+        }
+        self.event(
+            now,
+            "recovery:metrics_updated",
+            format_args!(
+                "{{\"min_rtt\":{},\"smoothed_rtt\":{},\"congestion_window\":{congestion_window},\
+                 \"bytes_in_flight\":{bytes_in_flight}}}",
+                Millis(rtt.min()),
+                Millis(rtt.get()),
+            ),
+        );
+    }
+
+    /// The peer raised the limit on data we may send on the connection, or on `stream` if given
+    pub(super) fn flow_control_updated(
+        &mut self,
+        now: Instant,
+        stream: Option<StreamId>,
+        max_data: u64,
+    ) {
+        if !self.enabled() {
+            return;
+        }
+        let stream = OptionalField("stream_id", stream.map(VarInt::from));
+        self.event(
+            now,
+            "quinn:flow_control_updated",
+            format_args!("{{\"max_data\":{max_data}{stream}}}"),
+        );
+    }
+
+    fn event(&mut self, now: Instant, name: &str, data: fmt::Arguments<'_>) {
+        #[cfg(feature = "qlog")]
+        self.emit(|trace| {
+            let time = Millis(now.saturating_duration_since(trace.start));
+            write!(
+                trace.writer,
+                "\x1e{{\"time\":{time},\"name\":\"{name}\",\"data\":{data}}}\n"
+            )
+        });
+        #[cfg(not(feature = "qlog"))]
+        let _ = (now, name, data);
+    }
+
+    #[cfg(feature = "qlog")]
+    fn enabled(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    #[cfg(not(feature = "qlog"))]
+    fn enabled(&self) -> bool {
+        false
+    }
+
+    /// Write to the trace, abandoning it if the destination fails
+    #[cfg(feature = "qlog")]
+    fn emit(&mut self, f: impl FnOnce(&mut Trace) -> io::Result<()>) {
+        let Some(trace) = &mut self.trace else {
+            return;
+        };
+        if let Err(e) = f(trace) {
+            debug!("abandoning qlog trace: {}", e);
+            self.trace = None;
+        }
+    }
+}
+
+#[cfg(feature = "qlog")]
+struct Trace {
+    writer: Box<dyn io::Write + Send + Sync>,
+    /// Instant that event times are relative to
+    start: Instant,
+}
+
+/// Formats a duration as fractional milliseconds
+struct Millis(std::time::Duration);
+
+impl fmt::Display for Millis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.as_secs_f64() * 1000.0)
+    }
+}
+
+/// Formats a JSON object member with a leading comma, or nothing if the value is absent
+struct OptionalField<T>(&'static str, Option<T>);
+
+impl<T: fmt::Display> fmt::Display for OptionalField<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.1 {
+            Some(value) => write!(f, ",\"{}\":{}", self.0, value),
+            None => Ok(()),
+        }
+    }
+}
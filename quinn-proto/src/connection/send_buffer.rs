@@ -1,15 +1,21 @@
 use std::{collections::VecDeque, ops::Range};
 
-use bytes::{Buf, Bytes};
+use bytes::{Buf, Bytes, BytesMut};
 
-use crate::{range_set::RangeSet, VarInt};
+use crate::{
+    connection::streams::{BytesSource, Written},
+    range_set::RangeSet,
+    SendBufferAllocator, VarInt,
+};
 
 /// Buffer of outgoing retransmittable stream data
 #[derive(Default, Debug)]
 pub(super) struct SendBuffer {
     /// Data queued by the application but not yet acknowledged. May or may not have been sent.
     unacked_segments: VecDeque<Bytes>,
-    /// Total size of `unacked_segments`
+    /// Arena chunk being filled with copied data, which follows `unacked_segments`
+    tail: BytesMut,
+    /// Total size of `unacked_segments` and `tail`
     unacked_len: usize,
     /// The first offset that hasn't been written by the application, i.e. the offset past the end of `unacked`
     offset: u64,
@@ -23,6 +29,11 @@ pub(super) struct SendBuffer {
     acks: RangeSet,
     /// Previously transmitted ranges deemed lost
     retransmits: RangeSet,
+    /// Total amount of bytes retransmitted
+    retransmitted: u64,
+    /// Offset past which data is no longer transmitted, if the stream was reset with a reliable
+    /// size
+    truncated: Option<u64>,
 }
 
 impl SendBuffer {
@@ -33,11 +44,68 @@ impl SendBuffer {
 
     /// Append application data to the end of the stream
     pub(super) fn write(&mut self, data: Bytes) {
+        self.seal_tail();
         self.unacked_len += data.len();
         self.offset += data.len() as u64;
         self.unacked_segments.push_back(data);
     }
 
+    /// Append up to `limit` bytes from `source` to the end of the stream
+    ///
+    /// With [`SendBufferAllocator::Arena`], data that `source` borrows is copied into shared
+    /// chunks rather than an allocation of its own.
+    pub(super) fn write_source<S: BytesSource>(
+        &mut self,
+        source: &mut S,
+        mut limit: usize,
+        allocator: SendBufferAllocator,
+    ) -> Written {
+        let mut result = Written::default();
+        loop {
+            let (written, chunks_consumed) = match allocator {
+                SendBufferAllocator::Arena { chunk_size } if source.is_borrowed() => {
+                    if self.tail.len() == self.tail.capacity() && limit > 0 {
+                        self.seal_tail();
+                        self.tail = BytesMut::with_capacity(chunk_size.max(1));
+                    }
+                    let len = self.tail.len();
+                    let space = self.tail.capacity() - len;
+                    let chunks_consumed = source.copy_chunk(limit.min(space), &mut self.tail);
+                    let written = self.tail.len() - len;
+                    self.unacked_len += written;
+                    self.offset += written as u64;
+                    (written, chunks_consumed)
+                }
+                _ => {
+                    let (chunk, chunks_consumed) = source.pop_chunk(limit);
+                    let written = chunk.len();
+                    if written > 0 {
+                        self.write(chunk);
+                    }
+                    (written, chunks_consumed)
+                }
+            };
+            result.chunks += chunks_consumed;
+            result.bytes += written;
+
+            if written == 0 {
+                break;
+            }
+            limit -= written;
+        }
+        result
+    }
+
+    /// Move the data copied into the current arena chunk to `unacked_segments`, so that data
+    /// stored elsewhere can follow it
+    ///
+    /// The rest of the chunk remains available for further copies.
+    fn seal_tail(&mut self) {
+        if !self.tail.is_empty() {
+            self.unacked_segments.push_back(self.tail.split().freeze());
+        }
+    }
+
     /// Discard a range of acknowledged stream data
     pub(super) fn ack(&mut self, mut range: Range<u64>) {
         // Clamp the range to data which is still tracked
@@ -53,10 +121,11 @@ impl SendBuffer {
 
             self.unacked_len -= to_advance;
             while to_advance > 0 {
-                let front = self
-                    .unacked_segments
-                    .front_mut()
-                    .expect("Expected buffered data");
+                let Some(front) = self.unacked_segments.front_mut() else {
+                    // The rest lies in the arena chunk still being filled
+                    self.tail.advance(to_advance);
+                    break;
+                };
 
                 if front.len() <= to_advance {
                     to_advance -= front.len();
@@ -107,6 +176,7 @@ impl SendBuffer {
             if end != range.end {
                 self.retransmits.insert(end..range.end);
             }
+            self.retransmitted += end - range.start;
             return (range.start..end, encode_length);
         }
 
@@ -117,14 +187,13 @@ impl SendBuffer {
         if self.unsent != 0 {
             max_len -= VarInt::size(unsafe { VarInt::from_u64_unchecked(self.unsent) });
         }
-        if self.offset - self.unsent < max_len as u64 {
+        let transmit_end = self.transmit_end();
+        if transmit_end - self.unsent < max_len as u64 {
             encode_length = true;
             max_len -= 8;
         }
 
-        let end = self
-            .offset
-            .min((max_len as u64).saturating_add(self.unsent));
+        let end = transmit_end.min((max_len as u64).saturating_add(self.unsent));
         let result = self.unsent..end;
         self.unsent = end;
         (result, encode_length)
@@ -140,7 +209,8 @@ impl SendBuffer {
         let base_offset = self.offset - self.unacked_len as u64;
 
         let mut segment_offset = base_offset;
-        for segment in self.unacked_segments.iter() {
+        let segments = self.unacked_segments.iter().map(|x| &x[..]);
+        for segment in segments.chain(Some(&self.tail[..])) {
             if offsets.start >= segment_offset
                 && offsets.start < segment_offset + segment.len() as u64
             {
@@ -156,11 +226,36 @@ impl SendBuffer {
     }
 
     /// Queue a range of sent but unacknowledged data to be retransmitted
-    pub(super) fn retransmit(&mut self, range: Range<u64>) {
+    pub(super) fn retransmit(&mut self, mut range: Range<u64>) {
         debug_assert!(range.end <= self.unsent, "unsent data can't be lost");
+        if let Some(truncated) = self.truncated {
+            range.end = range.end.min(truncated);
+            if range.start >= range.end {
+                return;
+            }
+        }
         self.retransmits.insert(range);
     }
 
+    /// Stop transmitting data past `len`, treating it as acknowledged
+    ///
+    /// Returns the amount of previously unacknowledged data discarded.
+    pub(super) fn truncate(&mut self, len: u64) -> u64 {
+        let len = len.min(self.offset);
+        let unacked = self.unacked();
+        self.truncated = Some(len);
+        self.retransmits.remove(len..u64::MAX);
+        if len < self.offset {
+            self.ack(len..self.offset);
+        }
+        unacked - self.unacked()
+    }
+
+    /// The offset up to which data will be transmitted
+    fn transmit_end(&self) -> u64 {
+        self.truncated.map_or(self.offset, |x| x.min(self.offset))
+    }
+
     pub(super) fn retransmit_all_for_0rtt(&mut self) {
         debug_assert_eq!(self.offset, self.unacked_len as u64);
         self.unsent = 0;
@@ -172,6 +267,17 @@ impl SendBuffer {
         self.offset
     }
 
+    /// Amount of written data which has been acknowledged
+    pub(super) fn acked(&self) -> u64 {
+        let base_offset = self.offset - self.unacked_len as u64;
+        base_offset + self.acks.iter().map(|r| r.end - r.start).sum::<u64>()
+    }
+
+    /// Amount of data which has been transmitted again after being deemed lost
+    pub(super) fn retransmitted(&self) -> u64 {
+        self.retransmitted
+    }
+
     /// Whether all sent data has been acknowledged
     pub(super) fn is_fully_acked(&self) -> bool {
         self.unacked_len == 0
@@ -181,7 +287,7 @@ impl SendBuffer {
     ///
     /// There may be sent unacknowledged data even when this is false.
     pub(super) fn has_unsent_data(&self) -> bool {
-        self.unsent != self.offset || !self.retransmits.is_empty()
+        self.unsent < self.transmit_end() || !self.retransmits.is_empty()
     }
 
     /// Compute the amount of data that hasn't been acknowledged
@@ -193,6 +299,7 @@ impl SendBuffer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::connection::streams::ByteSlice;
 
     #[test]
     fn fragment_with_length() {
@@ -384,11 +491,65 @@ mod tests {
         assert!(buf.acks.is_empty());
     }
 
+    #[test]
+    fn arena_coalesces_writes() {
+        let mut buf = SendBuffer::new();
+        const ARENA: SendBufferAllocator = SendBufferAllocator::Arena { chunk_size: 8 };
+        for part in [&b"Hel"[..], b"lo,", b" world", b"!"] {
+            let written = buf.write_source(&mut ByteSlice::from_slice(part), usize::MAX, ARENA);
+            assert_eq!(written.bytes, part.len());
+            assert_eq!(written.chunks, 1);
+        }
+        const MSG: &[u8] = b"Hello, world!";
+        const MSG_LEN: u64 = MSG.len() as u64;
+        assert_eq!(buf.offset(), MSG_LEN);
+        assert_eq!(aggregate_unacked(&buf), MSG);
+        // One full chunk, with the rest still being filled
+        assert_eq!(buf.unacked_segments.len(), 1);
+        assert_eq!(&buf.tail[..], b"orld!");
+
+        assert_eq!(buf.poll_transmit(16), (0..8, true));
+        assert_eq!(buf.get(0..8), b"Hello, w");
+        assert_eq!(buf.poll_transmit(16), (8..MSG_LEN, true));
+        assert_eq!(buf.get(8..MSG_LEN), b"orld!");
+
+        // Acknowledge data in the chunk being filled, then lose the rest of it
+        buf.ack(0..10);
+        assert_eq!(aggregate_unacked(&buf), &MSG[10..]);
+        buf.retransmit(10..MSG_LEN);
+        assert_eq!(buf.poll_transmit(16), (10..MSG_LEN, true));
+        assert_eq!(buf.get(10..MSG_LEN), b"ld!");
+
+        // Owned data follows the copies
+        buf.write(Bytes::from_static(b"?"));
+        assert_eq!(buf.get(12..MSG_LEN + 1), b"!");
+        assert_eq!(buf.get(MSG_LEN..MSG_LEN + 1), b"?");
+        buf.ack(0..MSG_LEN + 1);
+        assert!(buf.is_fully_acked());
+        assert_eq!(aggregate_unacked(&buf), &[]);
+    }
+
+    #[test]
+    fn arena_respects_limit() {
+        let mut buf = SendBuffer::new();
+        let arena = SendBufferAllocator::Arena { chunk_size: 4 };
+        let mut source = ByteSlice::from_slice(b"Hello, world!");
+        let written = buf.write_source(&mut source, 10, arena);
+        assert_eq!(written.bytes, 10);
+        assert_eq!(written.chunks, 0);
+        assert_eq!(buf.unacked_segments.len(), 2);
+        let written = buf.write_source(&mut source, 10, arena);
+        assert_eq!(written.bytes, 3);
+        assert_eq!(written.chunks, 1);
+        assert_eq!(aggregate_unacked(&buf), b"Hello, world!");
+    }
+
     fn aggregate_unacked(buf: &SendBuffer) -> Vec<u8> {
         let mut result = Vec::new();
         for segment in buf.unacked_segments.iter() {
             result.extend_from_slice(&segment[..]);
         }
+        result.extend_from_slice(&buf.tail);
         result
     }
 }
@@ -2,6 +2,7 @@ use std::{
     cmp,
     collections::{BTreeMap, VecDeque},
     mem,
+    net::SocketAddr,
     ops::{Bound, Index, IndexMut},
     time::{Duration, Instant},
 };
@@ -10,10 +11,10 @@ use rand::Rng;
 use rustc_hash::FxHashSet;
 use tracing::trace;
 
-use super::assembler::Assembler;
+use super::{assembler::Assembler, DatagramId};
 use crate::{
     connection::StreamsState, crypto::Keys, frame, packet::SpaceId, range_set::ArrayRangeSet,
-    shared::IssuedCid, Dir, StreamId, TransportError, VarInt,
+    shared::IssuedCid, Dir, EcnCodepoint, StreamId, TransportError, VarInt,
 };
 
 pub(super) struct PacketSpace {
@@ -172,12 +173,14 @@ impl PacketSpace {
         SendableFrames { acks, other }
     }
 
-    /// Verifies sanity of an ECN block and returns whether congestion was encountered.
+    /// Verifies sanity of an ECN block given the number of newly acknowledged packets marked with
+    /// `codepoint`, and returns the number of newly reported congestion experienced marks.
     pub(super) fn detect_ecn(
         &mut self,
         newly_acked: u64,
+        codepoint: EcnCodepoint,
         ecn: frame::EcnCounts,
-    ) -> Result<bool, &'static str> {
+    ) -> Result<u64, &'static str> {
         let ect0_increase = ecn
             .ect0
             .checked_sub(self.ecn_feedback.ect0)
@@ -194,7 +197,11 @@ impl PacketSpace {
         if total_increase < newly_acked {
             return Err("ECN bleaching");
         }
-        if (ect0_increase + ce_increase) < newly_acked || ect1_increase != 0 {
+        let (marked_increase, unmarked_increase) = match codepoint {
+            EcnCodepoint::Ect1 => (ect1_increase, ect0_increase),
+            _ => (ect0_increase, ect1_increase),
+        };
+        if (marked_increase + ce_increase) < newly_acked || unmarked_increase != 0 {
             return Err("ECN corruption");
         }
         // If total_increase > newly_acked (which happens when ACKs are lost), this is required by
@@ -202,7 +209,7 @@ impl PacketSpace {
         // to count CE packets as CE or ECT0. Recording them as CE is more consistent and keeps the
         // congestion check obvious.
         self.ecn_feedback = ecn;
-        Ok(ce_increase != 0)
+        Ok(ce_increase)
     }
 
     /// Stop tracking sent packet `number`, and return what we knew about it
@@ -284,6 +291,8 @@ pub(super) struct SentPacket {
     pub(super) size: u16,
     /// Whether an acknowledgement is expected directly in response to this packet.
     pub(super) ack_eliciting: bool,
+    /// Whether the packet was marked ECT(0) for explicit congestion notification
+    pub(super) ecn: bool,
     /// The largest packet number acknowledged by this packet
     pub(super) largest_acked: Option<u64>,
     /// Data which needs to be retransmitted in case the packet is lost.
@@ -294,6 +303,8 @@ pub(super) struct SentPacket {
     ///
     /// The actual application data is stored with the stream state.
     pub(super) stream_frames: frame::StreamMetaVec,
+    /// Tracked application datagrams in the packet
+    pub(super) datagrams: Vec<DatagramId>,
 }
 
 /// Retransmittable data queue
@@ -310,6 +321,9 @@ pub struct Retransmits {
     pub(super) retire_cids: Vec<u64>,
     pub(super) ack_frequency: bool,
     pub(super) handshake_done: bool,
+    /// Addresses to send NEW_TOKEN frames to, as a server
+    pub(super) new_tokens: Vec<SocketAddr>,
+    pub(super) extension_frames: VecDeque<frame::ExtensionFrame>,
 }
 
 impl Retransmits {
@@ -327,6 +341,8 @@ impl Retransmits {
             && self.retire_cids.is_empty()
             && !self.ack_frequency
             && !self.handshake_done
+            && self.new_tokens.is_empty()
+            && self.extension_frames.is_empty()
     }
 }
 
@@ -348,6 +364,8 @@ impl ::std::ops::BitOrAssign for Retransmits {
         self.retire_cids.extend(rhs.retire_cids);
         self.ack_frequency |= rhs.ack_frequency;
         self.handshake_done |= rhs.handshake_done;
+        self.new_tokens.extend_from_slice(&rhs.new_tokens);
+        self.extension_frames.extend(rhs.extension_frames);
     }
 }
 
@@ -587,6 +605,9 @@ pub(super) struct PendingAcks {
     largest_ack_eliciting_packet: Option<u64>,
     /// The largest acknowledged packet number sent in an ACK frame
     largest_acked: Option<u64>,
+    /// When packets were received since the last ACK frame was sent, in ascending order of packet
+    /// number, if the peer asked for receive timestamps
+    receive_times: VecDeque<(u64, Instant)>,
 }
 
 impl PendingAcks {
@@ -602,6 +623,7 @@ impl PendingAcks {
             largest_packet: None,
             largest_ack_eliciting_packet: None,
             largest_acked: None,
+            receive_times: VecDeque::new(),
         }
     }
 
@@ -732,6 +754,25 @@ impl PendingAcks {
         self.largest_acked = self.largest_ack_eliciting_packet;
     }
 
+    /// Remember when `packet` was received, to report it in the next ACK frame
+    ///
+    /// Packets received out of order are skipped so that receive times increase with packet
+    /// numbers, as the encoding requires. At most `max` of the latest packets are kept.
+    pub(super) fn record_receive_time(&mut self, packet: u64, now: Instant, max: usize) {
+        if matches!(self.receive_times.back(), Some(&(pn, _)) if pn >= packet) {
+            return;
+        }
+        if self.receive_times.len() >= max {
+            self.receive_times.pop_front();
+        }
+        self.receive_times.push_back((packet, now));
+    }
+
+    /// Take the receive times to report in an ACK frame, in descending order of packet number
+    pub(super) fn take_receive_times(&mut self) -> impl Iterator<Item = (u64, Instant)> + '_ {
+        self.receive_times.drain(..).rev()
+    }
+
     /// Insert one packet that needs to be acknowledged
     pub(super) fn insert_one(&mut self, packet: u64, now: Instant) {
         self.ranges.insert_one(packet);
@@ -23,6 +23,14 @@ impl UdpStats {
         self.bytes += bytes as u64;
         self.ios += 1;
     }
+
+    fn since(&self, earlier: &Self) -> Self {
+        Self {
+            datagrams: self.datagrams.saturating_sub(earlier.datagrams),
+            bytes: self.bytes.saturating_sub(earlier.bytes),
+            ios: self.ios.saturating_sub(earlier.ios),
+        }
+    }
 }
 
 /// Number of frames transmitted of each frame type
@@ -36,6 +44,7 @@ pub struct FrameStats {
     pub connection_close: u64,
     pub data_blocked: u64,
     pub datagram: u64,
+    pub extension: u64,
     pub handshake_done: u8,
     pub immediate_ack: u64,
     pub max_data: u64,
@@ -48,6 +57,7 @@ pub struct FrameStats {
     pub path_response: u64,
     pub ping: u64,
     pub reset_stream: u64,
+    pub reset_stream_at: u64,
     pub retire_connection_id: u64,
     pub stream_data_blocked: u64,
     pub streams_blocked_bidi: u64,
@@ -63,6 +73,7 @@ impl FrameStats {
             Frame::Ping => self.ping += 1,
             Frame::Ack(_) => self.acks += 1,
             Frame::ResetStream(_) => self.reset_stream += 1,
+            Frame::ResetStreamAt(_) => self.reset_stream_at += 1,
             Frame::StopSending(_) => self.stop_sending += 1,
             Frame::Crypto(_) => self.crypto += 1,
             Frame::Datagram(_) => self.datagram += 1,
@@ -94,6 +105,52 @@ impl FrameStats {
             Frame::AckFrequency(_) => self.ack_frequency += 1,
             Frame::ImmediateAck => self.immediate_ack += 1,
             Frame::HandshakeDone => self.handshake_done = self.handshake_done.saturating_add(1),
+            Frame::Extension(_) => self.extension += 1,
+        }
+    }
+
+    fn since(&self, earlier: &Self) -> Self {
+        Self {
+            acks: self.acks.saturating_sub(earlier.acks),
+            ack_frequency: self.ack_frequency.saturating_sub(earlier.ack_frequency),
+            crypto: self.crypto.saturating_sub(earlier.crypto),
+            connection_close: self
+                .connection_close
+                .saturating_sub(earlier.connection_close),
+            data_blocked: self.data_blocked.saturating_sub(earlier.data_blocked),
+            datagram: self.datagram.saturating_sub(earlier.datagram),
+            extension: self.extension.saturating_sub(earlier.extension),
+            handshake_done: self.handshake_done.saturating_sub(earlier.handshake_done),
+            immediate_ack: self.immediate_ack.saturating_sub(earlier.immediate_ack),
+            max_data: self.max_data.saturating_sub(earlier.max_data),
+            max_stream_data: self.max_stream_data.saturating_sub(earlier.max_stream_data),
+            max_streams_bidi: self
+                .max_streams_bidi
+                .saturating_sub(earlier.max_streams_bidi),
+            max_streams_uni: self.max_streams_uni.saturating_sub(earlier.max_streams_uni),
+            new_connection_id: self
+                .new_connection_id
+                .saturating_sub(earlier.new_connection_id),
+            new_token: self.new_token.saturating_sub(earlier.new_token),
+            path_challenge: self.path_challenge.saturating_sub(earlier.path_challenge),
+            path_response: self.path_response.saturating_sub(earlier.path_response),
+            ping: self.ping.saturating_sub(earlier.ping),
+            reset_stream: self.reset_stream.saturating_sub(earlier.reset_stream),
+            reset_stream_at: self.reset_stream_at.saturating_sub(earlier.reset_stream_at),
+            retire_connection_id: self
+                .retire_connection_id
+                .saturating_sub(earlier.retire_connection_id),
+            stream_data_blocked: self
+                .stream_data_blocked
+                .saturating_sub(earlier.stream_data_blocked),
+            streams_blocked_bidi: self
+                .streams_blocked_bidi
+                .saturating_sub(earlier.streams_blocked_bidi),
+            streams_blocked_uni: self
+                .streams_blocked_uni
+                .saturating_sub(earlier.streams_blocked_uni),
+            stop_sending: self.stop_sending.saturating_sub(earlier.stop_sending),
+            stream: self.stream.saturating_sub(earlier.stream),
         }
     }
 }
@@ -107,6 +164,7 @@ impl std::fmt::Debug for FrameStats {
             .field("CRYPTO", &self.crypto)
             .field("DATA_BLOCKED", &self.data_blocked)
             .field("DATAGRAM", &self.datagram)
+            .field("EXTENSION", &self.extension)
             .field("HANDSHAKE_DONE", &self.handshake_done)
             .field("IMMEDIATE_ACK", &self.immediate_ack)
             .field("MAX_DATA", &self.max_data)
@@ -119,6 +177,7 @@ impl std::fmt::Debug for FrameStats {
             .field("PATH_RESPONSE", &self.path_response)
             .field("PING", &self.ping)
             .field("RESET_STREAM", &self.reset_stream)
+            .field("RESET_STREAM_AT", &self.reset_stream_at)
             .field("RETIRE_CONNECTION_ID", &self.retire_connection_id)
             .field("STREAM_DATA_BLOCKED", &self.stream_data_blocked)
             .field("STREAMS_BLOCKED_BIDI", &self.streams_blocked_bidi)
@@ -137,6 +196,19 @@ pub struct PathStats {
     pub rtt: Duration,
     /// Current congestion window of the connection
     pub cwnd: u64,
+    /// Lowest round-trip time observed on this path
+    pub min_rtt: Duration,
+    /// Number of bytes sent but neither acknowledged nor deemed lost
+    pub bytes_in_flight: u64,
+    /// Current estimate of the bandwidth available on this path, in bytes per second
+    ///
+    /// Supplied by the congestion controller if it models bandwidth, as BBR does, otherwise
+    /// derived from the congestion window and the round-trip time.
+    pub bandwidth: u64,
+    /// Rate at which packets are currently paced, in bytes per second
+    ///
+    /// Zero if pacing is disabled.
+    pub pacing_rate: u64,
     /// Congestion events on the connection
     pub congestion_events: u64,
     /// The amount of packets lost on this path
@@ -154,6 +226,43 @@ pub struct PathStats {
     pub black_holes_detected: u64,
     /// Largest UDP payload size the path currently supports
     pub current_mtu: u16,
+    /// Whether the peer confirmed receiving explicit congestion notification markings intact
+    ///
+    /// Packets are no longer marked if the path is found to mangle or drop markings.
+    pub ecn_validated: bool,
+    /// The number of packets the peer reported as marked congestion experienced (CE) by the network
+    pub ecn_ce_marks: u64,
+}
+
+/// Statistics about a single send stream
+#[derive(Debug, Default, Copy, Clone)]
+#[non_exhaustive]
+pub struct StreamStats {
+    /// The amount of bytes written to the stream by the application
+    pub bytes_written: u64,
+    /// The amount of written bytes which have been acknowledged by the peer
+    pub bytes_acked: u64,
+    /// The amount of bytes which have been retransmitted after being deemed lost
+    pub bytes_retransmitted: u64,
+    /// Total time writes to the stream were blocked by flow control or send buffer limits
+    ///
+    /// `quinn-proto` has no notion of time outside of the calls that drive a connection, so this
+    /// is left at zero here and filled in by the I/O layer.
+    pub blocked_time: Duration,
+}
+
+/// Events that delayed the handshake of a client connection, each by a round trip
+///
+/// Retries indicate that the server validates client addresses, e.g. because it is under load,
+/// while version negotiation indicates that it doesn't support the version first attempted. Always
+/// zero for servers.
+#[derive(Debug, Default, Copy, Clone)]
+#[non_exhaustive]
+pub struct HandshakeEvents {
+    /// Number of Retry packets the handshake was restarted after
+    pub retries: u32,
+    /// Number of times the handshake was restarted in another version offered by the server
+    pub version_negotiations: u32,
 }
 
 /// Connection statistics
@@ -171,3 +280,56 @@ pub struct ConnectionStats {
     /// Statistics related to the current transmission path
     pub path: PathStats,
 }
+
+/// Progress of a connection between two [`ConnectionStats`] snapshots
+///
+/// Holds the difference of every counter, so that rates follow from dividing by the time between
+/// the snapshots. Gauges such as the RTT or congestion window are omitted, as the later snapshot's
+/// values are the relevant ones.
+#[derive(Debug, Default, Copy, Clone)]
+#[non_exhaustive]
+pub struct StatsDelta {
+    /// UDP datagrams transmitted
+    pub udp_tx: UdpStats,
+    /// UDP datagrams received
+    pub udp_rx: UdpStats,
+    /// Frames transmitted
+    pub frame_tx: FrameStats,
+    /// Frames received
+    pub frame_rx: FrameStats,
+    /// Packets sent on the current path
+    pub sent_packets: u64,
+    /// Packets lost on the current path
+    pub lost_packets: u64,
+    /// Bytes lost on the current path
+    pub lost_bytes: u64,
+    /// Congestion events
+    pub congestion_events: u64,
+    /// Black holes detected in the current path
+    pub black_holes_detected: u64,
+    /// Packets the peer reported as marked congestion experienced (CE) by the network
+    pub ecn_ce_marks: u64,
+}
+
+impl StatsDelta {
+    /// Compute the progress from `earlier` to `later`, snapshots of the same connection
+    pub fn between(earlier: &ConnectionStats, later: &ConnectionStats) -> Self {
+        let (earlier_path, path) = (&earlier.path, &later.path);
+        Self {
+            udp_tx: later.udp_tx.since(&earlier.udp_tx),
+            udp_rx: later.udp_rx.since(&earlier.udp_rx),
+            frame_tx: later.frame_tx.since(&earlier.frame_tx),
+            frame_rx: later.frame_rx.since(&earlier.frame_rx),
+            sent_packets: path.sent_packets.saturating_sub(earlier_path.sent_packets),
+            lost_packets: path.lost_packets.saturating_sub(earlier_path.lost_packets),
+            lost_bytes: path.lost_bytes.saturating_sub(earlier_path.lost_bytes),
+            congestion_events: path
+                .congestion_events
+                .saturating_sub(earlier_path.congestion_events),
+            black_holes_detected: path
+                .black_holes_detected
+                .saturating_sub(earlier_path.black_holes_detected),
+            ecn_ce_marks: path.ecn_ce_marks.saturating_sub(earlier_path.ecn_ce_marks),
+        }
+    }
+}
@@ -1,13 +1,16 @@
 use std::{
-    collections::{hash_map, BinaryHeap},
-    io,
+    collections::hash_map,
+    io::{self, IoSlice},
 };
 
 use bytes::Bytes;
 use thiserror::Error;
 use tracing::trace;
 
-use super::spaces::{Retransmits, ThinRetransmits};
+use super::{
+    spaces::{Retransmits, ThinRetransmits},
+    stats::StreamStats,
+};
 use crate::{
     connection::streams::state::{get_or_insert_recv, get_or_insert_send},
     frame, Dir, StreamId, VarInt,
@@ -18,13 +21,14 @@ use recv::Recv;
 pub use recv::{Chunks, ReadError, ReadableError};
 
 mod send;
-pub(crate) use send::{ByteSlice, BytesArray};
-pub use send::{BytesSource, FinishError, WriteError, Written};
+pub(crate) use send::{ByteSlice, BytesArray, IoSlices};
+pub use send::{BytesSource, FinishError, ResetAtError, WriteError, Written};
 use send::{Send, SendState};
 
 mod state;
 #[allow(unreachable_pub)] // fuzzing only
 pub use state::StreamsState;
+use state::{send_priority, StreamGroup};
 
 /// Access to streams
 pub struct Streams<'a> {
@@ -33,7 +37,7 @@ pub struct Streams<'a> {
 }
 
 impl<'a> Streams<'a> {
-    #[cfg(fuzzing)]
+    #[cfg(feature = "fuzzing")]
     pub fn new(state: &'a mut StreamsState, conn_state: &'a super::State) -> Self {
         Self { state, conn_state }
     }
@@ -76,7 +80,7 @@ impl<'a> Streams<'a> {
         Some(StreamId::new(!self.state.side, dir, x))
     }
 
-    #[cfg(fuzzing)]
+    #[cfg(feature = "fuzzing")]
     pub fn state(&mut self) -> &mut StreamsState {
         self.state
     }
@@ -86,6 +90,62 @@ impl<'a> Streams<'a> {
         self.state.send_streams
     }
 
+    /// The number of bytes that could currently be written to streams without blocking
+    ///
+    /// Accounts for connection-level flow control and the send window, but not for the flow
+    /// control limits of individual streams. See [`SendStream::max_writable`].
+    pub fn max_writable(&self) -> u64 {
+        if self.conn_state.is_closed() {
+            return 0;
+        }
+        self.state.write_limit()
+    }
+
+    /// Limit the amount of unacknowledged data buffered across all streams in `group`
+    ///
+    /// Writes to streams in the group block once `send_budget` bytes written to them are awaiting
+    /// acknowledgement, in addition to the connection-wide send window. Defaults to unlimited.
+    pub fn set_group_send_budget(&mut self, group: StreamGroupId, send_budget: u64) {
+        let group = self
+            .state
+            .groups
+            .entry(group)
+            .or_insert_with(StreamGroup::new);
+        group.send_budget = send_budget;
+        group.unblock(&mut self.state.events);
+    }
+
+    /// Set the priority with which all streams in `group` are scheduled
+    ///
+    /// While a stream is in a group, the group's priority is used in place of the stream's own. See
+    /// [`SendStream::set_priority`]. Defaults to 0.
+    pub fn set_group_priority(&mut self, group: StreamGroupId, priority: i32) {
+        let group = self
+            .state
+            .groups
+            .entry(group)
+            .or_insert_with(StreamGroup::new);
+        group.priority = priority;
+    }
+
+    /// Forget about a group, removing all streams from it
+    pub fn remove_group(&mut self, group: StreamGroupId) {
+        let Some(state) = self.state.groups.remove(&group) else {
+            return;
+        };
+        for stream in self.state.send.values_mut().flatten() {
+            if stream.group == Some(group) {
+                stream.group = None;
+            }
+        }
+        self.state.events.extend(
+            state
+                .blocked
+                .into_iter()
+                .map(|id| StreamEvent::Writable { id }),
+        );
+    }
+
     /// The number of remotely initiated open streams of a certain directionality.
     ///
     /// Includes remotely initiated streams, which have not been accepted via [`accept`](Self::accept).
@@ -161,6 +221,44 @@ impl<'a> RecvStream<'a> {
         Ok(())
     }
 
+    /// Grow the flow control window of this stream to at least `window` bytes
+    ///
+    /// Lets the peer send more data on this stream than
+    /// [`TransportConfig::stream_receive_window()`](crate::TransportConfig::stream_receive_window)
+    /// allows, e.g. to reserve room for a stream carrying a long-lived session. Data remains
+    /// subject to the connection-level window. Has no effect if the window is already larger, or
+    /// if credit is [app-driven](crate::StreamWindowUpdateConfig::app_driven).
+    pub fn set_receive_window(&mut self, window: VarInt) -> Result<(), ClosedStream> {
+        let stream = self.open_recv()?;
+        stream.min_window = stream.min_window.max(window.into());
+        self.state.queue_max_stream_data(self.id, self.pending);
+        Ok(())
+    }
+
+    /// Let the peer send `credit` more bytes on this stream
+    ///
+    /// Only has an effect if credit is
+    /// [app-driven](crate::StreamWindowUpdateConfig::app_driven), in which case this is the only
+    /// way the peer is allowed to send more than the initial
+    /// [`TransportConfig::stream_receive_window()`](crate::TransportConfig::stream_receive_window).
+    /// Data remains subject to the connection-level window.
+    pub fn extend_credit(&mut self, credit: VarInt) -> Result<(), ClosedStream> {
+        self.open_recv()?.extend_credit(credit.into());
+        self.state.queue_max_stream_data(self.id, self.pending);
+        Ok(())
+    }
+
+    fn open_recv(&mut self) -> Result<&mut Recv, ClosedStream> {
+        let Some(entry) = self.state.recv.get_mut(&self.id) else {
+            return Err(ClosedStream { _private: () });
+        };
+        let stream = get_or_insert_recv(self.state.stream_receive_window)(entry);
+        if stream.stopped {
+            return Err(ClosedStream { _private: () });
+        }
+        Ok(stream)
+    }
+
     /// Check whether this stream has been reset by the peer, returning the reset error code if so
     ///
     /// After returning `Ok(Some(_))` once, stream state will be discarded and all future calls will
@@ -199,7 +297,7 @@ pub struct SendStream<'a> {
 }
 
 impl<'a> SendStream<'a> {
-    #[cfg(fuzzing)]
+    #[cfg(feature = "fuzzing")]
     pub fn new(
         id: StreamId,
         state: &'a mut StreamsState,
@@ -231,6 +329,14 @@ impl<'a> SendStream<'a> {
         self.write_source(&mut BytesArray::from_chunks(data))
     }
 
+    /// Send data from a list of buffers on the given stream
+    ///
+    /// Returns the number of bytes successfully written. Data is copied only once it is known
+    /// how much of it can be accepted, and consecutive buffers are coalesced into a single chunk.
+    pub fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize, WriteError> {
+        Ok(self.write_source(&mut IoSlices::from_slices(bufs))?.bytes)
+    }
+
     fn write_source<B: BytesSource>(&mut self, source: &mut B) -> Result<Written, WriteError> {
         if self.conn_state.is_closed() {
             trace!(%self.id, "write blocked; connection draining");
@@ -260,17 +366,76 @@ impl<'a> SendStream<'a> {
             return Err(WriteError::Blocked);
         }
 
+        let buffer_limit = self
+            .state
+            .per_stream_send_buffer
+            .saturating_sub(stream.pending.unacked());
+        if buffer_limit == 0 && stream.is_writable() {
+            trace!(stream = %self.id, "write blocked by per-stream send buffer limit");
+            stream.buffer_blocked = true;
+            return Err(WriteError::Blocked);
+        }
+        let limit = limit.min(buffer_limit);
+
+        let mut group = stream.group.and_then(|g| self.state.groups.get_mut(&g));
+        let limit = match group {
+            Some(ref mut group) => {
+                let group_limit = group.write_limit();
+                if group_limit == 0 && stream.is_writable() {
+                    trace!(stream = %self.id, "write blocked by stream group send budget");
+                    if !group.blocked.contains(&self.id) {
+                        group.blocked.push(self.id);
+                    }
+                    return Err(WriteError::Blocked);
+                }
+                limit.min(group_limit)
+            }
+            None => limit,
+        };
+
         let was_pending = stream.is_pending();
-        let written = stream.write(source, limit)?;
+        let written = stream.write(source, limit, self.state.send_buffer_allocator)?;
         self.state.data_sent += written.bytes as u64;
         self.state.unacked_data += written.bytes as u64;
+        if let Some(group) = group {
+            group.unacked += written.bytes as u64;
+        }
         trace!(stream = %self.id, "wrote {} bytes", written.bytes);
         if !was_pending {
-            self.state.pending.push_pending(self.id, stream.priority);
+            let priority = send_priority(&self.state.groups, stream);
+            self.state.pending.push(self.id, priority);
         }
         Ok(written)
     }
 
+    /// The number of bytes that could currently be written to this stream without blocking
+    ///
+    /// This is the lesser of the stream's and the connection's flow control credit, further limited
+    /// by the send window and the per-stream send buffer limit. Congestion control is not taken
+    /// into account, as it only delays transmission of data that has already been written.
+    pub fn max_writable(&self) -> Result<u64, ClosedStream> {
+        let limit = match self.conn_state.is_closed() {
+            true => 0,
+            false => self.state.write_limit(),
+        };
+        let stream_limit = match self.state.send.get(&self.id) {
+            Some(Some(s)) if s.is_writable() => {
+                let group_limit = s
+                    .group
+                    .and_then(|g| self.state.groups.get(&g))
+                    .map_or(u64::MAX, |g| g.write_limit());
+                let buffer_limit = self
+                    .state
+                    .per_stream_send_buffer
+                    .saturating_sub(s.pending.unacked());
+                (s.max_data - s.offset()).min(group_limit).min(buffer_limit)
+            }
+            Some(Some(_)) | None => return Err(ClosedStream { _private: () }),
+            Some(None) => self.state.max_send_data(self.id).into(),
+        };
+        Ok(limit.min(stream_limit))
+    }
+
     /// Check if this stream was stopped, get the reason if it was
     pub fn stopped(&self) -> Result<Option<VarInt>, ClosedStream> {
         match self.state.send.get(&self.id).as_ref() {
@@ -280,6 +445,46 @@ impl<'a> SendStream<'a> {
         }
     }
 
+    /// Get statistics about data sent on this stream
+    ///
+    /// [`StreamStats::blocked_time`] is not tracked at this layer and is always zero.
+    pub fn stats(&self) -> Result<StreamStats, ClosedStream> {
+        match self.state.send.get(&self.id).as_ref() {
+            Some(Some(s)) => Ok(StreamStats {
+                bytes_written: s.pending.offset(),
+                bytes_acked: s.pending.acked(),
+                bytes_retransmitted: s.pending.retransmitted(),
+                ..StreamStats::default()
+            }),
+            Some(None) => Ok(StreamStats::default()),
+            None => Err(ClosedStream { _private: () }),
+        }
+    }
+
+    /// Check whether all data written to this stream so far has been acknowledged by the peer
+    ///
+    /// If it hasn't, a [`StreamEvent::Flushed`] will be emitted once it has. If the stream is
+    /// finished and fully acknowledged in the meantime, [`StreamEvent::Finished`] is emitted
+    /// instead. Fails with `ClosedStream` if the stream was reset or is otherwise closed.
+    pub fn flushed(&mut self) -> Result<bool, ClosedStream> {
+        let stream = match self.state.send.get_mut(&self.id) {
+            Some(Some(s)) => s,
+            // No data has been written yet
+            Some(None) => return Ok(true),
+            None => return Err(ClosedStream { _private: () }),
+        };
+        if matches!(
+            stream.state,
+            SendState::ResetSent | SendState::ResetAtSent { .. }
+        ) {
+            return Err(ClosedStream { _private: () });
+        }
+
+        let flushed = stream.pending.is_fully_acked();
+        stream.flush_requested = !flushed;
+        Ok(flushed)
+    }
+
     /// Finish a send stream, signalling that no more data will be sent.
     ///
     /// If this fails, no [`StreamEvent::Finished`] will be generated.
@@ -297,7 +502,8 @@ impl<'a> SendStream<'a> {
         let was_pending = stream.is_pending();
         stream.finish()?;
         if !was_pending {
-            self.state.pending.push_pending(self.id, stream.priority);
+            let priority = send_priority(&self.state.groups, stream);
+            self.state.pending.push(self.id, priority);
         }
 
         Ok(())
@@ -308,6 +514,30 @@ impl<'a> SendStream<'a> {
     /// # Panics
     /// - when applied to a receive stream
     pub fn reset(&mut self, error_code: VarInt) -> Result<(), ClosedStream> {
+        self.reset_inner(error_code, 0)
+    }
+
+    /// Abandon transmitting data on a stream, except for the first `reliable_size` bytes
+    ///
+    /// Uses the reliable stream reset extension to guarantee that the peer's application receives
+    /// the data written below `reliable_size`, which is (re)transmitted as needed, before learning
+    /// of the reset. `reliable_size` is capped to the amount of data written so far. A stream may
+    /// be reset again with a smaller reliable size, including by [`reset()`](Self::reset).
+    ///
+    /// Fails with [`ResetAtError::Unsupported`] if the peer didn't advertise support for the
+    /// extension, in which case [`reset()`](Self::reset) may be used instead.
+    ///
+    /// # Panics
+    /// - when applied to a receive stream
+    pub fn reset_at(&mut self, error_code: VarInt, reliable_size: u64) -> Result<(), ResetAtError> {
+        if !self.state.peer_supports_reset_at {
+            return Err(ResetAtError::Unsupported);
+        }
+        self.reset_inner(error_code, reliable_size)
+            .map_err(|ClosedStream { .. }| ResetAtError::ClosedStream)
+    }
+
+    fn reset_inner(&mut self, error_code: VarInt, reliable_size: u64) -> Result<(), ClosedStream> {
         let max_send_data = self.state.max_send_data(self.id);
         let stream = self
             .state
@@ -316,16 +546,25 @@ impl<'a> SendStream<'a> {
             .map(get_or_insert_send(max_send_data))
             .ok_or(ClosedStream { _private: () })?;
 
-        if matches!(stream.state, SendState::ResetSent) {
+        let reliable_size = reliable_size.min(stream.offset());
+        match stream.state {
             // Redundant reset call
-            return Err(ClosedStream { _private: () });
+            SendState::ResetSent => return Err(ClosedStream { _private: () }),
+            SendState::ResetAtSent {
+                reliable_size: current,
+                ..
+            } if reliable_size >= current => return Err(ClosedStream { _private: () }),
+            _ => {}
         }
 
         // Restore the portion of the send window consumed by the data that we aren't about to
         // send. We leave flow control alone because the peer's responsible for issuing additional
         // credit based on the final offset communicated in the RESET_STREAM frame we send.
-        self.state.unacked_data -= stream.pending.unacked();
-        stream.reset();
+        let released = stream.reset(reliable_size);
+        self.state.unacked_data -= released;
+        if let Some(group) = stream.group.and_then(|g| self.state.groups.get_mut(&g)) {
+            group.release(released, &mut self.state.events);
+        }
         self.pending.reset_stream.push((self.id, error_code));
 
         // Don't reopen an already-closed stream we haven't forgotten yet
@@ -349,107 +588,77 @@ impl<'a> SendStream<'a> {
         Ok(())
     }
 
-    /// Get the priority of a stream
+    /// Add the stream to `group`, or remove it from its current group if `None`
+    ///
+    /// Data already written to the stream but not yet acknowledged is moved to the new group's
+    /// send budget. Groups are created on first use with an unlimited send budget and priority 0.
+    /// Takes effect for scheduling the next time the stream becomes pending.
     ///
     /// # Panics
     /// - when applied to a receive stream
-    pub fn priority(&self) -> Result<i32, ClosedStream> {
+    pub fn set_group(&mut self, group: Option<StreamGroupId>) -> Result<(), ClosedStream> {
+        let max_send_data = self.state.max_send_data(self.id);
         let stream = self
             .state
             .send
-            .get(&self.id)
+            .get_mut(&self.id)
+            .map(get_or_insert_send(max_send_data))
             .ok_or(ClosedStream { _private: () })?;
 
-        Ok(stream.as_ref().map(|s| s.priority).unwrap_or_default())
-    }
-}
-
-/// A queue of streams with pending outgoing data, sorted by priority
-struct PendingStreamsQueue {
-    streams: BinaryHeap<PendingStream>,
-    /// The next stream to write out. This is `Some` when `TransportConfig::send_fairness(false)` and writing a stream is
-    /// interrupted while the stream still has some pending data. See `reinsert_pending()`.
-    next: Option<PendingStream>,
-    /// A monotonically decreasing counter, used to implement round-robin scheduling for streams of the same priority.
-    /// Underflowing is not a practical concern, as it is initialized to u64::MAX and only decremented by 1 in `push_pending`
-    recency: u64,
-}
-
-impl PendingStreamsQueue {
-    fn new() -> Self {
-        Self {
-            streams: BinaryHeap::new(),
-            next: None,
-            recency: u64::MAX,
+        let unacked = match stream.is_reset() {
+            true => 0,
+            false => stream.pending.unacked(),
+        };
+        if let Some(old) = stream.group.and_then(|g| self.state.groups.get_mut(&g)) {
+            old.blocked.retain(|&id| id != self.id);
+            old.release(unacked, &mut self.state.events);
         }
+        if let Some(group) = group {
+            let new = self
+                .state
+                .groups
+                .entry(group)
+                .or_insert_with(StreamGroup::new);
+            new.unacked += unacked;
+        }
+        stream.group = group;
+        Ok(())
     }
 
-    /// Reinsert a stream that was pending and still contains unsent data.
-    fn reinsert_pending(&mut self, id: StreamId, priority: i32) {
-        assert!(self.next.is_none());
-
-        self.next = Some(PendingStream {
-            priority,
-            recency: self.recency, // the value here doesn't really matter
-            id,
-        });
-    }
-
-    /// Push a pending stream ID with the given priority, queued after any already-queued streams for the priority
-    fn push_pending(&mut self, id: StreamId, priority: i32) {
-        // Note that in the case where fairness is disabled, if we have a reinserted stream we don't
-        // bump it even if priority > next.priority. In order to minimize fragmentation we
-        // always try to complete a stream once part of it has been written.
-
-        // As the recency counter is monotonically decreasing, we know that using its value to sort this stream will queue it
-        // after all other queued streams of the same priority.
-        // This is enough to implement round-robin scheduling for streams that are still pending even after being handled,
-        // as in that case they are removed from the `BinaryHeap`, handled, and then immediately reinserted.
-        self.recency -= 1;
-        self.streams.push(PendingStream {
-            priority,
-            recency: self.recency,
-            id,
-        });
-    }
-
-    fn pop(&mut self) -> Option<PendingStream> {
-        self.next.take().or_else(|| self.streams.pop())
-    }
+    /// Get the group of a stream
+    ///
+    /// # Panics
+    /// - when applied to a receive stream
+    pub fn group(&self) -> Result<Option<StreamGroupId>, ClosedStream> {
+        let stream = self
+            .state
+            .send
+            .get(&self.id)
+            .ok_or(ClosedStream { _private: () })?;
 
-    fn clear(&mut self) {
-        self.next = None;
-        self.streams.clear();
+        Ok(stream.as_ref().and_then(|s| s.group))
     }
 
-    fn iter(&self) -> impl Iterator<Item = &PendingStream> {
-        self.next.iter().chain(self.streams.iter())
-    }
+    /// Get the priority of a stream
+    ///
+    /// # Panics
+    /// - when applied to a receive stream
+    pub fn priority(&self) -> Result<i32, ClosedStream> {
+        let stream = self
+            .state
+            .send
+            .get(&self.id)
+            .ok_or(ClosedStream { _private: () })?;
 
-    #[cfg(test)]
-    fn len(&self) -> usize {
-        self.streams.len() + self.next.is_some() as usize
+        Ok(stream.as_ref().map(|s| s.priority).unwrap_or_default())
     }
 }
 
-/// The [`StreamId`] of a stream with pending data queued, ordered by its priority and recency
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
-struct PendingStream {
-    /// The priority of the stream
-    // Note that this field should be kept above the `recency` field, in order for the `Ord` derive to be correct
-    // (See https://doc.rust-lang.org/stable/std/cmp/trait.Ord.html#derivable)
-    priority: i32,
-    /// A tie-breaker for streams of the same priority, used to improve fairness by implementing round-robin scheduling:
-    /// Larger values are prioritized, so it is initialised to `u64::MAX`, and when a stream writes data, we know
-    /// that it currently has the highest recency value, so it is deprioritized by setting its recency to 1 less than the
-    /// previous lowest recency value, such that all other streams of this priority will get processed once before we get back
-    /// round to this one
-    recency: u64,
-    /// The ID of the stream
-    // The way this type is used ensures that every instance has a unique `recency` value, so this field should be kept below
-    // the `priority` and `recency` fields, so that it does not interfere with the behaviour of the `Ord` derive
-    id: StreamId,
-}
+/// Identifier of a group of send streams sharing a send budget and priority
+///
+/// Chosen by the application; see [`SendStream::set_group`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct StreamGroupId(pub u64);
 
 /// Application events about streams
 #[derive(Debug, PartialEq, Eq)]
@@ -476,6 +685,13 @@ pub enum StreamEvent {
         /// Which stream has been finished
         id: StreamId,
     },
+    /// All data written to a stream so far has been acknowledged
+    ///
+    /// Only generated after [`SendStream::flushed`] returned `false` for the stream.
+    Flushed {
+        /// Which stream has been flushed
+        id: StreamId,
+    },
     /// The peer asked us to stop sending on an outgoing stream
     Stopped {
         /// Which stream has been stopped
@@ -4,11 +4,11 @@ use std::mem;
 use thiserror::Error;
 use tracing::debug;
 
-use super::state::get_or_insert_recv;
+use super::state::{get_or_insert_recv, WindowEpoch};
 use super::{ClosedStream, Retransmits, ShouldTransmit, StreamId, StreamsState};
 use crate::connection::assembler::{Assembler, Chunk, IllegalOrderedRead};
 use crate::connection::streams::state::StreamRecv;
-use crate::{frame, TransportError, VarInt};
+use crate::{frame, StreamWindowUpdateConfig, TransportError, VarInt};
 
 #[derive(Debug, Default)]
 pub(super) struct Recv {
@@ -18,6 +18,12 @@ pub(super) struct Recv {
     sent_max_stream_data: u64,
     pub(super) end: u64,
     pub(super) stopped: bool,
+    /// Consumption of the receive window, for auto-tuning
+    pub(super) window_epoch: WindowEpoch,
+    /// Lower bound on the receive window set by the application
+    pub(super) min_window: u64,
+    /// Limit on the stream's data granted by the application, in app-driven mode
+    credit_limit: u64,
 }
 
 impl Recv {
@@ -28,6 +34,9 @@ impl Recv {
             sent_max_stream_data: initial_max_data,
             end: 0,
             stopped: false,
+            window_epoch: WindowEpoch::default(),
+            min_window: 0,
+            credit_limit: initial_max_data,
         })
     }
 
@@ -38,6 +47,9 @@ impl Recv {
         self.sent_max_stream_data = initial_max_data;
         self.end = 0;
         self.stopped = false;
+        self.window_epoch = WindowEpoch::default();
+        self.min_window = 0;
+        self.credit_limit = initial_max_data;
     }
 
     /// Process a STREAM frame
@@ -45,7 +57,7 @@ impl Recv {
     /// Return value is `(number_of_new_bytes_ingested, stream_is_closed)`
     pub(super) fn ingest(
         &mut self,
-        frame: frame::Stream,
+        mut frame: frame::Stream,
         payload_len: usize,
         received: u64,
         max_data: u64,
@@ -66,6 +78,14 @@ impl Recv {
 
         let new_bytes = self.credit_consumed_by(end, received, max_data)?;
 
+        if let RecvState::ResetRecvd { reliable_size, .. } = self.state {
+            // Data past the reliable size of a reset stream is never delivered
+            if frame.offset >= reliable_size {
+                return Ok((new_bytes, false));
+            }
+            frame.data.truncate((reliable_size - frame.offset) as usize);
+        }
+
         // Stopped streams don't need to wait for the actual data, they just need to know
         // how much there was.
         if frame.fin && !self.stopped {
@@ -92,7 +112,11 @@ impl Recv {
         self.stopped = true;
         self.assembler.clear();
         // Issue flow control credit for unread data
-        let read_credits = self.end - self.assembler.bytes_read();
+        let read_credits = match self.state {
+            // Credit for data past the reliable size was issued when the reset was received
+            RecvState::ResetRecvd { reliable_size, .. } => reliable_size,
+            RecvState::Recv { .. } => self.end,
+        } - self.assembler.bytes_read();
         // This may send a spurious STOP_SENDING if we've already received all data, but it's a bit
         // fiddly to distinguish that from the case where we've received a FIN but are missing some
         // data that the peer might still be trying to retransmit, in which case a STOP_SENDING is
@@ -107,21 +131,48 @@ impl Recv {
     /// transmission of the value is recommended. If the boolean value is
     /// `false` the new window should only be transmitted if a previous transmission
     /// had failed.
-    pub(super) fn max_stream_data(&mut self, stream_receive_window: u64) -> (u64, ShouldTransmit) {
+    pub(super) fn max_stream_data(
+        &self,
+        stream_receive_window: u64,
+        update: &StreamWindowUpdateConfig,
+    ) -> (u64, ShouldTransmit) {
+        if update.app_driven {
+            let transmit =
+                self.can_send_flow_control() && self.credit_limit > self.sent_max_stream_data;
+            return (self.credit_limit, ShouldTransmit(transmit));
+        }
+
+        let stream_receive_window = stream_receive_window.max(self.min_window);
         let max_stream_data = self.assembler.bytes_read() + stream_receive_window;
 
         // Only announce a window update if it's significant enough
         // to make it worthwhile sending a MAX_STREAM_DATA frame.
         // We use here a fraction of the configured stream receive window to make
         // the decision, and accommodate for streams using bigger windows requiring
-        // less updates. A fixed size would also work - but it would need to be
-        // smaller than `stream_receive_window` in order to make sure the stream
-        // does not get stuck.
+        // less updates. The configured minimum is capped to `stream_receive_window`
+        // in order to make sure the stream does not get stuck.
+        let threshold = ((stream_receive_window as f64 * f64::from(update.threshold)) as u64)
+            .max(update.min_delta)
+            .min(stream_receive_window);
         let diff = max_stream_data - self.sent_max_stream_data;
-        let transmit = self.can_send_flow_control() && diff >= (stream_receive_window / 8);
+        let transmit = self.can_send_flow_control() && diff >= threshold;
         (max_stream_data, ShouldTransmit(transmit))
     }
 
+    /// Whether the peer has used up more than half of the window it was last granted
+    pub(super) fn credit_running_low(&self, stream_receive_window: u64) -> bool {
+        let window = stream_receive_window.max(self.min_window);
+        self.sent_max_stream_data.saturating_sub(self.end) < window / 2
+    }
+
+    /// Grant the peer `credit` more bytes on this stream, in app-driven mode
+    pub(super) fn extend_credit(&mut self, credit: u64) {
+        self.credit_limit = self
+            .credit_limit
+            .saturating_add(credit)
+            .min(VarInt::MAX.into_inner());
+    }
+
     /// Records that a `MAX_STREAM_DATA` announcing a certain window was sent
     ///
     /// This will suppress enqueuing further `MAX_STREAM_DATA` frames unless
@@ -152,8 +203,15 @@ impl Recv {
     }
 
     /// Whether data is still being accepted from the peer
+    ///
+    /// After a reset, this is only the case while data below the reliable size remains unread.
     pub(super) fn is_receiving(&self) -> bool {
-        matches!(self.state, RecvState::Recv { .. })
+        match self.state {
+            RecvState::Recv { .. } => true,
+            RecvState::ResetRecvd { reliable_size, .. } => {
+                !self.stopped && self.assembler.bytes_read() < reliable_size
+            }
+        }
     }
 
     fn final_offset(&self) -> Option<u64> {
@@ -164,10 +222,14 @@ impl Recv {
     }
 
     /// Returns `false` iff the reset was redundant
+    ///
+    /// Data below `reliable_size` is still delivered before the reset is reported, if the stream
+    /// is being read in order. A later reset can only reduce the reliable size.
     pub(super) fn reset(
         &mut self,
         error_code: VarInt,
         final_offset: VarInt,
+        reliable_size: u64,
         received: u64,
         max_data: u64,
     ) -> Result<bool, TransportError> {
@@ -183,28 +245,62 @@ impl Recv {
         }
         self.credit_consumed_by(final_offset.into(), received, max_data)?;
 
-        if matches!(self.state, RecvState::ResetRecvd { .. }) {
-            return Ok(false);
+        // Unordered reads can't tell when all data below the reliable size has been delivered, so
+        // the reset takes effect immediately for them
+        let bytes_read = self.assembler.bytes_read();
+        let reliable_size = match self.stopped || !self.assembler.is_ordered() {
+            true => bytes_read,
+            false => reliable_size.max(bytes_read),
+        };
+
+        if let RecvState::ResetRecvd {
+            reliable_size: ref mut current,
+            ..
+        } = self.state
+        {
+            if reliable_size >= *current {
+                return Ok(false);
+            }
+            *current = reliable_size;
+            return Ok(true);
         }
         self.state = RecvState::ResetRecvd {
             size: final_offset.into(),
             error_code,
+            reliable_size,
         };
-        // Nuke buffers so that future reads fail immediately, which ensures future reads don't
-        // issue flow control credit redundant to that already issued. We could instead special-case
-        // reset streams during read, but it's unclear if there's any benefit to retaining data for
-        // reset streams.
-        self.assembler.clear();
+        // The final offset accounts for all data the peer may send
+        self.end = final_offset.into();
+        if reliable_size == bytes_read {
+            // Nuke buffers so that future reads fail immediately, which ensures future reads don't
+            // issue flow control credit redundant to that already issued. We could instead
+            // special-case reset streams during read, but it's unclear if there's any benefit to
+            // retaining data for reset streams.
+            self.assembler.clear();
+        }
         Ok(true)
     }
 
+    /// The error code of the peer's reset, once all data to be delivered reliably has been read
     pub(super) fn reset_code(&self) -> Option<VarInt> {
         match self.state {
-            RecvState::ResetRecvd { error_code, .. } => Some(error_code),
+            RecvState::ResetRecvd {
+                error_code,
+                reliable_size,
+                ..
+            } if self.assembler.bytes_read() >= reliable_size => Some(error_code),
             _ => None,
         }
     }
 
+    /// The offset up to which data is delivered despite a reset, if the stream was reset
+    pub(super) fn reliable_size(&self) -> Option<u64> {
+        match self.state {
+            RecvState::ResetRecvd { reliable_size, .. } => Some(reliable_size),
+            RecvState::Recv { .. } => None,
+        }
+    }
+
     /// Compute the amount of flow control credit consumed, or return an error if more was consumed
     /// than issued
     fn credit_consumed_by(
@@ -295,14 +391,29 @@ impl<'a> Chunks<'a> {
             ChunksState::Finalized => panic!("must not call next() after finalize()"),
         };
 
-        if let Some(chunk) = rs.assembler.read(max_length, self.ordered) {
-            self.read += chunk.bytes.len() as u64;
-            return Ok(Some(chunk));
+        // Only data below the reliable size is delivered from reset streams
+        let max_length = match rs.reliable_size() {
+            Some(size) => max_length
+                .min(usize::try_from(size - rs.assembler.bytes_read()).unwrap_or(usize::MAX)),
+            None => max_length,
+        };
+        if max_length > 0 || rs.reliable_size().is_none() {
+            if let Some(chunk) = rs.assembler.read(max_length, self.ordered) {
+                self.read += chunk.bytes.len() as u64;
+                return Ok(Some(chunk));
+            }
         }
 
         match rs.state {
-            RecvState::ResetRecvd { error_code, .. } => {
-                debug_assert_eq!(self.read, 0, "reset streams have empty buffers");
+            RecvState::ResetRecvd {
+                error_code,
+                reliable_size,
+                ..
+            } => {
+                if rs.assembler.bytes_read() < reliable_size {
+                    // Waiting for data that must be delivered before the reset
+                    return Err(ReadError::Blocked);
+                }
                 let state = mem::replace(&mut self.state, ChunksState::Reset(error_code));
                 // At this point if we have `rs` self.state must be `ChunksState::Readable`
                 let recv = match state {
@@ -333,6 +444,63 @@ impl<'a> Chunks<'a> {
         }
     }
 
+    /// Copy data that would be returned by subsequent ordered reads into `buf`, without consuming
+    /// it
+    ///
+    /// Yields the number of bytes copied, which is only zero if `buf` is empty, or `None` if the
+    /// stream was finished. Errors are reported as by [`next()`](Self::next). Must only be used on
+    /// `Chunks` obtained for ordered reads.
+    pub fn peek(&mut self, buf: &mut [u8]) -> Result<Option<usize>, ReadError> {
+        let Some(chunks) = self.peek_chunks()? else {
+            return Ok(None);
+        };
+        let mut copied = 0;
+        for chunk in chunks {
+            let n = chunk.bytes.len().min(buf.len() - copied);
+            buf[copied..copied + n].copy_from_slice(&chunk.bytes[..n]);
+            copied += n;
+            if copied == buf.len() {
+                break;
+            }
+        }
+        Ok(Some(copied))
+    }
+
+    /// Get the next chunk that would be returned by an ordered read, without consuming it
+    ///
+    /// Yields `None` if the stream was finished. Errors are reported as by [`next()`](Self::next).
+    /// Must only be used on `Chunks` obtained for ordered reads.
+    pub fn peek_chunk(&mut self) -> Result<Option<Chunk>, ReadError> {
+        Ok(self
+            .peek_chunks()?
+            .map(|chunks| chunks.into_iter().next().unwrap()))
+    }
+
+    /// Contiguous unread data, or `None` if the stream was finished
+    fn peek_chunks(&mut self) -> Result<Option<Vec<Chunk>>, ReadError> {
+        assert!(self.ordered, "peeking requires ordered reads");
+        if let ChunksState::Readable(ref mut rs) = self.state {
+            let mut chunks = rs.assembler.peek();
+            if let Some(size) = rs.reliable_size() {
+                chunks.retain_mut(|chunk| {
+                    let len = size
+                        .saturating_sub(chunk.offset)
+                        .min(chunk.bytes.len() as u64);
+                    chunk.bytes.truncate(len as usize);
+                    len > 0
+                });
+            }
+            if !chunks.is_empty() {
+                return Ok(Some(chunks));
+            }
+        }
+        // No data is available, so this only updates and reports the state of the stream
+        match self.next(0)? {
+            Some(_) => unreachable!("no data available"),
+            None => Ok(None),
+        }
+    }
+
     /// Mark the read data as consumed from the stream.
     ///
     /// The number of read bytes will be released from the congestion window,
@@ -359,16 +527,12 @@ impl<'a> Chunks<'a> {
         let mut should_transmit = self.streams.queue_max_stream_id(self.pending);
 
         // If the stream hasn't finished, we may need to issue stream-level flow control credit
-        if let ChunksState::Readable(mut rs) = state {
-            let (_, max_stream_data) = rs.max_stream_data(self.streams.stream_receive_window);
-            should_transmit |= max_stream_data.0;
-            if max_stream_data.0 {
-                self.pending.max_stream_data.insert(self.id);
-            }
+        if let ChunksState::Readable(rs) = state {
             // Return the stream to storage for future use
             self.streams
                 .recv
                 .insert(self.id, Some(StreamRecv::Open(rs)));
+            should_transmit |= self.streams.queue_max_stream_data(self.id, self.pending);
         }
 
         // Issue connection-level flow control credit for any data we read regardless of state
@@ -430,8 +594,15 @@ impl From<IllegalOrderedRead> for ReadableError {
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum RecvState {
-    Recv { size: Option<u64> },
-    ResetRecvd { size: u64, error_code: VarInt },
+    Recv {
+        size: Option<u64>,
+    },
+    ResetRecvd {
+        size: u64,
+        error_code: VarInt,
+        /// Offset up to which data is still delivered to the application
+        reliable_size: u64,
+    },
 }
 
 impl Default for RecvState {
@@ -481,7 +652,8 @@ mod tests {
             "full connection flow control credit is issued by stop"
         );
 
-        let (max_stream_data, transmit) = s.max_stream_data(RECV_WINDOW);
+        let (max_stream_data, transmit) =
+            s.max_stream_data(RECV_WINDOW, &StreamWindowUpdateConfig::default());
         assert!(!transmit.should_transmit());
         assert_eq!(
             max_stream_data, RECV_WINDOW,
@@ -506,7 +678,8 @@ mod tests {
         assert_eq!(new_bytes, RECV_WINDOW - (INITIAL_OFFSET + INITIAL_BYTES));
         assert!(!is_closed);
 
-        let (max_stream_data, transmit) = s.max_stream_data(RECV_WINDOW);
+        let (max_stream_data, transmit) =
+            s.max_stream_data(RECV_WINDOW, &StreamWindowUpdateConfig::default());
         assert!(!transmit.should_transmit());
         assert_eq!(
             max_stream_data, RECV_WINDOW,
@@ -533,7 +706,8 @@ mod tests {
         );
         assert!(!is_closed);
 
-        let (max_stream_data, transmit) = s.max_stream_data(RECV_WINDOW);
+        let (max_stream_data, transmit) =
+            s.max_stream_data(RECV_WINDOW, &StreamWindowUpdateConfig::default());
         assert!(!transmit.should_transmit());
         assert_eq!(
             max_stream_data, RECV_WINDOW,
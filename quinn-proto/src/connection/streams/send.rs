@@ -1,7 +1,10 @@
-use bytes::Bytes;
+use std::io::IoSlice;
+
+use bytes::{Bytes, BytesMut};
 use thiserror::Error;
 
-use crate::{connection::send_buffer::SendBuffer, frame, VarInt};
+use super::StreamGroupId;
+use crate::{connection::send_buffer::SendBuffer, frame, SendBufferAllocator, VarInt};
 
 #[derive(Debug)]
 pub(super) struct Send {
@@ -13,8 +16,15 @@ pub(super) struct Send {
     pub(super) fin_pending: bool,
     /// Whether this stream is in the `connection_blocked` list of `Streams`
     pub(super) connection_blocked: bool,
+    /// Whether a write failed because this stream's unacknowledged data reached the per-stream
+    /// send buffer limit
+    pub(super) buffer_blocked: bool,
     /// The reason the peer wants us to stop, if `STOP_SENDING` was received
     pub(super) stop_reason: Option<VarInt>,
+    /// Whether a `StreamEvent::Flushed` should be emitted once all written data is acknowledged
+    pub(super) flush_requested: bool,
+    /// The group whose send budget and priority apply to this stream, if any
+    pub(super) group: Option<StreamGroupId>,
 }
 
 impl Send {
@@ -26,11 +36,14 @@ impl Send {
             priority: 0,
             fin_pending: false,
             connection_blocked: false,
+            buffer_blocked: false,
             stop_reason: None,
+            flush_requested: false,
+            group: None,
         })
     }
 
-    /// Whether the stream has been reset
+    /// Whether the stream has been reset without any data left to deliver
     pub(super) fn is_reset(&self) -> bool {
         matches!(self.state, SendState::ResetSent { .. })
     }
@@ -53,6 +66,7 @@ impl Send {
         &mut self,
         source: &mut S,
         limit: u64,
+        allocator: SendBufferAllocator,
     ) -> Result<Written, WriteError> {
         if !self.is_writable() {
             return Err(WriteError::ClosedStream);
@@ -64,31 +78,29 @@ impl Send {
         if budget == 0 {
             return Err(WriteError::Blocked);
         }
-        let mut limit = limit.min(budget) as usize;
-
-        let mut result = Written::default();
-        loop {
-            let (chunk, chunks_consumed) = source.pop_chunk(limit);
-            result.chunks += chunks_consumed;
-            result.bytes += chunk.len();
-
-            if chunk.is_empty() {
-                break;
-            }
-
-            limit -= chunk.len();
-            self.pending.write(chunk);
-        }
-
-        Ok(result)
+        let limit = limit.min(budget) as usize;
+        Ok(self.pending.write_source(source, limit, allocator))
     }
 
     /// Update stream state due to a reset sent by the local application
-    pub(super) fn reset(&mut self) {
+    ///
+    /// Data below `reliable_size` continues to be transmitted until acknowledged. Returns the
+    /// amount of unacknowledged data that will no longer be transmitted.
+    pub(super) fn reset(&mut self, reliable_size: u64) -> u64 {
         use SendState::*;
-        if let DataSent { .. } | Ready = self.state {
+        if let ResetSent = self.state {
+            return 0;
+        }
+        self.fin_pending = false;
+        if reliable_size == 0 {
             self.state = ResetSent;
+            return self.pending.unacked();
         }
+        self.state = ResetAtSent {
+            reliable_size,
+            reset_acked: false,
+        };
+        self.pending.truncate(reliable_size)
     }
 
     /// Handle STOP_SENDING
@@ -114,6 +126,9 @@ impl Send {
                 *finish_acked |= frame.fin;
                 *finish_acked && self.pending.is_fully_acked()
             }
+            SendState::ResetAtSent { reset_acked, .. } => {
+                reset_acked && self.pending.is_fully_acked()
+            }
             _ => false,
         }
     }
@@ -221,6 +236,74 @@ impl<'a> BytesSource for ByteSlice<'a> {
         let chunks_consumed = usize::from(self.data.is_empty());
         (chunk, chunks_consumed)
     }
+
+    fn is_borrowed(&self) -> bool {
+        true
+    }
+
+    fn copy_chunk(&mut self, limit: usize, buf: &mut BytesMut) -> usize {
+        let limit = limit.min(self.data.len());
+        if limit == 0 {
+            return 0;
+        }
+
+        buf.extend_from_slice(&self.data[..limit]);
+        self.data = &self.data[limit..];
+        usize::from(self.data.is_empty())
+    }
+}
+
+/// A [`BytesSource`] implementation for `&[IoSlice]`
+///
+/// Like [`ByteSlice`], data is only copied once it is known how much of it can be written.
+/// Data from consecutive slices is coalesced into a single [`Bytes`] chunk, so that at most one
+/// allocation is performed per call to `pop_chunk`.
+pub(crate) struct IoSlices<'a> {
+    /// The wrapped slices which haven't been fully consumed yet
+    slices: &'a [IoSlice<'a>],
+    /// The amount of bytes already consumed from the first slice
+    offset: usize,
+}
+
+impl<'a> IoSlices<'a> {
+    pub(crate) fn from_slices(slices: &'a [IoSlice<'a>]) -> Self {
+        Self { slices, offset: 0 }
+    }
+}
+
+impl<'a> BytesSource for IoSlices<'a> {
+    fn pop_chunk(&mut self, limit: usize) -> (Bytes, usize) {
+        let available = self.slices.iter().map(|s| s.len()).sum::<usize>() - self.offset;
+        let mut buf = BytesMut::with_capacity(limit.min(available));
+        let chunks_consumed = self.copy_chunk(limit, &mut buf);
+        (buf.freeze(), chunks_consumed)
+    }
+
+    fn is_borrowed(&self) -> bool {
+        true
+    }
+
+    fn copy_chunk(&mut self, mut limit: usize, buf: &mut BytesMut) -> usize {
+        let mut chunks_consumed = 0;
+        while let Some(slice) = self.slices.first() {
+            let remaining = &slice[self.offset..];
+            let n = remaining.len().min(limit);
+            if n < remaining.len() {
+                // Partially consume the slice, which is only possible if the limit was reached
+                buf.extend_from_slice(&remaining[..n]);
+                self.offset += n;
+                break;
+            }
+
+            // The loop also skips empty slices while still marking them as consumed
+            buf.extend_from_slice(remaining);
+            limit -= n;
+            self.slices = &self.slices[1..];
+            self.offset = 0;
+            chunks_consumed += 1;
+        }
+        chunks_consumed
+    }
 }
 
 /// A source of one or more buffers which can be converted into `Bytes` buffers on demand
@@ -241,6 +324,24 @@ pub trait BytesSource {
     ///   source had been truncated in order to adhere to the limit. It can also
     ///   be more than 1, if zero-length chunks had been skipped.
     fn pop_chunk(&mut self, limit: usize) -> (Bytes, usize);
+
+    /// Whether the source borrows its data, which must then be copied to be retained
+    ///
+    /// Such sources should also implement [`copy_chunk()`](Self::copy_chunk), letting the copies
+    /// of small writes share storage rather than each get an allocation of its own.
+    fn is_borrowed(&self) -> bool {
+        false
+    }
+
+    /// Appends the next part of the source, up to `limit` bytes, to `buf`
+    ///
+    /// Consumes the source like [`pop_chunk()`](Self::pop_chunk), and returns how many complete
+    /// chunks were consumed. Only called if [`is_borrowed()`](Self::is_borrowed) returns `true`.
+    fn copy_chunk(&mut self, limit: usize, buf: &mut BytesMut) -> usize {
+        let (chunk, chunks_consumed) = self.pop_chunk(limit);
+        buf.extend_from_slice(&chunk);
+        chunks_consumed
+    }
 }
 
 /// Indicates how many bytes and chunks had been transferred in a write operation
@@ -286,6 +387,12 @@ pub(super) enum SendState {
     DataSent { finish_acked: bool },
     /// Sent RESET
     ResetSent,
+    /// Sent RESET_STREAM_AT; now sending retransmits of data below `reliable_size` only
+    ResetAtSent {
+        reliable_size: u64,
+        /// Whether the RESET_STREAM_AT frame has been acknowledged
+        reset_acked: bool,
+    },
 }
 
 /// Reasons why attempting to finish a stream might fail
@@ -304,6 +411,17 @@ pub enum FinishError {
     ClosedStream,
 }
 
+/// Reasons why attempting to reset a stream with a reliable size might fail
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ResetAtError {
+    /// The peer does not support the reliable stream reset extension
+    #[error("reliable reset unsupported by peer")]
+    Unsupported,
+    /// The stream has not been opened or was already reset with no larger reliable size
+    #[error("closed stream")]
+    ClosedStream,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -399,4 +517,56 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn io_slices() {
+        let full = b"Hello World 123456789 ABCDEFGHJIJKLMNOPQRSTUVWXYZ".to_owned();
+        let slices = [
+            IoSlice::new(b""),
+            IoSlice::new(b"Hello "),
+            IoSlice::new(b"Wo"),
+            IoSlice::new(b""),
+            IoSlice::new(b"r"),
+            IoSlice::new(b"ld"),
+            IoSlice::new(b""),
+            IoSlice::new(b" 12345678"),
+            IoSlice::new(b"9 ABCDE"),
+            IoSlice::new(b"F"),
+            IoSlice::new(b"GHJIJKLMNOPQRSTUVWXYZ"),
+        ];
+        for limit in 0..full.len() {
+            let mut source = IoSlices::from_slices(&slices);
+
+            let mut buf = Vec::new();
+            let mut chunks_popped = 0;
+            let mut chunks_consumed = 0;
+            let mut remaining = limit;
+            loop {
+                let (chunk, consumed) = source.pop_chunk(remaining);
+                chunks_consumed += consumed;
+
+                if !chunk.is_empty() {
+                    buf.extend_from_slice(&chunk);
+                    remaining -= chunk.len();
+                    chunks_popped += 1;
+                } else {
+                    break;
+                }
+            }
+
+            assert_eq!(&buf[..], &full[..limit]);
+            // Slices are coalesced into a single chunk
+            assert_eq!(chunks_popped, usize::from(limit != 0));
+
+            if limit == full.len() {
+                assert_eq!(chunks_consumed, slices.len());
+            }
+        }
+
+        // Resume from a partially consumed slice
+        let mut source = IoSlices::from_slices(&slices);
+        assert_eq!(source.pop_chunk(7), (Bytes::from_static(b"Hello W"), 2));
+        assert_eq!(source.pop_chunk(3), (Bytes::from_static(b"orl"), 3));
+        assert_eq!(source.pop_chunk(1), (Bytes::from_static(b"d"), 2));
+    }
 }
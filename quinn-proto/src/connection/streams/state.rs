@@ -2,22 +2,25 @@ use std::{
     collections::{hash_map, VecDeque},
     convert::TryFrom,
     mem,
+    time::{Duration, Instant},
 };
 
 use bytes::BufMut;
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 use tracing::{debug, trace};
 
 use super::{
-    PendingStreamsQueue, Recv, Retransmits, Send, SendState, ShouldTransmit, StreamEvent,
-    StreamHalf, ThinRetransmits,
+    Recv, Retransmits, Send, SendState, ShouldTransmit, StreamEvent, StreamGroupId, StreamHalf,
+    ThinRetransmits,
 };
 use crate::{
     coding::BufMutExt,
     connection::stats::FrameStats,
     frame::{self, FrameStruct, StreamMetaVec},
+    scheduler::StreamScheduler,
     transport_parameters::TransportParameters,
-    Dir, Side, StreamId, TransportError, VarInt, MAX_STREAM_COUNT,
+    Dir, SendBufferAllocator, Side, StreamId, StreamWindowUpdateConfig, TransportError, VarInt,
+    MAX_STREAM_COUNT,
 };
 
 /// Wrapper around `Recv` that facilitates reusing `Recv` instances
@@ -101,13 +104,17 @@ pub struct StreamsState {
     /// permitted to open but which have not yet been opened.
     pub(super) send_streams: usize,
     /// Streams with outgoing data queued, sorted by priority
-    pub(super) pending: PendingStreamsQueue,
+    pub(super) pending: Box<dyn StreamScheduler>,
 
-    events: VecDeque<StreamEvent>,
+    pub(super) events: VecDeque<StreamEvent>,
     /// Streams blocked on connection-level flow control or stream window space
     ///
     /// Streams are only added to this list when a write fails.
     pub(super) connection_blocked: Vec<StreamId>,
+    /// Send budgets and priorities shared by groups of streams
+    pub(super) groups: FxHashMap<StreamGroupId, StreamGroup>,
+    /// Whether the peer accepts RESET_STREAM_AT frames
+    pub(super) peer_supports_reset_at: bool,
     /// Connection-level flow control budget dictated by the peer
     pub(super) max_data: u64,
     /// The initial receive window
@@ -121,12 +128,24 @@ pub struct StreamsState {
     pub(super) data_sent: u64,
     /// Sum of end offsets of all receive streams. Includes gaps, so it's an upper bound.
     data_recvd: u64,
+    /// Quantity of received data credited back to the peer, i.e. no longer buffered
+    data_read: u64,
+    /// Upper bound on `MAX_DATA` imposed by the connection's memory limits
+    max_data_limit: u64,
     /// Total quantity of unacknowledged outgoing data
     pub(super) unacked_data: u64,
     /// Configured upper bound for `unacked_data`
     pub(super) send_window: u64,
+    /// Configured upper bound for unacked data on any single stream
+    pub(super) per_stream_send_buffer: u64,
+    /// How streams store written data until it's acknowledged
+    pub(super) send_buffer_allocator: SendBufferAllocator,
     /// Configured upper bound for how much unacked data the peer can send us per stream
     pub(super) stream_receive_window: u64,
+    /// When to extend the flow control credit of receive streams
+    window_update: StreamWindowUpdateConfig,
+    /// Streams whose `MAX_STREAM_DATA` is held back until a packet is sent for another reason
+    piggybacked_max_stream_data: FxHashSet<StreamId>,
 
     // Pertinent state from the TransportParameters supplied by the peer
     initial_max_stream_data_uni: VarInt,
@@ -135,6 +154,8 @@ pub struct StreamsState {
 
     /// The shrink to be applied to local_max_data when receive_window is shrunk
     receive_window_shrink_debt: u64,
+    /// Receive window auto-tuning state, if enabled
+    autotune: Option<WindowAutotune>,
 }
 
 impl StreamsState {
@@ -144,8 +165,10 @@ impl StreamsState {
         max_remote_uni: VarInt,
         max_remote_bi: VarInt,
         send_window: u64,
+        per_stream_send_buffer: u64,
         receive_window: VarInt,
         stream_receive_window: VarInt,
+        scheduler: Box<dyn StreamScheduler>,
     ) -> Self {
         let mut this = Self {
             side,
@@ -163,22 +186,31 @@ impl StreamsState {
             opened: [false, false],
             next_reported_remote: [0, 0],
             send_streams: 0,
-            pending: PendingStreamsQueue::new(),
+            pending: scheduler,
             events: VecDeque::new(),
             connection_blocked: Vec::new(),
+            groups: FxHashMap::default(),
+            peer_supports_reset_at: false,
             max_data: 0,
             receive_window: receive_window.into(),
             local_max_data: receive_window.into(),
             sent_max_data: receive_window,
             data_sent: 0,
             data_recvd: 0,
+            data_read: 0,
+            max_data_limit: u64::MAX,
             unacked_data: 0,
             send_window,
+            per_stream_send_buffer,
+            send_buffer_allocator: SendBufferAllocator::default(),
             stream_receive_window: stream_receive_window.into(),
+            window_update: StreamWindowUpdateConfig::default(),
+            piggybacked_max_stream_data: FxHashSet::default(),
             initial_max_stream_data_uni: 0u32.into(),
             initial_max_stream_data_bidi_local: 0u32.into(),
             initial_max_stream_data_bidi_remote: 0u32.into(),
             receive_window_shrink_debt: 0,
+            autotune: None,
         };
 
         for dir in Dir::iter() {
@@ -197,6 +229,7 @@ impl StreamsState {
         self.max[Dir::Bi as usize] = params.initial_max_streams_bidi.into();
         self.max[Dir::Uni as usize] = params.initial_max_streams_uni.into();
         self.received_max_data(params.initial_max_data);
+        self.peer_supports_reset_at = params.reset_stream_at;
         for i in 0..self.max_remote[Dir::Bi as usize] {
             let id = StreamId::new(!self.side, Dir::Bi, i);
             if let Some(s) = self.send.get_mut(&id).and_then(|s| s.as_mut()) {
@@ -243,6 +276,10 @@ impl StreamsState {
         self.send_streams = 0;
         self.data_sent = 0;
         self.connection_blocked.clear();
+        for group in self.groups.values_mut() {
+            group.unacked = 0;
+            group.blocked.clear();
+        }
     }
 
     /// Process incoming stream frame
@@ -312,7 +349,43 @@ impl StreamsState {
             debug!("received illegal RESET_STREAM frame");
             e
         })?;
+        self.reset_recv(id, error_code, final_offset, 0)
+    }
+
+    /// Process incoming RESET_STREAM_AT frame
+    ///
+    /// If successful, returns whether a `MAX_DATA` frame needs to be transmitted
+    #[allow(unreachable_pub)] // fuzzing only
+    pub fn received_reset_at(
+        &mut self,
+        frame: frame::ResetStreamAt,
+    ) -> Result<ShouldTransmit, TransportError> {
+        let frame::ResetStreamAt {
+            id,
+            error_code,
+            final_offset,
+            reliable_size,
+        } = frame;
+        self.validate_receive_id(id).map_err(|e| {
+            debug!("received illegal RESET_STREAM_AT frame");
+            e
+        })?;
+        if reliable_size > final_offset {
+            return Err(TransportError::FRAME_ENCODING_ERROR(
+                "reliable size exceeds final size",
+            ));
+        }
+        self.reset_recv(id, error_code, final_offset, reliable_size.into())
+    }
 
+    /// Shared logic for RESET_STREAM and RESET_STREAM_AT frames
+    fn reset_recv(
+        &mut self,
+        id: StreamId,
+        error_code: VarInt,
+        final_offset: VarInt,
+        reliable_size: u64,
+    ) -> Result<ShouldTransmit, TransportError> {
         let rs = match self
             .recv
             .get_mut(&id)
@@ -325,19 +398,23 @@ impl StreamsState {
             }
         };
 
+        // Credit for data past the reliable size of an earlier reset has already been issued
+        let credited_from = rs.reliable_size().unwrap_or(final_offset.into());
+        let end = rs.end;
+
         // State transition
         if !rs.reset(
             error_code,
             final_offset,
+            reliable_size,
             self.data_recvd,
             self.local_max_data,
         )? {
             // Redundant reset
             return Ok(ShouldTransmit(false));
         }
-        let bytes_read = rs.assembler.bytes_read();
+        let reliable_size = rs.reliable_size().expect("stream was just reset");
         let stopped = rs.stopped;
-        let end = rs.end;
         if stopped {
             // Stopped streams should be disposed immediately on reset
             let rs = self.recv.remove(&id).flatten().unwrap();
@@ -345,13 +422,13 @@ impl StreamsState {
         }
         self.on_stream_frame(!stopped, id);
 
-        // Update connection-level flow control
-        Ok(if bytes_read != final_offset.into_inner() {
-            // bytes_read is always <= end, so this won't underflow.
-            self.data_recvd = self
-                .data_recvd
-                .saturating_add(u64::from(final_offset) - end);
-            self.add_read_credits(u64::from(final_offset) - bytes_read)
+        // Update connection-level flow control. Data up to the reliable size is credited as it's
+        // read, as usual.
+        self.data_recvd = self
+            .data_recvd
+            .saturating_add(u64::from(final_offset) - end);
+        Ok(if credited_from != reliable_size {
+            self.add_read_credits(credited_from - reliable_size)
         } else {
             ShouldTransmit(false)
         })
@@ -380,11 +457,26 @@ impl StreamsState {
     pub(crate) fn reset_acked(&mut self, id: StreamId) {
         match self.send.entry(id) {
             hash_map::Entry::Vacant(_) => {}
-            hash_map::Entry::Occupied(e) => {
-                if let Some(SendState::ResetSent) = e.get().as_ref().map(|s| s.state) {
-                    e.remove_entry();
-                    self.stream_freed(id, StreamHalf::Send);
+            hash_map::Entry::Occupied(mut e) => {
+                let Some(stream) = e.get_mut().as_mut() else {
+                    return;
+                };
+                match stream.state {
+                    SendState::ResetSent => {}
+                    SendState::ResetAtSent {
+                        ref mut reset_acked,
+                        ..
+                    } => {
+                        *reset_acked = true;
+                        // Wait for the reliable portion of the stream to be acknowledged as well
+                        if !stream.pending.is_fully_acked() {
+                            return;
+                        }
+                    }
+                    _ => return,
                 }
+                e.remove_entry();
+                self.stream_freed(id, StreamHalf::Send);
             }
         }
     }
@@ -392,14 +484,23 @@ impl StreamsState {
     /// Whether any stream data is queued, regardless of control frames
     pub(crate) fn can_send_stream_data(&self) -> bool {
         // Reset streams may linger in the pending stream list, but will never produce stream frames
-        self.pending.iter().any(|stream| {
+        self.pending.iter().any(|id| {
             self.send
-                .get(&stream.id)
+                .get(&id)
                 .and_then(|s| s.as_ref())
                 .map_or(false, |s| !s.is_reset())
         })
     }
 
+    /// Number of finished send streams whose data or FIN has yet to be acknowledged
+    pub(crate) fn unacked_finished(&self) -> usize {
+        self.send
+            .values()
+            .filter_map(|s| s.as_ref())
+            .filter(|s| matches!(s.state, SendState::DataSent { .. }))
+            .count()
+    }
+
     /// Whether MAX_STREAM_DATA frames could be sent for stream `id`
     pub(crate) fn can_send_flow_control(&self, id: StreamId) -> bool {
         self.recv
@@ -411,14 +512,16 @@ impl StreamsState {
 
     pub(in crate::connection) fn write_control_frames(
         &mut self,
+        now: Instant,
+        rtt: Duration,
         buf: &mut Vec<u8>,
         pending: &mut Retransmits,
         retransmits: &mut ThinRetransmits,
         stats: &mut FrameStats,
         max_size: usize,
     ) {
-        // RESET_STREAM
-        while buf.len() + frame::ResetStream::SIZE_BOUND < max_size {
+        // RESET_STREAM, RESET_STREAM_AT
+        while buf.len() + frame::ResetStreamAt::SIZE_BOUND < max_size {
             let (id, error_code) = match pending.reset_stream.pop() {
                 Some(x) => x,
                 None => break,
@@ -427,18 +530,34 @@ impl StreamsState {
                 Some(x) => x,
                 None => continue,
             };
-            trace!(stream = %id, "RESET_STREAM");
             retransmits
                 .get_or_create()
                 .reset_stream
                 .push((id, error_code));
-            frame::ResetStream {
-                id,
-                error_code,
-                final_offset: VarInt::try_from(stream.offset()).expect("impossibly large offset"),
+            let final_offset = VarInt::try_from(stream.offset()).expect("impossibly large offset");
+            // A lost RESET_STREAM_AT is sent again as a RESET_STREAM if the stream has since been
+            // reset without a reliable size
+            if let SendState::ResetAtSent { reliable_size, .. } = stream.state {
+                trace!(stream = %id, reliable_size, "RESET_STREAM_AT");
+                frame::ResetStreamAt {
+                    id,
+                    error_code,
+                    final_offset,
+                    reliable_size: VarInt::try_from(reliable_size)
+                        .expect("impossibly large offset"),
+                }
+                .encode(buf);
+                stats.reset_stream_at += 1;
+            } else {
+                trace!(stream = %id, "RESET_STREAM");
+                frame::ResetStream {
+                    id,
+                    error_code,
+                    final_offset,
+                }
+                .encode(buf);
+                stats.reset_stream += 1;
             }
-            .encode(buf);
-            stats.reset_stream += 1;
         }
 
         // STOP_SENDING
@@ -464,10 +583,20 @@ impl StreamsState {
         if pending.max_data && buf.len() + 9 < max_size {
             pending.max_data = false;
 
-            // `local_max_data` can grow bigger than `VarInt`.
-            // For transmission inside QUIC frames we need to clamp it to the
-            // maximum allowed `VarInt` size.
-            let max = VarInt::try_from(self.local_max_data).unwrap_or(VarInt::MAX);
+            if let Some(autotune) = &mut self.autotune {
+                let max_window = autotune.max;
+                if autotune
+                    .epoch
+                    .update(now, rtt, self.data_read, self.receive_window)
+                    && self.receive_window < max_window
+                {
+                    let window = self.receive_window.saturating_mul(2).min(max_window);
+                    trace!(window, "growing receive window");
+                    self.set_receive_window(VarInt::from_u64(window).unwrap_or(VarInt::MAX));
+                }
+            }
+
+            let max = self.announceable_max_data();
 
             trace!(value = max.into_inner(), "MAX_DATA");
             if max > self.sent_max_data {
@@ -487,9 +616,13 @@ impl StreamsState {
         while buf.len() + 17 < max_size {
             let id = match pending.max_stream_data.iter().next() {
                 Some(x) => *x,
-                None => break,
+                None => match self.piggybacked_max_stream_data.iter().next() {
+                    Some(x) => *x,
+                    None => break,
+                },
             };
             pending.max_stream_data.remove(&id);
+            self.piggybacked_max_stream_data.remove(&id);
             let rs = match self
                 .recv
                 .get_mut(&id)
@@ -504,8 +637,36 @@ impl StreamsState {
             }
             retransmits.get_or_create().max_stream_data.insert(id);
 
-            let (max, _) = rs.max_stream_data(self.stream_receive_window);
+            let mut grown = false;
+            if let Some(autotune) = &self.autotune {
+                if rs.window_epoch.update(
+                    now,
+                    rtt,
+                    rs.assembler.bytes_read(),
+                    self.stream_receive_window,
+                ) && self.stream_receive_window < autotune.max
+                {
+                    self.stream_receive_window = self
+                        .stream_receive_window
+                        .saturating_mul(2)
+                        .min(autotune.max);
+                    trace!(
+                        window = self.stream_receive_window,
+                        "growing stream receive window"
+                    );
+                    grown = true;
+                }
+            }
+
+            let (max, _) = rs.max_stream_data(self.stream_receive_window, &self.window_update);
             rs.record_sent_max_stream_data(max);
+            if grown && self.receive_window < self.stream_receive_window {
+                // Keep the connection window from limiting a single stream
+                let window = self.stream_receive_window;
+                if self.set_receive_window(VarInt::from_u64(window).unwrap_or(VarInt::MAX)) {
+                    pending.max_data = true;
+                }
+            }
 
             trace!(stream = %id, max = max, "MAX_STREAM_DATA");
             buf.write(frame::Type::MAX_STREAM_DATA);
@@ -544,7 +705,6 @@ impl StreamsState {
         &mut self,
         buf: &mut Vec<u8>,
         max_buf_size: usize,
-        fair: bool,
     ) -> StreamMetaVec {
         let mut stream_frames = StreamMetaVec::new();
         while buf.len() + frame::Stream::SIZE_BOUND < max_buf_size {
@@ -557,12 +717,10 @@ impl StreamsState {
 
             // Pop the stream of the highest priority that currently has pending data
             // If the stream still has some pending data left after writing, it will be reinserted, otherwise not
-            let Some(stream) = self.pending.pop() else {
+            let Some(id) = self.pending.pop() else {
                 break;
             };
 
-            let id = stream.id;
-
             let stream = match self.send.get_mut(&id).and_then(|s| s.as_mut()) {
                 Some(s) => s,
                 // Stream was reset with pending data and the reset was acknowledged
@@ -588,14 +746,9 @@ impl StreamsState {
 
             if stream.is_pending() {
                 // If the stream still has pending data, reinsert it, possibly with an updated priority value
-                // Fairness with other streams is achieved by implementing round-robin scheduling,
-                // so that the other streams will have a chance to write data
-                // before we touch this stream again.
-                if fair {
-                    self.pending.push_pending(id, stream.priority);
-                } else {
-                    self.pending.reinsert_pending(id, stream.priority);
-                }
+                let priority = send_priority(&self.groups, stream);
+                self.pending
+                    .requeue(id, priority, offsets.end - offsets.start);
             }
 
             let meta = frame::StreamMeta { id, offsets, fin };
@@ -657,15 +810,38 @@ impl StreamsState {
             return;
         }
         let id = frame.id;
-        self.unacked_data -= frame.offsets.end - frame.offsets.start;
+        let acked = match stream.state {
+            // Data past the reliable size was accounted for at time of reset
+            SendState::ResetAtSent { reliable_size, .. } => frame
+                .offsets
+                .end
+                .min(reliable_size)
+                .saturating_sub(frame.offsets.start),
+            _ => frame.offsets.end - frame.offsets.start,
+        };
+        let reset = matches!(stream.state, SendState::ResetAtSent { .. });
+        self.unacked_data -= acked;
+        if let Some(group) = stream.group.and_then(|g| self.groups.get_mut(&g)) {
+            group.release(acked, &mut self.events);
+        }
         if !stream.ack(frame) {
             // The stream is unfinished or may still need retransmits
+            if stream.buffer_blocked && stream.pending.unacked() < self.per_stream_send_buffer {
+                stream.buffer_blocked = false;
+                self.events.push_back(StreamEvent::Writable { id });
+            }
+            if stream.flush_requested && stream.pending.is_fully_acked() {
+                stream.flush_requested = false;
+                self.events.push_back(StreamEvent::Flushed { id });
+            }
             return;
         }
 
         entry.remove_entry();
         self.stream_freed(id, StreamHalf::Send);
-        self.events.push_back(StreamEvent::Finished { id });
+        if !reset {
+            self.events.push_back(StreamEvent::Finished { id });
+        }
     }
 
     pub(crate) fn retransmit(&mut self, frame: frame::StreamMeta) {
@@ -675,7 +851,8 @@ impl StreamsState {
             Some(x) => x,
         };
         if !stream.is_pending() {
-            self.pending.push_pending(frame.id, stream.priority);
+            self.pending
+                .push(frame.id, send_priority(&self.groups, stream));
         }
         stream.fin_pending |= frame.fin;
         stream.pending.retransmit(frame.offsets);
@@ -695,7 +872,7 @@ impl StreamsState {
                     continue;
                 }
                 if !stream.is_pending() {
-                    self.pending.push_pending(id, stream.priority);
+                    self.pending.push(id, send_priority(&self.groups, stream));
                 }
                 stream.pending.retransmit_all_for_0rtt();
             }
@@ -818,6 +995,35 @@ impl StreamsState {
         queued
     }
 
+    /// Queues a MAX_STREAM_DATA frame for `id` in `pending` if its window grew enough
+    ///
+    /// Returns whether a frame was queued. Frames held back to be piggybacked on another packet
+    /// don't count, as they don't warrant sending one.
+    pub(super) fn queue_max_stream_data(
+        &mut self,
+        id: StreamId,
+        pending: &mut Retransmits,
+    ) -> bool {
+        let Some(rs) = self
+            .recv
+            .get(&id)
+            .and_then(|s| s.as_ref())
+            .and_then(|s| s.as_open_recv())
+        else {
+            return false;
+        };
+        let (_, transmit) = rs.max_stream_data(self.stream_receive_window, &self.window_update);
+        if !transmit.should_transmit() {
+            return false;
+        }
+        if self.window_update.piggyback && !rs.credit_running_low(self.stream_receive_window) {
+            self.piggybacked_max_stream_data.insert(id);
+            return false;
+        }
+        pending.max_stream_data.insert(id);
+        true
+    }
+
     /// Check for errors entailed by the peer's use of `id` as a send stream
     fn validate_receive_id(&mut self, id: StreamId) -> Result<(), TransportError> {
         if self.side == id.initiator() {
@@ -858,6 +1064,22 @@ impl StreamsState {
         self.allocated_remote_count[dir as usize]
     }
 
+    pub(crate) fn set_send_buffer_allocator(&mut self, allocator: SendBufferAllocator) {
+        self.send_buffer_allocator = allocator;
+    }
+
+    pub(crate) fn set_window_update(&mut self, config: StreamWindowUpdateConfig) {
+        self.window_update = config;
+    }
+
+    /// Let the receive windows grow up to `max` bytes based on how quickly the peer consumes them
+    pub(crate) fn enable_window_autotune(&mut self, max: VarInt) {
+        self.autotune = Some(WindowAutotune {
+            max: max.into(),
+            epoch: WindowEpoch::default(),
+        });
+    }
+
     /// Set the receive_window and returns whether the receive_window has been
     /// expanded or shrunk: true if expanded, false if shrunk.
     pub(crate) fn set_receive_window(&mut self, receive_window: VarInt) -> bool {
@@ -898,6 +1120,7 @@ impl StreamsState {
     /// suppress sending further updates until the window increases significantly
     /// again.
     pub(super) fn add_read_credits(&mut self, credits: u64) -> ShouldTransmit {
+        self.data_read = self.data_read.saturating_add(credits);
         if credits > self.receive_window_shrink_debt {
             let net_credits = credits - self.receive_window_shrink_debt;
             self.local_max_data = self.local_max_data.saturating_add(net_credits);
@@ -915,10 +1138,50 @@ impl StreamsState {
         // We use a fraction of the configured connection receive window to make
         // the decision, to accommodate for connection using bigger windows requiring
         // less updates.
-        let diff = self.local_max_data - self.sent_max_data.into_inner();
+        let diff = self.announceable_max_data().into_inner() - self.sent_max_data.into_inner();
         ShouldTransmit(diff >= (self.receive_window / 8))
     }
 
+    /// Number of bytes of stream data the connection is committed to buffering
+    ///
+    /// Counts data received but not yet read, flow control credit the peer may still use, and data
+    /// sent but not yet acknowledged.
+    pub(crate) fn committed_bytes(&self) -> u64 {
+        self.announceable_max_data()
+            .into_inner()
+            .saturating_sub(self.data_read)
+            .saturating_add(self.unacked_data)
+    }
+
+    /// Stop issuing connection-level flow control credit beyond what was already announced
+    ///
+    /// Returns the resulting [`committed_bytes()`](Self::committed_bytes).
+    pub(crate) fn release_unannounced_credit(&mut self) -> u64 {
+        self.max_data_limit = self.sent_max_data.into_inner();
+        self.committed_bytes()
+    }
+
+    /// Allow announcing up to `headroom` bytes of connection-level flow control credit beyond what
+    /// was already announced
+    ///
+    /// Returns whether a `MAX_DATA` frame should be sent because more credit became available.
+    pub(crate) fn reserve_credit(&mut self, headroom: u64) -> ShouldTransmit {
+        self.max_data_limit = self.sent_max_data.into_inner().saturating_add(headroom);
+        let diff = self.announceable_max_data().into_inner() - self.sent_max_data.into_inner();
+        ShouldTransmit(diff > 0 && diff >= self.receive_window / 8)
+    }
+
+    /// The connection-level flow control limit to announce in `MAX_DATA` frames
+    ///
+    /// `local_max_data` can grow bigger than `VarInt`, and is further bounded by the connection's
+    /// memory limits. Previously announced limits are never retracted.
+    fn announceable_max_data(&self) -> VarInt {
+        let max = self.local_max_data.min(self.max_data_limit);
+        VarInt::try_from(max)
+            .unwrap_or(VarInt::MAX)
+            .max(self.sent_max_data)
+    }
+
     /// Update counters for removal of a stream
     pub(super) fn stream_freed(&mut self, id: StreamId, half: StreamHalf) {
         if id.initiator() != self.side {
@@ -976,14 +1239,122 @@ pub(super) fn get_or_insert_recv(
     }
 }
 
+/// Send budget and priority shared by a group of streams
+#[derive(Debug)]
+pub(super) struct StreamGroup {
+    /// Upper bound for `unacked`
+    pub(super) send_budget: u64,
+    /// Priority used to schedule all streams in the group
+    pub(super) priority: i32,
+    /// Total quantity of unacknowledged outgoing data on streams in the group
+    pub(super) unacked: u64,
+    /// Streams blocked on the group's send budget
+    ///
+    /// Streams are only added to this list when a write fails.
+    pub(super) blocked: Vec<StreamId>,
+}
+
+impl StreamGroup {
+    pub(super) fn new() -> Self {
+        Self {
+            send_budget: u64::MAX,
+            priority: 0,
+            unacked: 0,
+            blocked: Vec::new(),
+        }
+    }
+
+    /// Maximum amount of data which may be written to streams in the group
+    pub(super) fn write_limit(&self) -> u64 {
+        self.send_budget.saturating_sub(self.unacked)
+    }
+
+    /// Account for `len` bytes no longer being outstanding, unblocking streams if possible
+    pub(super) fn release(&mut self, len: u64, events: &mut VecDeque<StreamEvent>) {
+        self.unacked -= len;
+        self.unblock(events);
+    }
+
+    /// Notify blocked streams if budget is available
+    pub(super) fn unblock(&mut self, events: &mut VecDeque<StreamEvent>) {
+        if self.write_limit() > 0 {
+            events.extend(
+                self.blocked
+                    .drain(..)
+                    .map(|id| StreamEvent::Writable { id }),
+            );
+        }
+    }
+}
+
+/// Priority used to schedule `stream`, taking its group into account
+pub(super) fn send_priority(groups: &FxHashMap<StreamGroupId, StreamGroup>, stream: &Send) -> i32 {
+    stream
+        .group
+        .and_then(|group| groups.get(&group))
+        .map_or(stream.priority, |group| group.priority)
+}
+
+/// Receive window auto-tuning state
+#[derive(Debug)]
+struct WindowAutotune {
+    /// Upper bound for both the connection and stream receive windows
+    max: u64,
+    /// Consumption of the connection receive window
+    epoch: WindowEpoch,
+}
+
+/// Tracks how quickly the peer consumes a receive window
+#[derive(Debug, Default, Copy, Clone)]
+pub(super) struct WindowEpoch {
+    /// When the current measurement started
+    start: Option<Instant>,
+    /// Amount of data consumed when the current measurement started
+    consumed: u64,
+}
+
+impl WindowEpoch {
+    /// Record that a total of `consumed` bytes have been consumed, as a window update is sent
+    ///
+    /// Returns whether `window` should grow because the peer consumed half of it in less than two
+    /// round trips.
+    pub(super) fn update(
+        &mut self,
+        now: Instant,
+        rtt: Duration,
+        consumed: u64,
+        window: u64,
+    ) -> bool {
+        let start = match self.start {
+            Some(start) => start,
+            None => {
+                self.start = Some(now);
+                self.consumed = consumed;
+                return false;
+            }
+        };
+        if consumed.saturating_sub(self.consumed) < window / 2 {
+            return false;
+        }
+        self.start = Some(now);
+        self.consumed = consumed;
+        now.saturating_duration_since(start) < 2 * rtt
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{
-        connection::State as ConnState, connection::Streams, ReadableError, RecvStream, SendStream,
-        TransportErrorCode, WriteError,
+        connection::State as ConnState,
+        connection::Streams,
+        scheduler::{
+            RoundRobinConfig, StreamSchedulerFactory, StrictPriorityConfig, WeightedFairConfig,
+        },
+        ReadableError, RecvStream, SendStream, TransportErrorCode, WriteError,
     };
     use bytes::Bytes;
+    use std::sync::Arc;
 
     fn make(side: Side) -> StreamsState {
         StreamsState::new(
@@ -991,8 +1362,10 @@ mod tests {
             128u32.into(),
             128u32.into(),
             1024 * 1024,
+            u64::MAX,
             (1024 * 1024u32).into(),
             (1024 * 1024u32).into(),
+            Arc::new(RoundRobinConfig::default()).build(),
         )
     }
 
@@ -1003,8 +1376,10 @@ mod tests {
             1u32.into(),
             1u32.into(),
             1024 * 1024,
+            u64::MAX,
             (1024 * 1024u32).into(),
             (1024 * 1024u32).into(),
+            Arc::new(RoundRobinConfig::default()).build(),
         );
         let id = StreamId::new(Side::Server, Dir::Uni, 0);
         let initial_max = client.local_max_data;
@@ -1316,6 +1691,63 @@ mod tests {
         assert!(stream.state.events.is_empty());
     }
 
+    #[test]
+    fn per_stream_send_buffer() {
+        let mut server = make(Side::Server);
+        server.per_stream_send_buffer = 4;
+        server.set_params(&TransportParameters {
+            initial_max_streams_uni: 2u32.into(),
+            initial_max_data: 100u32.into(),
+            initial_max_stream_data_uni: 100u32.into(),
+            ..TransportParameters::default()
+        });
+
+        let (mut pending, state) = (Retransmits::default(), ConnState::Established);
+        let mut streams = Streams {
+            state: &mut server,
+            conn_state: &state,
+        };
+        let first = streams.open(Dir::Uni).unwrap();
+        let second = streams.open(Dir::Uni).unwrap();
+
+        let mut stream = SendStream {
+            id: first,
+            state: &mut server,
+            pending: &mut pending,
+            conn_state: &state,
+        };
+        assert_eq!(stream.write(b"0123456789"), Ok(4));
+        assert_eq!(stream.max_writable(), Ok(0));
+        assert_eq!(stream.write(b"456789"), Err(WriteError::Blocked));
+
+        // Other streams are unaffected
+        let mut stream = SendStream {
+            id: second,
+            state: &mut server,
+            pending: &mut pending,
+            conn_state: &state,
+        };
+        assert_eq!(stream.write(b"abc"), Ok(3));
+
+        let mut buf = Vec::new();
+        let meta = server.write_stream_frames(&mut buf, 100);
+        let frame = meta.into_iter().find(|m| m.id == first).unwrap();
+        assert!(server.events.is_empty());
+        server.received_ack_of(frame);
+        assert_eq!(
+            server.events.pop_front(),
+            Some(StreamEvent::Writable { id: first })
+        );
+
+        let mut stream = SendStream {
+            id: first,
+            state: &mut server,
+            pending: &mut pending,
+            conn_state: &state,
+        };
+        assert_eq!(stream.write(b"456789"), Ok(4));
+    }
+
     #[test]
     fn final_offset_flow_control() {
         let mut client = make(Side::Client);
@@ -1379,13 +1811,13 @@ mod tests {
         high.write(b"high").unwrap();
 
         let mut buf = Vec::with_capacity(40);
-        let meta = server.write_stream_frames(&mut buf, 40, true);
+        let meta = server.write_stream_frames(&mut buf, 40);
         assert_eq!(meta[0].id, id_high);
         assert_eq!(meta[1].id, id_mid);
         assert_eq!(meta[2].id, id_low);
 
         assert!(!server.can_send_stream_data());
-        assert_eq!(server.pending.len(), 0);
+        assert_eq!(server.pending.iter().count(), 0);
     }
 
     #[test]
@@ -1414,7 +1846,7 @@ mod tests {
             conn_state: &state,
         };
         assert_eq!(mid.write(b"mid").unwrap(), 3);
-        assert_eq!(server.pending.len(), 1);
+        assert_eq!(server.pending.iter().count(), 1);
 
         let mut high = SendStream {
             id: id_high,
@@ -1424,7 +1856,7 @@ mod tests {
         };
         high.set_priority(1).unwrap();
         assert_eq!(high.write(&[0; 200]).unwrap(), 200);
-        assert_eq!(server.pending.len(), 2);
+        assert_eq!(server.pending.iter().count(), 2);
 
         // Requeue the high priority stream to lowest priority. The initial send
         // still uses high priority since it's queued that way. After that it will
@@ -1438,27 +1870,30 @@ mod tests {
         high.set_priority(-1).unwrap();
 
         let mut buf = Vec::with_capacity(1000);
-        let meta = server.write_stream_frames(&mut buf, 40, true);
+        let meta = server.write_stream_frames(&mut buf, 40);
         assert_eq!(meta.len(), 1);
         assert_eq!(meta[0].id, id_high);
 
         // After requeuing we should end up with 2 priorities - not 3
-        assert_eq!(server.pending.len(), 2);
+        assert_eq!(server.pending.iter().count(), 2);
 
         // Send the remaining data. The initial mid priority one should go first now
-        let meta = server.write_stream_frames(&mut buf, 1000, true);
+        let meta = server.write_stream_frames(&mut buf, 1000);
         assert_eq!(meta.len(), 2);
         assert_eq!(meta[0].id, id_mid);
         assert_eq!(meta[1].id, id_high);
 
         assert!(!server.can_send_stream_data());
-        assert_eq!(server.pending.len(), 0);
+        assert_eq!(server.pending.iter().count(), 0);
     }
 
     #[test]
     fn same_stream_priority() {
         for fair in [true, false] {
             let mut server = make(Side::Server);
+            if !fair {
+                server.pending = Arc::new(StrictPriorityConfig::default()).build();
+            }
             server.set_params(&TransportParameters {
                 initial_max_streams_bidi: 3u32.into(),
                 initial_max_data: 300u32.into(),
@@ -1507,7 +1942,7 @@ mod tests {
             // loop until all the streams are written
             loop {
                 let buf_len = buf.len();
-                let meta = server.write_stream_frames(&mut buf, buf_len + 40, fair);
+                let meta = server.write_stream_frames(&mut buf, buf_len + 40);
                 if meta.is_empty() {
                     break;
                 }
@@ -1515,7 +1950,7 @@ mod tests {
             }
 
             assert!(!server.can_send_stream_data());
-            assert_eq!(server.pending.len(), 0);
+            assert_eq!(server.pending.iter().count(), 0);
 
             let stream_ids = metas.iter().map(|m| m.id).collect::<Vec<_>>();
             if fair {
@@ -1536,9 +1971,111 @@ mod tests {
         }
     }
 
+    #[test]
+    fn round_robin_burst() {
+        let mut server = make(Side::Server);
+        let mut config = RoundRobinConfig::default();
+        config.burst_size(60);
+        server.pending = Arc::new(config).build();
+        server.set_params(&TransportParameters {
+            initial_max_streams_bidi: 3u32.into(),
+            initial_max_data: 300u32.into(),
+            initial_max_stream_data_bidi_remote: 300u32.into(),
+            ..TransportParameters::default()
+        });
+
+        let (mut pending, state) = (Retransmits::default(), ConnState::Established);
+        let mut streams = Streams {
+            state: &mut server,
+            conn_state: &state,
+        };
+        let id_a = streams.open(Dir::Bi).unwrap();
+        let id_b = streams.open(Dir::Bi).unwrap();
+
+        for (id, byte) in [(id_a, b'a'), (id_b, b'b')] {
+            let mut stream = SendStream {
+                id,
+                state: &mut server,
+                pending: &mut pending,
+                conn_state: &state,
+            };
+            stream.write(&[byte; 100]).unwrap();
+        }
+
+        let mut metas = vec![];
+        let mut buf = Vec::with_capacity(1024);
+        loop {
+            let buf_len = buf.len();
+            let meta = server.write_stream_frames(&mut buf, buf_len + 40);
+            if meta.is_empty() {
+                break;
+            }
+            metas.extend(meta);
+        }
+
+        assert!(!server.can_send_stream_data());
+        let stream_ids = metas.iter().map(|m| m.id).collect::<Vec<_>>();
+        // Each stream keeps its turn until it has sent at least 60 bytes
+        assert_eq!(stream_ids, vec![id_a, id_a, id_b, id_b, id_a, id_b]);
+    }
+
+    #[test]
+    fn weighted_fair_priority() {
+        let mut server = make(Side::Server);
+        server.pending = Arc::new(WeightedFairConfig::default()).build();
+        server.set_params(&TransportParameters {
+            initial_max_streams_bidi: 3u32.into(),
+            initial_max_data: 1000u32.into(),
+            initial_max_stream_data_bidi_remote: 1000u32.into(),
+            ..TransportParameters::default()
+        });
+
+        let (mut pending, state) = (Retransmits::default(), ConnState::Established);
+        let mut streams = Streams {
+            state: &mut server,
+            conn_state: &state,
+        };
+        let id_low = streams.open(Dir::Bi).unwrap();
+        let id_high = streams.open(Dir::Bi).unwrap();
+
+        for (id, priority) in [(id_low, 0), (id_high, 2)] {
+            let mut stream = SendStream {
+                id,
+                state: &mut server,
+                pending: &mut pending,
+                conn_state: &state,
+            };
+            stream.set_priority(priority).unwrap();
+            stream.write(&[0; 300]).unwrap();
+        }
+
+        let mut metas = vec![];
+        let mut buf = Vec::with_capacity(1024);
+        loop {
+            let buf_len = buf.len();
+            let meta = server.write_stream_frames(&mut buf, buf_len + 40);
+            if meta.is_empty() {
+                break;
+            }
+            metas.extend(meta);
+        }
+
+        assert!(!server.can_send_stream_data());
+        // While both streams are pending, the higher priority one sends about three times as much
+        // data, without starving the lower priority one
+        let high_done = metas.iter().rposition(|m| m.id == id_high).unwrap();
+        let low_sent = metas[..high_done]
+            .iter()
+            .filter(|m| m.id == id_low)
+            .map(|m| m.offsets.end - m.offsets.start)
+            .sum::<u64>();
+        assert!((75..=125).contains(&low_sent), "{low_sent}");
+    }
+
     #[test]
     fn unfair_priority_bump() {
         let mut server = make(Side::Server);
+        server.pending = Arc::new(StrictPriorityConfig::default()).build();
         server.set_params(&TransportParameters {
             initial_max_streams_bidi: 3u32.into(),
             initial_max_data: 300u32.into(),
@@ -1578,7 +2115,7 @@ mod tests {
 
         // Write the first chunk of stream_a
         let buf_len = buf.len();
-        let meta = server.write_stream_frames(&mut buf, buf_len + 40, false);
+        let meta = server.write_stream_frames(&mut buf, buf_len + 40);
         assert!(!meta.is_empty());
         metas.extend(meta);
 
@@ -1595,7 +2132,7 @@ mod tests {
         // loop until all the streams are written
         loop {
             let buf_len = buf.len();
-            let meta = server.write_stream_frames(&mut buf, buf_len + 40, false);
+            let meta = server.write_stream_frames(&mut buf, buf_len + 40);
             if meta.is_empty() {
                 break;
             }
@@ -1603,7 +2140,7 @@ mod tests {
         }
 
         assert!(!server.can_send_stream_data());
-        assert_eq!(server.pending.len(), 0);
+        assert_eq!(server.pending.iter().count(), 0);
 
         let stream_ids = metas.iter().map(|m| m.id).collect::<Vec<_>>();
         assert_eq!(
@@ -1868,6 +2405,76 @@ mod tests {
         }
     }
 
+    #[test]
+    fn window_autotune() {
+        const MIN: u64 = 4096;
+        const MAX: u64 = 64 * 1024;
+        let mut client = StreamsState::new(
+            Side::Client,
+            1u32.into(),
+            1u32.into(),
+            1024 * 1024,
+            u64::MAX,
+            VarInt::from_u64(MIN).unwrap(),
+            VarInt::from_u64(MIN).unwrap(),
+            Arc::new(RoundRobinConfig::default()).build(),
+        );
+        client.enable_window_autotune(VarInt::from_u64(MAX).unwrap());
+        let id = StreamId::new(Side::Server, Dir::Uni, 0);
+        let rtt = Duration::from_millis(100);
+        let mut now = Instant::now();
+        let mut offset = 0;
+        let mut transfer = |client: &mut StreamsState, now: Instant| {
+            let _ = client
+                .received(
+                    frame::Stream {
+                        id,
+                        offset,
+                        fin: false,
+                        data: Bytes::from_static(&[0; 1024]),
+                    },
+                    1024,
+                )
+                .unwrap();
+            offset += 1024;
+
+            let mut pending = Retransmits::default();
+            let mut recv = RecvStream {
+                id,
+                state: client,
+                pending: &mut pending,
+            };
+            let mut chunks = recv.read(true).unwrap();
+            assert_eq!(chunks.next(usize::MAX).unwrap().unwrap().bytes.len(), 1024);
+            let _ = chunks.finalize();
+            client.write_control_frames(
+                now,
+                rtt,
+                &mut Vec::new(),
+                &mut pending,
+                &mut ThinRetransmits::default(),
+                &mut FrameStats::default(),
+                1200,
+            );
+        };
+
+        // A peer consuming the windows slowly doesn't need them to grow
+        for _ in 0..32 {
+            now += rtt;
+            transfer(&mut client, now);
+        }
+        assert_eq!(client.stream_receive_window, MIN);
+        assert_eq!(client.receive_window, MIN);
+
+        // A peer consuming the windows within a round trip is limited by them
+        for _ in 0..128 {
+            now += rtt / 10;
+            transfer(&mut client, now);
+        }
+        assert_eq!(client.stream_receive_window, MAX);
+        assert_eq!(client.receive_window, MAX);
+    }
+
     #[test]
     fn expand_receive_window() {
         let mut server = make(Side::Server);
@@ -8,7 +8,7 @@
 //! Note that usage of any protocol (version) other than TLS 1.3 does not conform to any
 //! published versions of the specification, and will not be supported in QUIC v1.
 
-use std::{any::Any, str, sync::Arc};
+use std::{any::Any, ops::Range, str, sync::Arc};
 
 use bytes::BytesMut;
 
@@ -20,6 +20,8 @@ use crate::{
 /// Cryptography interface based on *ring*
 #[cfg(any(feature = "aws-lc-rs", feature = "ring"))]
 pub(crate) mod ring_like;
+#[cfg(feature = "aws-lc-rs")]
+pub use ring_like::AesBlockCipherKey;
 /// TLS interface based on rustls
 #[cfg(any(feature = "rustls-aws-lc-rs", feature = "rustls-ring"))]
 pub mod rustls;
@@ -118,6 +120,14 @@ pub trait ClientConfig: Send + Sync {
         server_name: &str,
         params: &TransportParameters,
     ) -> Result<Box<dyn Session>, ConnectError>;
+
+    /// Copy of this configuration which logs session secrets in the NSS key log format
+    ///
+    /// Returns `None` if key logging is unsupported. Used by
+    /// [`crate::ClientConfig::enable_key_log()`].
+    fn with_key_log(&self) -> Option<Arc<dyn ClientConfig>> {
+        None
+    }
 }
 
 /// Server-side configuration for the crypto protocol
@@ -142,12 +152,58 @@ pub trait ServerConfig: Send + Sync {
         version: u32,
         params: &TransportParameters,
     ) -> Box<dyn Session>;
+
+    /// Start a server session with this configuration which rejects 0-RTT
+    ///
+    /// Used when 0-RTT is refused by the server's [`ZeroRttPolicy`](crate::ZeroRttPolicy). The
+    /// default implementation starts a regular session, in which case 0-RTT packets are discarded
+    /// rather than rejected, and the client retransmits their contents once the handshake
+    /// completes.
+    fn start_session_without_0rtt(
+        self: Arc<Self>,
+        version: u32,
+        params: &TransportParameters,
+    ) -> Box<dyn Session> {
+        self.start_session(version, params)
+    }
+
+    /// The application protocol a session would negotiate with a client offering `offered`
+    ///
+    /// Returns `None` if no protocol would be negotiated or it can't be predicted. Used by
+    /// [`crate::ServerConfig::alpn_transport_config()`].
+    fn select_alpn<'a>(&'a self, offered: &[Vec<u8>]) -> Option<&'a [u8]> {
+        let _ = offered;
+        None
+    }
+
+    /// Copy of this configuration which logs session secrets in the NSS key log format
+    ///
+    /// Returns `None` if key logging is unsupported. Used by
+    /// [`crate::ServerConfig::enable_key_log()`].
+    fn with_key_log(&self) -> Option<Arc<dyn ServerConfig>> {
+        None
+    }
 }
 
 /// Keys used to protect packet payloads
 pub trait PacketKey: Send + Sync {
     /// Encrypt the packet payload with the given packet number
     fn encrypt(&self, packet: u64, buf: &mut [u8], header_len: usize);
+    /// Encrypt the payloads of several packets located in `buf`
+    ///
+    /// Called with the packets a connection writes in a single transmit. This is an extension
+    /// point: an implementation backed by a multi-buffer AEAD may seal the packets together, but
+    /// nothing here batches on its own. The default implementation encrypts each packet in turn
+    /// with [`encrypt()`](Self::encrypt), as does the rustls-backed key.
+    fn encrypt_batch(&self, buf: &mut [u8], packets: &[BatchedPacket]) {
+        for packet in packets {
+            self.encrypt(
+                packet.number,
+                &mut buf[packet.range.clone()],
+                packet.header_len,
+            );
+        }
+    }
     /// Decrypt the packet payload with the given packet number
     fn decrypt(
         &self,
@@ -164,6 +220,18 @@ pub trait PacketKey: Send + Sync {
     fn integrity_limit(&self) -> u64;
 }
 
+/// A packet to be encrypted by [`PacketKey::encrypt_batch()`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct BatchedPacket {
+    /// The packet number
+    pub number: u64,
+    /// Position of the packet in the buffer, including space for the AEAD tag
+    pub range: Range<usize>,
+    /// Length of the packet's header
+    pub header_len: usize,
+}
+
 /// Keys used to protect packet headers
 pub trait HeaderKey: Send + Sync {
     /// Decrypt the given packet's header
@@ -196,6 +264,19 @@ pub trait HandshakeTokenKey: Send + Sync {
     fn aead_from_hkdf(&self, random_bytes: &[u8]) -> Box<dyn AeadKey>;
 }
 
+/// A key for a 128-bit block cipher, such as AES-128
+///
+/// Used to encrypt connection IDs for load balancers by [`QuicLbConnectionIdGenerator`], and to
+/// decrypt them when validating connection IDs that fill exactly one block.
+///
+/// [`QuicLbConnectionIdGenerator`]: crate::QuicLbConnectionIdGenerator
+pub trait BlockCipherKey: Send + Sync {
+    /// Encrypt a single block in place
+    fn encrypt_block(&self, block: &mut [u8; 16]);
+    /// Decrypt a single block in place
+    fn decrypt_block(&self, block: &mut [u8; 16]);
+}
+
 /// A key for sealing data with AEAD-based algorithms
 pub trait AeadKey {
     /// Method for sealing message `data`
@@ -50,6 +50,41 @@ impl crypto::AeadKey for aead::LessSafeKey {
     }
 }
 
+/// An AES-128 key for encrypting QUIC-LB connection IDs
+///
+/// See [`QuicLbConfig::key()`](crate::QuicLbConfig::key).
+#[cfg(feature = "aws-lc-rs")]
+pub struct AesBlockCipherKey {
+    encrypt: aws_lc_rs::cipher::EncryptingKey,
+    decrypt: aws_lc_rs::cipher::DecryptingKey,
+}
+
+#[cfg(feature = "aws-lc-rs")]
+impl AesBlockCipherKey {
+    /// Construct a key from its raw bytes
+    pub fn new(key: &[u8; 16]) -> Self {
+        use aws_lc_rs::cipher::{DecryptingKey, EncryptingKey, UnboundCipherKey, AES_128};
+        let unbound = || UnboundCipherKey::new(&AES_128, key).unwrap();
+        Self {
+            encrypt: EncryptingKey::ecb(unbound()).unwrap(),
+            decrypt: DecryptingKey::ecb(unbound()).unwrap(),
+        }
+    }
+}
+
+#[cfg(feature = "aws-lc-rs")]
+impl crypto::BlockCipherKey for AesBlockCipherKey {
+    fn encrypt_block(&self, block: &mut [u8; 16]) {
+        self.encrypt.encrypt(block).unwrap();
+    }
+
+    fn decrypt_block(&self, block: &mut [u8; 16]) {
+        self.decrypt
+            .decrypt(block, aws_lc_rs::cipher::DecryptionContext::None)
+            .unwrap();
+    }
+}
+
 impl From<error::Unspecified> for CryptoError {
     fn from(_: error::Unspecified) -> Self {
         Self
@@ -1,4 +1,8 @@
-use std::{any::Any, io, str, sync::Arc};
+use std::{
+    any::Any,
+    io, str,
+    sync::{Arc, OnceLock},
+};
 
 #[cfg(all(feature = "aws-lc-rs", not(feature = "ring")))]
 use aws_lc_rs::aead;
@@ -9,9 +13,11 @@ pub use rustls::Error;
 use rustls::{
     self,
     client::danger::ServerCertVerifier,
+    crypto::{CryptoProvider, SupportedKxGroup},
     pki_types::{CertificateDer, PrivateKeyDer, ServerName},
     quic::{Connection, HeaderProtectionKey, KeyChange, PacketKey, Secrets, Suite, Version},
-    CipherSuite,
+    server::danger::ClientCertVerifier,
+    CipherSuite, KeyLogFile, NamedGroup,
 };
 
 use crate::{
@@ -64,6 +70,18 @@ impl crypto::Session for TlsSession {
                 Connection::Client(_) => None,
                 Connection::Server(ref session) => session.server_name().map(|x| x.into()),
             },
+            cipher_suite: self
+                .inner
+                .negotiated_cipher_suite()
+                .map(|suite| suite.suite()),
+            key_exchange_group: self
+                .inner
+                .negotiated_key_exchange_group()
+                .map(|group| group.name()),
+            peer_certificates: self
+                .inner
+                .peer_certificates()
+                .map(|certs| certs.iter().map(|cert| cert.clone().into_owned()).collect()),
         }))
     }
 
@@ -179,6 +197,7 @@ impl crypto::Session for TlsSession {
         let (nonce, key) = match self.version {
             Version::V1 => (RETRY_INTEGRITY_NONCE_V1, RETRY_INTEGRITY_KEY_V1),
             Version::V1Draft => (RETRY_INTEGRITY_NONCE_DRAFT, RETRY_INTEGRITY_KEY_DRAFT),
+            Version::V2 => (RETRY_INTEGRITY_NONCE_V2, RETRY_INTEGRITY_KEY_V2),
             _ => unreachable!(),
         };
 
@@ -216,6 +235,13 @@ const RETRY_INTEGRITY_NONCE_V1: [u8; 12] = [
     0x46, 0x15, 0x99, 0xd3, 0x5d, 0x63, 0x2b, 0xf2, 0x23, 0x98, 0x25, 0xbb,
 ];
 
+const RETRY_INTEGRITY_KEY_V2: [u8; 16] = [
+    0x8f, 0xb4, 0xb0, 0x1b, 0x56, 0xac, 0x48, 0xe2, 0x60, 0xfb, 0xcb, 0xce, 0xad, 0x7c, 0xcc, 0x92,
+];
+const RETRY_INTEGRITY_NONCE_V2: [u8; 12] = [
+    0xd8, 0x69, 0x69, 0xbc, 0x2d, 0x7c, 0x6d, 0x99, 0x90, 0xef, 0xb0, 0x4a,
+];
+
 impl crypto::HeaderKey for Box<dyn HeaderProtectionKey> {
     fn decrypt(&self, pn_offset: usize, packet: &mut [u8]) {
         let (header, sample) = packet.split_at_mut(pn_offset + 4);
@@ -247,6 +273,11 @@ impl crypto::HeaderKey for Box<dyn HeaderProtectionKey> {
 }
 
 /// Authentication data for (rustls) TLS session
+///
+/// Keying material bound to the connection can be derived with
+/// [`Session::export_keying_material()`](crypto::Session::export_keying_material).
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct HandshakeData {
     /// The negotiated application protocol, if ALPN is in use
     ///
@@ -256,6 +287,18 @@ pub struct HandshakeData {
     ///
     /// Always `None` for outgoing connections
     pub server_name: Option<String>,
+    /// The negotiated cipher suite
+    pub cipher_suite: Option<CipherSuite>,
+    /// The key exchange group negotiated for the handshake
+    ///
+    /// Only known once the handshake is complete, e.g. to confirm that a post-quantum hybrid
+    /// group such as [`NamedGroup::X25519MLKEM768`] was used.
+    pub key_exchange_group: Option<NamedGroup>,
+    /// The certificate chain presented by the peer, end-entity certificate first
+    ///
+    /// Only known once the handshake is complete, and `None` if the peer didn't authenticate, as
+    /// is usual for clients.
+    pub peer_certificates: Option<Vec<CertificateDer<'static>>>,
 }
 
 /// A QUIC-compatible TLS client configuration
@@ -287,7 +330,17 @@ impl QuicClientConfig {
     /// QUIC requires that TLS 1.3 be enabled. Advanced users can use any [`rustls::ClientConfig`] that
     /// satisfies this requirement.
     pub(crate) fn new(verifier: Arc<dyn ServerCertVerifier>) -> Self {
-        let inner = Self::inner(verifier);
+        Self::from_inner(Self::inner(verifier))
+    }
+
+    pub(crate) fn with_provider(
+        verifier: Arc<dyn ServerCertVerifier>,
+        provider: Arc<CryptoProvider>,
+    ) -> Self {
+        Self::from_inner(Self::inner_with_provider(verifier, provider))
+    }
+
+    fn from_inner(inner: rustls::ClientConfig) -> Self {
         Self {
             // We're confident that the *ring* default provider contains TLS13_AES_128_GCM_SHA256
             initial: initial_suite_from_provider(inner.crypto_provider())
@@ -310,9 +363,16 @@ impl QuicClientConfig {
     }
 
     pub(crate) fn inner(verifier: Arc<dyn ServerCertVerifier>) -> rustls::ClientConfig {
-        let mut config = rustls::ClientConfig::builder_with_provider(configured_provider())
+        Self::inner_with_provider(verifier, configured_provider())
+    }
+
+    fn inner_with_provider(
+        verifier: Arc<dyn ServerCertVerifier>,
+        provider: Arc<CryptoProvider>,
+    ) -> rustls::ClientConfig {
+        let mut config = rustls::ClientConfig::builder_with_provider(provider)
             .with_protocol_versions(&[&rustls::version::TLS13])
-            .unwrap() // The default providers support TLS 1.3
+            .unwrap() // Providers derived from the default ones support TLS 1.3
             .dangerous()
             .with_custom_certificate_verifier(verifier)
             .with_no_client_auth();
@@ -348,6 +408,15 @@ impl crypto::ClientConfig for QuicClientConfig {
             suite: self.initial,
         }))
     }
+
+    fn with_key_log(&self) -> Option<Arc<dyn crypto::ClientConfig>> {
+        let mut inner = (*self.inner).clone();
+        inner.key_log = Arc::new(KeyLogFile::new());
+        Some(Arc::new(Self {
+            inner: Arc::new(inner),
+            initial: self.initial,
+        }))
+    }
 }
 
 impl TryFrom<rustls::ClientConfig> for QuicClientConfig {
@@ -409,6 +478,8 @@ impl std::error::Error for NoInitialCipherSuite {}
 pub struct QuicServerConfig {
     inner: Arc<rustls::ServerConfig>,
     initial: Suite,
+    /// Copy of `inner` refusing early data, created on first use
+    without_0rtt: OnceLock<Arc<rustls::ServerConfig>>,
 }
 
 impl QuicServerConfig {
@@ -416,13 +487,42 @@ impl QuicServerConfig {
         cert_chain: Vec<CertificateDer<'static>>,
         key: PrivateKeyDer<'static>,
     ) -> Result<Self, rustls::Error> {
-        let inner = Self::inner(cert_chain, key)?;
-        Ok(Self {
+        Ok(Self::from_inner(Self::inner(cert_chain, key)?))
+    }
+
+    pub(crate) fn with_provider(
+        cert_chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+        provider: Arc<CryptoProvider>,
+    ) -> Result<Self, rustls::Error> {
+        Ok(Self::from_inner(Self::inner_with_provider(
+            cert_chain, key, provider,
+        )?))
+    }
+
+    pub(crate) fn with_client_cert_verifier(
+        cert_chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+        verifier: Arc<dyn ClientCertVerifier>,
+    ) -> Result<Self, rustls::Error> {
+        let mut inner = rustls::ServerConfig::builder_with_provider(configured_provider())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap() // The default providers support TLS 1.3
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(cert_chain, key)?;
+
+        inner.max_early_data_size = u32::MAX;
+        Ok(Self::from_inner(inner))
+    }
+
+    fn from_inner(inner: rustls::ServerConfig) -> Self {
+        Self {
             // We're confident that the *ring* default provider contains TLS13_AES_128_GCM_SHA256
             initial: initial_suite_from_provider(inner.crypto_provider())
                 .expect("no initial cipher suite found"),
             inner: Arc::new(inner),
-        })
+            without_0rtt: OnceLock::new(),
+        }
     }
 
     /// Initialize a QUIC-compatible TLS client configuration with a separate initial cipher suite
@@ -433,7 +533,11 @@ impl QuicServerConfig {
         initial: Suite,
     ) -> Result<Self, NoInitialCipherSuite> {
         match initial.suite.common.suite {
-            CipherSuite::TLS13_AES_128_GCM_SHA256 => Ok(Self { inner, initial }),
+            CipherSuite::TLS13_AES_128_GCM_SHA256 => Ok(Self {
+                inner,
+                initial,
+                without_0rtt: OnceLock::new(),
+            }),
             _ => Err(NoInitialCipherSuite { specific: true }),
         }
     }
@@ -447,9 +551,16 @@ impl QuicServerConfig {
         cert_chain: Vec<CertificateDer<'static>>,
         key: PrivateKeyDer<'static>,
     ) -> Result<rustls::ServerConfig, rustls::Error> {
-        let mut inner = rustls::ServerConfig::builder_with_provider(configured_provider())
-            .with_protocol_versions(&[&rustls::version::TLS13])
-            .unwrap() // The *ring* default provider supports TLS 1.3
+        Self::inner_with_provider(cert_chain, key, configured_provider())
+    }
+
+    fn inner_with_provider(
+        cert_chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+        provider: Arc<CryptoProvider>,
+    ) -> Result<rustls::ServerConfig, rustls::Error> {
+        let mut inner = rustls::ServerConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])?
             .with_no_client_auth()
             .with_single_cert(cert_chain, key)?;
 
@@ -474,13 +585,15 @@ impl TryFrom<Arc<rustls::ServerConfig>> for QuicServerConfig {
             initial: initial_suite_from_provider(inner.crypto_provider())
                 .ok_or(NoInitialCipherSuite { specific: false })?,
             inner,
+            without_0rtt: OnceLock::new(),
         })
     }
 }
 
-impl crypto::ServerConfig for QuicServerConfig {
-    fn start_session(
-        self: Arc<Self>,
+impl QuicServerConfig {
+    fn start_session_with(
+        &self,
+        inner: Arc<rustls::ServerConfig>,
         version: u32,
         params: &TransportParameters,
     ) -> Box<dyn crypto::Session> {
@@ -491,12 +604,44 @@ impl crypto::ServerConfig for QuicServerConfig {
             got_handshake_data: false,
             next_secrets: None,
             inner: rustls::quic::Connection::Server(
-                rustls::quic::ServerConnection::new(self.inner.clone(), version, to_vec(params))
-                    .unwrap(),
+                rustls::quic::ServerConnection::new(inner, version, to_vec(params)).unwrap(),
             ),
             suite: self.initial,
         })
     }
+}
+
+impl crypto::ServerConfig for QuicServerConfig {
+    fn start_session(
+        self: Arc<Self>,
+        version: u32,
+        params: &TransportParameters,
+    ) -> Box<dyn crypto::Session> {
+        self.start_session_with(self.inner.clone(), version, params)
+    }
+
+    fn start_session_without_0rtt(
+        self: Arc<Self>,
+        version: u32,
+        params: &TransportParameters,
+    ) -> Box<dyn crypto::Session> {
+        let inner = self.without_0rtt.get_or_init(|| {
+            let mut inner = (*self.inner).clone();
+            inner.max_early_data_size = 0;
+            Arc::new(inner)
+        });
+        self.start_session_with(inner.clone(), version, params)
+    }
+
+    fn with_key_log(&self) -> Option<Arc<dyn crypto::ServerConfig>> {
+        let mut inner = (*self.inner).clone();
+        inner.key_log = Arc::new(KeyLogFile::new());
+        Some(Arc::new(Self {
+            inner: Arc::new(inner),
+            initial: self.initial,
+            without_0rtt: OnceLock::new(),
+        }))
+    }
 
     fn initial_keys(
         &self,
@@ -507,12 +652,22 @@ impl crypto::ServerConfig for QuicServerConfig {
         Ok(initial_keys(version, dst_cid, Side::Server, &self.initial))
     }
 
+    fn select_alpn<'a>(&'a self, offered: &[Vec<u8>]) -> Option<&'a [u8]> {
+        // rustls picks the first of the server's protocols that the client offers
+        self.inner
+            .alpn_protocols
+            .iter()
+            .find(|protocol| offered.contains(protocol))
+            .map(|protocol| &protocol[..])
+    }
+
     fn retry_tag(&self, version: u32, orig_dst_cid: &ConnectionId, packet: &[u8]) -> [u8; 16] {
         // Safe: `start_session()` is never called if `initial_keys()` rejected `version`
         let version = interpret_version(version).unwrap();
         let (nonce, key) = match version {
             Version::V1 => (RETRY_INTEGRITY_NONCE_V1, RETRY_INTEGRITY_KEY_V1),
             Version::V1Draft => (RETRY_INTEGRITY_NONCE_DRAFT, RETRY_INTEGRITY_KEY_DRAFT),
+            Version::V2 => (RETRY_INTEGRITY_NONCE_V2, RETRY_INTEGRITY_KEY_V2),
             _ => unreachable!(),
         };
 
@@ -556,6 +711,19 @@ pub(crate) fn configured_provider() -> Arc<rustls::crypto::CryptoProvider> {
     Arc::new(provider)
 }
 
+/// The default crypto provider, restricted to the given key exchange groups in order of preference
+pub(crate) fn provider_with_kx_groups(
+    kx_groups: Vec<&'static dyn SupportedKxGroup>,
+) -> Result<Arc<CryptoProvider>, rustls::Error> {
+    if kx_groups.is_empty() {
+        return Err(rustls::Error::General("no kx groups configured".into()));
+    }
+    Ok(Arc::new(CryptoProvider {
+        kx_groups,
+        ..CryptoProvider::clone(&configured_provider())
+    }))
+}
+
 fn to_vec(params: &TransportParameters) -> Vec<u8> {
     let mut bytes = Vec::new();
     params.write(&mut bytes);
@@ -589,6 +757,21 @@ impl crypto::PacketKey for Box<dyn PacketKey> {
         tag_storage.copy_from_slice(tag.as_ref());
     }
 
+    fn encrypt_batch(&self, buf: &mut [u8], packets: &[crypto::BatchedPacket]) {
+        // rustls has no multi-buffer AEAD, so this still seals one packet per call; it only
+        // resolves the key and its tag length once for the whole batch
+        let key = &**self;
+        let tag_len = key.tag_len();
+        for packet in packets {
+            let (header, payload_tag) = buf[packet.range.clone()].split_at_mut(packet.header_len);
+            let (payload, tag_storage) = payload_tag.split_at_mut(payload_tag.len() - tag_len);
+            let tag = key
+                .encrypt_in_place(packet.number, &*header, payload)
+                .unwrap();
+            tag_storage.copy_from_slice(tag.as_ref());
+        }
+    }
+
     fn decrypt(
         &self,
         packet: u64,
@@ -620,6 +803,7 @@ fn interpret_version(version: u32) -> Result<Version, UnsupportedVersion> {
     match version {
         0xff00_001d..=0xff00_0020 => Ok(Version::V1Draft),
         0x0000_0001 | 0xff00_0021..=0xff00_0022 => Ok(Version::V1),
+        crate::QUIC_V2 => Ok(Version::V2),
         _ => Err(UnsupportedVersion),
     }
 }
@@ -5,7 +5,7 @@ use std::{
     net::{IpAddr, SocketAddr},
     ops::{Index, IndexMut},
     sync::Arc,
-    time::{Instant, SystemTime},
+    time::Instant,
 };
 
 use bytes::{BufMut, Bytes, BytesMut};
@@ -17,9 +17,10 @@ use tracing::{debug, error, trace, warn};
 
 use crate::{
     cid_generator::ConnectionIdGenerator,
+    client_hello::ClientHello,
     coding::BufMutExt,
     config::{ClientConfig, EndpointConfig, ServerConfig},
-    connection::{Connection, ConnectionError},
+    connection::{Connection, ConnectionError, DeferredHandshake, MemoryBudget, VersionFallback},
     crypto::{self, Keys, UnsupportedVersion},
     frame,
     packet::{
@@ -30,10 +31,10 @@ use crate::{
         ConnectionEvent, ConnectionEventInner, ConnectionId, DatagramConnectionEvent, EcnCodepoint,
         EndpointEvent, EndpointEventInner, IssuedCid,
     },
-    token::TokenDecodeError,
+    token::{TokenError, TokenStore, ValidatedToken},
     transport_parameters::{PreferredAddress, TransportParameters},
-    ResetToken, RetryToken, Side, Transmit, TransportConfig, TransportError, INITIAL_MTU,
-    MAX_CID_SIZE, MIN_INITIAL_SIZE, RESET_TOKEN_SIZE,
+    ResetToken, Side, Transmit, TransportConfig, TransportError, INITIAL_MTU, MAX_CID_SIZE,
+    MIN_INITIAL_SIZE, RESET_TOKEN_SIZE,
 };
 
 /// The main entry point to the library
@@ -54,6 +55,10 @@ pub struct Endpoint {
     /// Buffered Initial and 0-RTT messages for pending incoming connections
     incoming_buffers: Slab<IncomingBuffer>,
     all_incoming_buffers_total_bytes: u64,
+    /// Number of accepted incoming connections whose handshake hasn't completed
+    handshaking: usize,
+    /// Stream data buffered by all connections
+    memory: Arc<MemoryBudget>,
 }
 
 impl Endpoint {
@@ -74,6 +79,7 @@ impl Endpoint {
         rng_seed: Option<[u8; 32]>,
     ) -> Self {
         let rng_seed = rng_seed.or(config.rng_seed);
+        let memory = Arc::new(MemoryBudget::new(config.max_buffered_bytes));
         Self {
             rng: rng_seed.map_or(StdRng::from_entropy(), StdRng::from_seed),
             index: ConnectionIndex::default(),
@@ -85,6 +91,8 @@ impl Endpoint {
             last_stateless_reset: None,
             incoming_buffers: Slab::new(),
             all_incoming_buffers_total_bytes: 0,
+            handshaking: 0,
+            memory,
         }
     }
 
@@ -123,8 +131,18 @@ impl Endpoint {
                     }
                 }
             }
+            HandshakeComplete => {
+                let conn = &mut self.connections[ch];
+                if conn.handshaking {
+                    conn.handshaking = false;
+                    self.handshaking -= 1;
+                }
+            }
             Drained => {
                 if let Some(conn) = self.connections.try_remove(ch.0) {
+                    if conn.handshaking {
+                        self.handshaking -= 1;
+                    }
                     self.index.remove(&conn);
                 } else {
                     // This indicates a bug in downstream code, which could cause spurious
@@ -204,6 +222,7 @@ impl Endpoint {
             let event = DatagramConnectionEvent {
                 now,
                 remote: addresses.remote,
+                local_ip: addresses.local_ip,
                 ecn,
                 first_decode,
                 remaining,
@@ -412,9 +431,12 @@ impl Endpoint {
             self.local_cid_generator.as_ref(),
             loc_cid,
             None,
+            config.version,
+            &mut self.rng,
         );
         let tls = config
             .crypto
+            .clone()
             .start_session(config.version, server_name, &params)?;
 
         let conn = self.add_connection(
@@ -433,6 +455,14 @@ impl Endpoint {
             None,
             config.transport,
             true,
+            Some((config.token_store, server_name.into())),
+            Some(VersionFallback {
+                allow_version_negotiation: config.allow_version_negotiation,
+                allow_retry: config.allow_retry,
+                crypto: config.crypto,
+                server_name: server_name.into(),
+                params,
+            }),
         );
         Ok((ch, conn))
     }
@@ -495,27 +525,39 @@ impl Endpoint {
 
         let server_config = self.server_config.as_ref().unwrap().clone();
 
-        let (retry_src_cid, orig_dst_cid) = if header.token.is_empty() {
-            (None, header.dst_cid)
+        let (retry_src_cid, orig_dst_cid, address_validated) = if header.token.is_empty() {
+            (None, header.dst_cid, false)
         } else {
-            match RetryToken::from_bytes(
-                &*server_config.token_key,
-                &addresses.remote,
-                &header.dst_cid,
-                &header.token,
-            ) {
-                Ok(token)
-                    if token.issued + server_config.retry_token_lifetime > SystemTime::now() =>
-                {
-                    (Some(header.dst_cid), token.orig_dst_cid)
+            let default_tokens = server_config.default_token_provider();
+            let tokens = server_config
+                .token_provider
+                .as_deref()
+                .unwrap_or(&default_tokens);
+            match tokens.validate(&addresses.remote, &header.dst_cid, &header.token) {
+                Ok(ValidatedToken::Retry { orig_dst_cid }) => {
+                    (Some(header.dst_cid), orig_dst_cid, true)
+                }
+                // The client chose the destination CID itself, which `early_validate_first_packet`
+                // couldn't check without knowing where the token came from
+                Ok(ValidatedToken::NewToken) if header.dst_cid.len() < 8 => {
+                    debug!("rejecting connection due to invalid DCID length");
+                    return Some(DatagramEvent::Response(self.initial_close(
+                        header.version,
+                        addresses,
+                        &crypto,
+                        &header.src_cid,
+                        TransportError::PROTOCOL_VIOLATION("invalid destination CID length"),
+                        buf,
+                    )));
                 }
-                Err(TokenDecodeError::UnknownToken) => {
+                Ok(ValidatedToken::NewToken) => (None, header.dst_cid, true),
+                Err(TokenError::Unknown) => {
                     // Token may have been generated by an incompatible endpoint, e.g. a
                     // different version or a neighbor behind the same load balancer. We
                     // can't interpret it, so we proceed as if there was no token.
-                    (None, header.dst_cid)
+                    (None, header.dst_cid, false)
                 }
-                _ => {
+                Err(TokenError::Invalid) => {
                     debug!("rejecting invalid stateless retry token");
                     return Some(DatagramEvent::Response(self.initial_close(
                         header.version,
@@ -545,6 +587,7 @@ impl Endpoint {
             crypto,
             retry_src_cid,
             orig_dst_cid,
+            address_validated,
             incoming_idx,
             improper_drop_warner: IncomingImproperDropWarner,
         }))
@@ -552,11 +595,39 @@ impl Endpoint {
 
     /// Attempt to accept this incoming connection (an error may still occur)
     pub fn accept(
+        &mut self,
+        incoming: Incoming,
+        now: Instant,
+        buf: &mut Vec<u8>,
+        server_config: Option<Arc<ServerConfig>>,
+    ) -> Result<(ConnectionHandle, Connection), AcceptError> {
+        self.accept_inner(incoming, now, buf, server_config, false)
+    }
+
+    /// Accept this incoming connection, leaving its handshake cryptography for later
+    ///
+    /// Unlike [`accept()`](Self::accept), this doesn't process the client's first packets, so that
+    /// the expensive parts of the handshake, such as signing with the server's key, don't hold up
+    /// the caller. [`Connection::handle_deferred_handshake()`] must be called before the returned
+    /// connection is otherwise used. Errors in the client's first packets then close the connection
+    /// rather than being returned here.
+    pub fn accept_deferred(
+        &mut self,
+        incoming: Incoming,
+        now: Instant,
+        buf: &mut Vec<u8>,
+        server_config: Option<Arc<ServerConfig>>,
+    ) -> Result<(ConnectionHandle, Connection), AcceptError> {
+        self.accept_inner(incoming, now, buf, server_config, true)
+    }
+
+    fn accept_inner(
         &mut self,
         mut incoming: Incoming,
         now: Instant,
         buf: &mut Vec<u8>,
         server_config: Option<Arc<ServerConfig>>,
+        defer_handshake: bool,
     ) -> Result<(ConnectionHandle, Connection), AcceptError> {
         let remote_address_validated = incoming.remote_address_validated();
         incoming.improper_drop_warner.dismiss();
@@ -609,14 +680,40 @@ impl Endpoint {
             });
         };
 
+        let zero_rtt_policy = &server_config.zero_rtt_policy;
+        let hello = match server_config.alpn_transport.is_some()
+            || (zero_rtt_policy.enabled && zero_rtt_policy.anti_replay.is_some())
+        {
+            true => ClientHello::from_initial_payload(incoming.packet.payload.clone().freeze()),
+            false => None,
+        };
+        let transport_config = server_config
+            .alpn_transport
+            .as_ref()
+            .zip(hello.as_ref())
+            .and_then(|(f, hello)| f(server_config.crypto.select_alpn(&hello.alpn_protocols)?))
+            .unwrap_or_else(|| server_config.transport.clone());
+        let accept_0rtt = zero_rtt_policy.enabled
+            && zero_rtt_policy
+                .anti_replay
+                .as_ref()
+                .map_or(true, |anti_replay| {
+                    // Without a ClientHello to check, replays can't be ruled out
+                    hello
+                        .as_ref()
+                        .is_some_and(|hello| !hello.early_data || anti_replay.check(hello))
+                });
+
         let ch = ConnectionHandle(self.connections.vacant_key());
         let loc_cid = self.new_cid(ch);
         let mut params = TransportParameters::new(
-            &server_config.transport,
+            &transport_config,
             &self.config,
             self.local_cid_generator.as_ref(),
             loc_cid,
             Some(&server_config),
+            version,
+            &mut self.rng,
         );
         params.stateless_reset_token = Some(ResetToken::new(&*self.config.reset_key, &loc_cid));
         params.original_dst_cid = Some(incoming.orig_dst_cid);
@@ -635,8 +732,16 @@ impl Endpoint {
             });
         }
 
-        let tls = server_config.crypto.clone().start_session(version, &params);
-        let transport_config = server_config.transport.clone();
+        let tls = match accept_0rtt {
+            true => server_config.crypto.clone().start_session(version, &params),
+            false => {
+                trace!("rejecting 0-RTT");
+                server_config
+                    .crypto
+                    .clone()
+                    .start_session_without_0rtt(version, &params)
+            }
+        };
         let mut conn = self.add_connection(
             ch,
             version,
@@ -650,9 +755,28 @@ impl Endpoint {
             Some(server_config),
             transport_config,
             remote_address_validated,
+            None,
+            None,
         );
+        if !accept_0rtt {
+            // In case the crypto layer can't reject 0-RTT itself
+            conn.discard_0rtt();
+        }
         self.index.insert_initial(dst_cid, ch);
 
+        if defer_handshake {
+            trace!(id = ch.0, icid = %dst_cid, "new connection, handshake deferred");
+            conn.defer_handshake(DeferredHandshake {
+                remote: incoming.addresses.remote,
+                ecn: incoming.ecn,
+                packet_number,
+                packet: incoming.packet,
+                rest: incoming.rest,
+                datagrams: incoming_buffer.datagrams,
+            });
+            return Ok((ch, conn));
+        }
+
         match conn.handle_first_packet(
             now,
             incoming.addresses.remote,
@@ -701,8 +825,8 @@ impl Endpoint {
 
         // RFC9000 §7.2 dictates that initial (client-chosen) destination CIDs must be at least 8
         // bytes. If this is a Retry packet, then the length must instead match our usual CID
-        // length. If we ever issue non-Retry address validation tokens via `NEW_TOKEN`, then we'll
-        // also need to validate CID length for those after decoding the token.
+        // length. Tokens from `NEW_TOKEN` frames don't imply a Retry, so the length is checked
+        // again for those after decoding the token.
         if header.dst_cid.len() < 8
             && (header.token_pos.is_empty()
                 || header.dst_cid.len() != self.local_cid_generator.cid_len())
@@ -755,14 +879,15 @@ impl Endpoint {
         // retried by the application layer.
         let loc_cid = self.local_cid_generator.generate_cid();
 
-        let token = RetryToken {
-            orig_dst_cid: incoming.packet.header.dst_cid,
-            issued: SystemTime::now(),
-        }
-        .encode(
-            &*server_config.token_key,
+        let default_tokens = server_config.default_token_provider();
+        let tokens = server_config
+            .token_provider
+            .as_deref()
+            .unwrap_or(&default_tokens);
+        let token = tokens.retry_token(
             &incoming.addresses.remote,
             &loc_cid,
+            &incoming.packet.header.dst_cid,
         );
 
         let header = Header::Retry {
@@ -819,6 +944,8 @@ impl Endpoint {
         server_config: Option<Arc<ServerConfig>>,
         transport_config: Arc<TransportConfig>,
         path_validated: bool,
+        token_store: Option<(Arc<dyn TokenStore>, String)>,
+        version_fallback: Option<VersionFallback>,
     ) -> Connection {
         let mut rng_seed = [0; 32];
         self.rng.fill_bytes(&mut rng_seed);
@@ -843,6 +970,9 @@ impl Endpoint {
             self.allow_mtud,
             rng_seed,
             path_validated,
+            token_store,
+            version_fallback,
+            self.memory.clone(),
         );
 
         let mut cids_issued = 0;
@@ -864,7 +994,11 @@ impl Endpoint {
             addresses,
             side,
             reset_token: None,
+            handshaking: side.is_server(),
         });
+        if side.is_server() {
+            self.handshaking += 1;
+        }
         debug_assert_eq!(id, ch.0, "connection handle allocation out of sync");
 
         self.index.insert_conn(addresses, loc_cid, ch, side);
@@ -919,6 +1053,22 @@ impl Endpoint {
         self.connections.len()
     }
 
+    /// Number of bytes of stream data that connections are committed to buffering
+    ///
+    /// Includes flow control credit issued to peers for data not yet received. Only tracked when
+    /// [`EndpointConfig::max_buffered_bytes()`] is set; zero otherwise.
+    pub fn buffered_bytes(&self) -> u64 {
+        self.memory.used()
+    }
+
+    /// Number of incoming connection attempts whose handshake hasn't completed
+    ///
+    /// Counts attempts awaiting a decision from the application as well as accepted connections
+    /// still handshaking.
+    pub fn pending_handshakes(&self) -> usize {
+        self.incoming_buffers.len() + self.handshaking
+    }
+
     /// Counter for the number of bytes currently used
     /// in the buffers for Initial and 0-RTT messages for pending incoming connections
     pub fn incoming_buffer_bytes(&self) -> u64 {
@@ -1141,6 +1291,8 @@ pub(crate) struct ConnectionMeta {
     /// Reset token provided by the peer for the CID we're currently sending to, and the address
     /// being sent to
     reset_token: Option<(SocketAddr, ResetToken)>,
+    /// Whether this is a server connection whose handshake hasn't completed
+    handshaking: bool,
 }
 
 /// Internal identifier for a `Connection` currently associated with an endpoint
@@ -1186,6 +1338,7 @@ pub struct Incoming {
     crypto: Keys,
     retry_src_cid: Option<ConnectionId>,
     orig_dst_cid: ConnectionId,
+    address_validated: bool,
     incoming_idx: usize,
     improper_drop_warner: IncomingImproperDropWarner,
 }
@@ -1207,15 +1360,37 @@ impl Incoming {
     /// Whether the socket address that is initiating this connection has been validated.
     ///
     /// This means that the sender of the initial packet has proved that they can receive traffic
-    /// sent to `self.remote_address()`.
+    /// sent to `self.remote_address()`, either by responding to a Retry or by presenting a token
+    /// the server sent them on an earlier connection.
     pub fn remote_address_validated(&self) -> bool {
-        self.retry_src_cid.is_some()
+        self.address_validated
     }
 
     /// The original destination connection ID sent by the client
     pub fn orig_dst_cid(&self) -> &ConnectionId {
         &self.orig_dst_cid
     }
+
+    /// The address validation token sent by the client, which is empty if there was none
+    pub fn token(&self) -> &[u8] {
+        &self.packet.header.token
+    }
+
+    /// Decrypt and decode the client's TLS ClientHello
+    ///
+    /// Allows e.g. choosing a [`ServerConfig`] by server name before accepting the connection.
+    /// Returns `None` if the ClientHello is malformed or doesn't fit in the first packet of the
+    /// connection attempt, as is the case for some very large ClientHellos.
+    pub fn client_hello(&self) -> Option<ClientHello> {
+        let number = self.packet.header.number.expand(0);
+        let mut payload = self.packet.payload.clone();
+        self.crypto
+            .packet
+            .remote
+            .decrypt(number, &self.packet.header_data, &mut payload)
+            .ok()?;
+        ClientHello::from_initial_payload(payload.freeze())
+    }
 }
 
 impl fmt::Debug for Incoming {
@@ -1227,6 +1402,7 @@ impl fmt::Debug for Incoming {
             // rest is too big and not meaningful enough
             .field("retry_src_cid", &self.retry_src_cid)
             .field("orig_dst_cid", &self.orig_dst_cid)
+            .field("address_validated", &self.address_validated)
             .field("incoming_idx", &self.incoming_idx)
             // improper drop warner contains no information
             .finish_non_exhaustive()
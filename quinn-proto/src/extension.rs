@@ -0,0 +1,52 @@
+use bytes::Bytes;
+use thiserror::Error;
+
+use crate::{TransportError, VarInt};
+
+/// Handler for the frames of an application-defined QUIC extension
+///
+/// **Unstable:** this interface exists to prototype protocol extensions (e.g. receive timestamps
+/// or bandwidth reports) on top of quinn, and may change in any release without notice. Nothing
+/// prevents an extension from breaking the invariants of the connection, such as by accepting
+/// frames that confuse the peer.
+///
+/// Extensions are registered with
+/// [`TransportConfig::frame_extension()`](crate::TransportConfig::frame_extension), and negotiated
+/// through a transport parameter: frames are only accepted from, and may only be sent to, a peer
+/// that advertised the same parameter. Frames are only exchanged in 1-RTT packets, are
+/// ack-eliciting, and are retransmitted when lost.
+pub trait FrameExtension: Send + Sync {
+    /// Frame types defined by this extension
+    ///
+    /// Types understood by quinn itself are never passed to an extension.
+    fn frame_types(&self) -> &[VarInt];
+
+    /// Transport parameter advertising support for this extension, as an identifier and value
+    fn transport_parameter(&self) -> (VarInt, Bytes);
+
+    /// Length of the frame of type `ty` at the start of `buf`
+    ///
+    /// `buf` holds the remainder of the packet following the frame type. Returns `None` if the
+    /// frame is malformed, closing the connection with a `FRAME_ENCODING_ERROR`.
+    fn frame_len(&self, ty: VarInt, buf: &[u8]) -> Option<usize>;
+
+    /// Handle a frame received from the peer
+    ///
+    /// `body` is the frame following its type, as delimited by `frame_len()`. Returning an error
+    /// closes the connection with it.
+    fn on_frame(&self, ty: VarInt, body: Bytes) -> Result<(), TransportError>;
+}
+
+/// Errors that can arise when queueing an extension frame
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+pub enum SendExtensionFrameError {
+    /// No registered extension defines the frame type
+    #[error("frame type not defined by a registered extension")]
+    UnknownType,
+    /// The peer did not advertise support for the extension, or has not done so yet
+    #[error("extension not supported by peer")]
+    UnsupportedByPeer,
+    /// The frame could never fit in a packet
+    #[error("frame too large")]
+    TooLarge,
+}
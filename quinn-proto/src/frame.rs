@@ -2,6 +2,7 @@ use std::{
     fmt::{self, Write},
     io, mem,
     ops::{Range, RangeInclusive},
+    sync::Arc,
 };
 
 use bytes::{Buf, BufMut, Bytes};
@@ -11,15 +12,15 @@ use crate::{
     coding::{self, BufExt, BufMutExt, UnexpectedEnd},
     range_set::ArrayRangeSet,
     shared::{ConnectionId, EcnCodepoint},
-    Dir, ResetToken, StreamId, TransportError, TransportErrorCode, VarInt, MAX_CID_SIZE,
-    RESET_TOKEN_SIZE,
+    Dir, FrameExtension, ResetToken, StreamId, TransportError, TransportErrorCode, VarInt,
+    MAX_CID_SIZE, RESET_TOKEN_SIZE,
 };
 
 #[cfg(feature = "arbitrary")]
 use arbitrary::Arbitrary;
 
 #[derive(Copy, Clone, Eq, PartialEq)]
-pub struct Type(u64);
+pub struct Type(pub(crate) u64);
 
 impl Type {
     fn stream(self) -> Option<StreamInfo> {
@@ -132,6 +133,11 @@ frame_types! {
     // ACK Frequency
     ACK_FREQUENCY = 0xaf,
     IMMEDIATE_ACK = 0x1f,
+    // Reliable stream reset
+    RESET_STREAM_AT = 0x24,
+    // Receive timestamps
+    ACK_RECEIVE_TIMESTAMPS = 0xffa0,
+    ACK_ECN_RECEIVE_TIMESTAMPS = 0xffa1,
     // DATAGRAM
 }
 
@@ -144,6 +150,7 @@ pub(crate) enum Frame {
     Ping,
     Ack(Ack),
     ResetStream(ResetStream),
+    ResetStreamAt(ResetStreamAt),
     StopSending(StopSending),
     Crypto(Crypto),
     NewToken { token: Bytes },
@@ -163,6 +170,7 @@ pub(crate) enum Frame {
     AckFrequency(AckFrequency),
     ImmediateAck,
     HandshakeDone,
+    Extension(ExtensionFrame),
 }
 
 impl Frame {
@@ -171,6 +179,7 @@ impl Frame {
         match *self {
             Padding => Type::PADDING,
             ResetStream(_) => Type::RESET_STREAM,
+            ResetStreamAt(_) => Type::RESET_STREAM_AT,
             Close(self::Close::Connection(_)) => Type::CONNECTION_CLOSE,
             Close(self::Close::Application(_)) => Type::APPLICATION_CLOSE,
             MaxData(_) => Type::MAX_DATA,
@@ -204,6 +213,7 @@ impl Frame {
             AckFrequency(_) => Type::ACK_FREQUENCY,
             ImmediateAck => Type::IMMEDIATE_ACK,
             HandshakeDone => Type::HANDSHAKE_DONE,
+            Extension(ref x) => Type(x.ty.0),
         }
     }
 
@@ -343,6 +353,8 @@ pub struct Ack {
     pub delay: u64,
     pub additional: Bytes,
     pub ecn: Option<EcnCounts>,
+    /// Encoded timestamp ranges, when sent as an ACK_RECEIVE_TIMESTAMPS frame
+    pub timestamps: Option<Bytes>,
 }
 
 impl fmt::Debug for Ack {
@@ -363,6 +375,10 @@ impl fmt::Debug for Ack {
             .field("delay", &self.delay)
             .field("ecn", &self.ecn)
             .field("ranges", &ranges)
+            .field(
+                "timestamps",
+                &self.timestamps.as_ref().map(|_| self.timestamps().count()),
+            )
             .finish()
     }
 }
@@ -377,20 +393,25 @@ impl<'a> IntoIterator for &'a Ack {
 }
 
 impl Ack {
+    /// Encode an ACK frame, carrying `timestamps` if any
+    ///
+    /// `timestamps` holds packet numbers and their receive timestamps, in descending order of both.
     pub fn encode<W: BufMut>(
         delay: u64,
         ranges: &ArrayRangeSet,
         ecn: Option<&EcnCounts>,
+        timestamps: Option<&[(u64, u64)]>,
         buf: &mut W,
     ) {
         let mut rest = ranges.iter().rev();
         let first = rest.next().unwrap();
         let largest = first.end - 1;
         let first_size = first.end - first.start;
-        buf.write(if ecn.is_some() {
-            Type::ACK_ECN
-        } else {
-            Type::ACK
+        buf.write(match (ecn.is_some(), timestamps.is_some()) {
+            (false, false) => Type::ACK,
+            (true, false) => Type::ACK_ECN,
+            (false, true) => Type::ACK_RECEIVE_TIMESTAMPS,
+            (true, true) => Type::ACK_ECN_RECEIVE_TIMESTAMPS,
         });
         buf.write_var(largest);
         buf.write_var(delay);
@@ -406,11 +427,55 @@ impl Ack {
         if let Some(x) = ecn {
             x.encode(buf)
         }
+        if let Some(x) = timestamps {
+            encode_timestamp_ranges(largest, x, buf);
+        }
     }
 
     pub fn iter(&self) -> AckIter<'_> {
         self.into_iter()
     }
+
+    /// Packet numbers and their receive timestamps, in descending order
+    ///
+    /// Timestamps are in units of the receive timestamps exponent, from the peer's basis.
+    pub fn timestamps(&self) -> TimestampIter<'_> {
+        TimestampIter::new(self.largest, self.timestamps.as_deref().unwrap_or_default())
+    }
+}
+
+/// Encode timestamp ranges as specified by draft-smith-quic-receive-ts
+///
+/// Each range covers consecutive packet numbers in descending order. The first timestamp is
+/// relative to the receiver's basis, and each following one to its predecessor.
+fn encode_timestamp_ranges<W: BufMut>(largest: u64, timestamps: &[(u64, u64)], buf: &mut W) {
+    let breaks = timestamps
+        .windows(2)
+        .filter(|x| x[1].0 + 1 != x[0].0)
+        .count();
+    buf.write_var(breaks as u64 + 1);
+    let mut start = 0;
+    let mut prev = None;
+    while start < timestamps.len() {
+        let mut end = start + 1;
+        while end < timestamps.len() && timestamps[end].0 + 1 == timestamps[end - 1].0 {
+            end += 1;
+        }
+        let first = timestamps[start].0;
+        buf.write_var(match prev {
+            None => largest - first,
+            Some((smallest, _)) => smallest - first - 2,
+        });
+        buf.write_var((end - start) as u64);
+        for &(pn, timestamp) in &timestamps[start..end] {
+            buf.write_var(match prev {
+                None => timestamp,
+                Some((_, prev_timestamp)) => prev_timestamp - timestamp,
+            });
+            prev = Some((pn, timestamp));
+        }
+        start = end;
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -528,6 +593,7 @@ pub(crate) struct Iter {
     // TODO: ditch io::Cursor after bytes 0.5
     bytes: io::Cursor<Bytes>,
     last_ty: Option<Type>,
+    extensions: Vec<Arc<dyn FrameExtension>>,
 }
 
 impl Iter {
@@ -544,9 +610,16 @@ impl Iter {
         Ok(Self {
             bytes: io::Cursor::new(payload),
             last_ty: None,
+            extensions: Vec::new(),
         })
     }
 
+    /// Also decode the frames defined by `extensions`
+    pub(crate) fn with_extensions(mut self, extensions: &[Arc<dyn FrameExtension>]) -> Self {
+        self.extensions = extensions.to_vec();
+        self
+    }
+
     fn take_len(&mut self) -> Result<Bytes, UnexpectedEnd> {
         let len = self.bytes.get_var()?;
         if len > self.bytes.remaining() as u64 {
@@ -619,7 +692,10 @@ impl Iter {
             Type::RETIRE_CONNECTION_ID => Frame::RetireConnectionId {
                 sequence: self.bytes.get_var()?,
             },
-            Type::ACK | Type::ACK_ECN => {
+            Type::ACK
+            | Type::ACK_ECN
+            | Type::ACK_RECEIVE_TIMESTAMPS
+            | Type::ACK_ECN_RECEIVE_TIMESTAMPS => {
                 let largest = self.bytes.get_var()?;
                 let delay = self.bytes.get_var()?;
                 let extra_blocks = self.bytes.get_var()? as usize;
@@ -630,7 +706,7 @@ impl Iter {
                     delay,
                     largest,
                     additional: self.bytes.get_ref().slice(start..end),
-                    ecn: if ty != Type::ACK_ECN {
+                    ecn: if ty != Type::ACK_ECN && ty != Type::ACK_ECN_RECEIVE_TIMESTAMPS {
                         None
                     } else {
                         Some(EcnCounts {
@@ -639,6 +715,16 @@ impl Iter {
                             ce: self.bytes.get_var()?,
                         })
                     },
+                    timestamps: if ty != Type::ACK_RECEIVE_TIMESTAMPS
+                        && ty != Type::ACK_ECN_RECEIVE_TIMESTAMPS
+                    {
+                        None
+                    } else {
+                        let start = self.bytes.position() as usize;
+                        scan_timestamp_ranges(&mut self.bytes, largest)?;
+                        let end = self.bytes.position() as usize;
+                        Some(self.bytes.get_ref().slice(start..end))
+                    },
                 })
             }
             Type::PATH_CHALLENGE => Frame::PathChallenge(self.bytes.get()?),
@@ -686,6 +772,12 @@ impl Iter {
                 reordering_threshold: self.bytes.get()?,
             }),
             Type::IMMEDIATE_ACK => Frame::ImmediateAck,
+            Type::RESET_STREAM_AT => Frame::ResetStreamAt(ResetStreamAt {
+                id: self.bytes.get()?,
+                error_code: self.bytes.get()?,
+                final_offset: self.bytes.get()?,
+                reliable_size: self.bytes.get()?,
+            }),
             _ => {
                 if let Some(s) = ty.stream() {
                     Frame::Stream(Stream {
@@ -706,6 +798,22 @@ impl Iter {
                             self.take_remaining()
                         },
                     })
+                } else if let Some(ext) = self
+                    .extensions
+                    .iter()
+                    .find(|ext| ext.frame_types().contains(&VarInt(ty.0)))
+                {
+                    let start = self.bytes.position() as usize;
+                    let rest = &self.bytes.get_ref()[start..];
+                    let len = ext
+                        .frame_len(VarInt(ty.0), rest)
+                        .filter(|&len| len <= rest.len())
+                        .ok_or(IterErr::Malformed)?;
+                    self.bytes.advance(len);
+                    Frame::Extension(ExtensionFrame {
+                        ty: VarInt(ty.0),
+                        body: self.bytes.get_ref().slice(start..start + len),
+                    })
                 } else {
                     return Err(IterErr::InvalidFrameId);
                 }
@@ -767,6 +875,33 @@ fn scan_ack_blocks(buf: &mut io::Cursor<Bytes>, largest: u64, n: usize) -> Resul
     Ok(())
 }
 
+fn scan_timestamp_ranges(buf: &mut io::Cursor<Bytes>, largest: u64) -> Result<(), IterErr> {
+    let ranges = buf.get_var()?;
+    let mut next = largest;
+    let mut timestamp: Option<u64> = None;
+    for i in 0..ranges {
+        let gap = buf.get_var()?;
+        let first = match i {
+            0 => next.checked_sub(gap),
+            _ => next.checked_sub(gap).and_then(|x| x.checked_sub(2)),
+        }
+        .ok_or(IterErr::Malformed)?;
+        let count = buf.get_var()?;
+        next = (first + 1)
+            .checked_sub(count)
+            .filter(|_| count > 0)
+            .ok_or(IterErr::Malformed)?;
+        for _ in 0..count {
+            let delta = buf.get_var()?;
+            timestamp = Some(match timestamp {
+                None => delta,
+                Some(x) => x.checked_sub(delta).ok_or(IterErr::Malformed)?,
+            });
+        }
+    }
+    Ok(())
+}
+
 enum IterErr {
     UnexpectedEnd,
     InvalidFrameId,
@@ -818,6 +953,63 @@ impl<'a> Iterator for AckIter<'a> {
     }
 }
 
+/// Iterator over the receive timestamps of an [`Ack`], yielding `(packet number, timestamp)`
+#[derive(Debug, Clone)]
+pub struct TimestampIter<'a> {
+    data: io::Cursor<&'a [u8]>,
+    ranges: u64,
+    /// Timestamps left in the current range
+    count: u64,
+    next_pn: u64,
+    timestamp: Option<u64>,
+}
+
+impl<'a> TimestampIter<'a> {
+    fn new(largest: u64, payload: &'a [u8]) -> Self {
+        let mut data = io::Cursor::new(payload);
+        let ranges = match payload.is_empty() {
+            true => 0,
+            false => data.get_var().unwrap(),
+        };
+        Self {
+            data,
+            ranges,
+            count: 0,
+            next_pn: largest,
+            timestamp: None,
+        }
+    }
+}
+
+impl<'a> Iterator for TimestampIter<'a> {
+    type Item = (u64, u64);
+    fn next(&mut self) -> Option<(u64, u64)> {
+        // Validated by `scan_timestamp_ranges`
+        if self.count == 0 {
+            if self.ranges == 0 {
+                return None;
+            }
+            let gap = self.data.get_var().unwrap();
+            self.next_pn -= match self.timestamp {
+                None => gap,
+                Some(_) => gap + 2,
+            };
+            self.count = self.data.get_var().unwrap();
+            self.ranges -= 1;
+        } else {
+            self.next_pn -= 1;
+        }
+        self.count -= 1;
+        let delta = self.data.get_var().unwrap();
+        let timestamp = match self.timestamp {
+            None => delta,
+            Some(x) => x - delta,
+        };
+        self.timestamp = Some(timestamp);
+        Some((self.next_pn, timestamp))
+    }
+}
+
 #[allow(unreachable_pub)] // fuzzing only
 #[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 #[derive(Debug, Copy, Clone)]
@@ -840,6 +1032,34 @@ impl ResetStream {
     }
 }
 
+/// A RESET_STREAM_AT frame, from the reliable stream reset extension
+///
+/// Like [`ResetStream`], but the receiver must still deliver the first `reliable_size` bytes of
+/// the stream to the application.
+#[allow(unreachable_pub)] // fuzzing only
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ResetStreamAt {
+    pub(crate) id: StreamId,
+    pub(crate) error_code: VarInt,
+    pub(crate) final_offset: VarInt,
+    pub(crate) reliable_size: VarInt,
+}
+
+impl FrameStruct for ResetStreamAt {
+    const SIZE_BOUND: usize = 1 + 8 + 8 + 8 + 8;
+}
+
+impl ResetStreamAt {
+    pub(crate) fn encode<W: BufMut>(&self, out: &mut W) {
+        out.write(Type::RESET_STREAM_AT); // 1 byte
+        out.write(self.id); // <= 8 bytes
+        out.write(self.error_code); // <= 8 bytes
+        out.write(self.final_offset); // <= 8 bytes
+        out.write(self.reliable_size); // <= 8 bytes
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub(crate) struct StopSending {
     pub(crate) id: StreamId,
@@ -910,6 +1130,25 @@ impl Datagram {
     }
 }
 
+/// A frame defined by a [`FrameExtension`]
+#[derive(Debug, Clone)]
+pub(crate) struct ExtensionFrame {
+    pub(crate) ty: VarInt,
+    /// Everything following the frame type
+    pub(crate) body: Bytes,
+}
+
+impl ExtensionFrame {
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        out.write(self.ty);
+        out.extend_from_slice(&self.body);
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.ty.size() + self.body.len()
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub(crate) struct AckFrequency {
     pub(crate) sequence: VarInt,
@@ -955,7 +1194,7 @@ mod test {
             ect1: 24,
             ce: 12,
         };
-        Ack::encode(42, &ranges, Some(&ECN), &mut buf);
+        Ack::encode(42, &ranges, Some(&ECN), None, &mut buf);
         let frames = frames(buf);
         assert_eq!(frames.len(), 1);
         match frames[0] {
@@ -969,6 +1208,26 @@ mod test {
         }
     }
 
+    #[test]
+    fn ack_receive_timestamps_coding() {
+        let mut ranges = ArrayRangeSet::new();
+        ranges.insert(1..15);
+        const TIMESTAMPS: &[(u64, u64)] = &[(14, 900), (13, 850), (11, 800), (10, 800), (5, 10)];
+        for ecn in [None, Some(&EcnCounts::ZERO)] {
+            let mut buf = Vec::new();
+            Ack::encode(42, &ranges, ecn, Some(TIMESTAMPS), &mut buf);
+            let frames = frames(buf);
+            assert_eq!(frames.len(), 1);
+            match frames[0] {
+                Frame::Ack(ref ack) => {
+                    assert_eq!(ack.ecn.as_ref(), ecn);
+                    assert_eq!(ack.timestamps().collect::<Vec<_>>(), TIMESTAMPS);
+                }
+                ref x => panic!("incorrect frame {x:?}"),
+            }
+        }
+    }
+
     #[test]
     fn ack_frequency_coding() {
         let mut buf = Vec::new();
@@ -987,6 +1246,24 @@ mod test {
         }
     }
 
+    #[test]
+    fn reset_stream_at_coding() {
+        let mut buf = Vec::new();
+        let original = ResetStreamAt {
+            id: StreamId(4),
+            error_code: VarInt(7),
+            final_offset: VarInt(100_000),
+            reliable_size: VarInt(1_000),
+        };
+        original.encode(&mut buf);
+        let frames = frames(buf);
+        assert_eq!(frames.len(), 1);
+        match &frames[0] {
+            Frame::ResetStreamAt(decoded) => assert_eq!(decoded, &original),
+            x => panic!("incorrect frame {x:?}"),
+        }
+    }
+
     #[test]
     fn immediate_ack_coding() {
         let mut buf = Vec::new();
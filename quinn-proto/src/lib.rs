@@ -12,7 +12,7 @@
 //! related `Connection`. `Connection` types contain the bulk of the protocol logic related to
 //! managing a single connection and all the related state (such as streams).
 
-#![cfg_attr(not(fuzzing), warn(missing_docs))]
+#![cfg_attr(not(feature = "fuzzing"), warn(missing_docs))]
 #![cfg_attr(test, allow(dead_code))]
 // Fixes welcome:
 #![warn(unreachable_pub)]
@@ -40,17 +40,22 @@ mod varint;
 pub use varint::{VarInt, VarIntBoundsExceeded};
 
 mod connection;
+#[cfg(feature = "qlog")]
+pub use crate::connection::QlogFactory;
 pub use crate::connection::{
     BytesSource, Chunk, Chunks, ClosedStream, Connection, ConnectionError, ConnectionStats,
-    Datagrams, Event, FinishError, FrameStats, PathStats, ReadError, ReadableError, RecvStream,
-    RttEstimator, SendDatagramError, SendStream, ShouldTransmit, StreamEvent, Streams, UdpStats,
+    DatagramId, Datagrams, Event, FinishError, FrameStats, FrameSummary, HandshakeEvents,
+    KeepAliveProbe, MigrateError, PacketInspector, PacketSummary, PacketType, PathStats, ReadError,
+    ReadableError, RecvStream, ResetAtError, RttEstimator, SendDatagramError, SendStream,
+    ShouldTransmit, StatsDelta, StreamEvent, StreamGroupId, StreamStats, Streams, UdpStats,
     WriteError, Written,
 };
 
 mod config;
 pub use config::{
-    AckFrequencyConfig, ClientConfig, ConfigError, EndpointConfig, IdleTimeout, MtuDiscoveryConfig,
-    ServerConfig, TransportConfig,
+    AckFrequencyConfig, AntiReplay, ClientConfig, ConfigError, DatagramDropPolicy, EndpointConfig,
+    IdleTimeout, MtuDiscoveryConfig, PacingConfig, SendBufferAllocator, ServerConfig,
+    StreamWindowUpdateConfig, TransportConfig, ZeroRttPolicy,
 };
 
 pub mod crypto;
@@ -64,6 +69,9 @@ pub use crate::endpoint::{
     AcceptError, ConnectError, ConnectionHandle, DatagramEvent, Endpoint, Incoming, RetryError,
 };
 
+mod extension;
+pub use crate::extension::{FrameExtension, SendExtensionFrameError};
+
 mod packet;
 pub use packet::{
     ConnectionIdParser, FixedLengthConnectionIdParser, LongType, PacketDecodeError, PartialDecode,
@@ -78,30 +86,133 @@ pub use crate::transport_error::{Code as TransportErrorCode, Error as TransportE
 
 pub mod congestion;
 
+pub mod scheduler;
+
 mod cid_generator;
 pub use crate::cid_generator::{
-    ConnectionIdGenerator, HashedConnectionIdGenerator, InvalidCid, RandomConnectionIdGenerator,
+    ConnectionIdGenerator, HashedConnectionIdGenerator, InvalidCid, QuicLbConfig,
+    QuicLbConnectionIdGenerator, RandomConnectionIdGenerator,
 };
 
+mod client_hello;
+pub use crate::client_hello::ClientHello;
+
 mod token;
-use token::{ResetToken, RetryToken};
+use token::ResetToken;
+pub use token::{TokenError, TokenMemoryCache, TokenProvider, TokenStore, ValidatedToken};
 
 #[cfg(feature = "arbitrary")]
 use arbitrary::Arbitrary;
 
-#[doc(hidden)]
-#[cfg(fuzzing)]
+/// Entry points for fuzzing the decoding of untrusted input
+///
+/// Each `fuzz_*` function is deterministic with respect to its input, and panics only if an
+/// invariant is violated, so that it can serve directly as the body of a fuzz target. Inputs
+/// implement [`Arbitrary`](arbitrary::Arbitrary) for structured fuzzing. The remaining items
+/// expose internals to fuzz targets, and are not subject to semver guarantees.
+#[cfg(feature = "fuzzing")]
 pub mod fuzzing {
+    use std::{
+        net::{Ipv4Addr, SocketAddr},
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
     pub use crate::connection::{Retransmits, State as ConnectionState, StreamsState};
-    pub use crate::frame::ResetStream;
+    pub use crate::frame::{ResetStream, ResetStreamAt};
     pub use crate::packet::PartialDecode;
     pub use crate::transport_parameters::TransportParameters;
     pub use bytes::{BufMut, BytesMut};
 
-    #[cfg(feature = "arbitrary")]
+    use crate::{
+        frame, ClientConfig, DatagramEvent, Endpoint, EndpointConfig,
+        FixedLengthConnectionIdParser, RandomConnectionIdGenerator, DEFAULT_SUPPORTED_VERSIONS,
+    };
     use arbitrary::{Arbitrary, Result, Unstructured};
 
-    #[cfg(feature = "arbitrary")]
+    /// Decode the header of a datagram, checking that it splits into whole packets
+    pub fn fuzz_decode_packet(params: PacketParams) {
+        let len = params.buf.len();
+        if let Ok((first, rest)) = PartialDecode::new(
+            params.buf,
+            &FixedLengthConnectionIdParser::new(params.local_cid_len),
+            DEFAULT_SUPPORTED_VERSIONS,
+            params.grease_quic_bit,
+        ) {
+            assert_eq!(len, first.len() + rest.map_or(0, |rest| rest.len()));
+        }
+    }
+
+    /// Decode `payload` as the frames of a decrypted packet
+    pub fn fuzz_decode_frames(payload: &[u8]) {
+        let Ok(frames) = frame::Iter::new(payload.to_vec().into()) else {
+            return;
+        };
+        for frame in frames {
+            if frame.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Feed datagrams from the server to a client connection that has just sent its first Initial
+    ///
+    /// Without the keys of the handshake, only the packets processed before authentication are
+    /// reachable in practice: Version Negotiation, Retry, stateless resets, and the unprotected
+    /// part of other packets. The connection's events, transmits and timers are polled after every
+    /// datagram.
+    #[cfg(any(feature = "rustls-aws-lc-rs", feature = "rustls-ring"))]
+    pub fn fuzz_connection_event(input: ConnectionEventInput) {
+        let mut config = EndpointConfig::default();
+        // Route every datagram from the server to the connection
+        config.cid_generator(|| Box::new(RandomConnectionIdGenerator::new(0)));
+        let mut endpoint = Endpoint::new(Arc::new(config), None, true, Some([0; 32]));
+        let crypto = rustls::ClientConfig::builder_with_provider(
+            crate::crypto::rustls::configured_provider(),
+        )
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_root_certificates(rustls::RootCertStore::empty())
+        .with_no_client_auth();
+        let client_config = ClientConfig::new(Arc::new(
+            crate::crypto::rustls::QuicClientConfig::try_from(crypto).unwrap(),
+        ));
+        let server = SocketAddr::from((Ipv4Addr::new(192, 0, 2, 1), 443));
+        let mut now = Instant::now();
+        let (ch, mut conn) = endpoint
+            .connect(now, client_config, server, "example.com")
+            .unwrap();
+
+        let mut buf = Vec::new();
+        for (delay, datagram) in input.datagrams {
+            now += Duration::from_millis(delay.into());
+            conn.handle_timeout(now);
+            if let Some(DatagramEvent::ConnectionEvent(_, event)) =
+                endpoint.handle(now, server, None, None, datagram[..].into(), &mut buf)
+            {
+                conn.handle_event(event);
+            }
+            while let Some(event) = conn.poll_endpoint_events() {
+                if let Some(event) = endpoint.handle_event(ch, event) {
+                    conn.handle_event(event);
+                }
+            }
+            buf.clear();
+            while conn.poll_transmit(now, 1, &mut buf).is_some() {
+                buf.clear();
+            }
+            while conn.poll().is_some() {}
+            let _ = conn.poll_timeout();
+        }
+    }
+
+    /// Input of [`fuzz_connection_event()`]
+    #[derive(Debug, Arbitrary)]
+    pub struct ConnectionEventInput {
+        /// Datagrams received from the server, each after a delay in milliseconds
+        pub datagrams: Vec<(u16, Vec<u8>)>,
+    }
+
     impl<'arbitrary> Arbitrary<'arbitrary> for TransportParameters {
         fn arbitrary(u: &mut Unstructured<'arbitrary>) -> Result<Self> {
             Ok(Self {
@@ -114,14 +225,17 @@ pub mod fuzzing {
         }
     }
 
+    /// Input of [`fuzz_decode_packet()`]
     #[derive(Debug)]
     pub struct PacketParams {
+        /// Length of the connection IDs of the decoding endpoint
         pub local_cid_len: usize,
+        /// The datagram
         pub buf: BytesMut,
+        /// Whether the decoding endpoint accepts packets with the fixed bit cleared
         pub grease_quic_bit: bool,
     }
 
-    #[cfg(feature = "arbitrary")]
     impl<'arbitrary> Arbitrary<'arbitrary> for PacketParams {
         fn arbitrary(u: &mut Unstructured<'arbitrary>) -> Result<Self> {
             let local_cid_len: usize = u.int_in_range(0..=crate::MAX_CID_SIZE)?;
@@ -137,9 +251,19 @@ pub mod fuzzing {
     }
 }
 
+/// QUIC version 1, specified by RFC 9000
+pub const QUIC_V1: u32 = 0x0000_0001;
+
+/// QUIC version 2, specified by RFC 9369
+///
+/// Functionally identical to version 1, but encoded differently on the wire, so that middleboxes
+/// ossified around version 1 do not interfere with it.
+pub const QUIC_V2: u32 = 0x6b33_43cf;
+
 /// The QUIC protocol version implemented.
 pub const DEFAULT_SUPPORTED_VERSIONS: &[u32] = &[
-    0x00000001,
+    QUIC_V1,
+    QUIC_V2,
     0xff00_001d,
     0xff00_001e,
     0xff00_001f,
@@ -291,7 +291,7 @@ impl Header {
                 number,
                 version,
             }) => {
-                w.write(u8::from(LongHeaderType::Initial) | number.tag());
+                w.write(LongHeaderType::Initial.to_byte(version) | number.tag());
                 w.write(version);
                 dst_cid.encode_long(w);
                 src_cid.encode_long(w);
@@ -312,7 +312,7 @@ impl Header {
                 number,
                 version,
             } => {
-                w.write(u8::from(LongHeaderType::Standard(ty)) | number.tag());
+                w.write(LongHeaderType::Standard(ty).to_byte(version) | number.tag());
                 w.write(version);
                 dst_cid.encode_long(w);
                 src_cid.encode_long(w);
@@ -329,7 +329,7 @@ impl Header {
                 ref src_cid,
                 version,
             } => {
-                w.write(u8::from(LongHeaderType::Retry));
+                w.write(LongHeaderType::Retry.to_byte(version));
                 w.write(version);
                 dst_cid.encode_long(w);
                 src_cid.encode_long(w);
@@ -473,24 +473,37 @@ impl PartialEncode {
         header_crypto: &dyn crypto::HeaderKey,
         crypto: Option<(u64, &dyn crypto::PacketKey)>,
     ) {
-        let Self { header_len, pn, .. } = self;
-        let (pn_len, write_len) = match pn {
-            Some((pn_len, write_len)) => (pn_len, write_len),
-            None => return,
-        };
-
-        let pn_pos = header_len - pn_len;
-        if write_len {
-            let len = buf.len() - header_len + pn_len;
-            assert!(len < 2usize.pow(14)); // Fits in reserved space
-            let mut slice = &mut buf[pn_pos - 2..pn_pos];
-            slice.put_u16(len as u16 | 0b01 << 14);
+        if self.pn.is_none() {
+            return;
         }
 
+        self.write_len(buf);
         if let Some((number, crypto)) = crypto {
-            crypto.encrypt(number, buf, header_len);
+            crypto.encrypt(number, buf, self.header_len);
         }
+        self.protect_header(buf, header_crypto);
+    }
+
+    /// Fill in the payload length of the packet in `buf`, which must precede encryption
+    pub(crate) fn write_len(&self, buf: &mut [u8]) {
+        let Some((pn_len, true)) = self.pn else {
+            return;
+        };
+
+        let pn_pos = self.header_len - pn_len;
+        let len = buf.len() - self.header_len + pn_len;
+        assert!(len < 2usize.pow(14)); // Fits in reserved space
+        let mut slice = &mut buf[pn_pos - 2..pn_pos];
+        slice.put_u16(len as u16 | 0b01 << 14);
+    }
+
+    /// Apply header protection to the encrypted packet in `buf`
+    pub(crate) fn protect_header(&self, buf: &mut [u8], header_crypto: &dyn crypto::HeaderKey) {
+        let Some((pn_len, _)) = self.pn else {
+            return;
+        };
 
+        let pn_pos = self.header_len - pn_len;
         debug_assert!(
             pn_pos + 4 + header_crypto.sample_size() <= buf.len(),
             "packet must be padded to at least {} bytes for header protection sampling",
@@ -617,7 +630,7 @@ impl ProtectedHeader {
                 });
             }
 
-            match LongHeaderType::from_byte(first)? {
+            match LongHeaderType::from_byte(first, version) {
                 LongHeaderType::Initial => {
                     let token_len = buf.get_var()? as usize;
                     let token_start = buf.position() as usize;
@@ -814,28 +827,37 @@ pub(crate) enum LongHeaderType {
 }
 
 impl LongHeaderType {
-    fn from_byte(b: u8) -> Result<Self, PacketDecodeError> {
+    fn from_byte(b: u8, version: u32) -> Self {
         use self::{LongHeaderType::*, LongType::*};
         debug_assert!(b & LONG_HEADER_FORM != 0, "not a long packet");
-        Ok(match (b & 0x30) >> 4 {
+        let bits = (b & 0x30) >> 4;
+        // QUIC v2 rotates the packet types (RFC 9369 §3.2)
+        let bits = match version {
+            crate::QUIC_V2 => (bits + 3) % 4,
+            _ => bits,
+        };
+        match bits {
             0x0 => Initial,
             0x1 => Standard(ZeroRtt),
             0x2 => Standard(Handshake),
             0x3 => Retry,
             _ => unreachable!(),
-        })
+        }
     }
-}
 
-impl From<LongHeaderType> for u8 {
-    fn from(ty: LongHeaderType) -> Self {
+    fn to_byte(self, version: u32) -> u8 {
         use self::{LongHeaderType::*, LongType::*};
-        match ty {
-            Initial => LONG_HEADER_FORM | FIXED_BIT,
-            Standard(ZeroRtt) => LONG_HEADER_FORM | FIXED_BIT | (0x1 << 4),
-            Standard(Handshake) => LONG_HEADER_FORM | FIXED_BIT | (0x2 << 4),
-            Retry => LONG_HEADER_FORM | FIXED_BIT | (0x3 << 4),
-        }
+        let bits = match self {
+            Initial => 0x0,
+            Standard(ZeroRtt) => 0x1,
+            Standard(Handshake) => 0x2,
+            Retry => 0x3,
+        };
+        let bits = match version {
+            crate::QUIC_V2 => (bits + 1) % 4,
+            _ => bits,
+        };
+        LONG_HEADER_FORM | FIXED_BIT | (bits << 4)
     }
 }
 
@@ -0,0 +1,45 @@
+//! Logic for choosing which stream to send data from next
+
+use std::sync::Arc;
+
+use crate::StreamId;
+
+mod priority;
+mod weighted_fair;
+
+pub use priority::{RoundRobinConfig, StrictPriorityConfig};
+pub use weighted_fair::WeightedFairConfig;
+
+/// Orders the streams of a connection that have data to send
+///
+/// The connection queues a stream with [`push`](Self::push) when it gains data to send, such as
+/// when the application writes to it or previously sent data is lost. While assembling packets,
+/// the connection repeatedly [`pop`](Self::pop)s a stream and writes a single STREAM frame from it.
+/// If the stream still has data to send afterwards, it is handed back with
+/// [`requeue`](Self::requeue) before the next stream is popped.
+///
+/// A stream is never queued more than once at a time. Streams may be reset while queued, in which
+/// case they are skipped when popped.
+pub trait StreamScheduler: Send + Sync {
+    /// Queue a stream which has data to send
+    fn push(&mut self, id: StreamId, priority: i32);
+
+    /// Take the stream to send data from next out of the queue
+    fn pop(&mut self) -> Option<StreamId>;
+
+    /// Queue the stream most recently returned by [`pop`](Self::pop) after `bytes` of its data were
+    /// written into a STREAM frame, as it still has more data to send
+    fn requeue(&mut self, id: StreamId, priority: i32, bytes: u64);
+
+    /// Iterate over the queued streams, in no particular order
+    fn iter(&self) -> Box<dyn Iterator<Item = StreamId> + '_>;
+
+    /// Remove all streams from the queue
+    fn clear(&mut self);
+}
+
+/// Constructs schedulers on demand
+pub trait StreamSchedulerFactory {
+    /// Construct a fresh `StreamScheduler`
+    fn build(self: Arc<Self>) -> Box<dyn StreamScheduler>;
+}
@@ -0,0 +1,151 @@
+use std::{collections::BinaryHeap, sync::Arc};
+
+use super::{StreamScheduler, StreamSchedulerFactory};
+use crate::StreamId;
+
+/// Configuration for a scheduler which sends higher priority streams first, and streams of the
+/// same priority in the order they were written to
+///
+/// Once a stream has been partly written out, it is completed before moving on to any other
+/// stream, even one of higher priority. This minimizes fragmentation and protocol overhead for
+/// workloads that use many small streams, but allows a single stream to starve all others.
+#[derive(Debug, Clone, Default)]
+pub struct StrictPriorityConfig {
+    _private: (),
+}
+
+impl StreamSchedulerFactory for StrictPriorityConfig {
+    fn build(self: Arc<Self>) -> Box<dyn StreamScheduler> {
+        Box::new(PendingStreamsQueue::new(u64::MAX))
+    }
+}
+
+/// Configuration for a scheduler which sends higher priority streams first, and takes turns
+/// between streams of the same priority
+#[derive(Debug, Clone, Default)]
+pub struct RoundRobinConfig {
+    burst_size: u64,
+}
+
+impl RoundRobinConfig {
+    /// Amount of data in bytes a stream may send before yielding to the next stream of the same
+    /// priority
+    ///
+    /// A stream always sends at least one STREAM frame per turn, so the default of zero switches
+    /// streams after every frame. Larger values reduce fragmentation at the cost of latency for
+    /// other streams. A stream of higher priority that becomes pending during a turn is sent once
+    /// the turn ends.
+    pub fn burst_size(&mut self, value: u64) -> &mut Self {
+        self.burst_size = value;
+        self
+    }
+}
+
+impl StreamSchedulerFactory for RoundRobinConfig {
+    fn build(self: Arc<Self>) -> Box<dyn StreamScheduler> {
+        Box::new(PendingStreamsQueue::new(self.burst_size))
+    }
+}
+
+/// A queue of streams with pending outgoing data, sorted by priority
+struct PendingStreamsQueue {
+    streams: BinaryHeap<PendingStream>,
+    /// The stream whose turn is in progress, if it was interrupted while it still has some pending
+    /// data. See `requeue()`.
+    next: Option<PendingStream>,
+    /// A monotonically decreasing counter, used to implement round-robin scheduling for streams of the same priority.
+    /// Underflowing is not a practical concern, as it is initialized to u64::MAX and only decremented by 1 in `push`
+    recency: u64,
+    /// Amount of data a stream may send in one turn
+    burst_size: u64,
+    /// Amount of data sent by the stream whose turn is in progress
+    sent: u64,
+}
+
+impl PendingStreamsQueue {
+    fn new(burst_size: u64) -> Self {
+        Self {
+            streams: BinaryHeap::new(),
+            next: None,
+            recency: u64::MAX,
+            burst_size,
+            sent: 0,
+        }
+    }
+}
+
+impl StreamScheduler for PendingStreamsQueue {
+    /// Push a pending stream ID with the given priority, queued after any already-queued streams for the priority
+    fn push(&mut self, id: StreamId, priority: i32) {
+        // Note that if a stream's turn is in progress we don't bump it even if
+        // priority > next.priority. In order to minimize fragmentation we always try to complete
+        // a turn once part of it has been written.
+
+        // As the recency counter is monotonically decreasing, we know that using its value to sort this stream will queue it
+        // after all other queued streams of the same priority.
+        // This is enough to implement round-robin scheduling for streams that are still pending even after being handled,
+        // as in that case they are removed from the `BinaryHeap`, handled, and then immediately reinserted.
+        self.recency -= 1;
+        self.streams.push(PendingStream {
+            priority,
+            recency: self.recency,
+            id,
+        });
+    }
+
+    fn pop(&mut self) -> Option<StreamId> {
+        if let Some(next) = self.next.take() {
+            return Some(next.id);
+        }
+        self.sent = 0;
+        self.streams.pop().map(|s| s.id)
+    }
+
+    /// Reinsert a stream that was pending and still contains unsent data
+    ///
+    /// The stream keeps its turn until it has sent `burst_size` bytes, and otherwise goes to the
+    /// back of the queue for its priority.
+    fn requeue(&mut self, id: StreamId, priority: i32, bytes: u64) {
+        assert!(self.next.is_none());
+
+        self.sent = self.sent.saturating_add(bytes);
+        if self.sent >= self.burst_size {
+            self.push(id, priority);
+            return;
+        }
+
+        self.next = Some(PendingStream {
+            priority,
+            recency: self.recency, // the value here doesn't really matter
+            id,
+        });
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = StreamId> + '_> {
+        Box::new(self.next.iter().chain(self.streams.iter()).map(|s| s.id))
+    }
+
+    fn clear(&mut self) {
+        self.next = None;
+        self.streams.clear();
+    }
+}
+
+/// The [`StreamId`] of a stream with pending data queued, ordered by its priority and recency
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct PendingStream {
+    /// The priority of the stream
+    // Note that this field should be kept above the `recency` field, in order for the `Ord` derive to be correct
+    // (See https://doc.rust-lang.org/stable/std/cmp/trait.Ord.html#derivable)
+    priority: i32,
+    /// A tie-breaker for streams of the same priority, used to improve fairness by implementing round-robin scheduling:
+    /// Larger values are prioritized, so it is initialised to `u64::MAX`, and when a stream writes data, we know
+    /// that it currently has the highest recency value, so it is deprioritized by setting its recency to 1 less than the
+    /// previous lowest recency value, such that all other streams of this priority will get processed once before we get back
+    /// round to this one
+    recency: u64,
+    /// The ID of the stream
+    // The way this type is used ensures that every instance has a unique `recency` value, so this field should be kept below
+    // the `priority` and `recency` fields, so that it does not interfere with the behaviour of the `Ord` derive
+    id: StreamId,
+}
@@ -0,0 +1,72 @@
+use std::{cmp::Reverse, collections::BinaryHeap, sync::Arc};
+
+use super::{StreamScheduler, StreamSchedulerFactory};
+use crate::StreamId;
+
+/// Configuration for a scheduler which shares bandwidth between streams in proportion to their
+/// priority
+///
+/// A stream with priority `p` is given a weight of `p + 1`, with negative priorities treated as
+/// zero. While several streams have data to send, each receives a share of the connection's
+/// throughput proportional to its weight, so lower priority streams are slowed down rather than
+/// starved. Streams of equal weight take turns.
+#[derive(Debug, Clone, Default)]
+pub struct WeightedFairConfig {
+    _private: (),
+}
+
+impl StreamSchedulerFactory for WeightedFairConfig {
+    fn build(self: Arc<Self>) -> Box<dyn StreamScheduler> {
+        Box::new(WeightedFairQueue::default())
+    }
+}
+
+/// A queue of streams with pending outgoing data, sorted by virtual finish time
+#[derive(Default)]
+struct WeightedFairQueue {
+    /// Queued streams, ordered by the virtual time at which they may next send
+    streams: BinaryHeap<Reverse<(u64, u64, StreamId)>>,
+    /// Virtual time of the most recently popped stream
+    now: u64,
+    /// Tie-breaker for streams queued at the same virtual time, preserving insertion order
+    seq: u64,
+}
+
+impl WeightedFairQueue {
+    fn insert(&mut self, id: StreamId, time: u64) {
+        self.seq += 1;
+        self.streams.push(Reverse((time, self.seq, id)));
+    }
+}
+
+impl StreamScheduler for WeightedFairQueue {
+    fn push(&mut self, id: StreamId, _priority: i32) {
+        self.insert(id, self.now);
+    }
+
+    fn pop(&mut self) -> Option<StreamId> {
+        let Reverse((time, _, id)) = self.streams.pop()?;
+        self.now = time;
+        Some(id)
+    }
+
+    fn requeue(&mut self, id: StreamId, priority: i32, bytes: u64) {
+        let weight = u64::from(priority.max(0).unsigned_abs()) + 1;
+        // Always advance by a nonzero amount, so that a stream sending empty frames can't
+        // monopolize the connection
+        let cost = (bytes.max(1) << VIRTUAL_TIME_SHIFT) / weight;
+        self.insert(id, self.now.saturating_add(cost.max(1)));
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = StreamId> + '_> {
+        Box::new(self.streams.iter().map(|Reverse((_, _, id))| *id))
+    }
+
+    fn clear(&mut self) {
+        self.streams.clear();
+    }
+}
+
+/// Scale of virtual time relative to bytes, so that costs of streams with large weights retain
+/// precision
+const VIRTUAL_TIME_SHIFT: u32 = 16;
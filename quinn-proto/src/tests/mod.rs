@@ -1,8 +1,12 @@
 use std::{
+    any::Any,
     convert::TryInto,
-    mem,
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
-    sync::Arc,
+    iter, mem,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, Instant},
 };
 
@@ -188,6 +192,455 @@ fn stateless_retry() {
     assert_eq!(pair.server.known_cids(), 0);
 }
 
+#[test]
+fn pending_handshakes() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.server.incoming_connection_behavior = IncomingConnectionBehavior::Wait;
+    let client_ch = pair.begin_connect(client_config());
+    pair.drive();
+    assert_eq!(pair.server.pending_handshakes(), 1);
+
+    let incoming = pair.server.waiting_incoming.pop().unwrap();
+    let server_ch = pair.server.try_accept(incoming, pair.time).unwrap();
+    assert_eq!(pair.server.pending_handshakes(), 1);
+    pair.drive();
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::HandshakeDataReady)
+    );
+    assert_eq!(pair.server.pending_handshakes(), 0);
+
+    let now = pair.time;
+    pair.client_conn_mut(client_ch)
+        .close(now, VarInt(0), Bytes::new());
+    pair.drive();
+    assert_eq!(pair.server.pending_handshakes(), 0);
+    assert_eq!(pair.client.pending_handshakes(), 0);
+}
+
+#[test]
+fn quic_v2() {
+    let _guard = subscribe();
+    let mut client_config = client_config();
+    client_config.version(QUIC_V2);
+
+    let mut pair = Pair::default();
+    pair.server.incoming_connection_behavior = IncomingConnectionBehavior::Validate;
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    const MSG: &[u8] = b"hello";
+    pair.client_send(client_ch, s).write(MSG).unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
+    );
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(false).unwrap();
+    assert_matches!(
+        chunks.next(usize::MAX),
+        Ok(Some(chunk)) if chunk.offset == 0 && chunk.bytes == MSG
+    );
+    let _ = chunks.finalize();
+}
+
+#[test]
+fn version_fallback() {
+    let _guard = subscribe();
+    let client = Endpoint::new(Default::default(), None, true, None);
+    let mut server_endpoint_config = EndpointConfig::default();
+    server_endpoint_config.supported_versions(vec![QUIC_V1]);
+    let server = Endpoint::new(
+        Arc::new(server_endpoint_config),
+        Some(Arc::new(server_config())),
+        true,
+        None,
+    );
+    let mut pair = Pair::new_from_endpoint(client, server);
+
+    // The server only speaks v1, so the client must fall back after Version Negotiation
+    let mut client_config = client_config();
+    client_config.version(QUIC_V2);
+    let (client_ch, _) = pair.connect_with(client_config);
+    let events = pair.client_conn_mut(client_ch).handshake_events();
+    assert_eq!(events.retries, 0);
+    assert_eq!(events.version_negotiations, 1);
+}
+
+#[test]
+fn retry_events() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.server.incoming_connection_behavior = IncomingConnectionBehavior::Validate;
+    let (client_ch, _) = pair.connect();
+    let events = pair.client_conn_mut(client_ch).handshake_events();
+    assert_eq!(events.retries, 1);
+    assert_eq!(events.version_negotiations, 0);
+}
+
+#[test]
+fn retry_refused() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.server.incoming_connection_behavior = IncomingConnectionBehavior::Validate;
+    let mut client_config = client_config();
+    client_config.allow_retry(false);
+    let client_ch = pair.begin_connect(client_config);
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::ConnectionLost {
+            reason: ConnectionError::RetryRefused,
+        })
+    );
+    assert_eq!(pair.server.known_connections(), 0);
+}
+
+#[test]
+fn version_negotiation_refused() {
+    let _guard = subscribe();
+    let client = Endpoint::new(Default::default(), None, true, None);
+    let mut server_endpoint_config = EndpointConfig::default();
+    server_endpoint_config.supported_versions(vec![QUIC_V1]);
+    let server = Endpoint::new(
+        Arc::new(server_endpoint_config),
+        Some(Arc::new(server_config())),
+        true,
+        None,
+    );
+    let mut pair = Pair::new_from_endpoint(client, server);
+
+    let mut client_config = client_config();
+    client_config
+        .version(QUIC_V2)
+        .allow_version_negotiation(false);
+    let client_ch = pair.begin_connect(client_config);
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::ConnectionLost {
+            reason: ConnectionError::VersionMismatch,
+        })
+    );
+}
+
+#[test]
+fn version_downgrade() {
+    let _guard = subscribe();
+    // Use empty CIDs on the client so we can easily hardcode a version negotiation packet
+    let cid_generator_factory: fn() -> Box<dyn ConnectionIdGenerator> =
+        || Box::new(RandomConnectionIdGenerator::new(0));
+    let mut client_endpoint_config = EndpointConfig {
+        connection_id_generator_factory: Arc::new(cid_generator_factory),
+        ..Default::default()
+    };
+    client_endpoint_config.supported_versions(vec![QUIC_V2, QUIC_V1]);
+    let client = Endpoint::new(Arc::new(client_endpoint_config), None, true, None);
+    let server = Endpoint::new(
+        Default::default(),
+        Some(Arc::new(server_config())),
+        true,
+        None,
+    );
+    let mut pair = Pair::new_from_endpoint(client, server);
+
+    let mut client_config = client_config();
+    client_config.version(QUIC_V2);
+    let client_ch = pair.begin_connect(client_config);
+    pair.client.drive_outgoing(pair.time);
+    // An attacker drops the client's first flight and forges Version Negotiation offering only v1
+    pair.client.outbound.clear();
+    pair.client.inbound.push_back((
+        pair.time,
+        None,
+        hex!("80 00000000 00 00 00000001")[..].into(),
+    ));
+    pair.drive();
+
+    // The server's version_information reveals that both peers prefer v2
+    loop {
+        match pair.client_conn_mut(client_ch).poll() {
+            Some(Event::ConnectionLost {
+                reason: ConnectionError::TransportError(err),
+            }) => {
+                assert_eq!(err.code, TransportErrorCode::VERSION_NEGOTIATION_ERROR);
+                break;
+            }
+            Some(_) => {}
+            None => panic!("connection was not closed"),
+        }
+    }
+}
+
+#[test]
+fn custom_transport_parameters() {
+    let _guard = subscribe();
+    const ID: VarInt = VarInt(0x4242);
+    let mut transport = TransportConfig::default();
+    assert_eq!(
+        transport
+            .custom_transport_parameter(VarInt(0x04), &b"conflict"[..])
+            .unwrap_err(),
+        ConfigError::ReservedTransportParameter
+    );
+    transport
+        .custom_transport_parameter(ID, &b"old"[..])
+        .unwrap()
+        .custom_transport_parameter(ID, &b"ext"[..])
+        .unwrap();
+    let mut server_config = server_config();
+    server_config.transport = Arc::new(transport);
+
+    let mut pair = Pair::new(Default::default(), server_config);
+    let client_ch = pair.begin_connect(client_config());
+    assert!(pair
+        .client_conn_mut(client_ch)
+        .peer_transport_parameters()
+        .is_none());
+    pair.drive();
+    let server_ch = pair.server.assert_accept();
+
+    let params = pair
+        .client_conn_mut(client_ch)
+        .peer_transport_parameters()
+        .unwrap();
+    assert_eq!(params.custom(ID), Some(&b"ext"[..]));
+    assert_eq!(params.custom_parameters().count(), 1);
+    let params = pair
+        .server_conn_mut(server_ch)
+        .peer_transport_parameters()
+        .unwrap();
+    assert_eq!(params.custom_parameters().count(), 0);
+}
+
+/// A frame extension whose frames hold a one-byte length followed by that many bytes
+#[derive(Default)]
+struct TestExtension {
+    received: Mutex<Vec<Bytes>>,
+}
+
+impl TestExtension {
+    const FRAME_TYPE: VarInt = VarInt(0x7f00);
+}
+
+impl FrameExtension for TestExtension {
+    fn frame_types(&self) -> &[VarInt] {
+        &[Self::FRAME_TYPE]
+    }
+
+    fn transport_parameter(&self) -> (VarInt, Bytes) {
+        (VarInt(0x7f00), Bytes::new())
+    }
+
+    fn frame_len(&self, _: VarInt, buf: &[u8]) -> Option<usize> {
+        Some(1 + usize::from(*buf.first()?))
+    }
+
+    fn on_frame(&self, _: VarInt, body: Bytes) -> Result<(), TransportError> {
+        self.received.lock().unwrap().push(body.slice(1..));
+        Ok(())
+    }
+}
+
+#[test]
+fn frame_extension() {
+    let _guard = subscribe();
+    let server_ext = Arc::new(TestExtension::default());
+    let mut transport = TransportConfig::default();
+    transport.frame_extension(server_ext.clone()).unwrap();
+    let mut server_config = server_config();
+    server_config.transport = Arc::new(transport);
+    let mut transport = TransportConfig::default();
+    transport
+        .frame_extension(Arc::new(TestExtension::default()))
+        .unwrap();
+    let mut client_config = client_config();
+    client_config.transport_config(Arc::new(transport));
+
+    let mut pair = Pair::new(Default::default(), server_config);
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+    assert_eq!(
+        pair.client_conn_mut(client_ch)
+            .send_extension_frame(VarInt(0x7f01), Bytes::new()),
+        Err(SendExtensionFrameError::UnknownType)
+    );
+    pair.client_conn_mut(client_ch)
+        .send_extension_frame(TestExtension::FRAME_TYPE, Bytes::from_static(b"\x02hi"))
+        .unwrap();
+    pair.drive();
+    assert_eq!(*server_ext.received.lock().unwrap(), [&b"hi"[..]]);
+    assert_eq!(
+        pair.server_conn_mut(server_ch).stats().frame_rx.extension,
+        1
+    );
+}
+
+#[test]
+fn frame_extension_not_negotiated() {
+    let _guard = subscribe();
+    let mut transport = TransportConfig::default();
+    transport
+        .frame_extension(Arc::new(TestExtension::default()))
+        .unwrap();
+    let mut client_config = client_config();
+    client_config.transport_config(Arc::new(transport));
+
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect_with(client_config);
+    assert_eq!(
+        pair.client_conn_mut(client_ch)
+            .send_extension_frame(TestExtension::FRAME_TYPE, Bytes::from_static(b"\x00")),
+        Err(SendExtensionFrameError::UnsupportedByPeer)
+    );
+}
+
+/// Congestion controller with a fixed window, recording the receive timestamps reported by the peer
+#[derive(Clone, Default)]
+struct TimestampRecorder(Arc<Mutex<Vec<(Instant, Duration)>>>);
+
+impl congestion::Controller for TimestampRecorder {
+    fn on_congestion_event(&mut self, _: Instant, _: Instant, _: bool, _: u64) {}
+
+    fn on_receive_timestamp(&mut self, sent: Instant, received: Duration) {
+        self.0.lock().unwrap().push((sent, received));
+    }
+
+    fn on_mtu_update(&mut self, _: u16) {}
+
+    fn window(&self) -> u64 {
+        1_000_000
+    }
+
+    fn clone_box(&self) -> Box<dyn congestion::Controller> {
+        Box::new(self.clone())
+    }
+
+    fn initial_window(&self) -> u64 {
+        1_000_000
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl congestion::ControllerFactory for TimestampRecorder {
+    fn build(self: Arc<Self>, _: Instant, _: u16) -> Box<dyn congestion::Controller> {
+        Box::new((*self).clone())
+    }
+}
+
+#[test]
+fn receive_timestamps() {
+    let _guard = subscribe();
+    let recorder = TimestampRecorder::default();
+    let mut transport = TransportConfig::default();
+    transport
+        .receive_timestamps(true)
+        .congestion_controller_factory(Arc::new(recorder.clone()));
+    let mut client_config = client_config();
+    client_config.transport_config(Arc::new(transport));
+
+    let mut pair = Pair::default();
+    pair.latency = Duration::from_millis(10);
+    let (client_ch, _) = pair.connect_with(client_config);
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(&[0; 32_000]).unwrap();
+    pair.drive();
+
+    let reports = recorder.0.lock().unwrap();
+    assert!(reports.len() > 10);
+    // The one-way delay is constant, so the sender and receiver clocks differ by a constant offset
+    let offset = reports[0].0 - reports[0].1;
+    assert!(reports
+        .iter()
+        .all(|&(sent, received)| sent - received == offset));
+}
+
+#[test]
+fn new_token() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.server.incoming_connection_behavior = IncomingConnectionBehavior::Validate;
+    let client_config = client_config();
+    let (client_ch, _) = pair.connect_with(client_config.clone());
+    pair.drive();
+    assert_eq!(
+        pair.client_conn_mut(client_ch).stats().frame_rx.new_token,
+        2
+    );
+    let now = pair.time;
+    pair.client_conn_mut(client_ch)
+        .close(now, VarInt(0), Bytes::new());
+    pair.drive();
+
+    // Connecting again presents one of the tokens, so the address is already validated, even
+    // though the client's port changed
+    pair.client.addr = SocketAddr::new(
+        Ipv6Addr::LOCALHOST.into(),
+        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    );
+    pair.server.incoming_connection_behavior = IncomingConnectionBehavior::Wait;
+    let client_ch = pair.begin_connect(client_config);
+    pair.drive();
+    let incoming = pair.server.waiting_incoming.pop().unwrap();
+    assert!(incoming.remote_address_validated());
+    let _ = pair.server.try_accept(incoming, pair.time);
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::HandshakeDataReady)
+    );
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Connected)
+    );
+}
+
+#[test]
+fn custom_token_provider() {
+    /// Stores the original destination CID in the clear, which is fine for a test
+    #[derive(Default)]
+    struct PlainTokens {
+        validated: AtomicUsize,
+    }
+
+    impl TokenProvider for PlainTokens {
+        fn retry_token(
+            &self,
+            _remote: &SocketAddr,
+            _retry_src_cid: &ConnectionId,
+            orig_dst_cid: &ConnectionId,
+        ) -> Vec<u8> {
+            [b"plain", &orig_dst_cid[..]].concat()
+        }
+
+        fn validate(
+            &self,
+            _remote: &SocketAddr,
+            _dst_cid: &ConnectionId,
+            token: &[u8],
+        ) -> Result<ValidatedToken, TokenError> {
+            let cid = token.strip_prefix(b"plain").ok_or(TokenError::Unknown)?;
+            self.validated.fetch_add(1, Ordering::Relaxed);
+            Ok(ValidatedToken::Retry {
+                orig_dst_cid: ConnectionId::new(cid),
+            })
+        }
+    }
+
+    let _guard = subscribe();
+    let tokens = Arc::new(PlainTokens::default());
+    let mut server_config = server_config();
+    server_config.token_provider(tokens.clone());
+    let mut pair = Pair::new(Default::default(), server_config);
+    pair.server.incoming_connection_behavior = IncomingConnectionBehavior::Validate;
+    pair.connect();
+    assert_eq!(tokens.validated.load(Ordering::Relaxed), 1);
+}
+
 #[test]
 fn server_stateless_reset() {
     let _guard = subscribe();
@@ -357,6 +810,194 @@ fn finish_stream_simple() {
     let _ = chunks.finalize();
 }
 
+#[test]
+fn close_after_flush() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(b"hello").unwrap();
+    assert_eq!(
+        pair.client_conn_mut(client_ch).unacked_finished_streams(),
+        0
+    );
+    // Finished streams count until the peer acknowledges all of their data
+    pair.client_send(client_ch, s).finish().unwrap();
+    assert_eq!(
+        pair.client_conn_mut(client_ch).unacked_finished_streams(),
+        1
+    );
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Finished { id })) if id == s
+    );
+    assert_eq!(
+        pair.client_conn_mut(client_ch).unacked_finished_streams(),
+        0
+    );
+}
+
+#[test]
+fn flushed_stream() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    assert_matches!(pair.client_send(client_ch, s).flushed(), Ok(true));
+
+    const MSG: &[u8] = b"hello";
+    pair.client_send(client_ch, s).write(MSG).unwrap();
+    assert_matches!(pair.client_send(client_ch, s).flushed(), Ok(false));
+    pair.drive();
+
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Flushed { id })) if id == s
+    );
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+    assert_matches!(pair.client_send(client_ch, s).flushed(), Ok(true));
+    assert_eq!(pair.client_streams(client_ch).send_streams(), 1);
+}
+
+#[test]
+fn send_stream_stats() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    const MSG: &[u8] = b"hello";
+    pair.client_send(client_ch, s).write(MSG).unwrap();
+    let stats = pair.client_send(client_ch, s).stats().unwrap();
+    assert_eq!(stats.bytes_written, MSG.len() as u64);
+    assert_eq!(stats.bytes_acked, 0);
+
+    pair.drive();
+    let stats = pair.client_send(client_ch, s).stats().unwrap();
+    assert_eq!(stats.bytes_written, MSG.len() as u64);
+    assert_eq!(stats.bytes_acked, MSG.len() as u64);
+    assert_eq!(stats.bytes_retransmitted, 0);
+
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+    assert!(pair.client_send(client_ch, s).stats().is_err());
+}
+
+#[test]
+fn arena_send_buffer() {
+    let _guard = subscribe();
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            transport: Arc::new(TransportConfig {
+                send_buffer_allocator: SendBufferAllocator::Arena { chunk_size: 64 },
+                ..TransportConfig::default()
+            }),
+            ..server_config()
+        },
+    );
+    let (client_ch, server_ch) = pair.connect();
+
+    let s = pair.server_streams(server_ch).open(Dir::Uni).unwrap();
+    let msg = (0..200u8).collect::<Vec<_>>();
+    for part in msg.chunks(7) {
+        assert_eq!(pair.server_send(server_ch, s).write(part), Ok(part.len()));
+    }
+    pair.server_send(server_ch, s).finish().unwrap();
+    pair.drive();
+
+    let stats = pair.server_send(server_ch, s).stats();
+    assert!(stats.is_err(), "stream should be fully acknowledged");
+    assert_matches!(
+        pair.client_streams(client_ch).accept(Dir::Uni),
+        Some(stream) if stream == s
+    );
+    let mut recv = pair.client_recv(client_ch, s);
+    let mut chunks = recv.read(true).unwrap();
+    let mut received = Vec::new();
+    while let Ok(Some(chunk)) = chunks.next(usize::MAX) {
+        received.extend_from_slice(&chunk.bytes);
+    }
+    assert_eq!(received, msg);
+    let _ = chunks.finalize();
+}
+
+#[test]
+fn stream_max_writable() {
+    let _guard = subscribe();
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            transport: Arc::new(TransportConfig {
+                stream_receive_window: 100u32.into(),
+                receive_window: 150u32.into(),
+                ..TransportConfig::default()
+            }),
+            ..server_config()
+        },
+    );
+    let (client_ch, _) = pair.connect();
+
+    let s1 = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    let s2 = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    assert_eq!(pair.client_streams(client_ch).max_writable(), 150);
+    assert_matches!(pair.client_send(client_ch, s1).max_writable(), Ok(100));
+
+    assert_eq!(
+        pair.client_send(client_ch, s1).write(&[0; 120]).unwrap(),
+        100
+    );
+    assert_matches!(pair.client_send(client_ch, s1).max_writable(), Ok(0));
+    assert_eq!(pair.client_streams(client_ch).max_writable(), 50);
+    // Limited by connection-level flow control
+    assert_matches!(pair.client_send(client_ch, s2).max_writable(), Ok(50));
+
+    pair.client_send(client_ch, s1).finish().unwrap();
+    assert!(pair.client_send(client_ch, s1).max_writable().is_err());
+}
+
+#[test]
+fn stream_group_send_budget() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+
+    const GROUP: StreamGroupId = StreamGroupId(7);
+    pair.client_streams(client_ch)
+        .set_group_send_budget(GROUP, 100);
+    let s1 = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    let s2 = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    let s3 = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s1)
+        .set_group(Some(GROUP))
+        .unwrap();
+    pair.client_send(client_ch, s2)
+        .set_group(Some(GROUP))
+        .unwrap();
+    assert_matches!(pair.client_send(client_ch, s2).group(), Ok(Some(GROUP)));
+
+    assert_eq!(pair.client_send(client_ch, s1).write(&[0; 80]).unwrap(), 80);
+    assert_eq!(pair.client_send(client_ch, s2).write(&[0; 80]).unwrap(), 20);
+    assert_matches!(pair.client_send(client_ch, s2).max_writable(), Ok(0));
+    assert_matches!(
+        pair.client_send(client_ch, s2).write(&[0; 80]),
+        Err(WriteError::Blocked)
+    );
+    // Streams outside the group are unaffected
+    assert_eq!(pair.client_send(client_ch, s3).write(&[0; 80]).unwrap(), 80);
+
+    // Acknowledgement of the group's data frees up its budget
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Writable { id })) if id == s2
+    );
+    assert_eq!(pair.client_send(client_ch, s2).write(&[0; 80]).unwrap(), 80);
+}
+
 #[test]
 fn reset_stream() {
     let _guard = subscribe();
@@ -386,6 +1027,48 @@ fn reset_stream() {
     assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
 }
 
+#[test]
+fn reset_stream_at() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+
+    const MSG: &[u8] = b"hello world";
+    pair.client_send(client_ch, s).write(MSG).unwrap();
+    pair.drive_client();
+    pair.server.inbound.clear(); // Lose the data
+
+    info!("resetting stream with a reliable size");
+    const ERROR: VarInt = VarInt(42);
+    pair.client_send(client_ch, s).reset_at(ERROR, 5).unwrap();
+    assert_matches!(
+        pair.client_send(client_ch, s).reset_at(ERROR, 5),
+        Err(ResetAtError::ClosedStream)
+    );
+    pair.drive();
+
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
+    );
+    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(true).unwrap();
+    assert_matches!(chunks.next(usize::MAX), Ok(Some(chunk)) if chunk.bytes == MSG[..5]);
+    assert_matches!(chunks.next(usize::MAX), Err(ReadError::Reset(ERROR)));
+    let _ = chunks.finalize();
+    assert_eq!(
+        pair.client_conn_mut(client_ch)
+            .stats()
+            .frame_tx
+            .reset_stream_at,
+        1
+    );
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+}
+
 #[test]
 fn stop_stream() {
     let _guard = subscribe();
@@ -568,40 +1251,138 @@ fn zero_rtt_happypath() {
     pair.client_send(client_ch, s).write(MSG).unwrap();
     pair.drive();
 
-    assert_matches!(
-        pair.client_conn_mut(client_ch).poll(),
-        Some(Event::HandshakeDataReady)
-    );
-    assert_matches!(
-        pair.client_conn_mut(client_ch).poll(),
-        Some(Event::Connected)
-    );
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::HandshakeDataReady)
+    );
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Connected)
+    );
+
+    assert!(pair.client_conn_mut(client_ch).accepted_0rtt());
+    let server_ch = pair.server.assert_accept();
+
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::HandshakeDataReady)
+    );
+    // We don't currently preserve stream event order wrt. connection events
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Connected)
+    );
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
+    );
+
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(false).unwrap();
+    assert_matches!(
+        chunks.next(usize::MAX),
+        Ok(Some(chunk)) if chunk.offset == 0 && chunk.bytes == MSG
+    );
+    let _ = chunks.finalize();
+    assert_eq!(pair.client_conn_mut(client_ch).lost_packets(), 0);
+}
+
+/// Establish and close a connection, then resume it, returning the stream opened in 0-RTT if any
+fn resume_with_0rtt(server_config: ServerConfig) -> (Pair, ConnectionHandle, Option<StreamId>) {
+    let mut pair = Pair::new(Default::default(), server_config);
+    let config = client_config();
+    let client_ch = pair.begin_connect(config.clone());
+    pair.drive();
+    pair.server.assert_accept();
+    pair.client
+        .connections
+        .get_mut(&client_ch)
+        .unwrap()
+        .close(pair.time, VarInt(0), [][..].into());
+    pair.drive();
+
+    pair.client.addr = SocketAddr::new(
+        Ipv6Addr::LOCALHOST.into(),
+        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    );
+    let client_ch = pair.begin_connect(config);
+    let s = pair.client_conn_mut(client_ch).has_0rtt().then(|| {
+        let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+        pair.client_send(client_ch, s)
+            .write(b"Hello, 0-RTT!")
+            .unwrap();
+        s
+    });
+    pair.drive();
+    (pair, client_ch, s)
+}
+
+#[test]
+fn zero_rtt_policy_disabled() {
+    let _guard = subscribe();
+    let mut server_config = server_config();
+    let mut policy = ZeroRttPolicy::default();
+    policy.enabled(false);
+    server_config.zero_rtt_policy(policy);
+    let (mut pair, client_ch, s) = resume_with_0rtt(server_config);
+    // Session tickets don't offer 0-RTT in the first place
+    assert_eq!(s, None);
+    assert!(!pair.client_conn_mut(client_ch).is_handshaking());
+    assert!(!pair.client_conn_mut(client_ch).accepted_0rtt());
+}
+
+#[test]
+fn zero_rtt_anti_replay() {
+    #[derive(Default)]
+    struct Seen(Mutex<Vec<[u8; 32]>>);
+
+    impl AntiReplay for Seen {
+        fn check(&self, hello: &ClientHello) -> bool {
+            assert!(hello.early_data);
+            let mut seen = self.0.lock().unwrap();
+            let fresh = !seen.contains(&hello.random);
+            seen.push(hello.random);
+            // Treat every attempt as a replay after the first
+            fresh && seen.len() == 1
+        }
+    }
+
+    let _guard = subscribe();
+    let anti_replay = Arc::new(Seen::default());
+    let mut server_config = server_config();
+    let mut policy = ZeroRttPolicy::default();
+    policy.anti_replay(anti_replay.clone());
+    server_config.zero_rtt_policy(policy);
+
+    let (mut pair, client_ch, s) = resume_with_0rtt(server_config.clone());
+    assert!(s.is_some());
+    assert!(pair.client_conn_mut(client_ch).accepted_0rtt());
+    let (mut pair, client_ch, s) = resume_with_0rtt(server_config);
+    assert!(s.is_some());
+    assert!(!pair.client_conn_mut(client_ch).accepted_0rtt());
+    // Connections without 0-RTT aren't checked
+    assert_eq!(anti_replay.0.lock().unwrap().len(), 2);
+}
 
+#[test]
+fn zero_rtt_max_early_data() {
+    let _guard = subscribe();
+    let mut server_config = server_config();
+    let mut policy = ZeroRttPolicy::default();
+    policy.max_early_data(0);
+    server_config.zero_rtt_policy(policy);
+    let (mut pair, client_ch, s) = resume_with_0rtt(server_config);
+    // The 0-RTT data is discarded by the server, then retransmitted as 1-RTT data
     assert!(pair.client_conn_mut(client_ch).accepted_0rtt());
+    assert!(pair.client_conn_mut(client_ch).lost_packets() > 0);
     let server_ch = pair.server.assert_accept();
-
-    assert_matches!(
-        pair.server_conn_mut(server_ch).poll(),
-        Some(Event::HandshakeDataReady)
-    );
-    // We don't currently preserve stream event order wrt. connection events
-    assert_matches!(
-        pair.server_conn_mut(server_ch).poll(),
-        Some(Event::Connected)
-    );
-    assert_matches!(
-        pair.server_conn_mut(server_ch).poll(),
-        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
-    );
-
-    let mut recv = pair.server_recv(server_ch, s);
+    let mut recv = pair.server_recv(server_ch, s.unwrap());
     let mut chunks = recv.read(false).unwrap();
     assert_matches!(
         chunks.next(usize::MAX),
-        Ok(Some(chunk)) if chunk.offset == 0 && chunk.bytes == MSG
+        Ok(Some(chunk)) if chunk.offset == 0 && chunk.bytes == b"Hello, 0-RTT!"[..]
     );
     let _ = chunks.finalize();
-    assert_eq!(pair.client_conn_mut(client_ch).lost_packets(), 0);
 }
 
 #[test]
@@ -825,6 +1606,141 @@ fn alpn_success() {
     assert_eq!(hd.protocol.unwrap(), &b"bar"[..]);
 }
 
+#[test]
+fn handshake_data() {
+    let _guard = subscribe();
+    let group = *crate::crypto::rustls::configured_provider()
+        .kx_groups
+        .iter()
+        .find(|group| group.name() == rustls::NamedGroup::secp384r1)
+        .unwrap();
+    let server_config = ServerConfig::with_single_cert_and_kx_groups(
+        vec![CERTIFIED_KEY.cert.der().clone()],
+        PrivateKeyDer::Pkcs8(CERTIFIED_KEY.key_pair.serialize_der().into()),
+        vec![group],
+    )
+    .unwrap();
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(CERTIFIED_KEY.cert.der().clone()).unwrap();
+    let client_config =
+        ClientConfig::with_root_certificates_and_kx_groups(Arc::new(roots), vec![group]).unwrap();
+    assert!(ClientConfig::with_root_certificates_and_kx_groups(
+        Arc::new(rustls::RootCertStore::empty()),
+        Vec::new()
+    )
+    .is_err());
+
+    let mut pair = Pair::new(Default::default(), server_config);
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+    let hd = pair
+        .client_conn_mut(client_ch)
+        .crypto_session()
+        .handshake_data()
+        .unwrap()
+        .downcast::<crate::crypto::rustls::HandshakeData>()
+        .unwrap();
+    assert_eq!(hd.key_exchange_group, Some(rustls::NamedGroup::secp384r1));
+    assert!(hd.cipher_suite.is_some());
+    assert_eq!(
+        hd.peer_certificates,
+        Some(vec![CERTIFIED_KEY.cert.der().clone()])
+    );
+    let hd = pair
+        .server_conn_mut(server_ch)
+        .crypto_session()
+        .handshake_data()
+        .unwrap()
+        .downcast::<crate::crypto::rustls::HandshakeData>()
+        .unwrap();
+    assert_eq!(hd.key_exchange_group, Some(rustls::NamedGroup::secp384r1));
+    assert_eq!(hd.peer_certificates, None);
+}
+
+#[test]
+fn alpn_transport_config() {
+    let _guard = subscribe();
+    let mut server_config = ServerConfig::with_crypto(Arc::new(server_crypto_with_alpn(vec![
+        "foo".into(),
+        "bar".into(),
+    ])));
+    server_config.alpn_transport_config(|protocol| {
+        let mut transport = TransportConfig::default();
+        transport.datagram_receive_buffer_size(None);
+        (protocol == b"bar").then(|| Arc::new(transport))
+    });
+    let mut pair = Pair::new(Arc::new(EndpointConfig::default()), server_config);
+    let client_config = ClientConfig::new(Arc::new(client_crypto_with_alpn(vec![
+        "bar".into(),
+        "baz".into(),
+    ])));
+
+    let client_ch = pair.begin_connect(client_config);
+    pair.drive();
+    let server_ch = pair.server.assert_accept();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::HandshakeDataReady)
+    );
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Connected)
+    );
+    assert_eq!(pair.client_datagrams(client_ch).max_size(), None);
+    assert!(pair.server_datagrams(server_ch).max_size().is_some());
+}
+
+#[test]
+fn client_hello() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let client_config = ClientConfig::new(Arc::new(client_crypto_with_alpn(vec![
+        "bar".into(),
+        "quux".into(),
+    ])));
+
+    pair.server.incoming_connection_behavior = IncomingConnectionBehavior::Wait;
+    let client_ch = pair.begin_connect(client_config);
+    pair.drive();
+    let incoming = pair.server.waiting_incoming.pop().unwrap();
+    let hello = incoming.client_hello().unwrap();
+    assert_eq!(hello.server_name.as_deref(), Some("localhost"));
+    assert_eq!(hello.alpn_protocols, [&b"bar"[..], &b"quux"[..]]);
+    assert!(hello.transport_parameters.is_some());
+
+    // Inspecting the ClientHello leaves the connection attempt intact
+    let server_config =
+        ServerConfig::with_crypto(Arc::new(server_crypto_with_alpn(vec!["quux".into()])));
+    let now = pair.time;
+    let (server_ch, server_conn) = pair
+        .server
+        .endpoint
+        .accept(
+            incoming,
+            now,
+            &mut Vec::new(),
+            Some(Arc::new(server_config)),
+        )
+        .unwrap();
+    pair.server.connections.insert(server_ch, server_conn);
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::HandshakeDataReady)
+    );
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Connected)
+    );
+    let hd = pair
+        .client_conn_mut(client_ch)
+        .crypto_session()
+        .handshake_data()
+        .unwrap()
+        .downcast::<crate::crypto::rustls::HandshakeData>()
+        .unwrap();
+    assert_eq!(hd.protocol.unwrap(), &b"quux"[..]);
+}
+
 #[test]
 fn server_alpn_unset() {
     let _guard = subscribe();
@@ -879,6 +1795,83 @@ fn alpn_mismatch() {
     );
 }
 
+#[test]
+fn deferred_handshake() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.server.incoming_connection_behavior = IncomingConnectionBehavior::Wait;
+    let client_ch = pair.begin_connect(client_config());
+    pair.drive();
+
+    let incoming = pair.server.waiting_incoming.pop().unwrap();
+    let now = pair.time;
+    let (server_ch, mut server_conn) = pair
+        .server
+        .endpoint
+        .accept_deferred(incoming, now, &mut Vec::new(), None)
+        .unwrap();
+    assert!(server_conn.has_deferred_handshake());
+    // Nothing to send before the client's Initial packet is processed
+    assert!(server_conn.poll_transmit(now, 1, &mut Vec::new()).is_none());
+    server_conn.handle_deferred_handshake(now);
+    assert!(!server_conn.has_deferred_handshake());
+    pair.server.connections.insert(server_ch, server_conn);
+    pair.drive();
+
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::HandshakeDataReady)
+    );
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Connected)
+    );
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::HandshakeDataReady)
+    );
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Connected)
+    );
+}
+
+#[test]
+fn deferred_handshake_failure() {
+    let _guard = subscribe();
+    let server_config =
+        ServerConfig::with_crypto(Arc::new(server_crypto_with_alpn(vec!["foo".into()])));
+    let mut pair = Pair::new(Arc::new(EndpointConfig::default()), server_config);
+    pair.server.incoming_connection_behavior = IncomingConnectionBehavior::Wait;
+    let client_ch = pair.begin_connect(ClientConfig::new(Arc::new(client_crypto_with_alpn(vec![
+        "bar".into(),
+    ]))));
+    pair.drive();
+
+    let incoming = pair.server.waiting_incoming.pop().unwrap();
+    let now = pair.time;
+    // The mismatch is only found by the deferred handshake
+    let (server_ch, mut server_conn) = pair
+        .server
+        .endpoint
+        .accept_deferred(incoming, now, &mut Vec::new(), None)
+        .unwrap();
+    server_conn.handle_deferred_handshake(now);
+    assert!(server_conn.is_closed());
+    pair.server.connections.insert(server_ch, server_conn);
+    pair.drive();
+
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::ConnectionLost { reason: ConnectionError::ConnectionClosed(err) }) if err.error_code == TransportErrorCode::crypto(0x78)
+    );
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::ConnectionLost { reason: ConnectionError::TransportError(err) }) if err.code == TransportErrorCode::crypto(0x78)
+    );
+    assert_eq!(pair.server.endpoint.open_connections(), 0);
+}
+
 #[test]
 fn stream_id_limit() {
     let _guard = subscribe();
@@ -1002,22 +1995,190 @@ fn key_update_simple() {
     info!("initiating key update");
     pair.client_conn_mut(client_ch).initiate_key_update();
 
-    const MSG2: &[u8] = b"hello2";
-    pair.client_send(client_ch, s).write(MSG2).unwrap();
+    const MSG2: &[u8] = b"hello2";
+    pair.client_send(client_ch, s).write(MSG2).unwrap();
+    pair.drive();
+
+    assert_matches!(pair.server_conn_mut(server_ch).poll(), Some(Event::Stream(StreamEvent::Readable { id })) if id == s);
+    assert_matches!(pair.server_conn_mut(server_ch).poll(), None);
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(false).unwrap();
+    assert_matches!(
+        chunks.next(usize::MAX),
+        Ok(Some(chunk)) if chunk.offset == 6 && chunk.bytes == MSG2
+    );
+    let _ = chunks.finalize();
+
+    assert_eq!(pair.client_conn_mut(client_ch).lost_packets(), 0);
+    assert_eq!(pair.server_conn_mut(server_ch).lost_packets(), 0);
+}
+
+#[test]
+fn key_update_phase() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    let key_phase = pair.client_conn_mut(client_ch).key_phase();
+    assert_eq!(pair.server_conn_mut(server_ch).key_phase(), key_phase);
+
+    pair.client_conn_mut(client_ch).initiate_key_update();
+    // The peer notices the update when it receives a packet protected with the new keys
+    pair.client_conn_mut(client_ch).ping();
+    pair.drive();
+    assert_eq!(pair.client_conn_mut(client_ch).key_phase(), key_phase + 1);
+    assert_eq!(pair.server_conn_mut(server_ch).key_phase(), key_phase + 1);
+}
+
+#[test]
+fn key_update_idle() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    let key_phase = pair.client_conn_mut(client_ch).key_phase();
+    // Repeated requests are coalesced until the update is performed
+    pair.client_conn_mut(client_ch).initiate_key_update();
+    pair.client_conn_mut(client_ch).initiate_key_update();
+    pair.drive();
+    assert_eq!(pair.client_conn_mut(client_ch).key_phase(), key_phase + 1);
+    pair.client_conn_mut(client_ch).initiate_key_update();
+    pair.drive();
+    assert_eq!(pair.client_conn_mut(client_ch).key_phase(), key_phase + 2);
+    assert_eq!(pair.server_conn_mut(server_ch).key_phase(), key_phase + 2);
+    assert_eq!(pair.client_conn_mut(client_ch).lost_packets(), 0);
+}
+
+#[test]
+fn key_update_interval() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let mut client_config = client_config();
+    Arc::get_mut(&mut client_config.transport)
+        .unwrap()
+        .key_update_interval(Some(5));
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    for _ in 0..20 {
+        pair.client_send(client_ch, s).write(&[42; 1024]).unwrap();
+        pair.drive();
+    }
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+
+    let key_phase = pair.client_conn_mut(client_ch).key_phase();
+    assert!(key_phase >= 3, "only {key_phase} key updates");
+    assert_eq!(pair.server_conn_mut(server_ch).key_phase(), key_phase);
+    assert_eq!(pair.client_conn_mut(client_ch).lost_packets(), 0);
+}
+
+#[test]
+fn key_update_within_transmit() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let mut client_config = client_config();
+    Arc::get_mut(&mut client_config.transport)
+        .unwrap()
+        .key_update_interval(Some(3));
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+
+    // Large enough for transmits of several packets, which straddle key updates
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    const LEN: usize = 64 * 1024;
+    let msg = (0..LEN).map(|i| i as u8).collect::<Vec<_>>();
+    let mut written = 0;
+    while written < LEN {
+        written += pair
+            .client_send(client_ch, s)
+            .write(&msg[written..])
+            .unwrap();
+        pair.drive();
+    }
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+
+    let key_phase = pair.client_conn_mut(client_ch).key_phase();
+    assert!(key_phase >= 3, "only {key_phase} key updates");
+    assert_eq!(pair.server_conn_mut(server_ch).key_phase(), key_phase);
+    assert_eq!(pair.client_conn_mut(client_ch).lost_packets(), 0);
+
+    assert_matches!(
+        pair.server_streams(server_ch).accept(Dir::Uni),
+        Some(stream) if stream == s
+    );
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(true).unwrap();
+    let mut received = Vec::new();
+    while let Ok(Some(chunk)) = chunks.next(usize::MAX) {
+        received.extend_from_slice(&chunk.bytes);
+    }
+    assert_eq!(received, msg);
+    let _ = chunks.finalize();
+}
+
+#[test]
+fn encrypt_batches() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let largest_batch = Arc::new(AtomicUsize::new(0));
+    let client_config = ClientConfig::new(Arc::new(BatchRecorder::new(largest_batch.clone())));
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    const LEN: usize = 64 * 1024;
+    let msg = (0..LEN).map(|i| i as u8).collect::<Vec<_>>();
+    assert_eq!(pair.client_send(client_ch, s).write(&msg).unwrap(), LEN);
+    pair.client_send(client_ch, s).finish().unwrap();
     pair.drive();
 
-    assert_matches!(pair.server_conn_mut(server_ch).poll(), Some(Event::Stream(StreamEvent::Readable { id })) if id == s);
-    assert_matches!(pair.server_conn_mut(server_ch).poll(), None);
-    let mut recv = pair.server_recv(server_ch, s);
-    let mut chunks = recv.read(false).unwrap();
+    // Transmits of several datagrams are encrypted together
+    let largest_batch = largest_batch.load(Ordering::Relaxed);
+    assert!(
+        largest_batch > 1,
+        "largest batch had {largest_batch} packets"
+    );
+
     assert_matches!(
-        chunks.next(usize::MAX),
-        Ok(Some(chunk)) if chunk.offset == 6 && chunk.bytes == MSG2
+        pair.server_streams(server_ch).accept(Dir::Uni),
+        Some(stream) if stream == s
     );
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(true).unwrap();
+    let mut received = Vec::new();
+    while let Ok(Some(chunk)) = chunks.next(usize::MAX) {
+        received.extend_from_slice(&chunk.bytes);
+    }
+    assert_eq!(received, msg);
     let _ = chunks.finalize();
+}
 
-    assert_eq!(pair.client_conn_mut(client_ch).lost_packets(), 0);
-    assert_eq!(pair.server_conn_mut(server_ch).lost_packets(), 0);
+#[test]
+fn chaos_protection() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let mut client_config = client_config();
+    Arc::get_mut(&mut client_config.transport)
+        .unwrap()
+        .chaos_protection(true);
+    pair.server.incoming_connection_behavior = IncomingConnectionBehavior::Wait;
+    let client_ch = pair.begin_connect(client_config);
+    pair.drive();
+    // The scrambled ClientHello is reassembled for inspection
+    let incoming = pair.server.waiting_incoming.pop().unwrap();
+    let hello = incoming.client_hello().unwrap();
+    assert_eq!(hello.server_name.as_deref(), Some("localhost"));
+    assert!(hello.transport_parameters.is_some());
+
+    let now = pair.time;
+    let (server_ch, server_conn) = pair
+        .server
+        .endpoint
+        .accept(incoming, now, &mut Vec::new(), None)
+        .unwrap();
+    pair.server.connections.insert(server_ch, server_conn);
+    pair.drive();
+    assert!(!pair.client_conn_mut(client_ch).is_handshaking());
+    // An Initial and a Handshake CRYPTO frame would be sent without chaos protection
+    assert!(pair.client_conn_mut(client_ch).stats().frame_tx.crypto > 2);
 }
 
 #[test]
@@ -1254,6 +2415,114 @@ fn server_hs_retransmit() {
     );
 }
 
+#[test]
+fn client_initiated_migration() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    pair.drive();
+
+    let now = pair.time;
+    assert_matches!(
+        pair.server_conn_mut(server_ch).migrate_local(now, None),
+        Err(MigrateError::NotClient)
+    );
+
+    let local_ip = Ipv4Addr::new(127, 0, 0, 1).into();
+    pair.client.addr = SocketAddr::new(local_ip, CLIENT_PORTS.lock().unwrap().next().unwrap());
+    pair.client_conn_mut(client_ch)
+        .migrate_local(now, Some(local_ip))
+        .unwrap();
+    pair.drive();
+    assert_eq!(pair.client_conn_mut(client_ch).local_ip(), Some(local_ip));
+    assert_eq!(
+        pair.server_conn_mut(server_ch).remote_address(),
+        pair.client.addr
+    );
+    assert_eq!(
+        pair.client_conn_mut(client_ch)
+            .stats()
+            .frame_rx
+            .path_response,
+        1
+    );
+
+    // The new path was validated, so it's kept once the validation timer would have expired
+    pair.time += Duration::from_secs(10);
+    let now = pair.time;
+    pair.client_conn_mut(client_ch).handle_timeout(now);
+    assert_eq!(pair.client_conn_mut(client_ch).local_ip(), Some(local_ip));
+    pair.client_conn_mut(client_ch).ping();
+    pair.drive();
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+}
+
+#[test]
+fn preferred_address() {
+    let _guard = subscribe();
+    let preferred = SocketAddrV6::new(
+        Ipv6Addr::LOCALHOST,
+        SERVER_PORTS.lock().unwrap().next().unwrap(),
+        0,
+        0,
+    );
+    let mut server_config = server_config();
+    server_config.preferred_address(None, Some(preferred));
+    let mut pair = Pair::new(Default::default(), server_config);
+    let client_ch = pair.begin_connect(client_config());
+
+    // The client migrates once the handshake is confirmed, after which the server is only
+    // reachable at the preferred address
+    while pair.client_conn_mut(client_ch).remote_address() != preferred.into() {
+        assert!(pair.step());
+    }
+    pair.server.addr = preferred.into();
+    pair.drive();
+    let server_ch = pair.server.assert_accept();
+    let mut events = iter::from_fn(|| pair.client_conn_mut(client_ch).poll());
+    assert!(events.any(|event| matches!(
+        event,
+        Event::MigratedToPreferredAddress(addr) if addr == preferred.into()
+    )));
+    assert_eq!(
+        pair.client_conn_mut(client_ch).remote_address(),
+        preferred.into()
+    );
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(b"hello").unwrap();
+    pair.drive();
+    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
+}
+
+#[test]
+fn preferred_address_unreachable() {
+    let _guard = subscribe();
+    let preferred = SocketAddrV6::new(
+        Ipv6Addr::LOCALHOST,
+        SERVER_PORTS.lock().unwrap().next().unwrap(),
+        0,
+        0,
+    );
+    let mut server_config = server_config();
+    server_config.preferred_address(None, Some(preferred));
+    let mut pair = Pair::new(Default::default(), server_config);
+    let server_addr = pair.server.addr;
+    let (client_ch, server_ch) = pair.connect();
+
+    // Validation of the preferred address fails, so the client keeps using the original one
+    assert_eq!(
+        pair.client_conn_mut(client_ch).remote_address(),
+        server_addr
+    );
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(b"hello").unwrap();
+    pair.drive();
+    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
+}
+
 #[test]
 fn migration() {
     let _guard = subscribe();
@@ -1406,15 +2675,380 @@ fn stream_flow_control() {
     );
 }
 
+#[test]
+fn stream_receive_window_override() {
+    let _guard = subscribe();
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            transport: Arc::new(TransportConfig {
+                stream_receive_window: 2000u32.into(),
+                ..TransportConfig::default()
+            }),
+            ..server_config()
+        },
+    );
+    let (client_ch, server_ch) = pair.connect();
+    let msg = vec![0xAB; 5000];
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    assert_eq!(pair.client_send(client_ch, s).write(&msg), Ok(2000));
+    pair.drive();
+
+    // Extending the window lets the client send more without anything being read
+    pair.server_recv(server_ch, s)
+        .set_receive_window(4000u32.into())
+        .unwrap();
+    pair.drive();
+    assert_eq!(pair.client_send(client_ch, s).write(&msg[2000..]), Ok(2000));
+
+    // Shrinking it has no effect
+    pair.server_recv(server_ch, s)
+        .set_receive_window(1000u32.into())
+        .unwrap();
+    pair.drive();
+    assert_eq!(
+        pair.client_send(client_ch, s).write(&msg[4000..]),
+        Err(WriteError::Blocked)
+    );
+}
+
+/// Read everything currently available on `s`, returning the number of bytes read and whether
+/// the read credit should be announced
+fn read_available(pair: &mut Pair, conn: ConnectionHandle, s: StreamId) -> (usize, ShouldTransmit) {
+    read_at_most(pair, conn, s, usize::MAX)
+}
+
+/// Like [`read_available()`], but reading no more than `max` bytes
+fn read_at_most(
+    pair: &mut Pair,
+    conn: ConnectionHandle,
+    s: StreamId,
+    max: usize,
+) -> (usize, ShouldTransmit) {
+    let mut recv = pair.server_recv(conn, s);
+    let mut chunks = recv.read(true).unwrap();
+    let mut read = 0;
+    while read < max {
+        match chunks.next(max - read) {
+            Ok(Some(chunk)) => read += chunk.bytes.len(),
+            _ => break,
+        }
+    }
+    (read, chunks.finalize())
+}
+
+/// Number of `MAX_STREAM_DATA` frames sent by the server so far
+fn max_stream_data_sent(pair: &mut Pair, conn: ConnectionHandle) -> u64 {
+    pair.server_conn_mut(conn).stats().frame_tx.max_stream_data
+}
+
+#[test]
+fn stream_window_threshold() {
+    let _guard = subscribe();
+    let mut transport = TransportConfig::default();
+    let mut update = StreamWindowUpdateConfig::default();
+    update.threshold(0.5);
+    transport
+        .stream_receive_window(2000u32.into())
+        .stream_window_update(update);
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            transport: Arc::new(transport),
+            ..server_config()
+        },
+    );
+    let (client_ch, server_ch) = pair.connect();
+    let msg = vec![0xAB; 5000];
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    assert_eq!(pair.client_send(client_ch, s).write(&msg), Ok(2000));
+    pair.drive();
+    let sent = max_stream_data_sent(&mut pair, server_ch);
+
+    // Reading less than half the window isn't worth an update
+    let (read, transmit) = read_at_most(&mut pair, server_ch, s, 900);
+    assert_eq!(read, 900);
+    assert!(!transmit.should_transmit());
+    pair.drive();
+    assert_eq!(max_stream_data_sent(&mut pair, server_ch), sent);
+    assert_eq!(
+        pair.client_send(client_ch, s).write(&msg),
+        Err(WriteError::Blocked)
+    );
+
+    // Crossing the threshold announces everything read so far
+    let (read, transmit) = read_at_most(&mut pair, server_ch, s, 200);
+    assert_eq!(read, 200);
+    assert!(transmit.should_transmit());
+    pair.drive();
+    assert_eq!(max_stream_data_sent(&mut pair, server_ch), sent + 1);
+    assert_eq!(pair.client_send(client_ch, s).write(&msg), Ok(1100));
+    pair.drive();
+
+    // The stream keeps flowing as the rest is read
+    assert_eq!(read_available(&mut pair, server_ch, s).0, 2000);
+    pair.drive();
+    assert_eq!(max_stream_data_sent(&mut pair, server_ch), sent + 2);
+    assert_eq!(pair.client_send(client_ch, s).write(&msg), Ok(2000));
+}
+
+#[test]
+fn stream_window_min_delta_capped() {
+    let _guard = subscribe();
+    let mut transport = TransportConfig::default();
+    let mut update = StreamWindowUpdateConfig::default();
+    update.min_delta(5000u32.into());
+    transport
+        .stream_receive_window(1000u32.into())
+        .stream_window_update(update);
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            transport: Arc::new(transport),
+            ..server_config()
+        },
+    );
+    let (client_ch, server_ch) = pair.connect();
+    let msg = vec![0xAB; 5000];
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    for _ in 0..3 {
+        assert_eq!(pair.client_send(client_ch, s).write(&msg), Ok(1000));
+        pair.drive();
+        let sent = max_stream_data_sent(&mut pair, server_ch);
+
+        // Held back until the whole window was read, rather than until `min_delta` bytes were
+        let (read, transmit) = read_at_most(&mut pair, server_ch, s, 999);
+        assert_eq!(read, 999);
+        assert!(!transmit.should_transmit());
+        pair.drive();
+        assert_eq!(max_stream_data_sent(&mut pair, server_ch), sent);
+        assert_eq!(
+            pair.client_send(client_ch, s).write(&msg),
+            Err(WriteError::Blocked)
+        );
+
+        let (read, transmit) = read_at_most(&mut pair, server_ch, s, 1);
+        assert_eq!(read, 1);
+        assert!(transmit.should_transmit());
+        pair.drive();
+        assert_eq!(max_stream_data_sent(&mut pair, server_ch), sent + 1);
+    }
+    assert_eq!(pair.client_send(client_ch, s).write(&msg), Ok(1000));
+}
+
+#[test]
+fn stream_window_app_driven() {
+    let _guard = subscribe();
+    let mut transport = TransportConfig::default();
+    let mut update = StreamWindowUpdateConfig::default();
+    update.app_driven(true);
+    transport
+        .stream_receive_window(2000u32.into())
+        .stream_window_update(update);
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            transport: Arc::new(transport),
+            ..server_config()
+        },
+    );
+    let (client_ch, server_ch) = pair.connect();
+    let msg = vec![0xAB; 5000];
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    assert_eq!(pair.client_send(client_ch, s).write(&msg), Ok(2000));
+    pair.drive();
+
+    // Reading doesn't extend credit
+    assert_eq!(read_available(&mut pair, server_ch, s).0, 2000);
+    pair.drive();
+    assert_eq!(
+        pair.client_send(client_ch, s).write(&msg[2000..]),
+        Err(WriteError::Blocked)
+    );
+
+    // Credit is extended by exactly as much as the application grants
+    pair.server_recv(server_ch, s)
+        .extend_credit(1000u32.into())
+        .unwrap();
+    pair.drive();
+    assert_eq!(pair.client_send(client_ch, s).write(&msg[2000..]), Ok(1000));
+    assert_eq!(
+        pair.client_send(client_ch, s).write(&msg[3000..]),
+        Err(WriteError::Blocked)
+    );
+}
+
+#[test]
+fn stream_window_piggyback() {
+    let _guard = subscribe();
+    let mut transport = TransportConfig::default();
+    let mut update = StreamWindowUpdateConfig::default();
+    update.piggyback(true);
+    transport
+        .stream_receive_window(2000u32.into())
+        .stream_window_update(update);
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            transport: Arc::new(transport),
+            ..server_config()
+        },
+    );
+    let (client_ch, server_ch) = pair.connect();
+    let msg = vec![0xAB; 5000];
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    assert_eq!(pair.client_send(client_ch, s).write(&msg[..500]), Ok(500));
+    pair.drive();
+
+    // With plenty of credit left, the update waits for another packet
+    let (read, transmit) = read_available(&mut pair, server_ch, s);
+    assert_eq!(read, 500);
+    assert!(!transmit.should_transmit());
+    pair.drive();
+    let sent = pair
+        .server_conn_mut(server_ch)
+        .stats()
+        .frame_tx
+        .max_stream_data;
+
+    // Acknowledging more data carries it along
+    assert_eq!(pair.client_send(client_ch, s).write(&msg[..100]), Ok(100));
+    pair.drive();
+    assert_eq!(
+        pair.server_conn_mut(server_ch)
+            .stats()
+            .frame_tx
+            .max_stream_data,
+        sent + 1
+    );
+    assert_eq!(pair.client_send(client_ch, s).write(&msg), Ok(1900));
+    pair.drive();
+
+    // Once the peer runs low on credit, the update is sent right away
+    assert_eq!(read_available(&mut pair, server_ch, s).0, 2000);
+    assert_eq!(
+        pair.client_send(client_ch, s).write(&msg),
+        Err(WriteError::Blocked)
+    );
+    pair.drive();
+    assert_eq!(pair.client_send(client_ch, s).write(&msg), Ok(2000));
+}
+
+#[test]
+#[cfg(feature = "fuzzing")]
+fn fuzzing_entry_points() {
+    use crate::fuzzing::*;
+
+    let _guard = subscribe();
+    fuzz_decode_packet(PacketParams {
+        local_cid_len: 8,
+        buf: BytesMut::from(&[0xc0, 0, 0, 0, 1, 8][..]),
+        grease_quic_bit: false,
+    });
+    // PING, PADDING, HANDSHAKE_DONE, then a truncated ACK
+    fuzz_decode_frames(&[0x01, 0x00, 0x1e, 0x02, 0x05]);
+    fuzz_connection_event(ConnectionEventInput {
+        datagrams: vec![
+            // Version Negotiation offering an unsupported version
+            (0, vec![0x80, 0, 0, 0, 0, 0, 0, 0x0a, 0x0a, 0x0a, 0x0a]),
+            (1000, vec![0x40; 32]),
+        ],
+    });
+}
+
 #[test]
 fn conn_flow_control() {
     test_flow_control(
         TransportConfig {
             receive_window: 2000u32.into(),
             ..TransportConfig::default()
-        },
-        2000,
+        },
+        2000,
+    );
+}
+
+#[test]
+fn max_buffered_bytes_flow_control() {
+    test_flow_control(
+        TransportConfig {
+            max_buffered_bytes: Some(2000),
+            ..TransportConfig::default()
+        },
+        2000,
+    );
+}
+
+#[test]
+fn endpoint_memory_budget() {
+    let _guard = subscribe();
+    const WINDOW: usize = 8192;
+    let mut endpoint_config = EndpointConfig::default();
+    endpoint_config.max_buffered_bytes(Some(WINDOW as u64));
+    let server = ServerConfig {
+        transport: Arc::new(TransportConfig {
+            receive_window: VarInt::from_u32(WINDOW as u32),
+            ..TransportConfig::default()
+        }),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Arc::new(endpoint_config), server);
+    let (client_a, server_a) = pair.connect();
+    let (client_b, server_b) = pair.connect();
+
+    // Each peer uses the initial window, committing the server to twice its budget
+    let mut streams = Vec::new();
+    for client_ch in [client_a, client_b] {
+        let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+        assert_eq!(
+            pair.client_send(client_ch, s).write(&[0; 2 * WINDOW]),
+            Ok(WINDOW)
+        );
+        streams.push(s);
+    }
+    pair.drive();
+    assert_eq!(pair.server.endpoint.buffered_bytes(), 2 * WINDOW as u64);
+
+    // Reading doesn't produce flow control credit while over budget
+    let read = |pair: &mut Pair, ch, s, mut len: usize| {
+        let mut recv = pair.server_recv(ch, s);
+        let mut chunks = recv.read(true).unwrap();
+        while len > 0 {
+            len -= chunks.next(len).unwrap().unwrap().bytes.len();
+        }
+        let _ = chunks.finalize();
+    };
+    assert_eq!(
+        pair.server_streams(server_a).accept(Dir::Uni),
+        Some(streams[0])
+    );
+    assert_eq!(
+        pair.server_streams(server_b).accept(Dir::Uni),
+        Some(streams[1])
     );
+    read(&mut pair, server_a, streams[0], WINDOW / 2);
+    pair.drive();
+    assert_eq!(
+        pair.client_send(client_a, streams[0]).write(&[0; WINDOW]),
+        Err(WriteError::Blocked)
+    );
+
+    // Once back under budget, credit is issued without exceeding it
+    read(&mut pair, server_b, streams[1], WINDOW);
+    pair.drive();
+    let mut written = 0;
+    for (client_ch, s) in [(client_a, streams[0]), (client_b, streams[1])] {
+        if let Ok(n) = pair.client_send(client_ch, s).write(&[0; WINDOW]) {
+            written += n;
+        }
+    }
+    assert_eq!(written, WINDOW / 2);
+    pair.drive();
+    assert!(pair.server.endpoint.buffered_bytes() <= WINDOW as u64);
 }
 
 #[test]
@@ -1536,6 +3170,68 @@ fn keep_alive() {
     }
 }
 
+#[test]
+fn keep_alive_probe() {
+    let _guard = subscribe();
+    const IDLE_TIMEOUT: u64 = 10;
+    let server = ServerConfig {
+        transport: Arc::new(TransportConfig {
+            keep_alive_interval: Some(Duration::from_millis(IDLE_TIMEOUT / 2)),
+            max_idle_timeout: Some(VarInt(IDLE_TIMEOUT)),
+            ..TransportConfig::default()
+        }),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let (client_ch, server_ch) = pair.connect();
+    let s = pair.server_streams(server_ch).open(Dir::Uni).unwrap();
+    pair.server_conn_mut(server_ch)
+        .set_keep_alive_probe(KeepAliveProbe::Datagram(Box::new(|| {
+            Bytes::from_static(b"dgram")
+        })));
+    let end = pair.time + Duration::from_millis(4 * IDLE_TIMEOUT);
+    let mut datagrams = 0;
+    while pair.time < end {
+        if !pair.step() {
+            if let Some(time) = min_opt(pair.client.next_wakeup(), pair.server.next_wakeup()) {
+                pair.time = time;
+            }
+        }
+        while let Some(data) = pair.client_datagrams(client_ch).recv() {
+            assert_eq!(data, &b"dgram"[..]);
+            datagrams += 1;
+        }
+        assert!(!pair.client_conn_mut(client_ch).is_closed());
+        assert!(!pair.server_conn_mut(server_ch).is_closed());
+    }
+    assert!(datagrams > 0);
+
+    // Switch to a stream probe
+    pair.server_conn_mut(server_ch)
+        .set_keep_alive_probe(KeepAliveProbe::Stream(
+            s,
+            Box::new(|| Bytes::from_static(b"stream")),
+        ));
+    let end = pair.time + Duration::from_millis(4 * IDLE_TIMEOUT);
+    while pair.time < end {
+        if !pair.step() {
+            if let Some(time) = min_opt(pair.client.next_wakeup(), pair.server.next_wakeup()) {
+                pair.time = time;
+            }
+        }
+        assert!(!pair.client_conn_mut(client_ch).is_closed());
+    }
+    assert_matches!(
+        pair.client_streams(client_ch).accept(Dir::Uni),
+        Some(stream) if stream == s
+    );
+    let mut recv = pair.client_recv(client_ch, s);
+    let mut chunks = recv.read(true).unwrap();
+    let chunk = chunks.next(usize::MAX).unwrap().unwrap();
+    assert!(chunk.bytes.starts_with(b"stream"));
+    let _ = chunks.finalize();
+}
+
 #[test]
 fn cid_rotation() {
     let _guard = subscribe();
@@ -1663,146 +3359,595 @@ fn finish_stream_flow_control_reordered() {
 
     assert_matches!(
         pair.client_conn_mut(client_ch).poll(),
-        Some(Event::Stream(StreamEvent::Finished { id })) if id == s
+        Some(Event::Stream(StreamEvent::Finished { id })) if id == s
+    );
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
+    );
+    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
+
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(false).unwrap();
+    assert_matches!(chunks.next(usize::MAX), Ok(None));
+    let _ = chunks.finalize();
+}
+
+#[test]
+fn handshake_1rtt_handling() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let client_ch = pair.begin_connect(client_config());
+    pair.drive_client();
+    pair.drive_server();
+    let server_ch = pair.server.assert_accept();
+    // Server now has 1-RTT keys, but remains in Handshake state until the TLS CFIN has
+    // authenticated the client. Delay the final client handshake flight so that doesn't happen yet.
+    pair.client.drive(pair.time, pair.server.addr);
+    pair.client.delay_outbound();
+
+    // Send some 1-RTT data which will be received first.
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    const MSG: &[u8] = b"hello";
+    pair.client_send(client_ch, s).write(MSG).unwrap();
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.client.drive(pair.time, pair.server.addr);
+
+    // Add the handshake flight back on.
+    pair.client.finish_delay();
+
+    pair.drive();
+
+    assert!(pair.client_conn_mut(client_ch).lost_packets() != 0);
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(false).unwrap();
+    assert_matches!(
+        chunks.next(usize::MAX),
+        Ok(Some(chunk)) if chunk.offset == 0 && chunk.bytes == MSG
+    );
+    let _ = chunks.finalize();
+}
+
+#[test]
+fn stop_before_finish() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    const MSG: &[u8] = b"hello";
+    pair.client_send(client_ch, s).write(MSG).unwrap();
+    pair.drive();
+
+    info!("stopping stream");
+    const ERROR: VarInt = VarInt(42);
+    pair.server_recv(server_ch, s).stop(ERROR).unwrap();
+    pair.drive();
+
+    assert_matches!(
+        pair.client_send(client_ch, s).finish(),
+        Err(FinishError::Stopped(ERROR))
+    );
+}
+
+#[test]
+fn stop_during_finish() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    const MSG: &[u8] = b"hello";
+    pair.client_send(client_ch, s).write(MSG).unwrap();
+    pair.drive();
+
+    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
+    info!("stopping and finishing stream");
+    const ERROR: VarInt = VarInt(42);
+    pair.server_recv(server_ch, s).stop(ERROR).unwrap();
+    pair.drive_server();
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive_client();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Stopped { id, error_code: ERROR })) if id == s
+    );
+}
+
+// Ensure we can recover from loss of tail packets when the congestion window is full
+#[test]
+fn congested_tail_loss() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+
+    const TARGET: u64 = 2048;
+    assert!(pair.client_conn_mut(client_ch).congestion_window() > TARGET);
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    // Send data without receiving ACKs until the congestion state falls below target
+    while pair.client_conn_mut(client_ch).congestion_window() > TARGET {
+        let n = pair.client_send(client_ch, s).write(&[42; 1024]).unwrap();
+        assert_eq!(n, 1024);
+        pair.drive_client();
+    }
+    assert!(!pair.server.inbound.is_empty());
+    pair.server.inbound.clear();
+    // Ensure that the congestion state recovers after retransmits occur and are ACKed
+    info!("recovering");
+    pair.drive();
+    assert!(pair.client_conn_mut(client_ch).congestion_window() > TARGET);
+    pair.client_send(client_ch, s).write(&[42; 1024]).unwrap();
+}
+
+#[test]
+fn bbr3_tail_loss() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let mut client_config = client_config();
+    Arc::get_mut(&mut client_config.transport)
+        .unwrap()
+        .congestion_controller_factory(Arc::new(crate::congestion::Bbr3Config::default()));
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+
+    const CHUNKS: usize = 256;
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    for i in 0..CHUNKS {
+        let n = pair.client_send(client_ch, s).write(&[42; 1024]).unwrap();
+        assert_eq!(n, 1024);
+        if i % 16 == 0 {
+            pair.drive_client();
+            // Lose a window's worth of packets once the transfer is underway
+            if i == 64 {
+                pair.server.inbound.clear();
+            }
+        }
+        pair.drive();
+    }
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+    assert_eq!(pair.client_conn_mut(client_ch).bytes_in_flight(), 0);
+    assert_ne!(pair.client_conn_mut(client_ch).stats().path.lost_packets, 0);
+
+    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(true).unwrap();
+    let mut received = 0;
+    while let Ok(Some(chunk)) = chunks.next(usize::MAX) {
+        received += chunk.bytes.len();
+    }
+    let _ = chunks.finalize();
+    assert_eq!(received, CHUNKS * 1024);
+}
+
+#[test]
+fn prague_ce_response() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.latency = Duration::from_millis(10); // Let round trips take time
+    let mut client_config = client_config();
+    Arc::get_mut(&mut client_config.transport)
+        .unwrap()
+        .congestion_controller_factory(Arc::new(crate::congestion::PragueConfig::default()));
+    let (client_ch, _) = pair.connect_with(client_config);
+    assert!(pair.client_conn_mut(client_ch).stats().path.ecn_validated);
+
+    // Grow the window over many unmarked round trips
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    for _ in 0..64 {
+        pair.client_send(client_ch, s).write(&[42; 4096]).unwrap();
+        pair.drive_client();
+        assert!(pair
+            .server
+            .inbound
+            .iter()
+            .all(|(_, ecn, _)| *ecn == Some(EcnCodepoint::Ect1)));
+        pair.drive();
+    }
+    let before = pair.client_conn_mut(client_ch).congestion_window();
+
+    pair.client_conn_mut(client_ch).ping();
+    pair.congestion_experienced = true;
+    pair.drive_client();
+    pair.congestion_experienced = false;
+    pair.drive();
+
+    // A single mark after a long unmarked period only slightly reduces the window
+    let after = pair.client_conn_mut(client_ch).congestion_window();
+    assert!(after < before);
+    assert!(after > before * 3 / 4, "{after} <= 3/4 * {before}");
+    assert_eq!(pair.client_conn_mut(client_ch).stats().path.ecn_ce_marks, 1);
+}
+
+#[test]
+fn careful_resume() {
+    let _guard = subscribe();
+    let window_after_handshake = |hint| {
+        let mut pair = Pair::default();
+        pair.latency = Duration::from_millis(10); // Let round trips take time
+        let mut client_config = client_config();
+        Arc::get_mut(&mut client_config.transport)
+            .unwrap()
+            .initial_rtt_and_cwnd_hint(hint);
+        let (client_ch, _) = pair.connect_with(client_config);
+        let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+        pair.client_send(client_ch, s).write(&[42; 4096]).unwrap();
+        pair.drive();
+        pair.client_conn_mut(client_ch).congestion_window()
+    };
+
+    let cold = window_after_handshake(None);
+    let resumed = window_after_handshake(Some((Duration::from_millis(20), 1_000_000)));
+    assert!(resumed >= 500_000, "{resumed} < 500000");
+    assert!(cold < 100_000, "{cold} >= 100000");
+
+    // A hint from a path with a much lower RTT is ignored
+    let mismatched = window_after_handshake(Some((Duration::from_millis(1), 1_000_000)));
+    assert_eq!(mismatched, cold);
+}
+
+#[test]
+fn hystart_slow_start_exit() {
+    use crate::congestion::{Controller, Cubic, CubicConfig};
+    let _guard = subscribe();
+    const MTU: u64 = 1200;
+    const PACKETS_PER_ROUND: u64 = 16;
+    let base_rtt = Duration::from_millis(40);
+
+    // Slow start for several rounds, the RTT rising by `increase` after the third
+    let window_after_rounds = |config: CubicConfig, increase: Duration| {
+        let now = Instant::now();
+        let mut cc = Cubic::new(Arc::new(config), now, MTU as u16);
+        let mut rtt = crate::connection::RttEstimator::new(base_rtt);
+        let mut pn = 0;
+        for round in 0..12 {
+            let sample = match round {
+                0..=2 => base_rtt,
+                _ => base_rtt + increase,
+            };
+            for _ in 0..PACKETS_PER_ROUND {
+                cc.on_sent(now, MTU, pn);
+                pn += 1;
+            }
+            for _ in 0..PACKETS_PER_ROUND {
+                cc.on_ack(now, now, MTU, false, &rtt);
+                rtt.update(Duration::ZERO, sample);
+            }
+            cc.on_end_acks(now, 0, false, Some(pn - 1));
+        }
+        cc.window()
+    };
+
+    let steady = window_after_rounds(CubicConfig::default(), Duration::ZERO);
+    let disabled = {
+        let mut config = CubicConfig::default();
+        config.hystart(false);
+        window_after_rounds(config, Duration::from_millis(20))
+    };
+    let exited = window_after_rounds(CubicConfig::default(), Duration::from_millis(20));
+    assert_eq!(steady, disabled);
+    // Growth slows to a quarter once the RTT increase is noticed
+    assert!(exited < steady / 2, "{exited} >= {steady} / 2");
+}
+
+#[test]
+fn probe_path() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.latency = Duration::from_millis(10); // Let round trips take time
+    let (client_ch, _) = pair.connect();
+
+    pair.client_conn_mut(client_ch).probe_path();
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::PathProbed { rtt: Some(rtt) }) if rtt == Duration::from_millis(20)
     );
     assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+
+    // Unanswered challenges are retried, then reported
+    pair.client_conn_mut(client_ch).probe_path();
+    let mut challenges = 0;
+    loop {
+        let sent = pair
+            .client_conn_mut(client_ch)
+            .stats()
+            .frame_tx
+            .path_challenge;
+        if !pair.step() {
+            break;
+        }
+        pair.server.inbound.clear();
+        challenges += pair
+            .client_conn_mut(client_ch)
+            .stats()
+            .frame_tx
+            .path_challenge
+            - sent;
+    }
+    assert_eq!(challenges, 3);
     assert_matches!(
-        pair.server_conn_mut(server_ch).poll(),
-        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::PathProbed { rtt: None })
     );
-    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
-
-    let mut recv = pair.server_recv(server_ch, s);
-    let mut chunks = recv.read(false).unwrap();
-    assert_matches!(chunks.next(usize::MAX), Ok(None));
-    let _ = chunks.finalize();
+    assert!(!pair.client_conn_mut(client_ch).is_closed());
 }
 
 #[test]
-fn handshake_1rtt_handling() {
+fn datagram_send_recv() {
     let _guard = subscribe();
     let mut pair = Pair::default();
-    let client_ch = pair.begin_connect(client_config());
-    pair.drive_client();
-    pair.drive_server();
-    let server_ch = pair.server.assert_accept();
-    // Server now has 1-RTT keys, but remains in Handshake state until the TLS CFIN has
-    // authenticated the client. Delay the final client handshake flight so that doesn't happen yet.
-    pair.client.drive(pair.time, pair.server.addr);
-    pair.client.delay_outbound();
-
-    // Send some 1-RTT data which will be received first.
-    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
-    const MSG: &[u8] = b"hello";
-    pair.client_send(client_ch, s).write(MSG).unwrap();
-    pair.client_send(client_ch, s).finish().unwrap();
-    pair.client.drive(pair.time, pair.server.addr);
-
-    // Add the handshake flight back on.
-    pair.client.finish_delay();
+    let (client_ch, server_ch) = pair.connect();
+    assert_matches!(pair.server_conn_mut(server_ch).poll(), None);
+    assert_matches!(pair.client_datagrams(client_ch).max_size(), Some(x) if x > 0);
 
+    const DATA: &[u8] = b"whee";
+    pair.client_datagrams(client_ch)
+        .send(DATA.into(), true)
+        .unwrap();
     pair.drive();
-
-    assert!(pair.client_conn_mut(client_ch).lost_packets() != 0);
-    let mut recv = pair.server_recv(server_ch, s);
-    let mut chunks = recv.read(false).unwrap();
     assert_matches!(
-        chunks.next(usize::MAX),
-        Ok(Some(chunk)) if chunk.offset == 0 && chunk.bytes == MSG
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::DatagramReceived)
     );
-    let _ = chunks.finalize();
+    assert_eq!(pair.server_datagrams(server_ch).recv().unwrap(), DATA);
+    assert_matches!(pair.server_datagrams(server_ch).recv(), None);
 }
 
 #[test]
-fn stop_before_finish() {
+fn datagram_expiry() {
     let _guard = subscribe();
     let mut pair = Pair::default();
     let (client_ch, server_ch) = pair.connect();
+    assert_matches!(pair.server_conn_mut(server_ch).poll(), None);
 
-    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
-    const MSG: &[u8] = b"hello";
-    pair.client_send(client_ch, s).write(MSG).unwrap();
+    let now = pair.time;
+    pair.client_datagrams(client_ch)
+        .send_with_deadline(b"stale"[..].into(), true, now + Duration::from_millis(10))
+        .unwrap();
+    pair.client_datagrams(client_ch)
+        .send_with_deadline(b"fresh"[..].into(), true, now + Duration::from_secs(1))
+        .unwrap();
+    pair.time += Duration::from_millis(20);
     pair.drive();
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::DatagramReceived)
+    );
+    assert_eq!(
+        pair.server_datagrams(server_ch).recv().unwrap(),
+        &b"fresh"[..]
+    );
+    assert_matches!(pair.server_datagrams(server_ch).recv(), None);
+}
 
-    info!("stopping stream");
-    const ERROR: VarInt = VarInt(42);
-    pair.server_recv(server_ch, s).stop(ERROR).unwrap();
+#[test]
+fn datagram_priority_drop_policy() {
+    let _guard = subscribe();
+    let mut client_config = client_config();
+    Arc::get_mut(&mut client_config.transport)
+        .unwrap()
+        .datagram_send_buffer_size(30)
+        .datagram_queue_policy(DatagramDropPolicy::DropLowestPriority);
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+    assert_matches!(pair.server_conn_mut(server_ch).poll(), None);
+
+    let mut datagrams = pair.client_datagrams(client_ch);
+    for (data, priority) in [
+        (b"low-00000", 0),
+        (b"high-0000", 2),
+        (b"mid-00000", 1),
+        (b"mid-11111", 1),
+        // Displaces the only low priority datagram
+        (b"mid-22222", 1),
+        // Lower priority than anything queued, so discarded itself
+        (b"low-11111", 0),
+    ] {
+        datagrams
+            .send_with_priority(data[..].into(), true, priority)
+            .unwrap();
+    }
     pair.drive();
 
     assert_matches!(
-        pair.client_send(client_ch, s).finish(),
-        Err(FinishError::Stopped(ERROR))
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::DatagramReceived)
     );
+    let mut datagrams = pair.server_datagrams(server_ch);
+    for expected in [b"high-0000", b"mid-00000", b"mid-11111", b"mid-22222"] {
+        assert_eq!(datagrams.recv().unwrap(), &expected[..]);
+    }
+    assert_matches!(datagrams.recv(), None);
 }
 
 #[test]
-fn stop_during_finish() {
+fn datagram_tracked() {
     let _guard = subscribe();
     let mut pair = Pair::default();
-    let (client_ch, server_ch) = pair.connect();
+    let (client_ch, _) = pair.connect();
+    while pair.client_conn_mut(client_ch).poll().is_some() {}
 
-    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
-    const MSG: &[u8] = b"hello";
-    pair.client_send(client_ch, s).write(MSG).unwrap();
+    let acked = pair
+        .client_datagrams(client_ch)
+        .send_tracked(b"acked"[..].into(), true)
+        .unwrap();
     pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::DatagramAcked(id)) if id == acked
+    );
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
 
-    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
-    info!("stopping and finishing stream");
-    const ERROR: VarInt = VarInt(42);
-    pair.server_recv(server_ch, s).stop(ERROR).unwrap();
-    pair.drive_server();
-    pair.client_send(client_ch, s).finish().unwrap();
+    let lost = pair
+        .client_datagrams(client_ch)
+        .send_tracked(b"lost"[..].into(), true)
+        .unwrap();
     pair.drive_client();
+    pair.server.inbound.clear();
+    // Acknowledgments of later packets reveal the loss
+    for _ in 0..3 {
+        pair.client_datagrams(client_ch)
+            .send(b"filler"[..].into(), true)
+            .unwrap();
+        pair.drive();
+    }
     assert_matches!(
         pair.client_conn_mut(client_ch).poll(),
-        Some(Event::Stream(StreamEvent::Stopped { id, error_code: ERROR })) if id == s
+        Some(Event::DatagramLost(id)) if id == lost
     );
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
 }
 
-// Ensure we can recover from loss of tail packets when the congestion window is full
+#[cfg(feature = "qlog")]
 #[test]
-fn congested_tail_loss() {
+fn qlog_trace() {
+    use std::{io, sync::Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Factory(SharedBuf);
+
+    impl crate::QlogFactory for Factory {
+        fn writer(&self, side: Side, _: ConnectionId) -> Option<Box<dyn io::Write + Send + Sync>> {
+            assert_eq!(side, Side::Client);
+            Some(Box::new(self.0.clone()))
+        }
+    }
+
     let _guard = subscribe();
-    let mut pair = Pair::default();
-    let (client_ch, _) = pair.connect();
+    let buf = SharedBuf::default();
+    let mut client_endpoint_config = EndpointConfig::default();
+    client_endpoint_config.qlog_writer(Arc::new(Factory(buf.clone())));
+    let client = Endpoint::new(Arc::new(client_endpoint_config), None, true, None);
+    let server = Endpoint::new(
+        Arc::new(EndpointConfig::default()),
+        Some(Arc::new(server_config())),
+        true,
+        None,
+    );
+    let mut pair = Pair::new_from_endpoint(client, server);
+    pair.connect();
 
-    const TARGET: u64 = 2048;
-    assert!(pair.client_conn_mut(client_ch).congestion_window() > TARGET);
-    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
-    // Send data without receiving ACKs until the congestion state falls below target
-    while pair.client_conn_mut(client_ch).congestion_window() > TARGET {
-        let n = pair.client_send(client_ch, s).write(&[42; 1024]).unwrap();
-        assert_eq!(n, 1024);
-        pair.drive_client();
+    let trace = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+    let records = trace.split('\x1e').skip(1).collect::<Vec<_>>();
+    assert!(records.iter().all(|r| r.ends_with('\n')));
+    assert!(records[0].contains("\"qlog_format\":\"JSON-SEQ\""));
+    assert!(records[0].contains("\"vantage_point\":{\"type\":\"client\"}"));
+    for name in [
+        "transport:packet_sent",
+        "transport:packet_received",
+        "recovery:metrics_updated",
+    ] {
+        assert!(
+            records[1..].iter().any(|r| r.contains(name)),
+            "no {name} event"
+        );
     }
-    assert!(!pair.server.inbound.is_empty());
-    pair.server.inbound.clear();
-    // Ensure that the congestion state recovers after retransmits occur and are ACKed
-    info!("recovering");
-    pair.drive();
-    assert!(pair.client_conn_mut(client_ch).congestion_window() > TARGET);
-    pair.client_send(client_ch, s).write(&[42; 1024]).unwrap();
 }
 
 #[test]
-fn datagram_send_recv() {
+fn packet_inspector() {
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Inspector {
+        sent: Mutex<Vec<PacketSummary>>,
+        received: Mutex<Vec<PacketSummary>>,
+    }
+
+    impl PacketInspector for Inspector {
+        fn packet_sent(&self, packet: &PacketSummary) {
+            self.sent.lock().unwrap().push(packet.clone());
+        }
+
+        fn packet_received(&self, packet: &PacketSummary) {
+            self.received.lock().unwrap().push(packet.clone());
+        }
+    }
+
     let _guard = subscribe();
-    let mut pair = Pair::default();
-    let (client_ch, server_ch) = pair.connect();
-    assert_matches!(pair.server_conn_mut(server_ch).poll(), None);
-    assert_matches!(pair.client_datagrams(client_ch).max_size(), Some(x) if x > 0);
+    let inspector = Arc::new(Inspector::default());
+    let mut client_endpoint_config = EndpointConfig::default();
+    client_endpoint_config.packet_inspector(inspector.clone());
+    let client = Endpoint::new(Arc::new(client_endpoint_config), None, true, None);
+    let server = Endpoint::new(
+        Arc::new(EndpointConfig::default()),
+        Some(Arc::new(server_config())),
+        true,
+        None,
+    );
+    let mut pair = Pair::new_from_endpoint(client, server);
+    let (client_ch, _) = pair.connect();
 
-    const DATA: &[u8] = b"whee";
-    pair.client_datagrams(client_ch)
-        .send(DATA.into(), true)
-        .unwrap();
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(b"hello").unwrap();
     pair.drive();
-    assert_matches!(
-        pair.server_conn_mut(server_ch).poll(),
-        Some(Event::DatagramReceived)
+
+    let sent = inspector.sent.lock().unwrap();
+    assert_eq!(sent[0].side, Side::Client);
+    assert_eq!(sent[0].ty, PacketType::Initial);
+    let crypto = &sent[0].frames[0];
+    assert_eq!(
+        crypto.to_string(),
+        format!("CRYPTO({} bytes)", crypto.len.unwrap())
+    );
+    let stream = sent
+        .iter()
+        .filter(|packet| packet.ty == PacketType::OneRtt)
+        .flat_map(|packet| &packet.frames)
+        .find(|frame| frame.stream == Some(s))
+        .unwrap();
+    assert_eq!(stream.len, Some(5));
+    assert_eq!(
+        stream.to_string(),
+        "STREAM(client unidirectional stream 0, 5 bytes)"
     );
-    assert_eq!(pair.server_datagrams(server_ch).recv().unwrap(), DATA);
-    assert_matches!(pair.server_datagrams(server_ch).recv(), None);
+
+    let received = inspector.received.lock().unwrap();
+    assert_eq!(received[0].ty, PacketType::Initial);
+    assert!(received
+        .iter()
+        .any(|packet| packet.ty == PacketType::OneRtt && !packet.frames.is_empty()));
+}
+
+#[test]
+fn key_log() {
+    let _guard = subscribe();
+    let path = std::env::temp_dir().join(format!("quinn-key-log-{}", std::process::id()));
+    // The key log is opened when enabled; other configs are built with the variable unset
+    std::env::set_var("SSLKEYLOGFILE", &path);
+    let mut server_config = server_config();
+    server_config.enable_key_log();
+    std::env::remove_var("SSLKEYLOGFILE");
+
+    let mut pair = Pair::new(Default::default(), server_config);
+    pair.connect();
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    for label in [
+        "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
+        "SERVER_HANDSHAKE_TRAFFIC_SECRET",
+        "CLIENT_TRAFFIC_SECRET_0",
+        "SERVER_TRAFFIC_SECRET_0",
+    ] {
+        assert!(
+            log.lines().any(|line| line.starts_with(label)),
+            "no {label} line"
+        );
+    }
 }
 
 #[test]
@@ -2183,6 +4328,31 @@ fn loss_probe_requests_immediate_ack() {
     );
 }
 
+#[test]
+fn max_pto_backoff() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+    pair.drive();
+    pair.client_conn_mut(client_ch).set_max_pto_backoff(1);
+
+    // Lose everything, so that successive probes go unacknowledged
+    pair.mtu = 0;
+    pair.client_conn_mut(client_ch).ping();
+    pair.drive_client();
+    let mut wakeups = vec![pair.time];
+    for _ in 0..5 {
+        pair.time = pair.client.next_wakeup().unwrap();
+        pair.drive_client();
+        wakeups.push(pair.time);
+    }
+    let intervals = wakeups.windows(2).map(|x| x[1] - x[0]).collect::<Vec<_>>();
+    info!(?intervals, "probe intervals");
+    // The PTO doubles once, then stays constant
+    assert_eq!(intervals[1], intervals[0] * 2);
+    assert!(intervals[2..].iter().all(|&x| x == intervals[1]));
+}
+
 #[test]
 /// This is mostly a sanity check to ensure our testing code is correctly dropping packets above the
 /// pmtu
@@ -2617,6 +4787,54 @@ fn single_ack_eliciting_packet_with_ce_bit_triggers_immediate_ack() {
     );
 }
 
+#[test]
+fn ecn_validation() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+    let stats_after_connect = pair.client_conn_mut(client_ch).stats();
+    assert!(stats_after_connect.path.ecn_validated);
+
+    pair.client_conn_mut(client_ch).ping();
+    pair.congestion_experienced = true;
+    pair.drive_client();
+    pair.congestion_experienced = false;
+    pair.drive();
+
+    // The peer's report of the CE mark is attributed to the path
+    let stats_after_ping = pair.client_conn_mut(client_ch).stats();
+    assert!(stats_after_ping.path.ecn_validated);
+    assert_eq!(
+        stats_after_ping.path.ecn_ce_marks - stats_after_connect.path.ecn_ce_marks,
+        1
+    );
+}
+
+#[test]
+fn ecn_bleaching() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.ecn_bleaching = true;
+    let (client_ch, server_ch) = pair.connect();
+    for side in [Side::Client, Side::Server] {
+        let conn = match side {
+            Side::Client => pair.client_conn_mut(client_ch),
+            Side::Server => pair.server_conn_mut(server_ch),
+        };
+        assert!(!conn.using_ecn());
+        assert!(!conn.stats().path.ecn_validated);
+    }
+
+    // Data still flows, unmarked
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(b"hello").unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
+    );
+}
+
 fn setup_ack_frequency_test(max_ack_delay: Duration) -> (Pair, ConnectionHandle, ConnectionHandle) {
     let mut client_config = client_config_with_deterministic_pns();
     let mut ack_freq_config = AckFrequencyConfig::default();
@@ -1,4 +1,5 @@
 use std::{
+    any::Any,
     cmp,
     collections::{HashMap, VecDeque},
     env,
@@ -7,7 +8,10 @@ use std::{
     net::{Ipv6Addr, SocketAddr, UdpSocket},
     ops::RangeFrom,
     str,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, Instant},
 };
 
@@ -37,6 +41,8 @@ pub(super) struct Pair {
     pub(super) mtu: usize,
     /// Simulates explicit congestion notification
     pub(super) congestion_experienced: bool,
+    /// Simulates a path clearing explicit congestion notification markings
+    pub(super) ecn_bleaching: bool,
     // One-way
     pub(super) latency: Duration,
     /// Number of spin bit flips
@@ -85,6 +91,7 @@ impl Pair {
             spins: 0,
             last_spin: false,
             congestion_experienced: false,
+            ecn_bleaching: false,
         }
     }
 
@@ -156,7 +163,8 @@ impl Pair {
                 socket.send_to(&buffer, packet.destination).unwrap();
             }
             if self.server.addr == packet.destination {
-                let ecn = set_congestion_experienced(packet.ecn, self.congestion_experienced);
+                let ecn = packet.ecn.filter(|_| !self.ecn_bleaching);
+                let ecn = set_congestion_experienced(ecn, self.congestion_experienced);
                 self.server.inbound.push_back((
                     self.time + self.latency,
                     ecn,
@@ -180,7 +188,8 @@ impl Pair {
                 socket.send_to(&buffer, packet.destination).unwrap();
             }
             if self.client.addr == packet.destination {
-                let ecn = set_congestion_experienced(packet.ecn, self.congestion_experienced);
+                let ecn = packet.ecn.filter(|_| !self.ecn_bleaching);
+                let ecn = set_congestion_experienced(ecn, self.congestion_experienced);
                 self.client.inbound.push_back((
                     self.time + self.latency,
                     ecn,
@@ -288,7 +297,7 @@ pub(super) struct TestEndpoint {
     pub(super) endpoint: Endpoint,
     pub(super) addr: SocketAddr,
     socket: Option<UdpSocket>,
-    timeout: Option<Instant>,
+    timeouts: HashMap<ConnectionHandle, Instant>,
     pub(super) outbound: VecDeque<(Transmit, Bytes)>,
     delayed: VecDeque<(Transmit, Bytes)>,
     pub(super) inbound: VecDeque<(Instant, Option<EcnCodepoint>, BytesMut)>,
@@ -325,7 +334,7 @@ impl TestEndpoint {
             endpoint,
             addr,
             socket,
-            timeout: None,
+            timeouts: HashMap::default(),
             outbound: VecDeque::new(),
             delayed: VecDeque::new(),
             inbound: VecDeque::new(),
@@ -415,15 +424,13 @@ impl TestEndpoint {
         loop {
             let mut endpoint_events: Vec<(ConnectionHandle, EndpointEvent)> = vec![];
             for (ch, conn) in self.connections.iter_mut() {
-                if self.timeout.map_or(false, |x| x <= now) {
-                    self.timeout = None;
+                if self.timeouts.get(ch).map_or(false, |&x| x <= now) {
+                    self.timeouts.remove(ch);
                     conn.handle_timeout(now);
                 }
 
-                for (_, mut events) in self.conn_events.drain() {
-                    for event in events.drain(..) {
-                        conn.handle_event(event);
-                    }
+                for event in self.conn_events.remove(ch).into_iter().flatten() {
+                    conn.handle_event(event);
                 }
 
                 while let Some(event) = conn.poll_endpoint_events() {
@@ -434,7 +441,10 @@ impl TestEndpoint {
                     self.outbound.extend(split_transmit(transmit, &buf[..size]));
                     buf.clear();
                 }
-                self.timeout = conn.poll_timeout();
+                match conn.poll_timeout() {
+                    Some(timeout) => self.timeouts.insert(*ch, timeout),
+                    None => self.timeouts.remove(ch),
+                };
             }
 
             if endpoint_events.is_empty() {
@@ -453,7 +463,13 @@ impl TestEndpoint {
 
     pub(super) fn next_wakeup(&self) -> Option<Instant> {
         let next_inbound = self.inbound.front().map(|x| x.0);
-        min_opt(self.timeout, next_inbound)
+        let timeout = self
+            .timeouts
+            .iter()
+            .filter(|(ch, _)| self.connections.contains_key(ch))
+            .map(|(_, &t)| t)
+            .min();
+        min_opt(timeout, next_inbound)
     }
 
     fn is_idle(&self) -> bool {
@@ -664,6 +680,156 @@ pub(super) fn min_opt<T: Ord>(x: Option<T>, y: Option<T>) -> Option<T> {
 /// The maximum of datagrams TestEndpoint will produce via `poll_transmit`
 const MAX_DATAGRAMS: usize = 10;
 
+/// Client crypto recording the largest batch of packets passed to
+/// [`PacketKey::encrypt_batch()`](crypto::PacketKey::encrypt_batch)
+pub(super) struct BatchRecorder {
+    inner: Arc<QuicClientConfig>,
+    largest: Arc<AtomicUsize>,
+}
+
+impl BatchRecorder {
+    pub(super) fn new(largest: Arc<AtomicUsize>) -> Self {
+        Self {
+            inner: Arc::new(client_crypto()),
+            largest,
+        }
+    }
+}
+
+impl crypto::ClientConfig for BatchRecorder {
+    fn start_session(
+        self: Arc<Self>,
+        version: u32,
+        server_name: &str,
+        params: &TransportParameters,
+    ) -> Result<Box<dyn crypto::Session>, ConnectError> {
+        Ok(Box::new(BatchRecorderSession {
+            inner: self
+                .inner
+                .clone()
+                .start_session(version, server_name, params)?,
+            largest: self.largest.clone(),
+        }))
+    }
+}
+
+struct BatchRecorderSession {
+    inner: Box<dyn crypto::Session>,
+    largest: Arc<AtomicUsize>,
+}
+
+impl BatchRecorderSession {
+    fn wrap(
+        &self,
+        keys: crypto::KeyPair<Box<dyn crypto::PacketKey>>,
+    ) -> crypto::KeyPair<Box<dyn crypto::PacketKey>> {
+        crypto::KeyPair {
+            local: Box::new(BatchRecorderKey {
+                inner: keys.local,
+                largest: self.largest.clone(),
+            }),
+            remote: keys.remote,
+        }
+    }
+}
+
+impl crypto::Session for BatchRecorderSession {
+    fn initial_keys(&self, dst_cid: &ConnectionId, side: Side) -> crypto::Keys {
+        self.inner.initial_keys(dst_cid, side)
+    }
+
+    fn handshake_data(&self) -> Option<Box<dyn Any>> {
+        self.inner.handshake_data()
+    }
+
+    fn peer_identity(&self) -> Option<Box<dyn Any>> {
+        self.inner.peer_identity()
+    }
+
+    fn early_crypto(&self) -> Option<(Box<dyn crypto::HeaderKey>, Box<dyn crypto::PacketKey>)> {
+        self.inner.early_crypto()
+    }
+
+    fn early_data_accepted(&self) -> Option<bool> {
+        self.inner.early_data_accepted()
+    }
+
+    fn is_handshaking(&self) -> bool {
+        self.inner.is_handshaking()
+    }
+
+    fn read_handshake(&mut self, buf: &[u8]) -> Result<bool, TransportError> {
+        self.inner.read_handshake(buf)
+    }
+
+    fn transport_parameters(&self) -> Result<Option<TransportParameters>, TransportError> {
+        self.inner.transport_parameters()
+    }
+
+    fn write_handshake(&mut self, buf: &mut Vec<u8>) -> Option<crypto::Keys> {
+        let keys = self.inner.write_handshake(buf)?;
+        Some(crypto::Keys {
+            header: keys.header,
+            packet: self.wrap(keys.packet),
+        })
+    }
+
+    fn next_1rtt_keys(&mut self) -> Option<crypto::KeyPair<Box<dyn crypto::PacketKey>>> {
+        let keys = self.inner.next_1rtt_keys()?;
+        Some(self.wrap(keys))
+    }
+
+    fn is_valid_retry(&self, orig_dst_cid: &ConnectionId, header: &[u8], payload: &[u8]) -> bool {
+        self.inner.is_valid_retry(orig_dst_cid, header, payload)
+    }
+
+    fn export_keying_material(
+        &self,
+        output: &mut [u8],
+        label: &[u8],
+        context: &[u8],
+    ) -> Result<(), crypto::ExportKeyingMaterialError> {
+        self.inner.export_keying_material(output, label, context)
+    }
+}
+
+struct BatchRecorderKey {
+    inner: Box<dyn crypto::PacketKey>,
+    largest: Arc<AtomicUsize>,
+}
+
+impl crypto::PacketKey for BatchRecorderKey {
+    fn encrypt(&self, packet: u64, buf: &mut [u8], header_len: usize) {
+        self.inner.encrypt(packet, buf, header_len);
+    }
+
+    fn encrypt_batch(&self, buf: &mut [u8], packets: &[crypto::BatchedPacket]) {
+        self.largest.fetch_max(packets.len(), Ordering::Relaxed);
+        self.inner.encrypt_batch(buf, packets);
+    }
+
+    fn decrypt(
+        &self,
+        packet: u64,
+        header: &[u8],
+        payload: &mut BytesMut,
+    ) -> Result<(), crypto::CryptoError> {
+        self.inner.decrypt(packet, header, payload)
+    }
+
+    fn tag_len(&self) -> usize {
+        self.inner.tag_len()
+    }
+
+    fn confidentiality_limit(&self) -> u64 {
+        self.inner.confidentiality_limit()
+    }
+
+    fn integrity_limit(&self) -> u64 {
+        self.inner.integrity_limit()
+    }
+}
+
 fn split_transmit(transmit: Transmit, buffer: &[u8]) -> Vec<(Transmit, Bytes)> {
     let mut buffer = Bytes::copy_from_slice(buffer);
     let segment_size = match transmit.segment_size {
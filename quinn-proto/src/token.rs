@@ -1,10 +1,13 @@
 use std::{
+    collections::{BTreeSet, VecDeque},
     fmt, io,
     net::{IpAddr, SocketAddr},
+    sync::Mutex,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use bytes::{Buf, BufMut};
+use bytes::{Buf, BufMut, Bytes};
+use rustc_hash::{FxHashMap, FxHashSet};
 
 use crate::{
     coding::{BufExt, BufMutExt},
@@ -13,6 +16,347 @@ use crate::{
     RESET_TOKEN_SIZE,
 };
 
+/// Issues and validates the tokens a server uses to confirm that clients own their addresses
+///
+/// A server that sends a Retry packet in response to a connection attempt includes a token, which
+/// the client must echo in its subsequent Initial packets. A valid token proves that the client can
+/// receive packets at the address it claims, and lets the server recover state it would otherwise
+/// have had to keep about the original connection attempt. Servers also hand out tokens in
+/// NEW_TOKEN frames once a connection is established, which clients can use to skip the Retry
+/// round trip when they connect again later.
+///
+/// By default, tokens are sealed with [`ServerConfig::token_key()`](crate::ServerConfig::token_key)
+/// and only the endpoint that issued them, or one sharing its key, can validate them. A custom
+/// provider can be configured with
+/// [`ServerConfig::token_provider()`](crate::ServerConfig::token_provider), e.g. to use a format
+/// understood by every server in a load-balanced fleet.
+pub trait TokenProvider: Send + Sync {
+    /// Construct the token to send in a Retry packet to the client at `remote`
+    ///
+    /// The client will echo the token in Initial packets addressed to `retry_src_cid`. Validating
+    /// the token must yield `orig_dst_cid`, the destination connection ID of the client's first
+    /// Initial packet.
+    fn retry_token(
+        &self,
+        remote: &SocketAddr,
+        retry_src_cid: &ConnectionId,
+        orig_dst_cid: &ConnectionId,
+    ) -> Vec<u8>;
+
+    /// Construct a token to send in a NEW_TOKEN frame to the client at `remote`
+    ///
+    /// Clients may present the token when connecting again, possibly much later and from a
+    /// different port, so it should be bound to the client's IP address at most. Returns `None` by
+    /// default, in which case no NEW_TOKEN frames are sent.
+    fn new_token(&self, remote: &SocketAddr) -> Option<Vec<u8>> {
+        let _ = remote;
+        None
+    }
+
+    /// Check a `token` found in an Initial packet from `remote` addressed to `dst_cid`
+    ///
+    /// Tokens that weren't issued by this provider, e.g. ones from a previous version of the server
+    /// or from a different deployment, should be rejected with [`TokenError::Unknown`] so that the
+    /// connection attempt proceeds as if no token was supplied. The same goes for tokens from
+    /// NEW_TOKEN frames that are expired or were issued to a different address, as the client had
+    /// no way of knowing that they wouldn't be accepted. Tokens from NEW_TOKEN frames must not be
+    /// accepted more than once, lest an observer reuse them to skip address validation.
+    fn validate(
+        &self,
+        remote: &SocketAddr,
+        dst_cid: &ConnectionId,
+        token: &[u8],
+    ) -> Result<ValidatedToken, TokenError>;
+}
+
+/// Information recovered from a token by a [`TokenProvider`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ValidatedToken {
+    /// The token was issued in a Retry packet
+    Retry {
+        /// The destination connection ID of the client's first Initial packet
+        orig_dst_cid: ConnectionId,
+    },
+    /// The token was issued in a NEW_TOKEN frame
+    NewToken,
+}
+
+/// Reasons why a token might fail to validate a client's address
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TokenError {
+    /// The token was not recognized, and will be ignored
+    Unknown,
+    /// The token was recognized but is expired or doesn't belong to the client's address, so the
+    /// connection attempt is refused
+    Invalid,
+}
+
+impl From<CryptoError> for TokenError {
+    fn from(CryptoError: CryptoError) -> Self {
+        Self::Unknown
+    }
+}
+
+/// The [`TokenProvider`] used unless another is configured
+pub(crate) struct DefaultTokenProvider<'a> {
+    pub(crate) key: &'a dyn HandshakeTokenKey,
+    /// NEW_TOKEN tokens that have already been accepted
+    pub(crate) used: &'a TokenLog,
+    /// Duration after a retry token was issued for which it's considered valid
+    pub(crate) retry_token_lifetime: Duration,
+    /// Duration after a NEW_TOKEN token was issued for which it's considered valid
+    pub(crate) new_token_lifetime: Duration,
+}
+
+impl DefaultTokenProvider<'_> {
+    /// Leading byte of tokens sent in Retry packets
+    const RETRY: u8 = 0;
+    /// Leading byte of tokens sent in NEW_TOKEN frames
+    const NEW_TOKEN: u8 = 1;
+}
+
+impl TokenProvider for DefaultTokenProvider<'_> {
+    fn retry_token(
+        &self,
+        remote: &SocketAddr,
+        retry_src_cid: &ConnectionId,
+        orig_dst_cid: &ConnectionId,
+    ) -> Vec<u8> {
+        let mut buf = vec![Self::RETRY];
+        buf.extend(
+            RetryToken {
+                orig_dst_cid: *orig_dst_cid,
+                issued: SystemTime::now(),
+            }
+            .encode(self.key, remote, retry_src_cid),
+        );
+        buf
+    }
+
+    fn new_token(&self, remote: &SocketAddr) -> Option<Vec<u8>> {
+        let mut buf = vec![Self::NEW_TOKEN];
+        buf.extend(
+            AddressToken {
+                ip: remote.ip(),
+                issued: SystemTime::now(),
+                salt: rand::random(),
+            }
+            .encode(self.key),
+        );
+        Some(buf)
+    }
+
+    fn validate(
+        &self,
+        remote: &SocketAddr,
+        dst_cid: &ConnectionId,
+        token: &[u8],
+    ) -> Result<ValidatedToken, TokenError> {
+        match token.split_first() {
+            Some((&Self::RETRY, token)) => {
+                let token = RetryToken::from_bytes(self.key, remote, dst_cid, token)?;
+                if token.issued + self.retry_token_lifetime <= SystemTime::now() {
+                    return Err(TokenError::Invalid);
+                }
+                Ok(ValidatedToken::Retry {
+                    orig_dst_cid: token.orig_dst_cid,
+                })
+            }
+            Some((&Self::NEW_TOKEN, token)) => {
+                let token = AddressToken::from_bytes(self.key, token)?;
+                let expires = token.issued + self.new_token_lifetime;
+                if token.ip != remote.ip() || expires <= SystemTime::now() {
+                    return Err(TokenError::Unknown);
+                }
+                // A token observed on the path must not validate the addresses of further
+                // connection attempts (RFC 9000 §8.1.4)
+                if !self.used.insert(token.salt, expires) {
+                    return Err(TokenError::Unknown);
+                }
+                Ok(ValidatedToken::NewToken)
+            }
+            _ => Err(TokenError::Unknown),
+        }
+    }
+}
+
+/// NEW_TOKEN tokens accepted by a [`DefaultTokenProvider`], which may not be used again
+///
+/// Tokens are identified by their random salt, and forgotten once they expire.
+#[derive(Default)]
+pub(crate) struct TokenLog {
+    state: Mutex<TokenLogState>,
+}
+
+#[derive(Default)]
+struct TokenLogState {
+    seen: FxHashSet<[u8; AddressToken::SALT_LEN]>,
+    /// Entries of `seen` in the order they expire, soonest first
+    expiry: BTreeSet<(SystemTime, [u8; AddressToken::SALT_LEN])>,
+}
+
+impl TokenLog {
+    /// Upper bound on the tokens remembered at once, past which further tokens are refused
+    const MAX_ENTRIES: usize = 1 << 18;
+
+    /// Record the use of the token identified by `salt`, returning whether it was unused
+    fn insert(&self, salt: [u8; AddressToken::SALT_LEN], expires: SystemTime) -> bool {
+        let state = &mut *self.state.lock().unwrap();
+        let now = SystemTime::now();
+        while let Some(&(time, salt)) = state.expiry.first() {
+            if time > now {
+                break;
+            }
+            state.expiry.pop_first();
+            state.seen.remove(&salt);
+        }
+        // Refusing is safe: the client falls back to other means of validating its address
+        if state.seen.len() >= Self::MAX_ENTRIES || !state.seen.insert(salt) {
+            return false;
+        }
+        state.expiry.insert((expires, salt));
+        true
+    }
+}
+
+impl fmt::Debug for TokenLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenLog").finish_non_exhaustive()
+    }
+}
+
+/// Stores tokens received in NEW_TOKEN frames for use in future connections
+///
+/// Presenting a token when connecting to a server again lets the server skip validating the
+/// client's address with a Retry round trip. Configured with
+/// [`ClientConfig::token_store()`](crate::ClientConfig::token_store).
+pub trait TokenStore: Send + Sync {
+    /// Save a `token` received from `server_name` for a future connection
+    fn insert(&self, server_name: &str, token: Bytes);
+
+    /// Take a token for a new connection to `server_name`, if one is available
+    ///
+    /// Tokens should be used at most once, as presenting the same token repeatedly allows
+    /// observers to link the connections together.
+    fn take(&self, server_name: &str) -> Option<Bytes>;
+}
+
+/// A [`TokenStore`] that keeps the most recent tokens in memory
+///
+/// Keeps up to two tokens for each of up to 256 servers, forgetting the oldest tokens first.
+pub struct TokenMemoryCache {
+    tokens: Mutex<TokenMemoryCacheState>,
+}
+
+#[derive(Default)]
+struct TokenMemoryCacheState {
+    servers: FxHashMap<String, VecDeque<Bytes>>,
+    /// Server names in the order they last received a token, oldest first
+    lru: VecDeque<String>,
+}
+
+impl TokenMemoryCache {
+    const MAX_SERVERS: usize = 256;
+    const MAX_TOKENS_PER_SERVER: usize = 2;
+}
+
+impl Default for TokenMemoryCache {
+    fn default() -> Self {
+        Self {
+            tokens: Mutex::new(TokenMemoryCacheState::default()),
+        }
+    }
+}
+
+impl TokenStore for TokenMemoryCache {
+    fn insert(&self, server_name: &str, token: Bytes) {
+        let state = &mut *self.tokens.lock().unwrap();
+        state.lru.retain(|x| x != server_name);
+        state.lru.push_back(server_name.into());
+        if state.lru.len() > Self::MAX_SERVERS {
+            let evicted = state.lru.pop_front().unwrap();
+            state.servers.remove(&evicted);
+        }
+        let tokens = state.servers.entry(server_name.into()).or_default();
+        if tokens.len() == Self::MAX_TOKENS_PER_SERVER {
+            tokens.pop_front();
+        }
+        tokens.push_back(token);
+    }
+
+    fn take(&self, server_name: &str) -> Option<Bytes> {
+        let state = &mut *self.tokens.lock().unwrap();
+        let tokens = state.servers.get_mut(server_name)?;
+        let token = tokens.pop_back();
+        if tokens.is_empty() {
+            state.servers.remove(server_name);
+            state.lru.retain(|x| x != server_name);
+        }
+        token
+    }
+}
+
+impl fmt::Debug for TokenMemoryCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenMemoryCache").finish_non_exhaustive()
+    }
+}
+
+/// A token issued in a NEW_TOKEN frame, vouching for the IP address it was sent to
+struct AddressToken {
+    ip: IpAddr,
+    /// The time at which this token was issued
+    issued: SystemTime,
+    /// Random value the token's AEAD key is derived from, which identifies the token
+    salt: [u8; Self::SALT_LEN],
+}
+
+impl AddressToken {
+    /// Length of the random value each token's AEAD key is derived from
+    const SALT_LEN: usize = 32;
+
+    fn encode(&self, key: &dyn HandshakeTokenKey) -> Vec<u8> {
+        let aead_key = key.aead_from_hkdf(&self.salt);
+
+        let mut buf = Vec::new();
+        encode_addr(&mut buf, &SocketAddr::new(self.ip, 0));
+        buf.write::<u64>(
+            self.issued
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_secs())
+                .unwrap_or(0),
+        );
+        aead_key.seal(&mut buf, &[]).unwrap();
+
+        let mut token = self.salt.to_vec();
+        token.extend(buf);
+        token
+    }
+
+    fn from_bytes(key: &dyn HandshakeTokenKey, raw_token_bytes: &[u8]) -> Result<Self, TokenError> {
+        if raw_token_bytes.len() < Self::SALT_LEN {
+            return Err(TokenError::Unknown);
+        }
+        let (salt, sealed) = raw_token_bytes.split_at(Self::SALT_LEN);
+        let aead_key = key.aead_from_hkdf(salt);
+        let mut sealed_token = sealed.to_vec();
+
+        let data = aead_key.open(&mut sealed_token, &[])?;
+        let mut reader = io::Cursor::new(data);
+        let ip = decode_addr(&mut reader).ok_or(TokenError::Unknown)?.ip();
+        let issued =
+            UNIX_EPOCH + Duration::new(reader.get::<u64>().map_err(|_| TokenError::Unknown)?, 0);
+
+        Ok(Self {
+            ip,
+            issued,
+            salt: salt.try_into().unwrap(),
+        })
+    }
+}
+
 pub(crate) struct RetryToken {
     /// The destination connection ID set in the very first packet from the client
     pub(crate) orig_dst_cid: ConnectionId,
@@ -49,25 +393,19 @@ impl RetryToken {
         address: &SocketAddr,
         retry_src_cid: &ConnectionId,
         raw_token_bytes: &[u8],
-    ) -> Result<Self, TokenDecodeError> {
+    ) -> Result<Self, TokenError> {
         let aead_key = key.aead_from_hkdf(retry_src_cid);
         let mut sealed_token = raw_token_bytes.to_vec();
 
         let data = aead_key.open(&mut sealed_token, &[])?;
         let mut reader = io::Cursor::new(data);
-        let token_addr = decode_addr(&mut reader).ok_or(TokenDecodeError::UnknownToken)?;
+        let token_addr = decode_addr(&mut reader).ok_or(TokenError::Unknown)?;
         if token_addr != *address {
-            return Err(TokenDecodeError::WrongAddress);
+            return Err(TokenError::Invalid);
         }
-        let orig_dst_cid =
-            ConnectionId::decode_long(&mut reader).ok_or(TokenDecodeError::UnknownToken)?;
-        let issued = UNIX_EPOCH
-            + Duration::new(
-                reader
-                    .get::<u64>()
-                    .map_err(|_| TokenDecodeError::UnknownToken)?,
-                0,
-            );
+        let orig_dst_cid = ConnectionId::decode_long(&mut reader).ok_or(TokenError::Unknown)?;
+        let issued =
+            UNIX_EPOCH + Duration::new(reader.get::<u64>().map_err(|_| TokenError::Unknown)?, 0);
 
         Ok(Self {
             orig_dst_cid,
@@ -100,22 +438,6 @@ fn decode_addr<B: Buf>(buf: &mut B) -> Option<SocketAddr> {
     Some(SocketAddr::new(ip, port))
 }
 
-/// Reasons why a retry token might fail to validate a client's address
-#[derive(Debug, Copy, Clone)]
-pub(crate) enum TokenDecodeError {
-    /// Token was not recognized. It should be silently ignored.
-    UnknownToken,
-    /// Token was well-formed but associated with an incorrect address. The connection cannot be
-    /// established.
-    WrongAddress,
-}
-
-impl From<CryptoError> for TokenDecodeError {
-    fn from(CryptoError: CryptoError) -> Self {
-        Self::UnknownToken
-    }
-}
-
 /// Stateless reset token
 ///
 /// Used for an endpoint to securely communicate that it has lost state for a connection.
@@ -231,4 +553,60 @@ mod test {
         // Assert: garbage sealed data returns err
         assert!(RetryToken::from_bytes(&prk, &addr, &retry_src_cid, &invalid_token).is_err());
     }
+
+    #[test]
+    fn new_token_single_use() {
+        use super::*;
+        use crate::cid_generator::{ConnectionIdGenerator, RandomConnectionIdGenerator};
+        use crate::MAX_CID_SIZE;
+        use rand::RngCore;
+        use std::net::Ipv6Addr;
+
+        let mut master_key = [0; 64];
+        rand::thread_rng().fill_bytes(&mut master_key);
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, &[]).extract(&master_key);
+        let used = TokenLog::default();
+        let provider = DefaultTokenProvider {
+            key: &prk,
+            used: &used,
+            retry_token_lifetime: Duration::from_secs(15),
+            new_token_lifetime: Duration::from_secs(3600),
+        };
+
+        let addr = SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 4433);
+        let token = provider.new_token(&addr).unwrap();
+        let other = provider.new_token(&addr).unwrap();
+        let dst_cid = RandomConnectionIdGenerator::new(MAX_CID_SIZE).generate_cid();
+        assert_eq!(
+            provider.validate(&addr, &dst_cid, &token),
+            Ok(ValidatedToken::NewToken)
+        );
+        // Presenting the same token again, even for another connection, fails
+        let dst_cid = RandomConnectionIdGenerator::new(MAX_CID_SIZE).generate_cid();
+        assert_eq!(
+            provider.validate(&addr, &dst_cid, &token),
+            Err(TokenError::Unknown)
+        );
+        assert_eq!(
+            provider.validate(&addr, &dst_cid, &other),
+            Ok(ValidatedToken::NewToken)
+        );
+    }
+
+    #[test]
+    fn memory_cache() {
+        use super::*;
+
+        let cache = TokenMemoryCache::default();
+        for token in [&b"a"[..], b"b", b"c"] {
+            cache.insert("example.com", Bytes::from_static(token));
+        }
+        cache.insert("example.org", Bytes::from_static(b"d"));
+
+        // Only the newest tokens are kept, and are handed out newest first
+        assert_eq!(cache.take("example.com").as_deref(), Some(&b"c"[..]));
+        assert_eq!(cache.take("example.com").as_deref(), Some(&b"b"[..]));
+        assert_eq!(cache.take("example.com"), None);
+        assert_eq!(cache.take("example.org").as_deref(), Some(&b"d"[..]));
+    }
 }
@@ -11,7 +11,8 @@ use std::{
     net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6},
 };
 
-use bytes::{Buf, BufMut};
+use bytes::{Buf, BufMut, Bytes};
+use rand::{Rng, RngCore};
 use thiserror::Error;
 
 use crate::{
@@ -66,7 +67,7 @@ macro_rules! apply_params {
 macro_rules! make_struct {
     {$($(#[$doc:meta])* $name:ident ($code:expr) = $default:expr,)*} => {
         /// Transport parameters used to negotiate connection-level preferences between peers
-        #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+        #[derive(Debug, Clone, Eq, PartialEq)]
         pub struct TransportParameters {
             $($(#[$doc])* pub(crate) $name : VarInt,)*
 
@@ -88,6 +89,28 @@ macro_rules! make_struct {
             /// Frequency
             pub(crate) min_ack_delay: Option<VarInt>,
 
+            /// The endpoint supports the reliable stream reset extension, i.e. receiving
+            /// RESET_STREAM_AT frames
+            pub(crate) reset_stream_at: bool,
+
+            /// Maximum number of receive timestamps the endpoint asks for in each ACK
+            ///
+            /// If a value is provided, the endpoint supports the receive timestamps extension.
+            pub(crate) max_receive_timestamps_per_ack: Option<VarInt>,
+            /// Exponent used to encode the receive timestamps the endpoint asks for
+            pub(crate) receive_timestamps_exponent: VarInt,
+
+            /// Randomized reserved parameter to send in place of the fixed one, never set when
+            /// decoding
+            pub(crate) grease: Option<ReservedTransportParameter>,
+
+            /// The version in use and those the endpoint supports, to prevent version downgrades
+            pub(crate) version_information: Option<VersionInformation>,
+
+            /// Parameters registered by the application, or sent by the peer but not understood
+            /// by quinn, in the order they were encoded
+            pub(crate) custom: Vec<(VarInt, Bytes)>,
+
             // Server-only
             /// The value of the Destination Connection ID field from the first Initial packet sent
             /// by the client
@@ -115,6 +138,12 @@ macro_rules! make_struct {
                     initial_src_cid: None,
                     grease_quic_bit: false,
                     min_ack_delay: None,
+                    reset_stream_at: false,
+                    max_receive_timestamps_per_ack: None,
+                    receive_timestamps_exponent: VarInt(0),
+                    grease: None,
+                    version_information: None,
+                    custom: Vec::new(),
 
                     original_dst_cid: None,
                     retry_src_cid: None,
@@ -135,15 +164,17 @@ impl TransportParameters {
         cid_gen: &dyn ConnectionIdGenerator,
         initial_src_cid: ConnectionId,
         server_config: Option<&ServerConfig>,
+        version: u32,
+        rng: &mut impl RngCore,
     ) -> Self {
         Self {
             initial_src_cid: Some(initial_src_cid),
             initial_max_streams_bidi: config.max_concurrent_bidi_streams,
             initial_max_streams_uni: config.max_concurrent_uni_streams,
-            initial_max_data: config.receive_window,
-            initial_max_stream_data_bidi_local: config.stream_receive_window,
-            initial_max_stream_data_bidi_remote: config.stream_receive_window,
-            initial_max_stream_data_uni: config.stream_receive_window,
+            initial_max_data: config.get_receive_window(),
+            initial_max_stream_data_bidi_local: config.get_stream_receive_window(),
+            initial_max_stream_data_bidi_remote: config.get_stream_receive_window(),
+            initial_max_stream_data_uni: config.get_stream_receive_window(),
             max_udp_payload_size: endpoint_config.max_udp_payload_size,
             max_idle_timeout: config.max_idle_timeout.unwrap_or(VarInt(0)),
             disable_active_migration: server_config.map_or(false, |c| !c.migration),
@@ -160,6 +191,18 @@ impl TransportParameters {
             min_ack_delay: Some(
                 VarInt::from_u64(u64::try_from(TIMER_GRANULARITY.as_micros()).unwrap()).unwrap(),
             ),
+            reset_stream_at: true,
+            max_receive_timestamps_per_ack: config
+                .receive_timestamps
+                .then_some(VarInt(MAX_RECEIVE_TIMESTAMPS_PER_ACK)),
+            grease: config
+                .chaos_protection
+                .then(|| ReservedTransportParameter::random(rng)),
+            version_information: Some(VersionInformation::new(
+                version,
+                &endpoint_config.supported_versions,
+            )),
+            custom: config.custom_transport_parameters.clone(),
             ..Self::default()
         }
     }
@@ -191,8 +234,56 @@ impl TransportParameters {
     pub(crate) fn issue_cids_limit(&self) -> u64 {
         self.active_connection_id_limit.0.min(LOC_CID_COUNT)
     }
+
+    /// Value of the custom parameter `id`, if the peer sent it
+    ///
+    /// Custom parameters are those that quinn does not understand itself, such as those registered
+    /// with [`TransportConfig::custom_transport_parameter()`].
+    pub fn custom(&self, id: VarInt) -> Option<&[u8]> {
+        self.custom
+            .iter()
+            .find(|(x, _)| *x == id)
+            .map(|(_, value)| &value[..])
+    }
+
+    /// All custom parameters, in the order they were encoded
+    pub fn custom_parameters(&self) -> impl Iterator<Item = (VarInt, &[u8])> {
+        self.custom.iter().map(|(id, value)| (*id, &value[..]))
+    }
+
+    /// Whether `id` identifies a parameter that quinn encodes itself, or a reserved one
+    ///
+    /// Such identifiers cannot be used for custom parameters.
+    pub(crate) fn is_builtin(id: VarInt) -> bool {
+        macro_rules! codes {
+            {$($(#[$doc:meta])* $name:ident ($code:expr) = $default:expr,)*} => {
+                [$($code,)*]
+            }
+        }
+        let id = id.into_inner();
+        apply_params!(codes).contains(&id)
+            || matches!(
+                id,
+                0x00 | 0x02
+                    | 0x0c
+                    | 0x0d
+                    | 0x0f
+                    | 0x10
+                    | 0x11
+                    | 0x20
+                    | 0x2ab2
+                    | 0xff04de1b
+                    | 0x17f7586d2cb571
+                    | 0xff0a002
+                    | 0xff0a003
+            )
+            || id % 31 == 27
+    }
 }
 
+/// Number of receive timestamps an endpoint asks for in each ACK, when enabled
+const MAX_RECEIVE_TIMESTAMPS_PER_ACK: u64 = 32;
+
 /// A server's preferred address
 ///
 /// This is communicated as a transport parameter during TLS session establishment.
@@ -301,8 +392,13 @@ impl TransportParameters {
         apply_params!(write_params);
 
         // Add a reserved parameter to keep people on their toes
-        w.write_var(31 * 5 + 27);
-        w.write_var(0);
+        match self.grease {
+            Some(ref grease) => grease.write(w),
+            None => {
+                w.write_var(31 * 5 + 27);
+                w.write_var(0);
+            }
+        }
 
         if let Some(ref x) = self.stateless_reset_token {
             w.write_var(0x02);
@@ -349,6 +445,34 @@ impl TransportParameters {
             w.write_var(x.size() as u64);
             w.write(x);
         }
+
+        if self.reset_stream_at {
+            w.write_var(0x17f7586d2cb571);
+            w.write_var(0);
+        }
+
+        if let Some(x) = self.max_receive_timestamps_per_ack {
+            w.write_var(0xff0a002);
+            w.write_var(x.size() as u64);
+            w.write(x);
+            if self.receive_timestamps_exponent.0 != 0 {
+                w.write_var(0xff0a003);
+                w.write_var(self.receive_timestamps_exponent.size() as u64);
+                w.write(self.receive_timestamps_exponent);
+            }
+        }
+
+        if let Some(ref x) = self.version_information {
+            w.write_var(0x11);
+            w.write_var(x.wire_size() as u64);
+            x.write(w);
+        }
+
+        for (id, value) in &self.custom {
+            w.write(*id);
+            w.write_var(value.len() as u64);
+            w.put_slice(value);
+        }
     }
 
     /// Decode `TransportParameters` from buffer
@@ -369,6 +493,7 @@ impl TransportParameters {
             }}
         }
         let mut got = apply_params!(param_state);
+        let mut got_receive_timestamps_exponent = false;
 
         while r.has_remaining() {
             let id = r.get_var()?;
@@ -402,6 +527,12 @@ impl TransportParameters {
                 }
                 0x0f => decode_cid(len, &mut params.initial_src_cid, r)?,
                 0x10 => decode_cid(len, &mut params.retry_src_cid, r)?,
+                0x11 => {
+                    if params.version_information.is_some() {
+                        return Err(Error::Malformed);
+                    }
+                    params.version_information = Some(VersionInformation::read(&mut r.take(len))?);
+                }
                 0x20 => {
                     if len > 8 || params.max_datagram_frame_size.is_some() {
                         return Err(Error::Malformed);
@@ -413,6 +544,25 @@ impl TransportParameters {
                     _ => return Err(Error::Malformed),
                 },
                 0xff04de1b => params.min_ack_delay = Some(r.get().unwrap()),
+                0x17f7586d2cb571 => match len {
+                    0 if !params.reset_stream_at => params.reset_stream_at = true,
+                    _ => return Err(Error::Malformed),
+                },
+                0xff0a002 => {
+                    let value = r.get::<VarInt>()?;
+                    if len != value.size() || params.max_receive_timestamps_per_ack.is_some() {
+                        return Err(Error::Malformed);
+                    }
+                    params.max_receive_timestamps_per_ack = Some(value);
+                }
+                0xff0a003 => {
+                    let value = r.get::<VarInt>()?;
+                    if len != value.size() || got_receive_timestamps_exponent {
+                        return Err(Error::Malformed);
+                    }
+                    params.receive_timestamps_exponent = value;
+                    got_receive_timestamps_exponent = true;
+                }
                 _ => {
                     macro_rules! parse {
                         {$($(#[$doc:meta])* $name:ident ($code:expr) = $default:expr,)*} => {
@@ -423,7 +573,14 @@ impl TransportParameters {
                                     params.$name = value.into();
                                     got.$name = true;
                                 })*
-                                _ => r.advance(len as usize),
+                                _ if id % 31 == 27 => r.advance(len as usize),
+                                _ => {
+                                    let id = VarInt::from_u64(id).unwrap();
+                                    if params.custom.iter().any(|(x, _)| *x == id) {
+                                        return Err(Error::Malformed);
+                                    }
+                                    params.custom.push((id, r.copy_to_bytes(len)));
+                                }
                             }
                         }
                     }
@@ -438,6 +595,7 @@ impl TransportParameters {
         if params.ack_delay_exponent.0 > 20
             // https://www.rfc-editor.org/rfc/rfc9000.html#section-18.2-4.28.1
             || params.max_ack_delay.0 >= 1 << 14
+            || params.receive_timestamps_exponent.0 > 20
             // https://www.rfc-editor.org/rfc/rfc9000.html#section-18.2-6.2.1
             || params.active_connection_id_limit.0 < 2
             // https://www.rfc-editor.org/rfc/rfc9000.html#section-18.2-4.10.1
@@ -468,6 +626,105 @@ impl TransportParameters {
     }
 }
 
+/// Content of the version_information transport parameter (RFC 9368 §3)
+///
+/// Kept inline to avoid allocating; versions beyond [`Self::MAX_AVAILABLE`] are ignored.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct VersionInformation {
+    /// The version of the connection
+    pub(crate) chosen: u32,
+    available: [u32; Self::MAX_AVAILABLE],
+    available_len: usize,
+}
+
+impl VersionInformation {
+    pub(crate) fn new(chosen: u32, available: &[u32]) -> Self {
+        let mut this = Self {
+            chosen,
+            available: [0; Self::MAX_AVAILABLE],
+            available_len: 0,
+        };
+        for &version in available.iter().take(Self::MAX_AVAILABLE) {
+            this.available[this.available_len] = version;
+            this.available_len += 1;
+        }
+        this
+    }
+
+    /// Versions the endpoint supports
+    pub(crate) fn available(&self) -> &[u32] {
+        &self.available[..self.available_len]
+    }
+
+    fn wire_size(&self) -> usize {
+        4 + 4 * self.available_len
+    }
+
+    fn write<W: BufMut>(&self, w: &mut W) {
+        w.write(self.chosen);
+        for &version in self.available() {
+            w.write(version);
+        }
+    }
+
+    fn read<R: Buf>(r: &mut R) -> Result<Self, Error> {
+        if r.remaining() % 4 != 0 {
+            return Err(Error::Malformed);
+        }
+        let chosen = r.get::<u32>()?;
+        // https://www.rfc-editor.org/rfc/rfc9368.html#section-3-5
+        if chosen == 0 {
+            return Err(Error::IllegalValue);
+        }
+        let mut this = Self::new(chosen, &[]);
+        while r.has_remaining() {
+            let version = r.get::<u32>()?;
+            if version == 0 {
+                return Err(Error::IllegalValue);
+            }
+            if this.available_len < Self::MAX_AVAILABLE {
+                this.available[this.available_len] = version;
+                this.available_len += 1;
+            }
+        }
+        Ok(this)
+    }
+
+    const MAX_AVAILABLE: usize = 16;
+}
+
+/// A transport parameter with a reserved identifier and random contents
+///
+/// See <https://www.rfc-editor.org/rfc/rfc9000.html#section-18.1>.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct ReservedTransportParameter {
+    id: VarInt,
+    payload: [u8; Self::MAX_PAYLOAD_LEN],
+    payload_len: usize,
+}
+
+impl ReservedTransportParameter {
+    fn random(rng: &mut impl RngCore) -> Self {
+        // Identifiers of the form 31 * N + 27 are reserved; N is bounded to fit in a varint
+        let id = VarInt::from_u64(31 * rng.gen_range(0..1 << 57) + 27).unwrap();
+        let mut payload = [0; Self::MAX_PAYLOAD_LEN];
+        rng.fill_bytes(&mut payload);
+        Self {
+            id,
+            payload,
+            payload_len: rng.gen_range(0..=Self::MAX_PAYLOAD_LEN),
+        }
+    }
+
+    fn write<W: BufMut>(&self, w: &mut W) {
+        w.write(self.id);
+        w.write_var(self.payload_len as u64);
+        w.put_slice(&self.payload[..self.payload_len]);
+    }
+
+    const MAX_PAYLOAD_LEN: usize = 16;
+}
+
 fn decode_cid(len: usize, value: &mut Option<ConnectionId>, r: &mut impl Buf) -> Result<(), Error> {
     if len > MAX_CID_SIZE || value.is_some() || r.remaining() < len {
         return Err(Error::Malformed);
@@ -499,6 +756,14 @@ mod test {
             }),
             grease_quic_bit: true,
             min_ack_delay: Some(2_000u32.into()),
+            reset_stream_at: true,
+            max_receive_timestamps_per_ack: Some(32u32.into()),
+            receive_timestamps_exponent: 3u32.into(),
+            version_information: Some(VersionInformation::new(
+                crate::QUIC_V2,
+                &[crate::QUIC_V2, crate::QUIC_V1],
+            )),
+            custom: vec![(VarInt(0x4242), Bytes::from_static(b"ext"))],
             ..TransportParameters::default()
         };
         params.write(&mut buf);
@@ -508,6 +773,22 @@ mod test {
         );
     }
 
+    #[test]
+    fn grease() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..16 {
+            let mut params = TransportParameters::default();
+            params.grease = Some(ReservedTransportParameter::random(&mut rng));
+            let mut buf = Vec::new();
+            params.write(&mut buf);
+            // Reserved parameters are ignored by the receiver
+            assert_eq!(
+                TransportParameters::read(Side::Client, &mut buf.as_slice()).unwrap(),
+                TransportParameters::default()
+            );
+        }
+    }
+
     #[test]
     fn read_semantic_validation() {
         #[allow(clippy::type_complexity)]
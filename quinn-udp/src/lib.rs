@@ -53,6 +53,9 @@ mod imp;
 #[path = "fallback.rs"]
 mod imp;
 
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+mod xdp;
+
 #[allow(unused_imports, unused_macros)]
 mod log {
     #[cfg(all(feature = "direct-log", not(feature = "tracing")))]
@@ -77,10 +80,31 @@ mod log {
 }
 
 pub use imp::UdpSocketState;
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+pub use xdp::{XdpConfig, XdpSocket};
 
 /// Number of UDP packets to send/receive at a time
 pub const BATCH_SIZE: usize = imp::BATCH_SIZE;
 
+/// Send `transmits` one at a time, stopping at the first failure
+///
+/// An error is only returned if nothing could be sent.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn send_each(
+    transmits: &[Transmit<'_>],
+    mut send: impl FnMut(&Transmit<'_>) -> std::io::Result<()>,
+) -> std::io::Result<usize> {
+    for (i, transmit) in transmits.iter().enumerate() {
+        if let Err(e) = send(transmit) {
+            return match i {
+                0 => Err(e),
+                _ => Ok(i),
+            };
+        }
+    }
+    Ok(transmits.len())
+}
+
 /// Metadata for a single buffer filled with bytes received from the network
 ///
 /// This associated buffer can contain one or more datagrams, see [`stride`].
@@ -194,6 +194,19 @@ impl UdpSocketState {
         send(self, socket.0, transmit)
     }
 
+    /// Send a sequence of transmits, returning how many of them were consumed
+    ///
+    /// Errors are only returned if the first transmit could not be sent. On Linux and Android, up
+    /// to [`BATCH_SIZE`](crate::BATCH_SIZE) datagrams are passed to the kernel in a single
+    /// `sendmmsg` call; elsewhere they are sent one at a time.
+    pub fn send_batch(
+        &self,
+        socket: UdpSockRef<'_>,
+        transmits: &[Transmit<'_>],
+    ) -> io::Result<usize> {
+        send_batch(self, socket.0, transmits)
+    }
+
     pub fn recv(
         &self,
         socket: UdpSockRef<'_>,
@@ -321,6 +334,24 @@ fn send(
                         state.set_sendmsg_einval();
                     }
 
+                    // Rather than dropping a batch rejected due to GSO, send its datagrams
+                    // individually. Should a later datagram block, the caller retries the whole
+                    // batch, which is harmless since peers discard duplicate packets.
+                    #[cfg(target_os = "linux")]
+                    if let (Some(libc::EIO) | Some(libc::EINVAL), Some(segment_size)) =
+                        (e.raw_os_error(), transmit.segment_size)
+                    {
+                        for contents in transmit.contents.chunks(segment_size) {
+                            let segment = Transmit {
+                                contents,
+                                segment_size: None,
+                                ..*transmit
+                            };
+                            send(state, SockRef::from(&*io), &segment)?;
+                        }
+                        return Ok(());
+                    }
+
                     // Other errors are ignored, since they will usually be handled
                     // by higher level retransmits and timeouts.
                     // - PermissionDenied errors have been observed due to iptable rules.
@@ -341,6 +372,64 @@ fn send(
     }
 }
 
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn send_batch(
+    state: &UdpSocketState,
+    io: SockRef<'_>,
+    transmits: &[Transmit<'_>],
+) -> io::Result<usize> {
+    let count = transmits.len().min(BATCH_SIZE);
+    if count <= 1 {
+        return match transmits.first() {
+            Some(transmit) => send(state, io, transmit).map(|()| 1),
+            None => Ok(0),
+        };
+    }
+
+    let mut hdrs = unsafe { mem::zeroed::<[libc::mmsghdr; BATCH_SIZE]>() };
+    let mut iovs = unsafe { mem::zeroed::<[libc::iovec; BATCH_SIZE]>() };
+    let mut ctrls = [cmsg::Aligned([0u8; CMSG_LEN]); BATCH_SIZE];
+    let addrs: [socket2::SockAddr; BATCH_SIZE] =
+        std::array::from_fn(|i| socket2::SockAddr::from(transmits[i.min(count - 1)].destination));
+    for i in 0..count {
+        prepare_msg(
+            &transmits[i],
+            &addrs[i],
+            &mut hdrs[i].msg_hdr,
+            &mut iovs[i],
+            &mut ctrls[i],
+            true,
+            state.sendmsg_einval(),
+        );
+    }
+
+    loop {
+        let n = unsafe { libc::sendmmsg(io.as_raw_fd(), hdrs.as_mut_ptr(), count as _, 0) };
+        if n > 0 {
+            return Ok(n as usize);
+        }
+        let e = io::Error::last_os_error();
+        match e.kind() {
+            io::ErrorKind::Interrupted => continue,
+            io::ErrorKind::WouldBlock => return Err(e),
+            // `sendmmsg` only fails outright if the first message failed. Resend it on its own so
+            // that GSO fallback and error logging are handled in one place.
+            _ => return send(state, io, &transmits[0]).map(|()| 1),
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn send_batch(
+    state: &UdpSocketState,
+    io: SockRef<'_>,
+    transmits: &[Transmit<'_>],
+) -> io::Result<usize> {
+    crate::send_each(transmits, |transmit| {
+        send(state, SockRef::from(&*io), transmit)
+    })
+}
+
 #[cfg(any(
     target_os = "macos",
     target_os = "ios",
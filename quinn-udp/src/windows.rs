@@ -228,6 +228,19 @@ impl UdpSocketState {
         Ok(())
     }
 
+    /// Send a sequence of transmits one at a time, returning how many of them were consumed
+    ///
+    /// Errors are only returned if the first transmit could not be sent.
+    pub fn send_batch(
+        &self,
+        socket: UdpSockRef<'_>,
+        transmits: &[Transmit<'_>],
+    ) -> io::Result<usize> {
+        crate::send_each(transmits, |transmit| {
+            self.send(UdpSockRef(socket2::SockRef::from(&*socket.0)), transmit)
+        })
+    }
+
     pub fn recv(
         &self,
         socket: UdpSockRef<'_>,
@@ -0,0 +1,920 @@
+use std::{
+    ffi::CString,
+    io::{self, IoSliceMut},
+    mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    ops::Range,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use super::{log::debug, EcnCodepoint, RecvMeta, Transmit};
+
+/// Configuration for an [`XdpSocket`]
+#[derive(Debug, Clone)]
+pub struct XdpConfig {
+    interface: String,
+    queue_id: u32,
+    local_addr: SocketAddr,
+    local_mac: [u8; 6],
+    next_hop_mac: [u8; 6],
+    frame_count: u32,
+    frame_size: u32,
+    zero_copy: bool,
+}
+
+impl XdpConfig {
+    /// Construct a configuration for a socket attached to queue `queue_id` of `interface`
+    ///
+    /// Datagrams are received on and sent from `local_addr`, which must have a specified IP
+    /// address. Frames are sent from `local_mac` to `next_hop_mac`, which must be the link-layer
+    /// address of the gateway or, for peers on the local link, of the peer itself: no neighbor
+    /// discovery is performed.
+    pub fn new(
+        interface: impl Into<String>,
+        queue_id: u32,
+        local_addr: SocketAddr,
+        local_mac: [u8; 6],
+        next_hop_mac: [u8; 6],
+    ) -> Self {
+        Self {
+            interface: interface.into(),
+            queue_id,
+            local_addr,
+            local_mac,
+            next_hop_mac,
+            frame_count: 4096,
+            frame_size: 2048,
+            zero_copy: true,
+        }
+    }
+
+    /// Number of frames in the memory shared with the kernel
+    ///
+    /// Half of the frames are used for receiving, and half for sending. Must be a power of two.
+    /// Defaults to 4096.
+    pub fn frame_count(&mut self, value: u32) -> &mut Self {
+        self.frame_count = value;
+        self
+    }
+
+    /// Size of each frame, bounding the size of datagrams including their headers
+    ///
+    /// Must be 2048 or 4096. Defaults to 2048.
+    pub fn frame_size(&mut self, value: u32) -> &mut Self {
+        self.frame_size = value;
+        self
+    }
+
+    /// Whether to attempt to share frames directly with the network interface
+    ///
+    /// If the driver doesn't support zero-copy mode, or this is `false`, the kernel copies
+    /// frames to and from its own buffers instead. Defaults to `true`.
+    pub fn zero_copy(&mut self, value: bool) -> &mut Self {
+        self.zero_copy = value;
+        self
+    }
+}
+
+/// An experimental UDP socket exchanging raw frames with a network interface through AF_XDP
+///
+/// Bypasses the kernel's network stack, constructing and parsing Ethernet, IP and UDP headers in
+/// userspace, including their checksums. Only traffic that an XDP program attached to the
+/// interface redirects to this socket is received; attaching that program, and inserting this
+/// socket's file descriptor into its `XSKMAP`, is left to the caller. Fragmented datagrams, VLAN
+/// tags other than a single 802.1Q tag, and IPv6 extension headers are not supported, and
+/// received datagrams that use them are dropped.
+///
+/// Requires `CAP_NET_RAW`. All operations are non-blocking; readiness to receive is signaled by
+/// the file descriptor becoming readable.
+#[derive(Debug)]
+pub struct XdpSocket {
+    fd: OwnedFd,
+    umem: Mmap,
+    fill: Ring<u64>,
+    completion: Ring<u64>,
+    rx: Ring<XdpDesc>,
+    tx: Ring<XdpDesc>,
+    /// Frames available for sending
+    free_frames: Vec<u64>,
+    config: XdpConfig,
+    zero_copy: bool,
+}
+
+// SAFETY: the raw pointers only refer to the socket's own mappings, which live as long as it does.
+// The rings and frames are only written through `&mut self`, and ring indices shared with the
+// kernel are accessed atomically.
+unsafe impl Send for XdpSocket {}
+
+impl XdpSocket {
+    /// Create a socket and bind it to the configured interface queue
+    pub fn new(config: &XdpConfig) -> io::Result<Self> {
+        if !config.frame_count.is_power_of_two()
+            || config.frame_count < 2
+            || !(config.frame_size == 2048 || config.frame_size == 4096)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid frame count or size",
+            ));
+        }
+        if config.local_addr.ip().is_unspecified() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "local address must be specified",
+            ));
+        }
+        let interface = CString::new(config.interface.as_str())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: `interface` is a valid NUL-terminated string
+        let ifindex = unsafe { libc::if_nametoindex(interface.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: no pointers are passed
+        let fd = unsafe { libc::socket(AF_XDP, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` was just opened, and nothing else owns it
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let umem = Mmap::new(
+            -1,
+            config.frame_count as usize * config.frame_size as usize,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            0,
+        )?;
+        let reg = UmemReg {
+            addr: umem.ptr as u64,
+            len: umem.len as u64,
+            chunk_size: config.frame_size,
+            headroom: 0,
+            flags: 0,
+            tx_metadata_len: 0,
+        };
+        set_option(&fd, XDP_UMEM_REG, &reg)?;
+        let ring_size = config.frame_count / 2;
+        for option in [
+            XDP_UMEM_FILL_RING,
+            XDP_UMEM_COMPLETION_RING,
+            XDP_RX_RING,
+            XDP_TX_RING,
+        ] {
+            set_option(&fd, option, &ring_size)?;
+        }
+
+        // SAFETY: `MmapOffsets` only contains integers, for which all-zero is valid
+        let mut offsets: MmapOffsets = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<MmapOffsets>() as libc::socklen_t;
+        // SAFETY: `len` is the size of the buffer `offsets` points to, which the kernel won't
+        // write past
+        let rc = unsafe {
+            libc::getsockopt(
+                fd.as_raw_fd(),
+                SOL_XDP,
+                XDP_MMAP_OFFSETS,
+                &mut offsets as *mut _ as *mut _,
+                &mut len,
+            )
+        };
+        if rc == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        if len as usize != mem::size_of::<MmapOffsets>() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "kernel does not support AF_XDP ring flags",
+            ));
+        }
+
+        let mut socket = Self {
+            fill: Ring::map(&fd, &offsets.fr, ring_size, XDP_UMEM_PGOFF_FILL_RING)?,
+            completion: Ring::map(&fd, &offsets.cr, ring_size, XDP_UMEM_PGOFF_COMPLETION_RING)?,
+            rx: Ring::map(&fd, &offsets.rx, ring_size, XDP_PGOFF_RX_RING)?,
+            tx: Ring::map(&fd, &offsets.tx, ring_size, XDP_PGOFF_TX_RING)?,
+            fd,
+            umem,
+            free_frames: (ring_size..config.frame_count)
+                .map(|i| i as u64 * config.frame_size as u64)
+                .collect(),
+            config: config.clone(),
+            zero_copy: config.zero_copy,
+        };
+
+        let mut addr = SockaddrXdp {
+            family: AF_XDP as u16,
+            flags: XDP_USE_NEED_WAKEUP,
+            ifindex,
+            queue_id: config.queue_id,
+            shared_umem_fd: 0,
+        };
+        let mut result = Err(io::ErrorKind::Unsupported.into());
+        if config.zero_copy {
+            addr.flags = XDP_USE_NEED_WAKEUP | XDP_ZEROCOPY;
+            result = socket.bind(&addr);
+            if let Err(ref e) = result {
+                debug!(
+                    "zero-copy AF_XDP unavailable, falling back to copy mode: {}",
+                    e
+                );
+            }
+        }
+        if result.is_err() {
+            addr.flags = XDP_USE_NEED_WAKEUP | XDP_COPY;
+            socket.bind(&addr)?;
+            socket.zero_copy = false;
+        }
+
+        // Hand the first half of the frames to the kernel for receiving
+        for i in 0..ring_size {
+            socket.fill.put(i, i as u64 * config.frame_size as u64);
+        }
+        socket.fill.commit(ring_size);
+        Ok(socket)
+    }
+
+    fn bind(&self, addr: &SockaddrXdp) -> io::Result<()> {
+        // SAFETY: the address length passed matches the `SockaddrXdp` pointed to
+        let rc = unsafe {
+            libc::bind(
+                self.fd.as_raw_fd(),
+                addr as *const _ as *const libc::sockaddr,
+                mem::size_of::<SockaddrXdp>() as libc::socklen_t,
+            )
+        };
+        match rc {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Send a transmit, splitting it into one frame per segment
+    ///
+    /// Fails with [`io::ErrorKind::WouldBlock`] if not enough frames are free. Frames are
+    /// reclaimed once the kernel reports their transmission, which is checked on every call.
+    pub fn send(&mut self, transmit: &Transmit<'_>) -> io::Result<()> {
+        self.reclaim()?;
+        let destination = match (self.config.local_addr, transmit.destination) {
+            (SocketAddr::V4(_), SocketAddr::V6(addr)) => match addr.ip().to_ipv4_mapped() {
+                Some(ip) => SocketAddr::new(ip.into(), addr.port()),
+                None => return Err(family_mismatch()),
+            },
+            (SocketAddr::V6(_), SocketAddr::V4(_)) => return Err(family_mismatch()),
+            (_, destination) => destination,
+        };
+        let source_ip = match (self.config.local_addr.ip(), transmit.src_ip) {
+            (IpAddr::V4(_), Some(IpAddr::V6(ip))) => match ip.to_ipv4_mapped() {
+                Some(ip) => ip.into(),
+                None => return Err(family_mismatch()),
+            },
+            (IpAddr::V6(_), Some(IpAddr::V4(_))) => return Err(family_mismatch()),
+            (local, src_ip) => src_ip.unwrap_or(local),
+        };
+        let source = SocketAddr::new(source_ip, self.config.local_addr.port());
+        let segment_size = transmit
+            .segment_size
+            .unwrap_or(transmit.contents.len())
+            .max(1);
+        if header_len(&destination) + segment_size > self.config.frame_size as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "datagram exceeds frame size",
+            ));
+        }
+
+        let segments = transmit.contents.chunks(segment_size);
+        let count = segments.len();
+        if count > self.free_frames.len() || count > self.tx.vacant() as usize {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let ecn = transmit.ecn.map_or(0, |x| x as u8);
+        let (local_mac, next_hop_mac) = (self.config.local_mac, self.config.next_hop_mac);
+        for (i, segment) in segments.enumerate() {
+            let addr = self.free_frames.pop().unwrap();
+            let frame = self.frame_mut(addr);
+            let len = encode(
+                frame,
+                local_mac,
+                next_hop_mac,
+                source,
+                destination,
+                ecn,
+                segment,
+            );
+            self.tx.put(
+                i as u32,
+                XdpDesc {
+                    addr,
+                    len: len as u32,
+                    options: 0,
+                },
+            );
+        }
+        self.tx.commit(count as u32);
+
+        self.wake_tx()
+    }
+
+    /// Receive datagrams addressed to the local address
+    ///
+    /// Frames that aren't well-formed UDP datagrams addressed to the local address, or have
+    /// invalid checksums, are dropped. Fails with [`io::ErrorKind::WouldBlock`] if nothing was
+    /// received.
+    pub fn recv(
+        &mut self,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> io::Result<usize> {
+        let max = bufs.len().min(meta.len());
+        let mut count = 0;
+        while count == 0 {
+            let available = self.rx.available().min(max as u32);
+            if available == 0 {
+                break;
+            }
+            for i in 0..available {
+                let desc = self.rx.get(i);
+                let frame = self.frame(desc.addr, desc.len as usize);
+                if let Some(datagram) = decode(frame, self.config.local_addr) {
+                    let payload = &frame[datagram.payload];
+                    let len = payload.len().min(bufs[count].len());
+                    bufs[count][..len].copy_from_slice(&payload[..len]);
+                    meta[count] = RecvMeta {
+                        addr: datagram.source,
+                        len,
+                        stride: len,
+                        ecn: EcnCodepoint::from_bits(datagram.ecn),
+                        dst_ip: Some(datagram.destination),
+                    };
+                    count += 1;
+                }
+                // Hand the frame back to the kernel, which has room for every frame it owns
+                let addr = desc.addr - desc.addr % self.config.frame_size as u64;
+                self.fill.put(i, addr);
+            }
+            self.rx.release(available);
+            self.fill.commit(available);
+        }
+
+        if self.fill.needs_wakeup() {
+            // SAFETY: a zero-length receive into a null buffer doesn't access memory
+            unsafe {
+                libc::recvfrom(
+                    self.fd.as_raw_fd(),
+                    ptr::null_mut(),
+                    0,
+                    libc::MSG_DONTWAIT,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                );
+            }
+        }
+        match count {
+            0 => Err(io::ErrorKind::WouldBlock.into()),
+            _ => Ok(count),
+        }
+    }
+
+    /// Return frames whose transmission has completed to the free list, returning whether a
+    /// transmit of up to [`max_gso_segments()`](Self::max_gso_segments) segments can be queued
+    ///
+    /// Prompts the kernel to make progress if frames remain queued for transmission.
+    pub fn reclaim(&mut self) -> io::Result<bool> {
+        let available = self.completion.available();
+        for i in 0..available {
+            self.free_frames.push(self.completion.get(i));
+        }
+        self.completion.release(available);
+        if available == 0 && self.free_frames.len() < self.config.frame_count as usize / 2 {
+            self.wake_tx()?;
+        }
+        let segments = self.max_gso_segments();
+        Ok(self.free_frames.len() >= segments && self.tx.vacant() as usize >= segments)
+    }
+
+    /// Prompt the kernel to transmit queued frames, if necessary
+    fn wake_tx(&self) -> io::Result<()> {
+        // In copy mode, frames are only transmitted when the kernel is prompted to
+        if self.zero_copy && !self.tx.needs_wakeup() {
+            return Ok(());
+        }
+        // SAFETY: a zero-length send from a null buffer doesn't access memory
+        let rc = unsafe {
+            libc::sendto(
+                self.fd.as_raw_fd(),
+                ptr::null(),
+                0,
+                libc::MSG_DONTWAIT,
+                ptr::null(),
+                0,
+            )
+        };
+        if rc == -1 {
+            let e = io::Error::last_os_error();
+            // The frames remain queued, and are sent when the kernel next gets to them
+            match e.raw_os_error() {
+                Some(libc::EAGAIN | libc::EBUSY | libc::ENOBUFS | libc::ENETDOWN) => {}
+                _ => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// The address datagrams are received on and sent from
+    pub fn local_addr(&self) -> SocketAddr {
+        self.config.local_addr
+    }
+
+    /// Whether frames are shared directly with the network interface, rather than copied
+    pub fn is_zero_copy(&self) -> bool {
+        self.zero_copy
+    }
+
+    /// The maximum number of segments which can be sent at once
+    pub fn max_gso_segments(&self) -> usize {
+        (self.config.frame_count as usize / 2).min(MAX_SEGMENTS)
+    }
+
+    /// The `len` bytes of the UMEM at `addr` received by the kernel
+    ///
+    /// The kernel only reports frames within the UMEM, but a descriptor pointing outside of it is
+    /// treated as empty rather than trusted.
+    fn frame(&self, addr: u64, len: usize) -> &[u8] {
+        let Some(available) = self.umem.len.checked_sub(addr as usize) else {
+            return &[];
+        };
+        let len = len.min(available);
+        // SAFETY: `addr..addr + len` lies within the UMEM, which lives as long as `self`. The
+        // kernel no longer writes to received frames until they are returned on the fill ring.
+        unsafe { std::slice::from_raw_parts((self.umem.ptr as *const u8).add(addr as usize), len) }
+    }
+
+    /// The frame at `addr`, which must be free
+    fn frame_mut(&mut self, addr: u64) -> &mut [u8] {
+        debug_assert!(addr as usize + self.config.frame_size as usize <= self.umem.len);
+        // SAFETY: free frames are frame-aligned offsets into the UMEM, which lives as long as
+        // `self`, and aren't accessed by the kernel until they are submitted on the TX ring.
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                (self.umem.ptr as *mut u8).add(addr as usize),
+                self.config.frame_size as usize,
+            )
+        }
+    }
+}
+
+impl AsFd for XdpSocket {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for XdpSocket {
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+fn family_mismatch() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "address family does not match local address",
+    )
+}
+
+fn set_option<T>(fd: &OwnedFd, name: libc::c_int, value: &T) -> io::Result<()> {
+    // SAFETY: the option length passed matches the `T` pointed to
+    let rc = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            SOL_XDP,
+            name,
+            value as *const T as *const _,
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    match rc {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// A memory mapping, unmapped on drop
+#[derive(Debug)]
+struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mmap {
+    fn new(fd: libc::c_int, len: usize, flags: libc::c_int, offset: u64) -> io::Result<Self> {
+        // SAFETY: a null hint lets the kernel choose a fresh range, so no existing memory is
+        // replaced
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                flags,
+                fd,
+                offset as libc::off_t,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr, len })
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: the range was mapped by `Mmap::new`, and no references into it outlive `self`
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+/// A single-producer single-consumer ring shared with the kernel
+///
+/// We are the producer of the fill and TX rings, and the consumer of the completion and RX rings.
+#[derive(Debug)]
+struct Ring<T> {
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    flags: *const AtomicU32,
+    descs: *mut T,
+    size: u32,
+    _map: Mmap,
+}
+
+impl<T: Copy> Ring<T> {
+    fn map(fd: &OwnedFd, offsets: &RingOffset, size: u32, pgoff: u64) -> io::Result<Self> {
+        let len = offsets.desc as usize + size as usize * mem::size_of::<T>();
+        let map = Mmap::new(
+            fd.as_raw_fd(),
+            len,
+            libc::MAP_SHARED | libc::MAP_POPULATE,
+            pgoff,
+        )?;
+        let base = map.ptr as *mut u8;
+        // SAFETY: the kernel-provided offsets lie within the mapping, which is `len` bytes long
+        unsafe {
+            Ok(Self {
+                producer: base.add(offsets.producer as usize).cast(),
+                consumer: base.add(offsets.consumer as usize).cast(),
+                flags: base.add(offsets.flags as usize).cast(),
+                descs: base.add(offsets.desc as usize).cast(),
+                size,
+                _map: map,
+            })
+        }
+    }
+
+    fn producer(&self) -> &AtomicU32 {
+        // SAFETY: points into `_map`, which lives as long as `self`, and is aligned by the kernel
+        unsafe { &*self.producer }
+    }
+
+    fn consumer(&self) -> &AtomicU32 {
+        // SAFETY: as for `producer`
+        unsafe { &*self.consumer }
+    }
+
+    fn needs_wakeup(&self) -> bool {
+        // SAFETY: as for `producer`
+        unsafe { &*self.flags }.load(Ordering::Relaxed) & XDP_RING_NEED_WAKEUP != 0
+    }
+
+    /// Number of entries that can be produced
+    fn vacant(&self) -> u32 {
+        let used = self
+            .producer()
+            .load(Ordering::Relaxed)
+            .wrapping_sub(self.consumer().load(Ordering::Acquire));
+        self.size - used
+    }
+
+    /// Write the `i`th entry after the current producer position
+    fn put(&mut self, i: u32, value: T) {
+        let index = self.producer().load(Ordering::Relaxed).wrapping_add(i) & (self.size - 1);
+        // SAFETY: `index` is masked to within the `size` descriptors mapped, and entries past the
+        // producer position are owned by us until committed
+        unsafe { self.descs.add(index as usize).write(value) };
+    }
+
+    /// Publish `n` entries written by `put`
+    fn commit(&mut self, n: u32) {
+        let producer = self.producer().load(Ordering::Relaxed);
+        self.producer()
+            .store(producer.wrapping_add(n), Ordering::Release);
+    }
+
+    /// Number of entries that can be consumed
+    fn available(&self) -> u32 {
+        self.producer()
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.consumer().load(Ordering::Relaxed))
+    }
+
+    /// Read the `i`th entry after the current consumer position
+    fn get(&self, i: u32) -> T {
+        let index = self.consumer().load(Ordering::Relaxed).wrapping_add(i) & (self.size - 1);
+        // SAFETY: `index` is masked to within the `size` descriptors mapped, and entries before
+        // the producer position are owned by us until released
+        unsafe { self.descs.add(index as usize).read() }
+    }
+
+    /// Return `n` entries read by `get` to the producer
+    fn release(&mut self, n: u32) {
+        let consumer = self.consumer().load(Ordering::Relaxed);
+        self.consumer()
+            .store(consumer.wrapping_add(n), Ordering::Release);
+    }
+}
+
+/// Write an Ethernet frame carrying `payload` from `source` to `destination` into `frame`,
+/// returning its length
+fn encode(
+    frame: &mut [u8],
+    source_mac: [u8; 6],
+    destination_mac: [u8; 6],
+    source: SocketAddr,
+    destination: SocketAddr,
+    ecn: u8,
+    payload: &[u8],
+) -> usize {
+    frame[0..6].copy_from_slice(&destination_mac);
+    frame[6..12].copy_from_slice(&source_mac);
+    let udp_len = UDP_HEADER_LEN + payload.len();
+    let ip_len = match (source.ip(), destination.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            frame[12..14].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+            let ip = &mut frame[ETHERNET_HEADER_LEN..ETHERNET_HEADER_LEN + IPV4_HEADER_LEN];
+            ip[0] = 0x45;
+            ip[1] = ecn;
+            ip[2..4].copy_from_slice(&((IPV4_HEADER_LEN + udp_len) as u16).to_be_bytes());
+            // Identification, and the don't fragment flag
+            ip[4..8].copy_from_slice(&[0, 0, 0x40, 0]);
+            ip[8] = TTL;
+            ip[9] = IPPROTO_UDP;
+            ip[10..12].fill(0);
+            ip[12..16].copy_from_slice(&src.octets());
+            ip[16..20].copy_from_slice(&dst.octets());
+            let checksum = finish_checksum(sum(0, ip));
+            ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+            IPV4_HEADER_LEN
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            frame[12..14].copy_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+            let ip = &mut frame[ETHERNET_HEADER_LEN..ETHERNET_HEADER_LEN + IPV6_HEADER_LEN];
+            ip[0] = 0x60;
+            ip[1] = ecn << 4;
+            ip[2..4].fill(0);
+            ip[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+            ip[6] = IPPROTO_UDP;
+            ip[7] = TTL;
+            ip[8..24].copy_from_slice(&src.octets());
+            ip[24..40].copy_from_slice(&dst.octets());
+            IPV6_HEADER_LEN
+        }
+        _ => unreachable!("address families are checked by the caller"),
+    };
+
+    let start = ETHERNET_HEADER_LEN + ip_len;
+    let udp = &mut frame[start..start + udp_len];
+    udp[0..2].copy_from_slice(&source.port().to_be_bytes());
+    udp[2..4].copy_from_slice(&destination.port().to_be_bytes());
+    udp[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+    udp[6..8].fill(0);
+    udp[UDP_HEADER_LEN..].copy_from_slice(payload);
+    let checksum = match finish_checksum(sum(
+        pseudo_header_sum(source.ip(), destination.ip(), udp_len),
+        udp,
+    )) {
+        // Zero denotes the absence of a checksum
+        0 => 0xffff,
+        x => x,
+    };
+    udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+    start + udp_len
+}
+
+/// A datagram parsed from a received frame
+struct Datagram {
+    source: SocketAddr,
+    destination: IpAddr,
+    ecn: u8,
+    payload: Range<usize>,
+}
+
+/// Parse a frame, returning `None` unless it's a valid UDP datagram addressed to `local`
+fn decode(frame: &[u8], local: SocketAddr) -> Option<Datagram> {
+    let mut offset = ETHERNET_HEADER_LEN;
+    let mut ethertype = u16::from_be_bytes(frame.get(12..14)?.try_into().ok()?);
+    if ethertype == ETHERTYPE_VLAN {
+        ethertype = u16::from_be_bytes(frame.get(16..18)?.try_into().ok()?);
+        offset += 4;
+    }
+
+    let ip = frame.get(offset..)?;
+    let (source, destination, ecn, udp_start, udp_end) = match (ethertype, local) {
+        (ETHERTYPE_IPV4, SocketAddr::V4(_)) => {
+            let header_len = usize::from(ip.first()? & 0x0f) * 4;
+            let header = ip.get(..header_len)?;
+            let total_len = usize::from(u16::from_be_bytes([header[2], header[3]]));
+            if header[0] >> 4 != 4
+                || header_len < IPV4_HEADER_LEN
+                || finish_checksum(sum(0, header)) != 0
+                || total_len > ip.len()
+                // More fragments, or a non-zero fragment offset
+                || u16::from_be_bytes([header[6], header[7]]) & 0x3fff != 0
+                || header[9] != IPPROTO_UDP
+            {
+                return None;
+            }
+            let src = Ipv4Addr::from(<[u8; 4]>::try_from(&header[12..16]).ok()?);
+            let dst = Ipv4Addr::from(<[u8; 4]>::try_from(&header[16..20]).ok()?);
+            (
+                IpAddr::V4(src),
+                IpAddr::V4(dst),
+                header[1] & 0b11,
+                header_len,
+                total_len,
+            )
+        }
+        (ETHERTYPE_IPV6, SocketAddr::V6(_)) => {
+            let header = ip.get(..IPV6_HEADER_LEN)?;
+            let payload_len = usize::from(u16::from_be_bytes([header[4], header[5]]));
+            if header[0] >> 4 != 6
+                || header[6] != IPPROTO_UDP
+                || IPV6_HEADER_LEN + payload_len > ip.len()
+            {
+                return None;
+            }
+            let src = Ipv6Addr::from(<[u8; 16]>::try_from(&header[8..24]).ok()?);
+            let dst = Ipv6Addr::from(<[u8; 16]>::try_from(&header[24..40]).ok()?);
+            let traffic_class = (header[0] << 4) | (header[1] >> 4);
+            (
+                IpAddr::V6(src),
+                IpAddr::V6(dst),
+                traffic_class & 0b11,
+                IPV6_HEADER_LEN,
+                IPV6_HEADER_LEN + payload_len,
+            )
+        }
+        _ => return None,
+    };
+    if destination != local.ip() {
+        return None;
+    }
+
+    let udp = ip.get(udp_start..udp_end)?;
+    let header = udp.get(..UDP_HEADER_LEN)?;
+    let udp_len = usize::from(u16::from_be_bytes([header[4], header[5]]));
+    if u16::from_be_bytes([header[2], header[3]]) != local.port()
+        || udp_len < UDP_HEADER_LEN
+        || udp_len > udp.len()
+    {
+        return None;
+    }
+    let udp = &udp[..udp_len];
+    let checksum = u16::from_be_bytes([header[6], header[7]]);
+    // The checksum is optional for IPv4 only
+    if (checksum != 0 || source.is_ipv6())
+        && finish_checksum(sum(pseudo_header_sum(source, destination, udp_len), udp)) != 0
+    {
+        return None;
+    }
+
+    let payload_start = offset + udp_start + UDP_HEADER_LEN;
+    Some(Datagram {
+        source: SocketAddr::new(source, u16::from_be_bytes([header[0], header[1]])),
+        destination,
+        ecn,
+        payload: payload_start..offset + udp_start + udp_len,
+    })
+}
+
+fn header_len(destination: &SocketAddr) -> usize {
+    let ip = match destination {
+        SocketAddr::V4(_) => IPV4_HEADER_LEN,
+        SocketAddr::V6(_) => IPV6_HEADER_LEN,
+    };
+    ETHERNET_HEADER_LEN + ip + UDP_HEADER_LEN
+}
+
+fn pseudo_header_sum(source: IpAddr, destination: IpAddr, udp_len: usize) -> u32 {
+    let mut acc = 0;
+    match (source, destination) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            acc = sum(acc, &src.octets());
+            acc = sum(acc, &dst.octets());
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            acc = sum(acc, &src.octets());
+            acc = sum(acc, &dst.octets());
+        }
+        _ => unreachable!("IP headers carry addresses of a single family"),
+    }
+    acc + u32::from(IPPROTO_UDP) + udp_len as u32
+}
+
+/// Add `data` to a running internet checksum, as big-endian 16-bit words
+///
+/// `data` must have an even length unless it's the last to be added.
+fn sum(mut acc: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for chunk in &mut chunks {
+        acc += u32::from(u16::from_be_bytes([chunk[0], chunk[1]]));
+    }
+    if let [last] = chunks.remainder() {
+        acc += u32::from(*last) << 8;
+    }
+    acc
+}
+
+fn finish_checksum(mut acc: u32) -> u16 {
+    while acc >> 16 != 0 {
+        acc = (acc & 0xffff) + (acc >> 16);
+    }
+    !(acc as u16)
+}
+
+// linux/if_xdp.h
+
+#[repr(C)]
+struct SockaddrXdp {
+    family: u16,
+    flags: u16,
+    ifindex: u32,
+    queue_id: u32,
+    shared_umem_fd: u32,
+}
+
+#[repr(C)]
+struct RingOffset {
+    producer: u64,
+    consumer: u64,
+    desc: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+struct MmapOffsets {
+    rx: RingOffset,
+    tx: RingOffset,
+    fr: RingOffset,
+    cr: RingOffset,
+}
+
+#[repr(C)]
+struct UmemReg {
+    addr: u64,
+    len: u64,
+    chunk_size: u32,
+    headroom: u32,
+    flags: u32,
+    tx_metadata_len: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct XdpDesc {
+    addr: u64,
+    len: u32,
+    options: u32,
+}
+
+const AF_XDP: libc::c_int = 44;
+const SOL_XDP: libc::c_int = 283;
+const XDP_COPY: u16 = 1 << 1;
+const XDP_ZEROCOPY: u16 = 1 << 2;
+const XDP_USE_NEED_WAKEUP: u16 = 1 << 3;
+const XDP_RING_NEED_WAKEUP: u32 = 1 << 0;
+const XDP_MMAP_OFFSETS: libc::c_int = 1;
+const XDP_RX_RING: libc::c_int = 2;
+const XDP_TX_RING: libc::c_int = 3;
+const XDP_UMEM_REG: libc::c_int = 4;
+const XDP_UMEM_FILL_RING: libc::c_int = 5;
+const XDP_UMEM_COMPLETION_RING: libc::c_int = 6;
+const XDP_PGOFF_RX_RING: u64 = 0;
+const XDP_PGOFF_TX_RING: u64 = 0x80000000;
+const XDP_UMEM_PGOFF_FILL_RING: u64 = 0x100000000;
+const XDP_UMEM_PGOFF_COMPLETION_RING: u64 = 0x180000000;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const UDP_HEADER_LEN: usize = 8;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IPPROTO_UDP: u8 = 17;
+const TTL: u8 = 64;
+/// Upper bound on segments per transmit, matching the Linux limit for UDP GSO
+const MAX_SEGMENTS: usize = 64;
@@ -31,6 +31,54 @@ fn basic() {
     );
 }
 
+#[test]
+fn send_batch() {
+    let send = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0))
+        .or_else(|_| UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)))
+        .unwrap();
+    let recv = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0))
+        .or_else(|_| UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)))
+        .unwrap();
+    let send_state = UdpSocketState::new((&send).into()).unwrap();
+    let recv_state = UdpSocketState::new((&recv).into()).unwrap();
+    recv.set_nonblocking(false).unwrap();
+
+    let dst_addr = recv.local_addr().unwrap();
+    let payloads = (0..8u8)
+        .map(|i| vec![i; 64 + i as usize])
+        .collect::<Vec<_>>();
+    let transmits = payloads
+        .iter()
+        .map(|contents| Transmit {
+            destination: dst_addr,
+            ecn: None,
+            contents,
+            segment_size: None,
+            src_ip: None,
+        })
+        .collect::<Vec<_>>();
+    let mut sent = 0;
+    while sent < transmits.len() {
+        sent += send_state
+            .send_batch((&send).into(), &transmits[sent..])
+            .unwrap();
+    }
+
+    let mut buf = [0; u16::MAX as usize];
+    let mut meta = RecvMeta::default();
+    for expected in &payloads {
+        let n = recv_state
+            .recv(
+                (&recv).into(),
+                &mut [IoSliceMut::new(&mut buf)],
+                slice::from_mut(&mut meta),
+            )
+            .unwrap();
+        assert_eq!(n, 1);
+        assert_eq!(&buf[..meta.len], &expected[..]);
+    }
+}
+
 #[test]
 fn ecn_v6() {
     let send = Socket::from(UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).unwrap());
@@ -183,6 +231,102 @@ fn gso() {
     );
 }
 
+#[test]
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+fn xdp_send() {
+    use quinn_udp::{XdpConfig, XdpSocket};
+    use std::{
+        io::Read,
+        mem,
+        os::fd::{AsRawFd, FromRawFd},
+        time::{Duration, Instant},
+    };
+
+    // Only traffic redirected by an XDP program is received through AF_XDP, and the kernel drops
+    // loopback-addressed packets that it didn't route itself, so capture the frames instead
+    let capture = unsafe {
+        libc::socket(
+            libc::AF_PACKET,
+            libc::SOCK_RAW | libc::SOCK_NONBLOCK,
+            (libc::ETH_P_ALL as u16).to_be().into(),
+        )
+    };
+    if capture == -1 {
+        eprintln!("skipping AF_XDP test: {}", std::io::Error::last_os_error());
+        return;
+    }
+    let capture = unsafe { Socket::from_raw_fd(capture) };
+    let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+    addr.sll_family = libc::AF_PACKET as u16;
+    addr.sll_protocol = (libc::ETH_P_ALL as u16).to_be();
+    addr.sll_ifindex = unsafe { libc::if_nametoindex(b"lo\0".as_ptr().cast()) } as i32;
+    let rc = unsafe {
+        libc::bind(
+            capture.as_raw_fd(),
+            &addr as *const _ as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+        )
+    };
+    assert_eq!(rc, 0);
+
+    let local = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 4433);
+    let mut config = XdpConfig::new("lo", 0, local, [0; 6], [0; 6]);
+    config.frame_count(64);
+    let mut send = XdpSocket::new(&config).unwrap();
+    // The loopback interface has no driver support for zero-copy mode
+    assert!(!send.is_zero_copy());
+
+    let contents = (0..200).map(|i| i as u8).collect::<Vec<_>>();
+    send.send(&Transmit {
+        destination: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 4434),
+        ecn: Some(EcnCodepoint::Ect0),
+        contents: &contents,
+        segment_size: Some(100),
+        src_ip: None,
+    })
+    .unwrap();
+
+    let mut received = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while received.len() < contents.len() {
+        let mut frame = [0; 2048];
+        let len = match (&capture).read(&mut frame) {
+            Ok(len) => len,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                assert!(Instant::now() < deadline, "timed out");
+                std::thread::sleep(Duration::from_millis(1));
+                continue;
+            }
+            Err(e) => panic!("capture failed: {e}"),
+        };
+        let (ip, udp) = (&frame[14..34], &frame[34..len]);
+        if frame[12..14] != [0x08, 0x00] || ip[9] != 17 || udp[0..4] != [0x11, 0x51, 0x11, 0x52] {
+            continue;
+        }
+        assert_eq!(ip[1], EcnCodepoint::Ect0 as u8);
+        assert_eq!(checksum(0, ip), 0);
+        let pseudo_header = checksum(
+            u32::from(u16::from_be_bytes([udp[4], udp[5]])) + 17,
+            &ip[12..20],
+        );
+        assert_eq!(checksum(!pseudo_header as u32, udp), 0);
+        received.extend_from_slice(&udp[8..]);
+    }
+    assert_eq!(received, contents);
+}
+
+/// Internet checksum of `data`, continuing from a partial sum
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+fn checksum(mut acc: u32, data: &[u8]) -> u16 {
+    for chunk in data.chunks(2) {
+        acc += u32::from(u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]));
+    }
+    while acc >> 16 != 0 {
+        acc = (acc & 0xffff) + (acc >> 16);
+    }
+    !(acc as u16)
+}
+
 fn test_send_recv(send: &Socket, recv: &Socket, transmit: Transmit) {
     let send_state = UdpSocketState::new(send.into()).unwrap();
     let recv_state = UdpSocketState::new(recv.into()).unwrap();
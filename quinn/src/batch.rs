@@ -0,0 +1,324 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, IoSliceMut},
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use bytes::BytesMut;
+use rustc_hash::FxHashMap;
+use udp::{EcnCodepoint, RecvMeta, Transmit, BATCH_SIZE};
+
+use crate::runtime::{AsyncUdpSocket, UdpPoller};
+
+/// Limits on how an endpoint batches the datagrams of its connections
+///
+/// See [`Endpoint::set_transmit_batching()`](crate::Endpoint::set_transmit_batching).
+#[derive(Debug, Clone)]
+pub struct TransmitBatchConfig {
+    max_batch: usize,
+    max_per_connection: usize,
+}
+
+impl TransmitBatchConfig {
+    /// Maximum number of transmits handed to the socket at once
+    ///
+    /// Transmits queued beyond this wait for the next batch. Defaults to
+    /// [`udp::BATCH_SIZE`], the most the platform sends in a single system call.
+    pub fn max_batch(&mut self, value: usize) -> &mut Self {
+        self.max_batch = value.max(1);
+        self
+    }
+
+    /// Maximum number of transmits a single connection may have queued
+    ///
+    /// A connection reaching this limit waits for its transmits to be sent before queueing more,
+    /// so that busy connections can't crowd others out of a batch. Defaults to 4.
+    pub fn max_per_connection(&mut self, value: usize) -> &mut Self {
+        self.max_per_connection = value.max(1);
+        self
+    }
+}
+
+impl Default for TransmitBatchConfig {
+    fn default() -> Self {
+        Self {
+            max_batch: BATCH_SIZE,
+            max_per_connection: 4,
+        }
+    }
+}
+
+/// Collects the transmits of an endpoint's connections, to be sent together by the endpoint driver
+#[derive(Debug)]
+pub(crate) struct TransmitBatcher {
+    socket: Arc<dyn AsyncUdpSocket>,
+    config: TransmitBatchConfig,
+    state: Mutex<BatchState>,
+}
+
+impl TransmitBatcher {
+    pub(crate) fn new(socket: Arc<dyn AsyncUdpSocket>, config: TransmitBatchConfig) -> Arc<Self> {
+        let poller = socket.clone().create_io_poller();
+        Arc::new(Self {
+            socket,
+            state: Mutex::new(BatchState {
+                queue: VecDeque::with_capacity(config.max_batch),
+                queued: FxHashMap::default(),
+                next_id: 0,
+                free: Vec::new(),
+                poller,
+                driver: None,
+                waiters: Vec::new(),
+                batches: 0,
+                transmits: 0,
+            }),
+            config,
+        })
+    }
+
+    /// Socket through which a new connection queues its transmits
+    pub(crate) fn connection_socket(self: &Arc<Self>) -> Arc<dyn AsyncUdpSocket> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        Arc::new(BatchSocket {
+            batcher: self.clone(),
+            id,
+        })
+    }
+
+    /// Send queued transmits, registering the endpoint driver to be woken when more are queued
+    ///
+    /// Returns `true` if transmits remain that could be sent right away.
+    pub(crate) fn flush(&self, cx: &mut Context) -> io::Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        if !state
+            .driver
+            .as_ref()
+            .is_some_and(|w| w.will_wake(cx.waker()))
+        {
+            state.driver = Some(cx.waker().clone());
+        }
+
+        for _ in 0..MAX_BATCHES_PER_FLUSH {
+            if state.queue.is_empty() {
+                return Ok(false);
+            }
+            if state.poller.as_mut().poll_writable(cx)?.is_pending() {
+                return Ok(false);
+            }
+
+            let count = state.queue.len().min(self.config.max_batch);
+            let transmits = state
+                .queue
+                .iter()
+                .take(count)
+                .map(QueuedTransmit::as_transmit)
+                .collect::<Vec<_>>();
+            let sent = match self.socket.try_send_batch(&transmits) {
+                Ok(sent) => sent,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => {
+                    // Treat the transmit like a lost packet rather than failing every connection
+                    tracing::warn!(%e, "dropping batched transmit");
+                    1
+                }
+            };
+            drop(transmits);
+
+            state.batches += 1;
+            state.transmits += sent as u64;
+            for transmit in state.queue.drain(..sent) {
+                let queued = state.queued.get_mut(&transmit.connection).unwrap();
+                *queued -= 1;
+                if *queued == 0 {
+                    state.queued.remove(&transmit.connection);
+                }
+                state.free.push(transmit.contents);
+            }
+            for waker in state.waiters.drain(..) {
+                waker.wake();
+            }
+        }
+
+        Ok(!state.queue.is_empty())
+    }
+
+    /// Number of batches and transmits sent so far
+    pub(crate) fn stats(&self) -> (u64, u64) {
+        let state = self.state.lock().unwrap();
+        (state.batches, state.transmits)
+    }
+
+    /// Whether no connection uses the batcher anymore and everything it queued was sent
+    pub(crate) fn is_idle(self: &Arc<Self>) -> bool {
+        Arc::strong_count(self) == 1 && self.state.lock().unwrap().queue.is_empty()
+    }
+
+    pub(crate) fn config(&self) -> &TransmitBatchConfig {
+        &self.config
+    }
+}
+
+struct BatchState {
+    queue: VecDeque<QueuedTransmit>,
+    /// Number of transmits in `queue` for each connection that has any
+    queued: FxHashMap<u64, usize>,
+    next_id: u64,
+    /// Buffers of sent transmits, for reuse
+    free: Vec<Vec<u8>>,
+    /// Write-readiness of the underlying socket, polled by the endpoint driver
+    poller: Pin<Box<dyn UdpPoller>>,
+    /// The endpoint driver, woken when a transmit is queued
+    driver: Option<Waker>,
+    /// Connections waiting for room in the queue
+    waiters: Vec<Waker>,
+    batches: u64,
+    transmits: u64,
+}
+
+impl BatchState {
+    fn has_room(&self, config: &TransmitBatchConfig, connection: u64) -> bool {
+        self.queue.len() < config.max_batch * QUEUED_BATCHES
+            && self.queued.get(&connection).map_or(0, |&n| n) < config.max_per_connection
+    }
+}
+
+impl fmt::Debug for BatchState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BatchState")
+            .field("queued", &self.queue.len())
+            .field("connections", &self.queued.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+struct QueuedTransmit {
+    connection: u64,
+    destination: SocketAddr,
+    ecn: Option<EcnCodepoint>,
+    contents: Vec<u8>,
+    segment_size: Option<usize>,
+    src_ip: Option<IpAddr>,
+}
+
+impl QueuedTransmit {
+    fn as_transmit(&self) -> Transmit<'_> {
+        Transmit {
+            destination: self.destination,
+            ecn: self.ecn,
+            contents: &self.contents,
+            segment_size: self.segment_size,
+            src_ip: self.src_ip,
+        }
+    }
+}
+
+/// A connection's view of a [`TransmitBatcher`]
+#[derive(Debug)]
+struct BatchSocket {
+    batcher: Arc<TransmitBatcher>,
+    id: u64,
+}
+
+impl AsyncUdpSocket for BatchSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        Box::pin(BatchPoller {
+            batcher: self.batcher.clone(),
+            id: self.id,
+        })
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        let batcher = &*self.batcher;
+        let mut state = batcher.state.lock().unwrap();
+        if !state.has_room(&batcher.config, self.id) {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let mut contents = state.free.pop().unwrap_or_default();
+        contents.clear();
+        contents.extend_from_slice(transmit.contents);
+        state.queue.push_back(QueuedTransmit {
+            connection: self.id,
+            destination: transmit.destination,
+            ecn: transmit.ecn,
+            contents,
+            segment_size: transmit.segment_size,
+            src_ip: transmit.src_ip,
+        });
+        *state.queued.entry(self.id).or_default() += 1;
+        if let Some(driver) = &state.driver {
+            driver.wake_by_ref();
+        }
+        Ok(())
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        self.batcher.socket.poll_recv(cx, bufs, meta)
+    }
+
+    fn recv_owned(&self) -> bool {
+        self.batcher.socket.recv_owned()
+    }
+
+    fn poll_recv_owned(
+        &self,
+        cx: &mut Context,
+        datagrams: &mut Vec<(RecvMeta, BytesMut)>,
+    ) -> Poll<io::Result<()>> {
+        self.batcher.socket.poll_recv_owned(cx, datagrams)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.batcher.socket.local_addr()
+    }
+
+    fn max_transmit_segments(&self) -> usize {
+        self.batcher.socket.max_transmit_segments()
+    }
+
+    fn max_receive_segments(&self) -> usize {
+        self.batcher.socket.max_receive_segments()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.batcher.socket.may_fragment()
+    }
+}
+
+/// Ready when a connection may queue another transmit
+#[derive(Debug)]
+struct BatchPoller {
+    batcher: Arc<TransmitBatcher>,
+    id: u64,
+}
+
+impl UdpPoller for BatchPoller {
+    fn poll_writable(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let batcher = &*self.batcher;
+        let mut state = batcher.state.lock().unwrap();
+        if state.has_room(&batcher.config, self.id) {
+            return Poll::Ready(Ok(()));
+        }
+        state.waiters.push(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// Number of batches the queue can hold before connections must wait
+const QUEUED_BATCHES: usize = 4;
+
+/// Number of batches sent by a single call to [`TransmitBatcher::flush()`], to bound the time
+/// spent by the endpoint driver before it attends to incoming datagrams
+const MAX_BATCHES_PER_FLUSH: usize = 8;
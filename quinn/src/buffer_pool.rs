@@ -0,0 +1,201 @@
+use std::{
+    collections::VecDeque,
+    io::{self, IoSliceMut},
+    mem,
+    task::{Context, Poll},
+};
+
+use bytes::BytesMut;
+use udp::{RecvMeta, BATCH_SIZE};
+
+use crate::runtime::AsyncUdpSocket;
+
+/// Buffers for receiving a batch of datagrams, which are handed over without copying
+///
+/// Each datagram stays in the buffer it was received into, so that packet payloads and the stream
+/// data sliced out of them by `quinn-proto` share it until the application has read them. The
+/// buffer is then recycled through a [`BufferPool`] rather than freed.
+#[derive(Debug)]
+pub(crate) struct RecvBuffers {
+    bufs: [BytesMut; BATCH_SIZE],
+    pool: BufferPool,
+}
+
+impl RecvBuffers {
+    /// Create buffers of `buf_len` bytes, each large enough for the datagrams of one [`RecvMeta`]
+    pub(crate) fn new(buf_len: usize) -> Self {
+        let mut pool = BufferPool::new(buf_len);
+        Self {
+            bufs: std::array::from_fn(|_| pool.get()),
+            pool,
+        }
+    }
+
+    /// Receive a batch from `socket`, passing the datagrams read into each buffer to `f`
+    pub(crate) fn poll_recv(
+        &mut self,
+        cx: &mut Context,
+        socket: &dyn AsyncUdpSocket,
+        mut f: impl FnMut(RecvMeta, BytesMut),
+    ) -> Poll<io::Result<()>> {
+        let mut metas = [RecvMeta::default(); BATCH_SIZE];
+        let msgs = {
+            let mut bufs = self.bufs.iter_mut().map(|buf| IoSliceMut::new(buf));
+            let mut iovs: [IoSliceMut; BATCH_SIZE] =
+                std::array::from_fn(|_| bufs.next().expect("BATCH_SIZE elements"));
+            match socket.poll_recv(cx, &mut iovs, &mut metas) {
+                Poll::Ready(Ok(msgs)) => msgs,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        };
+        for (meta, buf) in metas.iter().zip(&mut self.bufs).take(msgs) {
+            // A buffer mostly left empty, e.g. by a lone datagram on a GRO-enabled socket, would
+            // tie up much more memory than its contents need for as long as they are retained
+            let data = match meta.len * 2 >= buf.len() {
+                true => {
+                    let buf = mem::replace(buf, self.pool.get());
+                    self.pool.lend(buf, meta.len)
+                }
+                false => BytesMut::from(&buf[..meta.len]),
+            };
+            f(*meta, data);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Fixed-size buffers, reused once everything handed out of them has been dropped
+#[derive(Debug)]
+pub(crate) struct BufferPool {
+    buf_len: usize,
+    /// Unused ends of buffers whose start was handed out, oldest first
+    lent: VecDeque<BytesMut>,
+}
+
+impl BufferPool {
+    pub(crate) fn new(buf_len: usize) -> Self {
+        Self {
+            buf_len,
+            lent: VecDeque::new(),
+        }
+    }
+
+    /// Take a buffer of `buf_len` bytes, reclaiming one lent out earlier if possible
+    pub(crate) fn get(&mut self) -> BytesMut {
+        // Buffers lent out longest ago are the likeliest to have been released
+        for _ in 0..self.lent.len().min(RECLAIM_ATTEMPTS) {
+            let mut buf = self.lent.pop_front().unwrap();
+            if buf.try_reclaim(self.buf_len) {
+                // Safety: the whole allocation was initialized by `BytesMut::zeroed()`, and is
+                // only ever written to since
+                unsafe { buf.set_len(self.buf_len) };
+                return buf;
+            }
+            self.lent.push_back(buf);
+        }
+        BytesMut::zeroed(self.buf_len)
+    }
+
+    /// Hand out the first `len` bytes of `buf`, a buffer from [`get()`](Self::get)
+    ///
+    /// The buffer is reused once the returned bytes, and everything split from them, are dropped.
+    pub(crate) fn lend(&mut self, mut buf: BytesMut, len: usize) -> BytesMut {
+        let mut rest = buf.split_off(len);
+        rest.clear();
+        if self.lent.len() == MAX_LENT {
+            // Let the buffer be freed whenever it is released instead
+            self.lent.pop_front();
+        }
+        self.lent.push_back(rest);
+        buf
+    }
+}
+
+/// Number of lent buffers checked for release before allocating a new one
+const RECLAIM_ATTEMPTS: usize = 4;
+
+/// Number of lent buffers tracked for reuse
+const MAX_LENT: usize = 4 * BATCH_SIZE;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reclaim_released() {
+        let mut pool = BufferPool::new(1500);
+        let mut buf = pool.get();
+        buf[..3].copy_from_slice(b"abc");
+        let ptr = buf.as_ptr();
+        let data = pool.lend(buf, 3);
+        assert_eq!(&data[..], b"abc");
+
+        // Still in use
+        let held = data.freeze().slice(1..);
+        let other = pool.get();
+        assert_ne!(other.as_ptr(), ptr);
+        assert_eq!(other.len(), 1500);
+
+        drop(held);
+        let reused = pool.get();
+        assert_eq!(reused.as_ptr(), ptr);
+        assert_eq!(reused.len(), 1500);
+        assert_eq!(&reused[..3], b"abc");
+    }
+
+    #[cfg(feature = "runtime-tokio")]
+    #[tokio::test]
+    async fn recv_handoff() {
+        use crate::runtime::Runtime;
+
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let socket = crate::TokioRuntime.wrap_udp_socket(socket).unwrap();
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut bufs = RecvBuffers::new(1200);
+
+        // A datagram filling most of its buffer is handed over in place
+        let start = bufs.bufs[0].as_ptr();
+        sender.send_to(&[1; 1000], addr).unwrap();
+        let data = recv(&mut bufs, &*socket).await;
+        assert_eq!(&data[..], [1; 1000]);
+        assert_eq!(data.as_ptr(), start);
+        assert_ne!(bufs.bufs[0].as_ptr(), start);
+
+        // A small one is copied out, leaving the buffer for the next batch
+        let start = bufs.bufs[0].as_ptr();
+        sender.send_to(&[2; 100], addr).unwrap();
+        let data = recv(&mut bufs, &*socket).await;
+        assert_eq!(&data[..], [2; 100]);
+        assert_ne!(data.as_ptr(), start);
+        assert_eq!(bufs.bufs[0].as_ptr(), start);
+    }
+
+    #[cfg(feature = "runtime-tokio")]
+    async fn recv(bufs: &mut RecvBuffers, socket: &dyn AsyncUdpSocket) -> BytesMut {
+        let mut received = Vec::new();
+        std::future::poll_fn(|cx| bufs.poll_recv(cx, socket, |_, data| received.push(data)))
+            .await
+            .unwrap();
+        assert_eq!(received.len(), 1);
+        received.pop().unwrap()
+    }
+
+    #[test]
+    fn bounded_tracking() {
+        let mut pool = BufferPool::new(16);
+        let held = (0..MAX_LENT + 1)
+            .map(|_| {
+                let buf = pool.get();
+                pool.lend(buf, 8)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(pool.lent.len(), MAX_LENT);
+        drop(held);
+        for _ in 0..MAX_LENT {
+            pool.get();
+        }
+        assert!(pool.lent.is_empty());
+    }
+}
@@ -2,10 +2,10 @@ use std::{
     any::Any,
     fmt,
     future::Future,
-    io,
+    io, mem,
     net::{IpAddr, SocketAddr},
-    pin::Pin,
-    sync::Arc,
+    pin::{pin, Pin},
+    sync::{Arc, Weak},
     task::{Context, Poll, Waker},
     time::{Duration, Instant},
 };
@@ -17,16 +17,21 @@ use thiserror::Error;
 use tokio::sync::{futures::Notified, mpsc, oneshot, Notify};
 use tracing::{debug_span, Instrument, Span};
 
+#[cfg(feature = "metrics")]
+use crate::metrics::ConnectionMetrics;
 use crate::{
     mutex::Mutex,
+    offload::HandshakePool,
     recv_stream::RecvStream,
     runtime::{AsyncTimer, AsyncUdpSocket, Runtime, UdpPoller},
-    send_stream::SendStream,
+    send_stream::{SendStream, WriteError},
+    timer_wheel::TimerWheel,
     udp_transmit, ConnectionEvent, VarInt,
 };
 use proto::{
-    congestion::Controller, ConnectionError, ConnectionHandle, ConnectionStats, Dir, EndpointEvent,
-    StreamEvent, StreamId,
+    congestion::Controller, transport_parameters::TransportParameters, ConnectionError,
+    ConnectionHandle, ConnectionStats, DatagramId, Dir, EndpointEvent, HandshakeEvents,
+    KeepAliveProbe, MigrateError, PathStats, StreamEvent, StreamGroupId, StreamId,
 };
 
 /// In-progress connection attempt future
@@ -39,14 +44,25 @@ pub struct Connecting {
 }
 
 impl Connecting {
+    /// Start driving `conn`
+    ///
+    /// If the connection's handshake was deferred, it is performed on `handshake_pool` before the
+    /// driver first runs.
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         handle: ConnectionHandle,
-        conn: proto::Connection,
+        mut conn: proto::Connection,
         endpoint_events: mpsc::UnboundedSender<(ConnectionHandle, EndpointEvent)>,
         conn_events: mpsc::UnboundedReceiver<ConnectionEvent>,
         socket: Arc<dyn AsyncUdpSocket>,
         runtime: Arc<dyn Runtime>,
+        timers: TimerWheel,
+        handshake_pool: Option<&HandshakePool>,
     ) -> Self {
+        let handshake_pool = handshake_pool.filter(|_| conn.has_deferred_handshake());
+        if handshake_pool.is_none() {
+            conn.handle_deferred_handshake(runtime.now());
+        }
         let (on_handshake_data_send, on_handshake_data_recv) = oneshot::channel();
         let (on_connected_send, on_connected_recv) = oneshot::channel();
         let conn = ConnectionRef::new(
@@ -58,11 +74,30 @@ impl Connecting {
             on_connected_send,
             socket,
             runtime.clone(),
+            timers,
         );
 
+        let handshake = handshake_pool.map(|pool| {
+            let (done, wait) = oneshot::channel();
+            let inner = conn.0.clone();
+            let span = Span::current();
+            pool.execute(move || {
+                let _guard = span.enter();
+                let state = &mut *inner.state.lock("handshake");
+                let now = state.runtime.now();
+                state.inner.handle_deferred_handshake(now);
+                let _ = done.send(());
+            });
+            wait
+        });
+
         let driver = ConnectionDriver(conn.clone());
         runtime.spawn(Box::pin(
             async {
+                if let Some(handshake) = handshake {
+                    // Datagrams received meanwhile stay queued for the driver
+                    let _ = handshake.await;
+                }
                 if let Err(e) = driver.await {
                     tracing::error!("I/O error: {e}");
                 }
@@ -77,6 +112,22 @@ impl Connecting {
         }
     }
 
+    /// Export the connection's statistics through `metrics` from now on
+    #[cfg(feature = "metrics")]
+    pub(crate) fn set_metrics(&self, metrics: ConnectionMetrics) {
+        self.conn
+            .as_ref()
+            .unwrap()
+            .state
+            .lock("set_metrics")
+            .metrics = Some(metrics);
+    }
+
+    /// Reference to the connection that doesn't keep it alive, for the endpoint's registry
+    pub(crate) fn downgrade(&self) -> Weak<ConnectionInner> {
+        Arc::downgrade(&self.conn.as_ref().unwrap().0)
+    }
+
     /// Convert into a 0-RTT or 0.5-RTT connection at the cost of weakened security
     ///
     /// Returns `Ok` immediately if the local endpoint is able to attempt sending 0/0.5-RTT data.
@@ -187,6 +238,19 @@ impl Connecting {
         let conn_ref: &ConnectionRef = self.conn.as_ref().expect("used after yielding Ready");
         conn_ref.state.lock("remote_address").inner.remote_address()
     }
+
+    /// How often the handshake was restarted at the server's request so far
+    ///
+    /// Allows telling a slow network from a server under load or not supporting the version
+    /// attempted. Will panic if called after `poll` has returned `Ready`.
+    pub fn handshake_events(&self) -> HandshakeEvents {
+        let conn_ref: &ConnectionRef = self.conn.as_ref().expect("used after yielding Ready");
+        conn_ref
+            .state
+            .lock("handshake_events")
+            .inner
+            .handshake_events()
+    }
 }
 
 impl Future for Connecting {
@@ -222,6 +286,32 @@ impl Future for ZeroRttAccepted {
     }
 }
 
+/// Future produced by [`Connection::send_datagram_tracked`]
+///
+/// Resolves to the fate of the datagram, or the error that closed the connection before it was
+/// determined.
+#[derive(Debug)]
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct DatagramTicket(oneshot::Receiver<Result<DatagramOutcome, ConnectionError>>);
+
+impl Future for DatagramTicket {
+    type Output = Result<DatagramOutcome, ConnectionError>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        Pin::new(&mut self.0)
+            .poll(cx)
+            .map(|x| x.unwrap_or(Err(ConnectionError::LocallyClosed)))
+    }
+}
+
+/// What became of a datagram sent with [`Connection::send_datagram_tracked`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DatagramOutcome {
+    /// The peer acknowledged the datagram
+    Acknowledged,
+    /// The datagram was declared lost, or dropped before being sent
+    Lost,
+}
+
 /// A future that drives protocol logic for a connection
 ///
 /// This future handles the protocol logic for a single connection, routing events from the
@@ -241,7 +331,9 @@ impl Future for ConnectionDriver {
 
     #[allow(unused_mut)] // MSRV
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let start = thread_time();
         let conn = &mut *self.0.state.lock("poll");
+        let datagrams = conn.driver_stats.datagrams;
 
         let span = debug_span!("drive", id = conn.handle.0);
         let _guard = span.enter();
@@ -254,8 +346,22 @@ impl Future for ConnectionDriver {
         // If a timer expires, there might be more to transmit. When we transmit something, we
         // might need to reset a timer. Hence, we must loop until neither happens.
         keep_going |= conn.drive_timer(cx);
+        keep_going |= conn.drive_write_deadlines(cx);
         conn.forward_endpoint_events();
         conn.forward_app_events(&self.0.shared);
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &mut conn.metrics {
+            metrics.update(conn.inner.stats());
+        }
+        // Acknowledgements and timers may have updated the congestion controller
+        self.0.shared.path_updated.notify_waiters();
+
+        let stats = &mut conn.driver_stats;
+        stats.wakeups += 1;
+        stats.max_datagrams_per_wakeup = stats
+            .max_datagrams_per_wakeup
+            .max(stats.datagrams - datagrams);
+        stats.cpu_time += thread_time().saturating_sub(start);
 
         if !conn.inner.is_drained() {
             if keep_going {
@@ -301,9 +407,26 @@ impl Connection {
         OpenUni {
             conn: &self.0,
             notify: self.0.shared.stream_budget_available[Dir::Uni as usize].notified(),
+            opts: StreamOpts::default(),
         }
     }
 
+    /// Initiate a new outgoing unidirectional stream configured by `opts`
+    ///
+    /// The options are applied as the stream is opened, before any other write can take place.
+    /// Completes once the stream is open and all of [`StreamOpts::initial_chunk`] has been
+    /// written. See also [`open_uni()`](Self::open_uni).
+    pub async fn open_uni_with(&self, opts: StreamOpts) -> Result<SendStream, WriteError> {
+        let mut open = pin!(OpenUni {
+            conn: &self.0,
+            notify: self.0.shared.stream_budget_available[Dir::Uni as usize].notified(),
+            opts,
+        });
+        let send = open.as_mut().await?;
+        let opts = mem::take(open.project().opts);
+        finish_open(send, opts).await
+    }
+
     /// Initiate a new outgoing bidirectional stream.
     ///
     /// Streams are cheap and instantaneous to open unless blocked by flow control. As a
@@ -318,6 +441,41 @@ impl Connection {
         OpenBi {
             conn: &self.0,
             notify: self.0.shared.stream_budget_available[Dir::Bi as usize].notified(),
+            opts: StreamOpts::default(),
+        }
+    }
+
+    /// Initiate a new outgoing bidirectional stream configured by `opts`
+    ///
+    /// The options are applied as the stream is opened, before any other write can take place.
+    /// Completes once the stream is open and all of [`StreamOpts::initial_chunk`] has been
+    /// written. See also [`open_bi()`](Self::open_bi).
+    pub async fn open_bi_with(
+        &self,
+        opts: StreamOpts,
+    ) -> Result<(SendStream, RecvStream), WriteError> {
+        let mut open = pin!(OpenBi {
+            conn: &self.0,
+            notify: self.0.shared.stream_budget_available[Dir::Bi as usize].notified(),
+            opts,
+        });
+        let (send, recv) = open.as_mut().await?;
+        let opts = mem::take(open.project().opts);
+        Ok((finish_open(send, opts).await?, recv))
+    }
+
+    /// Initiate a new outgoing bidirectional stream with the given send priority
+    ///
+    /// Unlike [`open_bi_with()`](Self::open_bi_with), no initial data is written, so this
+    /// completes as soon as the stream is open.
+    pub fn open_bi_with_priority(&self, priority: i32) -> OpenBi<'_> {
+        OpenBi {
+            conn: &self.0,
+            notify: self.0.shared.stream_budget_available[Dir::Bi as usize].notified(),
+            opts: StreamOpts {
+                priority,
+                ..StreamOpts::default()
+            },
         }
     }
 
@@ -354,6 +512,21 @@ impl Connection {
         }
     }
 
+    /// Receive up to `max` application datagrams at once, appending them to `buf`
+    ///
+    /// Waits until at least one datagram is available, then drains as many as are buffered, up to
+    /// `max`, under a single lock acquisition. Yields the number of datagrams received. More
+    /// efficient than repeated calls to [`read_datagram()`](Self::read_datagram) for high-rate
+    /// workloads.
+    pub fn read_datagrams<'a>(&'a self, buf: &'a mut Vec<Bytes>, max: usize) -> ReadDatagrams<'a> {
+        ReadDatagrams {
+            conn: &self.0,
+            buf,
+            max,
+            notify: self.0.shared.datagram_received.notified(),
+        }
+    }
+
     /// Wait for the connection to be closed for any reason
     ///
     /// Despite the return type's name, closed connections are often not an error condition at the
@@ -380,6 +553,43 @@ impl Connection {
             .clone()
     }
 
+    /// Wait until the connection has been idle for `idle`
+    ///
+    /// Completes once neither peer has sent anything for `idle`, which if shorter than the
+    /// negotiated idle timeout leaves the application time to decide whether to send a keep-alive
+    /// or close the connection gracefully before it times out. Activity while waiting pushes the
+    /// warning back. Fails if the connection is closed first.
+    ///
+    /// See [`proto::Connection::last_activity()`] for what counts as activity.
+    pub async fn idle_warning(&self, idle: Duration) -> Result<(), ConnectionError> {
+        loop {
+            let (deadline, runtime, closed) = {
+                let conn = self.0.state.lock("idle_warning");
+                if let Some(error) = conn.error.as_ref() {
+                    return Err(error.clone());
+                }
+                (
+                    conn.inner.last_activity() + idle,
+                    conn.runtime.clone(),
+                    // Construct the future while the lock is held to ensure we can't miss a wakeup
+                    self.0.shared.closed.notified(),
+                )
+            };
+            if runtime.now() >= deadline {
+                return Ok(());
+            }
+            let mut timer = runtime.new_timer(deadline);
+            let mut closed = pin!(closed);
+            std::future::poll_fn(|cx| {
+                if timer.as_mut().poll(cx).is_ready() || closed.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(());
+                }
+                Poll::Pending
+            })
+            .await;
+        }
+    }
+
     /// If the connection is closed, the reason why.
     ///
     /// Returns `None` if the connection is still open.
@@ -423,21 +633,149 @@ impl Connection {
         conn.close(error_code, Bytes::copy_from_slice(reason), &self.0.shared);
     }
 
+    /// Close the connection once the peer has acknowledged all data on finished streams
+    ///
+    /// Waits until every stream that was [finished](crate::SendStream::finish) has had its data
+    /// acknowledged or been stopped by the peer, then behaves like [`close()`]. If that takes
+    /// longer than `timeout`, the connection is closed anyway. Streams that are still open are
+    /// not waited for. Returns early if the connection is closed for another reason.
+    ///
+    /// This spares applications from tracking [`SendStream::stopped()`] for each stream just to
+    /// ensure their last response is received before closing. As with [`close()`], the peer
+    /// application may still not have read the data.
+    ///
+    /// [`close()`]: Connection::close
+    /// [`SendStream::stopped()`]: crate::SendStream::stopped
+    pub async fn close_after_flush(&self, error_code: VarInt, reason: &[u8], timeout: Duration) {
+        let runtime = self.0.state.lock("close_after_flush").runtime.clone();
+        let mut timer = runtime.new_timer(runtime.now() + timeout);
+        loop {
+            let (finished, closed) = {
+                let conn = self.0.state.lock("close_after_flush");
+                if conn.error.is_some() {
+                    return;
+                }
+                if conn.inner.unacked_finished_streams() == 0 {
+                    break;
+                }
+                // Construct the futures while the lock is held to ensure we can't miss a wakeup
+                (
+                    self.0.shared.stream_finished.notified(),
+                    self.0.shared.closed.notified(),
+                )
+            };
+            let (mut finished, mut closed) = (pin!(finished), pin!(closed));
+            let timed_out = std::future::poll_fn(|cx| {
+                if timer.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(true);
+                }
+                if finished.as_mut().poll(cx).is_ready() || closed.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(false);
+                }
+                Poll::Pending
+            })
+            .await;
+            if timed_out {
+                break;
+            }
+        }
+        self.close(error_code, reason);
+    }
+
     /// Transmit `data` as an unreliable, unordered application datagram
     ///
     /// Application datagrams are a low-level primitive. They may be lost or delivered out of order,
     /// and `data` must both fit inside a single QUIC packet and be smaller than the maximum
     /// dictated by the peer.
     pub fn send_datagram(&self, data: Bytes) -> Result<(), SendDatagramError> {
-        let conn = &mut *self.0.state.lock("send_datagram");
+        self.send_datagram_dropping("send_datagram", |conn| {
+            conn.inner.datagrams().send(data, true)
+        })
+    }
+
+    /// Transmit `data` as an unreliable, unordered application datagram with the given `priority`
+    ///
+    /// Queued datagrams of higher priority are transmitted first. Datagrams sent through other
+    /// methods have priority 0. Which datagrams are dropped when the outgoing buffer is full is
+    /// governed by [`TransportConfig::datagram_queue_policy()`].
+    ///
+    /// See [`send_datagram()`] for details.
+    ///
+    /// [`send_datagram()`]: Connection::send_datagram
+    /// [`TransportConfig::datagram_queue_policy()`]: crate::TransportConfig::datagram_queue_policy
+    pub fn send_datagram_with_priority(
+        &self,
+        data: Bytes,
+        priority: i32,
+    ) -> Result<(), SendDatagramError> {
+        self.send_datagram_dropping("send_datagram_with_priority", |conn| {
+            conn.inner
+                .datagrams()
+                .send_with_priority(data, true, priority)
+        })
+    }
+
+    /// Transmit `data` as an unreliable, unordered application datagram, unless it can't be sent
+    /// within `expiry`
+    ///
+    /// Datagrams which are still queued once `expiry` has elapsed, e.g. due to congestion, are
+    /// silently dropped rather than sent late. Useful for real-time data that is worthless once
+    /// stale.
+    ///
+    /// See [`send_datagram()`] for details.
+    ///
+    /// [`send_datagram()`]: Connection::send_datagram
+    pub fn send_datagram_with_expiry(
+        &self,
+        data: Bytes,
+        expiry: Duration,
+    ) -> Result<(), SendDatagramError> {
+        self.send_datagram_dropping("send_datagram_with_expiry", |conn| {
+            let deadline = conn.runtime.now() + expiry;
+            conn.inner
+                .datagrams()
+                .send_with_deadline(data, true, deadline)
+        })
+    }
+
+    /// Transmit `data` as an unreliable, unordered application datagram, and find out whether the
+    /// peer acknowledged it
+    ///
+    /// The returned [`DatagramTicket`] resolves once the datagram is acknowledged or declared lost,
+    /// allowing the application to retransmit important messages. A datagram which is dropped
+    /// before being sent, e.g. to make space in the outgoing buffer, is reported as lost.
+    ///
+    /// Acknowledgment only indicates that the peer's QUIC stack received the datagram, not that the
+    /// application read it. Conversely, a datagram declared lost may have been received anyway.
+    ///
+    /// See [`send_datagram()`] for details.
+    ///
+    /// [`send_datagram()`]: Connection::send_datagram
+    pub fn send_datagram_tracked(&self, data: Bytes) -> Result<DatagramTicket, SendDatagramError> {
+        let (send, recv) = oneshot::channel();
+        self.send_datagram_dropping("send_datagram_tracked", |conn| {
+            let id = conn.inner.datagrams().send_tracked(data, true)?;
+            conn.tracked_datagrams.insert(id, send);
+            Ok(())
+        })?;
+        Ok(DatagramTicket(recv))
+    }
+
+    /// Queue a datagram through `send`, which must allow older datagrams to be dropped
+    fn send_datagram_dropping<T>(
+        &self,
+        purpose: &'static str,
+        send: impl FnOnce(&mut State) -> Result<T, proto::SendDatagramError>,
+    ) -> Result<T, SendDatagramError> {
+        let conn = &mut *self.0.state.lock(purpose);
         if let Some(ref x) = conn.error {
             return Err(SendDatagramError::ConnectionLost(x.clone()));
         }
         use proto::SendDatagramError::*;
-        match conn.inner.datagrams().send(data, true) {
-            Ok(()) => {
+        match send(conn) {
+            Ok(x) => {
                 conn.wake();
-                Ok(())
+                Ok(x)
             }
             Err(e) => Err(match e {
                 Blocked(..) => unreachable!(),
@@ -464,6 +802,28 @@ impl Connection {
         }
     }
 
+    /// Get a [`Sink`](futures_sink::Sink) which sends each item as an application datagram
+    ///
+    /// Like [`send_datagram_wait()`](Self::send_datagram_wait), the sink waits for buffer space
+    /// rather than dropping older datagrams when the outgoing buffer is full.
+    #[cfg(feature = "futures")]
+    pub fn datagram_sink(&self) -> DatagramSink {
+        DatagramSink {
+            conn: self.clone(),
+            pending: None,
+        }
+    }
+
+    /// Get a [`Stream`](futures_core::Stream) of received application datagrams
+    ///
+    /// The stream ends once the connection is closed and all buffered datagrams have been
+    /// yielded. Multiple streams, or a stream and [`read_datagram()`](Self::read_datagram) callers,
+    /// share the incoming datagrams between them.
+    #[cfg(feature = "futures")]
+    pub fn datagram_stream(&self) -> DatagramStream {
+        DatagramStream { conn: self.clone() }
+    }
+
     /// Compute the maximum size of datagrams that may be passed to [`send_datagram()`].
     ///
     /// Returns `None` if datagrams are unsupported by the peer or disabled locally.
@@ -511,13 +871,33 @@ impl Connection {
     /// This can be different from the address the endpoint is bound to, in case
     /// the endpoint is bound to a wildcard address like `0.0.0.0` or `::`.
     ///
-    /// This will return `None` for clients, or when the platform does not expose this
-    /// information. See [`quinn_udp::RecvMeta::dst_ip`](udp::RecvMeta::dst_ip) for a list of
-    /// supported platforms when using [`quinn_udp`](udp) for I/O, which is the default.
+    /// This will return `None` for clients that haven't [migrated](Self::migrate) to a specific
+    /// address, or when the platform does not expose this information. See
+    /// [`quinn_udp::RecvMeta::dst_ip`](udp::RecvMeta::dst_ip) for a list of supported platforms
+    /// when using [`quinn_udp`](udp) for I/O, which is the default.
     pub fn local_ip(&self) -> Option<IpAddr> {
         self.0.state.lock("local_ip").inner.local_ip()
     }
 
+    /// Move this client connection to a new local IP address
+    ///
+    /// Subsequent packets are sent from `local_ip`, and the peer's reachability from it is
+    /// validated before the new path is relied upon. If validation fails, the connection falls
+    /// back to its previous address. This allows an application to switch networks deliberately,
+    /// e.g. from Wi-Fi to cellular when the operating system reports an interface change, rather
+    /// than only reacting to passive rebinding.
+    ///
+    /// The endpoint's socket must be able to send from `local_ip`, which usually means it is bound
+    /// to a wildcard address like `0.0.0.0` or `::`. To move every connection of an endpoint to a
+    /// different socket instead, use [`Endpoint::rebind()`](crate::Endpoint::rebind).
+    pub fn migrate(&self, local_ip: IpAddr) -> Result<(), MigrateError> {
+        let mut conn = self.0.state.lock("migrate");
+        let now = conn.runtime.now();
+        conn.inner.migrate_local(now, Some(local_ip))?;
+        conn.wake();
+        Ok(())
+    }
+
     /// Current best estimate of this connection's latency (round-trip-time)
     pub fn rtt(&self) -> Duration {
         self.0.state.lock("rtt").inner.rtt()
@@ -528,6 +908,78 @@ impl Connection {
         self.0.state.lock("stats").inner.stats()
     }
 
+    /// How the connection's driver has used the runtime
+    ///
+    /// Helps find the connections responsible for an endpoint's load. See also
+    /// [`Endpoint::top()`](crate::Endpoint::top).
+    pub fn driver_stats(&self) -> DriverStats {
+        self.0.state.lock("driver_stats").driver_stats
+    }
+
+    /// How often the handshake was restarted at the server's request
+    ///
+    /// See [`Connecting::handshake_events()`].
+    pub fn handshake_events(&self) -> HandshakeEvents {
+        self.0
+            .state
+            .lock("handshake_events")
+            .inner
+            .handshake_events()
+    }
+
+    /// Sample the connection's statistics every `interval`
+    ///
+    /// The first snapshot is taken one `interval` from now, and the stream ends once the
+    /// connection is closed. Rates, e.g. for a dashboard, follow from the differences between
+    /// consecutive snapshots computed by [`StatsDelta::between()`](crate::StatsDelta::between).
+    pub fn stats_stream(&self, interval: Duration) -> StatsStream {
+        let runtime = self.0.state.lock("stats_stream").runtime.clone();
+        let deadline = runtime.now() + interval;
+        StatsStream {
+            conn: self.clone(),
+            timer: runtime.new_timer(deadline),
+            runtime,
+            deadline,
+            interval,
+        }
+    }
+
+    /// Returns statistics of the current path, including the congestion controller's estimates
+    ///
+    /// Computed on each call, so repeated calls follow the estimates as they evolve. See
+    /// [`path_updates()`](Self::path_updates) to be notified of significant changes instead.
+    pub fn path_stats(&self) -> PathStats {
+        self.0.state.lock("path_stats").inner.path_stats()
+    }
+
+    /// Subscribe to significant changes in the path's estimates
+    ///
+    /// [`PathUpdates::next()`] yields the path's statistics whenever the bandwidth estimate,
+    /// pacing rate, congestion window or minimum RTT has changed by more than `threshold`, a
+    /// fraction of its value when last yielded, or when first subscribing. Suits applications
+    /// adapting their bitrate to the capacity of the path, such as media streaming.
+    pub fn path_updates(&self, threshold: f64) -> PathUpdates {
+        PathUpdates {
+            last: self.path_stats(),
+            conn: self.clone(),
+            threshold,
+        }
+    }
+
+    /// Number of bytes that could currently be written across all streams without blocking
+    ///
+    /// Accounts for connection-level flow control and the send window, but not for congestion
+    /// control or the flow control limits of individual streams. See
+    /// [`SendStream::max_writable()`](crate::SendStream::max_writable).
+    pub fn max_concurrent_send_capacity(&self) -> u64 {
+        self.0
+            .state
+            .lock("max_concurrent_send_capacity")
+            .inner
+            .streams()
+            .max_writable()
+    }
+
     /// Current state of the congestion control algorithm, for debugging purposes
     pub fn congestion_state(&self) -> Box<dyn Controller> {
         self.0
@@ -554,6 +1006,19 @@ impl Connection {
             .handshake_data()
     }
 
+    /// Transport parameters sent by the peer, including custom ones
+    ///
+    /// `None` until received during the handshake. Custom parameters are registered with
+    /// [`TransportConfig::custom_transport_parameter()`](crate::TransportConfig::custom_transport_parameter).
+    pub fn peer_transport_parameters(&self) -> Option<TransportParameters> {
+        self.0
+            .state
+            .lock("peer_transport_parameters")
+            .inner
+            .peer_transport_parameters()
+            .cloned()
+    }
+
     /// Cryptographic identity of the peer
     ///
     /// The dynamic type returned is determined by the configured
@@ -568,6 +1033,19 @@ impl Connection {
             .peer_identity()
     }
 
+    /// Certificate chain presented by the peer, end-entity certificate first
+    ///
+    /// Convenience for downcasting [`peer_identity()`](Self::peer_identity) when using the
+    /// `rustls` session, e.g. to identify clients of a server configured with
+    /// [`ServerConfig::with_client_cert_verifier()`]. `None` if the peer hasn't authenticated (yet)
+    /// or another session type is in use.
+    ///
+    /// [`ServerConfig::with_client_cert_verifier()`]: crate::ServerConfig::with_client_cert_verifier
+    #[cfg(any(feature = "rustls-aws-lc-rs", feature = "rustls-ring"))]
+    pub fn peer_identity_der(&self) -> Option<Vec<rustls::pki_types::CertificateDer<'static>>> {
+        self.peer_identity()?.downcast().ok().map(|certs| *certs)
+    }
+
     /// A stable identifier for this connection
     ///
     /// Peer addresses and connection IDs can change, but this value will remain
@@ -576,14 +1054,133 @@ impl Connection {
         self.0.stable_id()
     }
 
-    // Update traffic keys spontaneously for testing purposes.
-    #[doc(hidden)]
-    pub fn force_key_update(&self) {
+    /// Choose what is sent when the connection has been idle for the configured keep-alive interval
+    ///
+    /// See [`proto::Connection::set_keep_alive_probe()`]. A stream probe is identified by
+    /// [`SendStream::id()`](crate::SendStream::id).
+    pub fn set_keep_alive_probe(&self, probe: KeepAliveProbe) {
         self.0
             .state
-            .lock("force_key_update")
+            .lock("set_keep_alive_probe")
             .inner
-            .initiate_key_update()
+            .set_keep_alive_probe(probe);
+    }
+
+    /// Initiate an update of the 1-RTT packet protection keys
+    ///
+    /// See [`proto::Connection::initiate_key_update()`]. Use [`key_updated()`](Self::key_updated)
+    /// to wait for the update to be performed.
+    pub fn initiate_key_update(&self) {
+        let mut conn = self.0.state.lock("initiate_key_update");
+        conn.inner.initiate_key_update();
+        // Need to send a packet with the new keys
+        conn.wake();
+    }
+
+    /// Number of 1-RTT key updates performed on this connection so far, by either peer
+    pub fn key_phase(&self) -> u64 {
+        self.0.state.lock("key_phase").inner.key_phase()
+    }
+
+    /// Wait for the next 1-RTT key update, returning the new [`key_phase()`](Self::key_phase)
+    ///
+    /// Fails if the connection is closed before the keys are updated.
+    pub async fn key_updated(&self) -> Result<u64, ConnectionError> {
+        let start = self.key_phase();
+        loop {
+            {
+                let conn = self.0.state.lock("key_updated");
+                if let Some(error) = conn.error.as_ref() {
+                    return Err(error.clone());
+                }
+                let phase = conn.inner.key_phase();
+                if phase != start {
+                    return Ok(phase);
+                }
+                // Construct the future while the lock is held to ensure we can't miss a wakeup
+                self.0.shared.key_updated.notified()
+            }
+            .await;
+        }
+    }
+
+    /// Measure the round-trip time to the peer on demand
+    ///
+    /// Sends a PATH_CHALLENGE, to which the peer responds immediately, and yields the time taken
+    /// for its response to arrive, or `None` if the peer didn't respond to several challenges.
+    /// Suitable for liveness checks and latency sampling without involving application streams.
+    /// Concurrent calls share a single probe.
+    ///
+    /// Fails if the connection is closed first.
+    pub async fn probe_path(&self) -> Result<Option<Duration>, ConnectionError> {
+        let start = {
+            let mut conn = self.0.state.lock("probe_path");
+            if let Some(error) = conn.error.as_ref() {
+                return Err(error.clone());
+            }
+            conn.inner.probe_path();
+            conn.wake();
+            conn.path_probes
+        };
+        loop {
+            {
+                let conn = self.0.state.lock("probe_path");
+                if let Some(error) = conn.error.as_ref() {
+                    return Err(error.clone());
+                }
+                if conn.path_probes != start {
+                    return Ok(conn.path_probe_rtt);
+                }
+                // Construct the future while the lock is held to ensure we can't miss a wakeup
+                self.0.shared.path_probed.notified()
+            }
+            .await;
+        }
+    }
+
+    /// Subscribe to notable events on the connection
+    ///
+    /// Yields the events occurring after this call, such as migrations, key updates and MTU
+    /// changes, for observability without polling [`stats()`](Self::stats) or wrapping every call
+    /// site. The stream ends once the connection is closed. Events are buffered until consumed, so
+    /// subscribers should keep up with them or be dropped.
+    pub fn events(&self) -> Events {
+        let (send, recv) = mpsc::unbounded_channel();
+        let mut conn = self.0.state.lock("events");
+        if conn.error.is_none() {
+            conn.event_subscribers.push(send);
+        }
+        Events(recv)
+    }
+
+    /// Largest UDP payload size currently supported by the path
+    ///
+    /// Raised as MTU discovery finds larger packets to be delivered, and lowered when a black hole
+    /// is detected. See [`MtuDiscoveryConfig`](crate::MtuDiscoveryConfig).
+    pub fn current_mtu(&self) -> u16 {
+        self.0.state.lock("current_mtu").inner.current_mtu()
+    }
+
+    /// Wait for the path's MTU to change, returning the new [`current_mtu()`](Self::current_mtu)
+    ///
+    /// Fails if the connection is closed before the MTU changes.
+    pub async fn mtu_updated(&self) -> Result<u16, ConnectionError> {
+        let start = self.current_mtu();
+        loop {
+            {
+                let conn = self.0.state.lock("mtu_updated");
+                if let Some(error) = conn.error.as_ref() {
+                    return Err(error.clone());
+                }
+                let mtu = conn.inner.current_mtu();
+                if mtu != start {
+                    return Ok(mtu);
+                }
+                // Construct the future while the lock is held to ensure we can't miss a wakeup
+                self.0.shared.mtu_updated.notified()
+            }
+            .await;
+        }
     }
 
     /// Derive keying material from this connection's TLS session secrets.
@@ -626,6 +1223,30 @@ impl Connection {
         conn.wake();
     }
 
+    /// See [`proto::TransportConfig::packet_threshold()`]
+    ///
+    /// Takes effect from the next acknowledgement or timeout.
+    pub fn set_packet_threshold(&self, value: u32) {
+        let mut conn = self.0.state.lock("set_packet_threshold");
+        conn.inner.set_packet_threshold(value);
+    }
+
+    /// See [`proto::TransportConfig::time_threshold()`]
+    ///
+    /// Takes effect from the next acknowledgement or timeout.
+    pub fn set_time_threshold(&self, value: f32) {
+        let mut conn = self.0.state.lock("set_time_threshold");
+        conn.inner.set_time_threshold(value);
+    }
+
+    /// See [`proto::TransportConfig::max_pto_backoff()`]
+    ///
+    /// Takes effect from the next acknowledgement or timeout.
+    pub fn set_max_pto_backoff(&self, value: u32) {
+        let mut conn = self.0.state.lock("set_max_pto_backoff");
+        conn.inner.set_max_pto_backoff(value);
+    }
+
     /// Modify the number of remotely initiated bidirectional streams that may be concurrently open
     ///
     /// No streams may be opened by the peer unless fewer than `count` are already open. Large
@@ -636,6 +1257,94 @@ impl Connection {
         // May need to send MAX_STREAMS to make progress
         conn.wake();
     }
+
+    /// Get a handle to the group of streams identified by `id`
+    ///
+    /// Streams opened through the returned [`StreamGroup`] share its send budget and priority.
+    pub fn stream_group(&self, id: StreamGroupId) -> StreamGroup {
+        StreamGroup {
+            conn: self.clone(),
+            id,
+        }
+    }
+}
+
+/// A group of send streams sharing a send budget and priority
+///
+/// Useful for multiplexing independent tenants over a single connection, where priorities of
+/// individual streams are too coarse. Groups are identified by an application-chosen
+/// [`StreamGroupId`] and created on first use with an unlimited send budget and priority 0.
+/// Existing streams can be moved between groups with
+/// [`SendStream::set_group()`](crate::SendStream::set_group).
+///
+/// May be cloned to obtain another handle to the same group. The group's state lives on until
+/// [`remove()`](Self::remove) is called.
+#[derive(Debug, Clone)]
+pub struct StreamGroup {
+    conn: Connection,
+    id: StreamGroupId,
+}
+
+impl StreamGroup {
+    /// Initiate a new outgoing unidirectional stream in this group
+    ///
+    /// See [`Connection::open_uni()`].
+    pub async fn open_uni(&self) -> Result<SendStream, ConnectionError> {
+        let mut send = self.conn.open_uni().await?;
+        send.set_group(Some(self.id))
+            .expect("newly opened streams are not closed");
+        Ok(send)
+    }
+
+    /// Initiate a new outgoing bidirectional stream in this group
+    ///
+    /// See [`Connection::open_bi()`].
+    pub async fn open_bi(&self) -> Result<(SendStream, RecvStream), ConnectionError> {
+        let (mut send, recv) = self.conn.open_bi().await?;
+        send.set_group(Some(self.id))
+            .expect("newly opened streams are not closed");
+        Ok((send, recv))
+    }
+
+    /// Limit the amount of unacknowledged data buffered across all streams in the group
+    ///
+    /// Writes to streams in the group block once `send_budget` bytes written to them are awaiting
+    /// acknowledgement, in addition to the connection-wide
+    /// [`send_window`](proto::TransportConfig::send_window).
+    pub fn set_send_budget(&self, send_budget: u64) {
+        let mut conn = self.conn.0.state.lock("StreamGroup::set_send_budget");
+        conn.inner
+            .streams()
+            .set_group_send_budget(self.id, send_budget);
+        // May unblock writers
+        conn.wake();
+    }
+
+    /// Set the priority with which streams in the group are scheduled
+    ///
+    /// Overrides the priorities of individual streams in the group. See
+    /// [`SendStream::set_priority()`](crate::SendStream::set_priority).
+    pub fn set_priority(&self, priority: i32) {
+        let mut conn = self.conn.0.state.lock("StreamGroup::set_priority");
+        conn.inner.streams().set_group_priority(self.id, priority);
+    }
+
+    /// The identifier of the group
+    pub fn id(&self) -> StreamGroupId {
+        self.id
+    }
+
+    /// Forget about the group, removing all streams from it
+    ///
+    /// Frees the group's state. Its streams fall back to their own priorities and are no longer
+    /// limited by the group's send budget. Using the same [`StreamGroupId`] again later creates a
+    /// new group with default settings.
+    pub fn remove(self) {
+        let mut conn = self.conn.0.state.lock("StreamGroup::remove");
+        conn.inner.streams().remove_group(self.id);
+        // May unblock writers
+        conn.wake();
+    }
 }
 
 pin_project! {
@@ -644,6 +1353,7 @@ pin_project! {
         conn: &'a ConnectionRef,
         #[pin]
         notify: Notified<'a>,
+        opts: StreamOpts,
     }
 }
 
@@ -651,7 +1361,8 @@ impl Future for OpenUni<'_> {
     type Output = Result<SendStream, ConnectionError>;
     fn poll(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
-        let (conn, id, is_0rtt) = ready!(poll_open(ctx, this.conn, this.notify, Dir::Uni))?;
+        let (conn, id, is_0rtt) =
+            ready!(poll_open(ctx, this.conn, this.notify, this.opts, Dir::Uni))?;
         Poll::Ready(Ok(SendStream::new(conn, id, is_0rtt)))
     }
 }
@@ -662,6 +1373,7 @@ pin_project! {
         conn: &'a ConnectionRef,
         #[pin]
         notify: Notified<'a>,
+        opts: StreamOpts,
     }
 }
 
@@ -669,7 +1381,8 @@ impl Future for OpenBi<'_> {
     type Output = Result<(SendStream, RecvStream), ConnectionError>;
     fn poll(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
-        let (conn, id, is_0rtt) = ready!(poll_open(ctx, this.conn, this.notify, Dir::Bi))?;
+        let (conn, id, is_0rtt) =
+            ready!(poll_open(ctx, this.conn, this.notify, this.opts, Dir::Bi))?;
 
         Poll::Ready(Ok((
             SendStream::new(conn.clone(), id, is_0rtt),
@@ -682,6 +1395,7 @@ fn poll_open<'a>(
     ctx: &mut Context<'_>,
     conn: &'a ConnectionRef,
     mut notify: Pin<&mut Notified<'a>>,
+    opts: &mut StreamOpts,
     dir: Dir,
 ) -> Poll<Result<(ConnectionRef, StreamId, bool), ConnectionError>> {
     let mut state = conn.state.lock("poll_open");
@@ -689,6 +1403,7 @@ fn poll_open<'a>(
         return Poll::Ready(Err(e.clone()));
     } else if let Some(id) = state.inner.streams().open(dir) {
         let is_0rtt = state.inner.side().is_client() && state.inner.is_handshaking();
+        apply_stream_opts(&mut state, id, opts);
         drop(state); // Release the lock so clone can take it
         return Poll::Ready(Ok((conn.clone(), id, is_0rtt)));
     }
@@ -704,6 +1419,58 @@ fn poll_open<'a>(
     }
 }
 
+/// Apply `opts` to the newly opened stream `id`
+///
+/// Any part of the initial chunk that doesn't fit in the stream's flow control window is left in
+/// `opts` to be written once the stream has been handed to the application.
+fn apply_stream_opts(state: &mut State, id: StreamId, opts: &mut StreamOpts) {
+    let mut send = state.inner.send_stream(id);
+    if opts.priority != 0 {
+        send.set_priority(opts.priority)
+            .expect("newly opened streams are not closed");
+    }
+    if let Some(mut chunk) = opts.initial_chunk.take() {
+        // Blocking is the only way writing to a fresh stream can fail
+        let _ = send.write_chunks(std::slice::from_mut(&mut chunk));
+        if !chunk.is_empty() {
+            opts.initial_chunk = Some(chunk);
+        }
+        state.wake();
+    }
+    if opts.unidirectional_hint && id.dir() == Dir::Bi {
+        let _ = state.inner.recv_stream(id).stop(0u32.into());
+        state.wake();
+    }
+}
+
+/// Write whatever remains of the initial chunk after [`apply_stream_opts`]
+async fn finish_open(mut send: SendStream, opts: StreamOpts) -> Result<SendStream, WriteError> {
+    if let Some(chunk) = opts.initial_chunk {
+        send.write_chunk(chunk).await?;
+    }
+    Ok(send)
+}
+
+/// Options applied to a stream as it is opened
+///
+/// See [`Connection::open_uni_with()`] and [`Connection::open_bi_with()`].
+#[derive(Debug, Clone, Default)]
+pub struct StreamOpts {
+    /// Send priority of the stream, as set by [`SendStream::set_priority()`]
+    pub priority: i32,
+    /// Whether the application expects data to flow only from the opener of a bidirectional stream
+    ///
+    /// If set, the receive half of a bidirectional stream is stopped with error code 0 as soon as
+    /// it's opened, letting the peer know that nothing it sends will be read. Has no effect on
+    /// unidirectional streams.
+    pub unidirectional_hint: bool,
+    /// Data to write to the stream as it's opened
+    ///
+    /// As much as flow control permits is written atomically with opening the stream, so that it
+    /// can be sent in the same packet as the stream's first frame.
+    pub initial_chunk: Option<Bytes>,
+}
+
 pin_project! {
     /// Future produced by [`Connection::accept_uni`]
     pub struct AcceptUni<'a> {
@@ -806,6 +1573,53 @@ impl Future for ReadDatagram<'_> {
     }
 }
 
+pin_project! {
+    /// Future produced by [`Connection::read_datagrams`]
+    pub struct ReadDatagrams<'a> {
+        conn: &'a ConnectionRef,
+        buf: &'a mut Vec<Bytes>,
+        max: usize,
+        #[pin]
+        notify: Notified<'a>,
+    }
+}
+
+impl Future for ReadDatagrams<'_> {
+    type Output = Result<usize, ConnectionError>;
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        if *this.max == 0 {
+            return Poll::Ready(Ok(0));
+        }
+        let mut state = this.conn.state.lock("ReadDatagrams::poll");
+        let mut datagrams = state.inner.datagrams();
+        let start = this.buf.len();
+        while this.buf.len() - start < *this.max {
+            match datagrams.recv() {
+                Some(x) => this.buf.push(x),
+                None => break,
+            }
+        }
+        let count = this.buf.len() - start;
+        // As in `ReadDatagram`, drain buffered datagrams before reporting the connection closed
+        if count > 0 {
+            return Poll::Ready(Ok(count));
+        } else if let Some(ref e) = state.error {
+            return Poll::Ready(Err(e.clone()));
+        }
+        loop {
+            match this.notify.as_mut().poll(ctx) {
+                // `state` lock ensures we didn't race with readiness
+                Poll::Pending => return Poll::Pending,
+                // Spurious wakeup, get a new future
+                Poll::Ready(()) => this
+                    .notify
+                    .set(this.conn.shared.datagram_received.notified()),
+            }
+        }
+    }
+}
+
 pin_project! {
     /// Future produced by [`Connection::send_datagram_wait`]
     pub struct SendDatagram<'a> {
@@ -855,6 +1669,237 @@ impl Future for SendDatagram<'_> {
     }
 }
 
+/// [`Sink`](futures_sink::Sink) produced by [`Connection::datagram_sink`]
+///
+/// Holds at most one datagram that could not yet be buffered by the connection. Flushing the sink
+/// only waits for datagrams to be buffered, not for them to be transmitted.
+#[cfg(feature = "futures")]
+#[derive(Debug)]
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct DatagramSink {
+    conn: Connection,
+    pending: Option<Bytes>,
+}
+
+#[cfg(feature = "futures")]
+impl futures_sink::Sink<Bytes> for DatagramSink {
+    type Error = SendDatagramError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_flush(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        debug_assert!(
+            this.pending.is_none(),
+            "start_send called before poll_ready"
+        );
+        this.pending = Some(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        let Some(data) = this.pending.take() else {
+            return Poll::Ready(Ok(()));
+        };
+        let mut state = this.conn.0.state.lock("DatagramSink::poll_flush");
+        if let Some(ref e) = state.error {
+            return Poll::Ready(Err(SendDatagramError::ConnectionLost(e.clone())));
+        }
+        use proto::SendDatagramError::*;
+        match state.inner.datagrams().send(data, false) {
+            Ok(()) => {
+                state.wake();
+                Poll::Ready(Ok(()))
+            }
+            Err(e) => Poll::Ready(Err(match e {
+                Blocked(data) => {
+                    this.pending = Some(data);
+                    register_waker(&mut state.datagram_writers, cx);
+                    return Poll::Pending;
+                }
+                UnsupportedByPeer => SendDatagramError::UnsupportedByPeer,
+                Disabled => SendDatagramError::Disabled,
+                TooLarge => SendDatagramError::TooLarge,
+            })),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_flush(cx)
+    }
+}
+
+/// Notable occurrence on a connection, yielded by [`Events`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Event {
+    /// The handshake was confirmed, after which migration and key updates are permitted
+    HandshakeConfirmed,
+    /// The peer's address changed, through migration or NAT rebinding
+    Migrated {
+        /// The peer's new address
+        remote: SocketAddr,
+    },
+    /// The 1-RTT packet protection keys were updated
+    KeyUpdated {
+        /// Number of key updates performed so far, as reported by [`Connection::key_phase()`]
+        key_phase: u64,
+    },
+    /// The path's MTU changed, as reported by [`Connection::current_mtu()`]
+    MtuUpdated {
+        /// The new MTU
+        mtu: u16,
+    },
+    /// The peer asked us to stop sending on a stream
+    StreamStopped {
+        /// Which stream was stopped
+        id: StreamId,
+        /// Error code supplied by the peer
+        error_code: VarInt,
+    },
+    /// A datagram sent with [`Connection::send_datagram_tracked()`] was declared lost, or dropped
+    /// before being sent
+    DatagramLost,
+}
+
+/// Stream of events on a connection, produced by [`Connection::events`]
+///
+/// Implements `futures_core::Stream` if the `futures` feature is enabled.
+#[derive(Debug)]
+pub struct Events(mpsc::UnboundedReceiver<Event>);
+
+impl Events {
+    /// Wait for the next event, or `None` once the connection is closed
+    pub async fn next(&mut self) -> Option<Event> {
+        self.0.recv().await
+    }
+}
+
+#[cfg(feature = "futures")]
+impl futures_core::Stream for Events {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.0.poll_recv(cx)
+    }
+}
+
+/// Subscription to changes in a path's estimates, produced by [`Connection::path_updates`]
+#[derive(Debug)]
+pub struct PathUpdates {
+    conn: Connection,
+    threshold: f64,
+    last: PathStats,
+}
+
+impl PathUpdates {
+    /// Wait for the path's estimates to change significantly, returning its new statistics
+    ///
+    /// Fails if the connection is closed first.
+    pub async fn next(&mut self) -> Result<PathStats, ConnectionError> {
+        loop {
+            {
+                let conn = self.conn.0.state.lock("PathUpdates::next");
+                if let Some(error) = conn.error.as_ref() {
+                    return Err(error.clone());
+                }
+                let stats = conn.inner.path_stats();
+                if self.changed(&stats) {
+                    self.last = stats;
+                    return Ok(stats);
+                }
+                // Construct the future while the lock is held to ensure we can't miss a wakeup
+                self.conn.0.shared.path_updated.notified()
+            }
+            .await;
+        }
+    }
+
+    /// Whether any tracked estimate moved by more than the threshold since last yielded
+    fn changed(&self, stats: &PathStats) -> bool {
+        let exceeds = |old: f64, new: f64| (new - old).abs() > old * self.threshold;
+        exceeds(self.last.bandwidth as f64, stats.bandwidth as f64)
+            || exceeds(self.last.pacing_rate as f64, stats.pacing_rate as f64)
+            || exceeds(self.last.cwnd as f64, stats.cwnd as f64)
+            || exceeds(self.last.min_rtt.as_secs_f64(), stats.min_rtt.as_secs_f64())
+    }
+}
+
+/// Periodic snapshots of a connection's statistics, produced by [`Connection::stats_stream`]
+///
+/// Implements `futures_core::Stream` if the `futures` feature is enabled.
+#[derive(Debug)]
+pub struct StatsStream {
+    conn: Connection,
+    runtime: Arc<dyn Runtime>,
+    timer: Pin<Box<dyn AsyncTimer>>,
+    deadline: Instant,
+    interval: Duration,
+}
+
+impl StatsStream {
+    /// Wait for the next snapshot, or `None` once the connection is closed
+    pub async fn next(&mut self) -> Option<ConnectionStats> {
+        std::future::poll_fn(|cx| self.poll_snapshot(cx)).await
+    }
+
+    fn poll_snapshot(&mut self, cx: &mut Context<'_>) -> Poll<Option<ConnectionStats>> {
+        if self.conn.close_reason().is_some() {
+            return Poll::Ready(None);
+        }
+        ready!(self.timer.as_mut().poll(cx));
+        // Skip ticks missed by a slow consumer rather than yielding a burst of snapshots
+        let now = self.runtime.now();
+        self.deadline += self.interval;
+        if self.deadline <= now {
+            self.deadline = now + self.interval;
+        }
+        self.timer.as_mut().reset(self.deadline);
+        let conn = self.conn.0.state.lock("StatsStream::poll_snapshot");
+        if conn.error.is_some() {
+            return Poll::Ready(None);
+        }
+        Poll::Ready(Some(conn.inner.stats()))
+    }
+}
+
+#[cfg(feature = "futures")]
+impl futures_core::Stream for StatsStream {
+    type Item = ConnectionStats;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ConnectionStats>> {
+        self.poll_snapshot(cx)
+    }
+}
+
+/// [`Stream`](futures_core::Stream) produced by [`Connection::datagram_stream`]
+#[cfg(feature = "futures")]
+#[derive(Debug)]
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct DatagramStream {
+    conn: Connection,
+}
+
+#[cfg(feature = "futures")]
+impl futures_core::Stream for DatagramStream {
+    type Item = Bytes;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Bytes>> {
+        let mut state = self.conn.0.state.lock("DatagramStream::poll_next");
+        // As in `ReadDatagram`, drain buffered datagrams before reporting the connection closed
+        if let Some(x) = state.inner.datagrams().recv() {
+            return Poll::Ready(Some(x));
+        } else if state.error.is_some() {
+            return Poll::Ready(None);
+        }
+        register_waker(&mut state.datagram_readers, cx);
+        Poll::Pending
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct ConnectionRef(Arc<ConnectionInner>);
 
@@ -869,7 +1914,10 @@ impl ConnectionRef {
         on_connected: oneshot::Sender<bool>,
         socket: Arc<dyn AsyncUdpSocket>,
         runtime: Arc<dyn Runtime>,
+        timers: TimerWheel,
     ) -> Self {
+        let mtu = conn.current_mtu();
+        let remote = conn.remote_address();
         Self(Arc::new(ConnectionInner {
             state: Mutex::new(State {
                 inner: conn,
@@ -883,13 +1931,30 @@ impl ConnectionRef {
                 conn_events,
                 endpoint_events,
                 blocked_writers: FxHashMap::default(),
+                write_deadlines: FxHashMap::default(),
+                write_timer: None,
                 blocked_readers: FxHashMap::default(),
                 stopped: FxHashMap::default(),
+                flushed: FxHashMap::default(),
+                datagram_readers: Vec::new(),
+                datagram_writers: Vec::new(),
+                tracked_datagrams: FxHashMap::default(),
+                key_phase: 0,
+                mtu,
+                remote,
+                handshake_confirmed: false,
+                event_subscribers: Vec::new(),
+                path_probes: 0,
+                path_probe_rtt: None,
+                driver_stats: DriverStats::default(),
+                #[cfg(feature = "metrics")]
+                metrics: None,
                 error: None,
                 ref_count: 0,
                 io_poller: socket.clone().create_io_poller(),
                 socket,
                 runtime,
+                timers,
                 send_buffer: Vec::new(),
                 buffered_transmit: None,
             }),
@@ -898,7 +1963,7 @@ impl ConnectionRef {
     }
 
     fn stable_id(&self) -> usize {
-        &*self.0 as *const _ as usize
+        self.0.stable_id()
     }
 }
 
@@ -938,6 +2003,82 @@ pub(crate) struct ConnectionInner {
     pub(crate) shared: Shared,
 }
 
+impl ConnectionInner {
+    pub(crate) fn stable_id(&self) -> usize {
+        self as *const _ as usize
+    }
+
+    pub(crate) fn close(&self, error_code: VarInt, reason: Bytes) {
+        let conn = &mut *self.state.lock("close");
+        conn.close(error_code, reason, &self.shared);
+    }
+}
+
+/// Statistics on the work done by a connection's driver
+///
+/// Queue times are measured with [`Runtime::now()`].
+#[derive(Debug, Default, Copy, Clone)]
+#[non_exhaustive]
+pub struct DriverStats {
+    /// Total CPU time consumed by the threads running the driver
+    ///
+    /// Excludes time the threads spent preempted or blocked, e.g. waiting for locks, so that load
+    /// caused by other connections isn't attributed to this one. Measured with
+    /// `CLOCK_THREAD_CPUTIME_ID` on Unix; elsewhere, wall-clock time is measured instead.
+    pub cpu_time: Duration,
+    /// Number of times the driver ran
+    pub wakeups: u64,
+    /// Number of datagrams from the peer the driver processed
+    pub datagrams: u64,
+    /// Largest number of datagrams processed in a single wakeup
+    pub max_datagrams_per_wakeup: u64,
+    /// Total time datagrams waited between being received by the endpoint and processed by the
+    /// driver
+    pub queue_time: Duration,
+    /// Longest time a datagram waited between being received by the endpoint and processed by
+    /// the driver
+    pub max_queue_time: Duration,
+}
+
+/// Time consumed by the calling thread, in CPU time where the platform tracks it
+#[cfg(unix)]
+pub(crate) fn thread_time() -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `time` is valid for writes, and the clock is supported on every Unix we target
+    match unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) } {
+        0 => Duration::new(time.tv_sec as u64, time.tv_nsec as u32),
+        _ => Duration::ZERO,
+    }
+}
+
+/// Time consumed by the calling thread, in CPU time where the platform tracks it
+#[cfg(not(unix))]
+pub(crate) fn thread_time() -> Duration {
+    static EPOCH: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    EPOCH.get_or_init(std::time::Instant::now).elapsed()
+}
+
+impl DriverStats {
+    /// Average number of datagrams processed per wakeup
+    pub fn datagrams_per_wakeup(&self) -> f64 {
+        match self.wakeups {
+            0 => 0.0,
+            n => self.datagrams as f64 / n as f64,
+        }
+    }
+
+    /// Average time datagrams waited to be processed by the driver
+    pub fn mean_queue_time(&self) -> Duration {
+        match self.datagrams {
+            0 => Duration::ZERO,
+            n => self.queue_time.div_f64(n as f64),
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct Shared {
     /// Notified when new streams may be locally initiated due to an increase in stream ID flow
@@ -947,6 +2088,16 @@ pub(crate) struct Shared {
     stream_incoming: [Notify; 2],
     datagram_received: Notify,
     datagrams_unblocked: Notify,
+    /// Notified when the 1-RTT keys have been updated
+    key_updated: Notify,
+    /// Notified when the path's MTU has changed
+    mtu_updated: Notify,
+    /// Notified when a path probe has completed
+    path_probed: Notify,
+    /// Notified when a finished send stream is fully acknowledged or stopped by the peer
+    stream_finished: Notify,
+    /// Notified whenever the driver has run, which may have changed the path's estimates
+    path_updated: Notify,
     closed: Notify,
 }
 
@@ -962,15 +2113,47 @@ pub(crate) struct State {
     conn_events: mpsc::UnboundedReceiver<ConnectionEvent>,
     endpoint_events: mpsc::UnboundedSender<(ConnectionHandle, EndpointEvent)>,
     pub(crate) blocked_writers: FxHashMap<StreamId, Waker>,
+    /// Deadlines after which blocked writes on a stream fail
+    pub(crate) write_deadlines: FxHashMap<StreamId, Instant>,
+    /// Fires at the earliest deadline among blocked writers
+    write_timer: Option<Pin<Box<dyn AsyncTimer>>>,
     pub(crate) blocked_readers: FxHashMap<StreamId, Waker>,
     pub(crate) stopped: FxHashMap<StreamId, Waker>,
+    pub(crate) flushed: FxHashMap<StreamId, Waker>,
+    /// Tasks polling a [`DatagramStream`] with no datagram available
+    datagram_readers: Vec<Waker>,
+    /// Tasks polling a [`DatagramSink`] while the outgoing datagram buffer is full
+    datagram_writers: Vec<Waker>,
+    /// Pending [`DatagramTicket`]s
+    tracked_datagrams:
+        FxHashMap<DatagramId, oneshot::Sender<Result<DatagramOutcome, ConnectionError>>>,
+    /// Key phase last reported to [`Connection::key_updated()`] waiters
+    key_phase: u64,
+    /// MTU last reported to [`Connection::mtu_updated()`] waiters
+    mtu: u16,
+    /// Peer address last reported to [`Events`] subscribers
+    remote: SocketAddr,
+    /// Whether handshake confirmation was reported to [`Events`] subscribers
+    handshake_confirmed: bool,
+    /// Senders to live [`Events`] streams
+    event_subscribers: Vec<mpsc::UnboundedSender<Event>>,
+    /// Number of path probes completed, to tell [`Connection::probe_path()`] waiters when theirs is
+    path_probes: u64,
+    /// Outcome of the latest path probe
+    path_probe_rtt: Option<Duration>,
+    pub(crate) driver_stats: DriverStats,
+    /// Counters the connection's statistics are exported to
+    #[cfg(feature = "metrics")]
+    metrics: Option<ConnectionMetrics>,
     /// Always set to Some before the connection becomes drained
     pub(crate) error: Option<ConnectionError>,
     /// Number of live handles that can be used to initiate or handle I/O; excludes the driver
     ref_count: usize,
     socket: Arc<dyn AsyncUdpSocket>,
     io_poller: Pin<Box<dyn UdpPoller>>,
-    runtime: Arc<dyn Runtime>,
+    pub(crate) runtime: Arc<dyn Runtime>,
+    /// Source of the connection's timers, shared with the other connections of its endpoint shard
+    timers: TimerWheel,
     send_buffer: Vec<u8>,
     /// We buffer a transmit when the underlying I/O would block
     buffered_transmit: Option<proto::Transmit>,
@@ -1065,6 +2248,18 @@ impl State {
                 Poll::Ready(Some(ConnectionEvent::Proto(event))) => {
                     self.inner.handle_event(event);
                 }
+                Poll::Ready(Some(ConnectionEvent::Datagrams { events, queued })) => {
+                    let waited = self.runtime.now().saturating_duration_since(queued);
+                    let stats = &mut self.driver_stats;
+                    stats.queue_time = stats
+                        .queue_time
+                        .saturating_add(waited.saturating_mul(events.len() as u32));
+                    stats.max_queue_time = stats.max_queue_time.max(waited);
+                    stats.datagrams += events.len() as u64;
+                    for event in events {
+                        self.inner.handle_event(event);
+                    }
+                }
                 Poll::Ready(Some(ConnectionEvent::Close { reason, error_code })) => {
                     self.close(error_code, reason, shared);
                 }
@@ -1082,7 +2277,34 @@ impl State {
         }
     }
 
+    /// Send `event` to [`Events`] subscribers, forgetting those that were dropped
+    fn emit(&mut self, event: Event) {
+        self.event_subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
     fn forward_app_events(&mut self, shared: &Shared) {
+        if !self.handshake_confirmed && self.inner.is_handshake_confirmed() {
+            self.handshake_confirmed = true;
+            self.emit(Event::HandshakeConfirmed);
+        }
+        let key_phase = self.inner.key_phase();
+        if key_phase != self.key_phase {
+            self.key_phase = key_phase;
+            shared.key_updated.notify_waiters();
+            self.emit(Event::KeyUpdated { key_phase });
+        }
+        let mtu = self.inner.current_mtu();
+        if mtu != self.mtu {
+            self.mtu = mtu;
+            shared.mtu_updated.notify_waiters();
+            self.emit(Event::MtuUpdated { mtu });
+        }
+        let remote = self.inner.remote_address();
+        if remote != self.remote {
+            self.remote = remote;
+            self.emit(Event::Migrated { remote });
+        }
         while let Some(event) = self.inner.poll() {
             use proto::Event::*;
             match event {
@@ -1103,6 +2325,7 @@ impl State {
                         wake_all(&mut self.blocked_writers);
                         wake_all(&mut self.blocked_readers);
                         wake_all(&mut self.stopped);
+                        wake_all(&mut self.flushed);
                     }
                 }
                 ConnectionLost { reason } => {
@@ -1117,19 +2340,48 @@ impl State {
                 }
                 DatagramReceived => {
                     shared.datagram_received.notify_waiters();
+                    wake_list(&mut self.datagram_readers);
                 }
                 DatagramsUnblocked => {
                     shared.datagrams_unblocked.notify_waiters();
+                    wake_list(&mut self.datagram_writers);
+                }
+                DatagramAcked(id) => {
+                    if let Some(x) = self.tracked_datagrams.remove(&id) {
+                        let _ = x.send(Ok(DatagramOutcome::Acknowledged));
+                    }
+                }
+                DatagramLost(id) => {
+                    if let Some(x) = self.tracked_datagrams.remove(&id) {
+                        let _ = x.send(Ok(DatagramOutcome::Lost));
+                    }
+                    self.emit(Event::DatagramLost);
+                }
+                // Reflected by `remote_address()`
+                MigratedToPreferredAddress(_) => {}
+                PathProbed { rtt } => {
+                    self.path_probes += 1;
+                    self.path_probe_rtt = rtt;
+                    shared.path_probed.notify_waiters();
                 }
                 Stream(StreamEvent::Readable { id }) => wake_stream(id, &mut self.blocked_readers),
                 Stream(StreamEvent::Available { dir }) => {
                     // Might mean any number of streams are ready, so we wake up everyone
                     shared.stream_budget_available[dir as usize].notify_waiters();
                 }
-                Stream(StreamEvent::Finished { id }) => wake_stream(id, &mut self.stopped),
-                Stream(StreamEvent::Stopped { id, .. }) => {
+                Stream(StreamEvent::Finished { id }) => {
+                    wake_stream(id, &mut self.stopped);
+                    wake_stream(id, &mut self.flushed);
+                    shared.stream_finished.notify_waiters();
+                }
+                Stream(StreamEvent::Flushed { id }) => wake_stream(id, &mut self.flushed),
+                Stream(StreamEvent::Stopped { id, error_code }) => {
                     wake_stream(id, &mut self.stopped);
                     wake_stream(id, &mut self.blocked_writers);
+                    // Data that's yet to be acknowledged may never be
+                    wake_stream(id, &mut self.flushed);
+                    shared.stream_finished.notify_waiters();
+                    self.emit(Event::StreamStopped { id, error_code });
                 }
             }
         }
@@ -1152,7 +2404,7 @@ impl State {
                         delay.as_mut().reset(deadline);
                     }
                 } else {
-                    self.timer = Some(self.runtime.new_timer(deadline));
+                    self.timer = Some(self.timers.timer(deadline));
                 }
                 // Store the actual expiration time of the timer
                 self.timer_deadline = Some(deadline);
@@ -1185,6 +2437,46 @@ impl State {
         true
     }
 
+    /// Wake up blocked writers whose write deadline has passed, and arm a timer for the earliest
+    /// deadline that hasn't
+    ///
+    /// Returns whether the timer fired, in which case the deadlines must be checked again.
+    fn drive_write_deadlines(&mut self, cx: &mut Context) -> bool {
+        if self.write_deadlines.is_empty() {
+            return false;
+        }
+
+        let now = self.runtime.now();
+        let deadlines = &self.write_deadlines;
+        let mut next = None::<Instant>;
+        self.blocked_writers
+            .retain(|id, waker| match deadlines.get(id).copied() {
+                Some(deadline) if deadline <= now => {
+                    waker.wake_by_ref();
+                    false
+                }
+                Some(deadline) => {
+                    next = Some(next.map_or(deadline, |next| next.min(deadline)));
+                    true
+                }
+                None => true,
+            });
+
+        let Some(next) = next else {
+            return false;
+        };
+        match &mut self.write_timer {
+            Some(timer) => timer.as_mut().reset(next),
+            None => self.write_timer = Some(self.timers.timer(next)),
+        }
+        self.write_timer
+            .as_mut()
+            .expect("timer must exist in this state")
+            .as_mut()
+            .poll(cx)
+            .is_ready()
+    }
+
     /// Wake up a blocked `Driver` task to process I/O
     pub(crate) fn wake(&mut self) {
         if let Some(x) = self.driver.take() {
@@ -1206,10 +2498,22 @@ impl State {
         shared.stream_incoming[Dir::Bi as usize].notify_waiters();
         shared.datagram_received.notify_waiters();
         shared.datagrams_unblocked.notify_waiters();
+        shared.key_updated.notify_waiters();
+        shared.mtu_updated.notify_waiters();
+        shared.path_probed.notify_waiters();
+        shared.path_updated.notify_waiters();
+        wake_list(&mut self.datagram_readers);
+        wake_list(&mut self.datagram_writers);
+        for (_, x) in self.tracked_datagrams.drain() {
+            let _ = x.send(Err(reason.clone()));
+        }
         if let Some(x) = self.on_connected.take() {
             let _ = x.send(false);
         }
         wake_all(&mut self.stopped);
+        wake_all(&mut self.flushed);
+        // End `Events` streams
+        self.event_subscribers.clear();
         shared.closed.notify_waiters();
     }
 
@@ -1263,6 +2567,18 @@ fn wake_all(wakers: &mut FxHashMap<StreamId, Waker>) {
     wakers.drain().for_each(|(_, waker)| waker.wake())
 }
 
+fn wake_list(wakers: &mut Vec<Waker>) {
+    wakers.drain(..).for_each(Waker::wake)
+}
+
+/// Arrange for the task in `cx` to be woken along with `wakers`, unless it already will be
+#[cfg(feature = "futures")]
+fn register_waker(wakers: &mut Vec<Waker>, cx: &Context<'_>) {
+    if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+        wakers.push(cx.waker().clone());
+    }
+}
+
 /// Errors that can arise when sending a datagram
 #[derive(Debug, Error, Clone, Eq, PartialEq)]
 pub enum SendDatagramError {
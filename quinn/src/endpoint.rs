@@ -1,17 +1,21 @@
 use std::{
     collections::VecDeque,
-    future::Future,
-    io,
-    io::IoSliceMut,
-    mem,
+    fmt,
+    future::{poll_fn, Future},
+    io, mem,
     net::{SocketAddr, SocketAddrV6},
     pin::Pin,
     str,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
     task::{Context, Poll, Waker},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
+#[cfg(feature = "metrics")]
+use crate::metrics::{EndpointMetrics, MetricsConfig};
 #[cfg(any(feature = "aws-lc-rs", feature = "ring"))]
 use crate::runtime::default_runtime;
 use crate::{
@@ -21,32 +25,50 @@ use crate::{
 use bytes::{Bytes, BytesMut};
 use pin_project_lite::pin_project;
 use proto::{
-    self as proto, ClientConfig, ConnectError, ConnectionError, ConnectionHandle, DatagramEvent,
-    EndpointEvent, ServerConfig,
+    self as proto, ClientConfig, ConnectError, ConnectionError, ConnectionHandle, ConnectionStats,
+    DatagramEvent, EndpointEvent, ServerConfig,
 };
 use rustc_hash::FxHashMap;
 #[cfg(any(feature = "aws-lc-rs", feature = "ring"))]
 use socket2::{Domain, Protocol, Socket, Type};
+use thiserror::Error;
 use tokio::sync::{futures::Notified, mpsc, Notify};
 use tracing::{Instrument, Span};
 use udp::{RecvMeta, BATCH_SIZE};
 
 use crate::{
-    connection::Connecting, incoming::Incoming, work_limiter::WorkLimiter, ConnectionEvent,
-    EndpointConfig, VarInt, IO_LOOP_BOUND, RECV_TIME_BOUND,
+    batch::{TransmitBatchConfig, TransmitBatcher},
+    buffer_pool::RecvBuffers,
+    connection::{Connecting, Connection, ConnectionInner, DriverStats},
+    gso::{GsoSocket, GsoStats},
+    incoming::{Incoming, ServerConfigSelector},
+    incoming_filter::{IncomingAction, IncomingFilter, IncomingInfo},
+    offload::{HandshakeOffloadConfig, HandshakePool},
+    resolver::{default_resolver, split_host_port, AddressFamilyPreference, Resolver},
+    timer_wheel::TimerWheel,
+    work_limiter::WorkLimiter,
+    ConnectionEvent, EndpointConfig, VarInt, IO_LOOP_BOUND, RECV_TIME_BOUND,
 };
 
 /// A QUIC endpoint.
 ///
 /// An endpoint corresponds to a single UDP socket, may host many connections, and may act as both
-/// client and server for different connections.
+/// client and server for different connections. An endpoint constructed with
+/// [`new_with_sockets()`](Self::new_with_sockets) or [`new_sharded()`](Self::new_sharded) instead
+/// spreads its connections across several sockets.
 ///
 /// May be cloned to obtain another handle to the same endpoint.
 #[derive(Debug, Clone)]
 pub struct Endpoint {
     pub(crate) inner: EndpointRef,
+    /// Every shard of the endpoint, each with its own socket, starting with `inner`
+    shards: Arc<[EndpointRef]>,
+    /// Shard to initiate the next outgoing connection on
+    next_shard: Arc<AtomicUsize>,
     pub(crate) default_client_config: Option<ClientConfig>,
-    runtime: Arc<dyn Runtime>,
+    resolver: Option<Arc<dyn Resolver>>,
+    address_family_preference: AddressFamilyPreference,
+    pub(crate) runtime: Arc<dyn Runtime>,
 }
 
 impl Endpoint {
@@ -88,7 +110,27 @@ impl Endpoint {
 
     /// Returns relevant stats from this Endpoint
     pub fn stats(&self) -> EndpointStats {
-        self.inner.state.lock().unwrap().stats
+        let mut stats = EndpointStats::default();
+        for shard in self.shards.iter() {
+            let state = shard.state.lock().unwrap();
+            stats.accepted_handshakes += state.stats.accepted_handshakes;
+            stats.outgoing_handshakes += state.stats.outgoing_handshakes;
+            stats.refused_handshakes += state.stats.refused_handshakes;
+            stats.ignored_handshakes += state.stats.ignored_handshakes;
+            stats.retried_handshakes += state.recv_state.retried_handshakes;
+            stats.pending_handshakes += state.inner.pending_handshakes();
+            stats.accept_queue_len += state.recv_state.incoming.len();
+            stats.accept_queue_overflows += state.recv_state.incoming.overflows;
+            stats.gso_transmits += state.gso_stats.transmits.load(Ordering::Relaxed);
+            stats.gso_datagrams += state.gso_stats.datagrams.load(Ordering::Relaxed);
+            stats.max_gso_segments = stats
+                .max_gso_segments
+                .max(state.socket.max_transmit_segments());
+            let (batches, transmits) = state.recv_state.connections.batch_stats();
+            stats.transmit_batches += batches;
+            stats.batched_transmits += transmits;
+        }
+        stats
     }
 
     /// Helper to construct an endpoint for use with both incoming and outgoing connections
@@ -131,33 +173,144 @@ impl Endpoint {
         socket: Arc<dyn AsyncUdpSocket>,
         runtime: Arc<dyn Runtime>,
     ) -> io::Result<Self> {
-        let addr = socket.local_addr()?;
-        let allow_mtud = !socket.may_fragment();
-        let rc = EndpointRef::new(
+        let shard = spawn_shard(
+            Arc::new(config),
+            server_config.map(Arc::new),
             socket,
-            proto::Endpoint::new(
-                Arc::new(config),
-                server_config.map(Arc::new),
-                allow_mtud,
-                None,
-            ),
-            addr.is_ipv6(),
+            &runtime,
+            Arc::new(Shared::new()),
+        )?;
+        Ok(Self::from_shards(vec![shard], runtime))
+    }
+
+    /// Construct an endpoint with arbitrary configuration, bound to every one of `sockets`
+    ///
+    /// Lets a single endpoint serve several local addresses, e.g. both an IPv4 and an IPv6 address,
+    /// or addresses on several network interfaces, with the same configuration.
+    /// [`accept()`](Self::accept) yields incoming connections from every socket.
+    /// [`connect()`](Self::connect) initiates connections on a socket of the remote address's
+    /// family if there is one, and [`connect_on()`](Self::connect_on) on a specific socket.
+    /// Such endpoints cannot be [`rebind()`](Self::rebind)ed.
+    pub fn new_with_sockets(
+        config: EndpointConfig,
+        server_config: Option<ServerConfig>,
+        sockets: Vec<std::net::UdpSocket>,
+        runtime: Arc<dyn Runtime>,
+    ) -> io::Result<Self> {
+        if sockets.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "an endpoint needs at least one socket",
+            ));
+        }
+        let config = Arc::new(config);
+        let server_config = server_config.map(Arc::new);
+        let shared = Arc::new(Shared::new());
+        let shards = sockets
+            .into_iter()
+            .map(|socket| {
+                spawn_shard(
+                    config.clone(),
+                    server_config.clone(),
+                    runtime.wrap_udp_socket(socket)?,
+                    &runtime,
+                    shared.clone(),
+                )
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self::from_shards(shards, runtime))
+    }
+
+    /// Construct an endpoint spreading its connections across `shards` sockets bound to `addr`
+    ///
+    /// Each shard has its own socket, bound with `SO_REUSEPORT`, and its own driver task, so that
+    /// a busy server can make use of several cores. The first byte of every connection ID issued
+    /// by the endpoint identifies the shard owning the connection, which the kernel uses to steer
+    /// the connection's datagrams to the right socket. This is incompatible with connection ID
+    /// generators that must control that byte, such as QUIC-LB encodings, and requires
+    /// connection IDs shorter than the maximum of 20 bytes.
+    ///
+    /// The endpoint otherwise behaves as a single one: [`accept()`](Self::accept) yields incoming
+    /// connections from every shard, and outgoing connections are spread across shards in turn.
+    /// Sharded endpoints cannot be [`rebind()`](Self::rebind)ed.
+    #[cfg(target_os = "linux")]
+    pub fn new_sharded(
+        config: EndpointConfig,
+        server_config: Option<ServerConfig>,
+        addr: SocketAddr,
+        shards: usize,
+        runtime: Arc<dyn Runtime>,
+    ) -> io::Result<Self> {
+        crate::shard::validate(&config, shards)?;
+        let server_config = server_config.map(Arc::new);
+        let shared = Arc::new(Shared::new());
+        let shards = crate::shard::bind(addr, shards)?
+            .into_iter()
+            .enumerate()
+            .map(|(index, socket)| {
+                spawn_shard(
+                    Arc::new(crate::shard::config(&config, index, shards)),
+                    server_config.clone(),
+                    runtime.wrap_udp_socket(socket)?,
+                    &runtime,
+                    shared.clone(),
+                )
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self::from_shards(shards, runtime))
+    }
+
+    /// Construct an endpoint spreading its connections across `shards` driver tasks sharing
+    /// `socket`
+    ///
+    /// Like [`new_sharded()`](Self::new_sharded), but available on every platform, and
+    /// independent of the kernel's support for steering datagrams: a single task receives every
+    /// datagram from `socket`, and hands it over to the shard owning its connection through a
+    /// lock-free queue. Each shard otherwise has its own driver task and state, so connections on
+    /// different shards don't contend for the same locks. The same restrictions on connection ID
+    /// generators apply.
+    pub fn new_sharded_socket(
+        config: EndpointConfig,
+        server_config: Option<ServerConfig>,
+        socket: std::net::UdpSocket,
+        shards: usize,
+        runtime: Arc<dyn Runtime>,
+    ) -> io::Result<Self> {
+        crate::shard::validate(&config, shards)?;
+        let server_config = server_config.map(Arc::new);
+        let shared = Arc::new(Shared::new());
+        let sockets = crate::shard::split(
+            runtime.wrap_udp_socket(socket)?,
+            shards,
+            &config,
             runtime.clone(),
         );
-        let driver = EndpointDriver(rc.clone());
-        runtime.spawn(Box::pin(
-            async {
-                if let Err(e) = driver.await {
-                    tracing::error!("I/O error: {}", e);
-                }
-            }
-            .instrument(Span::current()),
-        ));
-        Ok(Self {
-            inner: rc,
+        let shards = sockets
+            .into_iter()
+            .enumerate()
+            .map(|(index, socket)| {
+                spawn_shard(
+                    Arc::new(crate::shard::config(&config, index, shards)),
+                    server_config.clone(),
+                    socket,
+                    &runtime,
+                    shared.clone(),
+                )
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self::from_shards(shards, runtime))
+    }
+
+    fn from_shards(shards: Vec<EndpointRef>, runtime: Arc<dyn Runtime>) -> Self {
+        Self {
+            inner: shards[0].clone(),
+            shards: shards.into(),
+            next_shard: Arc::new(AtomicUsize::new(0)),
             default_client_config: None,
+            resolver: None,
+            address_family_preference: AddressFamilyPreference::default(),
             runtime,
-        })
+        }
     }
 
     /// Get the next incoming connection attempt from a client
@@ -173,11 +326,93 @@ impl Endpoint {
         }
     }
 
+    /// Get the next incoming connection attempt if one is already queued, without waiting
+    ///
+    /// Allows draining the accept queue in a burst, e.g. after an [`accept()`](Self::accept)
+    /// resolved. Returns `None` if no connection attempt is queued, including if the endpoint is
+    /// closed.
+    pub fn try_accept(&self) -> Option<Incoming> {
+        for shard in self.shards.iter() {
+            let mut endpoint = shard.state.lock().unwrap();
+            if let Some(incoming) = endpoint.recv_state.incoming.pop_front() {
+                // Release the mutex lock on endpoint so cloning it doesn't deadlock
+                drop(endpoint);
+                return Some(Incoming::new(incoming, shard.clone()));
+            }
+        }
+        None
+    }
+
+    /// Bound the number of connection attempts waiting to be [`accept()`](Self::accept)ed
+    ///
+    /// While `limit` attempts are queued, further attempts are handled according to `overflow`
+    /// and counted in [`EndpointStats::accept_queue_overflows`], rather than buffered until the
+    /// application catches up. For endpoints with several sockets, the limit applies to the
+    /// attempts received on each socket separately. Passing `None`, the default, leaves the queue
+    /// bounded only by [`ServerConfig::max_incoming()`](proto::ServerConfig::max_incoming).
+    pub fn set_accept_queue_limit(&self, limit: Option<usize>, overflow: AcceptQueueOverflow) {
+        for shard in self.shards.iter() {
+            let incoming = &mut shard.state.lock().unwrap().recv_state.incoming;
+            incoming.limit = limit;
+            incoming.overflow = overflow;
+        }
+    }
+
+    /// Queue the datagrams of new connections to be sent together by the endpoint driver
+    ///
+    /// Rather than each connection making its own system calls, the driver hands the datagrams
+    /// queued by all connections since its last wakeup to the socket at once, using a single
+    /// `sendmmsg` call where supported. This saves system calls on servers with many moderately
+    /// active connections, at the cost of copying each datagram and of some latency. See
+    /// [`TransmitBatchConfig`] for how connections share a batch. Passing `None`, the default,
+    /// lets each connection send its own datagrams. Existing connections are unaffected.
+    pub fn set_transmit_batching(&self, config: Option<TransmitBatchConfig>) {
+        for shard in self.shards.iter() {
+            let mut state = shard.state.lock().unwrap();
+            let batcher = config
+                .clone()
+                .map(|config| TransmitBatcher::new(state.socket.clone(), config));
+            state.recv_state.connections.replace_batcher(batcher);
+            if let Some(driver) = &state.driver {
+                driver.wake_by_ref();
+            }
+        }
+    }
+
+    /// Perform the handshake cryptography of accepted connections on dedicated threads
+    ///
+    /// By default, accepting a connection processes the client's first packets, including the
+    /// signature proving the server's identity, while holding the state that the endpoint driver
+    /// needs to route datagrams. A burst of new connections can then delay the packets of
+    /// established ones. With offloading, that work is done by a pool of threads configured by
+    /// `config`, and each connection's driver starts once its share is done. Passing `None`, the
+    /// default, disables offloading. Connections already accepted are unaffected.
+    pub fn set_handshake_offload(&self, config: Option<HandshakeOffloadConfig>) -> io::Result<()> {
+        let pool = config.as_ref().map(HandshakePool::new).transpose()?;
+        for shard in self.shards.iter() {
+            let mut state = shard.state.lock().unwrap();
+            state.recv_state.connections.handshake_pool = pool.clone();
+        }
+        Ok(())
+    }
+
     /// Set the client configuration used by `connect`
     pub fn set_default_client_config(&mut self, config: ClientConfig) {
         self.default_client_config = Some(config);
     }
 
+    /// Set the resolver used by [`connect_to()`](Self::connect_to)
+    ///
+    /// Defaults to [`default_resolver()`](crate::default_resolver).
+    pub fn set_resolver(&mut self, resolver: Arc<dyn Resolver>) {
+        self.resolver = Some(resolver);
+    }
+
+    /// Set which address families [`connect_to()`](Self::connect_to) connects over
+    pub fn set_address_family_preference(&mut self, preference: AddressFamilyPreference) {
+        self.address_family_preference = preference;
+    }
+
     /// Connect to a remote endpoint
     ///
     /// `server_name` must be covered by the certificate presented by the server. This prevents a
@@ -206,7 +441,141 @@ impl Endpoint {
         addr: SocketAddr,
         server_name: &str,
     ) -> Result<Connecting, ConnectError> {
-        let mut endpoint = self.inner.state.lock().unwrap();
+        self.connect_via(self.shard_for(addr), config, addr, server_name)
+    }
+
+    /// Connect to a remote endpoint from the socket at `addr_index` in
+    /// [`local_addrs()`](Self::local_addrs)
+    ///
+    /// See [`connect()`](Self::connect) for details.
+    ///
+    /// # Panics
+    ///
+    /// If `addr_index` is out of bounds.
+    pub fn connect_on(
+        &self,
+        addr_index: usize,
+        addr: SocketAddr,
+        server_name: &str,
+    ) -> Result<Connecting, ConnectError> {
+        let config = match &self.default_client_config {
+            Some(config) => config.clone(),
+            None => return Err(ConnectError::NoDefaultClientConfig),
+        };
+
+        self.connect_via(&self.shards[addr_index], config, addr, server_name)
+    }
+
+    /// Connect to a server reachable at several addresses, racing the attempts as in [RFC 8305]
+    ///
+    /// Attempts are started in the order of `addrs`, alternating between IPv6 and IPv4 starting
+    /// with the family of the first address, each one once the previous attempt has failed or
+    /// after 250ms. The first connection to complete its handshake is returned along with the
+    /// address it was established with; the remaining attempts are abandoned, closing any
+    /// connection they started. Fails with the error of the last attempt if none succeeds.
+    ///
+    /// `addrs` is typically the result of a DNS lookup, in the order of preference it returned.
+    ///
+    /// [RFC 8305]: https://www.rfc-editor.org/rfc/rfc8305.html
+    pub async fn connect_multi(
+        &self,
+        addrs: &[SocketAddr],
+        server_name: &str,
+    ) -> Result<(Connection, SocketAddr), ConnectMultiError> {
+        let config = match &self.default_client_config {
+            Some(config) => config.clone(),
+            None => return Err(ConnectError::NoDefaultClientConfig.into()),
+        };
+
+        let mut candidates = interleave_families(addrs);
+        let mut attempts = Vec::<(SocketAddr, Connecting)>::new();
+        let mut error = ConnectMultiError::NoAddresses;
+        let mut timer = self.runtime.new_timer(self.runtime.now());
+        poll_fn(|cx| {
+            loop {
+                let mut i = 0;
+                while i < attempts.len() {
+                    match Pin::new(&mut attempts[i].1).poll(cx) {
+                        Poll::Ready(Ok(conn)) => return Poll::Ready(Ok((conn, attempts[i].0))),
+                        Poll::Ready(Err(e)) => {
+                            drop(attempts.swap_remove(i));
+                            error = e.into();
+                        }
+                        Poll::Pending => i += 1,
+                    }
+                }
+
+                // Start the next attempt if all others failed or the delay has elapsed
+                if candidates.is_empty()
+                    || (!attempts.is_empty() && timer.as_mut().poll(cx).is_pending())
+                {
+                    break;
+                }
+                let addr = candidates.pop_front().unwrap();
+                match self.connect_with(config.clone(), addr, server_name) {
+                    Ok(connecting) => {
+                        attempts.push((addr, connecting));
+                        timer
+                            .as_mut()
+                            .reset(self.runtime.now() + CONNECTION_ATTEMPT_DELAY);
+                    }
+                    Err(e) => error = e.into(),
+                }
+            }
+            match attempts.is_empty() {
+                true => Poll::Ready(Err(mem::replace(
+                    &mut error,
+                    ConnectMultiError::NoAddresses,
+                ))),
+                false => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Connect to a remote endpoint identified by a `host:port` string
+    ///
+    /// The host, which may be a domain name or an IP address, is resolved by the configured
+    /// [`Resolver`], and used as the server name. Every resolved address allowed by the
+    /// [`AddressFamilyPreference`] is tried as described in
+    /// [`connect_multi()`](Self::connect_multi). IPv6 addresses must be enclosed in brackets, as
+    /// in `[::1]:4433`.
+    pub async fn connect_to(
+        &self,
+        target: &str,
+    ) -> Result<(Connection, SocketAddr), ConnectToError> {
+        let (host, port) = split_host_port(target)?;
+        let mut addrs = match host.parse() {
+            Ok(ip) => vec![SocketAddr::new(ip, port)],
+            Err(_) => {
+                let resolver = self.resolver.clone().unwrap_or_else(default_resolver);
+                resolver.resolve(host, port).await?
+            }
+        };
+        self.address_family_preference.apply(&mut addrs);
+        Ok(self.connect_multi(&addrs, host).await?)
+    }
+
+    /// Pick the shard to initiate a connection to `addr` on
+    ///
+    /// Shards are taken in turn, skipping those of another address family than `addr` if possible.
+    fn shard_for(&self, addr: SocketAddr) -> &EndpointRef {
+        let start = self.next_shard.fetch_add(1, Ordering::Relaxed);
+        let count = self.shards.len();
+        (0..count)
+            .map(|i| &self.shards[(start + i) % count])
+            .find(|shard| shard.state.lock().unwrap().ipv6 == addr.is_ipv6())
+            .unwrap_or(&self.shards[start % count])
+    }
+
+    fn connect_via(
+        &self,
+        shard: &EndpointRef,
+        config: ClientConfig,
+        addr: SocketAddr,
+        server_name: &str,
+    ) -> Result<Connecting, ConnectError> {
+        let mut endpoint = shard.state.lock().unwrap();
         if endpoint.driver_lost || endpoint.recv_state.connections.close.is_some() {
             return Err(ConnectError::EndpointStopping);
         }
@@ -225,12 +594,81 @@ impl Endpoint {
 
         let socket = endpoint.socket.clone();
         endpoint.stats.outgoing_handshakes += 1;
+        #[cfg(feature = "metrics")]
+        endpoint
+            .recv_state
+            .connections
+            .metrics
+            .outgoing_handshakes
+            .increment(1);
         Ok(endpoint
             .recv_state
             .connections
             .insert(ch, conn, socket, self.runtime.clone()))
     }
 
+    /// Send a probe to `addr` to open or keep open a path through NATs and firewalls
+    ///
+    /// Sends a one-byte datagram to `addr` from every local address of the endpoint that can reach
+    /// it, creating or refreshing a mapping for `addr` in NATs and stateful firewalls between this
+    /// endpoint and the peer, so that the peer's packets can reach us. The datagram is too short to
+    /// be a QUIC packet, so QUIC endpoints, including this one, discard it without a response.
+    ///
+    /// This is only a building block for NAT traversal: it doesn't coordinate with the peer,
+    /// discover reflexive addresses, or retry by itself. Applications must exchange addresses and
+    /// agree on timing out of band, e.g. through a rendezvous server, and typically call this
+    /// repeatedly while the other side calls [`connect()`](Self::connect) towards it.
+    ///
+    /// As with any UDP datagram, successful completion doesn't imply that the probe reached its
+    /// destination.
+    pub async fn send_probe(&self, addr: SocketAddr) -> io::Result<()> {
+        let mut targets = Vec::<(Arc<dyn AsyncUdpSocket>, SocketAddr)>::new();
+        let mut local_addrs = Vec::new();
+        for shard in self.shards.iter() {
+            let endpoint = shard.state.lock().unwrap();
+            if addr.is_ipv6() && !endpoint.ipv6 {
+                continue;
+            }
+            // Shards sharing a local address would only send duplicates
+            let local = endpoint.socket.local_addr()?;
+            if local_addrs.contains(&local) {
+                continue;
+            }
+            local_addrs.push(local);
+            let destination = match endpoint.ipv6 {
+                true => SocketAddr::V6(ensure_ipv6(addr)),
+                false => addr,
+            };
+            targets.push((endpoint.socket.clone(), destination));
+        }
+        if targets.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no socket can reach the address",
+            ));
+        }
+        for (socket, destination) in targets {
+            let transmit = udp::Transmit {
+                destination,
+                ecn: None,
+                contents: &[0],
+                segment_size: None,
+                src_ip: None,
+            };
+            let mut poller = socket.clone().create_io_poller();
+            poll_fn(|cx| loop {
+                match socket.try_send(&transmit) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        ready!(poller.as_mut().poll_writable(cx))?;
+                    }
+                    result => return Poll::Ready(result),
+                }
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
     /// Switch to a new UDP socket
     ///
     /// See [`Endpoint::rebind_abstract()`] for details.
@@ -242,19 +680,45 @@ impl Endpoint {
     ///
     /// Allows the endpoint's address to be updated live, affecting all active connections. Incoming
     /// connections and connections to servers unreachable from the new address will be lost.
+    /// Each connection switches to a fresh connection ID, if available, and sends a packet from
+    /// the new address, prompting its peer to validate the new path. Tasks waiting on
+    /// [`local_addr_changed()`](Self::local_addr_changed) are woken.
     ///
-    /// On error, the old UDP socket is retained.
+    /// On error, the old UDP socket is retained. Fails for endpoints with several sockets.
     pub fn rebind_abstract(&self, socket: Arc<dyn AsyncUdpSocket>) -> io::Result<()> {
+        if self.shards.len() > 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "endpoints with several sockets cannot be rebound",
+            ));
+        }
         let addr = socket.local_addr()?;
         let mut inner = self.inner.state.lock().unwrap();
+        let socket = GsoSocket::wrap(
+            socket,
+            inner.inner.config().get_max_gso_segments(),
+            inner.gso_stats.clone(),
+        );
         inner.prev_socket = Some(mem::replace(&mut inner.socket, socket));
         inner.ipv6 = addr.is_ipv6();
+        let inner = &mut *inner;
+        let connections = &mut inner.recv_state.connections;
+        let batcher = connections
+            .batcher
+            .as_ref()
+            .map(|batcher| TransmitBatcher::new(inner.socket.clone(), batcher.config().clone()));
+        connections.replace_batcher(batcher);
 
         // Update connection socket references
-        for sender in inner.recv_state.connections.senders.values() {
+        for sender in connections.senders.values() {
+            let socket = match &connections.batcher {
+                Some(batcher) => batcher.connection_socket(),
+                None => inner.socket.clone(),
+            };
             // Ignoring errors from dropped connections
-            let _ = sender.send(ConnectionEvent::Rebind(inner.socket.clone()));
+            let _ = sender.send(ConnectionEvent::Rebind(socket));
         }
+        self.inner.shared.rebound.notify_waiters();
 
         Ok(())
     }
@@ -263,22 +727,169 @@ impl Endpoint {
     ///
     /// Useful for e.g. refreshing TLS certificates without disrupting existing connections.
     pub fn set_server_config(&self, server_config: Option<ServerConfig>) {
-        self.inner
-            .state
-            .lock()
-            .unwrap()
-            .inner
-            .set_server_config(server_config.map(Arc::new))
+        let server_config = server_config.map(Arc::new);
+        for shard in self.shards.iter() {
+            let mut state = shard.state.lock().unwrap();
+            state.inner.set_server_config(server_config.clone());
+        }
+    }
+
+    /// Choose the server configuration of each incoming connection with `selector`
+    ///
+    /// Connections for which `selector` makes no choice, or whose ClientHello cannot be decoded
+    /// early, use the configuration set by [`set_server_config()`](Self::set_server_config).
+    /// Passing `None` removes any selector.
+    pub fn set_server_config_selector(&self, selector: Option<Arc<dyn ServerConfigSelector>>) {
+        for shard in self.shards.iter() {
+            shard.state.lock().unwrap().server_config_selector = selector.clone();
+        }
+    }
+
+    /// Screen new connection attempts with `filter` before they are returned by
+    /// [`accept()`](Self::accept)
+    ///
+    /// See [`IncomingFilter`] for details, and [`IncomingRateLimiter`] for a built-in filter.
+    /// Passing `None` removes any filter.
+    ///
+    /// [`IncomingRateLimiter`]: crate::IncomingRateLimiter
+    pub fn set_incoming_filter(&self, filter: Option<Arc<dyn IncomingFilter>>) {
+        for shard in self.shards.iter() {
+            shard.state.lock().unwrap().recv_state.filter = filter.clone();
+        }
+    }
+
+    /// Validate the addresses of clients with a Retry while more than `threshold` incoming
+    /// handshakes are in progress
+    ///
+    /// A Retry costs legitimate clients a round trip, but prevents attackers from opening
+    /// handshakes from spoofed addresses, so this bounds the resources that a flood of connection
+    /// attempts can consume. Applies to attempts accepted by the [incoming
+    /// filter](Self::set_incoming_filter), if any; to also limit the rate of attempts from each
+    /// source, use an [`IncomingRateLimiter`](crate::IncomingRateLimiter). Passing `None`, the
+    /// default, disables the threshold.
+    pub fn set_retry_threshold(&self, threshold: Option<usize>) {
+        for shard in self.shards.iter() {
+            shard.state.lock().unwrap().recv_state.retry_threshold = threshold;
+        }
+    }
+
+    /// Replace the configuration of the counters exported through the `metrics` facade
+    ///
+    /// Counters are registered with the recorder installed at the time, so this must be called
+    /// again if the recorder is installed after the endpoint is created. Existing connections keep
+    /// the counters they were created with.
+    #[cfg(feature = "metrics")]
+    pub fn set_metrics_config(&self, config: MetricsConfig) {
+        for shard in self.shards.iter() {
+            shard.state.lock().unwrap().recv_state.connections.metrics =
+                EndpointMetrics::new(config.clone());
+        }
     }
 
     /// Get the local `SocketAddr` the underlying socket is bound to
+    ///
+    /// For endpoints with several sockets, this is the address of the first.
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
         self.inner.state.lock().unwrap().socket.local_addr()
     }
 
+    /// Get the local `SocketAddr` of every socket of the endpoint
+    ///
+    /// Indices in the result are those accepted by [`connect_on()`](Self::connect_on).
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.shards
+            .iter()
+            .map(|shard| shard.state.lock().unwrap().socket.local_addr())
+            .collect()
+    }
+
+    /// Wait for the endpoint to be [`rebind()`](Self::rebind)ed, returning its new local address
+    ///
+    /// Only changes after the call are reported. Useful for e.g. advertising the new address to
+    /// peers through a rendezvous server.
+    pub fn local_addr_changed(&self) -> impl Future<Output = io::Result<SocketAddr>> + '_ {
+        // Construct future before returning so that no change is missed
+        let rebound = self.inner.shared.rebound.notified();
+        async move {
+            rebound.await;
+            self.local_addr()
+        }
+    }
+
     /// Get the number of connections that are currently open
     pub fn open_connections(&self) -> usize {
-        self.inner.state.lock().unwrap().inner.open_connections()
+        self.shards
+            .iter()
+            .map(|shard| shard.state.lock().unwrap().inner.open_connections())
+            .sum()
+    }
+
+    /// Describe every open connection on this endpoint
+    ///
+    /// Includes connections that are still handshaking, but not those that have been closed.
+    /// Useful for building administrative or diagnostic interfaces without tracking each
+    /// connection separately.
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        self.registered_connections()
+            .into_iter()
+            .filter_map(|conn| {
+                let state = conn.state.lock("connections");
+                if state.error.is_some() {
+                    return None;
+                }
+                Some(ConnectionInfo {
+                    stable_id: conn.stable_id(),
+                    remote_address: state.inner.remote_address(),
+                    stats: state.inner.stats(),
+                    driver: state.driver_stats,
+                })
+            })
+            .collect()
+    }
+
+    /// Describe the `n` open connections whose drivers have consumed the most CPU time
+    ///
+    /// Connections are sorted by decreasing [`DriverStats::cpu_time`], to help find those
+    /// degrading the service of others sharing the endpoint.
+    pub fn top(&self, n: usize) -> Vec<ConnectionInfo> {
+        let mut connections = self.connections();
+        connections.sort_unstable_by_key(|info| std::cmp::Reverse(info.driver.cpu_time));
+        connections.truncate(n);
+        connections
+    }
+
+    /// Close the connection whose [`stable_id()`](crate::Connection::stable_id) is `id`
+    ///
+    /// Returns whether the connection was found. See [`Connection::close()`] for details.
+    ///
+    /// [`Connection::close()`]: crate::Connection::close
+    pub fn close_connection(&self, id: usize, error_code: VarInt, reason: &[u8]) -> bool {
+        let Some(conn) = self
+            .registered_connections()
+            .into_iter()
+            .find(|conn| conn.stable_id() == id)
+        else {
+            return false;
+        };
+        conn.close(error_code, Bytes::copy_from_slice(reason));
+        true
+    }
+
+    /// Live connections, collected so that they can be locked without holding the endpoint lock
+    fn registered_connections(&self) -> Vec<Arc<ConnectionInner>> {
+        let mut connections = Vec::new();
+        for shard in self.shards.iter() {
+            let endpoint = shard.state.lock().unwrap();
+            connections.extend(
+                endpoint
+                    .recv_state
+                    .connections
+                    .refs
+                    .values()
+                    .filter_map(Weak::upgrade),
+            );
+        }
+        connections
     }
 
     /// Close all of this endpoint's connections immediately and cease accepting new connections.
@@ -288,16 +899,66 @@ impl Endpoint {
     /// [`Connection::close()`]: crate::Connection::close
     pub fn close(&self, error_code: VarInt, reason: &[u8]) {
         let reason = Bytes::copy_from_slice(reason);
-        let mut endpoint = self.inner.state.lock().unwrap();
-        endpoint.recv_state.connections.close = Some((error_code, reason.clone()));
-        for sender in endpoint.recv_state.connections.senders.values() {
-            // Ignoring errors from dropped connections
-            let _ = sender.send(ConnectionEvent::Close {
-                error_code,
-                reason: reason.clone(),
-            });
+        for shard in self.shards.iter() {
+            let mut endpoint = shard.state.lock().unwrap();
+            endpoint.recv_state.connections.close = Some((error_code, reason.clone()));
+            for sender in endpoint.recv_state.connections.senders.values() {
+                // Ignoring errors from dropped connections
+                let _ = sender.send(ConnectionEvent::Close {
+                    error_code,
+                    reason: reason.clone(),
+                });
+            }
+        }
+        self.inner.shared.incoming.notify_waiters();
+    }
+
+    /// Gracefully shut down the endpoint, reporting progress as its connections close
+    ///
+    /// New connection attempts, including any not yet returned by [`accept()`](Self::accept), are
+    /// refused from now on, and `accept()` returns `None`. Existing connections are left alone
+    /// unless `close` is set, in which case each is closed with the given error code and reason,
+    /// e.g. an application-level GOAWAY. Unlike [`close()`](Self::close), the endpoint can still
+    /// initiate outgoing connections.
+    ///
+    /// The returned [`Drain`] yields the number of remaining connections each time it changes,
+    /// ending after reporting zero. Applications wanting a bounded shutdown can stop waiting on it
+    /// after a deadline and call [`close()`](Self::close).
+    pub fn drain(&self, close: Option<(VarInt, &[u8])>) -> Drain<'_> {
+        let close = close.map(|(error_code, reason)| (error_code, Bytes::copy_from_slice(reason)));
+        for shard in self.shards.iter() {
+            let mut endpoint = shard.state.lock().unwrap();
+            let endpoint = &mut *endpoint;
+            endpoint.recv_state.connections.draining = true;
+            for incoming in endpoint.recv_state.incoming.drain(..) {
+                endpoint.stats.refused_handshakes += 1;
+                #[cfg(feature = "metrics")]
+                endpoint
+                    .recv_state
+                    .connections
+                    .metrics
+                    .refused_handshakes
+                    .increment(1);
+                let mut response_buffer = Vec::new();
+                let transmit = endpoint.inner.refuse(incoming, &mut response_buffer);
+                respond(transmit, &response_buffer, &*endpoint.socket);
+            }
+            if let Some((error_code, reason)) = &close {
+                for sender in endpoint.recv_state.connections.senders.values() {
+                    // Ignoring errors from dropped connections
+                    let _ = sender.send(ConnectionEvent::Close {
+                        error_code: *error_code,
+                        reason: reason.clone(),
+                    });
+                }
+            }
         }
         self.inner.shared.incoming.notify_waiters();
+        Drain {
+            endpoint: self,
+            notify: Box::pin(self.inner.shared.connection_closed.notified()),
+            reported: None,
+        }
     }
 
     /// Wait for all connections on the endpoint to be cleanly shut down
@@ -312,19 +973,33 @@ impl Endpoint {
     /// [`close()`]: Endpoint::close
     pub async fn wait_idle(&self) {
         loop {
-            {
-                let endpoint = &mut *self.inner.state.lock().unwrap();
-                if endpoint.recv_state.connections.is_empty() {
-                    break;
-                }
-                // Construct future while lock is held to avoid race
-                self.inner.shared.idle.notified()
+            // Construct future before checking to avoid race
+            let idle = self.inner.shared.idle.notified();
+            if self.shards.iter().all(|shard| {
+                let endpoint = shard.state.lock().unwrap();
+                endpoint.recv_state.connections.is_empty()
+            }) {
+                break;
             }
-            .await;
+            idle.await;
         }
     }
 }
 
+/// Snapshot of a connection open on an [`Endpoint`], from [`Endpoint::connections()`]
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    /// The connection's [`stable_id()`](crate::Connection::stable_id)
+    pub stable_id: usize,
+    /// The peer's UDP address
+    pub remote_address: SocketAddr,
+    /// Connection statistics at the time of the call
+    pub stats: ConnectionStats,
+    /// Statistics on the connection's driver at the time of the call
+    pub driver: DriverStats,
+}
+
 /// Statistics on [Endpoint] activity
 #[non_exhaustive]
 #[derive(Debug, Default, Copy, Clone)]
@@ -337,6 +1012,47 @@ pub struct EndpointStats {
     pub refused_handshakes: u64,
     /// Cummulative number of Quic handshakes ignored on this [Endpoint]
     pub ignored_handshakes: u64,
+    /// Cummulative number of Retry packets sent by this [Endpoint] to validate client addresses
+    pub retried_handshakes: u64,
+    /// Number of incoming handshakes currently in progress on this [Endpoint]
+    ///
+    /// Includes connection attempts not yet accepted by the application. See
+    /// [`Endpoint::set_retry_threshold()`].
+    pub pending_handshakes: usize,
+    /// Number of connection attempts currently waiting to be accepted by the application
+    pub accept_queue_len: usize,
+    /// Cummulative number of connection attempts turned away because the accept queue was full
+    ///
+    /// See [`Endpoint::set_accept_queue_limit()`].
+    pub accept_queue_overflows: u64,
+    /// Cummulative number of transmits sent as a single batch using generic segmentation offload
+    pub gso_transmits: u64,
+    /// Cummulative number of datagrams sent as part of a batch using generic segmentation offload
+    pub gso_datagrams: u64,
+    /// Number of datagrams currently allowed in a single batch
+    ///
+    /// 1 if generic segmentation offload is disabled, unsupported, or was found not to work at
+    /// runtime.
+    pub max_gso_segments: usize,
+    /// Cummulative number of batches of transmits sent on behalf of several connections
+    ///
+    /// See [`Endpoint::set_transmit_batching()`].
+    pub transmit_batches: u64,
+    /// Cummulative number of transmits sent as part of a batch
+    pub batched_transmits: u64,
+}
+
+/// How to turn away connection attempts once the accept queue is full
+///
+/// See [`Endpoint::set_accept_queue_limit()`].
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum AcceptQueueOverflow {
+    /// Close the connection attempt with a `CONNECTION_REFUSED` error, so the client fails fast
+    #[default]
+    Refuse,
+    /// Drop the connection attempt silently, so the client may succeed by retransmitting once the
+    /// application has caught up
+    Ignore,
 }
 
 /// A future that drives IO on an endpoint
@@ -367,6 +1083,7 @@ impl Future for EndpointDriver {
         let mut keep_going = false;
         keep_going |= endpoint.drive_recv(cx, now)?;
         keep_going |= endpoint.handle_events(cx, &self.0.shared);
+        keep_going |= endpoint.recv_state.connections.flush_transmits(cx)?;
 
         if !endpoint.recv_state.incoming.is_empty() {
             self.0.shared.incoming.notify_waiters();
@@ -398,10 +1115,77 @@ impl Drop for EndpointDriver {
     }
 }
 
+/// Progress of a graceful shutdown started by [`Endpoint::drain`]
+///
+/// Yields the number of connections still open on the endpoint each time it changes, starting
+/// with the number at the time of the call, and ends after yielding zero.
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct Drain<'a> {
+    endpoint: &'a Endpoint,
+    notify: Pin<Box<Notified<'a>>>,
+    reported: Option<usize>,
+}
+
+impl<'a> Drain<'a> {
+    /// Wait for the number of open connections to change, returning the new count
+    ///
+    /// Returns `None` once zero has been reported.
+    pub async fn next(&mut self) -> Option<usize> {
+        poll_fn(|cx| self.poll_remaining(cx)).await
+    }
+
+    fn poll_remaining(&mut self, cx: &mut Context<'_>) -> Poll<Option<usize>> {
+        if self.reported == Some(0) {
+            return Poll::Ready(None);
+        }
+        loop {
+            let mut remaining = 0;
+            for shard in self.endpoint.shards.iter() {
+                let endpoint = shard.state.lock().unwrap();
+                if endpoint.driver_lost {
+                    self.reported = Some(0);
+                    return Poll::Ready(Some(0));
+                }
+                remaining += endpoint.recv_state.connections.senders.len();
+            }
+            if self.reported != Some(remaining) {
+                self.reported = Some(remaining);
+                return Poll::Ready(Some(remaining));
+            }
+            match self.notify.as_mut().poll(cx) {
+                // The future predates the check above, so we didn't race with readiness
+                Poll::Pending => return Poll::Pending,
+                // Get a new future and check again
+                Poll::Ready(()) => self
+                    .notify
+                    .set(self.endpoint.inner.shared.connection_closed.notified()),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "futures")]
+impl futures_core::Stream for Drain<'_> {
+    type Item = usize;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<usize>> {
+        self.get_mut().poll_remaining(cx)
+    }
+}
+
+impl fmt::Debug for Drain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Drain")
+            .field("reported", &self.reported)
+            .finish_non_exhaustive()
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct EndpointInner {
     pub(crate) state: Mutex<State>,
-    pub(crate) shared: Shared,
+    /// Notifications shared by every shard of the endpoint
+    pub(crate) shared: Arc<Shared>,
 }
 
 impl EndpointInner {
@@ -411,14 +1195,33 @@ impl EndpointInner {
         server_config: Option<Arc<ServerConfig>>,
     ) -> Result<Connecting, ConnectionError> {
         let mut state = self.state.lock().unwrap();
+        let server_config = server_config.or_else(|| {
+            let selector = state.server_config_selector.as_ref()?;
+            selector.select(&incoming.client_hello()?)
+        });
         let mut response_buffer = Vec::new();
         let now = state.runtime.now();
-        match state
-            .inner
-            .accept(incoming, now, &mut response_buffer, server_config)
-        {
+        let accepted = match state.recv_state.connections.handshake_pool {
+            // Leave the handshake's cryptography to the pool, without holding up the driver
+            Some(_) => {
+                state
+                    .inner
+                    .accept_deferred(incoming, now, &mut response_buffer, server_config)
+            }
+            None => state
+                .inner
+                .accept(incoming, now, &mut response_buffer, server_config),
+        };
+        match accepted {
             Ok((handle, conn)) => {
                 state.stats.accepted_handshakes += 1;
+                #[cfg(feature = "metrics")]
+                state
+                    .recv_state
+                    .connections
+                    .metrics
+                    .accepted_handshakes
+                    .increment(1);
                 let socket = state.socket.clone();
                 let runtime = state.runtime.clone();
                 Ok(state
@@ -438,6 +1241,13 @@ impl EndpointInner {
     pub(crate) fn refuse(&self, incoming: proto::Incoming) {
         let mut state = self.state.lock().unwrap();
         state.stats.refused_handshakes += 1;
+        #[cfg(feature = "metrics")]
+        state
+            .recv_state
+            .connections
+            .metrics
+            .refused_handshakes
+            .increment(1);
         let mut response_buffer = Vec::new();
         let transmit = state.inner.refuse(incoming, &mut response_buffer);
         respond(transmit, &response_buffer, &*state.socket);
@@ -447,6 +1257,9 @@ impl EndpointInner {
         let mut state = self.state.lock().unwrap();
         let mut response_buffer = Vec::new();
         let transmit = state.inner.retry(incoming, &mut response_buffer)?;
+        state.recv_state.retried_handshakes += 1;
+        #[cfg(feature = "metrics")]
+        state.recv_state.connections.metrics.retries.increment(1);
         respond(transmit, &response_buffer, &*state.socket);
         Ok(())
     }
@@ -454,6 +1267,13 @@ impl EndpointInner {
     pub(crate) fn ignore(&self, incoming: proto::Incoming) {
         let mut state = self.state.lock().unwrap();
         state.stats.ignored_handshakes += 1;
+        #[cfg(feature = "metrics")]
+        state
+            .recv_state
+            .connections
+            .metrics
+            .ignored_handshakes
+            .increment(1);
         state.inner.ignore(incoming);
     }
 }
@@ -474,12 +1294,30 @@ pub(crate) struct State {
     driver_lost: bool,
     runtime: Arc<dyn Runtime>,
     stats: EndpointStats,
+    /// GSO usage across the sockets this endpoint has been bound to
+    gso_stats: Arc<GsoStats>,
+    server_config_selector: Option<Arc<dyn ServerConfigSelector>>,
 }
 
 #[derive(Debug)]
 pub(crate) struct Shared {
     incoming: Notify,
     idle: Notify,
+    /// Notified whenever a connection is removed from the endpoint
+    connection_closed: Notify,
+    /// Notified whenever the endpoint switches to a new socket
+    rebound: Notify,
+}
+
+impl Shared {
+    fn new() -> Self {
+        Self {
+            incoming: Notify::new(),
+            idle: Notify::new(),
+            connection_closed: Notify::new(),
+            rebound: Notify::new(),
+        }
+    }
 }
 
 impl State {
@@ -520,6 +1358,8 @@ impl State {
 
             if event.is_drained() {
                 self.recv_state.connections.senders.remove(&ch);
+                self.recv_state.connections.refs.remove(&ch);
+                shared.connection_closed.notify_waiters();
                 if self.recv_state.connections.is_empty() {
                     shared.idle.notify_waiters();
                 }
@@ -549,6 +1389,43 @@ impl Drop for State {
     }
 }
 
+/// Create one shard of an endpoint and spawn its driver
+fn spawn_shard(
+    config: Arc<EndpointConfig>,
+    server_config: Option<Arc<ServerConfig>>,
+    socket: Arc<dyn AsyncUdpSocket>,
+    runtime: &Arc<dyn Runtime>,
+    shared: Arc<Shared>,
+) -> io::Result<EndpointRef> {
+    let addr = socket.local_addr()?;
+    let allow_mtud = !socket.may_fragment();
+    let gso_stats = Arc::new(GsoStats::default());
+    let socket = GsoSocket::wrap(socket, config.get_max_gso_segments(), gso_stats.clone());
+    let rng_seed = config.get_rng_seed().unwrap_or_else(|| {
+        let mut seed = [0; 32];
+        runtime.fill_random(&mut seed);
+        seed
+    });
+    let rc = EndpointRef::new(
+        socket,
+        gso_stats,
+        proto::Endpoint::new(config, server_config, allow_mtud, Some(rng_seed)),
+        addr.is_ipv6(),
+        runtime.clone(),
+        shared,
+    );
+    let driver = EndpointDriver(rc.clone());
+    runtime.spawn(Box::pin(
+        async {
+            if let Err(e) = driver.await {
+                tracing::error!("I/O error: {}", e);
+            }
+        }
+        .instrument(Span::current()),
+    ));
+    Ok(rc)
+}
+
 fn respond(transmit: proto::Transmit, response_buffer: &[u8], socket: &dyn AsyncUdpSocket) {
     // Send if there's kernel buffer space; otherwise, drop it
     //
@@ -586,10 +1463,27 @@ fn proto_ecn(ecn: udp::EcnCodepoint) -> proto::EcnCodepoint {
 struct ConnectionSet {
     /// Senders for communicating with the endpoint's connections
     senders: FxHashMap<ConnectionHandle, mpsc::UnboundedSender<ConnectionEvent>>,
+    /// The endpoint's connections, for inspection by the application
+    refs: FxHashMap<ConnectionHandle, Weak<ConnectionInner>>,
     /// Stored to give out clones to new ConnectionInners
     sender: mpsc::UnboundedSender<(ConnectionHandle, EndpointEvent)>,
+    /// Source of connection timers
+    timers: TimerWheel,
+    /// Queue shared by new connections for their transmits, if batching is enabled
+    batcher: Option<Arc<TransmitBatcher>>,
+    /// Batchers replaced while connections still used them, kept until they have been drained
+    retired_batchers: Vec<Arc<TransmitBatcher>>,
+    /// Batches and transmits sent by batchers no longer in use
+    retired_batch_stats: (u64, u64),
+    /// Threads performing the handshakes of accepted connections, if offloading is enabled
+    handshake_pool: Option<Arc<HandshakePool>>,
     /// Set if the endpoint has been manually closed
     close: Option<(VarInt, Bytes)>,
+    /// Set if the endpoint is refusing new connections while existing ones finish
+    draining: bool,
+    /// Counters exported through the `metrics` facade
+    #[cfg(feature = "metrics")]
+    metrics: EndpointMetrics,
 }
 
 impl ConnectionSet {
@@ -609,14 +1503,117 @@ impl ConnectionSet {
             .unwrap();
         }
         self.senders.insert(handle, send);
-        Connecting::new(handle, conn, self.sender.clone(), recv, socket, runtime)
+        let socket = match &self.batcher {
+            Some(batcher) => batcher.connection_socket(),
+            None => socket,
+        };
+        #[cfg(feature = "metrics")]
+        let metrics = self.metrics.connection(conn.remote_address());
+        let connecting = Connecting::new(
+            handle,
+            conn,
+            self.sender.clone(),
+            recv,
+            socket,
+            runtime,
+            self.timers.clone(),
+            self.handshake_pool.as_deref(),
+        );
+        #[cfg(feature = "metrics")]
+        connecting.set_metrics(metrics);
+        self.refs.insert(handle, connecting.downgrade());
+        connecting
     }
 
     fn is_empty(&self) -> bool {
         self.senders.is_empty()
     }
+
+    /// Batch the transmits of connections created from now on with `batcher`
+    fn replace_batcher(&mut self, batcher: Option<Arc<TransmitBatcher>>) {
+        if let Some(old) = mem::replace(&mut self.batcher, batcher) {
+            // Connections already using the old batcher keep doing so
+            self.retired_batchers.push(old);
+        }
+    }
+
+    /// Send the transmits queued by connections, returning whether there is more work to do
+    fn flush_transmits(&mut self, cx: &mut Context) -> io::Result<bool> {
+        let mut keep_going = false;
+        for batcher in self.batcher.iter().chain(&self.retired_batchers) {
+            keep_going |= batcher.flush(cx)?;
+        }
+        let mut i = 0;
+        while i < self.retired_batchers.len() {
+            if self.retired_batchers[i].is_idle() {
+                let (batches, transmits) = self.retired_batchers.swap_remove(i).stats();
+                self.retired_batch_stats.0 += batches;
+                self.retired_batch_stats.1 += transmits;
+            } else {
+                i += 1;
+            }
+        }
+        Ok(keep_going)
+    }
+
+    /// Number of batches and transmits sent so far
+    fn batch_stats(&self) -> (u64, u64) {
+        self.batcher
+            .iter()
+            .chain(&self.retired_batchers)
+            .map(|batcher| batcher.stats())
+            .fold(self.retired_batch_stats, |(b, t), (batches, transmits)| {
+                (b + batches, t + transmits)
+            })
+    }
+}
+
+/// Order `addrs` alternating between address families, starting with the family of the first
+fn interleave_families(addrs: &[SocketAddr]) -> VecDeque<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return VecDeque::new();
+    };
+    let (mut preferred, mut other): (VecDeque<_>, VecDeque<_>) = addrs
+        .iter()
+        .partition(|addr| addr.is_ipv6() == first.is_ipv6());
+    let mut result = VecDeque::with_capacity(addrs.len());
+    while let Some(addr) = preferred.pop_front() {
+        result.push_back(addr);
+        result.extend(other.pop_front());
+    }
+    result.extend(other);
+    result
+}
+
+/// Errors that can arise from [`Endpoint::connect_multi()`]
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+pub enum ConnectMultiError {
+    /// No addresses were supplied
+    #[error("no addresses to connect to")]
+    NoAddresses,
+    /// The last connection attempt could not be started
+    #[error(transparent)]
+    Connect(#[from] ConnectError),
+    /// The last connection attempt failed
+    #[error(transparent)]
+    Connection(#[from] ConnectionError),
+}
+
+/// Errors that can arise from [`Endpoint::connect_to()`]
+#[derive(Debug, Error)]
+pub enum ConnectToError {
+    /// The target is malformed, or could not be resolved
+    #[error("failed to resolve target: {0}")]
+    Resolve(#[from] io::Error),
+    /// No connection could be established with the resolved addresses
+    #[error(transparent)]
+    Connect(#[from] ConnectMultiError),
 }
 
+/// Delay before starting the next attempt of [`Endpoint::connect_multi()`], as recommended by
+/// RFC 8305
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
 fn ensure_ipv6(x: SocketAddr) -> SocketAddrV6 {
     match x {
         SocketAddr::V6(x) => x,
@@ -637,24 +1634,27 @@ impl<'a> Future for Accept<'a> {
     type Output = Option<Incoming>;
     fn poll(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
         let mut this = self.project();
-        let mut endpoint = this.endpoint.inner.state.lock().unwrap();
-        if endpoint.driver_lost {
-            return Poll::Ready(None);
-        }
-        if let Some(incoming) = endpoint.recv_state.incoming.pop_front() {
-            // Release the mutex lock on endpoint so cloning it doesn't deadlock
-            drop(endpoint);
-            let incoming = Incoming::new(incoming, this.endpoint.inner.clone());
-            return Poll::Ready(Some(incoming));
-        }
-        if endpoint.recv_state.connections.close.is_some() {
-            return Poll::Ready(None);
-        }
         loop {
+            for shard in this.endpoint.shards.iter() {
+                let mut endpoint = shard.state.lock().unwrap();
+                if endpoint.driver_lost {
+                    return Poll::Ready(None);
+                }
+                if let Some(incoming) = endpoint.recv_state.incoming.pop_front() {
+                    // Release the mutex lock on endpoint so cloning it doesn't deadlock
+                    drop(endpoint);
+                    return Poll::Ready(Some(Incoming::new(incoming, shard.clone())));
+                }
+                if endpoint.recv_state.connections.close.is_some()
+                    || endpoint.recv_state.connections.draining
+                {
+                    return Poll::Ready(None);
+                }
+            }
             match this.notify.as_mut().poll(ctx) {
-                // `state` lock ensures we didn't race with readiness
+                // The future predates the checks above, so we didn't race with readiness
                 Poll::Pending => return Poll::Pending,
-                // Spurious wakeup, get a new future
+                // Get a new future and check again
                 Poll::Ready(()) => this
                     .notify
                     .set(this.endpoint.inner.shared.incoming.notified()),
@@ -669,17 +1669,22 @@ pub(crate) struct EndpointRef(Arc<EndpointInner>);
 impl EndpointRef {
     pub(crate) fn new(
         socket: Arc<dyn AsyncUdpSocket>,
+        gso_stats: Arc<GsoStats>,
         inner: proto::Endpoint,
         ipv6: bool,
         runtime: Arc<dyn Runtime>,
+        shared: Arc<Shared>,
     ) -> Self {
         let (sender, events) = mpsc::unbounded_channel();
-        let recv_state = RecvState::new(sender, socket.max_receive_segments(), &inner);
+        let recv_state = RecvState::new(
+            sender,
+            // Each shard keeps its own, so that its connections don't contend with the others'
+            TimerWheel::new(runtime.clone()),
+            socket.max_receive_segments(),
+            &inner,
+        );
         Self(Arc::new(EndpointInner {
-            shared: Shared {
-                incoming: Notify::new(),
-                idle: Notify::new(),
-            },
+            shared,
             state: Mutex::new(State {
                 socket,
                 prev_socket: None,
@@ -692,6 +1697,8 @@ impl EndpointRef {
                 recv_state,
                 runtime,
                 stats: EndpointStats::default(),
+                gso_stats,
+                server_config_selector: None,
             }),
         }))
     }
@@ -727,36 +1734,104 @@ impl std::ops::Deref for EndpointRef {
     }
 }
 
+/// Connection attempts waiting to be accepted by the application
+#[derive(Debug, Default)]
+struct AcceptQueue {
+    queue: VecDeque<proto::Incoming>,
+    /// Maximum length of `queue`
+    limit: Option<usize>,
+    overflow: AcceptQueueOverflow,
+    /// Connection attempts turned away so far because `queue` was full
+    overflows: u64,
+}
+
+impl AcceptQueue {
+    /// Queue `incoming` to be accepted, unless the queue is full
+    fn push(
+        &mut self,
+        incoming: proto::Incoming,
+        endpoint: &mut proto::Endpoint,
+        response_buffer: &mut Vec<u8>,
+        socket: &dyn AsyncUdpSocket,
+    ) {
+        if !self.limit.is_some_and(|limit| self.queue.len() >= limit) {
+            self.queue.push_back(incoming);
+            return;
+        }
+        self.overflows += 1;
+        match self.overflow {
+            AcceptQueueOverflow::Refuse => {
+                let transmit = endpoint.refuse(incoming, response_buffer);
+                respond(transmit, response_buffer, socket);
+            }
+            AcceptQueueOverflow::Ignore => endpoint.ignore(incoming),
+        }
+    }
+}
+
+impl std::ops::Deref for AcceptQueue {
+    type Target = VecDeque<proto::Incoming>;
+    fn deref(&self) -> &Self::Target {
+        &self.queue
+    }
+}
+
+impl std::ops::DerefMut for AcceptQueue {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.queue
+    }
+}
+
 /// State directly involved in handling incoming packets
 #[derive(Debug)]
 struct RecvState {
-    incoming: VecDeque<proto::Incoming>,
+    incoming: AcceptQueue,
+    filter: Option<Arc<dyn IncomingFilter>>,
+    /// Number of pending handshakes above which to validate client addresses
+    retry_threshold: Option<usize>,
+    /// Retry packets sent so far
+    retried_handshakes: u64,
     connections: ConnectionSet,
-    recv_buf: Box<[u8]>,
+    recv_bufs: RecvBuffers,
+    /// Datagrams of the current batch, with the metadata of each
+    received: Vec<(RecvMeta, BytesMut)>,
     recv_limiter: WorkLimiter,
+    /// Datagrams received for each connection in the current batch, delivered together
+    batched: FxHashMap<ConnectionHandle, Vec<proto::ConnectionEvent>>,
 }
 
 impl RecvState {
     fn new(
         sender: mpsc::UnboundedSender<(ConnectionHandle, EndpointEvent)>,
+        timers: TimerWheel,
         max_receive_segments: usize,
         endpoint: &proto::Endpoint,
     ) -> Self {
-        let recv_buf = vec![
-            0;
-            endpoint.config().get_max_udp_payload_size().min(64 * 1024) as usize
-                * max_receive_segments
-                * BATCH_SIZE
-        ];
+        let recv_buf_len = endpoint.config().get_max_udp_payload_size().min(64 * 1024) as usize
+            * max_receive_segments;
         Self {
             connections: ConnectionSet {
                 senders: FxHashMap::default(),
+                refs: FxHashMap::default(),
                 sender,
+                timers,
+                batcher: None,
+                retired_batchers: Vec::new(),
+                retired_batch_stats: (0, 0),
+                handshake_pool: None,
                 close: None,
+                draining: false,
+                #[cfg(feature = "metrics")]
+                metrics: EndpointMetrics::new(MetricsConfig::default()),
             },
-            incoming: VecDeque::new(),
-            recv_buf: recv_buf.into(),
+            incoming: AcceptQueue::default(),
+            filter: None,
+            retry_threshold: None,
+            retried_handshakes: 0,
+            recv_bufs: RecvBuffers::new(recv_buf_len),
+            received: Vec::with_capacity(BATCH_SIZE),
             recv_limiter: WorkLimiter::new(RECV_TIME_BOUND),
+            batched: FxHashMap::default(),
         }
     }
 
@@ -769,25 +1844,20 @@ impl RecvState {
         now: Instant,
     ) -> Result<PollProgress, io::Error> {
         let mut received_connection_packet = false;
-        let mut metas = [RecvMeta::default(); BATCH_SIZE];
-        let mut iovs: [IoSliceMut; BATCH_SIZE] = {
-            let mut bufs = self
-                .recv_buf
-                .chunks_mut(self.recv_buf.len() / BATCH_SIZE)
-                .map(IoSliceMut::new);
-
-            // expect() safe as self.recv_buf is chunked into BATCH_SIZE items
-            // and iovs will be of size BATCH_SIZE, thus from_fn is called
-            // exactly BATCH_SIZE times.
-            std::array::from_fn(|_| bufs.next().expect("BATCH_SIZE elements"))
-        };
+        let recv_owned = socket.recv_owned();
         loop {
-            match socket.poll_recv(cx, &mut iovs, &mut metas) {
-                Poll::Ready(Ok(msgs)) => {
-                    self.recv_limiter.record_work(msgs);
-                    for (meta, buf) in metas.iter().zip(iovs.iter()).take(msgs) {
-                        let mut data: BytesMut = buf[0..meta.len].into();
+            let received = match recv_owned {
+                true => socket.poll_recv_owned(cx, &mut self.received),
+                false => self
+                    .recv_bufs
+                    .poll_recv(cx, socket, |meta, data| self.received.push((meta, data))),
+            };
+            match received {
+                Poll::Ready(Ok(())) => {
+                    let mut datagrams = 0;
+                    for (meta, mut data) in self.received.drain(..) {
                         while !data.is_empty() {
+                            datagrams += 1;
                             let buf = data.split_to(meta.stride.min(data.len()));
                             let mut response_buffer = Vec::new();
                             match endpoint.handle(
@@ -799,31 +1869,85 @@ impl RecvState {
                                 &mut response_buffer,
                             ) {
                                 Some(DatagramEvent::NewConnection(incoming)) => {
-                                    if self.connections.close.is_none() {
-                                        self.incoming.push_back(incoming);
-                                    } else {
-                                        let transmit =
-                                            endpoint.refuse(incoming, &mut response_buffer);
-                                        respond(transmit, &response_buffer, socket);
+                                    let refuse = self.connections.close.is_some()
+                                        || self.connections.draining;
+                                    let action = match (refuse, &self.filter) {
+                                        (true, _) => IncomingAction::Refuse,
+                                        (false, Some(filter)) => {
+                                            filter.filter(&IncomingInfo(&incoming), now)
+                                        }
+                                        (false, None) => IncomingAction::Accept,
+                                    };
+                                    let overloaded = self
+                                        .retry_threshold
+                                        .is_some_and(|x| endpoint.pending_handshakes() > x);
+                                    let action = match action {
+                                        IncomingAction::Accept
+                                            if overloaded
+                                                && !incoming.remote_address_validated() =>
+                                        {
+                                            IncomingAction::Retry
+                                        }
+                                        action => action,
+                                    };
+                                    match action {
+                                        IncomingAction::Accept => self.incoming.push(
+                                            incoming,
+                                            endpoint,
+                                            &mut response_buffer,
+                                            socket,
+                                        ),
+                                        IncomingAction::Retry => {
+                                            match endpoint.retry(incoming, &mut response_buffer) {
+                                                Ok(transmit) => {
+                                                    self.retried_handshakes += 1;
+                                                    #[cfg(feature = "metrics")]
+                                                    self.connections.metrics.retries.increment(1);
+                                                    respond(transmit, &response_buffer, socket)
+                                                }
+                                                Err(e) => self.incoming.push(
+                                                    e.into_incoming(),
+                                                    endpoint,
+                                                    &mut response_buffer,
+                                                    socket,
+                                                ),
+                                            }
+                                        }
+                                        IncomingAction::Refuse => {
+                                            let transmit =
+                                                endpoint.refuse(incoming, &mut response_buffer);
+                                            respond(transmit, &response_buffer, socket);
+                                        }
+                                        IncomingAction::Ignore => endpoint.ignore(incoming),
                                     }
                                 }
                                 Some(DatagramEvent::ConnectionEvent(handle, event)) => {
-                                    // Ignoring errors from dropped connections that haven't yet been cleaned up
                                     received_connection_packet = true;
-                                    let _ = self
-                                        .connections
-                                        .senders
-                                        .get_mut(&handle)
-                                        .unwrap()
-                                        .send(ConnectionEvent::Proto(event));
+                                    self.batched.entry(handle).or_default().push(event);
                                 }
                                 Some(DatagramEvent::Response(transmit)) => {
+                                    #[cfg(feature = "metrics")]
+                                    self.connections
+                                        .metrics
+                                        .on_response(&response_buffer[..transmit.size]);
                                     respond(transmit, &response_buffer, socket);
                                 }
                                 None => {}
                             }
                         }
                     }
+                    // A GRO-enabled socket may yield many datagrams for each connection per
+                    // batch, so hand them over together to save on channel operations and wakeups
+                    for (handle, events) in self.batched.drain() {
+                        // Ignoring errors from dropped connections that haven't yet been cleaned up
+                        let _ = self.connections.senders.get_mut(&handle).unwrap().send(
+                            ConnectionEvent::Datagrams {
+                                events,
+                                queued: now,
+                            },
+                        );
+                    }
+                    self.recv_limiter.record_work(datagrams);
                 }
                 Poll::Pending => {
                     return Ok(PollProgress {
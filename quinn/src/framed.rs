@@ -0,0 +1,181 @@
+use bytes::{Bytes, BytesMut};
+use proto::coding::Codec;
+use thiserror::Error;
+
+use crate::{
+    recv_stream::{ReadError, ReadExactError, RecvStream},
+    send_stream::SendStream,
+    VarInt, WriteError,
+};
+
+/// A [`SendStream`] adapter that writes length-prefixed messages
+///
+/// Each message is preceded by its length in bytes, encoded as a QUIC variable-length integer, so
+/// that the receiver can recover message boundaries from the otherwise unstructured stream.
+///
+/// Constructed by [`SendStream::into_frame_writer()`].
+#[derive(Debug)]
+pub struct FramedSendStream {
+    stream: SendStream,
+    max_frame_size: usize,
+}
+
+impl FramedSendStream {
+    pub(crate) fn new(stream: SendStream) -> Self {
+        Self {
+            stream,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+
+    /// Write `message` to the stream, preceded by its length
+    ///
+    /// Fails with [`SendMessageError::TooLarge`] without writing anything if `message` is longer
+    /// than [`max_frame_size`](Self::max_frame_size).
+    ///
+    /// This operation is *not* cancel-safe.
+    pub async fn send_message(&mut self, message: Bytes) -> Result<(), SendMessageError> {
+        if message.len() > self.max_frame_size {
+            return Err(SendMessageError::TooLarge);
+        }
+        let mut header = BytesMut::with_capacity(8);
+        VarInt::from_u64(message.len() as u64)
+            .map_err(|_| SendMessageError::TooLarge)?
+            .encode(&mut header);
+        self.stream
+            .write_all_chunks(&mut [header.freeze(), message])
+            .await?;
+        Ok(())
+    }
+
+    /// Maximum length of a message in bytes, excluding the length prefix
+    ///
+    /// Defaults to 16 MiB.
+    pub fn max_frame_size(&mut self, value: usize) -> &mut Self {
+        self.max_frame_size = value;
+        self
+    }
+
+    /// Get a reference to the underlying stream
+    pub fn get_ref(&self) -> &SendStream {
+        &self.stream
+    }
+
+    /// Get a mutable reference to the underlying stream
+    ///
+    /// Writing to the stream directly while messages are being framed will corrupt the framing.
+    pub fn get_mut(&mut self) -> &mut SendStream {
+        &mut self.stream
+    }
+
+    /// Recover the underlying stream
+    pub fn into_inner(self) -> SendStream {
+        self.stream
+    }
+}
+
+/// Errors that arise from sending a message on a [`FramedSendStream`]
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum SendMessageError {
+    /// The message exceeds the configured maximum frame size
+    #[error("message too large")]
+    TooLarge,
+    /// Writing the message to the stream failed
+    #[error("write error: {0}")]
+    Write(#[from] WriteError),
+}
+
+/// A [`RecvStream`] adapter that reads length-prefixed messages
+///
+/// Reads messages written by a [`FramedSendStream`].
+///
+/// Constructed by [`RecvStream::into_frame_reader()`].
+#[derive(Debug)]
+pub struct FramedRecvStream {
+    stream: RecvStream,
+    max_frame_size: usize,
+}
+
+impl FramedRecvStream {
+    pub(crate) fn new(stream: RecvStream) -> Self {
+        Self {
+            stream,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+
+    /// Read the next message from the stream
+    ///
+    /// Yields `Ok(None)` if the stream was finished after a complete message. Fails with
+    /// [`ReadMessageError::TooLarge`] if the peer announced a message longer than
+    /// [`max_frame_size`](Self::max_frame_size), after which the framing can't be recovered.
+    ///
+    /// This operation is *not* cancel-safe.
+    pub async fn read_message(&mut self) -> Result<Option<Bytes>, ReadMessageError> {
+        let mut header = [0; 8];
+        if self.stream.read(&mut header[..1]).await?.is_none() {
+            return Ok(None);
+        }
+        let header_len = 1 << (header[0] >> 6);
+        self.stream.read_exact(&mut header[1..header_len]).await?;
+        let len = VarInt::decode(&mut &header[..header_len])
+            .expect("length of the header is taken from its first byte")
+            .into_inner();
+        if len > self.max_frame_size as u64 {
+            return Err(ReadMessageError::TooLarge);
+        }
+        let mut message = BytesMut::zeroed(len as usize);
+        self.stream.read_exact(&mut message).await?;
+        Ok(Some(message.freeze()))
+    }
+
+    /// Maximum length of a message in bytes, excluding the length prefix
+    ///
+    /// Defaults to 16 MiB.
+    pub fn max_frame_size(&mut self, value: usize) -> &mut Self {
+        self.max_frame_size = value;
+        self
+    }
+
+    /// Get a reference to the underlying stream
+    pub fn get_ref(&self) -> &RecvStream {
+        &self.stream
+    }
+
+    /// Get a mutable reference to the underlying stream
+    ///
+    /// Reading from the stream directly while messages are being framed will corrupt the framing.
+    pub fn get_mut(&mut self) -> &mut RecvStream {
+        &mut self.stream
+    }
+
+    /// Recover the underlying stream
+    pub fn into_inner(self) -> RecvStream {
+        self.stream
+    }
+}
+
+/// Errors that arise from reading a message from a [`FramedRecvStream`]
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ReadMessageError {
+    /// The peer announced a message exceeding the configured maximum frame size
+    #[error("message too large")]
+    TooLarge,
+    /// The stream was finished in the middle of a message
+    #[error("stream finished early")]
+    FinishedEarly,
+    /// Reading the message from the stream failed
+    #[error("read error: {0}")]
+    Read(#[from] ReadError),
+}
+
+impl From<ReadExactError> for ReadMessageError {
+    fn from(e: ReadExactError) -> Self {
+        match e {
+            ReadExactError::FinishedEarly(_) => Self::FinishedEarly,
+            ReadExactError::ReadError(e) => Self::Read(e),
+        }
+    }
+}
+
+const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
@@ -0,0 +1,118 @@
+use std::{
+    io::{self, IoSliceMut},
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use bytes::BytesMut;
+use udp::{RecvMeta, Transmit};
+
+use crate::runtime::{AsyncUdpSocket, UdpPoller};
+
+/// Wraps an endpoint's socket to apply the configured GSO limit and track how GSO is used
+#[derive(Debug)]
+pub(crate) struct GsoSocket {
+    inner: Arc<dyn AsyncUdpSocket>,
+    /// Limit on segments per transmit from [`EndpointConfig::gso`](proto::EndpointConfig::gso)
+    max_segments: usize,
+    stats: Arc<GsoStats>,
+}
+
+impl GsoSocket {
+    pub(crate) fn wrap(
+        inner: Arc<dyn AsyncUdpSocket>,
+        max_segments: usize,
+        stats: Arc<GsoStats>,
+    ) -> Arc<dyn AsyncUdpSocket> {
+        Arc::new(Self {
+            inner,
+            max_segments,
+            stats,
+        })
+    }
+
+    fn record(&self, transmit: &Transmit) {
+        if let Some(segment_size) = transmit.segment_size {
+            // A batch rejected by the network interface is sent as individual datagrams, after
+            // which the socket stops offering GSO
+            if self.inner.max_transmit_segments() > 1 {
+                self.stats.transmits.fetch_add(1, Ordering::Relaxed);
+                let datagrams = (transmit.contents.len() + segment_size - 1) / segment_size;
+                self.stats
+                    .datagrams
+                    .fetch_add(datagrams as u64, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl AsyncUdpSocket for GsoSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        self.inner.clone().create_io_poller()
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        self.inner.try_send(transmit)?;
+        self.record(transmit);
+        Ok(())
+    }
+
+    fn try_send_batch(&self, transmits: &[Transmit]) -> io::Result<usize> {
+        let sent = self.inner.try_send_batch(transmits)?;
+        for transmit in &transmits[..sent] {
+            self.record(transmit);
+        }
+        Ok(sent)
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        self.inner.poll_recv(cx, bufs, meta)
+    }
+
+    fn recv_owned(&self) -> bool {
+        self.inner.recv_owned()
+    }
+
+    fn poll_recv_owned(
+        &self,
+        cx: &mut Context,
+        datagrams: &mut Vec<(RecvMeta, BytesMut)>,
+    ) -> Poll<io::Result<()>> {
+        self.inner.poll_recv_owned(cx, datagrams)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn max_transmit_segments(&self) -> usize {
+        self.inner.max_transmit_segments().min(self.max_segments)
+    }
+
+    fn max_receive_segments(&self) -> usize {
+        self.inner.max_receive_segments()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
+
+/// Counters shared by the sockets an endpoint has been bound to
+#[derive(Debug, Default)]
+pub(crate) struct GsoStats {
+    /// Transmits sent as a single GSO batch
+    pub(crate) transmits: AtomicU64,
+    /// Datagrams sent as part of a GSO batch
+    pub(crate) datagrams: AtomicU64,
+}
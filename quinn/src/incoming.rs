@@ -1,4 +1,5 @@
 use std::{
+    fmt,
     future::{Future, IntoFuture},
     net::{IpAddr, SocketAddr},
     pin::Pin,
@@ -6,7 +7,7 @@ use std::{
     task::{Context, Poll},
 };
 
-use proto::{ConnectionError, ConnectionId, ServerConfig};
+use proto::{ClientHello, ConnectionError, ConnectionId, ServerConfig};
 use thiserror::Error;
 
 use crate::{
@@ -24,6 +25,8 @@ impl Incoming {
     }
 
     /// Attempt to accept this incoming connection (an error may still occur)
+    ///
+    /// The server configuration is chosen by the endpoint's [`ServerConfigSelector`], if any.
     pub fn accept(mut self) -> Result<Connecting, ConnectionError> {
         let state = self.0.take().unwrap();
         state.endpoint.accept(state.inner, None)
@@ -81,7 +84,8 @@ impl Incoming {
     /// Whether the socket address that is initiating this connection has been validated
     ///
     /// This means that the sender of the initial packet has proved that they can receive traffic
-    /// sent to `self.remote_address()`.
+    /// sent to `self.remote_address()`, either by responding to a Retry or by presenting a token
+    /// the server sent them on an earlier connection.
     pub fn remote_address_validated(&self) -> bool {
         self.0.as_ref().unwrap().inner.remote_address_validated()
     }
@@ -90,6 +94,28 @@ impl Incoming {
     pub fn orig_dst_cid(&self) -> ConnectionId {
         *self.0.as_ref().unwrap().inner.orig_dst_cid()
     }
+
+    /// Decode the client's TLS ClientHello, if it fits in the first packet
+    ///
+    /// Useful for choosing a configuration to [`accept_with()`](Self::accept_with) by server name
+    /// or application protocol.
+    pub fn client_hello(&self) -> Option<ClientHello> {
+        self.0.as_ref().unwrap().inner.client_hello()
+    }
+}
+
+/// Chooses the [`ServerConfig`] for incoming connections based on their ClientHello
+///
+/// Allows a single endpoint to serve e.g. multiple domains with distinct certificates or
+/// transport settings. Consulted when an [`Incoming`] is accepted without an explicit
+/// configuration, before any TLS work is done for the connection.
+///
+/// Configured with [`Endpoint::set_server_config_selector()`](crate::Endpoint::set_server_config_selector).
+pub trait ServerConfigSelector: Send + Sync + fmt::Debug {
+    /// Select the configuration for a connection whose client sent `hello`
+    ///
+    /// Returning `None` uses the endpoint's default server configuration.
+    fn select(&self, hello: &ClientHello) -> Option<Arc<ServerConfig>>;
 }
 
 impl Drop for Incoming {
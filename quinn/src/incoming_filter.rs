@@ -0,0 +1,203 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Mutex,
+    time::Instant,
+};
+
+use proto::ConnectionId;
+use rustc_hash::FxHashMap;
+
+/// Decides how to handle connection attempts before they reach [`Endpoint::accept()`]
+///
+/// Filters run on the endpoint driver as soon as the first packet of a connection attempt has been
+/// decoded, before any state is allocated for it or any cryptographic handshake work is done, which
+/// makes them suitable for shedding floods of spoofed or abusive connection attempts cheaply. As
+/// they hold up the processing of all incoming packets, filters must not block. Decisions that
+/// require asynchronous work can instead be made on the [`Incoming`] returned by
+/// [`Endpoint::accept()`].
+///
+/// Configured with [`Endpoint::set_incoming_filter()`].
+///
+/// [`Endpoint::accept()`]: crate::Endpoint::accept
+/// [`Endpoint::set_incoming_filter()`]: crate::Endpoint::set_incoming_filter
+/// [`Incoming`]: crate::Incoming
+pub trait IncomingFilter: Send + Sync + fmt::Debug {
+    /// Decide what to do with the connection attempt described by `incoming`
+    fn filter(&self, incoming: &IncomingInfo<'_>, now: Instant) -> IncomingAction;
+}
+
+/// What to do with a connection attempt, as decided by an [`IncomingFilter`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IncomingAction {
+    /// Pass the connection attempt on to [`Endpoint::accept()`](crate::Endpoint::accept)
+    Accept,
+    /// Respond with a Retry packet, requiring the client to prove that it can receive traffic at
+    /// its address before the connection attempt is passed on
+    ///
+    /// Treated as [`Accept`](Self::Accept) if the client's address has already been validated.
+    Retry,
+    /// Reject the connection attempt, informing the client
+    Refuse,
+    /// Drop the connection attempt without sending any packet in response
+    Ignore,
+}
+
+/// A connection attempt being examined by an [`IncomingFilter`]
+pub struct IncomingInfo<'a>(pub(crate) &'a proto::Incoming);
+
+impl IncomingInfo<'_> {
+    /// The local IP address which was used when the peer established the connection
+    pub fn local_ip(&self) -> Option<IpAddr> {
+        self.0.local_ip()
+    }
+
+    /// The peer's UDP address
+    pub fn remote_address(&self) -> SocketAddr {
+        self.0.remote_address()
+    }
+
+    /// Whether the socket address that is initiating this connection has been validated
+    ///
+    /// See [`Incoming::remote_address_validated()`](crate::Incoming::remote_address_validated).
+    pub fn remote_address_validated(&self) -> bool {
+        self.0.remote_address_validated()
+    }
+
+    /// The original destination CID when initiating the connection
+    pub fn orig_dst_cid(&self) -> ConnectionId {
+        *self.0.orig_dst_cid()
+    }
+
+    /// The address validation token sent by the client, which is empty if there was none
+    pub fn token(&self) -> &[u8] {
+        self.0.token()
+    }
+}
+
+impl fmt::Debug for IncomingInfo<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// An [`IncomingFilter`] limiting the rate of connection attempts from each network prefix
+///
+/// Each source prefix gets a token bucket which holds up to `burst` tokens and is refilled at
+/// `rate` tokens per second. Every connection attempt takes a token from the bucket of its prefix.
+/// Once the bucket is empty, attempts from unvalidated addresses are answered with a Retry, so that
+/// spoofed sources cannot drain it further, and attempts from validated addresses are refused.
+pub struct IncomingRateLimiter {
+    rate: f64,
+    burst: f64,
+    ipv4_prefix_len: u8,
+    ipv6_prefix_len: u8,
+    buckets: Mutex<FxHashMap<IpAddr, Bucket>>,
+}
+
+impl IncomingRateLimiter {
+    /// Allow `rate` connection attempts per second from each prefix, with bursts of up to `burst`
+    pub fn new(rate: f64, burst: u32) -> Self {
+        Self {
+            rate,
+            burst: burst.into(),
+            ipv4_prefix_len: 24,
+            ipv6_prefix_len: 48,
+            buckets: Mutex::new(FxHashMap::default()),
+        }
+    }
+
+    /// Length of the prefixes IPv4 addresses are grouped by
+    ///
+    /// Defaults to 24 bits. Values greater than 32 are treated as 32.
+    pub fn ipv4_prefix_len(&mut self, value: u8) -> &mut Self {
+        self.ipv4_prefix_len = value.min(32);
+        self
+    }
+
+    /// Length of the prefixes IPv6 addresses are grouped by
+    ///
+    /// Defaults to 48 bits. Values greater than 128 are treated as 128.
+    pub fn ipv6_prefix_len(&mut self, value: u8) -> &mut Self {
+        self.ipv6_prefix_len = value.min(128);
+        self
+    }
+
+    fn prefix(&self, ip: IpAddr) -> IpAddr {
+        match ip {
+            IpAddr::V4(ip) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.ipv4_prefix_len))
+                    .unwrap_or(0);
+                Ipv4Addr::from(u32::from(ip) & mask).into()
+            }
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.ipv6_prefix_len))
+                    .unwrap_or(0);
+                Ipv6Addr::from(u128::from(ip) & mask).into()
+            }
+        }
+    }
+}
+
+impl IncomingFilter for IncomingRateLimiter {
+    fn filter(&self, incoming: &IncomingInfo<'_>, now: Instant) -> IncomingAction {
+        let remote = incoming.remote_address().ip();
+        // Treat IPv4 clients of dual-stack sockets like those of IPv4 sockets
+        let remote = match remote {
+            IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(remote, IpAddr::V4),
+            IpAddr::V4(_) => remote,
+        };
+        let prefix = self.prefix(remote);
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_PREFIXES && !buckets.contains_key(&prefix) {
+            // Buckets that have refilled completely are indistinguishable from fresh ones
+            buckets.retain(|_, bucket| bucket.level(now, self.rate) < self.burst);
+            if buckets.len() >= MAX_TRACKED_PREFIXES {
+                return IncomingAction::Retry;
+            }
+        }
+        let bucket = buckets.entry(prefix).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = bucket.level(now, self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            IncomingAction::Accept
+        } else if incoming.remote_address_validated() {
+            IncomingAction::Refuse
+        } else {
+            IncomingAction::Retry
+        }
+    }
+}
+
+impl fmt::Debug for IncomingRateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IncomingRateLimiter")
+            .field("rate", &self.rate)
+            .field("burst", &self.burst)
+            .field("ipv4_prefix_len", &self.ipv4_prefix_len)
+            .field("ipv6_prefix_len", &self.ipv6_prefix_len)
+            .finish_non_exhaustive()
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Number of tokens in the bucket at `now`, before capping to the burst size
+    fn level(&self, now: Instant, rate: f64) -> f64 {
+        self.tokens + now.saturating_duration_since(self.updated).as_secs_f64() * rate
+    }
+}
+
+/// Maximum number of prefixes an [`IncomingRateLimiter`] keeps track of, bounding its memory use
+const MAX_TRACKED_PREFIXES: usize = 64 * 1024;
@@ -52,39 +52,91 @@ macro_rules! ready {
     };
 }
 
+mod batch;
+mod buffer_pool;
 mod connection;
 mod endpoint;
+mod framed;
+mod gso;
 mod incoming;
+mod incoming_filter;
+#[cfg(feature = "metrics")]
+mod metrics;
 mod mutex;
+mod offload;
+pub mod pool;
+mod reconnect;
 mod recv_stream;
+mod resolver;
 mod runtime;
 mod send_stream;
+mod shard;
+#[cfg(feature = "test-util")]
+pub mod sim;
+mod socks5;
+mod timer_wheel;
+pub mod webtransport;
 mod work_limiter;
 
+#[cfg(feature = "qlog")]
+pub use proto::QlogFactory;
 pub use proto::{
-    congestion, crypto, AckFrequencyConfig, ApplicationClose, Chunk, ClientConfig, ClosedStream,
-    ConfigError, ConnectError, ConnectionClose, ConnectionError, ConnectionStats, EndpointConfig,
-    IdleTimeout, MtuDiscoveryConfig, ServerConfig, StreamId, Transmit, TransportConfig, VarInt,
+    congestion, crypto, scheduler, transport_parameters, AckFrequencyConfig, ApplicationClose,
+    Chunk, ClientConfig, ClientHello, ClosedStream, ConfigError, ConnectError, ConnectionClose,
+    ConnectionError, ConnectionStats, DatagramDropPolicy, EndpointConfig, FrameSummary,
+    HandshakeEvents, IdleTimeout, KeepAliveProbe, MigrateError, MtuDiscoveryConfig, PacingConfig,
+    PacketInspector, PacketSummary, PacketType, PathStats, ResetAtError, SendBufferAllocator,
+    ServerConfig, StatsDelta, StreamGroupId, StreamId, StreamStats, StreamWindowUpdateConfig,
+    Transmit, TransportConfig, VarInt,
 };
 #[cfg(any(feature = "rustls-aws-lc-rs", feature = "rustls-ring"))]
 pub use rustls;
 pub use udp;
 
+pub use crate::batch::TransmitBatchConfig;
 pub use crate::connection::{
-    AcceptBi, AcceptUni, Connecting, Connection, OpenBi, OpenUni, ReadDatagram, SendDatagram,
-    SendDatagramError, ZeroRttAccepted,
+    AcceptBi, AcceptUni, Connecting, Connection, DatagramOutcome, DatagramTicket, DriverStats,
+    Event, Events, OpenBi, OpenUni, PathUpdates, ReadDatagram, ReadDatagrams, SendDatagram,
+    SendDatagramError, StatsStream, StreamGroup, StreamOpts, ZeroRttAccepted,
 };
-pub use crate::endpoint::{Accept, Endpoint, EndpointStats};
-pub use crate::incoming::{Incoming, IncomingFuture, RetryError};
+#[cfg(feature = "futures")]
+pub use crate::connection::{DatagramSink, DatagramStream};
+pub use crate::endpoint::{
+    Accept, AcceptQueueOverflow, ConnectMultiError, ConnectToError, ConnectionInfo, Drain,
+    Endpoint, EndpointStats,
+};
+pub use crate::framed::{FramedRecvStream, FramedSendStream, ReadMessageError, SendMessageError};
+pub use crate::incoming::{Incoming, IncomingFuture, RetryError, ServerConfigSelector};
+pub use crate::incoming_filter::{
+    IncomingAction, IncomingFilter, IncomingInfo, IncomingRateLimiter,
+};
+#[cfg(feature = "metrics")]
+pub use crate::metrics::MetricsConfig;
+pub use crate::offload::HandshakeOffloadConfig;
+pub use crate::reconnect::{
+    ReconnectConfig, ReconnectError, ReconnectHook, ReconnectingConnection,
+};
+#[cfg(feature = "futures")]
+pub use crate::recv_stream::UnorderedChunks;
 pub use crate::recv_stream::{ReadError, ReadExactError, ReadToEndError, RecvStream, ResetError};
+#[cfg(feature = "runtime-tokio")]
+pub use crate::resolver::TokioResolver;
+pub use crate::resolver::{
+    default_resolver, AddressFamilyPreference, Resolve, Resolver, ThreadResolver,
+};
 #[cfg(feature = "runtime-async-std")]
 pub use crate::runtime::AsyncStdRuntime;
+#[cfg(all(feature = "runtime-io-uring", target_os = "linux"))]
+pub use crate::runtime::IoUringRuntime;
 #[cfg(feature = "runtime-smol")]
 pub use crate::runtime::SmolRuntime;
 #[cfg(feature = "runtime-tokio")]
 pub use crate::runtime::TokioRuntime;
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+pub use crate::runtime::XdpUdpSocket;
 pub use crate::runtime::{default_runtime, AsyncTimer, AsyncUdpSocket, Runtime, UdpPoller};
 pub use crate::send_stream::{SendStream, StoppedError, WriteError};
+pub use crate::socks5::Socks5UdpSocket;
 
 #[cfg(test)]
 mod tests;
@@ -96,6 +148,12 @@ enum ConnectionEvent {
         reason: bytes::Bytes,
     },
     Proto(proto::ConnectionEvent),
+    /// Datagrams for the connection received in a single batch from the socket
+    Datagrams {
+        events: Vec<proto::ConnectionEvent>,
+        /// When the datagrams were handed to the connection
+        queued: std::time::Instant,
+    },
     Rebind(Arc<dyn AsyncUdpSocket>),
 }
 
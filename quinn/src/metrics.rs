@@ -0,0 +1,157 @@
+use std::{fmt, net::SocketAddr, sync::Arc};
+
+use ::metrics::{Counter, Label};
+use proto::{ConnectionStats, StatsDelta};
+
+/// Controls how endpoint and connection counters are exported through the `metrics` facade
+///
+/// Counters are recorded with whichever recorder is installed, so any exporter built on the facade
+/// works, e.g. `metrics-exporter-prometheus`, or an OpenTelemetry meter through a bridge such as
+/// `metrics-exporter-opentelemetry`. Every name is prefixed, and every counter carries the
+/// configured static labels. Per-connection labels are opt-in, as their cardinality grows with the
+/// number of peers.
+///
+/// The exported counters are, before prefixing:
+///
+/// - `handshakes_accepted_total`, `handshakes_outgoing_total`, `handshakes_refused_total` and
+///   `handshakes_ignored_total`, mirroring [`EndpointStats`](crate::EndpointStats)
+/// - `retries_total`: Retry packets sent to validate a client's address
+/// - `version_negotiations_total`: Version Negotiation packets sent in response to unsupported
+///   versions
+/// - `packets_sent_total`, `packets_received_total` and `packets_lost_total`
+/// - `bytes_sent_total`, `bytes_received_total` and `bytes_lost_total`
+/// - `congestion_events_total`
+#[derive(Debug, Clone)]
+pub struct MetricsConfig {
+    prefix: String,
+    labels: Vec<Label>,
+    remote_label: bool,
+}
+
+impl MetricsConfig {
+    /// Prefix of every counter name, separated from it by an underscore
+    ///
+    /// Defaults to `quinn`.
+    pub fn prefix(&mut self, value: impl Into<String>) -> &mut Self {
+        self.prefix = value.into();
+        self
+    }
+
+    /// Add a label with a fixed value to every counter, e.g. to tell endpoints apart
+    pub fn label(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.labels.push(Label::new(key.into(), value.into()));
+        self
+    }
+
+    /// Whether to label connection counters with the peer's IP address
+    ///
+    /// Produces a time series for every peer, so should only be enabled when they are few.
+    /// Defaults to false.
+    pub fn remote_label(&mut self, value: bool) -> &mut Self {
+        self.remote_label = value;
+        self
+    }
+
+    fn counter(&self, name: &str, extra: Option<Label>) -> Counter {
+        let labels = self.labels.iter().cloned().chain(extra).collect::<Vec<_>>();
+        ::metrics::counter!(format!("{}_{}", self.prefix, name), labels)
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            prefix: "quinn".into(),
+            labels: Vec::new(),
+            remote_label: false,
+        }
+    }
+}
+
+/// Endpoint-level counters
+pub(crate) struct EndpointMetrics {
+    config: Arc<MetricsConfig>,
+    pub(crate) accepted_handshakes: Counter,
+    pub(crate) outgoing_handshakes: Counter,
+    pub(crate) refused_handshakes: Counter,
+    pub(crate) ignored_handshakes: Counter,
+    pub(crate) retries: Counter,
+    pub(crate) version_negotiations: Counter,
+}
+
+impl EndpointMetrics {
+    pub(crate) fn new(config: MetricsConfig) -> Self {
+        Self {
+            accepted_handshakes: config.counter("handshakes_accepted_total", None),
+            outgoing_handshakes: config.counter("handshakes_outgoing_total", None),
+            refused_handshakes: config.counter("handshakes_refused_total", None),
+            ignored_handshakes: config.counter("handshakes_ignored_total", None),
+            retries: config.counter("retries_total", None),
+            version_negotiations: config.counter("version_negotiations_total", None),
+            config: Arc::new(config),
+        }
+    }
+
+    /// Counters for a new connection with `remote`
+    pub(crate) fn connection(&self, remote: SocketAddr) -> ConnectionMetrics {
+        let config = &*self.config;
+        let remote = config
+            .remote_label
+            .then(|| Label::new("remote", remote.ip().to_string()));
+        let counter = |name| config.counter(name, remote.clone());
+        ConnectionMetrics {
+            packets_sent: counter("packets_sent_total"),
+            packets_received: counter("packets_received_total"),
+            packets_lost: counter("packets_lost_total"),
+            bytes_sent: counter("bytes_sent_total"),
+            bytes_received: counter("bytes_received_total"),
+            bytes_lost: counter("bytes_lost_total"),
+            congestion_events: counter("congestion_events_total"),
+            last: ConnectionStats::default(),
+        }
+    }
+
+    /// Count `response` if it is a Version Negotiation packet
+    pub(crate) fn on_response(&self, response: &[u8]) {
+        // Long header with version 0 (RFC 9000 §17.2.1)
+        if response.len() >= 5 && response[0] & 0x80 != 0 && response[1..5] == [0; 4] {
+            self.version_negotiations.increment(1);
+        }
+    }
+}
+
+impl fmt::Debug for EndpointMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EndpointMetrics")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Connection-level counters, fed from the connection's statistics
+pub(crate) struct ConnectionMetrics {
+    packets_sent: Counter,
+    packets_received: Counter,
+    packets_lost: Counter,
+    bytes_sent: Counter,
+    bytes_received: Counter,
+    bytes_lost: Counter,
+    congestion_events: Counter,
+    /// Statistics as of the previous update
+    last: ConnectionStats,
+}
+
+impl ConnectionMetrics {
+    /// Increment the counters by the progress since the previous update
+    pub(crate) fn update(&mut self, stats: ConnectionStats) {
+        let delta = StatsDelta::between(&self.last, &stats);
+        self.packets_sent.increment(delta.udp_tx.datagrams);
+        self.packets_received.increment(delta.udp_rx.datagrams);
+        self.packets_lost.increment(delta.lost_packets);
+        self.bytes_sent.increment(delta.udp_tx.bytes);
+        self.bytes_received.increment(delta.udp_rx.bytes);
+        self.bytes_lost.increment(delta.lost_bytes);
+        self.congestion_events.increment(delta.congestion_events);
+        self.last = stats;
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    collections::VecDeque,
+    fmt, io,
+    num::NonZeroUsize,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    thread,
+};
+
+use tracing::error;
+
+/// Threads performing the handshake cryptography of an endpoint's incoming connections
+///
+/// See [`Endpoint::set_handshake_offload()`](crate::Endpoint::set_handshake_offload).
+#[derive(Debug, Clone)]
+pub struct HandshakeOffloadConfig {
+    threads: usize,
+}
+
+impl HandshakeOffloadConfig {
+    /// Number of threads handshakes are spread over
+    ///
+    /// Defaults to the available parallelism, up to 4.
+    pub fn threads(&mut self, value: NonZeroUsize) -> &mut Self {
+        self.threads = value.get();
+        self
+    }
+}
+
+impl Default for HandshakeOffloadConfig {
+    fn default() -> Self {
+        Self {
+            threads: thread::available_parallelism().map_or(1, |n| n.get().min(4)),
+        }
+    }
+}
+
+/// A pool of threads running jobs handed over by an endpoint
+///
+/// The threads exit once the pool is dropped and the jobs queued by then have run.
+pub(crate) struct HandshakePool {
+    shared: Arc<PoolShared>,
+}
+
+impl HandshakePool {
+    pub(crate) fn new(config: &HandshakeOffloadConfig) -> io::Result<Arc<Self>> {
+        let shared = Arc::new(PoolShared {
+            jobs: Mutex::new(Jobs {
+                queue: VecDeque::new(),
+                closed: false,
+            }),
+            available: Condvar::new(),
+        });
+        let pool = Arc::new(Self {
+            shared: shared.clone(),
+        });
+        for i in 0..config.threads {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name(format!("quinn-handshake-{i}"))
+                .spawn(move || shared.run())?;
+        }
+        Ok(pool)
+    }
+
+    /// Run `job` on one of the pool's threads
+    pub(crate) fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        self.shared.lock().queue.push_back(Box::new(job));
+        self.shared.available.notify_one();
+    }
+}
+
+impl Drop for HandshakePool {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.available.notify_all();
+    }
+}
+
+impl fmt::Debug for HandshakePool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HandshakePool")
+            .field("queued", &self.shared.lock().queue.len())
+            .finish_non_exhaustive()
+    }
+}
+
+struct PoolShared {
+    jobs: Mutex<Jobs>,
+    /// Notified when a job is queued or the pool is dropped
+    available: Condvar,
+}
+
+impl PoolShared {
+    fn run(&self) {
+        let mut jobs = self.lock();
+        loop {
+            if let Some(job) = jobs.queue.pop_front() {
+                drop(jobs);
+                // A panicking job must neither take its thread down nor stall the jobs behind it
+                if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                    error!("handshake job panicked");
+                }
+                jobs = self.lock();
+            } else if jobs.closed {
+                return;
+            } else {
+                jobs = self
+                    .available
+                    .wait(jobs)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+        }
+    }
+
+    /// Lock the job queue, which is left consistent even if a thread panicked while holding it
+    fn lock(&self) -> MutexGuard<'_, Jobs> {
+        self.jobs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+struct Jobs {
+    queue: VecDeque<Box<dyn FnOnce() + Send>>,
+    /// Whether the pool was dropped
+    closed: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn panicking_job() {
+        let mut config = HandshakeOffloadConfig::default();
+        config.threads(NonZeroUsize::new(1).unwrap());
+        let pool = HandshakePool::new(&config).unwrap();
+        pool.execute(|| panic!("job failed"));
+        let (send, recv) = mpsc::channel();
+        pool.execute(move || send.send(()).unwrap());
+        recv.recv_timeout(std::time::Duration::from_secs(5))
+            .expect("pool thread died with the panicking job");
+    }
+}
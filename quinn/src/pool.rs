@@ -0,0 +1,256 @@
+//! Reuse of client connections across requests
+//!
+//! A [`ConnectionPool`] hands out [`Lease`]s on connections to servers, opening a connection only
+//! when no existing one to the same server has room for another lease. Each lease stands for a
+//! unit of work sharing the connection, typically a single request on its own stream, so that the
+//! number of leases per connection bounds how many requests are multiplexed on it.
+//!
+//! ```no_run
+//! # async fn f(endpoint: quinn::Endpoint) -> Result<(), Box<dyn std::error::Error>> {
+//! use quinn::pool::{ConnectionPool, PoolConfig};
+//!
+//! let pool = ConnectionPool::new(endpoint, PoolConfig::default());
+//! let conn = pool.get("192.0.2.1:4433".parse()?, "example.com").await?;
+//! let (mut send, mut recv) = conn.open_bi().await?;
+//! // ...
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    net::SocketAddr,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use proto::{ConnectError, ConnectionError};
+use rustc_hash::FxHashMap;
+use thiserror::Error;
+
+use crate::{Connection, Endpoint};
+
+/// Limits and timings of a [`ConnectionPool`]
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    max_leases_per_connection: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl PoolConfig {
+    /// Maximum number of concurrent leases on a single connection
+    ///
+    /// Once every connection to a server has this many leases outstanding, the next lease opens
+    /// another connection. Defaults to 100.
+    ///
+    /// The pool doesn't track the stream credit granted by the server. If this exceeds the number
+    /// of concurrent streams the server allows, leases beyond that credit still share the
+    /// connection, and opening their streams waits until the server allows more, rather than
+    /// spilling over to a new connection. Keep it no higher than the server's stream limit for
+    /// the kind of stream each lease opens.
+    pub fn max_leases_per_connection(&mut self, value: u32) -> &mut Self {
+        self.max_leases_per_connection = value.max(1);
+        self
+    }
+
+    /// Time to wait before connecting again to a server that the last attempt failed to reach
+    ///
+    /// Doubles with each consecutive failure, up to `max`. During that time, leases on the server
+    /// fail immediately with the error of the last attempt. Defaults to 100ms, up to 30s.
+    pub fn backoff(&mut self, initial: Duration, max: Duration) -> &mut Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_leases_per_connection: 100,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Connections to servers shared between independent users
+///
+/// Connections are kept per server name and address, and made with the endpoint's
+/// [default client config](Endpoint::set_default_client_config). Closed connections, and servers
+/// left without connections or recent failures, are dropped from the pool on the next lookup. Leases taken concurrently while no connection has room may
+/// each open a connection.
+///
+/// May be cloned to obtain another handle to the same pool.
+#[derive(Debug, Clone)]
+pub struct ConnectionPool {
+    endpoint: Endpoint,
+    config: Arc<PoolConfig>,
+    servers: Arc<Mutex<FxHashMap<(String, SocketAddr), Server>>>,
+}
+
+impl ConnectionPool {
+    /// Create an empty pool making connections from `endpoint`
+    pub fn new(endpoint: Endpoint, config: PoolConfig) -> Self {
+        Self {
+            endpoint,
+            config: Arc::new(config),
+            servers: Arc::default(),
+        }
+    }
+
+    /// Lease a connection to the server at `addr` named `server_name`, connecting if necessary
+    ///
+    /// The least loaded open connection with room for another lease is used if there is one.
+    pub async fn get(&self, addr: SocketAddr, server_name: &str) -> Result<Lease, PoolError> {
+        let key = (server_name.to_owned(), addr);
+        {
+            let mut servers = self.servers.lock().unwrap();
+            self.prune(&mut servers);
+            let server = servers.entry(key.clone()).or_default();
+            if let Some((conn, leases)) = server
+                .connections
+                .iter()
+                .filter(|(_, leases)| {
+                    leases.load(Ordering::Relaxed) < self.config.max_leases_per_connection
+                })
+                .min_by_key(|(_, leases)| leases.load(Ordering::Relaxed))
+            {
+                return Ok(Lease::new(conn.clone(), leases.clone()));
+            }
+            if let Some((retry_at, error)) = &server.backoff {
+                if self.endpoint.runtime.now() < *retry_at {
+                    return Err(error.clone());
+                }
+            }
+        }
+
+        let result = match self.endpoint.connect(addr, server_name) {
+            Ok(connecting) => connecting.await.map_err(PoolError::from),
+            Err(e) => Err(e.into()),
+        };
+
+        let mut servers = self.servers.lock().unwrap();
+        let server = servers.entry(key).or_default();
+        match result {
+            Ok(conn) => {
+                server.failures = 0;
+                server.backoff = None;
+                let leases = Arc::new(AtomicU32::new(0));
+                server.connections.push((conn.clone(), leases.clone()));
+                Ok(Lease::new(conn, leases))
+            }
+            Err(error) => {
+                let backoff = self
+                    .config
+                    .initial_backoff
+                    .saturating_mul(1 << server.failures.min(16))
+                    .min(self.config.max_backoff);
+                server.failures += 1;
+                server.backoff = Some((self.endpoint.runtime.now() + backoff, error.clone()));
+                Err(error)
+            }
+        }
+    }
+
+    /// Drop closed connections, and servers with neither connections nor recent failures
+    ///
+    /// A server's failures are remembered for `max_backoff` after its backoff expires, so that
+    /// attempts made soon after keep backing off further.
+    fn prune(&self, servers: &mut FxHashMap<(String, SocketAddr), Server>) {
+        let now = self.endpoint.runtime.now();
+        servers.retain(|_, server| {
+            server
+                .connections
+                .retain(|(conn, _)| conn.close_reason().is_none());
+            !server.connections.is_empty()
+                || server
+                    .backoff
+                    .as_ref()
+                    .is_some_and(|(retry_at, _)| now < *retry_at + self.config.max_backoff)
+        });
+    }
+
+    /// Number of servers the pool holds connections to or state about
+    #[cfg(test)]
+    pub(crate) fn servers(&self) -> usize {
+        self.servers.lock().unwrap().len()
+    }
+
+    /// Number of open connections in the pool
+    pub fn open_connections(&self) -> usize {
+        self.servers
+            .lock()
+            .unwrap()
+            .values()
+            .flat_map(|server| &server.connections)
+            .filter(|(conn, _)| conn.close_reason().is_none())
+            .count()
+    }
+}
+
+/// Connections to, and recent failures of, a single server
+#[derive(Default)]
+struct Server {
+    /// Each connection along with its number of outstanding leases
+    connections: Vec<(Connection, Arc<AtomicU32>)>,
+    /// Consecutive failed connection attempts
+    failures: u32,
+    /// When to attempt another connection after a failure, and the error to fail with until then
+    backoff: Option<(Instant, PoolError)>,
+}
+
+impl std::fmt::Debug for Server {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Server")
+            .field("connections", &self.connections.len())
+            .field("failures", &self.failures)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A connection taken from a [`ConnectionPool`]
+///
+/// Dereferences to the [`Connection`]. Dropping the lease makes room for another one on the
+/// connection, but doesn't close it; streams opened through the lease remain usable, though they
+/// no longer count towards the connection's load.
+#[derive(Debug)]
+pub struct Lease {
+    conn: Connection,
+    leases: Arc<AtomicU32>,
+}
+
+impl Lease {
+    fn new(conn: Connection, leases: Arc<AtomicU32>) -> Self {
+        leases.fetch_add(1, Ordering::Relaxed);
+        Self { conn, leases }
+    }
+}
+
+impl Deref for Lease {
+    type Target = Connection;
+    fn deref(&self) -> &Connection {
+        &self.conn
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.leases.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Errors that can arise when leasing a connection from a [`ConnectionPool`]
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+pub enum PoolError {
+    /// The connection could not be started
+    #[error(transparent)]
+    Connect(#[from] ConnectError),
+    /// The connection failed to be established
+    #[error(transparent)]
+    Connection(#[from] ConnectionError),
+}
@@ -0,0 +1,226 @@
+use std::{
+    fmt,
+    future::{poll_fn, Future},
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+
+use proto::{ConnectError, ConnectionError};
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use crate::{Connection, Endpoint};
+
+/// Application callbacks of a [`ReconnectingConnection`]
+///
+/// Every method has a default implementation doing nothing.
+pub trait ReconnectHook: Send + Sync + 'static {
+    /// A connection was established, for the first time if `generation` is zero
+    ///
+    /// The connection is handed out by [`ReconnectingConnection::connection()`] only once the
+    /// returned future completes, so this is where streams the application keeps open should be
+    /// re-opened, and any state the peer lost resynchronized. With 0-RTT, the handshake may not be
+    /// complete yet.
+    #[allow(unused_variables)]
+    fn on_connected(
+        &self,
+        conn: Connection,
+        generation: u64,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(async {})
+    }
+
+    /// The connection was found lost, and is about to be re-established
+    #[allow(unused_variables)]
+    fn on_disconnected(&self, reason: &ConnectionError) {}
+}
+
+/// Limits and timings of a [`ReconnectingConnection`]
+#[derive(Debug, Clone)]
+pub struct ReconnectConfig {
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    max_attempts: Option<u32>,
+    zero_rtt: bool,
+}
+
+impl ReconnectConfig {
+    /// Time to wait before connecting again after a failed attempt
+    ///
+    /// Doubles with each consecutive failure, up to `max`. The first attempt after losing a
+    /// connection is made immediately. Defaults to 100ms, up to 30s.
+    pub fn backoff(&mut self, initial: Duration, max: Duration) -> &mut Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Number of consecutive failed attempts after which to give up, or `None` to retry forever
+    ///
+    /// Once given up, [`ReconnectingConnection::connection()`] fails with the last error, and
+    /// starts over on the next call. Defaults to `None`.
+    pub fn max_attempts(&mut self, value: Option<u32>) -> &mut Self {
+        self.max_attempts = value;
+        self
+    }
+
+    /// Whether to hand out re-established connections in 0-RTT when possible
+    ///
+    /// Data sent in 0-RTT may be replayed by an attacker, so this should only be enabled if
+    /// whatever the application and its [`ReconnectHook`] send first is idempotent. Defaults to
+    /// `false`.
+    pub fn zero_rtt(&mut self, value: bool) -> &mut Self {
+        self.zero_rtt = value;
+        self
+    }
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+            max_attempts: None,
+            zero_rtt: false,
+        }
+    }
+}
+
+/// A connection to a single server that is transparently re-established once lost
+///
+/// Suited to long-lived channels, e.g. between a device and its controller. The connection is
+/// re-established lazily, by the first call to [`connection()`](Self::connection) after it was
+/// lost; to do so eagerly, call it as soon as [`Connection::closed()`] resolves. Connections are
+/// made with the endpoint's [default client config](Endpoint::set_default_client_config).
+///
+/// May be cloned to obtain another handle to the same connection.
+#[derive(Clone)]
+pub struct ReconnectingConnection {
+    endpoint: Endpoint,
+    addr: SocketAddr,
+    server_name: Arc<str>,
+    config: Arc<ReconnectConfig>,
+    hook: Option<Arc<dyn ReconnectHook>>,
+    state: Arc<Mutex<State>>,
+}
+
+impl ReconnectingConnection {
+    /// Connect to the server at `addr` named `server_name`
+    ///
+    /// Makes the first connection, retrying according to `config`, and calls
+    /// [`ReconnectHook::on_connected()`] with it.
+    pub async fn connect(
+        endpoint: Endpoint,
+        addr: SocketAddr,
+        server_name: &str,
+        config: ReconnectConfig,
+        hook: Option<Arc<dyn ReconnectHook>>,
+    ) -> Result<Self, ReconnectError> {
+        let this = Self {
+            endpoint,
+            addr,
+            server_name: server_name.into(),
+            config: Arc::new(config),
+            hook,
+            state: Arc::new(Mutex::new(State {
+                conn: None,
+                generation: 0,
+            })),
+        };
+        this.connection().await?;
+        Ok(this)
+    }
+
+    /// The current connection, re-established first if it was lost
+    ///
+    /// Concurrent callers wait for the same attempt to re-establish the connection.
+    pub async fn connection(&self) -> Result<Connection, ReconnectError> {
+        let mut state = self.state.lock().await;
+        if let Some(conn) = &state.conn {
+            let Some(reason) = conn.close_reason() else {
+                return Ok(conn.clone());
+            };
+            debug!(%reason, "connection lost, reconnecting");
+            if let Some(hook) = &self.hook {
+                hook.on_disconnected(&reason);
+            }
+            state.conn = None;
+            state.generation += 1;
+        }
+
+        let mut failures = 0;
+        let conn = loop {
+            match self.attempt().await {
+                Ok(conn) => break conn,
+                Err(e) => {
+                    failures += 1;
+                    if self.config.max_attempts.is_some_and(|max| failures >= max) {
+                        return Err(e);
+                    }
+                    debug!(error = %e, failures, "reconnection attempt failed");
+                }
+            }
+            let backoff = self
+                .config
+                .initial_backoff
+                .saturating_mul(1 << (failures - 1).min(16))
+                .min(self.config.max_backoff);
+            let runtime = &self.endpoint.runtime;
+            let mut timer = runtime.new_timer(runtime.now() + backoff);
+            poll_fn(|cx| timer.as_mut().poll(cx)).await;
+        };
+
+        if let Some(hook) = &self.hook {
+            hook.on_connected(conn.clone(), state.generation).await;
+        }
+        state.conn = Some(conn.clone());
+        Ok(conn)
+    }
+
+    /// Number of times the connection was re-established
+    pub async fn generation(&self) -> u64 {
+        self.state.lock().await.generation
+    }
+
+    async fn attempt(&self) -> Result<Connection, ReconnectError> {
+        let connecting = self.endpoint.connect(self.addr, &self.server_name)?;
+        if !self.config.zero_rtt {
+            return Ok(connecting.await?);
+        }
+        match connecting.into_0rtt() {
+            Ok((conn, _)) => Ok(conn),
+            Err(connecting) => Ok(connecting.await?),
+        }
+    }
+}
+
+impl fmt::Debug for ReconnectingConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReconnectingConnection")
+            .field("addr", &self.addr)
+            .field("server_name", &self.server_name)
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+struct State {
+    /// The last connection established, if any
+    conn: Option<Connection>,
+    /// Connections lost so far
+    generation: u64,
+}
+
+/// Errors that can arise when (re-)establishing a [`ReconnectingConnection`]
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+pub enum ReconnectError {
+    /// The last connection attempt could not be started
+    #[error(transparent)]
+    Connect(#[from] ConnectError),
+    /// The last connection attempt failed
+    #[error(transparent)]
+    Connection(#[from] ConnectionError),
+}
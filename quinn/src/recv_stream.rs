@@ -5,12 +5,12 @@ use std::{
     task::{Context, Poll},
 };
 
-use bytes::Bytes;
+use bytes::{Buf, Bytes};
 use proto::{Chunk, Chunks, ClosedStream, ConnectionError, ReadableError, StreamId};
 use thiserror::Error;
 use tokio::io::ReadBuf;
 
-use crate::{connection::ConnectionRef, VarInt};
+use crate::{connection::ConnectionRef, FramedRecvStream, VarInt};
 
 /// A stream that can only be used to receive data
 ///
@@ -55,6 +55,8 @@ pub struct RecvStream {
     is_0rtt: bool,
     all_data_read: bool,
     reset: Option<VarInt>,
+    /// Data peeked by `AsyncBufRead::poll_fill_buf` that hasn't been consumed yet
+    buffered: Bytes,
 }
 
 impl RecvStream {
@@ -65,6 +67,7 @@ impl RecvStream {
             is_0rtt,
             all_data_read: false,
             reset: None,
+            buffered: Bytes::new(),
         }
     }
 
@@ -141,6 +144,50 @@ impl RecvStream {
         .map(|res| res.map(|_| ()))
     }
 
+    /// Foundation of the `AsyncBufRead` implementations
+    ///
+    /// Exposes the next contiguous chunk held by the stream as-is, without copying it out. The
+    /// data remains in the stream until passed to [`Self::consume_buffered`].
+    fn poll_fill_buf(&mut self, cx: &mut Context) -> Poll<Result<&[u8], ReadError>> {
+        if self.buffered.is_empty() {
+            let chunk = ready!(self.poll_read_generic(cx, true, |chunks| {
+                match chunks.peek_chunk() {
+                    Ok(Some(chunk)) => ReadStatus::Readable(chunk),
+                    res => (None, res.err()).into(),
+                }
+            }))?;
+            if let Some(chunk) = chunk {
+                self.buffered = chunk.bytes;
+            }
+        }
+        Poll::Ready(Ok(&self.buffered))
+    }
+
+    /// Consume `amt` bytes of the data exposed by [`Self::poll_fill_buf`]
+    fn consume_buffered(&mut self, amt: usize) {
+        let amt = amt.min(self.buffered.len());
+        if amt == 0 {
+            return;
+        }
+        self.buffered.advance(amt);
+
+        let mut conn = self.conn.state.lock("RecvStream::consume");
+        let mut recv = conn.inner.recv_stream(self.stream);
+        let Ok(mut chunks) = recv.read(true) else {
+            return;
+        };
+        let mut remaining = amt;
+        while remaining > 0 {
+            match chunks.next(remaining) {
+                Ok(Some(chunk)) => remaining -= chunk.bytes.len(),
+                _ => break,
+            }
+        }
+        if chunks.finalize().should_transmit() {
+            conn.wake();
+        }
+    }
+
     /// Read the next segment of data
     ///
     /// Yields `None` if the stream was finished. Otherwise, yields a segment of data and its
@@ -167,6 +214,20 @@ impl RecvStream {
         .await
     }
 
+    /// Get a [`Stream`](futures_core::Stream) of the data on this stream, in the order it arrives
+    ///
+    /// Each item is a [`Chunk`] whose `offset` locates it within the stream, as with unordered
+    /// [`read_chunk()`](Self::read_chunk) calls. Data is yielded as soon as it is received rather
+    /// than after any gaps before it are filled, and isn't buffered by the stream once yielded,
+    /// which suits applications that can consume ranges out of order. The stream ends once the peer
+    /// finishes sending and all data has been yielded.
+    ///
+    /// As with other unordered reads, ordered reads are no longer possible afterwards.
+    #[cfg(feature = "futures")]
+    pub fn read_unordered_stream(&mut self) -> UnorderedChunks<'_> {
+        UnorderedChunks { stream: self }
+    }
+
     /// Attempts to read a chunk from the stream.
     ///
     /// On success, returns `Poll::Ready(Ok(Some(chunk)))`. If `Poll::Ready(Ok(None))`
@@ -230,6 +291,44 @@ impl RecvStream {
         })
     }
 
+    /// Copy data from the stream into `buf` without consuming it
+    ///
+    /// Waits until data is available like [`read()`](Self::read), and yields the same data that a
+    /// subsequent `read()` would, which remains in the stream to be read later. This makes it
+    /// possible to inspect the beginning of a stream, e.g. to detect which protocol it carries,
+    /// before handing it off. Peeked data occupies stream flow control credit until it is read.
+    /// Yields `None` if the stream was finished.
+    ///
+    /// This operation is cancel-safe.
+    pub async fn peek(&mut self, buf: &mut [u8]) -> Result<Option<usize>, ReadError> {
+        if buf.is_empty() {
+            return Ok(Some(0));
+        }
+        poll_fn(|cx| {
+            self.poll_read_generic(cx, true, |chunks| match chunks.peek(buf) {
+                Ok(Some(n)) => ReadStatus::Readable(n),
+                res => (None, res.err()).into(),
+            })
+        })
+        .await
+    }
+
+    /// Get the next chunk of data from the stream without consuming it
+    ///
+    /// Yields the chunk that a subsequent ordered [`read_chunk()`](Self::read_chunk) would start
+    /// with, or `None` if the stream was finished. See [`peek()`](Self::peek) for details.
+    ///
+    /// This operation is cancel-safe.
+    pub async fn peek_chunk(&mut self) -> Result<Option<Chunk>, ReadError> {
+        poll_fn(|cx| {
+            self.poll_read_generic(cx, true, |chunks| match chunks.peek_chunk() {
+                Ok(Some(chunk)) => ReadStatus::Readable(chunk),
+                res => (None, res.err()).into(),
+            })
+        })
+        .await
+    }
+
     /// Convenience method to read all remaining data into a buffer
     ///
     /// Fails with [`ReadToEndError::TooLong`] on reading more than `size_limit` bytes, discarding
@@ -253,6 +352,32 @@ impl RecvStream {
         .await
     }
 
+    /// Read all remaining data, appending it to `buf`
+    ///
+    /// Unlike [`read_to_end()`](Self::read_to_end), data is delivered in order and retained in
+    /// `buf` as it arrives, so that whatever was received before a failure is not lost. Yields the
+    /// number of bytes appended on success. Fails with [`ReadToEndError::TooLong`] on reading more
+    /// than `size_limit` bytes, after appending the first `size_limit` bytes to `buf`.
+    ///
+    /// This operation is cancel-safe in the sense that dropping the future before completion
+    /// leaves all data read so far in `buf`, allowing the read to be resumed later or abandoned
+    /// with the partial data in hand.
+    ///
+    /// [`ReadToEndError::TooLong`]: crate::ReadToEndError::TooLong
+    pub async fn read_to_end_into(
+        &mut self,
+        buf: &mut Vec<u8>,
+        size_limit: usize,
+    ) -> Result<usize, ReadToEndError> {
+        ReadToEndInto {
+            stream: self,
+            buf,
+            read: 0,
+            size_limit,
+        }
+        .await
+    }
+
     /// Stop accepting data
     ///
     /// Discards unread data and notifies the peer to stop transmitting. Once stopped, further
@@ -265,6 +390,36 @@ impl RecvStream {
         conn.inner.recv_stream(self.stream).stop(error_code)?;
         conn.wake();
         self.all_data_read = true;
+        self.buffered.clear();
+        Ok(())
+    }
+
+    /// Grow the flow control window of this stream to at least `window` bytes
+    ///
+    /// Lets the peer send more data on this stream than
+    /// [`TransportConfig::stream_receive_window()`](crate::TransportConfig::stream_receive_window)
+    /// allows, e.g. to reserve room for a stream carrying a long-lived session. Data remains
+    /// subject to the connection-level window. Has no effect if the window is already larger.
+    pub fn set_receive_window(&mut self, window: VarInt) -> Result<(), ClosedStream> {
+        let mut conn = self.conn.state.lock("RecvStream::set_receive_window");
+        conn.inner
+            .recv_stream(self.stream)
+            .set_receive_window(window)?;
+        conn.wake();
+        Ok(())
+    }
+
+    /// Let the peer send `credit` more bytes on this stream
+    ///
+    /// Only has an effect if credit is
+    /// [app-driven](crate::StreamWindowUpdateConfig::app_driven), in which case this is the only
+    /// way the peer is allowed to send more than the initial
+    /// [`TransportConfig::stream_receive_window()`](crate::TransportConfig::stream_receive_window).
+    /// Data remains subject to the connection-level window.
+    pub fn extend_credit(&mut self, credit: VarInt) -> Result<(), ClosedStream> {
+        let mut conn = self.conn.state.lock("RecvStream::extend_credit");
+        conn.inner.recv_stream(self.stream).extend_credit(credit)?;
+        conn.wake();
         Ok(())
     }
 
@@ -276,6 +431,13 @@ impl RecvStream {
         self.is_0rtt
     }
 
+    /// Convert into an adapter that reads length-prefixed messages
+    ///
+    /// See [`FramedRecvStream`].
+    pub fn into_frame_reader(self) -> FramedRecvStream {
+        FramedRecvStream::new(self)
+    }
+
     /// Get the identity of this stream
     pub fn id(&self) -> StreamId {
         self.stream
@@ -340,6 +502,8 @@ impl RecvStream {
         T: FnMut(&mut Chunks) -> ReadStatus<U>,
     {
         use proto::ReadError::*;
+        // Any other read invalidates data peeked by `poll_fill_buf`
+        self.buffered.clear();
         if self.all_data_read {
             return Poll::Ready(Ok(None));
         }
@@ -454,7 +618,40 @@ impl Future for ReadToEnd<'_> {
     }
 }
 
-/// Errors from [`RecvStream::read_to_end`]
+/// Future produced by [`RecvStream::read_to_end_into()`].
+///
+/// [`RecvStream::read_to_end_into()`]: crate::RecvStream::read_to_end_into
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+struct ReadToEndInto<'a> {
+    stream: &'a mut RecvStream,
+    buf: &'a mut Vec<u8>,
+    read: usize,
+    size_limit: usize,
+}
+
+impl Future for ReadToEndInto<'_> {
+    type Output = Result<usize, ReadToEndError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            // Read at most one byte past the limit, to detect overlong streams
+            let max_length = this.size_limit.saturating_sub(this.read).saturating_add(1);
+            match ready!(this.stream.poll_read_chunk(cx, max_length, true))? {
+                Some(chunk) => {
+                    let len = chunk.bytes.len().min(this.size_limit - this.read);
+                    this.buf.extend_from_slice(&chunk.bytes[..len]);
+                    this.read += len;
+                    if len < chunk.bytes.len() {
+                        return Poll::Ready(Err(ReadToEndError::TooLong));
+                    }
+                }
+                None => return Poll::Ready(Ok(this.read)),
+            }
+        }
+    }
+}
+
+/// Errors from [`RecvStream::read_to_end`] and [`RecvStream::read_to_end_into`]
 #[derive(Debug, Error, Clone, PartialEq, Eq)]
 pub enum ReadToEndError {
     /// An error occurred during reading
@@ -489,6 +686,27 @@ impl tokio::io::AsyncRead for RecvStream {
     }
 }
 
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncBufRead for RecvStream {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<&[u8]>> {
+        Poll::Ready(Ok(ready!(Self::poll_fill_buf(self.get_mut(), cx))?))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().consume_buffered(amt);
+    }
+}
+
+impl tokio::io::AsyncBufRead for RecvStream {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Poll::Ready(Ok(ready!(Self::poll_fill_buf(self.get_mut(), cx))?))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().consume_buffered(amt);
+    }
+}
+
 impl Drop for RecvStream {
     fn drop(&mut self) {
         let mut conn = self.conn.state.lock("RecvStream::drop");
@@ -671,6 +889,26 @@ impl<'a> Future for ReadChunk<'a> {
     }
 }
 
+/// [`Stream`](futures_core::Stream) produced by [`RecvStream::read_unordered_stream()`]
+#[cfg(feature = "futures")]
+#[derive(Debug)]
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct UnorderedChunks<'a> {
+    stream: &'a mut RecvStream,
+}
+
+#[cfg(feature = "futures")]
+impl futures_core::Stream for UnorderedChunks<'_> {
+    type Item = Result<Chunk, ReadError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut()
+            .stream
+            .poll_read_chunk(cx, usize::MAX, false)
+            .map(Result::transpose)
+    }
+}
+
 /// Future produced by [`RecvStream::read_chunks()`].
 ///
 /// [`RecvStream::read_chunks()`]: crate::RecvStream::read_chunks
@@ -0,0 +1,129 @@
+use std::{
+    fmt::Debug,
+    future::Future,
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    pin::Pin,
+    sync::Arc,
+};
+
+use tokio::sync::oneshot;
+
+/// Abstract implementation of host name resolution, used by
+/// [`Endpoint::connect_to()`](crate::Endpoint::connect_to)
+pub trait Resolver: Send + Sync + Debug + 'static {
+    /// Look up the addresses of `host`, combined with `port`, in order of preference
+    fn resolve(&self, host: &str, port: u16) -> Resolve;
+}
+
+/// Future produced by [`Resolver::resolve()`]
+pub type Resolve = Pin<Box<dyn Future<Output = io::Result<Vec<SocketAddr>>> + Send>>;
+
+/// Resolves host names through the system resolver on Tokio's blocking thread pool
+#[cfg(feature = "runtime-tokio")]
+#[derive(Debug)]
+pub struct TokioResolver;
+
+#[cfg(feature = "runtime-tokio")]
+impl Resolver for TokioResolver {
+    fn resolve(&self, host: &str, port: u16) -> Resolve {
+        let host = host.to_owned();
+        Box::pin(async move { Ok(tokio::net::lookup_host((host, port)).await?.collect()) })
+    }
+}
+
+/// Resolves host names through the system resolver on a dedicated thread per lookup
+///
+/// Independent of any async runtime.
+#[derive(Debug)]
+pub struct ThreadResolver;
+
+impl Resolver for ThreadResolver {
+    fn resolve(&self, host: &str, port: u16) -> Resolve {
+        let host = host.to_owned();
+        let (send, recv) = oneshot::channel();
+        let spawned = std::thread::Builder::new()
+            .name("quinn-resolver".into())
+            .spawn(move || {
+                let _ = send.send(
+                    (host.as_str(), port)
+                        .to_socket_addrs()
+                        .map(|addrs| addrs.collect()),
+                );
+            });
+        Box::pin(async move {
+            spawned?;
+            recv.await
+                .map_err(|_| io::Error::new(io::ErrorKind::Other, "resolver thread panicked"))?
+        })
+    }
+}
+
+/// Automatically select a resolver appropriate for the runtime in use
+///
+/// If `runtime-tokio` is enabled and this function is called from within a Tokio runtime context,
+/// then `TokioResolver` is returned. Otherwise, `ThreadResolver` is returned.
+pub fn default_resolver() -> Arc<dyn Resolver> {
+    #[cfg(feature = "runtime-tokio")]
+    {
+        if ::tokio::runtime::Handle::try_current().is_ok() {
+            return Arc::new(TokioResolver);
+        }
+    }
+
+    Arc::new(ThreadResolver)
+}
+
+/// Which address families [`Endpoint::connect_to()`](crate::Endpoint::connect_to) connects over
+///
+/// Whatever the preference, attempts alternate between address families as described in
+/// [`Endpoint::connect_multi()`](crate::Endpoint::connect_multi), so an unreachable family
+/// only delays the connection.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum AddressFamilyPreference {
+    /// Start with the family of the first address returned by the resolver
+    #[default]
+    AsResolved,
+    /// Start with IPv6 if any IPv6 address was resolved
+    PreferIpv6,
+    /// Start with IPv4 if any IPv4 address was resolved
+    PreferIpv4,
+    /// Only connect over IPv6
+    Ipv6Only,
+    /// Only connect over IPv4
+    Ipv4Only,
+}
+
+impl AddressFamilyPreference {
+    /// Reorder or filter resolved `addrs` according to the preference
+    pub(crate) fn apply(self, addrs: &mut Vec<SocketAddr>) {
+        match self {
+            Self::AsResolved => {}
+            Self::PreferIpv6 => addrs.sort_by_key(|addr| !addr.is_ipv6()),
+            Self::PreferIpv4 => addrs.sort_by_key(|addr| addr.is_ipv6()),
+            Self::Ipv6Only => addrs.retain(|addr| addr.is_ipv6()),
+            Self::Ipv4Only => addrs.retain(|addr| addr.is_ipv4()),
+        }
+    }
+}
+
+/// Split `target` into a host and port, removing the brackets around IPv6 literals
+pub(crate) fn split_host_port(target: &str) -> io::Result<(&str, u16)> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid target {target:?}, expected host:port"),
+        )
+    };
+    let (host, port) = target.rsplit_once(':').ok_or_else(invalid)?;
+    let port = port.parse().map_err(|_| invalid())?;
+    let host = match host.strip_prefix('[') {
+        Some(host) => host.strip_suffix(']').ok_or_else(invalid)?,
+        None => host,
+    };
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    Ok((host, port))
+}
@@ -9,6 +9,8 @@ use std::{
     time::Instant,
 };
 
+use bytes::BytesMut;
+use rand::{rngs::OsRng, RngCore};
 use udp::{RecvMeta, Transmit};
 
 /// Abstracts I/O and timer operations for runtime independence
@@ -21,10 +23,21 @@ pub trait Runtime: Send + Sync + Debug + 'static {
     fn wrap_udp_socket(&self, t: std::net::UdpSocket) -> io::Result<Arc<dyn AsyncUdpSocket>>;
     /// Look up the current time
     ///
-    /// Allows simulating the flow of time for testing.
+    /// Allows simulating the flow of time for testing, or supplying a clock in environments where
+    /// `Instant::now()` is unavailable, such as some WebAssembly hosts.
     fn now(&self) -> Instant {
         Instant::now()
     }
+    /// Fill `dest` with random bytes
+    ///
+    /// Seeds the random number generator of each endpoint, unless
+    /// [`EndpointConfig::rng_seed()`](proto::EndpointConfig::rng_seed) is set. Defaults to the
+    /// operating system's entropy source, which environments lacking one can replace. Connection
+    /// ID generators and token keys draw their own randomness, and can be replaced through
+    /// [`EndpointConfig`](proto::EndpointConfig) and [`ServerConfig`](proto::ServerConfig).
+    fn fill_random(&self, dest: &mut [u8]) {
+        OsRng.fill_bytes(dest);
+    }
 }
 
 /// Abstract implementation of an async timer for runtime independence
@@ -55,6 +68,23 @@ pub trait AsyncUdpSocket: Send + Sync + Debug + 'static {
     /// to register the calling task to be woken when a send should be attempted again.
     fn try_send(&self, transmit: &Transmit) -> io::Result<()>;
 
+    /// Send a sequence of transmits, returning how many of them were consumed
+    ///
+    /// Errors, including `WouldBlock`, are only returned if the first transmit could not be sent.
+    /// Sockets that can hand several datagrams to the OS in one system call should override the
+    /// default, which sends each transmit through [`try_send()`](Self::try_send).
+    fn try_send_batch(&self, transmits: &[Transmit]) -> io::Result<usize> {
+        for (i, transmit) in transmits.iter().enumerate() {
+            if let Err(e) = self.try_send(transmit) {
+                return match i {
+                    0 => Err(e),
+                    _ => Ok(i),
+                };
+            }
+        }
+        Ok(transmits.len())
+    }
+
     /// Receive UDP datagrams, or register to be woken if receiving may succeed in the future
     fn poll_recv(
         &self,
@@ -63,6 +93,30 @@ pub trait AsyncUdpSocket: Send + Sync + Debug + 'static {
         meta: &mut [RecvMeta],
     ) -> Poll<io::Result<usize>>;
 
+    /// Whether to receive through [`poll_recv_owned()`](Self::poll_recv_owned) rather than
+    /// [`poll_recv()`](Self::poll_recv)
+    ///
+    /// Completion-based runtimes hand buffers to the kernel for the duration of each receive, so
+    /// they can't fill the caller's borrowed buffers without copying. Sockets of such runtimes
+    /// should return `true` and implement `poll_recv_owned()`.
+    fn recv_owned(&self) -> bool {
+        false
+    }
+
+    /// Receive UDP datagrams into buffers supplied by the socket, or register to be woken if
+    /// receiving may succeed in the future
+    ///
+    /// Appends each batch of datagrams received, along with its metadata, to `datagrams`. Only
+    /// called if [`recv_owned()`](Self::recv_owned) returns `true`.
+    fn poll_recv_owned(
+        &self,
+        cx: &mut Context,
+        datagrams: &mut Vec<(RecvMeta, BytesMut)>,
+    ) -> Poll<io::Result<()>> {
+        let _ = (cx, datagrams);
+        Poll::Ready(Err(io::ErrorKind::Unsupported.into()))
+    }
+
     /// Look up the local IP address and port used by this socket
     fn local_addr(&self) -> io::Result<SocketAddr>;
 
@@ -190,6 +244,16 @@ mod tokio;
 #[cfg(feature = "runtime-tokio")]
 pub use self::tokio::TokioRuntime;
 
+#[cfg(all(feature = "runtime-io-uring", target_os = "linux"))]
+mod io_uring;
+#[cfg(all(feature = "runtime-io-uring", target_os = "linux"))]
+pub use self::io_uring::IoUringRuntime;
+
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+mod xdp;
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+pub use self::xdp::XdpUdpSocket;
+
 #[cfg(feature = "async-io")]
 mod async_io;
 #[cfg(feature = "async-io")]
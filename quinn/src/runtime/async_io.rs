@@ -106,6 +106,10 @@ impl AsyncUdpSocket for UdpSocket {
         self.inner.send((&self.io).into(), transmit)
     }
 
+    fn try_send_batch(&self, transmits: &[udp::Transmit]) -> io::Result<usize> {
+        self.inner.send_batch((&self.io).into(), transmits)
+    }
+
     fn poll_recv(
         &self,
         cx: &mut Context,
@@ -0,0 +1,773 @@
+use std::{
+    alloc::{self, Layout},
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    io::{self, IoSliceMut},
+    mem::{self, ManuallyDrop},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    pin::Pin,
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicU16, Ordering},
+        Arc, Mutex, Weak,
+    },
+    task::{Context, Poll, Waker},
+    time::Instant,
+};
+
+use bytes::BytesMut;
+use io_uring::{cqueue, opcode, types, IoUring};
+use tokio::{io::unix::AsyncFd, sync::Notify};
+use tracing::{debug, error};
+use udp::{EcnCodepoint, RecvMeta, Transmit};
+
+use super::{AsyncTimer, AsyncUdpSocket, Runtime, TokioRuntime, UdpPollHelper, UdpPoller};
+
+/// A Quinn runtime for Tokio performing UDP I/O through io_uring
+///
+/// Timers and tasks are handled by Tokio exactly as with [`TokioRuntime`]. Sockets, however,
+/// submit their sends to an io_uring instance and receive datagrams through a single multishot
+/// receive operation, which the kernel completes into buffers from a ring registered up front.
+/// This avoids most of the system calls otherwise made for every batch of datagrams, benefiting
+/// servers whose throughput is bound by system call overhead. Each socket reserves about 4MiB of
+/// receive buffers.
+///
+/// Requires Linux 6.0 or later, and must be used from within a Tokio runtime with I/O enabled.
+#[derive(Debug)]
+pub struct IoUringRuntime;
+
+impl Runtime for IoUringRuntime {
+    fn new_timer(&self, t: Instant) -> Pin<Box<dyn AsyncTimer>> {
+        TokioRuntime.new_timer(t)
+    }
+
+    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        TokioRuntime.spawn(future)
+    }
+
+    fn wrap_udp_socket(&self, sock: std::net::UdpSocket) -> io::Result<Arc<dyn AsyncUdpSocket>> {
+        Ok(UdpSocket::new(sock)?)
+    }
+
+    fn now(&self) -> Instant {
+        TokioRuntime.now()
+    }
+}
+
+struct UdpSocket {
+    io: std::net::UdpSocket,
+    state: udp::UdpSocketState,
+    /// Only taken by `drop`, which hands the ring over to be closed without blocking
+    ring: ManuallyDrop<Mutex<Ring>>,
+    /// Notified whenever a send completes, freeing its slot
+    send_completed: Notify,
+    /// Signalled by the kernel when operations complete, and by `drop` to stop the reactor
+    event: OwnedFd,
+    /// Set if the network interface rejected a segmented send
+    gso_failed: AtomicBool,
+}
+
+impl UdpSocket {
+    fn new(io: std::net::UdpSocket) -> io::Result<Arc<Self>> {
+        let state = udp::UdpSocketState::new((&io).into())?;
+        io.set_nonblocking(true)?;
+        let event = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if event == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let event = unsafe { OwnedFd::from_raw_fd(event) };
+        let ring = Ring::new(&event)?;
+        let reactor = AsyncFd::new(event.try_clone()?)?;
+        let socket = Arc::new(Self {
+            io,
+            state,
+            ring: ManuallyDrop::new(Mutex::new(ring)),
+            send_completed: Notify::new(),
+            event,
+            gso_failed: AtomicBool::new(false),
+        });
+        tokio::spawn(drive(Arc::downgrade(&socket), reactor));
+        Ok(socket)
+    }
+
+    /// Process completed operations, waking tasks waiting on them
+    fn complete(&self, ring: &mut Ring) {
+        let completions = ring.reap();
+        if completions.gso_failed && !self.gso_failed.swap(true, Ordering::Relaxed) {
+            error!("got transmit error, halting segmentation offload");
+        }
+        if completions.received {
+            if let Some(waker) = ring.recv_waker.take() {
+                waker.wake();
+            }
+        }
+        if completions.sent {
+            self.send_completed.notify_waiters();
+        }
+    }
+
+    async fn writable(&self) -> io::Result<()> {
+        loop {
+            let notified = {
+                let mut ring = self.ring.lock().unwrap();
+                self.complete(&mut ring);
+                if !ring.free_slots.is_empty() {
+                    return Ok(());
+                }
+                self.send_completed.notified()
+            };
+            notified.await;
+        }
+    }
+
+    /// Pass up to `max` datagrams received so far to `f`, or register to be woken when some
+    /// arrive
+    fn poll_received(
+        &self,
+        cx: &mut Context,
+        max: usize,
+        mut f: impl FnMut(RecvMeta, &[u8]),
+    ) -> Poll<io::Result<()>> {
+        let mut ring = self.ring.lock().unwrap();
+        self.complete(&mut ring);
+        let mut count = 0;
+        while count < max {
+            let Some((bid, len)) = ring.received.pop_front() else {
+                break;
+            };
+            if let Some((meta, payload)) = ring.decode(bid, len) {
+                f(meta, payload);
+                count += 1;
+            }
+            ring.recycle(bid);
+        }
+        if count > 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        if !ring.recv_armed {
+            let entry = opcode::RecvMsgMulti::new(
+                types::Fd(self.io.as_raw_fd()),
+                &*ring.recv_hdr,
+                BUF_GROUP,
+            )
+            .build()
+            .user_data(RECV_USER_DATA);
+            // Safety: the message header template is owned by the ring, and only read from
+            if let Err(e) = unsafe { ring.push(&entry) }.and_then(|()| ring.flush()) {
+                return Poll::Ready(Err(e));
+            }
+            ring.in_flight += 1;
+            ring.recv_armed = true;
+        }
+        ring.recv_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl AsyncUdpSocket for UdpSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        Box::pin(UdpPollHelper::new(move || {
+            let socket = self.clone();
+            async move { socket.writable().await }
+        }))
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        self.try_send_batch(std::slice::from_ref(transmit))
+            .map(|_| ())
+    }
+
+    fn try_send_batch(&self, transmits: &[Transmit]) -> io::Result<usize> {
+        let mut ring = self.ring.lock().unwrap();
+        let mut sent = 0;
+        for transmit in transmits {
+            match ring.queue_send(types::Fd(self.io.as_raw_fd()), transmit) {
+                Ok(()) => sent += 1,
+                Err(e) if sent == 0 => return Err(e),
+                Err(_) => break,
+            }
+        }
+        // One system call hands the whole batch to the kernel
+        ring.flush()?;
+        Ok(sent)
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let mut count = 0;
+        let max = bufs.len().min(meta.len());
+        ready!(self.poll_received(cx, max, |mut decoded, payload| {
+            let len = payload.len().min(bufs[count].len());
+            bufs[count][..len].copy_from_slice(&payload[..len]);
+            decoded.len = len;
+            meta[count] = decoded;
+            count += 1;
+        }))?;
+        Poll::Ready(Ok(count))
+    }
+
+    fn recv_owned(&self) -> bool {
+        true
+    }
+
+    fn poll_recv_owned(
+        &self,
+        cx: &mut Context,
+        datagrams: &mut Vec<(RecvMeta, BytesMut)>,
+    ) -> Poll<io::Result<()>> {
+        // The buffer ring is shared with the kernel, so datagrams are still copied out of it, but
+        // only once rather than again by the endpoint
+        self.poll_received(cx, udp::BATCH_SIZE, |meta, payload| {
+            datagrams.push((meta, payload.into()))
+        })
+    }
+
+    fn local_addr(&self) -> io::Result<std::net::SocketAddr> {
+        self.io.local_addr()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.state.may_fragment()
+    }
+
+    fn max_transmit_segments(&self) -> usize {
+        match self.gso_failed.load(Ordering::Relaxed) {
+            true => 1,
+            false => self.state.max_gso_segments(),
+        }
+    }
+
+    fn max_receive_segments(&self) -> usize {
+        self.state.gro_segments()
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        // Safety: `ring` is never accessed again
+        let ring = unsafe { ManuallyDrop::take(&mut self.ring) };
+        ring.into_inner().unwrap_or_else(|e| e.into_inner()).close();
+        // Wake the reactor so it notices the socket is gone
+        let value = 1u64;
+        unsafe {
+            libc::write(
+                self.event.as_raw_fd(),
+                &value as *const u64 as *const _,
+                mem::size_of::<u64>(),
+            );
+        }
+    }
+}
+
+impl fmt::Debug for UdpSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UdpSocket")
+            .field("io", &self.io)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Process completions as the kernel signals them, until the socket is dropped
+async fn drive(socket: Weak<UdpSocket>, event: AsyncFd<OwnedFd>) {
+    loop {
+        let mut guard = match event.readable().await {
+            Ok(guard) => guard,
+            Err(e) => {
+                error!("io_uring reactor failed: {}", e);
+                return;
+            }
+        };
+        // Reset the counter, so that completions posted from here on signal readiness again
+        loop {
+            let mut value = 0u64;
+            let result = guard.try_io(|fd| {
+                let n = unsafe {
+                    libc::read(
+                        fd.as_raw_fd(),
+                        &mut value as *mut u64 as *mut _,
+                        mem::size_of::<u64>(),
+                    )
+                };
+                match n {
+                    -1 => Err(io::Error::last_os_error()),
+                    _ => Ok(()),
+                }
+            });
+            if result.is_err() {
+                break;
+            }
+        }
+
+        let Some(socket) = socket.upgrade() else {
+            return;
+        };
+        let mut ring = socket.ring.lock().unwrap();
+        socket.complete(&mut ring);
+    }
+}
+
+/// An io_uring instance with the buffers its operations refer to
+struct Ring {
+    uring: IoUring,
+    /// Number of operations the kernel might still access memory for
+    in_flight: usize,
+    /// Number of entries queued since the last [`flush()`](Self::flush)
+    unsubmitted: usize,
+    /// Entries describing available receive buffers to the kernel
+    buf_ring: *mut types::BufRingEntry,
+    /// Tail of `buf_ring`, advanced as buffers are returned to the kernel
+    buf_tail: u16,
+    recv_buffers: Box<[u64]>,
+    /// Template for the multishot receive, defining how much space to reserve for the sender's
+    /// address and control messages
+    recv_hdr: Box<libc::msghdr>,
+    /// Whether the multishot receive is active
+    recv_armed: bool,
+    /// Buffer IDs and lengths of completed receives
+    received: VecDeque<(u16, usize)>,
+    recv_waker: Option<Waker>,
+    send_slots: Box<[SendSlot]>,
+    free_slots: Vec<usize>,
+}
+
+// Safety: the raw pointers only refer to memory owned by the `Ring`
+unsafe impl Send for Ring {}
+
+impl Ring {
+    fn new(event: &OwnedFd) -> io::Result<Self> {
+        let uring = IoUring::new(RING_ENTRIES)?;
+        uring.submitter().register_eventfd(event.as_raw_fd())?;
+
+        let buf_ring = unsafe { alloc::alloc_zeroed(buf_ring_layout()) };
+        if buf_ring.is_null() {
+            alloc::handle_alloc_error(buf_ring_layout());
+        }
+        let mut recv_hdr: Box<libc::msghdr> = Box::new(unsafe { mem::zeroed() });
+        recv_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+        recv_hdr.msg_controllen = CONTROL_LEN as _;
+        let mut ring = Self {
+            uring,
+            in_flight: 0,
+            unsubmitted: 0,
+            buf_ring: buf_ring.cast(),
+            buf_tail: 0,
+            recv_buffers: vec![0; RECV_BUFFERS as usize * RECV_BUFFER_SIZE / 8].into(),
+            recv_hdr,
+            recv_armed: false,
+            received: VecDeque::new(),
+            recv_waker: None,
+            send_slots: (0..SEND_SLOTS).map(|_| SendSlot::default()).collect(),
+            free_slots: (0..SEND_SLOTS).collect(),
+        };
+        unsafe {
+            ring.uring.submitter().register_buf_ring_with_flags(
+                ring.buf_ring as u64,
+                RECV_BUFFERS,
+                BUF_GROUP,
+                0,
+            )?;
+        }
+        for bid in 0..RECV_BUFFERS {
+            ring.recycle(bid);
+        }
+        Ok(ring)
+    }
+
+    /// Queue `entry` for submission by the next [`flush()`](Self::flush)
+    ///
+    /// # Safety
+    ///
+    /// Memory referred to by `entry` must remain valid until the operation completes.
+    unsafe fn push(&mut self, entry: &io_uring::squeue::Entry) -> io::Result<()> {
+        if self.uring.submission().push(entry).is_err() {
+            // Make room by handing the queued entries to the kernel
+            self.flush()?;
+            if self.uring.submission().push(entry).is_err() {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "io_uring submission queue full",
+                ));
+            }
+        }
+        self.unsubmitted += 1;
+        Ok(())
+    }
+
+    /// Submit the queued entries to the kernel
+    fn flush(&mut self) -> io::Result<()> {
+        if self.unsubmitted == 0 {
+            return Ok(());
+        }
+        self.uring.submit()?;
+        self.unsubmitted = 0;
+        Ok(())
+    }
+
+    /// Copy `transmit` into a free send slot, and queue sending it through `fd`
+    fn queue_send(&mut self, fd: types::Fd, transmit: &Transmit) -> io::Result<()> {
+        let Some(index) = self.free_slots.pop() else {
+            return Err(io::ErrorKind::WouldBlock.into());
+        };
+        let hdr = self.send_slots[index].prepare(transmit);
+        let entry = opcode::SendMsg::new(fd, hdr)
+            .build()
+            .user_data(index as u64);
+        // Safety: the message header and everything it points to is owned by the slot, which isn't
+        // reused until the send completes
+        if let Err(e) = unsafe { self.push(&entry) } {
+            self.free_slots.push(index);
+            return Err(e);
+        }
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    /// Cancel the operations in flight, and free the ring once they've completed
+    ///
+    /// Waiting for the kernel to acknowledge the cancellations may block, so unless nothing is in
+    /// flight, the ring is dropped on a thread of its own.
+    fn close(mut self) {
+        if self.in_flight > 0 {
+            let entry = opcode::AsyncCancel2::new(types::CancelBuilder::any())
+                .build()
+                .user_data(CANCEL_USER_DATA);
+            // Safety: cancellation doesn't refer to any memory
+            let _ = unsafe { self.push(&entry) }.and_then(|()| self.flush());
+            self.reap();
+        }
+        if self.in_flight == 0 {
+            return;
+        }
+        // If no thread can be spawned, the closure and the ring with it are dropped right here
+        let _ = std::thread::Builder::new()
+            .name("quinn-io-uring-close".into())
+            .spawn(move || drop(self));
+    }
+
+    fn reap(&mut self) -> Completions {
+        let mut completions = Completions::default();
+        for cqe in self.uring.completion() {
+            match cqe.user_data() {
+                RECV_USER_DATA => {
+                    if !cqueue::more(cqe.flags()) {
+                        self.recv_armed = false;
+                        self.in_flight -= 1;
+                    }
+                    match (cqe.result(), cqueue::buffer_select(cqe.flags())) {
+                        (len, Some(bid)) if len >= 0 => {
+                            self.received.push_back((bid, len as usize));
+                            completions.received = true;
+                        }
+                        // Out of buffers; the receive is resubmitted once some are returned
+                        (err, _) if err == -libc::ENOBUFS => {}
+                        (err, _) => {
+                            debug!("receive failed: {}", io::Error::from_raw_os_error(-err));
+                        }
+                    }
+                }
+                CANCEL_USER_DATA => {}
+                index => {
+                    let index = index as usize;
+                    self.in_flight -= 1;
+                    self.free_slots.push(index);
+                    completions.sent = true;
+                    let err = -cqe.result();
+                    if err > 0 {
+                        // Some network adapters and drivers do not support GSO, which can only
+                        // be detected by attempting to use it
+                        if self.send_slots[index].segmented
+                            && (err == libc::EIO || err == libc::EINVAL)
+                        {
+                            completions.gso_failed = true;
+                        }
+                        // As with synchronous sends, errors are left to the protocol's loss
+                        // recovery to handle
+                        debug!("send failed: {}", io::Error::from_raw_os_error(err));
+                    }
+                }
+            }
+        }
+        completions
+    }
+
+    /// Parse the datagrams received into buffer `bid`, returning their metadata and contents
+    fn decode(&self, bid: u16, len: usize) -> Option<(RecvMeta, &[u8])> {
+        let buffer = &self.recv_buffer(bid)[..len];
+        let out = types::RecvMsgOut::parse(buffer, &self.recv_hdr).ok()?;
+        if out.is_payload_truncated() || out.is_name_data_truncated() {
+            return None;
+        }
+
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let name = out.name_data();
+        unsafe {
+            ptr::copy_nonoverlapping(
+                name.as_ptr(),
+                &mut storage as *mut _ as *mut u8,
+                name.len().min(mem::size_of::<libc::sockaddr_storage>()),
+            );
+        }
+        let addr = unsafe { socket2::SockAddr::new(storage, name.len() as _) }.as_socket()?;
+
+        let payload = out.payload_data();
+        let mut meta = RecvMeta {
+            addr,
+            len: payload.len(),
+            stride: payload.len(),
+            ecn: None,
+            dst_ip: None,
+        };
+        decode_control(out.control_data(), &mut meta);
+        // `RecvMsgOut` only lends the payload for its own lifetime, though it lies within `buffer`
+        let start = payload.as_ptr() as usize - buffer.as_ptr() as usize;
+        Some((meta, &buffer[start..start + payload.len()]))
+    }
+
+    fn recv_buffer(&self, bid: u16) -> &[u8] {
+        let start = bid as usize * RECV_BUFFER_SIZE;
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                self.recv_buffers.as_ptr() as *const u8,
+                self.recv_buffers.len() * 8,
+            )
+        };
+        &bytes[start..start + RECV_BUFFER_SIZE]
+    }
+
+    /// Provide buffer `bid` to the kernel for receiving
+    fn recycle(&mut self, bid: u16) {
+        let addr = self.recv_buffer(bid).as_ptr() as u64;
+        unsafe {
+            let entry = &mut *self
+                .buf_ring
+                .add((self.buf_tail & (RECV_BUFFERS - 1)) as usize);
+            entry.set_addr(addr);
+            entry.set_len(RECV_BUFFER_SIZE as u32);
+            entry.set_bid(bid);
+        }
+        self.buf_tail = self.buf_tail.wrapping_add(1);
+        unsafe {
+            let tail = types::BufRingEntry::tail(self.buf_ring) as *const AtomicU16;
+            (*tail).store(self.buf_tail, Ordering::Release);
+        }
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        // The kernel may access the memory of in-flight operations until they complete
+        while self.in_flight > 0 {
+            if self.uring.submit_and_wait(1).is_err() {
+                // Leak the memory rather than risk it being accessed after being freed
+                mem::forget(mem::take(&mut self.recv_buffers));
+                mem::forget(mem::take(&mut self.send_slots));
+                return;
+            }
+            self.reap();
+        }
+        unsafe { alloc::dealloc(self.buf_ring.cast(), buf_ring_layout()) };
+    }
+}
+
+#[derive(Default)]
+struct Completions {
+    received: bool,
+    sent: bool,
+    gso_failed: bool,
+}
+
+/// Memory backing an in-flight send
+struct SendSlot {
+    contents: Vec<u8>,
+    name: libc::sockaddr_storage,
+    iov: libc::iovec,
+    control: [u64; CONTROL_LEN / 8],
+    hdr: libc::msghdr,
+    segmented: bool,
+}
+
+impl SendSlot {
+    /// Copy `transmit` into the slot, returning the message header describing it
+    fn prepare(&mut self, transmit: &Transmit) -> *const libc::msghdr {
+        self.contents.clear();
+        self.contents.extend_from_slice(transmit.contents);
+        self.segmented = transmit.segment_size.is_some();
+
+        let addr = socket2::SockAddr::from(transmit.destination);
+        unsafe {
+            ptr::copy_nonoverlapping(
+                addr.as_ptr() as *const u8,
+                &mut self.name as *mut _ as *mut u8,
+                addr.len() as usize,
+            );
+        }
+        self.iov = libc::iovec {
+            iov_base: self.contents.as_mut_ptr().cast(),
+            iov_len: self.contents.len(),
+        };
+
+        self.hdr = unsafe { mem::zeroed() };
+        self.hdr.msg_name = &mut self.name as *mut _ as *mut _;
+        self.hdr.msg_namelen = addr.len();
+        self.hdr.msg_iov = &mut self.iov;
+        self.hdr.msg_iovlen = 1;
+        self.hdr.msg_control = self.control.as_mut_ptr().cast();
+        self.hdr.msg_controllen = CONTROL_LEN as _;
+        let len = unsafe { encode_control(&self.hdr, transmit) };
+        self.hdr.msg_controllen = len as _;
+        &self.hdr
+    }
+}
+
+impl Default for SendSlot {
+    fn default() -> Self {
+        // Safety: all-zero is a valid value of these C types
+        unsafe {
+            Self {
+                contents: Vec::new(),
+                name: mem::zeroed(),
+                iov: mem::zeroed(),
+                control: [0; CONTROL_LEN / 8],
+                hdr: mem::zeroed(),
+                segmented: false,
+            }
+        }
+    }
+}
+
+/// Write the control messages for `transmit` into the control buffer of `hdr`, returning their
+/// length
+///
+/// # Safety
+///
+/// `hdr` must refer to an aligned control buffer of at least [`CONTROL_LEN`] bytes.
+unsafe fn encode_control(hdr: &libc::msghdr, transmit: &Transmit) -> usize {
+    let mut cursor = libc::CMSG_FIRSTHDR(hdr);
+    let mut len = 0;
+    let mut push = |level: libc::c_int, ty: libc::c_int, data: &[u8]| {
+        let cmsg = cursor;
+        (*cmsg).cmsg_level = level;
+        (*cmsg).cmsg_type = ty;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(data.len() as _) as _;
+        ptr::copy_nonoverlapping(data.as_ptr(), libc::CMSG_DATA(cmsg), data.len());
+        len += libc::CMSG_SPACE(data.len() as _) as usize;
+        cursor = libc::CMSG_NXTHDR(hdr, cmsg);
+    };
+
+    let ecn = transmit.ecn.map_or(0, |x| x as libc::c_int);
+    // True for IPv4 or IPv4-Mapped IPv6
+    let is_ipv4 = transmit.destination.is_ipv4()
+        || matches!(transmit.destination.ip(), IpAddr::V6(addr) if addr.to_ipv4_mapped().is_some());
+    if is_ipv4 {
+        push(libc::IPPROTO_IP, libc::IP_TOS, &ecn.to_ne_bytes());
+    } else {
+        push(libc::IPPROTO_IPV6, libc::IPV6_TCLASS, &ecn.to_ne_bytes());
+    }
+
+    if let Some(segment_size) = transmit.segment_size {
+        push(
+            libc::SOL_UDP,
+            libc::UDP_SEGMENT,
+            &(segment_size as u16).to_ne_bytes(),
+        );
+    }
+
+    match transmit.src_ip {
+        Some(IpAddr::V4(v4)) => {
+            let pktinfo = libc::in_pktinfo {
+                ipi_ifindex: 0,
+                ipi_spec_dst: libc::in_addr {
+                    s_addr: u32::from_ne_bytes(v4.octets()),
+                },
+                ipi_addr: libc::in_addr { s_addr: 0 },
+            };
+            push(libc::IPPROTO_IP, libc::IP_PKTINFO, as_bytes(&pktinfo));
+        }
+        Some(IpAddr::V6(v6)) => {
+            let pktinfo = libc::in6_pktinfo {
+                ipi6_ifindex: 0,
+                ipi6_addr: libc::in6_addr {
+                    s6_addr: v6.octets(),
+                },
+            };
+            push(libc::IPPROTO_IPV6, libc::IPV6_PKTINFO, as_bytes(&pktinfo));
+        }
+        None => {}
+    }
+    len
+}
+
+/// Fill in the ECN codepoint, destination address and GRO segment size from received control
+/// messages
+fn decode_control(control: &[u8], meta: &mut RecvMeta) {
+    let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+    hdr.msg_control = control.as_ptr() as *mut _;
+    hdr.msg_controllen = control.len() as _;
+    let mut ecn_bits = 0;
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&hdr) };
+    while !cmsg.is_null() {
+        let (level, ty, data) = unsafe {
+            let header = ptr::read_unaligned(cmsg);
+            (header.cmsg_level, header.cmsg_type, libc::CMSG_DATA(cmsg))
+        };
+        match (level, ty) {
+            (libc::IPPROTO_IP, libc::IP_TOS) => ecn_bits = unsafe { *data },
+            (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                ecn_bits = unsafe { ptr::read_unaligned(data as *const libc::c_int) } as u8;
+            }
+            (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                let pktinfo = unsafe { ptr::read_unaligned(data as *const libc::in_pktinfo) };
+                meta.dst_ip = Some(IpAddr::V4(Ipv4Addr::from(
+                    pktinfo.ipi_addr.s_addr.to_ne_bytes(),
+                )));
+            }
+            (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                let pktinfo = unsafe { ptr::read_unaligned(data as *const libc::in6_pktinfo) };
+                meta.dst_ip = Some(IpAddr::V6(Ipv6Addr::from(pktinfo.ipi6_addr.s6_addr)));
+            }
+            (libc::SOL_UDP, libc::UDP_GRO) => {
+                meta.stride = unsafe { ptr::read_unaligned(data as *const libc::c_int) } as usize;
+            }
+            _ => {}
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&hdr, cmsg) };
+    }
+    meta.ecn = EcnCodepoint::from_bits(ecn_bits);
+}
+
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) }
+}
+
+fn buf_ring_layout() -> Layout {
+    Layout::from_size_align(
+        RECV_BUFFERS as usize * mem::size_of::<types::BufRingEntry>(),
+        4096,
+    )
+    .unwrap()
+}
+
+/// Number of submission queue entries, comfortably exceeding the number of operations in flight
+const RING_ENTRIES: u32 = 256;
+/// Number of receive buffers, which must be a power of two
+const RECV_BUFFERS: u16 = 64;
+/// Size of each receive buffer, enough for a full batch of coalesced datagrams and the sender's
+/// address and control messages preceding them
+const RECV_BUFFER_SIZE: usize = 64 * 1024 + 512;
+/// Buffer group of the receive buffers
+const BUF_GROUP: u16 = 0;
+/// Number of sends that may be in flight at once
+const SEND_SLOTS: usize = 64;
+/// Space for control messages
+const CONTROL_LEN: usize = 128;
+/// User data identifying the multishot receive; sends are identified by their slot index
+const RECV_USER_DATA: u64 = u64::MAX;
+/// User data identifying the cancellation of the operations in flight
+const CANCEL_USER_DATA: u64 = u64::MAX - 1;
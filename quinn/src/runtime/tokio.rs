@@ -68,6 +68,12 @@ impl AsyncUdpSocket for UdpSocket {
         })
     }
 
+    fn try_send_batch(&self, transmits: &[udp::Transmit]) -> io::Result<usize> {
+        self.io.try_io(Interest::WRITABLE, || {
+            self.inner.send_batch((&self.io).into(), transmits)
+        })
+    }
+
     fn poll_recv(
         &self,
         cx: &mut Context,
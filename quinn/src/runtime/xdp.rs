@@ -0,0 +1,117 @@
+use std::{
+    io::{self, IoSliceMut},
+    net::SocketAddr,
+    os::fd::{AsFd, AsRawFd, OwnedFd, RawFd},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::io::unix::AsyncFd;
+use udp::{RecvMeta, Transmit, XdpConfig, XdpSocket};
+
+use super::{AsyncUdpSocket, UdpPollHelper, UdpPoller};
+
+/// An experimental [`AsyncUdpSocket`] exchanging datagrams with a network interface through
+/// AF_XDP, for use with Tokio
+///
+/// See [`XdpSocket`] for requirements and limitations; notably, an XDP program redirecting
+/// traffic to this socket's file descriptor must be attached by the caller. Construct an endpoint
+/// over it with [`Endpoint::new_with_abstract_socket`](crate::Endpoint::new_with_abstract_socket).
+#[derive(Debug)]
+pub struct XdpUdpSocket {
+    inner: Mutex<XdpSocket>,
+    /// Registration of a duplicate of the socket's file descriptor with the reactor
+    io: AsyncFd<OwnedFd>,
+    local_addr: SocketAddr,
+    max_gso_segments: usize,
+    zero_copy: bool,
+}
+
+impl XdpUdpSocket {
+    /// Create a socket and bind it to the configured interface queue
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn new(config: &XdpConfig) -> io::Result<Arc<Self>> {
+        let inner = XdpSocket::new(config)?;
+        Ok(Arc::new(Self {
+            io: AsyncFd::new(inner.as_fd().try_clone_to_owned()?)?,
+            local_addr: inner.local_addr(),
+            max_gso_segments: inner.max_gso_segments(),
+            zero_copy: inner.is_zero_copy(),
+            inner: Mutex::new(inner),
+        }))
+    }
+
+    /// Whether frames are shared directly with the network interface, rather than copied
+    pub fn is_zero_copy(&self) -> bool {
+        self.zero_copy
+    }
+
+    /// Wait until a transmit of up to [`max_transmit_segments()`] segments can be queued
+    ///
+    /// The file descriptor becomes writable when space frees up in the TX ring, but completed
+    /// transmissions aren't reliably signaled through it, so frames are also reclaimed every
+    /// [`SEND_RETRY_INTERVAL`]. While the socket is saturated, each of those checks costs a timer
+    /// wakeup.
+    ///
+    /// [`max_transmit_segments()`]: AsyncUdpSocket::max_transmit_segments
+    async fn writable(&self) -> io::Result<()> {
+        while !self.inner.lock().unwrap().reclaim()? {
+            if let Ok(guard) = tokio::time::timeout(SEND_RETRY_INTERVAL, self.io.writable()).await {
+                // Wait for the next signal rather than spinning while the ring stays writable
+                guard?.clear_ready();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AsyncUdpSocket for XdpUdpSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        Box::pin(UdpPollHelper::new(move || {
+            let socket = self.clone();
+            async move { socket.writable().await }
+        }))
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        self.inner.lock().unwrap().send(transmit)
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.io.poll_read_ready(cx))?;
+            if let Ok(res) = guard.try_io(|_| self.inner.lock().unwrap().recv(bufs, meta)) {
+                return Poll::Ready(res);
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    fn may_fragment(&self) -> bool {
+        false
+    }
+
+    fn max_transmit_segments(&self) -> usize {
+        self.max_gso_segments
+    }
+}
+
+impl AsRawFd for XdpUdpSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.lock().unwrap().as_raw_fd()
+    }
+}
+
+/// How long to wait before checking for completed transmissions again
+const SEND_RETRY_INTERVAL: Duration = Duration::from_micros(100);
@@ -1,15 +1,19 @@
 use std::{
     future::Future,
-    io,
+    io::{self, IoSlice},
     pin::Pin,
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
 use bytes::Bytes;
-use proto::{ClosedStream, ConnectionError, FinishError, StreamId, Written};
+use proto::{
+    ClosedStream, ConnectionError, FinishError, ResetAtError, StreamGroupId, StreamId, StreamStats,
+    Written,
+};
 use thiserror::Error;
 
-use crate::{connection::ConnectionRef, VarInt};
+use crate::{connection::ConnectionRef, framed::FramedSendStream, VarInt};
 
 /// A stream that can only be used to send data
 ///
@@ -32,6 +36,12 @@ pub struct SendStream {
     conn: ConnectionRef,
     stream: StreamId,
     is_0rtt: bool,
+    /// When the most recent write was first blocked, if it still is
+    blocked_since: Option<Instant>,
+    /// Total time spent blocked in previous writes
+    blocked_time: Duration,
+    /// Whether the application reset the stream, discarding unacknowledged data
+    reset: bool,
 }
 
 impl SendStream {
@@ -40,6 +50,9 @@ impl SendStream {
             conn,
             stream,
             is_0rtt,
+            blocked_since: None,
+            blocked_time: Duration::ZERO,
+            reset: false,
         }
     }
 
@@ -53,6 +66,17 @@ impl SendStream {
         Write { stream: self, buf }.await
     }
 
+    /// Write bytes from a list of buffers to the stream
+    ///
+    /// Yields the number of bytes written on success. Congestion and flow control may cause this to
+    /// be shorter than the combined length of `bufs`, indicating that only a prefix of the data was
+    /// written. Data is copied out of `bufs` only once it is known how much of it can be written.
+    ///
+    /// This operation is cancel-safe.
+    pub async fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize, WriteError> {
+        WriteVectored { stream: self, bufs }.await
+    }
+
     /// Convenience method to write an entire buffer to the stream
     ///
     /// This operation is *not* cancel-safe.
@@ -94,6 +118,50 @@ impl SendStream {
         .await
     }
 
+    /// Write the given byte range of a file to the stream
+    ///
+    /// Data is read from `file` in fixed-size chunks as flow and congestion control allow, reusing
+    /// buffer space once previously sent chunks have been acknowledged. Yields the number of bytes
+    /// written on success, which is always `range.end - range.start`. Fails with
+    /// [`io::ErrorKind::UnexpectedEof`] if the file ends before `range.end`.
+    ///
+    /// This operation is *not* cancel-safe.
+    #[cfg(feature = "send-file")]
+    pub async fn send_file(
+        &mut self,
+        mut file: tokio::fs::File,
+        range: std::ops::Range<u64>,
+    ) -> io::Result<u64> {
+        use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+        file.seek(io::SeekFrom::Start(range.start)).await?;
+        let mut remaining = range.end.saturating_sub(range.start);
+        let mut buf = bytes::BytesMut::new();
+        let mut written = 0;
+        while remaining > 0 {
+            let len = remaining.min(SEND_FILE_CHUNK_SIZE as u64);
+            // Reclaims the previous allocation if all chunks split off from it have been released
+            buf.reserve(len as usize);
+            let n = (&mut file).take(len).read_buf(&mut buf).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            remaining -= n as u64;
+            written += n as u64;
+            self.write_chunk(buf.split().freeze()).await?;
+        }
+        Ok(written)
+    }
+
+    /// Stop counting blocked time once a write future is dropped while blocked
+    fn abandon_write(&mut self) {
+        if let Some(since) = self.blocked_since.take() {
+            let mut conn = self.conn.state.lock("SendStream::abandon_write");
+            self.blocked_time += conn.runtime.now().saturating_duration_since(since);
+            conn.blocked_writers.remove(&self.stream);
+        }
+    }
+
     fn execute_poll<F, R>(&mut self, cx: &mut Context, write_fn: F) -> Poll<Result<R, WriteError>>
     where
         F: FnOnce(&mut proto::SendStream) -> Result<R, proto::WriteError>,
@@ -108,9 +176,25 @@ impl SendStream {
             return Poll::Ready(Err(WriteError::ConnectionLost(x.clone())));
         }
 
-        let result = match write_fn(&mut conn.inner.send_stream(self.stream)) {
+        let result = write_fn(&mut conn.inner.send_stream(self.stream));
+        if !matches!(result, Err(Blocked)) {
+            if let Some(since) = self.blocked_since.take() {
+                self.blocked_time += conn.runtime.now().saturating_duration_since(since);
+            }
+        }
+        let result = match result {
             Ok(result) => result,
             Err(Blocked) => {
+                if self.blocked_since.is_none() {
+                    self.blocked_since = Some(conn.runtime.now());
+                }
+                if let Some(&deadline) = conn.write_deadlines.get(&self.stream) {
+                    if deadline <= conn.runtime.now() {
+                        return Poll::Ready(Err(WriteError::TimedOut));
+                    }
+                    // Ensure the driver arms a timer for this deadline
+                    conn.wake();
+                }
                 conn.blocked_writers.insert(self.stream, cx.waker().clone());
                 return Poll::Pending;
             }
@@ -166,6 +250,28 @@ impl SendStream {
             return Ok(());
         }
         conn.inner.send_stream(self.stream).reset(error_code)?;
+        self.reset = true;
+        conn.wake();
+        Ok(())
+    }
+
+    /// Close the send stream immediately, except for the first `reliable_size` bytes
+    ///
+    /// Like [`reset()`](Self::reset), but data written below `reliable_size` continues to be
+    /// (re)transmitted, and the peer's application is guaranteed to receive it before learning of
+    /// the reset. Requires the peer to support the reliable stream reset extension; otherwise
+    /// fails with [`ResetAtError::Unsupported`](proto::ResetAtError::Unsupported), and
+    /// [`reset()`](Self::reset) may be used instead. May be called again with a smaller
+    /// `reliable_size`.
+    pub fn reset_at(&mut self, error_code: VarInt, reliable_size: u64) -> Result<(), ResetAtError> {
+        let mut conn = self.conn.state.lock("SendStream::reset_at");
+        if self.is_0rtt && conn.check_0rtt().is_err() {
+            return Ok(());
+        }
+        conn.inner
+            .send_stream(self.stream)
+            .reset_at(error_code, reliable_size)?;
+        self.reset = true;
         conn.wake();
         Ok(())
     }
@@ -189,6 +295,29 @@ impl SendStream {
         conn.inner.send_stream(self.stream).priority()
     }
 
+    /// Set a deadline for blocked writes on the send stream
+    ///
+    /// Once `deadline` has passed, write operations which are blocked by flow or congestion
+    /// control fail with [`WriteError::TimedOut`] instead of waiting for the stream to become
+    /// writable. Writes which can make progress immediately are unaffected. The deadline applies to
+    /// all subsequent writes until it is changed again, and `None` disables it, which is the
+    /// default.
+    pub fn set_write_deadline(&mut self, deadline: Option<Instant>) {
+        let mut conn = self.conn.state.lock("SendStream::set_write_deadline");
+        match deadline {
+            Some(deadline) => conn.write_deadlines.insert(self.stream, deadline),
+            None => conn.write_deadlines.remove(&self.stream),
+        };
+        // Blocked writers need to be woken up if their new deadline has already passed
+        conn.wake();
+    }
+
+    /// Get the deadline for blocked writes on the send stream
+    pub fn write_deadline(&self) -> Option<Instant> {
+        let conn = self.conn.state.lock("SendStream::write_deadline");
+        conn.write_deadlines.get(&self.stream).copied()
+    }
+
     /// Completes when the peer stops the stream or reads the stream to completion
     ///
     /// Yields `Some` with the stop error code if the peer stops the stream. Yields `None` if the
@@ -225,6 +354,96 @@ impl SendStream {
         }
     }
 
+    /// Completes when all data written to the stream so far has been acknowledged by the peer
+    ///
+    /// Unlike [`stopped()`](Self::stopped), this does not require the stream to be
+    /// [`finish()`](Self::finish)ed, which makes it suitable for application-level backpressure or
+    /// checkpointing. Acknowledgement only indicates receipt by the peer's QUIC stack, not
+    /// processing by the peer application. Fails with [`WriteError::Stopped`] if the peer stops
+    /// the stream before everything was acknowledged, and with [`WriteError::ClosedStream`] if the
+    /// stream was reset, as unacknowledged data is then never delivered.
+    ///
+    /// This operation is cancel-safe.
+    pub async fn flushed(&mut self) -> Result<(), WriteError> {
+        Flushed { stream: self }.await
+    }
+
+    #[doc(hidden)]
+    pub fn poll_flushed(&mut self, cx: &mut Context) -> Poll<Result<(), WriteError>> {
+        let mut conn = self.conn.state.lock("SendStream::poll_flushed");
+
+        if self.is_0rtt {
+            conn.check_0rtt()
+                .map_err(|()| WriteError::ZeroRttRejected)?;
+        }
+        if self.reset {
+            return Poll::Ready(Err(WriteError::ClosedStream));
+        }
+
+        let mut stream = conn.inner.send_stream(self.stream);
+        match stream.flushed() {
+            // A stream that wasn't reset is only forgotten once finished and fully acknowledged
+            Ok(true) | Err(_) => Poll::Ready(Ok(())),
+            Ok(false) => {
+                if let Ok(Some(error_code)) = stream.stopped() {
+                    return Poll::Ready(Err(WriteError::Stopped(error_code)));
+                }
+                if let Some(e) = &conn.error {
+                    return Poll::Ready(Err(e.clone().into()));
+                }
+                conn.flushed.insert(self.stream, cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Number of bytes that could currently be written to this stream without blocking
+    ///
+    /// This is the lesser of the stream's and the connection's flow control credit, further limited
+    /// by the send window, and can be used to size writes so that they are never partial.
+    /// Congestion control is not taken into account.
+    pub fn max_writable(&self) -> Result<u64, ClosedStream> {
+        let mut conn = self.conn.state.lock("SendStream::max_writable");
+        conn.inner.send_stream(self.stream).max_writable()
+    }
+
+    /// Get statistics about data sent on this stream
+    ///
+    /// Fails with `ClosedStream` once the stream has been fully acknowledged or reset.
+    pub fn stats(&self) -> Result<StreamStats, ClosedStream> {
+        let mut conn = self.conn.state.lock("SendStream::stats");
+        let mut stats = conn.inner.send_stream(self.stream).stats()?;
+        stats.blocked_time = self.blocked_time;
+        if let Some(since) = self.blocked_since {
+            stats.blocked_time += conn.runtime.now().saturating_duration_since(since);
+        }
+        Ok(stats)
+    }
+
+    /// Add the stream to `group`, or remove it from its current group if `None`
+    ///
+    /// See [`StreamGroup`](crate::StreamGroup).
+    pub fn set_group(&mut self, group: Option<StreamGroupId>) -> Result<(), ClosedStream> {
+        let mut conn = self.conn.state.lock("SendStream::set_group");
+        conn.inner.send_stream(self.stream).set_group(group)?;
+        // May unblock writers of the previous group
+        conn.wake();
+        Ok(())
+    }
+
+    /// Get the group of the stream
+    pub fn group(&self) -> Result<Option<StreamGroupId>, ClosedStream> {
+        let mut conn = self.conn.state.lock("SendStream::group");
+        conn.inner.send_stream(self.stream).group()
+    }
+
+    /// Convert into an adapter that writes length-prefixed messages
+    ///
+    /// See [`FramedSendStream`].
+    pub fn into_frame_writer(self) -> FramedSendStream {
+        FramedSendStream::new(self)
+    }
+
     /// Get the identity of this stream
     pub fn id(&self) -> StreamId {
         self.stream
@@ -244,6 +463,18 @@ impl SendStream {
     ) -> Poll<Result<usize, WriteError>> {
         self.get_mut().execute_poll(cx, |stream| stream.write(buf))
     }
+
+    /// Attempt to write bytes from a list of buffers into the stream.
+    ///
+    /// Behaves like [`poll_write()`](Self::poll_write), but takes the data from multiple buffers.
+    pub fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, WriteError>> {
+        self.get_mut()
+            .execute_poll(cx, |stream| stream.write_vectored(bufs))
+    }
 }
 
 #[cfg(feature = "futures-io")]
@@ -252,6 +483,15 @@ impl futures_io::AsyncWrite for SendStream {
         Self::execute_poll(self.get_mut(), cx, |stream| stream.write(buf)).map_err(Into::into)
     }
 
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Self::execute_poll(self.get_mut(), cx, |stream| stream.write_vectored(bufs))
+            .map_err(Into::into)
+    }
+
     fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
         Poll::Ready(Ok(()))
     }
@@ -271,6 +511,19 @@ impl tokio::io::AsyncWrite for SendStream {
         Self::execute_poll(self.get_mut(), cx, |stream| stream.write(buf)).map_err(Into::into)
     }
 
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Self::execute_poll(self.get_mut(), cx, |stream| stream.write_vectored(bufs))
+            .map_err(Into::into)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
     fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
         Poll::Ready(Ok(()))
     }
@@ -286,7 +539,9 @@ impl Drop for SendStream {
 
         // clean up any previously registered wakers
         conn.stopped.remove(&self.stream);
+        conn.flushed.remove(&self.stream);
         conn.blocked_writers.remove(&self.stream);
+        conn.write_deadlines.remove(&self.stream);
 
         if conn.error.is_some() || (self.is_0rtt && conn.check_0rtt().is_err()) {
             return;
@@ -304,6 +559,10 @@ impl Drop for SendStream {
     }
 }
 
+/// Size of the buffers used by [`SendStream::send_file()`]
+#[cfg(feature = "send-file")]
+const SEND_FILE_CHUNK_SIZE: usize = 64 * 1024;
+
 /// Future produced by `SendStream::stopped`
 #[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
 struct Stopped<'a> {
@@ -318,6 +577,20 @@ impl Future for Stopped<'_> {
     }
 }
 
+/// Future produced by `SendStream::flushed`
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+struct Flushed<'a> {
+    stream: &'a mut SendStream,
+}
+
+impl Future for Flushed<'_> {
+    type Output = Result<(), WriteError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.get_mut().stream.poll_flushed(cx)
+    }
+}
+
 /// Future produced by [`SendStream::write()`].
 ///
 /// [`SendStream::write()`]: crate::SendStream::write
@@ -336,6 +609,36 @@ impl<'a> Future for Write<'a> {
     }
 }
 
+impl<'a> Drop for Write<'a> {
+    fn drop(&mut self) {
+        self.stream.abandon_write();
+    }
+}
+
+/// Future produced by [`SendStream::write_vectored()`].
+///
+/// [`SendStream::write_vectored()`]: crate::SendStream::write_vectored
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+struct WriteVectored<'a, 'b> {
+    stream: &'a mut SendStream,
+    bufs: &'a [IoSlice<'b>],
+}
+
+impl<'a, 'b> Future for WriteVectored<'a, 'b> {
+    type Output = Result<usize, WriteError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let bufs = this.bufs;
+        this.stream.execute_poll(cx, |s| s.write_vectored(bufs))
+    }
+}
+
+impl<'a, 'b> Drop for WriteVectored<'a, 'b> {
+    fn drop(&mut self) {
+        self.stream.abandon_write();
+    }
+}
+
 /// Future produced by [`SendStream::write_all()`].
 ///
 /// [`SendStream::write_all()`]: crate::SendStream::write_all
@@ -360,6 +663,12 @@ impl<'a> Future for WriteAll<'a> {
     }
 }
 
+impl<'a> Drop for WriteAll<'a> {
+    fn drop(&mut self) {
+        self.stream.abandon_write();
+    }
+}
+
 /// Future produced by [`SendStream::write_chunks()`].
 ///
 /// [`SendStream::write_chunks()`]: crate::SendStream::write_chunks
@@ -378,6 +687,12 @@ impl<'a> Future for WriteChunks<'a> {
     }
 }
 
+impl<'a> Drop for WriteChunks<'a> {
+    fn drop(&mut self) {
+        self.stream.abandon_write();
+    }
+}
+
 /// Future produced by [`SendStream::write_chunk()`].
 ///
 /// [`SendStream::write_chunk()`]: crate::SendStream::write_chunk
@@ -401,6 +716,12 @@ impl<'a> Future for WriteChunk<'a> {
     }
 }
 
+impl<'a> Drop for WriteChunk<'a> {
+    fn drop(&mut self) {
+        self.stream.abandon_write();
+    }
+}
+
 /// Future produced by [`SendStream::write_all_chunks()`].
 ///
 /// [`SendStream::write_all_chunks()`]: crate::SendStream::write_all_chunks
@@ -426,6 +747,12 @@ impl<'a> Future for WriteAllChunks<'a> {
     }
 }
 
+impl<'a> Drop for WriteAllChunks<'a> {
+    fn drop(&mut self) {
+        self.stream.abandon_write();
+    }
+}
+
 /// Errors that arise from writing to a stream
 #[derive(Debug, Error, Clone, PartialEq, Eq)]
 pub enum WriteError {
@@ -448,6 +775,11 @@ pub enum WriteError {
     /// [`Connecting::into_0rtt()`]: crate::Connecting::into_0rtt()
     #[error("0-RTT rejected")]
     ZeroRttRejected,
+    /// The write deadline passed while the stream was blocked
+    ///
+    /// See [`SendStream::set_write_deadline()`].
+    #[error("write deadline exceeded")]
+    TimedOut,
 }
 
 impl From<ClosedStream> for WriteError {
@@ -472,6 +804,7 @@ impl From<WriteError> for io::Error {
         let kind = match x {
             Stopped(_) | ZeroRttRejected => io::ErrorKind::ConnectionReset,
             ConnectionLost(_) | ClosedStream => io::ErrorKind::NotConnected,
+            TimedOut => io::ErrorKind::TimedOut,
         };
         Self::new(kind, x)
     }
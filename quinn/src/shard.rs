@@ -0,0 +1,488 @@
+use std::{
+    future::Future,
+    io::{self, IoSliceMut},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+#[cfg(target_os = "linux")]
+use std::{mem, net::SocketAddr, os::fd::AsRawFd};
+
+use bytes::BytesMut;
+use proto::{ConnectionId, ConnectionIdGenerator, EndpointConfig, InvalidCid};
+#[cfg(target_os = "linux")]
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::sync::mpsc;
+use udp::{RecvMeta, Transmit, BATCH_SIZE};
+
+use crate::{
+    buffer_pool::RecvBuffers,
+    runtime::{AsyncUdpSocket, Runtime, UdpPoller},
+    work_limiter::WorkLimiter,
+    RECV_TIME_BOUND,
+};
+
+/// Derive the configuration of shard `index` of `count` from `config`
+///
+/// Connection IDs are prefixed with a byte identifying the shard, which the kernel uses to steer
+/// datagrams to the shard's socket.
+pub(crate) fn config(config: &EndpointConfig, index: usize, count: usize) -> EndpointConfig {
+    let factory = config.get_cid_generator();
+    let mut config = config.clone();
+    config.cid_generator(move || {
+        Box::new(ShardCidGenerator {
+            inner: factory(),
+            index: index as u8,
+            count: count as u8,
+        })
+    });
+    config
+}
+
+/// Bind `count` sockets to `addr` with `SO_REUSEPORT`, steering datagrams between them by the
+/// first byte of their destination connection ID
+#[cfg(target_os = "linux")]
+pub(crate) fn bind(addr: SocketAddr, count: usize) -> io::Result<Vec<std::net::UdpSocket>> {
+    let mut addr = addr;
+    let mut sockets = Vec::with_capacity(count);
+    for _ in 0..count {
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        set_option(&socket, libc::SO_REUSEPORT, &1)?;
+        socket.bind(&addr.into())?;
+        if sockets.is_empty() {
+            // Later sockets must join the group at the port the first was assigned
+            addr = socket.local_addr()?.as_socket().unwrap();
+            // Sockets are indexed within the group in the order they were bound
+            set_option(
+                &socket,
+                libc::SO_ATTACH_REUSEPORT_CBPF,
+                &steering_program(count),
+            )?;
+        }
+        sockets.push(socket.into());
+    }
+    Ok(sockets)
+}
+
+/// Check that shards can extend the connection IDs generated for `config`
+pub(crate) fn validate(config: &EndpointConfig, count: usize) -> io::Result<()> {
+    if count == 0 || count > usize::from(u8::MAX) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "shard count must be between 1 and 255",
+        ));
+    }
+    if (config.get_cid_generator())().cid_len() >= MAX_CID_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "connection IDs are too long to identify shards",
+        ));
+    }
+    Ok(())
+}
+
+/// Split `socket` into `count` sockets, each receiving the datagrams of one shard
+///
+/// A task spawned on `runtime` receives every datagram from `socket`, and hands it over to the
+/// shard selected by [`shard_of()`] through a lock-free queue. Datagrams for a shard whose queue
+/// is full are dropped, as the kernel would for a socket of its own. Sends go straight to `socket`.
+pub(crate) fn split(
+    socket: Arc<dyn AsyncUdpSocket>,
+    count: usize,
+    config: &EndpointConfig,
+    runtime: Arc<dyn Runtime>,
+) -> Vec<Arc<dyn AsyncUdpSocket>> {
+    // Sized like an endpoint's own buffers, so that full batches are handed over without copying
+    let datagram_len = config
+        .get_max_udp_payload_size()
+        .min(MAX_DATAGRAM_SIZE as u64) as usize;
+    split_with_capacity(socket, count, datagram_len, QUEUE_CAPACITY, runtime)
+}
+
+fn split_with_capacity(
+    socket: Arc<dyn AsyncUdpSocket>,
+    count: usize,
+    datagram_len: usize,
+    capacity: usize,
+    runtime: Arc<dyn Runtime>,
+) -> Vec<Arc<dyn AsyncUdpSocket>> {
+    let dispatcher_waker = Arc::new(Mutex::new(None));
+    let (senders, shards) = (0..count)
+        .map(|_| {
+            let (send, recv) = mpsc::channel(capacity);
+            let shard: Arc<dyn AsyncUdpSocket> = Arc::new(ShardSocket {
+                inner: socket.clone(),
+                received: Mutex::new(recv),
+                dispatcher: dispatcher_waker.clone(),
+            });
+            (send, shard)
+        })
+        .unzip();
+    let recv_buf_len = datagram_len * socket.max_receive_segments();
+    runtime.spawn(Box::pin(Dispatcher {
+        socket,
+        shards: senders,
+        waker: dispatcher_waker,
+        recv_bufs: RecvBuffers::new(recv_buf_len),
+        recv_limiter: WorkLimiter::new(RECV_TIME_BOUND),
+        runtime: runtime.clone(),
+    }));
+    shards
+}
+
+/// The shard owning the connection `datagram` belongs to
+///
+/// Looks at the same byte as the kernel does for sockets from [`bind()`].
+fn shard_of(datagram: &[u8], count: usize) -> usize {
+    let offset = match datagram.first() {
+        // Long header: the connection ID follows the version and its length
+        Some(x) if x & 0x80 != 0 => 6,
+        // Short header: the connection ID follows the first byte
+        _ => 1,
+    };
+    datagram.get(offset).map_or(0, |&x| usize::from(x) % count)
+}
+
+/// Receives datagrams for a set of [`ShardSocket`]s
+struct Dispatcher {
+    socket: Arc<dyn AsyncUdpSocket>,
+    shards: Vec<mpsc::Sender<(RecvMeta, BytesMut)>>,
+    /// Woken when a shard is dropped
+    waker: Arc<Mutex<Option<Waker>>>,
+    recv_bufs: RecvBuffers,
+    recv_limiter: WorkLimiter,
+    runtime: Arc<dyn Runtime>,
+}
+
+impl Future for Dispatcher {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        *this.waker.lock().unwrap() = Some(cx.waker().clone());
+        let count = this.shards.len();
+        let runtime = this.runtime.clone();
+        let get_time = || runtime.now();
+        this.recv_limiter.start_cycle(get_time);
+        let result = loop {
+            if this.shards.iter().all(|x| x.is_closed()) {
+                break Poll::Ready(());
+            }
+            if !this.recv_limiter.allow_work(get_time) {
+                // Let other tasks, including the shards draining their queues, run
+                cx.waker().wake_by_ref();
+                break Poll::Pending;
+            }
+            let shards = &this.shards;
+            let mut datagrams = 0;
+            let received = this
+                .recv_bufs
+                .poll_recv(cx, &*this.socket, |meta, mut data| {
+                    // Segments of a batch may belong to different connections
+                    while !data.is_empty() {
+                        let datagram = data.split_to(meta.stride.min(data.len()));
+                        let shard = shard_of(&datagram, count);
+                        let meta = RecvMeta {
+                            len: datagram.len(),
+                            stride: datagram.len(),
+                            ..meta
+                        };
+                        datagrams += 1;
+                        // A shard that's gone has no connections left to deliver to, and one
+                        // that's falling behind must shed load like an overflowing socket would
+                        if let Err(mpsc::error::TrySendError::Full(_)) =
+                            shards[shard].try_send((meta, datagram))
+                        {
+                            tracing::trace!(shard, "shard queue full, dropping datagram");
+                        }
+                    }
+                });
+            match received {
+                Poll::Ready(Ok(())) => this.recv_limiter.record_work(datagrams),
+                Poll::Pending => break Poll::Pending,
+                // Ignore ECONNRESET as it's undefined in QUIC and may be injected by an attacker
+                Poll::Ready(Err(e)) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+                Poll::Ready(Err(e)) => {
+                    tracing::error!("receiving for shards failed: {e}");
+                    break Poll::Ready(());
+                }
+            }
+        };
+        this.recv_limiter.finish_cycle(get_time);
+        result
+    }
+}
+
+/// One shard's view of a socket split by [`split()`]
+#[derive(Debug)]
+struct ShardSocket {
+    inner: Arc<dyn AsyncUdpSocket>,
+    received: Mutex<mpsc::Receiver<(RecvMeta, BytesMut)>>,
+    dispatcher: Arc<Mutex<Option<Waker>>>,
+}
+
+impl AsyncUdpSocket for ShardSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        self.inner.clone().create_io_poller()
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        self.inner.try_send(transmit)
+    }
+
+    fn try_send_batch(&self, transmits: &[Transmit]) -> io::Result<usize> {
+        self.inner.try_send_batch(transmits)
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let mut received = self.received.lock().unwrap();
+        let mut count = 0;
+        while count < bufs.len().min(meta.len()) {
+            let (datagram_meta, datagram) = match received.poll_recv(cx) {
+                Poll::Ready(Some(x)) => x,
+                Poll::Ready(None) if count == 0 => return Poll::Ready(Err(dispatcher_stopped())),
+                _ => break,
+            };
+            let len = datagram.len().min(bufs[count].len());
+            bufs[count][..len].copy_from_slice(&datagram[..len]);
+            meta[count] = RecvMeta {
+                len,
+                stride: len,
+                ..datagram_meta
+            };
+            count += 1;
+        }
+        match count {
+            0 => Poll::Pending,
+            _ => Poll::Ready(Ok(count)),
+        }
+    }
+
+    fn recv_owned(&self) -> bool {
+        true
+    }
+
+    fn poll_recv_owned(
+        &self,
+        cx: &mut Context,
+        datagrams: &mut Vec<(RecvMeta, BytesMut)>,
+    ) -> Poll<io::Result<()>> {
+        let mut received = self.received.lock().unwrap();
+        let start = datagrams.len();
+        while datagrams.len() - start < BATCH_SIZE {
+            match received.poll_recv(cx) {
+                Poll::Ready(Some(x)) => datagrams.push(x),
+                Poll::Ready(None) if datagrams.len() == start => {
+                    return Poll::Ready(Err(dispatcher_stopped()))
+                }
+                _ => break,
+            }
+        }
+        match datagrams.len() == start {
+            true => Poll::Pending,
+            false => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<std::net::SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+
+    fn max_transmit_segments(&self) -> usize {
+        self.inner.max_transmit_segments()
+    }
+}
+
+fn dispatcher_stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "shared socket failed")
+}
+
+impl Drop for ShardSocket {
+    fn drop(&mut self) {
+        // Let the dispatcher stop once every shard is gone
+        self.received.get_mut().unwrap().close();
+        if let Some(waker) = self.dispatcher.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Classic BPF program selecting the socket for a datagram from the first byte of its destination
+/// connection ID
+///
+/// The program sees the datagram from the start of the UDP payload. A datagram too short to
+/// contain the byte is steered to the first socket.
+#[cfg(target_os = "linux")]
+struct SteeringProgram {
+    filters: [libc::sock_filter; 8],
+    prog: libc::sock_fprog,
+}
+
+#[cfg(target_os = "linux")]
+fn steering_program(count: usize) -> Box<SteeringProgram> {
+    const LDB_ABS: u16 = 0x30; // BPF_LD | BPF_B | BPF_ABS
+    const JSET_K: u16 = 0x45; // BPF_JMP | BPF_JSET | BPF_K
+    const MOD_K: u16 = 0x94; // BPF_ALU | BPF_MOD | BPF_K
+    const RET_A: u16 = 0x16; // BPF_RET | BPF_A
+    let op = |code, jt, jf, k| libc::sock_filter { code, jt, jf, k };
+    let count = count as u32;
+    let mut program = Box::new(SteeringProgram {
+        filters: [
+            op(LDB_ABS, 0, 0, 0),
+            // Long header form?
+            op(JSET_K, 3, 0, 0x80),
+            // Short header: the connection ID follows the first byte
+            op(LDB_ABS, 0, 0, 1),
+            op(MOD_K, 0, 0, count),
+            op(RET_A, 0, 0, 0),
+            // Long header: the connection ID follows the version and its length
+            op(LDB_ABS, 0, 0, 6),
+            op(MOD_K, 0, 0, count),
+            op(RET_A, 0, 0, 0),
+        ],
+        prog: libc::sock_fprog {
+            len: 8,
+            filter: std::ptr::null_mut(),
+        },
+    });
+    program.prog.filter = program.filters.as_mut_ptr();
+    program
+}
+
+#[cfg(target_os = "linux")]
+trait SocketOption {
+    fn as_option(&self) -> (*const libc::c_void, libc::socklen_t);
+}
+
+#[cfg(target_os = "linux")]
+impl SocketOption for libc::c_int {
+    fn as_option(&self) -> (*const libc::c_void, libc::socklen_t) {
+        (self as *const _ as *const _, mem::size_of::<Self>() as _)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl SocketOption for Box<SteeringProgram> {
+    fn as_option(&self) -> (*const libc::c_void, libc::socklen_t) {
+        (
+            &self.prog as *const _ as *const _,
+            mem::size_of::<libc::sock_fprog>() as _,
+        )
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_option(socket: &Socket, name: libc::c_int, value: &impl SocketOption) -> io::Result<()> {
+    let (ptr, len) = value.as_option();
+    let rc = unsafe { libc::setsockopt(socket.as_raw_fd(), libc::SOL_SOCKET, name, ptr, len) };
+    match rc {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Maximum length of a connection ID in QUIC version 1
+const MAX_CID_SIZE: usize = 20;
+/// Largest possible UDP payload
+const MAX_DATAGRAM_SIZE: usize = 64 * 1024;
+/// Datagrams queued for a shard before further ones are dropped
+///
+/// Enough for a few dozen receive batches, so that a shard only loses datagrams when it falls
+/// persistently behind.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Prefixes connection IDs from another generator with a byte identifying a shard
+struct ShardCidGenerator {
+    inner: Box<dyn ConnectionIdGenerator>,
+    index: u8,
+    count: u8,
+}
+
+impl ConnectionIdGenerator for ShardCidGenerator {
+    fn generate_cid(&mut self) -> ConnectionId {
+        let inner = self.inner.generate_cid();
+        // Any byte congruent to the index identifies the shard; derive one from the inner
+        // connection ID to avoid exposing a constant to observers
+        let multiples = u16::from((u8::MAX - self.index) / self.count) + 1;
+        let seed = inner.first().map_or(0, |&x| u16::from(x) % multiples) as u8;
+        let mut bytes = [0; MAX_CID_SIZE];
+        bytes[0] = self.index + self.count * seed;
+        bytes[1..=inner.len()].copy_from_slice(&inner);
+        ConnectionId::new(&bytes[..=inner.len()])
+    }
+
+    fn validate(&self, cid: &ConnectionId) -> Result<(), InvalidCid> {
+        if cid.is_empty() || cid[0] % self.count != self.index {
+            return Err(InvalidCid);
+        }
+        self.inner.validate(&ConnectionId::new(&cid[1..]))
+    }
+
+    fn cid_len(&self) -> usize {
+        self.inner.cid_len() + 1
+    }
+
+    fn cid_lifetime(&self) -> Option<Duration> {
+        self.inner.cid_lifetime()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "runtime-tokio")]
+    #[tokio::test]
+    async fn queue_overflow() {
+        let runtime: Arc<dyn Runtime> = Arc::new(crate::TokioRuntime);
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let shards = split_with_capacity(
+            runtime.wrap_udp_socket(socket).unwrap(),
+            1,
+            1500,
+            4,
+            runtime,
+        );
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        for i in 0..16 {
+            sender.send_to(&[0x40, i], addr).unwrap();
+        }
+        // Let the dispatcher receive everything while the shard isn't reading
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut datagrams = Vec::new();
+        recv(&*shards[0], &mut datagrams).await;
+        assert_eq!(
+            datagrams.len(),
+            4,
+            "datagrams beyond the queue's capacity are dropped"
+        );
+        assert_eq!(&datagrams[0].1[..], [0x40, 0]);
+
+        // Draining the queue makes room for more
+        datagrams.clear();
+        sender.send_to(&[0x40, 16], addr).unwrap();
+        recv(&*shards[0], &mut datagrams).await;
+        assert_eq!(datagrams.len(), 1);
+        assert_eq!(&datagrams[0].1[..], [0x40, 16]);
+    }
+
+    #[cfg(feature = "runtime-tokio")]
+    async fn recv(shard: &dyn AsyncUdpSocket, datagrams: &mut Vec<(RecvMeta, BytesMut)>) {
+        let recv = std::future::poll_fn(|cx| shard.poll_recv_owned(cx, datagrams));
+        tokio::time::timeout(Duration::from_secs(2), recv)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
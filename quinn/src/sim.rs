@@ -0,0 +1,629 @@
+//! Deterministic simulation of networks and time, for testing protocols built on Quinn
+//!
+//! A [`Simulation`] is a [`Runtime`] with a virtual clock, and a network of in-memory sockets
+//! connected by links with configurable latency, jitter, loss, reordering and bandwidth. Time only
+//! passes when every task is waiting, and then jumps straight to the next timer expiry or datagram
+//! arrival, so simulations run as fast as the CPU allows however much virtual time they span.
+//! Tasks run one at a time in a fixed order, and random link behavior is drawn from a seeded
+//! generator, which also seeds the endpoints unless they were given a fixed
+//! [`EndpointConfig::rng_seed()`], so a simulation with the same seed and inputs always unfolds the
+//! same way.
+//!
+//! Endpoints are created with [`Endpoint::new_with_abstract_socket()`], passing a socket from
+//! [`Simulation::socket()`] and the runtime from [`Simulation::runtime()`]:
+//!
+//! ```no_run
+//! # fn f(server_config: quinn::ServerConfig) -> std::io::Result<()> {
+//! use quinn::{sim::Simulation, Endpoint, EndpointConfig};
+//!
+//! let sim = Simulation::new(42);
+//! let server = Endpoint::new_with_abstract_socket(
+//!     EndpointConfig::default(),
+//!     Some(server_config),
+//!     sim.socket("10.0.0.1:4433".parse().unwrap())?,
+//!     sim.runtime(),
+//! )?;
+//! sim.run(async {
+//!     while let Some(incoming) = server.accept().await {
+//!         // ...
+//!     }
+//! });
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Futures relying on another runtime, such as Tokio's timers, cannot be used in a simulation.
+//!
+//! Faults beyond those of a link, like duplicated or corrupted datagrams, can be injected by
+//! wrapping a socket in a [`FaultySocket`], which also works with real sockets.
+//!
+//! [`Endpoint::new_with_abstract_socket()`]: crate::Endpoint::new_with_abstract_socket
+//! [`EndpointConfig::rng_seed()`]: crate::EndpointConfig::rng_seed
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, VecDeque},
+    fmt,
+    future::Future,
+    io::{self, IoSliceMut},
+    net::SocketAddr,
+    pin::{pin, Pin},
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll, Wake, Waker},
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::OsRng, RngCore};
+use rustc_hash::FxHashMap;
+use udp::{EcnCodepoint, RecvMeta, Transmit};
+
+use crate::runtime::{AsyncTimer, AsyncUdpSocket, Runtime, UdpPoller};
+
+/// A simulated network and clock
+///
+/// Dropping the last handle to a simulation drops every task spawned on it.
+#[derive(Clone)]
+pub struct Simulation(Arc<Inner>);
+
+impl Simulation {
+    /// Create a simulation whose random link behavior is derived from `seed`
+    pub fn new(seed: u64) -> Self {
+        let now = Instant::now();
+        Self(Arc::new(Inner {
+            state: Mutex::new(State {
+                start: now,
+                now,
+                rng: Rng(seed),
+                next_seq: 0,
+                timers: BinaryHeap::new(),
+                in_flight: BinaryHeap::new(),
+                sockets: FxHashMap::default(),
+                default_link: LinkConfig::default(),
+                links: FxHashMap::default(),
+                busy_until: FxHashMap::default(),
+            }),
+            tasks: Mutex::new(FxHashMap::default()),
+            ready: Arc::new(ReadyQueue::default()),
+        }))
+    }
+
+    /// The runtime to construct endpoints with, running tasks in this simulation
+    pub fn runtime(&self) -> Arc<dyn Runtime> {
+        Arc::new(SimRuntime(Arc::downgrade(&self.0)))
+    }
+
+    /// Create a socket bound to `addr` in the simulated network
+    ///
+    /// Fails with [`io::ErrorKind::AddrInUse`] if a live socket is already bound to `addr`.
+    pub fn socket(&self, addr: SocketAddr) -> io::Result<Arc<dyn AsyncUdpSocket>> {
+        let mut state = self.0.state.lock().unwrap();
+        if state.sockets.contains_key(&addr) {
+            return Err(io::ErrorKind::AddrInUse.into());
+        }
+        state.sockets.insert(addr, Mailbox::default());
+        Ok(Arc::new(SimSocket {
+            sim: Arc::downgrade(&self.0),
+            addr,
+        }))
+    }
+
+    /// Set the behavior of links for which none was set by [`set_link()`](Self::set_link)
+    pub fn set_default_link(&self, config: LinkConfig) {
+        self.0.state.lock().unwrap().default_link = config;
+    }
+
+    /// Set the behavior of the link carrying datagrams from `src` to `dst`
+    ///
+    /// Links are unidirectional, so asymmetric networks can be simulated.
+    pub fn set_link(&self, src: SocketAddr, dst: SocketAddr, config: LinkConfig) {
+        self.0
+            .state
+            .lock()
+            .unwrap()
+            .links
+            .insert((src, dst), config);
+    }
+
+    /// The current virtual time
+    pub fn now(&self) -> Instant {
+        self.0.state.lock().unwrap().now
+    }
+
+    /// Virtual time elapsed since the simulation was created
+    pub fn elapsed(&self) -> Duration {
+        let state = self.0.state.lock().unwrap();
+        state.now - state.start
+    }
+
+    /// Run the simulation until `future` completes, returning its output
+    ///
+    /// Spawned tasks run along with `future`, and those not yet complete are resumed by the next
+    /// call. Panics if every task is waiting while no timer or datagram is pending, as the
+    /// simulation could then never make progress.
+    pub fn run<F: Future>(&self, future: F) -> F::Output {
+        let mut future = pin!(future);
+        let waker = Waker::from(Arc::new(TaskWaker {
+            id: MAIN_TASK,
+            ready: self.0.ready.clone(),
+        }));
+        self.0.ready.push(MAIN_TASK);
+        loop {
+            while let Some(id) = self.0.ready.pop() {
+                if id == MAIN_TASK {
+                    if let Poll::Ready(x) = future.as_mut().poll(&mut Context::from_waker(&waker)) {
+                        return x;
+                    }
+                    continue;
+                }
+                // Tasks may spawn or wake others while polled, so the lock can't be held
+                let Some((mut task, waker)) = self.0.tasks.lock().unwrap().remove(&id) else {
+                    continue;
+                };
+                if task
+                    .as_mut()
+                    .poll(&mut Context::from_waker(&waker))
+                    .is_pending()
+                {
+                    self.0.tasks.lock().unwrap().insert(id, (task, waker));
+                }
+            }
+            if !self.0.advance() {
+                panic!(
+                    "simulation stalled: all tasks are waiting, with no timer or datagram pending"
+                );
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Simulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.0.state.lock().unwrap();
+        f.debug_struct("Simulation")
+            .field("elapsed", &(state.now - state.start))
+            .field("sockets", &state.sockets.len())
+            .field("in_flight", &state.in_flight.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Behavior of a simulated network link
+#[derive(Debug, Clone)]
+pub struct LinkConfig {
+    latency: Duration,
+    jitter: Duration,
+    loss: f64,
+    reorder: f64,
+    bandwidth: Option<u64>,
+    queue_size: Option<u64>,
+}
+
+impl LinkConfig {
+    /// One-way delay of every datagram
+    ///
+    /// Defaults to 10ms.
+    pub fn latency(&mut self, value: Duration) -> &mut Self {
+        self.latency = value;
+        self
+    }
+
+    /// Upper bound of a uniformly distributed random delay added to every datagram
+    ///
+    /// Jitter larger than the interval between datagrams reorders them. Defaults to zero.
+    pub fn jitter(&mut self, value: Duration) -> &mut Self {
+        self.jitter = value;
+        self
+    }
+
+    /// Probability that a datagram is lost, between 0 and 1
+    ///
+    /// Defaults to zero.
+    pub fn loss(&mut self, value: f64) -> &mut Self {
+        self.loss = value;
+        self
+    }
+
+    /// Probability that a datagram is delayed by an additional [`latency`](Self::latency), letting
+    /// datagrams sent after it overtake it, between 0 and 1
+    ///
+    /// Defaults to zero.
+    pub fn reorder(&mut self, value: f64) -> &mut Self {
+        self.reorder = value;
+        self
+    }
+
+    /// Rate at which the link carries datagrams, in bits per second, or `None` for no limit
+    ///
+    /// Datagrams are queued while the link is busy with earlier ones. Defaults to `None`.
+    pub fn bandwidth(&mut self, value: Option<u64>) -> &mut Self {
+        self.bandwidth = value;
+        self
+    }
+
+    /// Number of bytes queued for a link with limited [`bandwidth`](Self::bandwidth) beyond which
+    /// further datagrams are dropped, or `None` for no limit
+    ///
+    /// Defaults to `None`.
+    pub fn queue_size(&mut self, value: Option<u64>) -> &mut Self {
+        self.queue_size = value;
+        self
+    }
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self {
+            latency: Duration::from_millis(10),
+            jitter: Duration::ZERO,
+            loss: 0.0,
+            reorder: 0.0,
+            bandwidth: None,
+            queue_size: None,
+        }
+    }
+}
+
+struct Inner {
+    state: Mutex<State>,
+    /// Spawned tasks not currently being polled, with the wakers that schedule them
+    tasks: Mutex<FxHashMap<u64, (Task, Waker)>>,
+    ready: Arc<ReadyQueue>,
+}
+
+impl Inner {
+    fn spawn(&self, future: Task) {
+        let id = self.state.lock().unwrap().seq();
+        let waker = Waker::from(Arc::new(TaskWaker {
+            id,
+            ready: self.ready.clone(),
+        }));
+        self.tasks.lock().unwrap().insert(id, (future, waker));
+        self.ready.push(id);
+    }
+
+    /// Advance the clock to the next event, returning `false` if there is none
+    fn advance(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let next_timer = state.timers.peek().map(|x| x.0.time);
+        let next_arrival = state.in_flight.peek().map(|x| x.0.time);
+        let next = match (next_timer, next_arrival) {
+            (Some(x), Some(y)) => x.min(y),
+            (Some(x), None) | (None, Some(x)) => x,
+            (None, None) => return false,
+        };
+        state.now = state.now.max(next);
+        let now = state.now;
+        while matches!(state.timers.peek(), Some(x) if x.0.time <= now) {
+            state.timers.pop().unwrap().0.item.wake();
+        }
+        while matches!(state.in_flight.peek(), Some(x) if x.0.time <= now) {
+            let (dst, datagram) = state.in_flight.pop().unwrap().0.item;
+            // Datagrams to unbound addresses vanish
+            if let Some(mailbox) = state.sockets.get_mut(&dst) {
+                mailbox.queue.push_back(datagram);
+                if let Some(waker) = mailbox.waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+        true
+    }
+}
+
+struct State {
+    start: Instant,
+    now: Instant,
+    rng: Rng,
+    /// Source of task IDs and of tie-breakers between simultaneous events
+    next_seq: u64,
+    timers: BinaryHeap<Reverse<Scheduled<Waker>>>,
+    in_flight: BinaryHeap<Reverse<Scheduled<(SocketAddr, Datagram)>>>,
+    sockets: FxHashMap<SocketAddr, Mailbox>,
+    default_link: LinkConfig,
+    links: FxHashMap<(SocketAddr, SocketAddr), LinkConfig>,
+    /// When each link with limited bandwidth finishes sending the datagrams queued on it
+    busy_until: FxHashMap<(SocketAddr, SocketAddr), Instant>,
+}
+
+impl State {
+    fn seq(&mut self) -> u64 {
+        self.next_seq += 1;
+        self.next_seq
+    }
+
+    fn send(&mut self, src: SocketAddr, dst: SocketAddr, ecn: Option<EcnCodepoint>, data: &[u8]) {
+        let link = self.links.get(&(src, dst)).unwrap_or(&self.default_link);
+        let (latency, jitter, reorder) = (link.latency, link.jitter, link.reorder);
+        if self.rng.next_f64() < link.loss {
+            return;
+        }
+
+        let mut departure = self.now;
+        if let Some(bandwidth) = link.bandwidth {
+            let queue_size = link.queue_size;
+            let busy_until = self.busy_until.entry((src, dst)).or_insert(self.now);
+            let start = (*busy_until).max(self.now);
+            let queued = (start - self.now).as_secs_f64() * bandwidth as f64 / 8.0;
+            if matches!(queue_size, Some(size) if queued > size as f64) {
+                return;
+            }
+            departure = start + Duration::from_secs_f64(data.len() as f64 * 8.0 / bandwidth as f64);
+            *busy_until = departure;
+        }
+
+        let mut arrival = departure + latency + jitter.mul_f64(self.rng.next_f64());
+        if self.rng.next_f64() < reorder {
+            arrival += latency;
+        }
+        let datagram = Datagram {
+            src,
+            ecn,
+            data: data.to_vec(),
+        };
+        let seq = self.seq();
+        self.in_flight.push(Reverse(Scheduled {
+            time: arrival,
+            seq,
+            item: (dst, datagram),
+        }));
+    }
+}
+
+type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// ID of the future passed to [`Simulation::run()`]
+const MAIN_TASK: u64 = 0;
+
+/// IDs of tasks to poll, in the order they were woken
+#[derive(Default)]
+struct ReadyQueue(Mutex<VecDeque<u64>>);
+
+impl ReadyQueue {
+    fn push(&self, id: u64) {
+        self.0.lock().unwrap().push_back(id);
+    }
+
+    fn pop(&self) -> Option<u64> {
+        self.0.lock().unwrap().pop_front()
+    }
+}
+
+struct TaskWaker {
+    id: u64,
+    ready: Arc<ReadyQueue>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.ready.push(self.id);
+    }
+}
+
+/// An event due at `time`, ordered by time then by when it was scheduled
+struct Scheduled<T> {
+    time: Instant,
+    seq: u64,
+    item: T,
+}
+
+impl<T> PartialEq for Scheduled<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.time, self.seq) == (other.time, other.seq)
+    }
+}
+
+impl<T> Eq for Scheduled<T> {}
+
+impl<T> PartialOrd for Scheduled<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Scheduled<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.time, self.seq).cmp(&(other.time, other.seq))
+    }
+}
+
+/// Small, fast and deterministic random number generator (SplitMix64)
+#[derive(Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly distributed in `[0, 1)`
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[derive(Default)]
+struct Mailbox {
+    queue: VecDeque<Datagram>,
+    /// Task waiting to receive
+    waker: Option<Waker>,
+}
+
+struct Datagram {
+    src: SocketAddr,
+    ecn: Option<EcnCodepoint>,
+    data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct SimRuntime(Weak<Inner>);
+
+impl Runtime for SimRuntime {
+    fn new_timer(&self, deadline: Instant) -> Pin<Box<dyn AsyncTimer>> {
+        Box::pin(SimTimer {
+            sim: self.0.clone(),
+            deadline,
+            registered: None,
+        })
+    }
+
+    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        if let Some(sim) = self.0.upgrade() {
+            sim.spawn(future);
+        }
+    }
+
+    fn wrap_udp_socket(&self, _: std::net::UdpSocket) -> io::Result<Arc<dyn AsyncUdpSocket>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "simulations only support sockets from `Simulation::socket()`",
+        ))
+    }
+
+    fn fill_random(&self, dest: &mut [u8]) {
+        let Some(sim) = self.0.upgrade() else {
+            return OsRng.fill_bytes(dest);
+        };
+        let rng = &mut sim.state.lock().unwrap().rng;
+        for chunk in dest.chunks_mut(8) {
+            chunk.copy_from_slice(&rng.next_u64().to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    fn now(&self) -> Instant {
+        match self.0.upgrade() {
+            Some(sim) => sim.state.lock().unwrap().now,
+            None => Instant::now(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SimTimer {
+    sim: Weak<Inner>,
+    deadline: Instant,
+    /// Deadline and waker last scheduled, to avoid scheduling duplicates on every poll
+    registered: Option<(Instant, Waker)>,
+}
+
+impl AsyncTimer for SimTimer {
+    fn reset(mut self: Pin<&mut Self>, deadline: Instant) {
+        self.deadline = deadline;
+    }
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let Some(sim) = self.sim.upgrade() else {
+            return Poll::Pending;
+        };
+        let mut state = sim.state.lock().unwrap();
+        if state.now >= self.deadline {
+            self.registered = None;
+            return Poll::Ready(());
+        }
+        if !matches!(&self.registered, Some((deadline, waker)) if *deadline == self.deadline && waker.will_wake(cx.waker()))
+        {
+            let seq = state.seq();
+            state.timers.push(Reverse(Scheduled {
+                time: self.deadline,
+                seq,
+                item: cx.waker().clone(),
+            }));
+            self.registered = Some((self.deadline, cx.waker().clone()));
+        }
+        Poll::Pending
+    }
+}
+
+#[derive(Debug)]
+struct SimSocket {
+    sim: Weak<Inner>,
+    addr: SocketAddr,
+}
+
+impl AsyncUdpSocket for SimSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        Box::pin(AlwaysWritable)
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        let Some(sim) = self.sim.upgrade() else {
+            return Ok(());
+        };
+        let mut state = sim.state.lock().unwrap();
+        let segment_size = transmit.segment_size.unwrap_or(transmit.contents.len());
+        for segment in transmit.contents.chunks(segment_size.max(1)) {
+            state.send(self.addr, transmit.destination, transmit.ecn, segment);
+        }
+        Ok(())
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let Some(sim) = self.sim.upgrade() else {
+            return Poll::Pending;
+        };
+        let mut state = sim.state.lock().unwrap();
+        let mailbox = state.sockets.get_mut(&self.addr).unwrap();
+        if mailbox.queue.is_empty() {
+            mailbox.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let mut count = 0;
+        for (buf, meta) in bufs.iter_mut().zip(meta) {
+            let Some(datagram) = mailbox.queue.pop_front() else {
+                break;
+            };
+            let len = datagram.data.len().min(buf.len());
+            buf[..len].copy_from_slice(&datagram.data[..len]);
+            *meta = RecvMeta {
+                addr: datagram.src,
+                len,
+                stride: len,
+                ecn: datagram.ecn,
+                dst_ip: Some(self.addr.ip()),
+            };
+            count += 1;
+        }
+        Poll::Ready(Ok(count))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn may_fragment(&self) -> bool {
+        false
+    }
+}
+
+impl Drop for SimSocket {
+    fn drop(&mut self) {
+        if let Some(sim) = self.sim.upgrade() {
+            sim.state.lock().unwrap().sockets.remove(&self.addr);
+        }
+    }
+}
+
+/// Simulated sockets never run out of buffer space
+#[derive(Debug)]
+struct AlwaysWritable;
+
+impl UdpPoller for AlwaysWritable {
+    fn poll_writable(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+mod fault;
+pub use self::fault::{FaultPolicy, FaultySocket};
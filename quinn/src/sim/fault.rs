@@ -0,0 +1,186 @@
+use std::{
+    future::poll_fn,
+    io::{self, IoSliceMut},
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use udp::{RecvMeta, Transmit};
+
+use super::Rng;
+use crate::runtime::{AsyncUdpSocket, Runtime, UdpPoller};
+
+/// Which faults a [`FaultySocket`] injects, and how often
+///
+/// Every probability is between 0 and 1, defaults to zero, and is rolled independently for each
+/// datagram sent.
+#[derive(Debug, Clone)]
+pub struct FaultPolicy {
+    seed: u64,
+    loss: f64,
+    duplicate: f64,
+    delay: f64,
+    max_delay: Duration,
+    corrupt: f64,
+    truncate: f64,
+}
+
+impl FaultPolicy {
+    /// Create a policy injecting no faults, whose random decisions are derived from `seed`
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            loss: 0.0,
+            duplicate: 0.0,
+            delay: 0.0,
+            max_delay: Duration::ZERO,
+            corrupt: 0.0,
+            truncate: 0.0,
+        }
+    }
+
+    /// Probability that a datagram is dropped
+    pub fn loss(&mut self, probability: f64) -> &mut Self {
+        self.loss = probability;
+        self
+    }
+
+    /// Probability that a datagram is sent twice
+    pub fn duplicate(&mut self, probability: f64) -> &mut Self {
+        self.duplicate = probability;
+        self
+    }
+
+    /// Probability that a datagram is held back for a uniformly distributed time up to `max`
+    ///
+    /// Datagrams sent while another is held back overtake it.
+    pub fn delay(&mut self, probability: f64, max: Duration) -> &mut Self {
+        self.delay = probability;
+        self.max_delay = max;
+        self
+    }
+
+    /// Probability that a random bit of a datagram is flipped
+    pub fn corrupt(&mut self, probability: f64) -> &mut Self {
+        self.corrupt = probability;
+        self
+    }
+
+    /// Probability that a datagram is cut to a random shorter length
+    pub fn truncate(&mut self, probability: f64) -> &mut Self {
+        self.truncate = probability;
+        self
+    }
+}
+
+/// Wraps a socket to inject faults into the datagrams sent through it
+///
+/// Works with any socket, whether from a [`Simulation`](super::Simulation) or a real one, so an
+/// application's resilience to pathological networks can be checked in production-like
+/// integration tests. Faults are only injected on the sending side; wrap the sockets of both
+/// endpoints to disturb both directions. Delayed datagrams are sent by tasks spawned on the
+/// supplied runtime.
+#[derive(Debug)]
+pub struct FaultySocket {
+    inner: Arc<dyn AsyncUdpSocket>,
+    runtime: Arc<dyn Runtime>,
+    policy: FaultPolicy,
+    rng: Mutex<Rng>,
+}
+
+impl FaultySocket {
+    /// Wrap `inner`, injecting faults according to `policy`
+    pub fn wrap(
+        inner: Arc<dyn AsyncUdpSocket>,
+        policy: FaultPolicy,
+        runtime: Arc<dyn Runtime>,
+    ) -> Arc<dyn AsyncUdpSocket> {
+        Arc::new(Self {
+            inner,
+            runtime,
+            rng: Mutex::new(Rng(policy.seed)),
+            policy,
+        })
+    }
+}
+
+impl AsyncUdpSocket for FaultySocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        self.inner.clone().create_io_poller()
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        let policy = &self.policy;
+        let mut rng = self.rng.lock().unwrap();
+        if rng.next_f64() < policy.loss {
+            return Ok(());
+        }
+
+        // `max_transmit_segments` ensures every transmit is a single datagram
+        let mut data = transmit.contents.to_vec();
+        if rng.next_f64() < policy.corrupt && !data.is_empty() {
+            let bit = rng.next_u64() as usize % (data.len() * 8);
+            data[bit / 8] ^= 1 << (bit % 8);
+        }
+        if rng.next_f64() < policy.truncate {
+            data.truncate(rng.next_u64() as usize % data.len().max(1));
+        }
+        let duplicate = rng.next_f64() < policy.duplicate;
+        let delay =
+            (rng.next_f64() < policy.delay).then(|| policy.max_delay.mul_f64(rng.next_f64()));
+        drop(rng);
+
+        let (destination, ecn, src_ip) = (transmit.destination, transmit.ecn, transmit.src_ip);
+        let send = move |socket: &dyn AsyncUdpSocket, contents: &[u8]| {
+            socket.try_send(&Transmit {
+                destination,
+                ecn,
+                contents,
+                segment_size: None,
+                src_ip,
+            })
+        };
+        if duplicate {
+            // Like any UDP datagram, the copy may be lost if the socket is busy
+            let _ = send(&*self.inner, &data);
+        }
+        let Some(delay) = delay else {
+            return send(&*self.inner, &data);
+        };
+        let mut timer = self.runtime.new_timer(self.runtime.now() + delay);
+        let inner = self.inner.clone();
+        self.runtime.spawn(Box::pin(async move {
+            poll_fn(|cx| timer.as_mut().poll(cx)).await;
+            let _ = send(&*inner, &data);
+        }));
+        Ok(())
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        self.inner.poll_recv(cx, bufs, meta)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn max_transmit_segments(&self) -> usize {
+        1
+    }
+
+    fn max_receive_segments(&self) -> usize {
+        self.inner.max_receive_segments()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
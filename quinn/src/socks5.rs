@@ -0,0 +1,336 @@
+use std::{
+    fmt,
+    io::{self, IoSliceMut, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use udp::{RecvMeta, Transmit};
+
+use crate::runtime::{AsyncUdpSocket, UdpPoller};
+
+/// Tunnels datagrams through a SOCKS5 proxy's UDP relay, as specified in [RFC 1928]
+///
+/// Allows endpoints behind a proxy that only forwards traffic through SOCKS5 to establish QUIC
+/// connections. The proxy must support the UDP ASSOCIATE command. Every datagram is prefixed with
+/// a header of up to 22 bytes addressing its actual destination, which reduces the space available
+/// to QUIC packets accordingly; path MTU discovery accounts for this automatically.
+///
+/// The association lasts as long as the control connection to the proxy, which is held until the
+/// socket is dropped.
+///
+/// [RFC 1928]: https://www.rfc-editor.org/rfc/rfc1928.html
+pub struct Socks5UdpSocket {
+    inner: Arc<dyn AsyncUdpSocket>,
+    relay: SocketAddr,
+    _control: TcpStream,
+}
+
+impl Socks5UdpSocket {
+    /// Associate `inner` with the relay of the SOCKS5 proxy listening on `proxy`
+    ///
+    /// `credentials` supplies a username and password to authenticate with as specified in
+    /// [RFC 1929]; without them, the proxy must allow unauthenticated access. Blocks the calling
+    /// thread until the proxy has answered.
+    ///
+    /// The resulting socket is used with
+    /// [`Endpoint::new_with_abstract_socket()`](crate::Endpoint::new_with_abstract_socket).
+    ///
+    /// [RFC 1929]: https://www.rfc-editor.org/rfc/rfc1929.html
+    pub fn associate(
+        proxy: SocketAddr,
+        credentials: Option<(&str, &str)>,
+        inner: Arc<dyn AsyncUdpSocket>,
+    ) -> io::Result<Arc<dyn AsyncUdpSocket>> {
+        let mut control = TcpStream::connect(proxy)?;
+        control.set_nodelay(true)?;
+
+        let method = match credentials {
+            Some(_) => METHOD_USERNAME_PASSWORD,
+            None => METHOD_NONE,
+        };
+        control.write_all(&[VERSION, 1, method])?;
+        let mut reply = [0; 2];
+        control.read_exact(&mut reply)?;
+        if reply[0] != VERSION {
+            return Err(protocol_error("unexpected protocol version"));
+        }
+        if reply[1] != method {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "SOCKS5 proxy rejected authentication method",
+            ));
+        }
+
+        if let Some((username, password)) = credentials {
+            let (username, password) = (username.as_bytes(), password.as_bytes());
+            let (Ok(username_len), Ok(password_len)) =
+                (u8::try_from(username.len()), u8::try_from(password.len()))
+            else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "SOCKS5 username or password longer than 255 bytes",
+                ));
+            };
+            let mut request = vec![1, username_len];
+            request.extend_from_slice(username);
+            request.push(password_len);
+            request.extend_from_slice(password);
+            control.write_all(&request)?;
+            control.read_exact(&mut reply)?;
+            if reply[1] != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "SOCKS5 proxy rejected credentials",
+                ));
+            }
+        }
+
+        // Announce the address datagrams will be sent from, which proxies may use to filter them
+        let mut request = vec![VERSION, COMMAND_UDP_ASSOCIATE, 0];
+        encode_addr(inner.local_addr()?, &mut request);
+        control.write_all(&request)?;
+        let mut reply = [0; 4];
+        control.read_exact(&mut reply)?;
+        if reply[0] != VERSION {
+            return Err(protocol_error("unexpected protocol version"));
+        }
+        if reply[1] != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("SOCKS5 proxy refused UDP association (code {})", reply[1]),
+            ));
+        }
+        let ip = match reply[3] {
+            ATYP_IPV4 => {
+                let mut octets = [0; 4];
+                control.read_exact(&mut octets)?;
+                IpAddr::from(octets)
+            }
+            ATYP_IPV6 => {
+                let mut octets = [0; 16];
+                control.read_exact(&mut octets)?;
+                IpAddr::from(octets)
+            }
+            _ => return Err(protocol_error("unsupported relay address type")),
+        };
+        let mut port = [0; 2];
+        control.read_exact(&mut port)?;
+        // An unspecified address designates the proxy's own address
+        let ip = match ip.is_unspecified() {
+            true => proxy.ip(),
+            false => ip,
+        };
+        let mut relay = SocketAddr::new(ip, u16::from_be_bytes(port));
+        if let (IpAddr::V4(ip), SocketAddr::V6(_)) = (relay.ip(), inner.local_addr()?) {
+            relay.set_ip(IpAddr::V6(ip.to_ipv6_mapped()));
+        }
+
+        Ok(Arc::new(Self {
+            inner,
+            relay,
+            _control: control,
+        }))
+    }
+
+    /// Strip the relay headers from the datagrams described by `meta`
+    ///
+    /// Returns `false` if the buffer doesn't hold any valid datagram.
+    fn unwrap_datagrams(&self, buf: &mut [u8], meta: &mut RecvMeta) -> bool {
+        if canonical(meta.addr) != canonical(self.relay) || meta.stride == 0 {
+            return false;
+        }
+        let mut len = 0;
+        let mut header_len = None;
+        let mut start = 0;
+        while start < meta.len {
+            let end = meta.len.min(start + meta.stride);
+            let Some((source, hlen)) = decode_header(&buf[start..end]) else {
+                break;
+            };
+            match header_len {
+                None => {
+                    header_len = Some(hlen);
+                    meta.addr = source;
+                }
+                // Coalesced datagrams must come from the same source to share a `RecvMeta`
+                Some(expected) if expected != hlen || meta.addr != source => break,
+                Some(_) => {}
+            }
+            buf.copy_within(start + hlen..end, len);
+            len += end - start - hlen;
+            start = end;
+        }
+        let Some(header_len) = header_len else {
+            return false;
+        };
+        meta.stride -= header_len;
+        meta.len = len;
+        true
+    }
+}
+
+impl fmt::Debug for Socks5UdpSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks5UdpSocket")
+            .field("inner", &self.inner)
+            .field("relay", &self.relay)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AsyncUdpSocket for Socks5UdpSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        self.inner.clone().create_io_poller()
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        // `max_transmit_segments` ensures every transmit is a single datagram
+        let mut data = Vec::with_capacity(MAX_HEADER_LEN + transmit.contents.len());
+        data.extend_from_slice(&[0, 0, 0]);
+        encode_addr(canonical(transmit.destination), &mut data);
+        data.extend_from_slice(transmit.contents);
+        self.inner.try_send(&Transmit {
+            destination: self.relay,
+            ecn: transmit.ecn,
+            contents: &data,
+            segment_size: None,
+            src_ip: transmit.src_ip,
+        })
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let n = ready!(self.inner.poll_recv(cx, bufs, meta))?;
+            // Move the valid datagrams to the front, dropping any others
+            let mut valid = 0;
+            for i in 0..n {
+                if self.unwrap_datagrams(&mut bufs[i], &mut meta[i]) {
+                    bufs.swap(valid, i);
+                    meta.swap(valid, i);
+                    valid += 1;
+                }
+            }
+            if valid > 0 {
+                return Poll::Ready(Ok(valid));
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn max_transmit_segments(&self) -> usize {
+        1
+    }
+
+    fn max_receive_segments(&self) -> usize {
+        self.inner.max_receive_segments()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
+
+/// Write a SOCKS5 address type, address and port
+fn encode_addr(addr: SocketAddr, buf: &mut Vec<u8>) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(ATYP_IPV4);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(ATYP_IPV6);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+    buf.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+/// Parse the header of a relayed datagram, returning its source and the header's length
+///
+/// Fragmented datagrams and domain name sources are not supported.
+fn decode_header(buf: &[u8]) -> Option<(SocketAddr, usize)> {
+    let (&[0, 0, 0, atyp], rest) = (buf.get(..4)?, &buf[4..]) else {
+        return None;
+    };
+    let (ip, ip_len) = match atyp {
+        ATYP_IPV4 => (IpAddr::from(<[u8; 4]>::try_from(rest.get(..4)?).ok()?), 4),
+        ATYP_IPV6 => (
+            IpAddr::from(<[u8; 16]>::try_from(rest.get(..16)?).ok()?),
+            16,
+        ),
+        _ => return None,
+    };
+    let port = u16::from_be_bytes(rest.get(ip_len..ip_len + 2)?.try_into().ok()?);
+    Some((SocketAddr::new(ip, port), 4 + ip_len + 2))
+}
+
+/// Unmap IPv4-mapped IPv6 addresses, so they compare equal to and are sent as IPv4
+fn canonical(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => SocketAddr::new(ip.into(), addr.port()),
+            None => addr,
+        },
+        IpAddr::V4(_) => addr,
+    }
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("SOCKS5: {msg}"))
+}
+
+const VERSION: u8 = 5;
+const METHOD_NONE: u8 = 0x00;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const COMMAND_UDP_ASSOCIATE: u8 = 0x03;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_IPV6: u8 = 0x04;
+const MAX_HEADER_LEN: usize = 4 + 16 + 2;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn header_roundtrip() {
+        for addr in [
+            SocketAddr::new(Ipv4Addr::new(192, 0, 2, 1).into(), 4433),
+            SocketAddr::new(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).into(), 443),
+        ] {
+            let mut buf = vec![0, 0, 0];
+            encode_addr(addr, &mut buf);
+            buf.extend_from_slice(b"payload");
+            let (decoded, len) = decode_header(&buf).unwrap();
+            assert_eq!(decoded, addr);
+            assert_eq!(&buf[len..], b"payload");
+        }
+    }
+
+    #[test]
+    fn rejects_fragments() {
+        let mut buf = vec![0, 0, 1];
+        encode_addr(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 1), &mut buf);
+        assert!(decode_header(&buf).is_none());
+        assert!(decode_header(&[0, 0, 0, ATYP_IPV4, 127]).is_none());
+    }
+
+    #[test]
+    fn canonical_addr() {
+        let v4 = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 1);
+        let mapped = SocketAddr::new(Ipv4Addr::LOCALHOST.to_ipv6_mapped().into(), 1);
+        assert_eq!(canonical(mapped), v4);
+        assert_eq!(canonical(v4), v4);
+    }
+}
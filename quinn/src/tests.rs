@@ -2,15 +2,19 @@
 
 use std::{
     convert::TryInto,
-    io,
-    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, UdpSocket},
+    num::NonZeroUsize,
     str,
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 
-use crate::runtime::TokioRuntime;
+use crate::runtime::{Runtime as _, TokioRuntime};
 use bytes::Bytes;
-use proto::{crypto::rustls::QuicClientConfig, RandomConnectionIdGenerator};
+use proto::{
+    crypto::rustls::{QuicClientConfig, QuicServerConfig},
+    RandomConnectionIdGenerator,
+};
 use rand::{rngs::StdRng, RngCore, SeedableRng};
 use rustls::{
     pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
@@ -24,7 +28,11 @@ use tracing::{error_span, info};
 use tracing_futures::Instrument as _;
 use tracing_subscriber::EnvFilter;
 
-use super::{ClientConfig, Endpoint, EndpointConfig, RecvStream, SendStream, TransportConfig};
+use super::{
+    ClientConfig, ClientHello, Endpoint, EndpointConfig, Event, HandshakeOffloadConfig,
+    IncomingRateLimiter, MigrateError, RecvStream, SendStream, ServerConfigSelector,
+    TransmitBatchConfig, TransportConfig, VarInt, WriteError,
+};
 
 #[test]
 fn handshake_timeout() {
@@ -251,6 +259,151 @@ async fn ip_blocking() {
     server_task.abort();
 }
 
+#[tokio::test]
+async fn incoming_rate_limit() {
+    let _guard = subscribe();
+    let endpoint_factory = EndpointFactory::new();
+    let client = endpoint_factory.endpoint();
+    let server = endpoint_factory.endpoint();
+    let server_addr = server.local_addr().unwrap();
+    server.set_incoming_filter(Some(Arc::new(IncomingRateLimiter::new(0.0, 1))));
+    let server_task = tokio::spawn(async move {
+        while let Some(incoming) = server.accept().await {
+            let conn = incoming.await.expect("connection");
+            conn.closed().await;
+        }
+    });
+
+    let conn = client
+        .connect(server_addr, "localhost")
+        .unwrap()
+        .await
+        .expect("connect");
+    // The bucket is now empty, so the next attempt must validate its address and is then refused
+    let e = client
+        .connect(server_addr, "localhost")
+        .unwrap()
+        .await
+        .expect_err("server should have refused this");
+    assert!(
+        matches!(e, crate::ConnectionError::ConnectionClosed(_)),
+        "wrong error"
+    );
+    drop(conn);
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn retry_threshold() {
+    let _guard = subscribe();
+    let endpoint_factory = EndpointFactory::new();
+    let client = endpoint_factory.endpoint();
+    let server = endpoint_factory.endpoint();
+    let server_addr = server.local_addr().unwrap();
+    server.set_retry_threshold(Some(0));
+    let server_task = tokio::spawn({
+        let server = server.clone();
+        async move {
+            let incoming = server.accept().await.unwrap();
+            assert!(incoming.remote_address_validated());
+            let conn = incoming.await.expect("connection");
+            conn.closed().await;
+        }
+    });
+
+    // The attempt counts as a pending handshake itself, so exceeds the threshold and is retried
+    let conn = client
+        .connect(server_addr, "localhost")
+        .unwrap()
+        .await
+        .expect("connect");
+    assert_eq!(conn.handshake_events().retries, 1);
+    assert_eq!(server.stats().retried_handshakes, 1);
+    conn.close(0u32.into(), b"done");
+    server_task.await.unwrap();
+    client.wait_idle().await;
+}
+
+#[tokio::test]
+async fn accept_queue_limit() {
+    let _guard = subscribe();
+    let endpoint_factory = EndpointFactory::new();
+    let client = endpoint_factory.endpoint();
+    let server = endpoint_factory.endpoint();
+    let server_addr = server.local_addr().unwrap();
+    server.set_accept_queue_limit(Some(1), crate::AcceptQueueOverflow::Refuse);
+
+    // Nothing is accepted, so the first attempt fills the queue and the second overflows it
+    let first = client.connect(server_addr, "localhost").unwrap();
+    while server.stats().accept_queue_len == 0 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    let e = client
+        .connect(server_addr, "localhost")
+        .unwrap()
+        .await
+        .expect_err("server should have refused this");
+    assert!(
+        matches!(e, crate::ConnectionError::ConnectionClosed(_)),
+        "wrong error"
+    );
+    let stats = server.stats();
+    assert_eq!(stats.accept_queue_len, 1);
+    assert_eq!(stats.accept_queue_overflows, 1);
+
+    let incoming = server.try_accept().expect("queued attempt");
+    assert!(server.try_accept().is_none());
+    let (conn, _) = tokio::join!(async { first.await.expect("connect") }, async {
+        incoming.await.expect("connection")
+    },);
+    assert_eq!(server.stats().accept_queue_len, 0);
+    conn.close(0u32.into(), b"done");
+    client.wait_idle().await;
+}
+
+#[tokio::test]
+async fn server_config_selector() {
+    #[derive(Debug)]
+    struct Selector {
+        config: Arc<crate::ServerConfig>,
+        server_names: Mutex<Vec<String>>,
+    }
+
+    impl ServerConfigSelector for Selector {
+        fn select(&self, hello: &ClientHello) -> Option<Arc<crate::ServerConfig>> {
+            let server_name = hello.server_name.clone()?;
+            self.server_names.lock().unwrap().push(server_name);
+            Some(self.config.clone())
+        }
+    }
+
+    let _guard = subscribe();
+    let factory = EndpointFactory::new();
+    let endpoint = factory.endpoint();
+    let key = PrivateKeyDer::Pkcs8(factory.cert.key_pair.serialize_der().into());
+    let mut config =
+        crate::ServerConfig::with_single_cert(vec![factory.cert.cert.der().clone()], key).unwrap();
+    let mut transport_config = TransportConfig::default();
+    transport_config.datagram_receive_buffer_size(None);
+    config.transport_config(Arc::new(transport_config));
+    let selector = Arc::new(Selector {
+        config: Arc::new(config),
+        server_names: Mutex::new(Vec::new()),
+    });
+    endpoint.set_server_config_selector(Some(selector.clone()));
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await },
+    );
+    let client = client.unwrap();
+    let _server = server.unwrap();
+    assert_eq!(client.max_datagram_size(), None);
+    assert_eq!(*selector.server_names.lock().unwrap(), ["localhost"]);
+}
+
 /// Construct an endpoint suitable for connecting to itself
 fn endpoint() -> Endpoint {
     EndpointFactory::new().endpoint()
@@ -264,6 +417,7 @@ fn endpoint_with_config(transport_config: TransportConfig) -> Endpoint {
 struct EndpointFactory {
     cert: rcgen::CertifiedKey,
     endpoint_config: EndpointConfig,
+    runtime: Arc<dyn crate::Runtime>,
 }
 
 impl EndpointFactory {
@@ -271,6 +425,7 @@ impl EndpointFactory {
         Self {
             cert: rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap(),
             endpoint_config: EndpointConfig::default(),
+            runtime: Arc::new(TokioRuntime),
         }
     }
 
@@ -291,7 +446,7 @@ impl EndpointFactory {
             self.endpoint_config.clone(),
             Some(server_config),
             UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap(),
-            Arc::new(TokioRuntime),
+            self.runtime.clone(),
         )
         .unwrap();
         let mut client_config = ClientConfig::with_root_certificates(Arc::new(roots)).unwrap();
@@ -302,6 +457,66 @@ impl EndpointFactory {
     }
 }
 
+#[tokio::test]
+async fn mutual_tls() {
+    let _guard = subscribe();
+    let factory = EndpointFactory::new();
+    let cert = factory.cert.cert.der().clone();
+    let key = PrivateKeyDer::Pkcs8(factory.cert.key_pair.serialize_der().into());
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(cert.clone()).unwrap();
+    let roots = Arc::new(roots);
+    let verifier = rustls::server::WebPkiClientVerifier::builder_with_provider(
+        roots.clone(),
+        provider.clone(),
+    )
+    .build()
+    .unwrap();
+    let server_config = crate::ServerConfig::with_client_cert_verifier(
+        vec![cert.clone()],
+        key.clone_key(),
+        verifier,
+    )
+    .unwrap();
+    let server = Endpoint::server(
+        server_config,
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
+    )
+    .unwrap();
+    let server_addr = server.local_addr().unwrap();
+
+    let client_crypto = rustls::ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_client_auth_cert(vec![cert.clone()], key)
+        .unwrap();
+    let client_config =
+        ClientConfig::new(Arc::new(QuicClientConfig::try_from(client_crypto).unwrap()));
+
+    let client = factory.endpoint();
+    let (client_conn, server_conn) = tokio::join!(
+        async {
+            client
+                .connect_with(client_config, server_addr, "localhost")
+                .unwrap()
+                .await
+                .unwrap()
+        },
+        async { server.accept().await.unwrap().await.unwrap() }
+    );
+    assert_eq!(server_conn.peer_identity_der(), Some(vec![cert.clone()]));
+    assert_eq!(client_conn.peer_identity_der(), Some(vec![cert]));
+
+    // Clients without a certificate are turned away
+    let (_, server_conn) = tokio::join!(client.connect(server_addr, "localhost").unwrap(), async {
+        server.accept().await.unwrap().await
+    });
+    assert!(server_conn.is_err());
+}
+
 #[tokio::test]
 async fn zero_rtt() {
     let _guard = subscribe();
@@ -727,6 +942,18 @@ async fn rebind_recv() {
     server.await.unwrap();
 }
 
+#[tokio::test]
+async fn local_addr_changed() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+    let changed = endpoint.local_addr_changed();
+    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap();
+    let new_addr = socket.local_addr().unwrap();
+    endpoint.rebind(socket).unwrap();
+    assert_eq!(changed.await.unwrap(), new_addr);
+    assert_eq!(endpoint.local_addr().unwrap(), new_addr);
+}
+
 #[tokio::test]
 async fn stream_id_flow_control() {
     let _guard = subscribe();
@@ -798,52 +1025,2318 @@ async fn two_datagram_readers() {
 }
 
 #[tokio::test]
-async fn multiple_conns_with_zero_length_cids() {
+async fn read_datagrams_batch() {
     let _guard = subscribe();
-    let mut factory = EndpointFactory::new();
-    factory
-        .endpoint_config
-        .cid_generator(|| Box::new(RandomConnectionIdGenerator::new(0)));
-    let server = {
-        let _guard = error_span!("server").entered();
-        factory.endpoint()
-    };
-    let server_addr = server.local_addr().unwrap();
+    let endpoint = endpoint();
 
-    let client1 = {
-        let _guard = error_span!("client1").entered();
-        factory.endpoint()
-    };
-    let client2 = {
-        let _guard = error_span!("client2").entered();
-        factory.endpoint()
-    };
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
 
-    let client1 = async move {
-        let conn = client1
-            .connect(server_addr, "localhost")
-            .unwrap()
-            .await
-            .unwrap();
-        conn.closed().await;
+    let mut buf = Vec::new();
+    let (count, ()) = tokio::join!(client.read_datagrams(&mut buf, 2), async {
+        server.send_datagram(b"one"[..].into()).unwrap();
+    });
+    assert_eq!(count.unwrap(), 1);
+
+    for msg in [&b"two"[..], b"three", b"four"] {
+        server.send_datagram(msg.into()).unwrap();
     }
-    .instrument(error_span!("client1"));
-    let client2 = async move {
-        let conn = client2
-            .connect(server_addr, "localhost")
-            .unwrap()
-            .await
-            .unwrap();
-        conn.closed().await;
+    while buf.len() < 4 {
+        let count = client.read_datagrams(&mut buf, 2).await.unwrap();
+        assert!((1..=2).contains(&count));
     }
-    .instrument(error_span!("client2"));
-    let server = async move {
-        let client1 = server.accept().await.unwrap().await.unwrap();
-        let client2 = server.accept().await.unwrap().await.unwrap();
-        // Both connections are now concurrently live.
-        client1.close(42u32.into(), &[]);
-        client2.close(42u32.into(), &[]);
+    assert_eq!(buf, [&b"one"[..], b"two", b"three", b"four"]);
+}
+
+#[tokio::test]
+async fn migrate() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    // Migration is only permitted once the handshake is confirmed
+    server.send_datagram(b"confirm"[..].into()).unwrap();
+    client.read_datagram().await.unwrap();
+
+    let local_ip = endpoint.local_addr().unwrap().ip();
+    client.migrate(local_ip).unwrap();
+    assert_eq!(client.local_ip(), Some(local_ip));
+    assert_eq!(server.migrate(local_ip), Err(MigrateError::NotClient));
+
+    let mut send = client.open_uni().await.unwrap();
+    send.write_all(b"hello").await.unwrap();
+    send.finish().unwrap();
+    let mut recv = server.accept_uni().await.unwrap();
+    assert_eq!(recv.read_to_end(usize::MAX).await.unwrap(), b"hello");
+}
+
+#[tokio::test]
+async fn send_probe() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+    let addr = endpoint.local_addr().unwrap();
+
+    // The probe reaches the peer
+    let peer = tokio::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+    endpoint
+        .send_probe(peer.local_addr().unwrap())
+        .await
+        .unwrap();
+    let mut buf = [0; 16];
+    let (len, from) = tokio::time::timeout(Duration::from_secs(1), peer.recv_from(&mut buf))
+        .await
+        .expect("probe not received")
+        .unwrap();
+    assert_eq!(&buf[..len], &[0]);
+    assert_eq!(from.port(), addr.port());
+
+    // Probes are ignored by a receiving QUIC endpoint
+    endpoint.send_probe(addr).await.unwrap();
+    let (client, server) = tokio::join!(endpoint.connect(addr, "localhost").unwrap(), async {
+        endpoint.send_probe(addr).await.unwrap();
+        endpoint.accept().await.unwrap().await
+    });
+    client.unwrap();
+    server.unwrap();
+}
+
+#[tokio::test]
+async fn datagram_tracked() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    let ticket = client.send_datagram_tracked(b"hello"[..].into()).unwrap();
+    assert_eq!(server.read_datagram().await.unwrap(), &b"hello"[..]);
+    assert_eq!(ticket.await.unwrap(), crate::DatagramOutcome::Acknowledged);
+}
+
+#[tokio::test]
+async fn key_update() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    let key_phase = client.key_phase();
+    let (client_phase, server_phase) = tokio::join!(
+        async {
+            client.initiate_key_update();
+            client.key_updated().await.unwrap()
+        },
+        server.key_updated()
+    );
+    assert_eq!(client_phase, key_phase + 1);
+    assert_eq!(server_phase.unwrap(), key_phase + 1);
+
+    client.close(0u32.into(), b"done");
+    assert!(server.key_updated().await.is_err());
+}
+
+#[tokio::test]
+async fn connection_events() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let (client, (server, mut events)) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async {
+            // Subscribe before the handshake is confirmed
+            let incoming = endpoint.accept().await.unwrap();
+            let (server, _) = incoming.accept().unwrap().into_0rtt().unwrap();
+            let events = server.events();
+            (server, events)
+        }
+    );
+    let client = client.unwrap();
+    let mut client_events = client.events();
+
+    // MTU discovery only starts once the handshake is confirmed
+    assert_eq!(events.next().await, Some(Event::HandshakeConfirmed));
+    assert!(matches!(
+        events.next().await,
+        Some(Event::MtuUpdated { .. })
+    ));
+
+    client.initiate_key_update();
+    let key_phase = client.key_updated().await.unwrap();
+    loop {
+        match events.next().await.unwrap() {
+            Event::KeyUpdated { key_phase: x } => {
+                assert_eq!(x, key_phase);
+                break;
+            }
+            Event::MtuUpdated { .. } => {}
+            event => panic!("unexpected event {event:?}"),
+        }
     }
-    .instrument(error_span!("server"));
-    tokio::join!(client1, client2, server);
+
+    let mut send = client.open_uni().await.unwrap();
+    send.write_all(b"hello").await.unwrap();
+    let mut recv = server.accept_uni().await.unwrap();
+    recv.stop(42u32.into()).unwrap();
+    loop {
+        match client_events.next().await.unwrap() {
+            Event::StreamStopped { id, error_code } => {
+                assert_eq!(id, send.id());
+                assert_eq!(error_code, 42u32.into());
+                break;
+            }
+            Event::HandshakeConfirmed | Event::MtuUpdated { .. } | Event::KeyUpdated { .. } => {}
+            event => panic!("unexpected event {event:?}"),
+        }
+    }
+
+    // Streams end once the connection is closed
+    client.close(0u32.into(), b"done");
+    while client_events.next().await.is_some() {}
+    while events.next().await.is_some() {}
+    assert_eq!(client.events().next().await, None);
+}
+
+#[tokio::test]
+async fn probe_path() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    let (a, b) = tokio::join!(client.probe_path(), client.probe_path());
+    let rtt = a.unwrap().expect("peer responds to challenges");
+    assert_eq!(b.unwrap(), Some(rtt));
+    assert!(server.probe_path().await.unwrap().is_some());
+
+    client.close(0u32.into(), b"done");
+    assert!(client.probe_path().await.is_err());
+}
+
+#[tokio::test]
+async fn mtu_updated() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let (client, (server, mtu)) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async {
+            // Get hold of the connection before MTU discovery starts, once the handshake completes
+            let incoming = endpoint.accept().await.unwrap();
+            let (server, _) = incoming.accept().unwrap().into_0rtt().unwrap();
+            let initial = server.current_mtu();
+            let mtu = server.mtu_updated().await.unwrap();
+            // The loopback interface supports larger packets
+            assert!(mtu > initial);
+            (server, mtu)
+        }
+    );
+    let client = client.unwrap();
+    assert_eq!(server.current_mtu(), mtu);
+
+    client.close(0u32.into(), b"done");
+    assert!(server.mtu_updated().await.is_err());
+}
+
+#[tokio::test]
+async fn idle_warning() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    client
+        .idle_warning(Duration::from_millis(50))
+        .await
+        .unwrap();
+    assert!(client.close_reason().is_none());
+
+    client.close(0u32.into(), b"done");
+    assert!(server.idle_warning(Duration::from_secs(60)).await.is_err());
+}
+
+#[tokio::test]
+async fn path_updates() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    let initial = client.path_stats();
+    assert!(initial.bandwidth > 0);
+    assert!(initial.pacing_rate > 0);
+    let mut updates = client.path_updates(0.5);
+
+    // Acknowledged data grows the window in slow start
+    let mut send = client.open_uni().await.unwrap();
+    let (stats, _) = tokio::join!(async { updates.next().await.unwrap() }, async {
+        send.write_all(&[0xab; 256 * 1024]).await.unwrap();
+        send.finish().unwrap();
+        let mut recv = server.accept_uni().await.unwrap();
+        recv.read_to_end(usize::MAX).await.unwrap();
+    });
+    assert!(stats.cwnd > initial.cwnd + initial.cwnd / 2);
+
+    client.close(0u32.into(), b"done");
+    assert!(updates.next().await.is_err());
+}
+
+#[tokio::test]
+async fn stats_stream() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    let mut snapshots = client.stats_stream(Duration::from_millis(10));
+    let first = snapshots.next().await.unwrap();
+    let mut send = client.open_uni().await.unwrap();
+    send.write_all(&[0xab; 64 * 1024]).await.unwrap();
+    send.finish().unwrap();
+    let mut recv = server.accept_uni().await.unwrap();
+    recv.read_to_end(usize::MAX).await.unwrap();
+    let second = snapshots.next().await.unwrap();
+
+    let delta = crate::StatsDelta::between(&first, &second);
+    assert!(delta.udp_tx.bytes > 64 * 1024);
+    assert_eq!(delta.udp_tx.bytes, second.udp_tx.bytes - first.udp_tx.bytes);
+    assert!(delta.frame_tx.stream > 0);
+
+    client.close(0u32.into(), b"done");
+    assert!(snapshots.next().await.is_none());
+}
+
+#[tokio::test]
+async fn close_after_flush() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    const MSG: &[u8] = &[0xab; 64 * 1024];
+    let mut send = client.open_uni().await.unwrap();
+    send.write_all(MSG).await.unwrap();
+    send.finish().unwrap();
+    client
+        .close_after_flush(0u32.into(), b"done", Duration::from_secs(5))
+        .await;
+    assert!(client.close_reason().is_some());
+
+    let mut recv = server.accept_uni().await.unwrap();
+    assert_eq!(recv.read_to_end(usize::MAX).await.unwrap(), MSG);
+}
+
+#[tokio::test]
+async fn connection_registry() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    let mut ids = endpoint
+        .connections()
+        .iter()
+        .map(|info| info.stable_id)
+        .collect::<Vec<_>>();
+    ids.sort_unstable();
+    let mut expected = vec![client.stable_id(), server.stable_id()];
+    expected.sort_unstable();
+    assert_eq!(ids, expected);
+    let info = endpoint
+        .connections()
+        .into_iter()
+        .find(|info| info.stable_id == client.stable_id())
+        .unwrap();
+    assert_eq!(info.remote_address, client.remote_address());
+
+    assert!(endpoint.close_connection(server.stable_id(), 7u32.into(), b"admin"));
+    assert!(!endpoint.close_connection(usize::MAX, 7u32.into(), b"admin"));
+    match client.closed().await {
+        crate::ConnectionError::ApplicationClosed(close) => {
+            assert_eq!(close.error_code, 7u32.into());
+        }
+        e => panic!("unexpected error: {e}"),
+    }
+    assert!(endpoint
+        .connections()
+        .iter()
+        .all(|info| info.stable_id != server.stable_id()));
+}
+
+#[tokio::test]
+async fn driver_stats() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    const MSG: &[u8] = &[0xab; 256 * 1024];
+    let mut send = client.open_uni().await.unwrap();
+    send.write_all(MSG).await.unwrap();
+    send.finish().unwrap();
+    let mut recv = server.accept_uni().await.unwrap();
+    assert_eq!(recv.read_to_end(usize::MAX).await.unwrap(), MSG);
+
+    let stats = server.driver_stats();
+    assert!(stats.wakeups > 0);
+    assert!(stats.datagrams as usize >= MSG.len() / 1500);
+    assert!(stats.max_datagrams_per_wakeup > 0);
+    assert!(stats.datagrams_per_wakeup() > 0.0);
+    assert!(stats.cpu_time > Duration::ZERO);
+    assert!(stats.max_queue_time >= stats.mean_queue_time());
+
+    let top = endpoint.top(2);
+    assert_eq!(top.len(), 2);
+    assert!(top[0].driver.cpu_time >= top[1].driver.cpu_time);
+    assert_eq!(endpoint.top(1).len(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn driver_cpu_time() {
+    use crate::connection::thread_time;
+
+    // Time spent blocked isn't charged to the thread
+    let start = thread_time();
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(thread_time() - start < Duration::from_millis(50));
+
+    // Time spent computing is
+    let start = thread_time();
+    while thread_time() - start < Duration::from_millis(10) {}
+}
+
+#[tokio::test]
+async fn drain() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let _server = server.unwrap();
+
+    let mut drain = endpoint.drain(Some((42u32.into(), b"goaway")));
+    assert_eq!(drain.next().await, Some(2));
+    let mut last = 2;
+    while let Some(remaining) = drain.next().await {
+        assert!(remaining < last);
+        last = remaining;
+    }
+    assert_eq!(last, 0);
+    assert!(endpoint.accept().await.is_none());
+    match client.close_reason() {
+        Some(crate::ConnectionError::LocallyClosed) => {}
+        x => panic!("unexpected close reason: {x:?}"),
+    }
+}
+
+#[tokio::test]
+async fn multi_address_endpoint() {
+    let _guard = subscribe();
+    let factory = EndpointFactory::new();
+    let key = PrivateKeyDer::Pkcs8(factory.cert.key_pair.serialize_der().into());
+    let server_config =
+        crate::ServerConfig::with_single_cert(vec![factory.cert.cert.der().clone()], key).unwrap();
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(factory.cert.cert.der().clone()).unwrap();
+    let mut endpoint = Endpoint::new_with_sockets(
+        EndpointConfig::default(),
+        Some(server_config),
+        vec![
+            UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap(),
+            UdpSocket::bind(SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 0)).unwrap(),
+            UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap(),
+        ],
+        Arc::new(TokioRuntime),
+    )
+    .unwrap();
+    endpoint
+        .set_default_client_config(ClientConfig::with_root_certificates(Arc::new(roots)).unwrap());
+    let addrs = endpoint.local_addrs().unwrap();
+    assert_eq!(endpoint.local_addr().unwrap(), addrs[0]);
+    assert!(addrs[0].is_ipv4() && addrs[1].is_ipv6() && addrs[2].is_ipv4());
+    assert!(endpoint
+        .rebind(UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap())
+        .is_err());
+
+    // Connections are accepted on, and initiated from, the socket of the matching family
+    for &addr in &addrs {
+        let (client, server) = tokio::join!(endpoint.connect(addr, "localhost").unwrap(), async {
+            endpoint.accept().await.unwrap().await
+        });
+        assert_eq!(client.unwrap().remote_address(), addr);
+        assert_eq!(server.unwrap().remote_address().is_ipv6(), addr.is_ipv6());
+    }
+
+    for index in [0, 2] {
+        let (client, server) = tokio::join!(
+            endpoint.connect_on(index, addrs[0], "localhost").unwrap(),
+            async { endpoint.accept().await.unwrap().await }
+        );
+        assert_eq!(client.unwrap().remote_address(), addrs[0]);
+        assert_eq!(server.unwrap().remote_address(), addrs[index]);
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn sharded_endpoint() {
+    let _guard = subscribe();
+    let factory = EndpointFactory::new();
+    let key = PrivateKeyDer::Pkcs8(factory.cert.key_pair.serialize_der().into());
+    let server_config =
+        crate::ServerConfig::with_single_cert(vec![factory.cert.cert.der().clone()], key).unwrap();
+    let server = Endpoint::new_sharded(
+        EndpointConfig::default(),
+        Some(server_config),
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
+        4,
+        Arc::new(TokioRuntime),
+    )
+    .unwrap();
+    check_sharded(&factory, server).await;
+}
+
+#[tokio::test]
+async fn sharded_socket() {
+    let _guard = subscribe();
+    let factory = EndpointFactory::new();
+    let key = PrivateKeyDer::Pkcs8(factory.cert.key_pair.serialize_der().into());
+    let server_config =
+        crate::ServerConfig::with_single_cert(vec![factory.cert.cert.der().clone()], key).unwrap();
+    let server = Endpoint::new_sharded_socket(
+        EndpointConfig::default(),
+        Some(server_config),
+        UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap(),
+        4,
+        Arc::new(TokioRuntime),
+    )
+    .unwrap();
+    check_sharded(&factory, server).await;
+}
+
+/// Exercise a server endpoint with several shards
+async fn check_sharded(factory: &EndpointFactory, server: Endpoint) {
+    const CONNECTIONS: usize = 16;
+    let server_addr = server.local_addr().unwrap();
+    assert_ne!(server_addr.port(), 0);
+    assert!(server
+        .rebind(UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap())
+        .is_err());
+
+    let server_task = tokio::spawn({
+        let server = server.clone();
+        async move {
+            for _ in 0..CONNECTIONS {
+                let conn = server.accept().await.unwrap().await.unwrap();
+                tokio::spawn(async move {
+                    echo(conn.accept_bi().await.unwrap()).await;
+                    conn.closed().await;
+                });
+            }
+        }
+    });
+
+    let client = factory.endpoint();
+    let mut conns = Vec::new();
+    for _ in 0..CONNECTIONS {
+        conns.push(client.connect(server_addr, "localhost").unwrap());
+    }
+    let mut clients = tokio::task::JoinSet::new();
+    for conn in conns {
+        clients.spawn(conn);
+    }
+    let mut conns = Vec::new();
+    while let Some(result) = clients.join_next().await {
+        conns.push(result.unwrap().unwrap());
+    }
+    // Only connection IDs can steer datagrams from the new address to the right shards
+    client
+        .rebind(UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap())
+        .unwrap();
+
+    let mut clients = tokio::task::JoinSet::new();
+    for (i, conn) in conns.into_iter().enumerate() {
+        clients.spawn(async move {
+            let (mut send, mut recv) = conn.open_bi().await.unwrap();
+            let data = gen_data(64 * 1024, i as u64);
+            send.write_all(&data).await.unwrap();
+            send.finish().unwrap();
+            assert_eq!(recv.read_to_end(usize::MAX).await.unwrap(), data);
+            conn.close(0u32.into(), b"done");
+        });
+    }
+    while let Some(result) = clients.join_next().await {
+        result.unwrap();
+    }
+    server_task.await.unwrap();
+
+    assert_eq!(server.stats().accepted_handshakes, CONNECTIONS as u64);
+    server.wait_idle().await;
+    assert_eq!(server.open_connections(), 0);
+}
+
+#[cfg(feature = "futures")]
+#[tokio::test]
+async fn datagram_sink_stream() {
+    use futures_core::Stream;
+    use futures_sink::Sink;
+    use std::{future::poll_fn, pin::Pin};
+
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    let mut sink = server.datagram_sink();
+    let mut stream = client.datagram_stream();
+    for msg in [&b"one"[..], b"two"] {
+        poll_fn(|cx| Pin::new(&mut sink).poll_ready(cx))
+            .await
+            .unwrap();
+        Pin::new(&mut sink)
+            .start_send(Bytes::from_static(msg))
+            .unwrap();
+    }
+    poll_fn(|cx| Pin::new(&mut sink).poll_flush(cx))
+        .await
+        .unwrap();
+
+    let mut received = Vec::new();
+    for _ in 0..2 {
+        let x = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await;
+        received.push(x.unwrap());
+    }
+    received.sort();
+    assert_eq!(received, [&b"one"[..], b"two"]);
+
+    server.close(0u32.into(), b"done");
+    client.closed().await;
+    assert_eq!(
+        poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await,
+        None
+    );
+    Pin::new(&mut sink)
+        .start_send(Bytes::from_static(b"three"))
+        .unwrap();
+    assert!(matches!(
+        poll_fn(|cx| Pin::new(&mut sink).poll_flush(cx)).await,
+        Err(crate::SendDatagramError::ConnectionLost(_))
+    ));
+}
+
+#[cfg(feature = "futures")]
+#[tokio::test]
+async fn read_unordered_stream() {
+    use futures_core::Stream;
+    use std::{future::poll_fn, pin::Pin};
+
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    let data = (0..64 * 1024).map(|i| i as u8).collect::<Vec<u8>>();
+    let mut send = client.open_uni().await.unwrap();
+    send.write_all(&data).await.unwrap();
+    send.finish().unwrap();
+
+    let mut recv = server.accept_uni().await.unwrap();
+    let mut chunks = Vec::new();
+    {
+        let mut stream = recv.read_unordered_stream();
+        while let Some(chunk) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+            chunks.push(chunk.unwrap());
+        }
+    }
+    chunks.sort_by_key(|chunk| chunk.offset);
+    let mut received = Vec::new();
+    for chunk in chunks {
+        assert_eq!(chunk.offset, received.len() as u64);
+        received.extend_from_slice(&chunk.bytes);
+    }
+    assert_eq!(received, data);
+}
+
+#[tokio::test]
+async fn gso_config() {
+    let _guard = subscribe();
+    for (enabled, max_segments) in [(false, 10), (true, 2)] {
+        let mut factory = EndpointFactory::new();
+        factory.endpoint_config.gso(enabled, max_segments);
+        let endpoint = factory.endpoint();
+        let (client, server) = tokio::join!(
+            endpoint
+                .connect(endpoint.local_addr().unwrap(), "localhost")
+                .unwrap(),
+            async { endpoint.accept().await.unwrap().await }
+        );
+        let client = client.unwrap();
+        let server = server.unwrap();
+
+        let data = vec![0xAB; 256 * 1024];
+        let mut send = client.open_uni().await.unwrap();
+        send.write_all(&data).await.unwrap();
+        send.finish().unwrap();
+        let mut recv = server.accept_uni().await.unwrap();
+        assert_eq!(recv.read_to_end(usize::MAX).await.unwrap(), data);
+
+        let stats = endpoint.stats();
+        if enabled {
+            assert!(stats.max_gso_segments <= max_segments);
+            assert!(stats.gso_datagrams <= stats.gso_transmits * max_segments as u64);
+        } else {
+            assert_eq!(stats.max_gso_segments, 1);
+            assert_eq!(stats.gso_transmits, 0);
+        }
+    }
+}
+
+#[tokio::test]
+async fn transmit_batching() {
+    const CONNECTIONS: usize = 8;
+    let _guard = subscribe();
+    let factory = EndpointFactory::new();
+    let server = factory.endpoint();
+    let mut config = TransmitBatchConfig::default();
+    config.max_per_connection(2);
+    server.set_transmit_batching(Some(config.clone()));
+    let server_addr = server.local_addr().unwrap();
+    let server_task = tokio::spawn({
+        let server = server.clone();
+        async move {
+            for _ in 0..CONNECTIONS {
+                let conn = server.accept().await.unwrap().await.unwrap();
+                tokio::spawn(async move {
+                    echo(conn.accept_bi().await.unwrap()).await;
+                    conn.closed().await;
+                });
+            }
+        }
+    });
+
+    let client = factory.endpoint();
+    client.set_transmit_batching(Some(config));
+    let mut conns = Vec::new();
+    for _ in 0..CONNECTIONS {
+        conns.push(
+            client
+                .connect(server_addr, "localhost")
+                .unwrap()
+                .await
+                .unwrap(),
+        );
+    }
+    // Connections keep batching through a new socket, and with the batcher they started with
+    client
+        .rebind(UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap())
+        .unwrap();
+    client.set_transmit_batching(None);
+
+    let mut clients = tokio::task::JoinSet::new();
+    for (i, conn) in conns.into_iter().enumerate() {
+        clients.spawn(async move {
+            let (mut send, mut recv) = conn.open_bi().await.unwrap();
+            let data = gen_data(256 * 1024, i as u64);
+            send.write_all(&data).await.unwrap();
+            send.finish().unwrap();
+            assert_eq!(recv.read_to_end(usize::MAX).await.unwrap(), data);
+            conn.close(0u32.into(), b"done");
+        });
+    }
+    while let Some(result) = clients.join_next().await {
+        result.unwrap();
+    }
+    server_task.await.unwrap();
+
+    for endpoint in [&server, &client] {
+        let stats = endpoint.stats();
+        assert!(stats.transmit_batches > 0);
+        assert!(stats.batched_transmits >= stats.transmit_batches);
+    }
+    client.wait_idle().await;
+    assert!(client.stats().batched_transmits > 0);
+}
+
+#[tokio::test]
+async fn handshake_offload() {
+    const CONNECTIONS: usize = 8;
+    let _guard = subscribe();
+    let factory = EndpointFactory::new();
+    let server = factory.endpoint();
+    // Certificates are resolved by the TLS handshake, so the resolver sees which threads run it
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let key = provider
+        .key_provider
+        .load_private_key(PrivateKeyDer::Pkcs8(
+            factory.cert.key_pair.serialize_der().into(),
+        ))
+        .unwrap();
+    let resolver = Arc::new(ThreadRecordingResolver {
+        key: Arc::new(rustls::sign::CertifiedKey::new(
+            vec![factory.cert.cert.der().clone()],
+            key,
+        )),
+        threads: Mutex::default(),
+    });
+    let crypto = rustls::ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver.clone());
+    server.set_server_config(Some(crate::ServerConfig::with_crypto(Arc::new(
+        QuicServerConfig::try_from(crypto).unwrap(),
+    ))));
+    let mut config = HandshakeOffloadConfig::default();
+    config.threads(NonZeroUsize::new(2).unwrap());
+    server.set_handshake_offload(Some(config)).unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let server_task = tokio::spawn({
+        let server = server.clone();
+        async move {
+            for _ in 0..CONNECTIONS {
+                let incoming = server.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let conn = incoming.await.unwrap();
+                    echo(conn.accept_bi().await.unwrap()).await;
+                    conn.closed().await;
+                });
+            }
+        }
+    });
+
+    let client = factory.endpoint();
+    let mut clients = tokio::task::JoinSet::new();
+    for i in 0..CONNECTIONS {
+        let connecting = client.connect(server_addr, "localhost").unwrap();
+        clients.spawn(async move {
+            let conn = connecting.await.unwrap();
+            let (mut send, mut recv) = conn.open_bi().await.unwrap();
+            let data = gen_data(16 * 1024, i as u64);
+            send.write_all(&data).await.unwrap();
+            send.finish().unwrap();
+            assert_eq!(recv.read_to_end(usize::MAX).await.unwrap(), data);
+            conn.close(0u32.into(), b"done");
+        });
+    }
+    while let Some(result) = clients.join_next().await {
+        result.unwrap();
+    }
+    server_task.await.unwrap();
+    assert_eq!(server.stats().accepted_handshakes, CONNECTIONS as u64);
+    let threads = std::mem::take(&mut *resolver.threads.lock().unwrap());
+    assert_eq!(threads.len(), CONNECTIONS);
+    for thread in threads {
+        assert!(thread.starts_with("quinn-handshake-"), "{thread}");
+    }
+
+    // Connections accepted after offloading is disabled are handshaken inline
+    server.set_handshake_offload(None).unwrap();
+    let server_task = tokio::spawn({
+        let server = server.clone();
+        async move { server.accept().await.unwrap().await.unwrap() }
+    });
+    let conn = client
+        .connect(server_addr, "localhost")
+        .unwrap()
+        .await
+        .unwrap();
+    server_task.await.unwrap();
+    {
+        let threads = resolver.threads.lock().unwrap();
+        assert_eq!(threads.len(), 1);
+        assert!(!threads[0].starts_with("quinn-handshake-"));
+    }
+    conn.close(0u32.into(), b"done");
+    client.wait_idle().await;
+}
+
+/// Records the names of the threads certificates are resolved on
+#[derive(Debug)]
+struct ThreadRecordingResolver {
+    key: Arc<rustls::sign::CertifiedKey>,
+    threads: Mutex<Vec<String>>,
+}
+
+impl rustls::server::ResolvesServerCert for ThreadRecordingResolver {
+    fn resolve(
+        &self,
+        _: rustls::server::ClientHello<'_>,
+    ) -> Option<Arc<rustls::sign::CertifiedKey>> {
+        let name = std::thread::current().name().unwrap_or_default().to_owned();
+        self.threads.lock().unwrap().push(name);
+        Some(self.key.clone())
+    }
+}
+
+#[tokio::test]
+async fn multiple_conns_with_zero_length_cids() {
+    let _guard = subscribe();
+    let mut factory = EndpointFactory::new();
+    factory
+        .endpoint_config
+        .cid_generator(|| Box::new(RandomConnectionIdGenerator::new(0)));
+    let server = {
+        let _guard = error_span!("server").entered();
+        factory.endpoint()
+    };
+    let server_addr = server.local_addr().unwrap();
+
+    let client1 = {
+        let _guard = error_span!("client1").entered();
+        factory.endpoint()
+    };
+    let client2 = {
+        let _guard = error_span!("client2").entered();
+        factory.endpoint()
+    };
+
+    let client1 = async move {
+        let conn = client1
+            .connect(server_addr, "localhost")
+            .unwrap()
+            .await
+            .unwrap();
+        conn.closed().await;
+    }
+    .instrument(error_span!("client1"));
+    let client2 = async move {
+        let conn = client2
+            .connect(server_addr, "localhost")
+            .unwrap()
+            .await
+            .unwrap();
+        conn.closed().await;
+    }
+    .instrument(error_span!("client2"));
+    let server = async move {
+        let client1 = server.accept().await.unwrap().await.unwrap();
+        let client2 = server.accept().await.unwrap().await.unwrap();
+        // Both connections are now concurrently live.
+        client1.close(42u32.into(), &[]);
+        client2.close(42u32.into(), &[]);
+    }
+    .instrument(error_span!("server"));
+    tokio::join!(client1, client2, server);
+}
+
+#[tokio::test]
+async fn write_deadline() {
+    let _guard = subscribe();
+    let mut cfg = TransportConfig::default();
+    cfg.stream_receive_window(1000u32.into());
+    let endpoint = endpoint_with_config(cfg);
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let _server = server.unwrap();
+
+    let mut stream = client.open_uni().await.unwrap();
+    let deadline = Instant::now() + Duration::from_millis(100);
+    stream.set_write_deadline(Some(deadline.into_std()));
+    assert_eq!(stream.write_deadline(), Some(deadline.into_std()));
+    // The peer never reads, so the write is blocked by flow control once the window is exhausted
+    assert_eq!(
+        stream.write_all(&[0; 2000]).await,
+        Err(crate::WriteError::TimedOut)
+    );
+    assert!(Instant::now() >= deadline);
+
+    // Further blocked writes fail immediately
+    assert_eq!(
+        stream.write(&[0; 10]).await,
+        Err(crate::WriteError::TimedOut)
+    );
+}
+
+#[tokio::test]
+async fn flushed_stopped() {
+    use std::{
+        pin::Pin,
+        sync::atomic::{AtomicBool, Ordering},
+        task::{Context, Poll},
+    };
+
+    use udp::{RecvMeta, Transmit};
+
+    /// Silently drops outgoing datagrams while `mute` is set
+    #[derive(Debug)]
+    struct MuteSocket {
+        inner: Arc<dyn crate::AsyncUdpSocket>,
+        mute: AtomicBool,
+    }
+
+    impl crate::AsyncUdpSocket for MuteSocket {
+        fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn crate::UdpPoller>> {
+            self.inner.clone().create_io_poller()
+        }
+
+        fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+            match self.mute.load(Ordering::Relaxed) {
+                true => Ok(()),
+                false => self.inner.try_send(transmit),
+            }
+        }
+
+        fn poll_recv(
+            &self,
+            cx: &mut Context,
+            bufs: &mut [io::IoSliceMut<'_>],
+            meta: &mut [RecvMeta],
+        ) -> Poll<io::Result<usize>> {
+            self.inner.poll_recv(cx, bufs, meta)
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.inner.local_addr()
+        }
+    }
+
+    let _guard = subscribe();
+    let factory = EndpointFactory::new();
+    let server = factory.endpoint();
+    let server_addr = server.local_addr().unwrap();
+    let socket = Arc::new(MuteSocket {
+        inner: TokioRuntime
+            .wrap_udp_socket(
+                UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap(),
+            )
+            .unwrap(),
+        mute: AtomicBool::new(false),
+    });
+    let mut client = Endpoint::new_with_abstract_socket(
+        EndpointConfig::default(),
+        None,
+        socket.clone(),
+        Arc::new(TokioRuntime),
+    )
+    .unwrap();
+    let mut roots = RootCertStore::empty();
+    roots.add(factory.cert.cert.der().clone()).unwrap();
+    client
+        .set_default_client_config(ClientConfig::with_root_certificates(Arc::new(roots)).unwrap());
+
+    let (client_conn, server_conn) = tokio::join!(
+        async {
+            client
+                .connect(server_addr, "localhost")
+                .unwrap()
+                .await
+                .unwrap()
+        },
+        async { server.accept().await.unwrap().await.unwrap() }
+    );
+    let mut send = client_conn.open_uni().await.unwrap();
+    send.write_all(b"hello").await.unwrap();
+    let mut recv = server_conn.accept_uni().await.unwrap();
+    send.flushed().await.unwrap();
+
+    // Data the peer never receives is never acknowledged, so stopping must end the wait
+    socket.mute.store(true, Ordering::Relaxed);
+    send.write_all(b"world").await.unwrap();
+    let (flushed, ()) = tokio::join!(
+        async {
+            // Checking the deadline first keeps it from polling a future that was never woken
+            tokio::select! {
+                biased;
+                _ = tokio::time::sleep(Duration::from_secs(1)) => panic!("flushed() hung"),
+                result = send.flushed() => result,
+            }
+        },
+        async { recv.stop(42u32.into()).unwrap() }
+    );
+    assert_eq!(flushed, Err(WriteError::Stopped(42u32.into())));
+    socket.mute.store(false, Ordering::Relaxed);
+
+    // Reset discards unacknowledged data, which is therefore never flushed
+    let mut send = client_conn.open_uni().await.unwrap();
+    send.write_all(b"hello").await.unwrap();
+    send.reset(0u32.into()).unwrap();
+    assert_eq!(send.flushed().await, Err(WriteError::ClosedStream));
+
+    client_conn.close(0u32.into(), b"done");
+    client.wait_idle().await;
+}
+
+#[tokio::test]
+async fn stream_group_remove() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let _server = server.unwrap();
+
+    let group = client.stream_group(crate::StreamGroupId(1));
+    group.set_send_budget(0);
+    let mut stream = group.open_uni().await.unwrap();
+    // Blocked by the group's send budget
+    tokio::time::timeout(Duration::from_millis(50), stream.write(&[0; 10]))
+        .await
+        .unwrap_err();
+
+    group.remove();
+    assert_eq!(stream.group(), Ok(None));
+    assert_eq!(stream.write(&[0; 10]).await, Ok(10));
+}
+
+#[cfg(feature = "send-file")]
+#[tokio::test]
+async fn send_file() {
+    let _guard = subscribe();
+    let data = (0..200_000u32).map(|i| i as u8).collect::<Vec<u8>>();
+    let path = std::env::temp_dir().join(format!("quinn-send-file-{}", std::process::id()));
+    std::fs::write(&path, &data).unwrap();
+
+    let endpoint = endpoint();
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    let range = 1000..150_000;
+    let file = tokio::fs::File::open(&path).await.unwrap();
+    let mut send = client.open_uni().await.unwrap();
+    let sender = async move {
+        let written = send.send_file(file, range.clone()).await.unwrap();
+        assert_eq!(written, range.end - range.start);
+        send.finish().unwrap();
+        send.stopped().await.unwrap();
+    };
+    let receiver = async {
+        let mut recv = server.accept_uni().await.unwrap();
+        recv.read_to_end(usize::MAX).await.unwrap()
+    };
+    let ((), received) = tokio::join!(sender, receiver);
+    assert_eq!(received, &data[1000..150_000]);
+
+    // Ranges extending past the end of the file are rejected
+    let file = tokio::fs::File::open(&path).await.unwrap();
+    let mut send = client.open_uni().await.unwrap();
+    let err = send.send_file(file, 190_000..210_000).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn read_to_end_into() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    let data = (0..100u8).collect::<Vec<u8>>();
+    for _ in 0..2 {
+        let mut send = client.open_uni().await.unwrap();
+        send.write_all(&data).await.unwrap();
+        send.finish().unwrap();
+    }
+
+    let mut buf = b"prefix".to_vec();
+    let mut recv = server.accept_uni().await.unwrap();
+    assert_eq!(recv.read_to_end_into(&mut buf, 100).await, Ok(100));
+    assert_eq!(&buf[..6], b"prefix");
+    assert_eq!(&buf[6..], &data[..]);
+
+    // Data read up to the limit is retained when the stream turns out to be too long
+    let mut buf = Vec::new();
+    let mut recv = server.accept_uni().await.unwrap();
+    assert_eq!(
+        recv.read_to_end_into(&mut buf, 60).await,
+        Err(crate::ReadToEndError::TooLong)
+    );
+    assert_eq!(buf, &data[..60]);
+}
+
+#[tokio::test]
+async fn peek_stream() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    let mut send = client.open_uni().await.unwrap();
+    send.write_all(b"hello world").await.unwrap();
+    send.finish().unwrap();
+
+    let mut recv = server.accept_uni().await.unwrap();
+    let mut buf = [0; 5];
+    assert_eq!(recv.peek(&mut buf).await, Ok(Some(5)));
+    assert_eq!(&buf, b"hello");
+    let chunk = recv.peek_chunk().await.unwrap().unwrap();
+    assert_eq!(chunk.offset, 0);
+    assert!(b"hello world".starts_with(&chunk.bytes));
+
+    // Peeked data is still read in full
+    assert_eq!(recv.read_to_end(usize::MAX).await.unwrap(), b"hello world");
+    assert_eq!(recv.peek(&mut buf).await, Ok(None));
+}
+
+#[tokio::test]
+async fn open_with_opts() {
+    let _guard = subscribe();
+    let mut transport_config = TransportConfig::default();
+    // Force the initial chunk to exceed the stream's flow control window
+    transport_config.stream_receive_window(1000u32.into());
+    let endpoint = endpoint_with_config(transport_config);
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    let data = Bytes::from((0..3000).map(|i| i as u8).collect::<Vec<u8>>());
+    tokio::join!(
+        async {
+            let (mut send, _recv) = client
+                .open_bi_with(crate::StreamOpts {
+                    priority: 3,
+                    unidirectional_hint: true,
+                    initial_chunk: Some(data.clone()),
+                })
+                .await
+                .unwrap();
+            assert_eq!(send.priority().unwrap(), 3);
+            send.finish().unwrap();
+        },
+        async {
+            let (mut send, mut recv) = server.accept_bi().await.unwrap();
+            assert_eq!(recv.read_to_end(usize::MAX).await.unwrap(), data);
+            assert_eq!(send.stopped().await, Ok(Some(crate::VarInt::from_u32(0))));
+        }
+    );
+}
+
+#[tokio::test]
+async fn buf_read_stream() {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+
+    let _guard = subscribe();
+    let endpoint = endpoint();
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    let mut send = client.open_uni().await.unwrap();
+    send.write_all(b"first line\nsecond line\nrest")
+        .await
+        .unwrap();
+    send.finish().unwrap();
+
+    let mut recv = server.accept_uni().await.unwrap();
+    let mut line = String::new();
+    recv.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "first line\n");
+
+    // Peek without consuming, then mix with plain reads
+    let buf = recv.fill_buf().await.unwrap();
+    assert!(buf.starts_with(b"second"));
+    let mut word = [0; 7];
+    recv.read_exact(&mut word).await.unwrap();
+    assert_eq!(&word, b"second ");
+
+    let mut rest = Vec::new();
+    recv.read_until(b'\n', &mut rest).await.unwrap();
+    assert_eq!(rest, b"line\n");
+    rest.clear();
+    AsyncReadExt::read_to_end(&mut recv, &mut rest)
+        .await
+        .unwrap();
+    assert_eq!(rest, b"rest");
+    assert!(recv.fill_buf().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn framed_streams() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    let mut send = client.open_uni().await.unwrap().into_frame_writer();
+    send.max_frame_size(100);
+    send.send_message(Bytes::from_static(b"hello"))
+        .await
+        .unwrap();
+    send.send_message(Bytes::new()).await.unwrap();
+    send.send_message(Bytes::from(vec![7; 100])).await.unwrap();
+    assert_eq!(
+        send.send_message(Bytes::from(vec![7; 101])).await,
+        Err(crate::SendMessageError::TooLarge)
+    );
+    send.get_mut().finish().unwrap();
+
+    let mut recv = server.accept_uni().await.unwrap().into_frame_reader();
+    recv.max_frame_size(100);
+    let mut messages = Vec::new();
+    while let Some(message) = recv.read_message().await.unwrap() {
+        messages.push(message);
+    }
+    assert_eq!(
+        messages,
+        vec![
+            Bytes::from_static(b"hello"),
+            Bytes::new(),
+            Bytes::from(vec![7; 100])
+        ]
+    );
+
+    // Messages exceeding the reader's limit are rejected
+    let mut send = client.open_uni().await.unwrap().into_frame_writer();
+    send.send_message(Bytes::from(vec![7; 101])).await.unwrap();
+    send.get_mut().finish().unwrap();
+    let mut recv = server.accept_uni().await.unwrap().into_frame_reader();
+    recv.max_frame_size(100);
+    assert_eq!(
+        recv.read_message().await,
+        Err(crate::ReadMessageError::TooLarge)
+    );
+
+    // As are streams finished mid-message
+    let mut send = client.open_uni().await.unwrap();
+    send.write_all(&[5, b'h', b'i']).await.unwrap();
+    send.finish().unwrap();
+    let mut recv = server.accept_uni().await.unwrap().into_frame_reader();
+    assert_eq!(
+        recv.read_message().await,
+        Err(crate::ReadMessageError::FinishedEarly)
+    );
+}
+
+#[cfg(all(feature = "runtime-io-uring", target_os = "linux"))]
+#[tokio::test]
+async fn io_uring_runtime() {
+    let _guard = subscribe();
+    let mut factory = EndpointFactory::new();
+    factory.runtime = Arc::new(crate::IoUringRuntime);
+    let endpoint = factory.endpoint();
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    // Enough data to exercise segmentation offload and exhaust the send slots
+    const SIZE: usize = 4 * 1024 * 1024;
+    let data = (0..SIZE).map(|i| i as u8).collect::<Vec<_>>();
+    let (echoed, ()) = tokio::join!(
+        async {
+            let (mut send, mut recv) = client.open_bi().await.unwrap();
+            send.write_all(&data).await.unwrap();
+            send.finish().unwrap();
+            recv.read_to_end(SIZE).await.unwrap()
+        },
+        async {
+            let (mut send, mut recv) = server.accept_bi().await.unwrap();
+            let received = recv.read_to_end(SIZE).await.unwrap();
+            send.write_all(&received).await.unwrap();
+            send.finish().unwrap();
+        }
+    );
+    assert!(echoed == data);
+
+    client.close(0u32.into(), b"done");
+    endpoint.wait_idle().await;
+}
+
+#[cfg(feature = "runtime-smol")]
+#[test]
+fn smol_runtime() {
+    let _guard = subscribe();
+
+    // Sockets get the same segmentation offload and fragmentation settings as under Tokio
+    let tokio_socket = {
+        let runtime = rt_basic();
+        let _guard = runtime.enter();
+        TokioRuntime
+            .wrap_udp_socket(UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap())
+            .unwrap()
+    };
+    let smol_socket = crate::SmolRuntime
+        .wrap_udp_socket(UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap())
+        .unwrap();
+    assert_eq!(
+        smol_socket.max_transmit_segments(),
+        tokio_socket.max_transmit_segments()
+    );
+    assert_eq!(
+        smol_socket.max_receive_segments(),
+        tokio_socket.max_receive_segments()
+    );
+    assert_eq!(smol_socket.may_fragment(), tokio_socket.may_fragment());
+
+    let mut factory = EndpointFactory::new();
+    factory.runtime = Arc::new(crate::SmolRuntime);
+    let endpoint = factory.endpoint();
+    smol::block_on(async {
+        let (client, server) = smol::future::zip(
+            async {
+                endpoint
+                    .connect(endpoint.local_addr().unwrap(), "localhost")
+                    .unwrap()
+                    .await
+            },
+            async { endpoint.accept().await.unwrap().await },
+        )
+        .await;
+        let client = client.unwrap();
+        let server = server.unwrap();
+
+        // Enough data to exercise segmentation offload
+        const SIZE: usize = 4 * 1024 * 1024;
+        let data = (0..SIZE).map(|i| i as u8).collect::<Vec<_>>();
+        let (echoed, ()) = smol::future::zip(
+            async {
+                let (mut send, mut recv) = client.open_bi().await.unwrap();
+                send.write_all(&data).await.unwrap();
+                send.finish().unwrap();
+                recv.read_to_end(SIZE).await.unwrap()
+            },
+            async {
+                let (mut send, mut recv) = server.accept_bi().await.unwrap();
+                let received = recv.read_to_end(SIZE).await.unwrap();
+                send.write_all(&received).await.unwrap();
+                send.finish().unwrap();
+            },
+        )
+        .await;
+        assert!(echoed == data);
+        assert!(endpoint.stats().max_gso_segments <= smol_socket.max_transmit_segments());
+
+        client.close(0u32.into(), b"done");
+        endpoint.wait_idle().await;
+    });
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn metrics() {
+    use std::{collections::HashMap, sync::atomic::AtomicU64};
+
+    /// Records counters by name, summing over labels
+    #[derive(Default)]
+    struct Recorder(Mutex<HashMap<String, Arc<AtomicU64>>>);
+
+    impl Recorder {
+        fn get(&self, name: &str) -> u64 {
+            let counters = self.0.lock().unwrap();
+            counters[name].load(std::sync::atomic::Ordering::Relaxed)
+        }
+    }
+
+    impl metrics::Recorder for Recorder {
+        fn describe_counter(
+            &self,
+            _: metrics::KeyName,
+            _: Option<metrics::Unit>,
+            _: metrics::SharedString,
+        ) {
+        }
+        fn describe_gauge(
+            &self,
+            _: metrics::KeyName,
+            _: Option<metrics::Unit>,
+            _: metrics::SharedString,
+        ) {
+        }
+        fn describe_histogram(
+            &self,
+            _: metrics::KeyName,
+            _: Option<metrics::Unit>,
+            _: metrics::SharedString,
+        ) {
+        }
+
+        fn register_counter(
+            &self,
+            key: &metrics::Key,
+            _: &metrics::Metadata<'_>,
+        ) -> metrics::Counter {
+            let mut counters = self.0.lock().unwrap();
+            let counter = counters.entry(key.name().to_owned()).or_default();
+            metrics::Counter::from_arc(counter.clone())
+        }
+
+        fn register_gauge(&self, _: &metrics::Key, _: &metrics::Metadata<'_>) -> metrics::Gauge {
+            metrics::Gauge::noop()
+        }
+
+        fn register_histogram(
+            &self,
+            _: &metrics::Key,
+            _: &metrics::Metadata<'_>,
+        ) -> metrics::Histogram {
+            metrics::Histogram::noop()
+        }
+    }
+
+    let _guard = subscribe();
+    let recorder = Recorder::default();
+    let runtime = rt_basic();
+    metrics::with_local_recorder(&recorder, || {
+        runtime.block_on(async {
+            let endpoint = endpoint();
+            let mut config = crate::MetricsConfig::default();
+            config.prefix("test").label("endpoint", "self");
+            endpoint.set_metrics_config(config);
+
+            let (client, server) = tokio::join!(
+                endpoint
+                    .connect(endpoint.local_addr().unwrap(), "localhost")
+                    .unwrap(),
+                async { endpoint.accept().await.unwrap().await }
+            );
+            let client = client.unwrap();
+            let server = server.unwrap();
+            let mut send = client.open_uni().await.unwrap();
+            send.write_all(b"hello").await.unwrap();
+            send.finish().unwrap();
+            let mut recv = server.accept_uni().await.unwrap();
+            recv.read_to_end(usize::MAX).await.unwrap();
+
+            // A long header packet of an unsupported version
+            let socket = tokio::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+                .await
+                .unwrap();
+            let mut packet = vec![0; 1200];
+            packet[..7].copy_from_slice(&[0xc0, 0x1a, 0x2a, 0x3a, 0x4a, 0, 0]);
+            socket
+                .send_to(&packet, endpoint.local_addr().unwrap())
+                .await
+                .unwrap();
+            socket.recv(&mut packet).await.unwrap();
+
+            client.close(0u32.into(), b"done");
+            endpoint.wait_idle().await;
+        });
+    });
+
+    assert_eq!(recorder.get("test_handshakes_accepted_total"), 1);
+    assert_eq!(recorder.get("test_handshakes_outgoing_total"), 1);
+    assert_eq!(recorder.get("test_version_negotiations_total"), 1);
+    assert!(recorder.get("test_packets_sent_total") > 0);
+    assert!(recorder.get("test_packets_received_total") > 0);
+    assert!(recorder.get("test_bytes_received_total") > 0);
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn simulation() {
+    use crate::sim::{LinkConfig, Simulation};
+
+    let _guard = subscribe();
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let server_addr = "10.0.0.1:4433".parse().unwrap();
+    let client_addr = "10.0.0.2:5000".parse().unwrap();
+
+    let run = |seed| {
+        let sim = Simulation::new(seed);
+        let mut link = LinkConfig::default();
+        link.latency(Duration::from_millis(50))
+            .jitter(Duration::from_millis(5))
+            .loss(0.05)
+            .bandwidth(Some(10_000_000));
+        sim.set_default_link(link);
+        let mut endpoint_config = EndpointConfig::default();
+        endpoint_config.rng_seed(Some([seed as u8; 32]));
+
+        let key = PrivateKeyDer::Pkcs8(cert.key_pair.serialize_der().into());
+        let server_config =
+            crate::ServerConfig::with_single_cert(vec![cert.cert.der().clone()], key).unwrap();
+        let server = Endpoint::new_with_abstract_socket(
+            endpoint_config.clone(),
+            Some(server_config),
+            sim.socket(server_addr).unwrap(),
+            sim.runtime(),
+        )
+        .unwrap();
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.cert.der().clone()).unwrap();
+        let mut client = Endpoint::new_with_abstract_socket(
+            endpoint_config,
+            None,
+            sim.socket(client_addr).unwrap(),
+            sim.runtime(),
+        )
+        .unwrap();
+        client.set_default_client_config(
+            ClientConfig::with_root_certificates(Arc::new(roots)).unwrap(),
+        );
+        assert_eq!(
+            sim.socket(server_addr).unwrap_err().kind(),
+            io::ErrorKind::AddrInUse
+        );
+
+        let received = sim.run(async {
+            let ((), received) = tokio::join!(
+                async {
+                    let conn = client
+                        .connect(server_addr, "localhost")
+                        .unwrap()
+                        .await
+                        .unwrap();
+                    let mut send = conn.open_uni().await.unwrap();
+                    send.write_all(&[0xab; 100_000]).await.unwrap();
+                    send.finish().unwrap();
+                    conn.closed().await;
+                    client.wait_idle().await;
+                },
+                async {
+                    let conn = server.accept().await.unwrap().await.unwrap();
+                    let mut recv = conn.accept_uni().await.unwrap();
+                    let data = recv.read_to_end(usize::MAX).await.unwrap();
+                    conn.close(0u32.into(), b"done");
+                    data
+                }
+            );
+            received
+        });
+        (received, sim.elapsed())
+    };
+
+    let (received, elapsed) = run(1);
+    assert_eq!(received, vec![0xab; 100_000]);
+    info!(?elapsed, "simulation complete");
+    // A handshake and a transfer over a 100ms RTT take a handful of round trips
+    assert!(elapsed > Duration::from_millis(300), "{elapsed:?}");
+    assert!(elapsed < Duration::from_secs(30), "{elapsed:?}");
+    // Simulations are reproducible
+    assert_eq!(run(1).1, elapsed);
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn fault_injection() {
+    use crate::sim::{FaultPolicy, FaultySocket, Simulation};
+
+    let _guard = subscribe();
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let server_addr = "10.0.0.1:4433".parse().unwrap();
+    let client_addr = "10.0.0.2:5000".parse().unwrap();
+    let sim = Simulation::new(0);
+    let faulty = |addr, seed| {
+        let mut policy = FaultPolicy::new(seed);
+        policy
+            .loss(0.05)
+            .duplicate(0.05)
+            .delay(0.05, Duration::from_millis(100))
+            .corrupt(0.05)
+            .truncate(0.05);
+        FaultySocket::wrap(sim.socket(addr).unwrap(), policy, sim.runtime())
+    };
+
+    let key = PrivateKeyDer::Pkcs8(cert.key_pair.serialize_der().into());
+    let server_config =
+        crate::ServerConfig::with_single_cert(vec![cert.cert.der().clone()], key).unwrap();
+    let server = Endpoint::new_with_abstract_socket(
+        EndpointConfig::default(),
+        Some(server_config),
+        faulty(server_addr, 1),
+        sim.runtime(),
+    )
+    .unwrap();
+    let mut roots = RootCertStore::empty();
+    roots.add(cert.cert.der().clone()).unwrap();
+    let mut client = Endpoint::new_with_abstract_socket(
+        EndpointConfig::default(),
+        None,
+        faulty(client_addr, 2),
+        sim.runtime(),
+    )
+    .unwrap();
+    client
+        .set_default_client_config(ClientConfig::with_root_certificates(Arc::new(roots)).unwrap());
+
+    let received = sim.run(async {
+        let ((), received) = tokio::join!(
+            async {
+                let conn = client
+                    .connect(server_addr, "localhost")
+                    .unwrap()
+                    .await
+                    .unwrap();
+                let mut send = conn.open_uni().await.unwrap();
+                send.write_all(&[0xab; 100_000]).await.unwrap();
+                send.finish().unwrap();
+                conn.closed().await;
+            },
+            async {
+                let conn = server.accept().await.unwrap().await.unwrap();
+                let mut recv = conn.accept_uni().await.unwrap();
+                let data = recv.read_to_end(usize::MAX).await.unwrap();
+                conn.close(0u32.into(), b"done");
+                data
+            }
+        );
+        received
+    });
+    assert_eq!(received, vec![0xab; 100_000]);
+}
+
+#[tokio::test]
+async fn socks5_udp_associate() {
+    let _guard = subscribe();
+    let factory = EndpointFactory::new();
+    let server = factory.endpoint();
+    let server_addr = server.local_addr().unwrap();
+
+    let socket = TokioRuntime
+        .wrap_udp_socket(UdpSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).unwrap())
+        .unwrap();
+    let socket = crate::Socks5UdpSocket::associate(socks5_proxy(), None, socket).unwrap();
+    let mut client = Endpoint::new_with_abstract_socket(
+        EndpointConfig::default(),
+        None,
+        socket,
+        Arc::new(TokioRuntime),
+    )
+    .unwrap();
+    let mut roots = RootCertStore::empty();
+    roots.add(factory.cert.cert.der().clone()).unwrap();
+    client
+        .set_default_client_config(ClientConfig::with_root_certificates(Arc::new(roots)).unwrap());
+
+    let (client_conn, server_conn) = tokio::join!(
+        async {
+            client
+                .connect(server_addr, "localhost")
+                .unwrap()
+                .await
+                .unwrap()
+        },
+        async { server.accept().await.unwrap().await.unwrap() }
+    );
+    // The server only sees the proxy
+    assert_ne!(server_conn.remote_address(), client.local_addr().unwrap());
+    let mut send = client_conn.open_uni().await.unwrap();
+    send.write_all(b"hello").await.unwrap();
+    send.finish().unwrap();
+    let mut recv = server_conn.accept_uni().await.unwrap();
+    assert_eq!(recv.read_to_end(usize::MAX).await.unwrap(), b"hello");
+}
+
+/// Run a SOCKS5 proxy relaying a single unauthenticated IPv4 UDP association
+fn socks5_proxy() -> SocketAddr {
+    let listener = TcpListener::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        let (mut control, _) = listener.accept().unwrap();
+        let mut request = [0; 10];
+        control.read_exact(&mut request[..3]).unwrap();
+        assert_eq!(request[..3], [5, 1, 0]);
+        control.write_all(&[5, 0]).unwrap();
+        control.read_exact(&mut request).unwrap();
+        assert_eq!(request[..4], [5, 3, 0, 1]);
+
+        let relay = UdpSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).unwrap();
+        relay
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        // An unspecified relay address refers to the proxy itself
+        let mut reply = vec![5, 0, 0, 1, 0, 0, 0, 0];
+        reply.extend_from_slice(&relay.local_addr().unwrap().port().to_be_bytes());
+        control.write_all(&reply).unwrap();
+
+        let mut client = None;
+        let mut buf = [0; 65536];
+        while let Ok((len, from)) = relay.recv_from(&mut buf[10..]) {
+            // The first datagram comes from the client
+            let client = *client.get_or_insert(from);
+            if from == client {
+                let header = &buf[10..20];
+                assert_eq!(header[..4], [0, 0, 0, 1]);
+                let ip = Ipv4Addr::new(header[4], header[5], header[6], header[7]);
+                let port = u16::from_be_bytes([header[8], header[9]]);
+                relay
+                    .send_to(&buf[20..10 + len], SocketAddr::new(ip.into(), port))
+                    .unwrap();
+            } else {
+                let SocketAddr::V4(from) = from else {
+                    unreachable!()
+                };
+                buf[..4].copy_from_slice(&[0, 0, 0, 1]);
+                buf[4..8].copy_from_slice(&from.ip().octets());
+                buf[8..10].copy_from_slice(&from.port().to_be_bytes());
+                relay.send_to(&buf[..10 + len], client).unwrap();
+            }
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn connect_multi() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+    let server_addr = endpoint.local_addr().unwrap();
+    // Nothing listens on the first address, so the second attempt wins after the delay
+    let silent = UdpSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).unwrap();
+    let addrs = [
+        silent.local_addr().unwrap(),
+        SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 1),
+        server_addr,
+    ];
+
+    let (result, server_conn) = tokio::join!(endpoint.connect_multi(&addrs, "localhost"), async {
+        endpoint.accept().await.unwrap().await.unwrap()
+    });
+    let (conn, addr) = result.unwrap();
+    assert_eq!(addr, server_addr);
+    assert_eq!(server_conn.remote_address(), server_addr);
+    conn.close(0u32.into(), b"done");
+
+    assert!(matches!(
+        endpoint.connect_multi(&[], "localhost").await,
+        Err(crate::ConnectMultiError::NoAddresses)
+    ));
+}
+
+#[tokio::test]
+async fn connect_to() {
+    let _guard = subscribe();
+    let mut endpoint = endpoint();
+    let port = endpoint.local_addr().unwrap().port();
+
+    for resolver in [
+        Arc::new(crate::TokioResolver) as Arc<dyn crate::Resolver>,
+        Arc::new(crate::ThreadResolver),
+    ] {
+        endpoint.set_resolver(resolver);
+        let target = format!("localhost:{port}");
+        let (result, server_conn) = tokio::join!(endpoint.connect_to(&target), async {
+            endpoint.accept().await.unwrap().await.unwrap()
+        });
+        let (conn, addr) = result.unwrap();
+        assert_eq!(addr, endpoint.local_addr().unwrap());
+        assert_eq!(
+            server_conn
+                .handshake_data()
+                .unwrap()
+                .downcast::<crate::crypto::rustls::HandshakeData>()
+                .unwrap()
+                .server_name
+                .as_deref(),
+            Some("localhost")
+        );
+        conn.close(0u32.into(), b"done");
+    }
+
+    endpoint.set_address_family_preference(crate::AddressFamilyPreference::Ipv6Only);
+    assert!(matches!(
+        endpoint.connect_to(&format!("127.0.0.1:{port}")).await,
+        Err(crate::ConnectToError::Connect(
+            crate::ConnectMultiError::NoAddresses
+        ))
+    ));
+    assert!(matches!(
+        endpoint.connect_to("localhost").await,
+        Err(crate::ConnectToError::Resolve(_))
+    ));
+}
+
+#[tokio::test]
+async fn webtransport_session() {
+    use crate::webtransport::{self, Session, StreamHeader};
+
+    let _guard = subscribe();
+    let endpoint = endpoint();
+    let (client, server) = tokio::join!(
+        async {
+            endpoint
+                .connect(endpoint.local_addr().unwrap(), "localhost")
+                .unwrap()
+                .await
+                .unwrap()
+        },
+        async { endpoint.accept().await.unwrap().await.unwrap() }
+    );
+
+    // Stand-in for the extended CONNECT request establishing the session
+    let (mut connect, _) = client.open_bi().await.unwrap();
+    connect.write_all(&[0x01]).await.unwrap();
+    let (_, mut connect_recv) = server.accept_bi().await.unwrap();
+    assert_eq!(
+        webtransport::read_bi_header(&mut connect_recv)
+            .await
+            .unwrap(),
+        StreamHeader::Other(VarInt::from_u32(0x01))
+    );
+
+    let mut session = Session::new(client.clone(), connect.id());
+    session.set_stream_receive_window(Some(VarInt::from_u32(1 << 20)));
+    let mut send = session.open_uni().await.unwrap();
+    send.write_all(b"uni").await.unwrap();
+    send.finish().unwrap();
+    let mut recv = server.accept_uni().await.unwrap();
+    assert_eq!(
+        webtransport::read_uni_header(&mut recv).await.unwrap(),
+        StreamHeader::WebTransport {
+            session: session.id()
+        }
+    );
+    assert_eq!(recv.read_to_end(usize::MAX).await.unwrap(), b"uni");
+
+    let (mut send, _recv) = session.open_bi().await.unwrap();
+    send.write_all(b"bi").await.unwrap();
+    send.finish().unwrap();
+    let (_, mut recv) = server.accept_bi().await.unwrap();
+    assert_eq!(
+        webtransport::read_bi_header(&mut recv).await.unwrap(),
+        StreamHeader::WebTransport {
+            session: session.id()
+        }
+    );
+    assert_eq!(recv.read_to_end(usize::MAX).await.unwrap(), b"bi");
+
+    session.send_datagram(b"datagram").unwrap();
+    let datagram = server.read_datagram().await.unwrap();
+    assert_eq!(
+        webtransport::decode_datagram(datagram),
+        Some((session.id(), Bytes::from_static(b"datagram")))
+    );
+
+    client.close(0u32.into(), b"done");
+    endpoint.wait_idle().await;
+}
+
+#[tokio::test]
+async fn runtime_entropy() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Supplies fixed "entropy", counting requests
+    #[derive(Debug, Default)]
+    struct FixedEntropy(AtomicUsize);
+
+    impl crate::Runtime for FixedEntropy {
+        fn new_timer(&self, i: std::time::Instant) -> std::pin::Pin<Box<dyn crate::AsyncTimer>> {
+            TokioRuntime.new_timer(i)
+        }
+
+        fn spawn(&self, future: std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>>) {
+            TokioRuntime.spawn(future)
+        }
+
+        fn wrap_udp_socket(&self, t: UdpSocket) -> io::Result<Arc<dyn crate::AsyncUdpSocket>> {
+            TokioRuntime.wrap_udp_socket(t)
+        }
+
+        fn fill_random(&self, dest: &mut [u8]) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            dest.fill(0x2a);
+        }
+    }
+
+    let _guard = subscribe();
+    let runtime = Arc::new(FixedEntropy::default());
+    let mut factory = EndpointFactory::new();
+    factory.runtime = runtime.clone();
+    let endpoint = factory.endpoint();
+    assert_eq!(runtime.0.load(Ordering::Relaxed), 1);
+
+    // An explicit seed takes precedence
+    factory.endpoint_config.rng_seed(Some([0; 32]));
+    let _seeded = factory.endpoint();
+    assert_eq!(runtime.0.load(Ordering::Relaxed), 1);
+
+    let (client, server) = tokio::join!(
+        async {
+            endpoint
+                .connect(endpoint.local_addr().unwrap(), "localhost")
+                .unwrap()
+                .await
+                .unwrap()
+        },
+        async { endpoint.accept().await.unwrap().await.unwrap() }
+    );
+    client.close(0u32.into(), b"done");
+    drop(server);
+    endpoint.wait_idle().await;
+}
+
+#[tokio::test]
+async fn recv_owned() {
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use bytes::BytesMut;
+    use udp::{RecvMeta, Transmit};
+
+    /// Receives only into buffers of its own, like the sockets of completion-based runtimes
+    #[derive(Debug)]
+    struct OwnedSocket(Arc<dyn crate::AsyncUdpSocket>);
+
+    impl crate::AsyncUdpSocket for OwnedSocket {
+        fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn crate::UdpPoller>> {
+            self.0.clone().create_io_poller()
+        }
+
+        fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+            self.0.try_send(transmit)
+        }
+
+        fn poll_recv(
+            &self,
+            _: &mut Context,
+            _: &mut [io::IoSliceMut<'_>],
+            _: &mut [RecvMeta],
+        ) -> Poll<io::Result<usize>> {
+            unreachable!("sockets receiving into owned buffers aren't polled for borrowed ones")
+        }
+
+        fn recv_owned(&self) -> bool {
+            true
+        }
+
+        fn poll_recv_owned(
+            &self,
+            cx: &mut Context,
+            datagrams: &mut Vec<(RecvMeta, BytesMut)>,
+        ) -> Poll<io::Result<()>> {
+            let mut buf = BytesMut::zeroed(64 * 1024);
+            let mut meta = [RecvMeta::default()];
+            ready!(self
+                .0
+                .poll_recv(cx, &mut [io::IoSliceMut::new(&mut buf)], &mut meta))?;
+            buf.truncate(meta[0].len);
+            datagrams.push((meta[0], buf));
+            Poll::Ready(Ok(()))
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.0.local_addr()
+        }
+    }
+
+    #[derive(Debug)]
+    struct OwnedRuntime;
+
+    impl crate::Runtime for OwnedRuntime {
+        fn new_timer(&self, i: std::time::Instant) -> Pin<Box<dyn crate::AsyncTimer>> {
+            TokioRuntime.new_timer(i)
+        }
+
+        fn spawn(&self, future: Pin<Box<dyn std::future::Future<Output = ()> + Send>>) {
+            TokioRuntime.spawn(future)
+        }
+
+        fn wrap_udp_socket(&self, t: UdpSocket) -> io::Result<Arc<dyn crate::AsyncUdpSocket>> {
+            Ok(Arc::new(OwnedSocket(TokioRuntime.wrap_udp_socket(t)?)))
+        }
+    }
+
+    let _guard = subscribe();
+    let mut factory = EndpointFactory::new();
+    factory.runtime = Arc::new(OwnedRuntime);
+    let endpoint = factory.endpoint();
+    let (client, server) = tokio::join!(
+        async {
+            endpoint
+                .connect(endpoint.local_addr().unwrap(), "localhost")
+                .unwrap()
+                .await
+                .unwrap()
+        },
+        async { endpoint.accept().await.unwrap().await.unwrap() }
+    );
+
+    let data = vec![0xAB; 256 * 1024];
+    let mut send = client.open_uni().await.unwrap();
+    send.write_all(&data).await.unwrap();
+    send.finish().unwrap();
+    let mut recv = server.accept_uni().await.unwrap();
+    assert_eq!(recv.read_to_end(usize::MAX).await.unwrap(), data);
+
+    client.close(0u32.into(), b"done");
+    endpoint.wait_idle().await;
+}
+
+#[tokio::test]
+async fn connection_pool() {
+    use crate::pool::{ConnectionPool, PoolConfig};
+
+    let _guard = subscribe();
+    let endpoint = endpoint();
+    let server_addr = endpoint.local_addr().unwrap();
+    let server = endpoint.clone();
+    tokio::spawn(async move {
+        let mut conns = Vec::new();
+        while let Some(incoming) = server.accept().await {
+            if let Ok(conn) = incoming.await {
+                conns.push(conn);
+            }
+        }
+    });
+
+    let mut config = PoolConfig::default();
+    config
+        .max_leases_per_connection(2)
+        .backoff(Duration::from_millis(100), Duration::from_millis(100));
+    let pool = ConnectionPool::new(endpoint.clone(), config);
+    let a = pool.get(server_addr, "localhost").await.unwrap();
+    let b = pool.get(server_addr, "localhost").await.unwrap();
+    assert_eq!(a.stable_id(), b.stable_id());
+    // Both leases on the first connection are taken
+    let c = pool.get(server_addr, "localhost").await.unwrap();
+    assert_ne!(c.stable_id(), a.stable_id());
+    assert_eq!(pool.open_connections(), 2);
+    drop(b);
+    let d = pool.get(server_addr, "localhost").await.unwrap();
+    assert_eq!(d.stable_id(), a.stable_id());
+
+    // A failed attempt, here due to the server's certificate, isn't repeated until the backoff
+    // has elapsed
+    let handshakes = endpoint.stats().outgoing_handshakes;
+    let err = pool.get(server_addr, "invalid.example").await.unwrap_err();
+    assert_eq!(endpoint.stats().outgoing_handshakes, handshakes + 1);
+    assert_eq!(
+        pool.get(server_addr, "invalid.example").await.unwrap_err(),
+        err
+    );
+    assert_eq!(endpoint.stats().outgoing_handshakes, handshakes + 1);
+    assert_eq!(pool.servers(), 2);
+
+    // Servers without connections are forgotten once their failures are no longer recent
+    a.close(0u32.into(), b"done");
+    c.close(0u32.into(), b"done");
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    let e = pool.get(server_addr, "localhost").await.unwrap();
+    assert_ne!(e.stable_id(), a.stable_id());
+    assert_eq!(pool.servers(), 1);
+    assert_eq!(pool.open_connections(), 1);
+}
+
+#[tokio::test]
+async fn reconnecting_connection() {
+    use crate::{ReconnectConfig, ReconnectHook, ReconnectingConnection};
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<u64>>);
+
+    impl ReconnectHook for Recorder {
+        fn on_connected(
+            &self,
+            conn: crate::Connection,
+            generation: u64,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>> {
+            self.0.lock().unwrap().push(generation);
+            Box::pin(async move {
+                // Re-open the application's control stream
+                let mut send = conn.open_uni().await.unwrap();
+                send.write_all(&generation.to_be_bytes()).await.unwrap();
+                send.finish().unwrap();
+            })
+        }
+    }
+
+    let _guard = subscribe();
+    let endpoint = endpoint();
+    let server_addr = endpoint.local_addr().unwrap();
+    let recorder = Arc::new(Recorder::default());
+    let server = endpoint.clone();
+    let server_task = tokio::spawn(async move {
+        for expected in 0..2u64 {
+            let conn = server.accept().await.unwrap().await.unwrap();
+            let mut recv = conn.accept_uni().await.unwrap();
+            let data = recv.read_to_end(8).await.unwrap();
+            assert_eq!(data, expected.to_be_bytes());
+            conn.close(0u32.into(), b"restarting");
+        }
+    });
+
+    let reconnecting = ReconnectingConnection::connect(
+        endpoint.clone(),
+        server_addr,
+        "localhost",
+        ReconnectConfig::default(),
+        Some(recorder.clone()),
+    )
+    .await
+    .unwrap();
+    let first = reconnecting.connection().await.unwrap();
+    first.closed().await;
+    let second = reconnecting.connection().await.unwrap();
+    assert_ne!(first.stable_id(), second.stable_id());
+    assert_eq!(reconnecting.generation().await, 1);
+    assert_eq!(*recorder.0.lock().unwrap(), [0, 1]);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn blocked_write_cancelled() {
+    let _guard = subscribe();
+    let mut config = TransportConfig::default();
+    config.stream_receive_window(1000u32.into());
+    let endpoint = endpoint_with_config(config);
+
+    let (client, server) = tokio::join!(
+        async {
+            endpoint
+                .connect(endpoint.local_addr().unwrap(), "localhost")
+                .unwrap()
+                .await
+                .unwrap()
+        },
+        async { endpoint.accept().await.unwrap().await.unwrap() }
+    );
+    let mut send = client.open_uni().await.unwrap();
+    // The peer never reads, so the write blocks on flow control until it's abandoned
+    tokio::time::timeout(Duration::from_millis(100), send.write_all(&[0; 10_000]))
+        .await
+        .unwrap_err();
+    let blocked = send.stats().unwrap().blocked_time;
+    assert!(blocked >= Duration::from_millis(50), "{blocked:?}");
+
+    // Time after the write was dropped isn't counted as blocked
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(send.stats().unwrap().blocked_time, blocked);
+
+    drop(server);
+    client.close(0u32.into(), b"done");
+    endpoint.wait_idle().await;
 }
@@ -0,0 +1,469 @@
+use std::{
+    fmt,
+    future::Future,
+    mem,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use slab::Slab;
+
+use crate::runtime::{AsyncTimer, Runtime};
+
+/// Timers of the connections of an endpoint shard, driven by a single runtime timer
+///
+/// Connections reset their timers whenever they send or receive, so servers with many mostly idle
+/// connections would otherwise keep the runtime busy maintaining a timer for each. Deadlines are
+/// instead rounded up to whole milliseconds and sorted into a hierarchical timer wheel, which a
+/// single task advances as time passes, arming a runtime timer for the earliest deadline only.
+#[derive(Clone)]
+pub(crate) struct TimerWheel(Arc<Shared>);
+
+impl TimerWheel {
+    pub(crate) fn new(runtime: Arc<dyn Runtime>) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                start: runtime.now(),
+                wheel: Wheel::new(),
+                driver: None,
+                driver_deadline: None,
+            }),
+            runtime: runtime.clone(),
+        });
+        runtime.spawn(Box::pin(Driver {
+            shared: Arc::downgrade(&shared),
+            timer: None,
+        }));
+        Self(shared)
+    }
+
+    /// Construct a timer that will expire at `deadline`
+    pub(crate) fn timer(&self, deadline: Instant) -> Pin<Box<dyn AsyncTimer>> {
+        let mut state = self.0.state.lock().unwrap();
+        let tick = state.tick_at(deadline);
+        let key = state.wheel.insert(tick);
+        self.0.schedule(&mut state);
+        Box::pin(WheelTimer {
+            shared: self.0.clone(),
+            key,
+            deadline,
+        })
+    }
+}
+
+impl fmt::Debug for TimerWheel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimerWheel").finish_non_exhaustive()
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    runtime: Arc<dyn Runtime>,
+}
+
+impl Shared {
+    /// Wake the driver if the earliest deadline precedes the one its timer is armed for
+    fn schedule(&self, state: &mut State) {
+        let Some(next) = state.wheel.next_expiration() else {
+            return;
+        };
+        if state
+            .driver_deadline
+            .is_some_and(|armed| armed <= next.tick)
+        {
+            return;
+        }
+        state.driver_deadline = Some(next.tick);
+        if let Some(waker) = state.driver.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        // Let the driver notice that it's no longer needed
+        if let Some(waker) = self.state.get_mut().unwrap().driver.take() {
+            waker.wake();
+        }
+    }
+}
+
+struct State {
+    /// Time of tick 0
+    start: Instant,
+    wheel: Wheel,
+    /// Task advancing the wheel
+    driver: Option<Waker>,
+    /// Tick the driver's timer is armed for
+    driver_deadline: Option<u64>,
+}
+
+impl State {
+    /// The first tick at or after `t`
+    fn tick_at(&self, t: Instant) -> u64 {
+        let elapsed = t.saturating_duration_since(self.start);
+        elapsed.as_millis() as u64 + u64::from(elapsed.subsec_nanos() % 1_000_000 != 0)
+    }
+
+    /// The last tick at or before `t`
+    fn tick_before(&self, t: Instant) -> u64 {
+        t.saturating_duration_since(self.start).as_millis() as u64
+    }
+}
+
+/// Advances the wheel, waking the timers that expire
+struct Driver {
+    shared: Weak<Shared>,
+    timer: Option<Pin<Box<dyn AsyncTimer>>>,
+}
+
+impl Future for Driver {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let mut expired = Vec::new();
+        loop {
+            let Some(shared) = self.shared.upgrade() else {
+                return Poll::Ready(());
+            };
+            let mut state = shared.state.lock().unwrap();
+            let now = state.tick_before(shared.runtime.now());
+            state.wheel.advance(now, &mut expired);
+            let next = state.wheel.next_expiration();
+            state.driver = Some(cx.waker().clone());
+            state.driver_deadline = next.map(|x| x.tick);
+            let start = state.start;
+            drop(state);
+            for waker in expired.drain(..) {
+                waker.wake();
+            }
+
+            let Some(next) = next else {
+                return Poll::Pending;
+            };
+            let deadline = start + Duration::from_millis(next.tick);
+            match &mut self.timer {
+                Some(timer) => timer.as_mut().reset(deadline),
+                None => self.timer = Some(shared.runtime.new_timer(deadline)),
+            }
+            drop(shared);
+            if self.timer.as_mut().unwrap().as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+/// A timer kept in a [`TimerWheel`]
+struct WheelTimer {
+    shared: Arc<Shared>,
+    key: usize,
+    deadline: Instant,
+}
+
+impl AsyncTimer for WheelTimer {
+    fn reset(mut self: Pin<&mut Self>, t: Instant) {
+        self.deadline = t;
+        let mut state = self.shared.state.lock().unwrap();
+        let tick = state.tick_at(t);
+        state.wheel.reset(self.key, tick);
+        self.shared.schedule(&mut state);
+    }
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.shared.runtime.now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        let mut state = self.shared.state.lock().unwrap();
+        let entry = &mut state.wheel.entries[self.key];
+        if entry.fired {
+            return Poll::Ready(());
+        }
+        match &mut entry.waker {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            waker => *waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for WheelTimer {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().wheel.remove(self.key);
+    }
+}
+
+impl fmt::Debug for WheelTimer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WheelTimer")
+            .field("deadline", &self.deadline)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Hierarchical timer wheel, counting time in ticks
+///
+/// Each level divides the range of the level above it into [`SLOTS`] slots, and each entry is
+/// kept in the slot of the lowest level whose range covers both the entry's deadline and the
+/// current time. When the current time reaches a slot, entries that haven't expired move to
+/// lower levels, so that entries are only touched a handful of times no matter how far off
+/// their deadline is.
+///
+/// Postponing an entry leaves it in its slot until that is reached, sparing the frequent resets
+/// of idle timers any bookkeeping. Slots may therefore refer to entries that have since moved,
+/// which are skipped.
+struct Wheel {
+    /// Ticks processed so far
+    elapsed: u64,
+    levels: [Level; LEVELS],
+    /// Entries due after the range of the top level, revisited once the range is over
+    overflow: Vec<usize>,
+    entries: Slab<Entry>,
+}
+
+impl Wheel {
+    fn new() -> Self {
+        Self {
+            elapsed: 0,
+            levels: std::array::from_fn(|_| Level::default()),
+            overflow: Vec::new(),
+            entries: Slab::new(),
+        }
+    }
+
+    fn insert(&mut self, deadline: u64) -> usize {
+        let key = self.entries.insert(Entry {
+            deadline,
+            slot: None,
+            fired: false,
+            waker: None,
+        });
+        self.place(key);
+        key
+    }
+
+    fn reset(&mut self, key: usize, deadline: u64) {
+        let entry = &mut self.entries[key];
+        entry.deadline = deadline;
+        entry.fired = false;
+        let postponed = entry
+            .slot
+            .is_some_and(|slot| self.slot_start(slot) <= deadline);
+        if !postponed {
+            self.place(key);
+        }
+    }
+
+    fn remove(&mut self, key: usize) {
+        self.entries.remove(key);
+    }
+
+    /// The earliest slot holding entries
+    fn next_expiration(&self) -> Option<Expiration> {
+        // Every slot of a level is reached before any slot of the levels above it
+        let slot = self
+            .levels
+            .iter()
+            .enumerate()
+            .find_map(|(level, slots)| {
+                let current = (self.elapsed >> (level * SLOT_BITS)) as usize % SLOTS;
+                let occupied = slots.occupied & (u64::MAX << current);
+                (occupied != 0).then(|| (level, occupied.trailing_zeros() as usize))
+            })
+            .or_else(|| (!self.overflow.is_empty()).then_some(OVERFLOW))?;
+        Some(Expiration {
+            slot,
+            tick: self.slot_start(slot),
+        })
+    }
+
+    /// Process the slots reached by tick `now`, collecting the wakers of expired entries
+    fn advance(&mut self, now: u64, expired: &mut Vec<Waker>) {
+        while let Some(next) = self.next_expiration().filter(|x| x.tick <= now) {
+            self.elapsed = next.tick;
+            let keys = match next.slot {
+                OVERFLOW => mem::take(&mut self.overflow),
+                (level, slot) => {
+                    self.levels[level].occupied &= !(1 << slot);
+                    mem::take(&mut self.levels[level].slots[slot])
+                }
+            };
+            for key in keys {
+                let Some(entry) = self.entries.get_mut(key) else {
+                    continue;
+                };
+                if entry.slot != Some(next.slot) {
+                    // Moved since
+                    continue;
+                }
+                entry.slot = None;
+                if entry.deadline <= now {
+                    entry.fired = true;
+                    expired.extend(entry.waker.take());
+                } else {
+                    self.place(key);
+                }
+            }
+        }
+        self.elapsed = self.elapsed.max(now);
+    }
+
+    /// Store entry `key` in the slot covering its deadline
+    fn place(&mut self, key: usize) {
+        let entry = &mut self.entries[key];
+        let tick = entry.deadline.max(self.elapsed);
+        // The highest digit in which the deadline differs from the current time
+        let level = (63 - ((self.elapsed ^ tick) | (SLOTS as u64 - 1)).leading_zeros()) as usize
+            / SLOT_BITS;
+        let slot = match level < LEVELS {
+            true => (level, (tick >> (level * SLOT_BITS)) as usize % SLOTS),
+            false => OVERFLOW,
+        };
+        if entry.slot == Some(slot) {
+            return;
+        }
+        entry.slot = Some(slot);
+        match slot {
+            OVERFLOW => self.overflow.push(key),
+            (level, slot) => {
+                self.levels[level].slots[slot].push(key);
+                self.levels[level].occupied |= 1 << slot;
+            }
+        }
+    }
+
+    /// First tick covered by `slot`, which must not have been reached yet
+    fn slot_start(&self, slot: (usize, usize)) -> u64 {
+        let (level, slot) = match slot {
+            OVERFLOW => return (self.elapsed | RANGE_MASK).saturating_add(1),
+            x => x,
+        };
+        let above = (1 << ((level + 1) * SLOT_BITS)) - 1;
+        ((self.elapsed & !above) | (slot as u64) << (level * SLOT_BITS)).max(self.elapsed)
+    }
+}
+
+struct Level {
+    /// Bitmap of non-empty slots
+    occupied: u64,
+    slots: [Vec<usize>; SLOTS],
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self {
+            occupied: 0,
+            slots: std::array::from_fn(|_| Vec::new()),
+        }
+    }
+}
+
+struct Entry {
+    deadline: u64,
+    /// Level and index of the slot referring to the entry
+    slot: Option<(usize, usize)>,
+    fired: bool,
+    waker: Option<Waker>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Expiration {
+    slot: (usize, usize),
+    tick: u64,
+}
+
+const SLOT_BITS: usize = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const LEVELS: usize = 6;
+/// Bits of a tick below the range of the top level, which spans about two years
+const RANGE_MASK: u64 = (1 << (LEVELS * SLOT_BITS)) - 1;
+/// Placeholder slot of entries in [`Wheel::overflow`]
+const OVERFLOW: (usize, usize) = (LEVELS, 0);
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn expires_in_order() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut wheel = Wheel::new();
+        let mut deadlines = Vec::new();
+        for _ in 0..1000 {
+            // Spread deadlines over every level
+            let bits = rng.gen_range(1..40);
+            let deadline = rng.gen_range(0..1u64 << bits);
+            deadlines.push((wheel.insert(deadline), deadline));
+        }
+
+        let mut now = 0;
+        while let Some(next) = wheel.next_expiration() {
+            assert!(next.tick >= now);
+            now = next.tick + rng.gen_range(0..100);
+            wheel.advance(now, &mut Vec::new());
+            for &(key, deadline) in &deadlines {
+                assert_eq!(wheel.entries[key].fired, deadline <= now);
+            }
+
+            // Postpone or bring forward some pending entries
+            for (key, deadline) in &mut deadlines {
+                if *deadline > now && rng.gen_ratio(1, 10) {
+                    *deadline = match rng.gen() {
+                        true => *deadline + rng.gen_range(0..*deadline),
+                        false => rng.gen_range(now + 1..=*deadline),
+                    };
+                    wheel.reset(*key, *deadline);
+                }
+            }
+        }
+        assert!(deadlines.iter().all(|&(key, _)| wheel.entries[key].fired));
+    }
+
+    #[test]
+    fn far_future() {
+        let mut wheel = Wheel::new();
+        let key = wheel.insert(u64::MAX);
+        let next = wheel.next_expiration().unwrap();
+        assert_eq!(next.tick, RANGE_MASK + 1);
+        wheel.advance(next.tick, &mut Vec::new());
+        assert!(!wheel.entries[key].fired);
+        assert_eq!(wheel.next_expiration().unwrap().tick, 2 * (RANGE_MASK + 1));
+    }
+
+    #[cfg(feature = "runtime-tokio")]
+    #[tokio::test]
+    async fn runtime_timers() {
+        let wheel = TimerWheel::new(Arc::new(crate::TokioRuntime));
+        let start = Instant::now();
+        let mut short = wheel.timer(start + Duration::from_millis(20));
+        let mut long = wheel.timer(start + Duration::from_secs(3600));
+        expire(short.as_mut(), start + Duration::from_millis(20)).await;
+
+        // Bringing a timer forward rearms the driver's runtime timer
+        let deadline = Instant::now() + Duration::from_millis(20);
+        long.as_mut().reset(deadline);
+        expire(long.as_mut(), deadline).await;
+
+        // Expired timers can be reused
+        let deadline = Instant::now() + Duration::from_millis(20);
+        short.as_mut().reset(deadline);
+        expire(short.as_mut(), deadline).await;
+    }
+
+    /// Wait for `timer` to be woken, checking that happens soon after `deadline`
+    #[cfg(feature = "runtime-tokio")]
+    async fn expire(mut timer: Pin<&mut dyn AsyncTimer>, deadline: Instant) {
+        // Bounded so that a timer that's never woken fails rather than hangs
+        let wait = std::future::poll_fn(|cx| timer.as_mut().poll(cx));
+        let _ = tokio::time::timeout(Duration::from_secs(2), wait).await;
+        let now = Instant::now();
+        assert!(now >= deadline);
+        assert!(now < deadline + Duration::from_secs(1), "timer fired late");
+    }
+}
@@ -0,0 +1,218 @@
+//! Building blocks for WebTransport over HTTP/3
+//!
+//! [WebTransport over HTTP/3] multiplexes sessions over an HTTP/3 connection. A session is
+//! established by an extended CONNECT request, and identified by the ID of the request stream.
+//! The streams and datagrams of the session carry that ID in a short prefix, which this module
+//! writes and parses, leaving HTTP/3 itself, including the CONNECT request and SETTINGS
+//! negotiation, to the application or an HTTP/3 crate.
+//!
+//! ```no_run
+//! # async fn f(conn: quinn::Connection, connect_stream: quinn::StreamId) -> Result<(), Box<dyn std::error::Error>> {
+//! use quinn::webtransport::{self, Session, StreamHeader};
+//!
+//! let session = Session::new(conn.clone(), connect_stream);
+//! let mut send = session.open_uni().await?;
+//! send.write_all(b"hello").await?;
+//!
+//! let mut recv = conn.accept_uni().await?;
+//! match webtransport::read_uni_header(&mut recv).await? {
+//!     StreamHeader::WebTransport { session: id } if id == session.id() => {
+//!         session.bind_recv(&mut recv)?;
+//!         // ...
+//!     }
+//!     // An HTTP/3 control, QPACK or push stream, or belonging to another session
+//!     _ => {}
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [WebTransport over HTTP/3]: https://datatracker.ietf.org/doc/draft-ietf-webtrans-http3/
+
+use bytes::{Bytes, BytesMut};
+use proto::{
+    coding::{BufMutExt, Codec},
+    ClosedStream, StreamGroupId, StreamId, VarInt,
+};
+
+use crate::{
+    Connection, ReadExactError, RecvStream, SendDatagramError, SendStream, StreamGroup, WriteError,
+};
+
+/// HTTP/3 unidirectional stream type of WebTransport streams
+pub const UNI_STREAM_TYPE: VarInt = VarInt::from_u32(0x54);
+/// Signal value opening WebTransport bidirectional streams, in place of an HTTP/3 frame type
+pub const BIDI_STREAM_SIGNAL: VarInt = VarInt::from_u32(0x41);
+/// HTTP/3 setting advertising support for HTTP datagrams, which WebTransport requires
+pub const SETTINGS_H3_DATAGRAM: VarInt = VarInt::from_u32(0x33);
+/// HTTP/3 setting advertising support for WebTransport
+pub const SETTINGS_ENABLE_WEBTRANSPORT: VarInt = VarInt::from_u32(0x2b60_3742);
+/// HTTP/3 setting bounding the number of concurrent WebTransport sessions
+pub const SETTINGS_WEBTRANSPORT_MAX_SESSIONS: VarInt = VarInt::from_u32(0xc671_706a);
+
+/// The streams and datagrams of a single WebTransport session
+///
+/// Send streams opened by the session share a [`StreamGroup`], so that its send budget and
+/// priority can be set independently of other sessions on the connection.
+///
+/// May be cloned to obtain another handle to the same session.
+#[derive(Debug, Clone)]
+pub struct Session {
+    conn: Connection,
+    id: StreamId,
+    group: StreamGroup,
+    receive_window: Option<VarInt>,
+}
+
+impl Session {
+    /// Handle the session established by the extended CONNECT request on `connect_stream`
+    pub fn new(conn: Connection, connect_stream: StreamId) -> Self {
+        Self {
+            group: conn.stream_group(StreamGroupId(connect_stream.0)),
+            conn,
+            id: connect_stream,
+            receive_window: None,
+        }
+    }
+
+    /// The session ID, i.e. the ID of the stream carrying the CONNECT request
+    pub fn id(&self) -> StreamId {
+        self.id
+    }
+
+    /// The group of the session's send streams
+    pub fn group(&self) -> &StreamGroup {
+        &self.group
+    }
+
+    /// Grow the flow control window of the session's receive streams to at least `window` bytes
+    ///
+    /// Applies to streams opened or bound by the session from now on. See
+    /// [`RecvStream::set_receive_window()`].
+    pub fn set_stream_receive_window(&mut self, window: Option<VarInt>) {
+        self.receive_window = window;
+    }
+
+    /// Open a unidirectional stream in the session
+    pub async fn open_uni(&self) -> Result<SendStream, WriteError> {
+        let mut send = self.group.open_uni().await?;
+        send.write_all(&self.prefix(UNI_STREAM_TYPE)).await?;
+        Ok(send)
+    }
+
+    /// Open a bidirectional stream in the session
+    pub async fn open_bi(&self) -> Result<(SendStream, RecvStream), WriteError> {
+        let (mut send, mut recv) = self.group.open_bi().await?;
+        self.bind_recv(&mut recv)?;
+        send.write_all(&self.prefix(BIDI_STREAM_SIGNAL)).await?;
+        Ok((send, recv))
+    }
+
+    /// Apply the session's settings to a stream sent by the peer in the session
+    ///
+    /// The session of incoming streams is only known once their header was read by
+    /// [`read_uni_header()`] or [`read_bi_header()`].
+    pub fn bind_recv(&self, recv: &mut RecvStream) -> Result<(), ClosedStream> {
+        match self.receive_window {
+            Some(window) => recv.set_receive_window(window),
+            None => Ok(()),
+        }
+    }
+
+    /// Apply the session's settings to the send half of a bidirectional stream opened by the peer
+    pub fn bind_send(&self, send: &mut SendStream) -> Result<(), ClosedStream> {
+        send.set_group(Some(self.group.id()))
+    }
+
+    /// Send a datagram in the session
+    ///
+    /// See [`Connection::send_datagram()`].
+    pub fn send_datagram(&self, payload: &[u8]) -> Result<(), SendDatagramError> {
+        self.conn.send_datagram(encode_datagram(self.id, payload))
+    }
+
+    fn prefix(&self, ty: VarInt) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(16);
+        buf.write(ty);
+        buf.write(VarInt::from(self.id));
+        buf
+    }
+}
+
+/// The purpose of an incoming stream, as announced by its first bytes
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StreamHeader {
+    /// A WebTransport stream in the session with the given ID
+    WebTransport {
+        /// ID of the stream carrying the session's CONNECT request
+        session: StreamId,
+    },
+    /// Any other HTTP/3 stream, starting with this stream type or frame type
+    Other(VarInt),
+}
+
+/// Read the header of a unidirectional stream opened by the peer
+pub async fn read_uni_header(recv: &mut RecvStream) -> Result<StreamHeader, ReadExactError> {
+    read_header(recv, UNI_STREAM_TYPE).await
+}
+
+/// Read the header of a bidirectional stream opened by the peer
+///
+/// HTTP/3 request streams are returned as [`StreamHeader::Other`] with the type of their first
+/// frame, whose length and payload follow.
+pub async fn read_bi_header(recv: &mut RecvStream) -> Result<StreamHeader, ReadExactError> {
+    read_header(recv, BIDI_STREAM_SIGNAL).await
+}
+
+async fn read_header(recv: &mut RecvStream, ty: VarInt) -> Result<StreamHeader, ReadExactError> {
+    let first = read_var(recv).await?;
+    if first != ty {
+        return Ok(StreamHeader::Other(first));
+    }
+    Ok(StreamHeader::WebTransport {
+        session: read_var(recv).await?.into(),
+    })
+}
+
+async fn read_var(recv: &mut RecvStream) -> Result<VarInt, ReadExactError> {
+    let mut buf = [0; 8];
+    recv.read_exact(&mut buf[..1]).await?;
+    let len = 1 << (buf[0] >> 6);
+    recv.read_exact(&mut buf[1..len]).await?;
+    Ok(VarInt::decode(&mut &buf[..len]).expect("length matches the encoded tag"))
+}
+
+/// Prefix `payload` with the quarter stream ID identifying `session`, forming an HTTP datagram
+pub fn encode_datagram(session: StreamId, payload: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(8 + payload.len());
+    // Sessions are identified by client-initiated bidirectional streams, whose IDs are multiples
+    // of 4
+    buf.write(VarInt::from_u64(session.0 / 4).expect("stream IDs are valid varints"));
+    buf.extend_from_slice(payload);
+    buf.freeze()
+}
+
+/// Split an HTTP datagram into the ID of its session and its payload
+///
+/// Returns `None` if the datagram is malformed.
+pub fn decode_datagram(mut datagram: Bytes) -> Option<(StreamId, Bytes)> {
+    let quarter = VarInt::decode(&mut datagram).ok()?;
+    let session = VarInt::from_u64(quarter.into_inner().checked_mul(4)?).ok()?;
+    Some((session.into(), datagram))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn datagram_roundtrip() {
+        let session = StreamId(4 * 1000);
+        let datagram = encode_datagram(session, b"payload");
+        assert_eq!(
+            decode_datagram(datagram),
+            Some((session, Bytes::from_static(b"payload")))
+        );
+        assert_eq!(decode_datagram(Bytes::from_static(&[0x40])), None);
+    }
+}